@@ -0,0 +1,9 @@
+//! Fuzz the whole processing pipeline. Run with `cargo fuzz run weave`.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = tt_weave::parse_fuzz_input(data);
+});
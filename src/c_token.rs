@@ -0,0 +1,353 @@
+//! Tokens in the C language, for CWEB input.
+//!
+//! Compared to our Pascal support, this is quite rudimentary: we don't try to
+//! understand the structure of the C code, just to split it into tokens that
+//! can be highlighted and spaced sensibly.
+
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, take_while, take_while1},
+    character::complete::{anychar, char, none_of, one_of, satisfy},
+    combinator::{opt, recognize, value},
+    error::ErrorKind,
+    multi::many0_count,
+    sequence::{pair, tuple},
+};
+use nom_locate::position;
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use std::{borrow::Cow, fmt};
+
+use crate::{
+    control::ControlKind,
+    index::IndexEntryKind,
+    parse_base::{new_parse_error, ParseResult, Span, StringSpan},
+    prettify::{
+        Prettifier, RenderInline, DECIMAL_LITERAL_SCOPE, FLOAT_LITERAL_SCOPE, KEYWORD_SCOPE,
+        STRING_LITERAL_SCOPE,
+    },
+    token::{expect_token, next_token, take_until_terminator, Token},
+};
+
+/// The reserved words of C.
+const C_KEYWORDS: &[&str] = &[
+    "auto", "break", "case", "char", "const", "continue", "default", "do", "double", "else",
+    "enum", "extern", "float", "for", "goto", "if", "inline", "int", "long", "register",
+    "restrict", "return", "short", "signed", "sizeof", "static", "struct", "switch", "typedef",
+    "union", "unsigned", "void", "volatile", "while",
+];
+
+/// The preprocessor directives that we recognize. A `#` followed by one of
+/// these is taken to start a directive, which runs to the end of the line.
+const C_DIRECTIVES: &[&str] = &[
+    "define", "elif", "else", "endif", "error", "if", "ifdef", "ifndef", "include", "line",
+    "pragma", "undef",
+];
+
+/// Operators and punctuation, longest first so that the first match wins.
+const C_OPERATORS: &[&str] = &[
+    "...", "<<=", ">>=", "->", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+=",
+    "-=", "*=", "/=", "%=", "&=", "^=", "|=", "##", "+", "-", "*", "/", "%", "&", "|", "^", "~",
+    "!", "<", ">", "=", "?", ":", ";", ",", ".", "(", ")", "[", "]", "{", "}", "#",
+];
+
+/// Test whether an identifier is a C reserved word.
+pub fn is_c_keyword(text: &str) -> bool {
+    C_KEYWORDS.contains(&text)
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub enum CToken<'a> {
+    /// The `@t` control code: TeX text for the woven output.
+    TexString(StringSpan<'a>),
+
+    /// One of the C reserved words.
+    Keyword(StringSpan<'a>),
+
+    /// An identifier.
+    Identifier(StringSpan<'a>),
+
+    /// An integer literal, as written in the source.
+    IntLiteral(StringSpan<'a>),
+
+    /// A floating-point literal, as written in the source.
+    FloatLiteral(StringSpan<'a>),
+
+    /// A string literal, including its delimiters.
+    StringLiteral(StringSpan<'a>),
+
+    /// A character constant, including its delimiters.
+    CharLiteral(StringSpan<'a>),
+
+    /// An operator or piece of punctuation.
+    Operator(StringSpan<'a>),
+
+    /// A preprocessor directive, running to the end of the line.
+    Preprocessor(StringSpan<'a>),
+
+    /// An index entry.
+    IndexEntry(IndexEntryKind, StringSpan<'a>),
+
+    /// Formatting control codes that ask for a line break: `@/`, `@#`, `@;`,
+    /// and `@\`.
+    LineBreak,
+
+    /// Other formatting control codes that we don't care about: `@,`, `@|`,
+    /// `@+`, `@&`
+    Formatting,
+
+    /// @!: the next identifier is being defined
+    DefinitionFlag,
+
+    /// @?: cancel the definition flag
+    CancelDefinitionFlag,
+}
+
+impl<'a> CToken<'a> {
+    /// Get the text of the token, if it has any.
+    pub fn text(&self) -> Option<&str> {
+        match self {
+            CToken::TexString(s)
+            | CToken::Keyword(s)
+            | CToken::Identifier(s)
+            | CToken::IntLiteral(s)
+            | CToken::FloatLiteral(s)
+            | CToken::StringLiteral(s)
+            | CToken::CharLiteral(s)
+            | CToken::Operator(s)
+            | CToken::Preprocessor(s) => Some(s.value.as_ref()),
+            _ => None,
+        }
+    }
+
+    /// Test whether this token is the specified operator.
+    pub fn is_operator(&self, op: &str) -> bool {
+        matches!(self, CToken::Operator(s) if s.value == op)
+    }
+
+    /// Test whether this token is the specified keyword.
+    pub fn is_keyword(&self, kw: &str) -> bool {
+        matches!(self, CToken::Keyword(s) if s.value == kw)
+    }
+}
+
+impl<'a> fmt::Display for CToken<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.text() {
+            Some(t) => write!(f, "{}", t),
+            None => Ok(()),
+        }
+    }
+}
+
+fn spanned<'a>(start: Span<'a>, end: Span<'a>, text: &'a str) -> StringSpan<'a> {
+    StringSpan {
+        start,
+        end,
+        value: Cow::Borrowed(text),
+    }
+}
+
+fn match_tex_string_token(span: Span) -> ParseResult<CToken> {
+    let (span, _) = expect_token(Token::Control(ControlKind::TexAnnotation))(span)?;
+    let (span, text) = take_until_terminator(span)?;
+    Ok((span, CToken::TexString(text)))
+}
+
+fn match_c_control_code_token(span: Span) -> ParseResult<CToken> {
+    let (span, tok) = next_token(span)?;
+
+    let ctok = match tok {
+        Token::Control(ControlKind::FormatBreak)
+        | Token::Control(ControlKind::FormatBigBreak)
+        | Token::Control(ControlKind::FormatLikeSemicolon)
+        | Token::Control(ControlKind::PascalForceEol) => CToken::LineBreak,
+        Token::Control(ControlKind::FormatThinSpace)
+        | Token::Control(ControlKind::FormatOptionalBreak)
+        | Token::Control(ControlKind::FormatCancelBreak)
+        | Token::Control(ControlKind::PasteText) => CToken::Formatting,
+        Token::Control(ControlKind::DefinitionFlag) => CToken::DefinitionFlag,
+        Token::Control(ControlKind::CancelDefinitionFlag) => CToken::CancelDefinitionFlag,
+        _ => return new_parse_error(span, ErrorKind::Char),
+    };
+
+    Ok((span, ctok))
+}
+
+fn match_index_entry(span: Span) -> ParseResult<CToken> {
+    let (span, tok) = next_token(span)?;
+
+    let kind = match tok.as_index_kind() {
+        Some(k) => k,
+        None => return new_parse_error(span, ErrorKind::Char),
+    };
+
+    let (span, text) = take_until_terminator(span)?;
+    Ok((span, CToken::IndexEntry(kind, text)))
+}
+
+/// Match an identifier or keyword. As in CWEB, letters outside of ASCII may
+/// appear in identifiers.
+fn match_word_token(span: Span) -> ParseResult<CToken> {
+    let (span, start) = position(span)?;
+    let (span, text) = recognize(pair(
+        satisfy(|c| c.is_alphabetic() || c == '_'),
+        take_while(|c: char| c.is_alphanumeric() || c == '_'),
+    ))(span)?;
+    let (span, end) = position(span)?;
+    let value = spanned(start, end, text.fragment());
+
+    if is_c_keyword(text.fragment()) {
+        Ok((span, CToken::Keyword(value)))
+    } else {
+        Ok((span, CToken::Identifier(value)))
+    }
+}
+
+/// Match a numeric literal. We're loose about the syntax: a number is a digit
+/// (or a period followed by a digit) followed by any alphanumerics and periods,
+/// plus signs directly following exponent markers.
+fn match_number_token(span: Span) -> ParseResult<CToken> {
+    let (span, start) = position(span)?;
+    let (span, text) = recognize(tuple((
+        alt((
+            recognize(satisfy(|c| c.is_ascii_digit())),
+            recognize(pair(char('.'), satisfy(|c| c.is_ascii_digit()))),
+        )),
+        many0_count(alt((
+            recognize(pair(one_of("eEpP"), opt(one_of("+-")))),
+            take_while1(|c: char| c.is_ascii_alphanumeric() || c == '.'),
+        ))),
+    )))(span)?;
+    let (span, end) = position(span)?;
+
+    let t = text.fragment();
+    let is_hex = t.starts_with("0x") || t.starts_with("0X");
+    let value = spanned(start, end, t);
+
+    if t.contains('.') || (!is_hex && t.contains(['e', 'E'])) {
+        Ok((span, CToken::FloatLiteral(value)))
+    } else {
+        Ok((span, CToken::IntLiteral(value)))
+    }
+}
+
+/// Match a string or character literal delimited by `delim`, with backslash
+/// escapes.
+fn match_quoted<'a>(delim: char) -> impl Fn(Span<'a>) -> ParseResult<'a, StringSpan<'a>> {
+    move |span: Span<'a>| {
+        let (span, start) = position(span)?;
+        let (span, text) = recognize(tuple((
+            char(delim),
+            many0_count(alt((
+                recognize(pair(char('\\'), anychar)),
+                recognize(none_of(if delim == '"' { "\"\\\n" } else { "'\\\n" })),
+            ))),
+            char(delim),
+        )))(span)?;
+        let (span, end) = position(span)?;
+        Ok((span, spanned(start, end, text.fragment())))
+    }
+}
+
+fn match_string_literal(span: Span) -> ParseResult<CToken> {
+    let (span, s) = match_quoted('"')(span)?;
+    Ok((span, CToken::StringLiteral(s)))
+}
+
+fn match_char_literal(span: Span) -> ParseResult<CToken> {
+    let (span, s) = match_quoted('\'')(span)?;
+    Ok((span, CToken::CharLiteral(s)))
+}
+
+fn match_preprocessor_token(span: Span) -> ParseResult<CToken> {
+    let (rest, _) = tuple((char('#'), take_while(|c| c == ' ' || c == '\t')))(span)?;
+    let (_, word) = take_while1(|c: char| c.is_ascii_alphabetic())(rest)?;
+
+    if !C_DIRECTIVES.contains(word.fragment()) {
+        return new_parse_error(span, ErrorKind::Tag);
+    }
+
+    let (span, start) = position(span)?;
+    let (span, text) = take_while(|c| c != '\n')(span)?;
+    let (span, end) = position(span)?;
+    Ok((
+        span,
+        CToken::Preprocessor(spanned(start, end, text.fragment().trim_end())),
+    ))
+}
+
+fn match_operator_token(span: Span) -> ParseResult<CToken> {
+    let (span, start) = position(span)?;
+
+    for op in C_OPERATORS {
+        if let Ok((rest, text)) = tag::<_, _, (Span, ErrorKind)>(*op)(span) {
+            let (rest, end) = position(rest)?;
+            return Ok((rest, CToken::Operator(spanned(start, end, text.fragment()))));
+        }
+    }
+
+    new_parse_error(span, ErrorKind::Tag)
+}
+
+/// Match the start of a comment, skipping leading whitespace. Returns true for
+/// a `/* ... */` block comment and false for a `//` line comment.
+pub fn match_c_comment_start(span: Span) -> ParseResult<bool> {
+    let (span, _) = take_while(|c| c == ' ' || c == '\t' || c == '\n')(span)?;
+    alt((value(true, tag("/*")), value(false, tag("//"))))(span)
+}
+
+/// Match the end of a comment: `*/` for a block comment, or a newline for a
+/// line comment.
+pub fn match_c_comment_end<'a>(block: bool) -> impl Fn(Span<'a>) -> ParseResult<'a, Span<'a>> {
+    move |span: Span<'a>| {
+        if block {
+            tag("*/")(span)
+        } else {
+            tag("\n")(span)
+        }
+    }
+}
+
+/// Match the next C token, skipping leading whitespace.
+///
+/// Comments are *not* handled here, since they contain TeX and so need to be
+/// processed by the caller.
+pub fn match_c_token(span: Span) -> ParseResult<CToken> {
+    let (span, _) = take_while(|c| c == ' ' || c == '\t' || c == '\n')(span)?;
+    alt((
+        match_tex_string_token,
+        match_c_control_code_token,
+        match_index_entry,
+        match_word_token,
+        match_number_token,
+        match_string_literal,
+        match_char_literal,
+        match_preprocessor_token,
+        match_operator_token,
+    ))(span)
+}
+
+// Prettification
+
+impl<'a> RenderInline for CToken<'a> {
+    fn measure_inline(&self) -> usize {
+        self.text().map(|t| t.chars().count()).unwrap_or(0)
+    }
+
+    fn render_inline(&self, dest: &mut Prettifier) {
+        match self {
+            CToken::Keyword(s) | CToken::Preprocessor(s) => dest.scope_push(*KEYWORD_SCOPE, s),
+            CToken::IntLiteral(s) => dest.scope_push(*DECIMAL_LITERAL_SCOPE, s),
+            CToken::FloatLiteral(s) => dest.scope_push(*FLOAT_LITERAL_SCOPE, s),
+            CToken::StringLiteral(s) | CToken::CharLiteral(s) => {
+                dest.scope_push(*STRING_LITERAL_SCOPE, s)
+            }
+            CToken::Identifier(s) | CToken::Operator(s) | CToken::TexString(s) => {
+                dest.noscope_push(s)
+            }
+            _ => {}
+        }
+    }
+}
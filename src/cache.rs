@@ -0,0 +1,158 @@
+//! Reusing the layout of code from an earlier run.
+//!
+//! When a large WEB is rewoven after an edit, most of its code hasn't changed,
+//! but laying out and highlighting all of it again takes a good part of the
+//! time. If [`crate::WeaveOptions::cache`] is set, the second pass looks up
+//! each fragment of code in it before laying the fragment out, and
+//! [`crate::WovenDocument::cache`] holds the fragments laid out by the run,
+//! ready to be saved for the next one.
+//!
+//! Fragments are keyed by a hash of their tokens and of everything else that
+//! affects their layout: the width, the pragmas, the theme, and so on. The
+//! positions of the tokens are left out, so that editing one module doesn't
+//! invalidate all of the ones after it. The code is still parsed, since the
+//! checks done once all of the code has been seen need the parse of every
+//! fragment.
+//!
+//! The hash is [FNV-1a], rather than the standard library's default, whose
+//! algorithm may change between Rust releases. A cache can then be reused by
+//! a build of the same version of this crate made with another compiler.
+//!
+//! [FNV-1a]: http://www.isthe.com/chongo/tech/comp/fnv/
+
+use std::{collections::HashMap, fs, hash::Hasher, path::Path};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use tectonic_errors::prelude::*;
+
+use crate::pass2::Prettified;
+
+/// Laid-out and highlighted fragments of code, keyed by a hash of their
+/// contents.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CodeCache {
+    /// The version of this crate that laid out the code. A cache written by
+    /// any other version is ignored, since the layout may have changed.
+    version: String,
+
+    entries: HashMap<u64, Prettified>,
+}
+
+impl Default for CodeCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CodeCache {
+    /// Create an empty cache.
+    pub fn new() -> Self {
+        CodeCache {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Load a cache saved by [`CodeCache::save`]. If the file doesn't exist,
+    /// or can't be used, the cache is empty.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+
+        if !path.is_file() {
+            return Ok(Self::new());
+        }
+
+        let text = atry!(
+            fs::read_to_string(path);
+            ["failed to read cache file `{}`", path.display()]
+        );
+
+        match serde_json::from_str::<CodeCache>(&text) {
+            Ok(cache) if cache.version == env!("CARGO_PKG_VERSION") => Ok(cache),
+            Ok(_) => Ok(Self::new()),
+            Err(e) => {
+                warn!("ignoring unreadable cache file `{}`: {}", path.display(), e);
+                Ok(Self::new())
+            }
+        }
+    }
+
+    /// Save the cache to a file.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let text = serde_json::to_string(self)?;
+        atry!(
+            fs::write(path, text);
+            ["failed to write cache file `{}`", path.display()]
+        );
+        Ok(())
+    }
+
+    /// Get the number of fragments of code in the cache.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check whether the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Add all of the fragments of another cache to this one, as when several
+    /// files share a cache.
+    pub fn merge(&mut self, other: CodeCache) {
+        self.entries.extend(other.entries);
+    }
+
+    /// Look up a fragment of code.
+    pub(crate) fn get(&self, key: u64) -> Option<&Prettified> {
+        self.entries.get(&key)
+    }
+
+    /// Add a fragment of code.
+    pub(crate) fn insert(&mut self, key: u64, code: Prettified) {
+        self.entries.insert(key, code);
+    }
+}
+
+/// The 64-bit FNV-1a hash used for the keys of the cache.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct KeyHasher(u64);
+
+impl Default for KeyHasher {
+    fn default() -> Self {
+        KeyHasher(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for KeyHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= u64::from(*b);
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_hasher() {
+        let hash = |bytes: &[u8]| {
+            let mut hasher = KeyHasher::default();
+            hasher.write(bytes);
+            hasher.finish()
+        };
+
+        assert_eq!(hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(hash(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+}
@@ -0,0 +1,276 @@
+//! The procedure call graph of a program.
+//!
+//! In a WEB program, the body of a procedure is usually spread across a tree of
+//! named modules, and many of its calls are hidden inside macros, so the calls
+//! that it makes can't be read off of its definition. As each fragment of code
+//! is woven, we record the identifiers and module references in each procedure
+//! or function, named module, and macro, along with the main program. Once all
+//! of the code has been seen, we follow the module references and macros out
+//! from each procedure, keeping the identifiers that the [`SymbolTable`] says
+//! are procedures or functions. The result is
+//! available as [`crate::WovenDocument::call_graph`], and can be written out
+//! for Graphviz with [`CallGraph::write_dot`].
+//!
+//! Procedures that are passed around as values, rather than called, count as
+//! calls, and so do calls in code that couldn't be parsed. Assignments to the
+//! name of a function, which set its return value, don't. If only some
+//! modules were woven, calls made by the code of the other modules are missed.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    io,
+};
+
+use crate::{
+    parse_base::StringSpan,
+    state::ModuleId,
+    symbols::normalize,
+    symtab::{SymbolKind, SymbolTable},
+    weblang::{
+        base::PascalToken, module_reference::WebModuleReference, visit::Visitor, WebAssignment,
+        WebCode, WebExpr, WebFunctionDefinition,
+    },
+};
+
+/// Something whose code can make calls.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+enum Owner {
+    /// The main program: unnamed code outside of any procedure.
+    Program,
+
+    /// A procedure or function, by normalized name.
+    Function(String),
+
+    /// The code of a named module.
+    Module(ModuleId),
+
+    /// A macro, by normalized name.
+    Macro(String),
+}
+
+/// Something that code refers to, which may lead to calls.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+enum Target {
+    /// An identifier, normalized.
+    Identifier(String),
+
+    /// A named module.
+    Module(ModuleId),
+}
+
+/// Gather up what a fragment of code refers to.
+struct Collector<'b> {
+    builder: &'b mut CallGraphBuilder,
+
+    /// Whose code is being visited.
+    owner: Owner,
+}
+
+impl<'a, 'b> Visitor<'a> for Collector<'b> {
+    fn visit_function_definition(&mut self, func: &WebFunctionDefinition<'a>) {
+        let key = normalize(&func.name().value);
+        self.builder
+            .names
+            .entry(key.clone())
+            .or_insert_with(|| func.name().value.to_string());
+
+        let outer = std::mem::replace(&mut self.owner, Owner::Function(key));
+        func.walk(self);
+        self.owner = outer;
+    }
+
+    fn visit_assignment(&mut self, assignment: &WebAssignment<'a>) {
+        // Assigning to a bare identifier doesn't call it, even if it's the
+        // name of a function: that sets the function's return value.
+        if let WebExpr::Token(PascalToken::Identifier(_)) = assignment.lhs().node {
+            self.visit_expr(assignment.rhs());
+        } else {
+            assignment.walk(self);
+        }
+    }
+
+    fn visit_identifier(&mut self, ident: &StringSpan<'a>) {
+        let target = Target::Identifier(normalize(&ident.value));
+        self.builder
+            .refs
+            .entry(self.owner.clone())
+            .or_default()
+            .insert(target);
+    }
+
+    fn visit_defined_identifier(&mut self, _ident: &StringSpan<'a>) {}
+
+    fn visit_module_reference(&mut self, mref: &WebModuleReference<'a>) {
+        self.builder
+            .refs
+            .entry(self.owner.clone())
+            .or_default()
+            .insert(Target::Module(mref.id));
+    }
+}
+
+/// Gathers up what the code of each procedure, named module, and macro refers
+/// to, so that the call graph can be worked out once all of the code has been
+/// seen.
+#[derive(Debug, Default)]
+pub(crate) struct CallGraphBuilder {
+    /// What each piece of code refers to.
+    refs: HashMap<Owner, HashSet<Target>>,
+
+    /// The name of each procedure or function as it's spelled in its
+    /// definition, keyed by its normalized form.
+    names: HashMap<String, String>,
+}
+
+impl CallGraphBuilder {
+    /// Record what a fragment of code refers to. If the code is the body of a
+    /// named module, `module` is the module's ID; if it's a macro definition,
+    /// `macro_name` is the name of the macro; and otherwise it's part of the
+    /// main program.
+    pub fn add(
+        &mut self,
+        code: &WebCode,
+        module: Option<ModuleId>,
+        macro_name: Option<&StringSpan>,
+    ) {
+        let owner = match (module, macro_name) {
+            (Some(id), _) => Owner::Module(id),
+            (None, Some(name)) => Owner::Macro(normalize(&name.value)),
+            (None, None) => Owner::Program,
+        };
+
+        let mut collector = Collector {
+            builder: self,
+            owner,
+        };
+
+        collector.visit_code(code);
+    }
+
+    /// Work out which procedures and functions call which, using `symbols` to
+    /// tell which identifiers are procedures and functions.
+    pub fn finish(self, symbols: &SymbolTable) -> CallGraph {
+        let is_callable = |key: &str| {
+            matches!(
+                symbols.kind(key),
+                Some(SymbolKind::Procedure | SymbolKind::Function)
+            )
+        };
+
+        let name_of = |key: &str| {
+            self.names
+                .get(key)
+                .cloned()
+                .unwrap_or_else(|| key.to_owned())
+        };
+
+        let mut calls = BTreeMap::new();
+
+        for owner in self.refs.keys() {
+            let caller = match owner {
+                Owner::Program => symbols
+                    .iter()
+                    .find(|s| s.kind == SymbolKind::Program)
+                    .map(|s| s.name.clone())
+                    .unwrap_or_else(|| "program".to_owned()),
+                Owner::Function(key) => name_of(key),
+                Owner::Module(_) | Owner::Macro(_) => continue,
+            };
+
+            // Follow the module references and macros, which are expanded
+            // into the caller's code.
+
+            let mut callees = BTreeSet::new();
+            let mut seen = HashSet::new();
+            let mut todo = vec![owner.clone()];
+
+            while let Some(o) = todo.pop() {
+                if !seen.insert(o.clone()) {
+                    continue;
+                }
+
+                for target in self.refs.get(&o).into_iter().flatten() {
+                    match target {
+                        Target::Module(id) => todo.push(Owner::Module(*id)),
+
+                        Target::Identifier(key) => {
+                            let m = Owner::Macro(key.clone());
+
+                            if self.refs.contains_key(&m) {
+                                todo.push(m);
+                            } else if is_callable(key) {
+                                callees.insert(name_of(key));
+                            }
+                        }
+                    }
+                }
+            }
+
+            calls.insert(caller, callees);
+        }
+
+        for key in self.names.keys() {
+            calls.entry(name_of(key)).or_default();
+        }
+
+        CallGraph { calls }
+    }
+}
+
+/// Which procedures and functions call which.
+///
+/// See the [module-level documentation](self) for an overview.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CallGraph {
+    /// The procedures and functions called by each procedure or function, and
+    /// by the main program, by name.
+    calls: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl CallGraph {
+    /// Get the procedures and functions that a procedure or function calls, in
+    /// alphabetical order. The main program is named as in its `program`
+    /// header.
+    pub fn callees(&self, name: &str) -> impl Iterator<Item = &str> {
+        self.calls
+            .get(name)
+            .into_iter()
+            .flatten()
+            .map(|s| s.as_str())
+    }
+
+    /// Get the procedures and functions that call a procedure or function, in
+    /// alphabetical order.
+    pub fn callers<'g>(&'g self, name: &'g str) -> impl Iterator<Item = &'g str> {
+        self.calls
+            .iter()
+            .filter(move |(_, callees)| callees.contains(name))
+            .map(|(caller, _)| caller.as_str())
+    }
+
+    /// Iterate over the calls, as `(caller, callee)` pairs, in alphabetical
+    /// order.
+    pub fn calls(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.calls.iter().flat_map(|(caller, callees)| {
+            callees
+                .iter()
+                .map(move |callee| (caller.as_str(), callee.as_str()))
+        })
+    }
+
+    /// Write the call graph in the DOT language, for Graphviz. Every procedure
+    /// and function is a node, even if it makes no calls and is never called.
+    pub fn write_dot<W: io::Write>(&self, mut dest: W) -> io::Result<()> {
+        writeln!(dest, "digraph calls {{")?;
+
+        for name in self.calls.keys() {
+            writeln!(dest, "  \"{}\";", name)?;
+        }
+
+        for (caller, callee) in self.calls() {
+            writeln!(dest, "  \"{}\" -> \"{}\";", caller, callee)?;
+        }
+
+        writeln!(dest, "}}")
+    }
+}
@@ -0,0 +1,126 @@
+//! A C API, for use by editor plugins and build systems that aren't written in
+//! Rust.
+//!
+//! This is only available with the `capi` feature. Build the crate's `cdylib`
+//! with something like `cargo build --release --lib --features capi`, and
+//! declare the functions like so:
+//!
+//! ```c
+//! char *ttweave_prettify_section(const char *text, const char *dialect);
+//! const char *ttweave_last_error(void);
+//! void ttweave_free_string(char *s);
+//! ```
+//!
+//! Strings are NUL-terminated UTF-8.
+
+use clap::ArgEnum;
+use std::{
+    cell::RefCell,
+    ffi::{CStr, CString},
+    os::raw::c_char,
+    panic, ptr,
+};
+use tectonic_errors::prelude::*;
+
+use crate::Dialect;
+
+thread_local! {
+    /// The message describing the most recent failure on this thread.
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Prettify the code in some WEB source text as a JSON array of HTML fragments.
+fn prettify_section(text: &CStr, dialect: Option<&CStr>) -> Result<String> {
+    let text = atry!(
+        text.to_str();
+        ["the input text is not valid UTF-8"]
+    );
+
+    let dialect = match dialect {
+        Some(d) => {
+            let d = atry!(
+                d.to_str();
+                ["the dialect is not valid UTF-8"]
+            );
+            Dialect::from_str(d, true).map_err(|e| anyhow!(e))?
+        }
+
+        None => Dialect::default(),
+    };
+
+    let theme = &crate::themes::default_theme();
+    let state = crate::scan(text, dialect)?;
+    let fragments = crate::weave_html(text, &state, theme, None)?;
+    Ok(serde_json::to_string(&fragments)?)
+}
+
+/// Prettify the code in some WEB source text as HTML.
+///
+/// This is the C analogue of `prettifyHtml` in the `wasm` module: the text
+/// should consist of one or more complete modules, optionally preceded by limbo
+/// material, and `dialect` is one of `web`, `web2c`, or `cweb`, or null to
+/// assume `web2c`. The return value is a JSON array with one entry for each
+/// fragment of code, which must be freed with [`ttweave_free_string`]. On
+/// failure, null is returned, and [`ttweave_last_error`] describes the problem.
+///
+/// # Safety
+///
+/// `text` must point to a NUL-terminated string, and `dialect` must either be
+/// null or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn ttweave_prettify_section(
+    text: *const c_char,
+    dialect: *const c_char,
+) -> *mut c_char {
+    let result = if text.is_null() {
+        Err(anyhow!("the input text is null"))
+    } else {
+        let text = CStr::from_ptr(text);
+        let dialect = if dialect.is_null() {
+            None
+        } else {
+            Some(CStr::from_ptr(dialect))
+        };
+
+        // Don't let a panic unwind into C code.
+        panic::catch_unwind(|| prettify_section(text, dialect))
+            .unwrap_or_else(|_| Err(anyhow!("an internal error occurred while prettifying")))
+            .and_then(|json| Ok(CString::new(json)?))
+    };
+
+    match result {
+        Ok(s) => {
+            LAST_ERROR.with(|e| *e.borrow_mut() = None);
+            s.into_raw()
+        }
+
+        Err(err) => {
+            let msg = format!("{:#}", err).replace('\0', " ");
+            LAST_ERROR.with(|e| *e.borrow_mut() = CString::new(msg).ok());
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Get a description of the most recent failure on the current thread, or null
+/// if the most recent call succeeded.
+///
+/// The returned string is owned by the library, and is only valid until the
+/// next call to [`ttweave_prettify_section`] on the same thread.
+#[no_mangle]
+pub extern "C" fn ttweave_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(ptr::null(), |s| s.as_ptr()))
+}
+
+/// Free a string returned by this library.
+///
+/// # Safety
+///
+/// `s` must either be null or a string returned by
+/// [`ttweave_prettify_section`] that hasn't already been freed.
+#[no_mangle]
+pub unsafe extern "C" fn ttweave_free_string(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
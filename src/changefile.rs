@@ -0,0 +1,486 @@
+//! Change files.
+//!
+//! A change file modifies a WEB file without editing it, which is how most
+//! TeX-related programs are adapted to new systems. It consists of a series of
+//! changes like this:
+//!
+//! ```text
+//! @x
+//! lines to be replaced
+//! @y
+//! replacement lines
+//! @z
+//! ```
+//!
+//! The lines to be replaced must match a consecutive run of lines in the WEB
+//! file, ignoring trailing whitespace, and the changes must appear in the same
+//! order as the lines that they replace. Anything outside of the changes is
+//! ignored. We apply change files up front, so that the rest of the processing
+//! doesn't need to know about them.
+//!
+//! Changes that add or remove modules renumber all of the modules that follow.
+//! [`renumbering`] works out the correspondence between the old numbers and
+//! the new ones, and [`apply_change_file_renumbered`] updates the explicit
+//! references to modules in the text accordingly.
+
+use std::{collections::HashMap, fmt::Write};
+use tectonic_errors::prelude::*;
+
+use crate::state::ModuleId;
+
+/// A single change.
+#[derive(Debug)]
+struct Change<'a> {
+    /// The line number of the `@x` line in the change file, for error reporting.
+    line: usize,
+
+    /// The lines to be replaced.
+    old: Vec<&'a str>,
+
+    /// The replacement lines.
+    new: Vec<&'a str>,
+}
+
+/// Determine whether a change file line starts with the given control code,
+/// case-insensitively.
+fn is_control_line(line: &str, code: char) -> bool {
+    let mut chars = line.chars();
+    chars.next() == Some('@') && chars.next().map(|c| c.to_ascii_lowercase()) == Some(code)
+}
+
+/// Parse the text of a change file into its changes.
+fn parse_changes(changes: &str) -> Result<Vec<Change<'_>>> {
+    let mut result = Vec::new();
+    let mut lines = changes.split_inclusive('\n').enumerate();
+
+    while let Some((n, line)) = lines.next() {
+        if !is_control_line(line, 'x') {
+            continue;
+        }
+
+        let mut change = Change {
+            line: n + 1,
+            old: Vec::new(),
+            new: Vec::new(),
+        };
+        let mut in_new = false;
+        let mut terminated = false;
+
+        for (_, line) in lines.by_ref() {
+            if is_control_line(line, 'y') && !in_new {
+                in_new = true;
+            } else if is_control_line(line, 'z') && in_new {
+                terminated = true;
+                break;
+            } else if is_control_line(line, 'x')
+                || is_control_line(line, 'y')
+                || is_control_line(line, 'z')
+            {
+                bail!(
+                    "change starting on line {} of the change file is malformed",
+                    change.line
+                );
+            } else if in_new {
+                change.new.push(line);
+            } else if !change.old.is_empty() || !line.trim().is_empty() {
+                // Like WEAVE, we skip blank lines at the start of a change.
+                change.old.push(line);
+            }
+        }
+
+        if !terminated {
+            bail!(
+                "change starting on line {} of the change file is not terminated",
+                change.line
+            );
+        }
+
+        if change.old.is_empty() {
+            bail!(
+                "change starting on line {} of the change file has nothing to match",
+                change.line
+            );
+        }
+
+        result.push(change);
+    }
+
+    Ok(result)
+}
+
+/// A run of lines of a WEB file, along with the lines that take their place
+/// once a change file is applied. If the change file doesn't touch the lines,
+/// both are the same.
+#[derive(Debug)]
+struct Segment<'a> {
+    /// The lines of the WEB file.
+    old: &'a [&'a str],
+
+    /// The lines that replace them.
+    new: &'a [&'a str],
+
+    /// Whether the lines are replaced by a change.
+    changed: bool,
+}
+
+/// Match the changes against the lines of a WEB file, dividing the file into
+/// the runs of lines that are left alone and the runs that are replaced.
+fn segments<'a>(web_lines: &'a [&'a str], changes: &'a [Change<'a>]) -> Result<Vec<Segment<'a>>> {
+    let mut result = Vec::new();
+    let mut pos = 0;
+
+    for change in changes {
+        let n = change.old.len();
+
+        let found = (pos..web_lines.len().saturating_sub(n - 1)).find(|&i| {
+            web_lines[i..i + n]
+                .iter()
+                .zip(&change.old)
+                .all(|(w, c)| w.trim_end() == c.trim_end())
+        });
+
+        let start = a_ok_or!(
+            found;
+            ["change starting on line {} of the change file does not match the WEB file", change.line]
+        );
+
+        let unchanged = &web_lines[pos..start];
+        result.push(Segment {
+            old: unchanged,
+            new: unchanged,
+            changed: false,
+        });
+        result.push(Segment {
+            old: &web_lines[start..start + n],
+            new: &change.new[..],
+            changed: true,
+        });
+        pos = start + n;
+    }
+
+    let unchanged = &web_lines[pos..];
+    result.push(Segment {
+        old: unchanged,
+        new: unchanged,
+        changed: false,
+    });
+    Ok(result)
+}
+
+/// Apply a change file to the text of a WEB file, returning the modified text.
+pub fn apply_change_file(web: &str, changes: &str) -> Result<String> {
+    let changes = parse_changes(changes)?;
+    let web_lines: Vec<&str> = web.split_inclusive('\n').collect();
+    let mut result = String::with_capacity(web.len());
+
+    for segment in segments(&web_lines, &changes)? {
+        for line in segment.new {
+            result.push_str(line);
+        }
+    }
+
+    Ok(result)
+}
+
+/// What becomes of a module when a change file is applied.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Renumbered {
+    /// The module with the first number in the WEB file has the second number
+    /// once the change file is applied.
+    Kept(ModuleId, ModuleId),
+
+    /// The change file removes the module with this number.
+    Removed(ModuleId),
+
+    /// The change file adds a module, which gets this number.
+    Added(ModuleId),
+}
+
+/// Count the modules that start in some lines of WEB source: the control
+/// codes `@ `, `@*`, and `@` at the end of a line.
+fn count_module_starts(lines: &[&str]) -> usize {
+    let mut n = 0;
+
+    for line in lines {
+        let mut chars = line.chars();
+
+        while let Some(c) = chars.next() {
+            if c != '@' {
+                continue;
+            }
+
+            // A missing character means that the last line has no newline.
+            if let None | Some(' ' | '\t' | '\n' | '\r' | '*') = chars.next() {
+                n += 1;
+            }
+        }
+    }
+
+    n
+}
+
+/// Work out how a change file renumbers the modules of a WEB file.
+///
+/// The result lists every module of the WEB file and every module added by the
+/// change file, in order. When a change replaces lines in which some modules
+/// start, the modules that start in the replacement lines take their places in
+/// order, and any left over are removed or added. So, a change that rewrites a
+/// module keeps it, while one that splits a module in two adds a module.
+pub fn renumbering(web: &str, changes: &str) -> Result<Vec<Renumbered>> {
+    let changes = parse_changes(changes)?;
+    let web_lines: Vec<&str> = web.split_inclusive('\n').collect();
+    let mut result = Vec::new();
+    let (mut old_id, mut new_id) = (0, 0);
+
+    for segment in segments(&web_lines, &changes)? {
+        let n_old = count_module_starts(segment.old);
+        let n_new = count_module_starts(segment.new);
+        let n_kept = n_old.min(n_new);
+
+        for _ in 0..n_kept {
+            old_id += 1;
+            new_id += 1;
+            result.push(Renumbered::Kept(old_id, new_id));
+        }
+
+        for _ in n_kept..n_old {
+            old_id += 1;
+            result.push(Renumbered::Removed(old_id));
+        }
+
+        for _ in n_kept..n_new {
+            new_id += 1;
+            result.push(Renumbered::Added(new_id));
+        }
+    }
+
+    Ok(result)
+}
+
+/// If an explicit reference to a module starts at the beginning of `text`,
+/// return the length of the text introducing its number: `\S`, `§`, `section`,
+/// or `module`, along with an optional space or tie.
+fn reference_prefix(text: &str) -> Option<usize> {
+    let n = if text.starts_with("\\S") && !text[2..].starts_with(|c: char| c.is_ascii_alphabetic())
+    {
+        2
+    } else if text.starts_with('§') {
+        '§'.len_utf8()
+    } else {
+        ["section", "module"]
+            .iter()
+            .find(|w| {
+                text.get(..w.len())
+                    .is_some_and(|t| t.eq_ignore_ascii_case(w))
+            })?
+            .len()
+    };
+
+    if text[n..].starts_with([' ', '~']) {
+        Some(n + 1)
+    } else {
+        Some(n)
+    }
+}
+
+/// Rewrite the explicit references to modules in a line, like `\S123` or
+/// `section~123`, according to `map`.
+fn rewrite_references(line: &str, map: &HashMap<ModuleId, ModuleId>, dest: &mut String) {
+    let mut rest = line;
+    let mut prev = None;
+
+    while let Some(c) = rest.chars().next() {
+        let prefix = if prev.is_some_and(|p: char| p.is_ascii_alphanumeric()) {
+            None
+        } else {
+            reference_prefix(rest)
+        };
+
+        if let Some(n) = prefix {
+            let digits = rest[n..]
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len() - n);
+
+            if let Some(new) = rest[n..n + digits]
+                .parse::<ModuleId>()
+                .ok()
+                .and_then(|old| map.get(&old))
+            {
+                write!(dest, "{}{}", &rest[..n], new).unwrap();
+                prev = rest[..n + digits].chars().next_back();
+                rest = &rest[n + digits..];
+                continue;
+            }
+        }
+
+        dest.push(c);
+        prev = Some(c);
+        rest = &rest[c.len_utf8()..];
+    }
+}
+
+/// Apply a change file to the text of a WEB file, like [`apply_change_file`],
+/// and update the explicit references to modules in the lines that the change
+/// file leaves alone, so that they refer to the modules' new numbers.
+///
+/// References are recognized textually, as `\S`, `§`, `section`, or `module`
+/// followed by a number, optionally separated by a space or a tie. References
+/// to removed modules are left as they are, as are the lines that the change
+/// file supplies, which are presumably already written for the new numbers.
+pub fn apply_change_file_renumbered(web: &str, changes: &str) -> Result<String> {
+    let map: HashMap<_, _> = renumbering(web, changes)?
+        .into_iter()
+        .filter_map(|r| match r {
+            Renumbered::Kept(old, new) if old != new => Some((old, new)),
+            _ => None,
+        })
+        .collect();
+
+    let changes = parse_changes(changes)?;
+    let web_lines: Vec<&str> = web.split_inclusive('\n').collect();
+    let mut result = String::with_capacity(web.len());
+
+    for segment in segments(&web_lines, &changes)? {
+        for line in segment.new {
+            if segment.changed {
+                result.push_str(line);
+            } else {
+                rewrite_references(line, &map, &mut result);
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WEB: &str = "\
+@ First.
+@p a:=1;
+@ Second, see \\S1.
+@p b:=2;
+@ Third, see section~2.
+@p c:=3;
+";
+
+    #[test]
+    fn matched_changes() {
+        let changes = "\
+Lines outside of changes are ignored.
+@x
+@p a:=1;
+@y
+@p a:=10;
+@z
+@X
+
+@p c:=3;   
+@Y
+@p c:=30;
+@Z
+";
+        assert_eq!(
+            apply_change_file(WEB, changes).unwrap(),
+            "\
+@ First.
+@p a:=10;
+@ Second, see \\S1.
+@p b:=2;
+@ Third, see section~2.
+@p c:=30;
+"
+        );
+    }
+
+    #[test]
+    fn malformed_changes() {
+        let unterminated = "@x\n@p a:=1;\n@y\n@p a:=10;\n";
+        assert!(apply_change_file(WEB, unterminated).is_err());
+
+        let missing_y = "@x\n@p a:=1;\n@z\n";
+        assert!(apply_change_file(WEB, missing_y).is_err());
+
+        let empty = "@x\n\n@y\n@p a:=10;\n@z\n";
+        assert!(apply_change_file(WEB, empty).is_err());
+    }
+
+    #[test]
+    fn unmatched_changes() {
+        let missing = "@x\n@p d:=4;\n@y\n@p d:=40;\n@z\n";
+        assert!(apply_change_file(WEB, missing).is_err());
+
+        // Changes must come in the same order as the lines they replace.
+        let out_of_order = "\
+@x
+@p b:=2;
+@y
+@z
+@x
+@p a:=1;
+@y
+@z
+";
+        assert!(apply_change_file(WEB, out_of_order).is_err());
+    }
+
+    #[test]
+    fn renumbered_modules() {
+        // Split the first module in two and remove the second.
+        let changes = "\
+@x
+@p a:=1;
+@y
+@p a:=1;
+@ Inserted.
+@p z:=0;
+@z
+@x
+@ Second, see \\S1.
+@p b:=2;
+@y
+@z
+";
+        assert_eq!(
+            renumbering(WEB, changes).unwrap(),
+            [
+                Renumbered::Kept(1, 1),
+                Renumbered::Added(2),
+                Renumbered::Removed(2),
+                Renumbered::Kept(3, 3),
+            ]
+        );
+
+        let changes = "\
+@x
+@ First.
+@y
+@ Zeroth.
+@ First.
+@z
+";
+        assert_eq!(
+            renumbering(WEB, changes).unwrap(),
+            [
+                Renumbered::Kept(1, 1),
+                Renumbered::Added(2),
+                Renumbered::Kept(2, 3),
+                Renumbered::Kept(3, 4),
+            ]
+        );
+        assert_eq!(
+            apply_change_file_renumbered(WEB, changes).unwrap(),
+            "\
+@ Zeroth.
+@ First.
+@p a:=1;
+@ Second, see \\S1.
+@p b:=2;
+@ Third, see section~3.
+@p c:=3;
+"
+        );
+    }
+}
@@ -0,0 +1,100 @@
+//! Extracting the text of the comments in the code.
+//!
+//! The comments in the Pascal code of a WEB program are written in TeX, with
+//! bits of code quoted in `|...|`. Tools like spell-checkers and translation
+//! aids don't understand either, so [`crate::extract_comments`] finds each
+//! comment and approximates it as plain text, along with where it appears.
+//!
+//! The conversion doesn't try to interpret the TeX: control sequences are
+//! replaced by their names, so that `\TeX` becomes `TeX`, escaped characters
+//! like `\$` are kept, grouping braces and math shifts are dropped, and runs of
+//! whitespace become single spaces. Code quoted in `|...|` is kept as written,
+//! bars included, so that it can be told apart from the prose.
+//!
+//! Only the comments in the code are extracted, not the TeX commentary of the
+//! modules, which is plain TeX that other tools already handle. Comments in
+//! code that couldn't be parsed are included too.
+
+use serde::Serialize;
+use std::ops::Range;
+
+use crate::state::ModuleId;
+
+/// A comment in the code, as plain text.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct CommentText {
+    /// The module in which the comment appears.
+    pub module: ModuleId,
+
+    /// The context of the code containing the comment: `named`, `anonymous`,
+    /// `define`, `format`, or `inline`.
+    pub context: &'static str,
+
+    /// The bytes of the source text occupied by the comment, including its
+    /// braces. Use [`crate::diagnostics::line_and_column`] to find its line.
+    pub range: Range<usize>,
+
+    /// The text of the comment.
+    pub text: String,
+}
+
+/// Approximate the source text of a comment, with its braces, as plain text.
+pub(crate) fn comment_text(source: &str) -> String {
+    let inner = source.strip_prefix('{').unwrap_or(source);
+    let inner = inner.strip_suffix('}').unwrap_or(inner);
+
+    let mut text = String::with_capacity(inner.len());
+    let mut chars = inner.chars().peekable();
+    let mut in_code = false;
+
+    while let Some(c) = chars.next() {
+        // Control spaces and ties are spaces too.
+        let c = match c {
+            '\\' if !in_code && chars.peek().is_some_and(|c| c.is_whitespace()) => ' ',
+            '~' if !in_code => ' ',
+            c => c,
+        };
+
+        if c.is_whitespace() {
+            if !text.is_empty() && !text.ends_with(' ') {
+                text.push(' ');
+            }
+
+            continue;
+        }
+
+        match c {
+            '|' => {
+                text.push(c);
+                in_code = !in_code;
+            }
+
+            // WEB's escape for an at sign.
+            '@' if chars.peek() == Some(&'@') => {
+                chars.next();
+                text.push('@');
+            }
+
+            _ if in_code => text.push(c),
+
+            '\\' => match chars.next() {
+                Some(c) if c.is_ascii_alphabetic() => {
+                    text.push(c);
+
+                    while let Some(c) = chars.next_if(|c| c.is_ascii_alphabetic()) {
+                        text.push(c);
+                    }
+                }
+
+                Some(c) => text.push(c),
+                None => {}
+            },
+
+            '{' | '}' | '$' => {}
+            other => text.push(other),
+        }
+    }
+
+    text.truncate(text.trim_end().len());
+    text
+}
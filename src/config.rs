@@ -0,0 +1,186 @@
+//! Configuration files.
+//!
+//! Rather than passing the same formatting options on the command line every
+//! time, a project can pin its style in a configuration file named
+//! `tt-weave.toml`, like this:
+//!
+//! ```toml
+//! # The width, in characters, to which the code is laid out.
+//! width = 72
+//!
+//! # The width to which code in the TeX parts of modules is laid out, and how
+//! # many lines it can take before it's displayed rather than set inline.
+//! inline_width = 100
+//! inline_max_lines = 1
+//!
+//! # How many levels of indentation the code may be nested in before it's no
+//! # longer wrapped to fit the width.
+//! max_depth = 12
+//!
+//! # The name of the theme, among syntect's defaults, used to highlight the
+//! # code.
+//! theme = "Solarized (light)"
+//!
+//! # Whether to emit a mini-index after each module.
+//! mini_index = true
+//!
+//! # Whether to note where each procedure and function is used.
+//! function_uses = true
+//!
+//! # Whether to link uses of identifiers to their declarations.
+//! identifier_links = true
+//!
+//! # Whether to write operators like `:=` and `<>` as in Pascal.
+//! preserve_operators = true
+//!
+//! # When to write the semicolons that end statements: "always",
+//! # "as-written", or "not-before-end".
+//! semicolons = "not-before-end"
+//!
+//! # How to write integer literals: with their digits grouped, as in
+//! # `1_000_000`, with uppercase hexadecimal digits, and with octal literals
+//! # as in WEB rather than in hexadecimal.
+//! group_digits = true
+//! uppercase_hex = true
+//! preserve_octal = true
+//!
+//! # How to typeset comments: "code" or "prose".
+//! comments = "prose"
+//!
+//! # The limits enforced by the `long-module-code` and `long-module-name`
+//! # lints, when they're enabled.
+//! max_code_lines = 40
+//! max_module_name_length = 50
+//!
+//! # Custom TeX to emit in place of particular identifiers.
+//! [identifier_tex]
+//! TeX_banner = "\\TeX\\ banner"
+//! ```
+//!
+//! The `tt-weave` program looks for this file in the current directory.
+//! Options given on the command line override the ones in the file. The flags
+//! that switch something on, like `--mini-index`, have counterparts like
+//! `--no-mini-index` that switch it off again.
+
+use serde::Deserialize;
+use std::{collections::HashMap, path::Path};
+use tectonic_errors::prelude::*;
+
+use crate::prettify::{CommentStyle, SemicolonPolicy};
+
+/// The name of the configuration file.
+pub const CONFIG_FILE_NAME: &str = "tt-weave.toml";
+
+/// A configuration file, as deserialized from TOML.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// The width to which the code is laid out. See
+    /// [`crate::WeaveOptions::width`].
+    #[serde(default)]
+    pub width: Option<usize>,
+
+    /// The width to which the code in the TeX parts of modules is laid out.
+    /// See [`crate::WeaveOptions::inline_width`].
+    #[serde(default)]
+    pub inline_width: Option<usize>,
+
+    /// How many lines the code in the TeX parts of modules may take before
+    /// it's displayed. See [`crate::WeaveOptions::inline_max_lines`].
+    #[serde(default)]
+    pub inline_max_lines: Option<usize>,
+
+    /// How many levels of indentation the code may be nested in before it's
+    /// no longer wrapped. See [`crate::WeaveOptions::max_depth`].
+    #[serde(default)]
+    pub max_depth: Option<usize>,
+
+    /// The name of the theme used to highlight the code.
+    #[serde(default)]
+    pub theme: Option<String>,
+
+    /// Whether to emit mini-indices.
+    #[serde(default)]
+    pub mini_index: Option<bool>,
+
+    /// Whether to annotate function definitions with where they're used. See
+    /// [`crate::WeaveOptions::function_uses`].
+    #[serde(default)]
+    pub function_uses: Option<bool>,
+
+    /// Whether to link identifiers to their declarations. See
+    /// [`crate::WeaveOptions::identifier_links`].
+    #[serde(default)]
+    pub identifier_links: Option<bool>,
+
+    /// Whether to write the operators as in Pascal. See
+    /// [`crate::prettify::CodeStyle::preserve_operators`].
+    #[serde(default)]
+    pub preserve_operators: Option<bool>,
+
+    /// When to write the semicolons that end statements. See
+    /// [`crate::prettify::SemicolonPolicy`].
+    #[serde(default)]
+    pub semicolons: Option<SemicolonPolicy>,
+
+    /// Whether to group the digits of long decimal literals. See
+    /// [`crate::prettify::CodeStyle::group_digits`].
+    #[serde(default)]
+    pub group_digits: Option<bool>,
+
+    /// Whether to write hexadecimal literals with uppercase digits.
+    #[serde(default)]
+    pub uppercase_hex: Option<bool>,
+
+    /// Whether to write octal literals in WEB's notation.
+    #[serde(default)]
+    pub preserve_octal: Option<bool>,
+
+    /// How to typeset the comments in the code. See
+    /// [`crate::prettify::CommentStyle`].
+    #[serde(default)]
+    pub comments: Option<CommentStyle>,
+
+    /// The most lines that the code of a module may take. See
+    /// [`crate::style::StyleLimits::max_code_lines`].
+    #[serde(default)]
+    pub max_code_lines: Option<usize>,
+
+    /// The most characters that a module name may have. See
+    /// [`crate::style::StyleLimits::max_module_name_length`].
+    #[serde(default)]
+    pub max_module_name_length: Option<usize>,
+
+    /// Custom TeX to emit in place of particular identifiers. See
+    /// [`crate::WeaveOptions::identifier_tex`].
+    #[serde(default)]
+    pub identifier_tex: HashMap<String, String>,
+}
+
+impl Config {
+    /// Load a configuration file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+
+        let text = atry!(
+            std::fs::read_to_string(path);
+            ["failed to read configuration file `{}`", path.display()]
+        );
+
+        Ok(atry!(
+            toml::from_str(&text);
+            ["failed to parse configuration file `{}`", path.display()]
+        ))
+    }
+
+    /// Load the configuration file in a directory, if there is one.
+    pub fn find<P: AsRef<Path>>(dir: P) -> Result<Option<Self>> {
+        let path = dir.as_ref().join(CONFIG_FILE_NAME);
+
+        if path.is_file() {
+            Ok(Some(Self::load(path)?))
+        } else {
+            Ok(None)
+        }
+    }
+}
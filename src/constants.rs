@@ -0,0 +1,332 @@
+//! The values of numeric macros.
+//!
+//! Parameterized WEB programs set their sizes with macros like
+//! `@d buf_size=500`, and then derive other sizes from those, as in
+//! `@d half_buf==(buf_size div 2)`. Readers would often like to know what the
+//! derived values come to. As the definitions are woven, in order, we evaluate
+//! the ones whose right-hand sides are integer arithmetic on literals and
+//! macros with known values, so that the definitions can be annotated with
+//! their values. As in TANGLE, a macro only has a known value after it's
+//! defined, so a definition that refers to a macro defined later isn't
+//! evaluated.
+
+use std::collections::HashMap;
+
+use crate::{
+    pascal_token::{DelimiterKind, IntLiteralKind, PascalToken, StringLiteralKind},
+    reserved::PascalReservedWord,
+    symbols::normalize,
+    weblang::base::WebToken,
+};
+
+/// Keeps track of the values of the numeric macros defined so far.
+#[derive(Debug, Default)]
+pub(crate) struct MacroValues {
+    /// The value of each macro, keyed by normalized name.
+    values: HashMap<String, isize>,
+}
+
+impl MacroValues {
+    /// Evaluate a `@d` definition, given its tokens, starting with the `@d`.
+    ///
+    /// If the right-hand side is a numeric constant, its value is recorded.
+    /// The value is returned if it's worth showing: that is, unless the
+    /// right-hand side is just a decimal number, possibly negated.
+    pub fn define(&mut self, tokens: &[WebToken]) -> Option<isize> {
+        let tokens: Vec<_> = tokens
+            .iter()
+            .filter_map(|t| t.as_pascal())
+            .filter(|t| {
+                !matches!(
+                    t,
+                    PascalToken::Formatting
+                        | PascalToken::DefinitionFlag
+                        | PascalToken::CancelDefinitionFlag
+                        | PascalToken::IndexEntry(..)
+                )
+            })
+            .collect();
+
+        let (name, rhs) = match &tokens[..] {
+            [PascalToken::ReservedWord(rw), PascalToken::Identifier(name), PascalToken::Equals | PascalToken::Equivalence, rhs @ ..]
+                if rw.value == PascalReservedWord::Define =>
+            {
+                (name, rhs)
+            }
+            _ => return None,
+        };
+
+        let key = normalize(&name.value);
+
+        let mut eval = Evaluator {
+            values: &self.values,
+            tokens: rhs,
+            pos: 0,
+            depth: 0,
+        };
+
+        let value = match eval.expr() {
+            Some(v) if eval.pos == rhs.len() => v,
+
+            _ => {
+                // A redefinition that can't be evaluated hides any earlier
+                // value.
+                self.values.remove(&key);
+                return None;
+            }
+        };
+
+        self.values.insert(key, value);
+
+        match rhs {
+            [PascalToken::IntLiteral(IntLiteralKind::Decimal, _)]
+            | [PascalToken::Minus, PascalToken::IntLiteral(IntLiteralKind::Decimal, _)] => None,
+            _ => Some(value),
+        }
+    }
+}
+
+/// How deeply parentheses may be nested in an expression that we evaluate.
+/// Each level recurses, so without a limit, pathological input could overflow
+/// the stack.
+const MAX_DEPTH: usize = 64;
+
+/// A recursive-descent evaluator of integer arithmetic, following the Pascal
+/// precedence rules.
+struct Evaluator<'v, 't, 'a> {
+    values: &'v HashMap<String, isize>,
+    tokens: &'t [&'t PascalToken<'a>],
+    pos: usize,
+
+    /// How many parenthesized expressions we're in.
+    depth: usize,
+}
+
+impl<'v, 't, 'a> Evaluator<'v, 't, 'a> {
+    fn peek(&self) -> Option<&'t PascalToken<'a>> {
+        self.tokens.get(self.pos).copied()
+    }
+
+    /// Evaluate a sum or difference of terms, which may have a leading sign.
+    fn expr(&mut self) -> Option<isize> {
+        let mut value = match self.peek()? {
+            PascalToken::Plus => {
+                self.pos += 1;
+                self.term()?
+            }
+
+            PascalToken::Minus => {
+                self.pos += 1;
+                self.term()?.checked_neg()?
+            }
+
+            _ => self.term()?,
+        };
+
+        loop {
+            match self.peek() {
+                Some(PascalToken::Plus) => {
+                    self.pos += 1;
+                    value = value.checked_add(self.term()?)?;
+                }
+
+                Some(PascalToken::Minus) => {
+                    self.pos += 1;
+                    value = value.checked_sub(self.term()?)?;
+                }
+
+                _ => return Some(value),
+            }
+        }
+    }
+
+    /// Evaluate a product or quotient of factors.
+    fn term(&mut self) -> Option<isize> {
+        let mut value = self.factor()?;
+
+        loop {
+            match self.peek() {
+                Some(PascalToken::Times) => {
+                    self.pos += 1;
+                    value = value.checked_mul(self.factor()?)?;
+                }
+
+                Some(t) if t.is_reserved_word(PascalReservedWord::Div) => {
+                    self.pos += 1;
+                    value = value.checked_div(self.factor()?)?;
+                }
+
+                Some(t) if t.is_reserved_word(PascalReservedWord::Mod) => {
+                    self.pos += 1;
+                    value = value.checked_rem(self.factor()?)?;
+                }
+
+                _ => return Some(value),
+            }
+        }
+    }
+
+    /// Evaluate a number, a macro, or a parenthesized expression.
+    fn factor(&mut self) -> Option<isize> {
+        let token = self.peek()?;
+        self.pos += 1;
+
+        match token {
+            PascalToken::IntLiteral(_, v) => Some(*v),
+
+            // A one-character double-quoted string is the character's code.
+            PascalToken::StringLiteral(StringLiteralKind::DoubleQuote, s) => {
+                let mut chars = s.value.chars();
+
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => Some(c as isize),
+                    _ => None,
+                }
+            }
+
+            PascalToken::Identifier(name) => self.values.get(&normalize(&name.value)).copied(),
+
+            PascalToken::OpenDelimiter(DelimiterKind::Paren) => {
+                if self.depth >= MAX_DEPTH {
+                    return None;
+                }
+
+                self.depth += 1;
+                let value = self.expr();
+                self.depth -= 1;
+                let value = value?;
+
+                match self.peek()? {
+                    PascalToken::CloseDelimiter(DelimiterKind::Paren) => {
+                        self.pos += 1;
+                        Some(value)
+                    }
+                    _ => None,
+                }
+            }
+
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        parse_base::{Span, SpanValue},
+        Dialect,
+    };
+
+    fn int(v: isize) -> PascalToken<'static> {
+        PascalToken::IntLiteral(IntLiteralKind::Decimal, v)
+    }
+
+    fn word(value: PascalReservedWord) -> PascalToken<'static> {
+        let span = Span::new_extra("", Dialect::Web);
+        PascalToken::ReservedWord(SpanValue {
+            start: span,
+            end: span,
+            value,
+        })
+    }
+
+    const OPEN: PascalToken = PascalToken::OpenDelimiter(DelimiterKind::Paren);
+    const CLOSE: PascalToken = PascalToken::CloseDelimiter(DelimiterKind::Paren);
+
+    /// Evaluate an expression, requiring all of the tokens to be used.
+    fn evaluate(tokens: &[PascalToken]) -> Option<isize> {
+        let tokens: Vec<_> = tokens.iter().collect();
+        let values = HashMap::new();
+        let mut eval = Evaluator {
+            values: &values,
+            tokens: &tokens,
+            pos: 0,
+            depth: 0,
+        };
+
+        eval.expr().filter(|_| eval.pos == tokens.len())
+    }
+
+    /// Wrap some tokens in `depth` levels of parentheses.
+    fn nest(depth: usize, inner: &[PascalToken<'static>]) -> Vec<PascalToken<'static>> {
+        let mut tokens = vec![OPEN; depth];
+        tokens.extend_from_slice(inner);
+        tokens.extend(std::iter::repeat_n(CLOSE, depth));
+        tokens
+    }
+
+    #[test]
+    fn arithmetic() {
+        let div = word(PascalReservedWord::Div);
+        let mod_ = word(PascalReservedWord::Mod);
+
+        assert_eq!(
+            evaluate(&[
+                int(1),
+                PascalToken::Plus,
+                int(2),
+                PascalToken::Times,
+                int(3)
+            ]),
+            Some(7)
+        );
+        assert_eq!(
+            evaluate(&[
+                OPEN,
+                int(1),
+                PascalToken::Plus,
+                int(2),
+                CLOSE,
+                PascalToken::Times,
+                int(3)
+            ]),
+            Some(9)
+        );
+        assert_eq!(
+            evaluate(&[PascalToken::Minus, int(7), div, int(2)]),
+            Some(-3)
+        );
+        assert_eq!(evaluate(&[int(7), mod_, int(4)]), Some(3));
+    }
+
+    #[test]
+    fn overflow() {
+        assert_eq!(
+            evaluate(&[int(isize::MAX), PascalToken::Plus, int(1)]),
+            None
+        );
+        assert_eq!(
+            evaluate(&[int(isize::MIN), PascalToken::Minus, int(1)]),
+            None
+        );
+        assert_eq!(
+            evaluate(&[int(isize::MAX), PascalToken::Times, int(2)]),
+            None
+        );
+        assert_eq!(evaluate(&[PascalToken::Minus, int(isize::MIN)]), None);
+    }
+
+    #[test]
+    fn division_by_zero() {
+        assert_eq!(
+            evaluate(&[int(1), word(PascalReservedWord::Div), int(0)]),
+            None
+        );
+        assert_eq!(
+            evaluate(&[int(1), word(PascalReservedWord::Mod), int(0)]),
+            None
+        );
+    }
+
+    #[test]
+    fn nesting() {
+        assert_eq!(evaluate(&nest(MAX_DEPTH, &[int(5)])), Some(5));
+        assert_eq!(evaluate(&nest(MAX_DEPTH + 1, &[int(5)])), None);
+        assert_eq!(evaluate(&nest(100_000, &[int(5)])), None);
+
+        // Unbalanced parentheses don't evaluate.
+        assert_eq!(evaluate(&[OPEN, int(5)]), None);
+        assert_eq!(evaluate(&[int(5), CLOSE]), None);
+    }
+}
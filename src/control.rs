@@ -19,13 +19,13 @@ pub enum ControlKind {
     /// `@d` or `@D`
     MacroDefinition,
 
-    /// `@f` or `@F`
+    /// `@f` or `@F`; or in CWEB, also `@s` or `@S`
     FormatDefinition,
 
-    /// `@p` or `@P`
+    /// `@p` or `@P`; or in CWEB, also `@c` or `@C`
     StartUnnamedPascal,
 
-    /// `@<`
+    /// `@<`; or in CWEB, also `@(`, which names an output file
     ModuleName,
 
     /// `@'`
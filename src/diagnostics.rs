@@ -0,0 +1,432 @@
+//! Rendering diagnostics about WEB source text.
+//!
+//! Problems with specific pieces of the input are reported in the style of
+//! `rustc`, quoting the offending line with carets underneath the relevant
+//! part:
+//!
+//! ```text
+//! module 12 (named module <Initialize>): code could not be parsed
+//!    --> tex.web:345:10
+//!     |
+//! 345 |     x := := 3;
+//!     |          ^^ expected an expression
+//! ```
+//!
+//! Besides outright errors, weaving can turn up things that are merely
+//! suspicious. These are reported as [`Warning`]s, each of which belongs to a
+//! [`Lint`] with a stable code. As with `rustc`, each lint can be allowed,
+//! left as a warning, or denied, which turns it into an error.
+//!
+//! For tools that would rather not parse the rendered text, [`sarif`] converts
+//! warnings into the SARIF format, which many code review tools understand.
+
+use serde_json::json;
+use std::{collections::HashMap, fmt, ops::Range, str::FromStr};
+use tectonic_errors::prelude::*;
+
+use crate::state::ModuleId;
+
+/// Get the line and column of a byte offset in some text. Both start at 1, and
+/// the column is measured in characters.
+pub fn line_and_column(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let line = before.matches('\n').count() + 1;
+    let column = before[line_start..].chars().count() + 1;
+    (line, column)
+}
+
+/// Render a diagnostic about the bytes `range` of `text`.
+///
+/// The `message` summarizes the problem, and the `label` is attached to the
+/// quoted source. If given, `path` is used to name the input in the location
+/// line. The result starts with the message, so that the caller can prefix it
+/// with something like `error: `, and ends with a newline.
+pub fn render(
+    text: &str,
+    path: Option<&str>,
+    range: Range<usize>,
+    message: &str,
+    label: &str,
+) -> String {
+    let (line, column) = line_and_column(text, range.start);
+    let line_start = text[..range.start].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let line_end = text[range.start..]
+        .find('\n')
+        .map(|i| range.start + i)
+        .unwrap_or(text.len());
+
+    // Line up the carets with the quoted text, preserving tabs so that they
+    // expand the same way.
+    let indent: String = text[line_start..range.start]
+        .chars()
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let n_carets = text[range.start..range.end.min(line_end)]
+        .chars()
+        .count()
+        .max(1);
+
+    let line_num = line.to_string();
+    let gutter = " ".repeat(line_num.len());
+    let location = match path {
+        Some(p) => format!("{}:{}:{}", p, line, column),
+        None => format!("line {}, column {}", line, column),
+    };
+
+    format!(
+        "{}\n{}--> {}\n{} |\n{} | {}\n{} | {}{} {}\n",
+        message,
+        gutter,
+        location,
+        gutter,
+        line_num,
+        text[line_start..line_end].trim_end(),
+        gutter,
+        indent,
+        "^".repeat(n_carets),
+        label
+    )
+}
+
+/// Suggest alternatives to a name that wasn't recognized, in the form `did you
+/// mean <A> or <B>?`. Returns `None` if there are no alternatives.
+pub fn did_you_mean(names: &[&str]) -> Option<String> {
+    if names.is_empty() {
+        return None;
+    }
+
+    let names: Vec<_> = names.iter().map(|n| format!("<{}>", n)).collect();
+    Some(format!("did you mean {}?", names.join(" or ")))
+}
+
+/// A kind of warning.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Lint {
+    /// A named module whose code never reaches the program, because it's
+    /// never used, or only used by code that doesn't reach the program itself
+    /// (`W0001`). This isn't checked in CWEB programs, where the modules that
+    /// name output files are never used by design.
+    UnreferencedModule,
+
+    /// A macro that takes a parameter, but whose definition never uses it
+    /// (`W0002`).
+    SuspiciousMacro,
+
+    /// Code that couldn't be laid out within the configured width (`W0003`).
+    OverlongLine,
+
+    /// A `goto` whose target isn't declared in the enclosing procedure,
+    /// function, or program (`W0004`).
+    UndeclaredLabel,
+
+    /// A declared label that no `goto` ever jumps to (`W0005`).
+    UnusedLabel,
+
+    /// An identifier that isn't declared anywhere in the program as a
+    /// variable, constant, type, macro, procedure, or function (`W0006`).
+    UndefinedIdentifier,
+
+    /// A `@d` macro that is never used in any Pascal code (`W0007`).
+    UnusedMacro,
+
+    /// A named module that is used but never defined (`W0008`).
+    UndefinedModule,
+
+    /// Code that tt-weave accepts, but that wouldn't be valid ISO Pascal after
+    /// tangling, like an assignment to a function call that isn't a macro
+    /// (`W0009`). Unlike the other lints, this is allowed by default, since
+    /// only people porting WEB programs to real Pascal compilers care.
+    NonstandardPascal,
+
+    /// A statement that can never run, because it follows a `goto`, or a
+    /// macro like `return` that expands to one (`W0010`).
+    UnreachableCode,
+
+    /// A preprocessor conditional like `$ifdef` that is never closed, or a
+    /// directive like `$endif` that doesn't match any open conditional
+    /// (`W0011`).
+    UnbalancedConditional,
+
+    /// A module whose displayed code takes more lines than the configured
+    /// limit, and so might be better split up (`W0012`). Like the other style
+    /// lints, this is allowed by default.
+    LongModuleCode,
+
+    /// A named module whose name is longer than the configured limit
+    /// (`W0013`).
+    LongModuleName,
+
+    /// A `const` declaration without a comment explaining what the constant
+    /// is for (`W0014`).
+    UndocumentedConstant,
+}
+
+impl Lint {
+    /// All of the lints.
+    pub const ALL: [Lint; 14] = [
+        Lint::UnreferencedModule,
+        Lint::SuspiciousMacro,
+        Lint::OverlongLine,
+        Lint::UndeclaredLabel,
+        Lint::UnusedLabel,
+        Lint::UndefinedIdentifier,
+        Lint::UnusedMacro,
+        Lint::UndefinedModule,
+        Lint::NonstandardPascal,
+        Lint::UnreachableCode,
+        Lint::UnbalancedConditional,
+        Lint::LongModuleCode,
+        Lint::LongModuleName,
+        Lint::UndocumentedConstant,
+    ];
+
+    /// Get the stable code identifying this lint, like `W0001`.
+    pub fn code(self) -> &'static str {
+        match self {
+            Lint::UnreferencedModule => "W0001",
+            Lint::SuspiciousMacro => "W0002",
+            Lint::OverlongLine => "W0003",
+            Lint::UndeclaredLabel => "W0004",
+            Lint::UnusedLabel => "W0005",
+            Lint::UndefinedIdentifier => "W0006",
+            Lint::UnusedMacro => "W0007",
+            Lint::UndefinedModule => "W0008",
+            Lint::NonstandardPascal => "W0009",
+            Lint::UnreachableCode => "W0010",
+            Lint::UnbalancedConditional => "W0011",
+            Lint::LongModuleCode => "W0012",
+            Lint::LongModuleName => "W0013",
+            Lint::UndocumentedConstant => "W0014",
+        }
+    }
+
+    /// Get the descriptive name of this lint, like `unreferenced-module`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Lint::UnreferencedModule => "unreferenced-module",
+            Lint::SuspiciousMacro => "suspicious-macro",
+            Lint::OverlongLine => "overlong-line",
+            Lint::UndeclaredLabel => "undeclared-label",
+            Lint::UnusedLabel => "unused-label",
+            Lint::UndefinedIdentifier => "undefined-identifier",
+            Lint::UnusedMacro => "unused-macro",
+            Lint::UndefinedModule => "undefined-module",
+            Lint::NonstandardPascal => "nonstandard-pascal",
+            Lint::UnreachableCode => "unreachable-code",
+            Lint::UnbalancedConditional => "unbalanced-conditional",
+            Lint::LongModuleCode => "long-module-code",
+            Lint::LongModuleName => "long-module-name",
+            Lint::UndocumentedConstant => "undocumented-constant",
+        }
+    }
+
+    /// Get a one-line description of what this lint looks for.
+    pub fn description(self) -> &'static str {
+        match self {
+            Lint::UnreferencedModule => "A named module whose code never reaches the program",
+            Lint::SuspiciousMacro => "A parameterized macro that never uses its parameter",
+            Lint::OverlongLine => "Code that can't be laid out within the configured width",
+            Lint::UndeclaredLabel => "A goto whose target label isn't declared",
+            Lint::UnusedLabel => "A declared label that no goto jumps to",
+            Lint::UndefinedIdentifier => "An identifier that isn't declared anywhere",
+            Lint::UnusedMacro => "A macro that is never used in any Pascal code",
+            Lint::UndefinedModule => "A named module that is used but never defined",
+            Lint::NonstandardPascal => "Code that wouldn't be valid ISO Pascal after tangling",
+            Lint::UnreachableCode => "A statement that can never run",
+            Lint::UnbalancedConditional => "An unbalanced preprocessor conditional",
+            Lint::LongModuleCode => "A module whose code takes too many lines",
+            Lint::LongModuleName => "A module name that is too long",
+            Lint::UndocumentedConstant => "A constant declared without a comment",
+        }
+    }
+
+    /// Get the level of this lint if it isn't set explicitly.
+    pub fn default_level(self) -> LintLevel {
+        match self {
+            Lint::NonstandardPascal
+            | Lint::LongModuleCode
+            | Lint::LongModuleName
+            | Lint::UndocumentedConstant => LintLevel::Allow,
+            _ => LintLevel::Warn,
+        }
+    }
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Lint {
+    type Err = Error;
+
+    /// Look up a lint by its code or its name.
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(a_ok_or!(
+            Lint::ALL
+                .iter()
+                .find(|l| l.code().eq_ignore_ascii_case(s) || l.name() == s)
+                .copied();
+            ["no such lint `{}`", s]
+        ))
+    }
+}
+
+/// How a lint is handled.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LintLevel {
+    /// The lint isn't reported.
+    Allow,
+
+    /// The lint is reported as a warning.
+    Warn,
+
+    /// The lint is reported as an error.
+    Deny,
+}
+
+/// The level of each lint. Lints that aren't set explicitly have their
+/// [`Lint::default_level`].
+#[derive(Clone, Debug, Default)]
+pub struct LintLevels(HashMap<Lint, LintLevel>);
+
+impl LintLevels {
+    /// Get the level of a lint.
+    pub fn get(&self, lint: Lint) -> LintLevel {
+        self.0.get(&lint).copied().unwrap_or(lint.default_level())
+    }
+
+    /// Set the level of a lint.
+    pub fn set(&mut self, lint: Lint, level: LintLevel) {
+        self.0.insert(lint, level);
+    }
+
+    /// Set the level of a lint identified by its code or name, or of all lints
+    /// if the name is `warnings`.
+    pub fn set_named(&mut self, name: &str, level: LintLevel) -> Result<()> {
+        if name == "warnings" {
+            for lint in Lint::ALL {
+                self.set(lint, level);
+            }
+        } else {
+            self.set(name.parse()?, level);
+        }
+
+        Ok(())
+    }
+}
+
+/// A warning about WEB source text.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Warning {
+    /// The lint that produced the warning.
+    pub lint: Lint,
+
+    /// The level of the lint, which is never [`LintLevel::Allow`].
+    pub level: LintLevel,
+
+    /// The module that the warning is about.
+    pub module: ModuleId,
+
+    /// What's wrong.
+    pub message: String,
+
+    /// The bytes of the source text that the warning is about, if it's about
+    /// specific code, along with a label to attach to them.
+    pub location: Option<(Range<usize>, String)>,
+}
+
+impl Warning {
+    /// Render the warning, quoting the relevant code if there is any.
+    ///
+    /// The `text` must be the WEB source text that was woven, and `path` is
+    /// used to name it. Unlike [`render`], the result starts with `warning` or
+    /// `error`, as appropriate.
+    pub fn render(&self, text: &str, path: Option<&str>) -> String {
+        let message = format!(
+            "{}[{}]: module {}: {}",
+            if self.level == LintLevel::Deny {
+                "error"
+            } else {
+                "warning"
+            },
+            self.lint.code(),
+            self.module,
+            self.message
+        );
+
+        match &self.location {
+            Some((range, label)) => render(text, path, range.clone(), &message, label),
+            None => message + "\n",
+        }
+    }
+}
+
+/// Convert warnings about WEB source text into a SARIF log.
+///
+/// The `text` must be the WEB source text that was woven, and `path` is used
+/// as the URI of the file in the log. Every lint is listed as a rule of the
+/// tool, identified by its code. Warnings that aren't about specific code have
+/// no location. The result can be serialized with `serde_json`.
+pub fn sarif(warnings: &[Warning], text: &str, path: &str) -> serde_json::Value {
+    let rules: Vec<_> = Lint::ALL
+        .iter()
+        .map(|lint| {
+            json!({
+                "id": lint.code(),
+                "name": lint.name(),
+                "shortDescription": { "text": lint.description() },
+            })
+        })
+        .collect();
+
+    let results: Vec<_> = warnings
+        .iter()
+        .map(|w| {
+            let mut result = json!({
+                "ruleId": w.lint.code(),
+                "ruleIndex": Lint::ALL.iter().position(|l| *l == w.lint),
+                "level": if w.level == LintLevel::Deny { "error" } else { "warning" },
+                "message": { "text": format!("module {}: {}", w.module, w.message) },
+            });
+
+            if let Some((range, label)) = &w.location {
+                let (start_line, start_column) = line_and_column(text, range.start);
+                let (end_line, end_column) = line_and_column(text, range.end);
+
+                result["locations"] = json!([{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": path },
+                        "region": {
+                            "startLine": start_line,
+                            "startColumn": start_column,
+                            "endLine": end_line,
+                            "endColumn": end_column,
+                        },
+                    },
+                    "message": { "text": label },
+                }]);
+            }
+
+            result
+        })
+        .collect();
+
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "tt-weave",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules,
+                },
+            },
+            "columnKind": "unicodeCodePoints",
+            "results": results,
+        }],
+    })
+}
@@ -0,0 +1,65 @@
+//! Dialects of the WEB language.
+//!
+//! Different WEB programs rely on slightly different conventions. Rather than
+//! hard-coding the quirks needed by any particular program, we associate a
+//! dialect with each input and query it to decide which quirks apply. The
+//! dialect is carried around as the "extra" data of our input spans, so that
+//! the lexer can consult it without it having to be passed around explicitly.
+
+use clap::ArgEnum;
+use serde::Deserialize;
+
+/// A dialect of WEB.
+#[derive(ArgEnum, Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Dialect {
+    /// Knuth's WEB, as understood by the original WEAVE.
+    Web,
+
+    /// WEB as found in the web2c-based TeX distributions, including the
+    /// workarounds needed to handle XeTeX's source.
+    #[default]
+    Web2c,
+
+    /// CWEB, with C code instead of Pascal. Support for this is fairly
+    /// rudimentary.
+    Cweb,
+}
+
+impl Dialect {
+    /// Whether the code is C rather than Pascal.
+    pub fn is_cweb(self) -> bool {
+        self == Dialect::Cweb
+    }
+
+    /// Whether CWEB's control codes, such as `@c`, `@s`, and `@(`, are
+    /// recognized.
+    pub fn has_cweb_control_codes(self) -> bool {
+        self == Dialect::Cweb
+    }
+
+    /// Whether a `@*` may be followed by a depth specification, like `@**` or
+    /// `@*2`. This is a CWEB convention that some WEB derivatives use too.
+    pub fn allows_module_depths(self) -> bool {
+        self != Dialect::Web
+    }
+
+    /// Whether stray `@z` codes are silently ignored. These are the
+    /// terminators of change-file entries, and can be left over in sources that
+    /// have been merged with their change files. WEAVE only tolerates them in
+    /// limbo.
+    pub fn ignores_stray_change_terminators(self) -> bool {
+        self == Dialect::Web2c
+    }
+
+    /// Whether to apply the special-case parsing needed to handle quirky
+    /// constructs in XeTeX's WEB source.
+    pub fn has_xetex_workarounds(self) -> bool {
+        self == Dialect::Web2c
+    }
+
+    /// Whether double-quoted strings go into a string pool, as in Pascal WEB.
+    pub fn has_string_pool(self) -> bool {
+        self != Dialect::Cweb
+    }
+}
@@ -0,0 +1,329 @@
+//! Semantic differences between two versions of a WEB program.
+//!
+//! A textual diff of two versions of a WEB file is noisy: reflowing a paragraph
+//! or rewording a comment shows up just the same as a change to the code. Here
+//! we instead compare the parsed Pascal code of each module, ignoring layout,
+//! comments, and the TeX commentary, so that only the changes that might affect
+//! the program are reported.
+//!
+//! Modules are numbered sequentially, so inserting or deleting a module
+//! renumbers all of the ones that follow it. We therefore line up the modules
+//! of the two versions by finding the longest common subsequence of modules
+//! with the same code. Between two matched modules, the unmatched modules are
+//! paired up and reported as changed, and any left over are reported as added
+//! or removed. Modules that contribute to the same named module are paired
+//! first, and then the rest are paired in order.
+
+use serde_json::Value;
+use tectonic_errors::prelude::*;
+
+use crate::{
+    parse_base::Span,
+    pass2,
+    state::{ModuleId, State},
+};
+
+/// A semantic difference between two versions of a WEB program.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ModuleChange {
+    /// The code of a module changed. The first ID is the module's number in
+    /// the old version, and the second is its number in the new version.
+    Changed(ModuleId, ModuleId),
+
+    /// A module of the old version doesn't appear in the new version.
+    Removed(ModuleId),
+
+    /// A module of the new version doesn't appear in the old version.
+    Added(ModuleId),
+}
+
+/// The code of a module, boiled down for comparison.
+#[derive(Debug)]
+struct ModuleCode {
+    /// A fingerprint of the code, such that two modules have the same
+    /// fingerprint if their code is semantically the same.
+    fingerprint: String,
+
+    /// The name of the named module to which the module contributes, if any.
+    name: Option<String>,
+}
+
+/// Determine whether the elements of a JSON array look like the pieces of a
+/// serialized [`crate::weblang::WebComment`].
+fn is_comment(items: &[Value]) -> bool {
+    !items.is_empty()
+        && items.iter().all(|item| match item.as_object() {
+            Some(map) if map.len() == 1 => {
+                matches!(map.get("Tex"), Some(Value::String(_)))
+                    || matches!(map.get("Pascal"), Some(Value::Array(_)))
+            }
+            _ => false,
+        })
+}
+
+/// Determine whether a JSON value is a freestanding comment that has already
+/// been normalized.
+fn is_normalized_comment(value: &Value) -> bool {
+    match value.as_object() {
+        Some(map) => map.len() == 1 && map.get("Comment") == Some(&Value::Null),
+        None => false,
+    }
+}
+
+/// Normalize a serialized AST so that it only captures the semantics of the
+/// code. Comments are removed, spans are replaced with their values, the
+/// locations of AST nodes are dropped, and module references are reduced to
+/// the module names, since module IDs change whenever modules are inserted or
+/// removed.
+fn normalize(value: &mut Value) {
+    match value {
+        Value::Array(items) => {
+            if is_comment(items) {
+                *value = Value::Null;
+                return;
+            }
+
+            items.iter_mut().for_each(normalize);
+            items.retain(|v| !is_normalized_comment(v));
+        }
+
+        Value::Object(map) => {
+            if map.len() == 3
+                && map.contains_key("value")
+                && map.contains_key("line")
+                && map.contains_key("column")
+            {
+                *value = map.remove("value").unwrap();
+                normalize(value);
+                return;
+            }
+
+            if map.len() == 2 && map.contains_key("span") && map.contains_key("node") {
+                *value = map.remove("node").unwrap();
+                normalize(value);
+                return;
+            }
+
+            if map.len() == 2 && map.contains_key("name") && map.contains_key("id") {
+                map.remove("id");
+            }
+
+            map.values_mut().for_each(normalize);
+        }
+
+        _ => {}
+    }
+}
+
+/// Boil down the code of each module in a program. The code of module `n` is
+/// at index `n - 1`.
+fn module_codes(text: &str, state: &State) -> Result<Vec<ModuleCode>> {
+    if state.dialect().is_cweb() {
+        bail!("semantic diffs are not supported for CWEB programs");
+    }
+
+    let input = Span::new_extra(text, state.dialect());
+    let failures = pass2::check(state, input, None)?;
+
+    if let Some(f) = failures.first() {
+        bail!(
+            "the code in module {} could not be parsed, so the program can't be diffed \
+             ({} parse problem(s) in total)",
+            f.module,
+            failures.len()
+        );
+    }
+
+    let mut modules = vec![Vec::new(); state.n_modules()];
+
+    for mut entry in pass2::dump(state, input, None)? {
+        let module = entry
+            .as_object_mut()
+            .and_then(|map| map.remove("module"))
+            .and_then(|m| m.as_u64())
+            .unwrap_or(0) as ModuleId;
+
+        // Code in limbo isn't part of the program.
+        if module == 0 {
+            continue;
+        }
+
+        normalize(&mut entry);
+        modules[module - 1].push(entry);
+    }
+
+    Ok(modules
+        .into_iter()
+        .map(|entries| {
+            let name = entries
+                .iter()
+                .find_map(|e| e["name"]["name"].as_str())
+                .map(|s| s.to_owned());
+
+            ModuleCode {
+                fingerprint: Value::Array(entries).to_string(),
+                name,
+            }
+        })
+        .collect())
+}
+
+/// Record the modules that were unmatched between two matched ones.
+fn flush_unmatched(
+    changes: &mut Vec<ModuleChange>,
+    old: &[ModuleCode],
+    new: &[ModuleCode],
+    removed: &mut Vec<ModuleId>,
+    added: &mut Vec<ModuleId>,
+) {
+    let mut pairs = Vec::new();
+
+    removed.retain(|&o| {
+        let name = match old[o - 1].name.as_ref() {
+            Some(n) => n,
+            None => return true,
+        };
+
+        match added
+            .iter()
+            .position(|&n| new[n - 1].name.as_ref() == Some(name))
+        {
+            Some(i) => {
+                pairs.push((o, added.remove(i)));
+                false
+            }
+            None => true,
+        }
+    });
+
+    let n_paired = usize::min(removed.len(), added.len());
+    pairs.extend(removed.drain(..n_paired).zip(added.drain(..n_paired)));
+    pairs.sort_unstable();
+
+    changes.extend(pairs.into_iter().map(|(o, n)| ModuleChange::Changed(o, n)));
+    changes.extend(removed.drain(..).map(ModuleChange::Removed));
+    changes.extend(added.drain(..).map(ModuleChange::Added));
+}
+
+/// Compare two versions of a WEB program, returning the modules whose code
+/// differs semantically, in order.
+///
+/// Each state must have been created by running [`crate::scan`] on the
+/// corresponding text. All of the Pascal code in both versions must be
+/// parseable.
+pub fn diff(
+    old_text: &str,
+    old_state: &State,
+    new_text: &str,
+    new_state: &State,
+) -> Result<Vec<ModuleChange>> {
+    let old = atry!(
+        module_codes(old_text, old_state);
+        ["failed to analyze the old version of the program"]
+    );
+    let new = atry!(
+        module_codes(new_text, new_state);
+        ["failed to analyze the new version of the program"]
+    );
+    let (n_old, n_new) = (old.len(), new.len());
+
+    // lcs[i][j] is the length of the longest common subsequence of old[i..]
+    // and new[j..].
+    let mut lcs = vec![vec![0u32; n_new + 1]; n_old + 1];
+
+    for i in (0..n_old).rev() {
+        for j in (0..n_new).rev() {
+            lcs[i][j] = if old[i].fingerprint == new[j].fingerprint {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                u32::max(lcs[i + 1][j], lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut changes = Vec::new();
+    let mut removed = Vec::new();
+    let mut added = Vec::new();
+    let (mut i, mut j) = (0, 0);
+
+    while i < n_old || j < n_new {
+        if i < n_old && j < n_new && old[i].fingerprint == new[j].fingerprint {
+            flush_unmatched(&mut changes, &old, &new, &mut removed, &mut added);
+            i += 1;
+            j += 1;
+        } else if j < n_new && (i == n_old || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            added.push(j + 1);
+            j += 1;
+        } else {
+            removed.push(i + 1);
+            i += 1;
+        }
+    }
+
+    flush_unmatched(&mut changes, &old, &new, &mut removed, &mut added);
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Dialect;
+
+    /// Build a WEB program with an unnamed module for each fragment of code.
+    fn program(codes: &[&str]) -> String {
+        codes.iter().map(|c| format!("@ @p {}\n", c)).collect()
+    }
+
+    fn diff_programs(old: &[&str], new: &[&str]) -> Vec<ModuleChange> {
+        let (old, new) = (program(old), program(new));
+        let old_state = crate::scan(&old, Dialect::Web).unwrap();
+        let new_state = crate::scan(&new, Dialect::Web).unwrap();
+        diff(&old, &old_state, &new, &new_state).unwrap()
+    }
+
+    #[test]
+    fn identical() {
+        let codes = ["a:=1;", "b:=2;", "c:=3;"];
+        assert_eq!(diff_programs(&codes, &codes), []);
+
+        // Layout and comments don't count.
+        assert_eq!(
+            diff_programs(&codes, &["a := 1; {one}", "b:=2;", "c:=3;"]),
+            []
+        );
+    }
+
+    #[test]
+    fn changed() {
+        assert_eq!(
+            diff_programs(&["a:=1;", "b:=2;", "c:=3;"], &["a:=1;", "b:=20;", "c:=3;"]),
+            [ModuleChange::Changed(2, 2)]
+        );
+    }
+
+    #[test]
+    fn insertions() {
+        assert_eq!(
+            diff_programs(&["a:=1;", "c:=3;"], &["a:=1;", "b:=2;", "c:=3;", "d:=4;"]),
+            [ModuleChange::Added(2), ModuleChange::Added(4)]
+        );
+    }
+
+    #[test]
+    fn deletions() {
+        assert_eq!(
+            diff_programs(&["a:=1;", "b:=2;", "c:=3;", "d:=4;"], &["b:=2;", "d:=4;"]),
+            [ModuleChange::Removed(1), ModuleChange::Removed(3)]
+        );
+    }
+
+    #[test]
+    fn reordered() {
+        // Moving a module shows up as removing it from one place and adding it
+        // in another, with the modules in between matched up.
+        assert_eq!(
+            diff_programs(&["a:=1;", "b:=2;", "c:=3;"], &["c:=3;", "a:=1;", "b:=2;"]),
+            [ModuleChange::Added(1), ModuleChange::Removed(3)]
+        );
+    }
+}
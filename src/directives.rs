@@ -0,0 +1,105 @@
+//! Checking that preprocessor conditionals are balanced.
+//!
+//! Some WEB programs, like XeTeX, wrap system-dependent code in Pascal
+//! preprocessor conditionals such as `@{@&$ifdef(...)@}` and
+//! `@{@&$endif(...)@}`. A conditional often opens in one module and closes in
+//! another, so they can't be matched up within a fragment of code. Instead, as
+//! each fragment is woven, [`DirectiveChecker::add`] feeds its directives into
+//! a program-wide [`DirectiveTree`], in the order in which they appear in the
+//! source text, and [`DirectiveChecker::finish`] reports the conditionals that
+//! are never closed and the directives that don't match any open conditional.
+
+use crate::{
+    diagnostics::{Lint, LintLevel, LintLevels, Warning},
+    state::ModuleId,
+    weblang::{base::Spanned, visit::Visitor, DirectiveSite, DirectiveTree, WebCode, WebStatement},
+};
+
+/// Gather up the directives in a fragment of code.
+struct Collector<'t> {
+    tree: &'t mut DirectiveTree,
+    module: ModuleId,
+}
+
+impl<'a, 't> Visitor<'a> for Collector<'t> {
+    fn visit_statement(&mut self, stmt: &Spanned<WebStatement<'a>>) {
+        if let WebStatement::PreprocessorDirective(pd) = &stmt.node {
+            self.tree.add(
+                pd.nesting(),
+                DirectiveSite {
+                    module: self.module,
+                    range: stmt.span.start..stmt.span.end,
+                },
+            );
+        }
+
+        stmt.walk(self);
+    }
+}
+
+/// Builds up the tree of preprocessor conditionals, so that unbalanced ones
+/// can be reported once all of the code has been seen.
+#[derive(Debug, Default)]
+pub struct DirectiveChecker {
+    /// The conditionals seen so far.
+    tree: DirectiveTree,
+
+    /// Whether some of the code wasn't seen, in which case we can't say
+    /// anything.
+    incomplete: bool,
+}
+
+impl DirectiveChecker {
+    /// Record the directives in a fragment of code that appears in `module`.
+    pub fn add(&mut self, code: &WebCode, module: ModuleId) {
+        let mut collector = Collector {
+            tree: &mut self.tree,
+            module,
+        };
+
+        collector.visit_code(code);
+    }
+
+    /// Note that some code was skipped, so that the directives in it are
+    /// missing.
+    pub fn skip(&mut self) {
+        self.incomplete = true;
+    }
+
+    /// Finish the tree, returning warnings about the conditionals that aren't
+    /// balanced.
+    pub fn finish(self, lints: &LintLevels) -> Vec<Warning> {
+        let level = lints.get(Lint::UnbalancedConditional);
+
+        if self.incomplete || level == LintLevel::Allow {
+            return Vec::new();
+        }
+
+        let tree = self.tree.finish();
+        let mut warnings = Vec::new();
+
+        for node in tree.iter().filter(|n| n.close.is_none()) {
+            warnings.push(Warning {
+                lint: Lint::UnbalancedConditional,
+                level,
+                module: node.open.module,
+                message: "this preprocessor conditional is never closed".to_owned(),
+                location: Some((node.open.range.clone(), "opened here".to_owned())),
+            });
+        }
+
+        for site in &tree.strays {
+            warnings.push(Warning {
+                lint: Lint::UnbalancedConditional,
+                level,
+                module: site.module,
+                message: "this preprocessor directive doesn't match any open conditional"
+                    .to_owned(),
+                location: Some((site.range.clone(), "unmatched".to_owned())),
+            });
+        }
+
+        warnings.sort_by_key(|w| w.location.as_ref().map(|(r, _)| r.start));
+        warnings
+    }
+}
@@ -0,0 +1,114 @@
+//! Encodings of the input files.
+//!
+//! WEB predates Unicode, so historical WEB files are often written in an 8-bit
+//! encoding rather than UTF-8: Latin-1 for programs from Western Europe, or
+//! the IBM PC's code page 437 for those written under DOS. We work with Rust
+//! strings throughout, so such files are transcoded to UTF-8 as they're read.
+//! The output is always UTF-8.
+
+use clap::ArgEnum;
+use serde::Deserialize;
+use std::{convert::TryFrom, fmt};
+use tectonic_errors::prelude::*;
+
+/// The characters of code page 437 from 0x80 up. The lower half is ASCII.
+const CP437_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', //
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', //
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', //
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐', //
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧', //
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀', //
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩', //
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+/// The encoding of an input file.
+#[derive(ArgEnum, Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+pub enum InputEncoding {
+    /// UTF-8, which is checked for validity.
+    #[default]
+    #[clap(name = "utf-8", alias = "utf8")]
+    #[serde(rename = "utf-8", alias = "utf8")]
+    Utf8,
+
+    /// ISO 8859-1, in which each byte is the Unicode character with the same
+    /// number.
+    #[clap(name = "latin-1", alias = "latin1")]
+    #[serde(rename = "latin-1", alias = "latin1")]
+    Latin1,
+
+    /// The IBM PC's code page 437. Its lower half is taken to be plain ASCII,
+    /// rather than the graphics that the PC displayed for the control
+    /// characters.
+    #[clap(name = "cp437")]
+    #[serde(rename = "cp437")]
+    Cp437,
+}
+
+impl InputEncoding {
+    /// Decode the contents of a file. Only UTF-8 can fail to decode, since
+    /// every byte means something in the other encodings.
+    pub fn decode(self, bytes: Vec<u8>) -> Result<String> {
+        match self {
+            InputEncoding::Utf8 => match String::from_utf8(bytes) {
+                Ok(text) => Ok(text),
+                Err(e) => {
+                    let pos = e.utf8_error().valid_up_to();
+                    bail!(
+                        "invalid UTF-8 at byte {}; the input may be in another encoding",
+                        pos
+                    )
+                }
+            },
+
+            InputEncoding::Latin1 => Ok(bytes.into_iter().map(char::from).collect()),
+
+            InputEncoding::Cp437 => Ok(bytes
+                .into_iter()
+                .map(|b| match b {
+                    0..=0x7f => char::from(b),
+                    _ => CP437_HIGH[usize::from(b - 0x80)],
+                })
+                .collect()),
+        }
+    }
+
+    /// Encode text to be written back to a file in this encoding, failing if
+    /// it contains a character that the encoding can't represent.
+    pub fn encode(self, text: &str) -> Result<Vec<u8>> {
+        if self == InputEncoding::Utf8 {
+            return Ok(text.as_bytes().to_owned());
+        }
+
+        let mut bytes = Vec::with_capacity(text.len());
+
+        for c in text.chars() {
+            let b = match self {
+                _ if c.is_ascii() => Some(c as u8),
+                InputEncoding::Latin1 => u8::try_from(u32::from(c)).ok(),
+                _ => CP437_HIGH
+                    .iter()
+                    .position(|h| *h == c)
+                    .map(|i| 0x80 + i as u8),
+            };
+
+            match b {
+                Some(b) => bytes.push(b),
+                None => bail!("the character `{}` can't be written in {}", c, self),
+            }
+        }
+
+        Ok(bytes)
+    }
+}
+
+impl fmt::Display for InputEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            InputEncoding::Utf8 => "UTF-8",
+            InputEncoding::Latin1 => "Latin-1",
+            InputEncoding::Cp437 => "code page 437",
+        })
+    }
+}
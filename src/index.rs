@@ -1,6 +1,8 @@
 //! Types for indexing.
 
-#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
 pub enum IndexEntryKind {
     /// Auto-sourced from Pascal code; printed in italics
     Normal,
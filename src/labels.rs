@@ -0,0 +1,728 @@
+//! Checking the labels that `goto` statements jump to.
+//!
+//! Pascal requires each label to be declared at the top of the procedure,
+//! function, or program that uses it. In a WEB program, the declaration and the
+//! `goto`s are usually far apart: a procedure declares `label exit`, and the
+//! `goto exit` is in some named module that the procedure's body references, or
+//! is hidden inside a macro like `return`. So the labels can only be checked
+//! once all of the code has been seen. As each fragment of code is woven,
+//! [`LabelChecker::add`] records what it does with labels, and
+//! [`LabelChecker::finish`] puts it all together.
+//!
+//! A `goto` may also leave the procedure that contains it, jumping to a label
+//! declared by an enclosing procedure or by the main program, as with tex.web's
+//! `jump_out`, which does `goto end_of_TEX`. So each target is looked for in
+//! the scope of the `goto` and then in each of the scopes enclosing it. A
+//! procedure that isn't nested in another one is taken to be declared in the
+//! outer block of the program, which is where WEB programs put them.
+//!
+//! The analysis is conservative. If any of the relevant code couldn't be
+//! parsed, or labels are declared in a way that we don't understand, we don't
+//! say anything about them.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    ops::Range,
+};
+
+use crate::{
+    diagnostics::{Lint, LintLevel, LintLevels, Warning},
+    parse_base::StringSpan,
+    reserved::PascalReservedWord,
+    state::ModuleId,
+    weblang::{
+        base::{NodeSpan, PascalToken, Spanned, WebModuleReference},
+        visit::Visitor,
+        WebCode, WebExpr, WebFunctionDefinition, WebLabelDeclaration, WebProgramDefinition,
+        WebStatement, WebToplevel,
+    },
+};
+
+/// A mention of a label or identifier in the code.
+#[derive(Clone, Debug)]
+struct Mention {
+    /// The name of the label, or the identifier.
+    name: String,
+
+    /// The module in which the mention appears.
+    module: ModuleId,
+
+    /// The bytes of the source text to point at when talking about it.
+    range: Range<usize>,
+}
+
+/// What a procedure or function, or the code outside of any procedure or
+/// function in a fragment, does with labels.
+#[derive(Debug, Default)]
+struct Scope {
+    /// The name of the procedure or function, if this is one.
+    name: Option<String>,
+
+    /// The procedure or function that this one is nested in, if any, as an
+    /// index into the list of them.
+    parent: Option<usize>,
+
+    /// Whether all of the code in the fragment could be parsed.
+    complete: bool,
+
+    /// The labels declared explicitly.
+    declared: Vec<Mention>,
+
+    /// Named modules whose code lists more declared labels, as in
+    /// `label @<Labels in the outer block@>`.
+    declared_via: Vec<ModuleId>,
+
+    /// Whether some labels are declared in a way that we don't understand.
+    opaque: bool,
+
+    /// The first use of each integer literal, in case this code is a list of
+    /// labels. Since the literals don't have locations of their own, they are
+    /// located by the toplevels containing them.
+    literals: BTreeMap<String, Mention>,
+
+    /// The targets of `goto`s.
+    gotos: Vec<Mention>,
+
+    /// The first use of each identifier, since any of them might be a macro
+    /// that expands into a `goto`.
+    identifiers: BTreeMap<String, Mention>,
+
+    /// Named modules whose code is included here.
+    references: Vec<ModuleId>,
+}
+
+/// Get the name of a label, if a token can be one.
+fn label_name(token: &PascalToken) -> Option<String> {
+    match token {
+        PascalToken::Identifier(s) | PascalToken::FormattedIdentifier(s, _) => {
+            Some(s.value.to_string())
+        }
+        PascalToken::IntLiteral(_, n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+fn span_range(s: &StringSpan) -> Range<usize> {
+    s.start.location_offset()..s.end.location_offset()
+}
+
+fn node_range(s: &NodeSpan) -> Range<usize> {
+    s.start..s.end
+}
+
+/// Gather up what a fragment of code does with labels.
+struct Collector {
+    module: ModuleId,
+
+    /// The scopes being collected, innermost last. The first is the code
+    /// outside of any procedure or function.
+    stack: Vec<Scope>,
+
+    /// The procedures and functions that have been collected.
+    functions: Vec<Scope>,
+
+    /// The index in `functions` of the one being visited, if any.
+    current: Option<usize>,
+
+    /// Whether the most recent toplevel was a bare `label` keyword, as in the
+    /// outer block of a program, where the labels come in the next toplevel.
+    after_label: bool,
+
+    /// Whether we're in a toplevel that lists labels.
+    in_label_list: bool,
+
+    /// Whether the code contains the program definition.
+    saw_program: bool,
+
+    /// The location of the toplevel being visited.
+    toplevel: Range<usize>,
+}
+
+impl Collector {
+    fn scope(&mut self) -> &mut Scope {
+        self.stack.last_mut().unwrap()
+    }
+
+    fn mention<S: ToString>(&self, name: S, range: Range<usize>) -> Mention {
+        Mention {
+            name: name.to_string(),
+            module: self.module,
+            range,
+        }
+    }
+}
+
+impl<'a> Visitor<'a> for Collector {
+    fn visit_toplevel(&mut self, toplevel: &Spanned<WebToplevel<'a>>) {
+        self.in_label_list = std::mem::take(&mut self.after_label);
+        self.toplevel = node_range(&toplevel.span);
+        toplevel.walk(self);
+    }
+
+    fn visit_program_definition(&mut self, program: &WebProgramDefinition<'a>) {
+        self.saw_program = true;
+        program.walk(self);
+    }
+
+    fn visit_label_declaration(&mut self, decl: &WebLabelDeclaration<'a>) {
+        for name in decl.names() {
+            if let Some(label) = label_name(&name.node) {
+                let m = self.mention(&label, node_range(&name.span));
+                self.scope().declared.push(m);
+            }
+        }
+
+        if let Some(mr) = decl.more() {
+            self.scope().declared_via.push(mr.id);
+        }
+    }
+
+    fn visit_function_definition(&mut self, func: &WebFunctionDefinition<'a>) {
+        // Claim a place in the list now, so that nested procedures can refer
+        // to this one.
+        let index = self.functions.len();
+        self.functions.push(Scope::default());
+
+        let mut scope = Scope {
+            name: Some(func.name().value.to_string()),
+            parent: self.current,
+            ..Scope::default()
+        };
+
+        for label in func.labels() {
+            match &label.name().node {
+                WebExpr::Token(t) => match label_name(t) {
+                    Some(name) => scope
+                        .declared
+                        .push(self.mention(name, node_range(&label.name().span))),
+                    None => scope.opaque = true,
+                },
+                WebExpr::ModuleReference(mref) => scope.declared_via.push(mref.id),
+                _ => scope.opaque = true,
+            }
+        }
+
+        let outer = self.current.replace(index);
+        self.stack.push(scope);
+        func.walk(self);
+        self.functions[index] = self.stack.pop().unwrap();
+        self.current = outer;
+    }
+
+    fn visit_statement(&mut self, stmt: &Spanned<WebStatement<'a>>) {
+        if let WebStatement::Goto(goto) = &stmt.node {
+            if let Some(name) = label_name(goto.label()) {
+                let m = self.mention(name, node_range(&stmt.span));
+                self.scope().gotos.push(m);
+            }
+        }
+
+        stmt.walk(self);
+    }
+
+    fn visit_expr(&mut self, expr: &Spanned<WebExpr<'a>>) {
+        if let WebExpr::SpecialGotoForm(label) = &expr.node {
+            let m = self.mention(&label.value, node_range(&expr.span));
+            self.scope().gotos.push(m);
+        }
+
+        expr.walk(self);
+    }
+
+    fn visit_token(&mut self, token: &PascalToken<'a>) {
+        match token {
+            PascalToken::ReservedWord(rw) => match rw.value {
+                PascalReservedWord::Label => self.after_label = true,
+                PascalReservedWord::Program => self.saw_program = true,
+                _ => {}
+            },
+
+            PascalToken::IntLiteral(_, n) => {
+                let m = self.mention(n, self.toplevel.clone());
+
+                if self.in_label_list {
+                    self.scope().declared.push(m);
+                } else {
+                    self.scope().literals.entry(m.name.clone()).or_insert(m);
+                }
+            }
+
+            PascalToken::Identifier(ident) | PascalToken::FormattedIdentifier(ident, _) => {
+                self.visit_identifier(ident);
+            }
+
+            _ => {}
+        }
+    }
+
+    fn visit_identifier(&mut self, ident: &StringSpan<'a>) {
+        let m = self.mention(&ident.value, span_range(ident));
+
+        if self.in_label_list {
+            self.scope().declared.push(m);
+        } else {
+            self.scope().identifiers.entry(m.name.clone()).or_insert(m);
+        }
+    }
+
+    fn visit_defined_identifier(&mut self, _ident: &StringSpan<'a>) {}
+
+    fn visit_module_reference(&mut self, mref: &WebModuleReference<'a>) {
+        let in_label_list = self.in_label_list;
+        let scope = self.scope();
+
+        if in_label_list {
+            scope.declared_via.push(mref.id);
+        } else if !scope.declared_via.contains(&mref.id) {
+            scope.references.push(mref.id);
+        }
+    }
+}
+
+/// Gathers up what the code does with labels, so that `goto`s can be checked
+/// against declarations once all of the code has been seen.
+#[derive(Debug, Default)]
+pub struct LabelChecker {
+    /// The code outside of any procedure or function in each anonymous module,
+    /// in order.
+    anonymous: Vec<Scope>,
+
+    /// The code outside of any procedure or function in each fragment of each
+    /// named module.
+    named: HashMap<ModuleId, Vec<Scope>>,
+
+    /// The procedures and functions, in order.
+    functions: Vec<Scope>,
+
+    /// What each macro expands to.
+    macros: HashMap<String, Scope>,
+
+    /// Whether the code contains the program definition.
+    saw_program: bool,
+
+    /// Whether some of the code wasn't seen, in which case we can't say
+    /// anything.
+    incomplete: bool,
+}
+
+/// Where a fragment of code appears.
+pub enum LabelContext<'s> {
+    /// The definition of the macro with this name.
+    Macro(&'s str),
+
+    /// An unnamed module.
+    Anonymous,
+
+    /// A contribution to the named module with this ID.
+    Named(ModuleId),
+}
+
+impl LabelChecker {
+    /// Record what a fragment of code does with labels. The `complete` flag
+    /// indicates whether all of the code could be parsed.
+    pub fn add(&mut self, code: &WebCode, module: ModuleId, context: LabelContext, complete: bool) {
+        let mut collector = Collector {
+            module,
+            stack: vec![Scope::default()],
+            functions: Vec::new(),
+            current: None,
+            after_label: false,
+            in_label_list: false,
+            saw_program: false,
+            toplevel: 0..0,
+        };
+
+        collector.visit_code(code);
+        self.saw_program |= collector.saw_program;
+
+        let mut outer = collector.stack.pop().unwrap();
+        outer.complete = complete;
+
+        let base = self.functions.len();
+
+        for mut func in collector.functions {
+            func.complete = complete;
+            func.parent = func.parent.map(|p| p + base);
+            self.functions.push(func);
+        }
+
+        match context {
+            LabelContext::Macro(name) => {
+                self.macros.insert(name.to_owned(), outer);
+            }
+            LabelContext::Anonymous => self.anonymous.push(outer),
+            LabelContext::Named(id) => self.named.entry(id).or_default().push(outer),
+        }
+    }
+
+    /// Note that some code was skipped, so that the labels can't be checked.
+    pub fn skip(&mut self) {
+        self.incomplete = true;
+    }
+
+    /// Check the labels, returning warnings about the problems found.
+    pub fn finish(self, lints: &LintLevels) -> Vec<Warning> {
+        let mut warnings = Vec::new();
+
+        if self.incomplete {
+            return warnings;
+        }
+
+        let mut checker = Checker {
+            labels: &self,
+            undeclared: lints.get(Lint::UndeclaredLabel),
+            unused: lints.get(Lint::UnusedLabel),
+            expansions: HashMap::new(),
+            seen: HashSet::new(),
+            warnings: &mut warnings,
+        };
+
+        // The procedures and functions come first, in order, so that their
+        // parents' indices carry over, followed by the main program.
+
+        let program = self.functions.len();
+        let mut blocks = Vec::with_capacity(program + 1);
+
+        for func in &self.functions {
+            let what = format!("`{}`", func.name.as_deref().unwrap_or_default());
+            let mut block = checker.gather(vec![func], what);
+
+            if block.parent.is_none() && self.saw_program {
+                block.parent = Some(program);
+            }
+
+            blocks.push(block);
+        }
+
+        if self.saw_program {
+            let block = checker.gather(
+                self.anonymous.iter().collect(),
+                "the main program".to_owned(),
+            );
+            blocks.push(block);
+        }
+
+        checker.check(&blocks);
+
+        warnings.sort_by_key(|w| (w.module, w.location.as_ref().map(|l| l.0.start)));
+        warnings
+    }
+}
+
+/// The labels that a macro might jump to.
+#[derive(Clone, Debug, Default)]
+struct Expansion {
+    targets: BTreeSet<String>,
+
+    /// Whether all of the macros involved could be parsed.
+    complete: bool,
+}
+
+/// The state of [`LabelChecker::finish`].
+struct Checker<'c> {
+    labels: &'c LabelChecker,
+    undeclared: LintLevel,
+    unused: LintLevel,
+
+    /// The expansions of the macros, as they're worked out.
+    expansions: HashMap<&'c str, Expansion>,
+
+    /// The warnings already issued, since code can be included in more than
+    /// one procedure.
+    seen: HashSet<(Lint, String, Range<usize>)>,
+
+    warnings: &'c mut Vec<Warning>,
+}
+
+impl<'c> Checker<'c> {
+    /// Work out the labels that a macro might jump to, including by way of
+    /// other macros.
+    fn expand(&mut self, name: &'c str) -> Option<Expansion> {
+        if let Some(e) = self.expansions.get(name) {
+            return Some(e.clone());
+        }
+
+        if !self.labels.macros.contains_key(name) {
+            return None;
+        }
+
+        let mut result = Expansion {
+            targets: BTreeSet::new(),
+            complete: true,
+        };
+        let mut visited = HashSet::new();
+        let mut todo = vec![name];
+
+        while let Some(n) = todo.pop() {
+            if !visited.insert(n) {
+                continue;
+            }
+
+            if let Some(scope) = self.labels.macros.get(n) {
+                result.complete &= scope.complete;
+                result
+                    .targets
+                    .extend(scope.gotos.iter().map(|g| g.name.clone()));
+                todo.extend(scope.identifiers.keys().map(|k| k.as_str()));
+            }
+        }
+
+        self.expansions.insert(name, result.clone());
+        Some(result)
+    }
+
+    fn warn(&mut self, lint: Lint, level: LintLevel, m: &Mention, message: String, label: &str) {
+        if level == LintLevel::Allow || !self.seen.insert((lint, m.name.clone(), m.range.clone())) {
+            return;
+        }
+
+        self.warnings.push(Warning {
+            lint,
+            level,
+            module: m.module,
+            message,
+            location: Some((m.range.clone(), label.to_owned())),
+        });
+    }
+
+    /// Work out what a procedure, function, or program does with labels, made
+    /// up of the code in `parts` and whatever they reference. It's described
+    /// to the user as `what`.
+    fn gather(&mut self, parts: Vec<&'c Scope>, what: String) -> Block<'c> {
+        let labels = self.labels;
+
+        let mut block = Block {
+            what,
+            parent: parts.first().and_then(|p| p.parent),
+            declared: Vec::new(),
+            opaque: false,
+            complete: true,
+            gotos: Vec::new(),
+        };
+
+        // Gather the declarations.
+
+        for part in &parts {
+            block.declared.extend(&part.declared);
+            block.opaque |= part.opaque;
+
+            for id in &part.declared_via {
+                match labels.named.get(id) {
+                    Some(lists) => {
+                        for list in lists {
+                            block.declared.extend(&list.declared);
+                            block.declared.extend(list.identifiers.values());
+                            block.declared.extend(list.literals.values());
+                        }
+                    }
+
+                    None => block.opaque = true,
+                }
+            }
+        }
+
+        // Gather the code, following module references.
+
+        let mut body = parts;
+        let mut visited = HashSet::new();
+        let mut i = 0;
+
+        while i < body.len() {
+            for id in &body[i].references {
+                if visited.insert(*id) {
+                    if let Some(frags) = labels.named.get(id) {
+                        body.extend(frags);
+                    }
+                }
+            }
+
+            i += 1;
+        }
+
+        if body.iter().any(|s| !s.complete) {
+            block.complete = false;
+            return block;
+        }
+
+        // Gather the `goto`s, including the ones hidden in macros.
+
+        for scope in &body {
+            for g in &scope.gotos {
+                block.gotos.push((g.name.clone(), g, None));
+            }
+
+            for (ident, m) in &scope.identifiers {
+                if let Some(expansion) = self.expand(ident) {
+                    if !expansion.complete {
+                        block.complete = false;
+                        return block;
+                    }
+
+                    for target in expansion.targets {
+                        block.gotos.push((target, m, Some(ident)));
+                    }
+                }
+            }
+        }
+
+        block
+    }
+
+    /// Check the labels of all of the procedures, functions, and the program.
+    /// Each `goto` is resolved against the labels of the block containing it
+    /// and then those of the blocks enclosing that one, and counts as a use
+    /// of the label that it finds.
+    fn check(&mut self, blocks: &[Block<'c>]) {
+        let enclosing = |i: usize| std::iter::successors(Some(i), move |j| blocks[*j].parent);
+
+        let mut used: HashSet<(usize, &str)> = HashSet::new();
+
+        // If we don't know all of the `goto`s in a block, any of the labels of
+        // the blocks enclosing it might be used.
+        let mut uncertain = vec![false; blocks.len()];
+
+        for (i, block) in blocks.iter().enumerate() {
+            if !block.complete {
+                for j in enclosing(i) {
+                    uncertain[j] = true;
+                }
+
+                continue;
+            }
+
+            for (target, m, via) in &block.gotos {
+                let mut found = false;
+                let mut opaque = false;
+
+                for j in enclosing(i) {
+                    if blocks[j].declared.iter().any(|d| d.name == *target) {
+                        used.insert((j, target.as_str()));
+                        found = true;
+                        break;
+                    }
+
+                    opaque |= blocks[j].opaque;
+                }
+
+                if found || opaque {
+                    continue;
+                }
+
+                let (message, label) = match via {
+                    Some(mac) => (
+                        format!(
+                            "macro `{}` jumps to label `{}`, which isn't declared in {}",
+                            mac, target, block.what
+                        ),
+                        "this macro jumps to an undeclared label",
+                    ),
+                    None => (
+                        format!("label `{}` isn't declared in {}", target, block.what),
+                        "this jumps to an undeclared label",
+                    ),
+                };
+
+                self.warn(Lint::UndeclaredLabel, self.undeclared, m, message, label);
+            }
+        }
+
+        for (i, block) in blocks.iter().enumerate() {
+            if uncertain[i] {
+                continue;
+            }
+
+            for d in &block.declared {
+                if used.contains(&(i, d.name.as_str())) {
+                    continue;
+                }
+
+                self.warn(
+                    Lint::UnusedLabel,
+                    self.unused,
+                    d,
+                    format!(
+                        "label `{}` is declared in {}, but nothing jumps to it",
+                        d.name, block.what
+                    ),
+                    "this label is never used",
+                );
+            }
+        }
+    }
+}
+
+/// What a procedure, function, or the main program does with labels, as
+/// worked out by [`Checker::gather`].
+struct Block<'c> {
+    /// How the block is described to the user.
+    what: String,
+
+    /// The index of the block that encloses this one, if any.
+    parent: Option<usize>,
+
+    /// The labels declared by the block.
+    declared: Vec<&'c Mention>,
+
+    /// Whether some labels are declared in a way that we don't understand.
+    opaque: bool,
+
+    /// Whether all of the code could be parsed, and all of the macros that it
+    /// uses, so that all of the `goto`s are known.
+    complete: bool,
+
+    /// The `goto`s. Each is the target, the code that jumps there, and the
+    /// macro that does it, if any.
+    gotos: Vec<(String, &'c Mention, Option<&'c str>)>,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{diagnostics::Lint, Dialect, WeaveOptions};
+
+    /// Get the label warnings about a WEB program.
+    fn warnings(web: &str) -> Vec<String> {
+        let state = crate::scan(web, Dialect::Web).unwrap();
+        let doc = crate::weave_document(
+            "test",
+            web,
+            &state,
+            &crate::themes::default_theme(),
+            &WeaveOptions::default(),
+        )
+        .unwrap();
+
+        doc.warnings
+            .into_iter()
+            .filter(|w| matches!(w.lint, Lint::UndeclaredLabel | Lint::UnusedLabel))
+            .map(|w| w.message)
+            .collect()
+    }
+
+    #[test]
+    fn non_local_goto() {
+        // As in tex.web, a procedure in the outer block jumps to a label of
+        // the main program, and a nested procedure jumps to one of the
+        // procedure containing it.
+        let web = "@ @p program tex;\n\
+                   label end_of_TEX;\n\
+                   @<Error handling procedures@>@;\n\
+                   begin end_of_TEX: end.\n\
+                   @ @<Error handling procedures@>=\n\
+                   procedure jump_out;\n\
+                   begin goto end_of_TEX;\n\
+                   end;\n\
+                   procedure p;\n\
+                   label exit, unused;\n\
+                   procedure q;\n\
+                   begin goto exit; end;\n\
+                   begin q; goto nowhere; exit: end;\n";
+
+        assert_eq!(
+            warnings(web),
+            [
+                "label `unused` is declared in `p`, but nothing jumps to it",
+                "label `nowhere` isn't declared in `p`",
+            ]
+        );
+    }
+}
@@ -0,0 +1,851 @@
+//! Weave Knuth's WEB language into TeX, with fancy syntax highlighting.
+//!
+//! Weaving happens in two passes. The first, [`scan`], builds up a [`State`]
+//! describing the program: its modules, index entries, string pool, and so on.
+//! The second, [`weave`], uses that state to emit the TeX document. The
+//! `tt-weave` command-line program is a thin layer on top of these two
+//! functions, using [`weave_to_writer`] to write out each module as soon as
+//! it's finished rather than holding the whole document in memory.
+//!
+//! The lower-level pieces are exposed too: [`weblang::WebCode::parse`] parses
+//! the Pascal code of a module into an AST, allocated in a
+//! [`weblang::AstArena`], and [`prettify::Prettifier`] lays it out and
+//! highlights it. To see what the lexer and parser make of a whole file, use
+//! [`dump_tokens`] and [`dump_ast`], or [`lex_modules`] to lex a large file one
+//! module at a time. To find out what the identifiers of a program are
+//! declared as, use [`symbol_table`]; the kinds of its macros are in
+//! [`State::macros`]. To spell-check the comments in the code, use
+//! [`extract_comments`]. To see which procedures call which,
+//! use [`WovenDocument::call_graph`]. To compare two versions of a program,
+//! use [`diff::diff`]. To prettify the code as HTML instead of TeX, use
+//! [`weave_html`], or [`weave_html_site`] for a complete static site; with
+//! the `wasm` feature, the `wasm` module exposes this to JavaScript, and with
+//! the `capi` feature, the `capi` module exposes it to C.
+
+use std::collections::HashMap;
+use syntect::highlighting::Theme;
+use tectonic_errors::prelude::*;
+
+use crate::diagnostics::{Lint, LintLevel, LintLevels, Warning};
+
+pub mod c_token;
+#[cfg(feature = "serde")]
+pub mod cache;
+pub mod callgraph;
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod changefile;
+pub mod comments;
+pub mod config;
+mod constants;
+pub mod control;
+pub mod diagnostics;
+pub mod dialect;
+#[cfg(feature = "serde")]
+pub mod diff;
+mod directives;
+pub mod encoding;
+pub mod index;
+mod labels;
+pub mod macros;
+pub mod metrics;
+pub mod parse_base;
+pub mod pascal_token;
+mod pass1;
+mod pass2;
+pub mod prettify;
+pub mod project;
+mod reachability;
+pub mod rename;
+pub mod reserved;
+pub mod sections;
+pub mod site;
+pub mod standalone;
+pub mod state;
+mod strict;
+pub mod style;
+mod symbols;
+pub mod symtab;
+pub mod tags;
+pub mod themes;
+pub mod timings;
+pub mod token;
+mod unreachable;
+pub mod verify;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod weblang;
+pub mod webmac;
+pub mod xref;
+
+pub use crate::{
+    dialect::Dialect,
+    encoding::InputEncoding,
+    pass2::{
+        CheckFailure, HtmlFragment, IdentifierLocation, LexedFragment, LexedModule, ModuleLexer,
+    },
+    sections::SectionSelection,
+    state::{ModuleId, State},
+};
+
+/// The name of the theme, among syntect's defaults, that we use to highlight
+/// code.
+pub const DEFAULT_THEME: &str = "InspiredGitHub";
+
+/// Options controlling how a document is woven.
+#[derive(Clone, Debug, Default)]
+pub struct WeaveOptions {
+    /// Whether to emit a mini-index after each module. See
+    /// [`State::emit_mini_index`].
+    pub mini_index: bool,
+
+    /// If set, only emit these modules. The table of contents and the indices
+    /// are omitted in this case, since they would refer to modules that aren't
+    /// in the output.
+    pub sections: Option<SectionSelection>,
+
+    /// If set, the table of contents is written to a separate file with this
+    /// name, which the document `\input`s, rather than into the document
+    /// itself. The caller is responsible for writing the file, whose text is
+    /// [`WovenDocument::contents`].
+    pub contents_file: Option<String>,
+
+    /// Likewise, if set, the indices are written to a separate file with this
+    /// name, whose text is [`WovenDocument::index`].
+    pub index_file: Option<String>,
+
+    /// Named modules to omit from the output, along with all of the modules
+    /// that contribute code to them. Names may be abbreviated with a trailing
+    /// `...`, as in WEB. Modules are numbered as if nothing were omitted, and
+    /// the indices still refer to the omitted modules.
+    pub exclude_modules: Vec<String>,
+
+    /// Starred parts to omit from the output, each identified by the number
+    /// of the module that starts it. See [`State::major_module_part`].
+    pub exclude_parts: Vec<ModuleId>,
+
+    /// The width, in characters, to which the code is laid out. If unset,
+    /// [`prettify::DEFAULT_WIDTH`] is used.
+    pub width: Option<usize>,
+
+    /// The width, in characters, to which code in the TeX part of a module is
+    /// laid out. A wider one keeps longer fragments of code on one line. If
+    /// unset, [`Self::width`] is used.
+    pub inline_width: Option<usize>,
+
+    /// If set, code in the TeX part of a module that takes more than this many
+    /// lines is displayed, like the code in the Pascal part, rather than set
+    /// inline in the text. Otherwise, such code is always set inline.
+    pub inline_max_lines: Option<usize>,
+
+    /// How many levels of indentation the code may be nested in before it's
+    /// no longer wrapped to fit the width. Code nested more deeply is laid out
+    /// in long lines instead of ever narrower columns. If unset,
+    /// [`prettify::DEFAULT_MAX_DEPTH`] is used.
+    pub max_depth: Option<usize>,
+
+    /// Custom TeX to emit in place of particular identifiers in the code, keyed
+    /// by identifier. For instance, this can map `TeX_banner` to something
+    /// fancier than the plain identifier. The code is laid out as if the
+    /// identifiers were written normally, so replacements should be about as
+    /// wide as the identifiers that they replace.
+    pub identifier_tex: HashMap<String, String>,
+
+    /// How the code is written out, such as whether the operators are written
+    /// as in Pascal. See [`prettify::CodeStyle`].
+    pub style: prettify::CodeStyle,
+
+    /// How to handle each kind of warning. See [`WovenDocument::warnings`].
+    pub lints: LintLevels,
+
+    /// The limits enforced by the style lints, which are allowed by default.
+    /// See [`style`].
+    pub style_limits: style::StyleLimits,
+
+    /// Whether to keep track of how long each phase of weaving each module
+    /// takes. See [`WovenDocument::timings`].
+    pub timings: bool,
+
+    /// Whether to measure the code of each module, such as how many
+    /// statements it has and how complex it is. See [`WovenDocument::metrics`].
+    pub metrics: bool,
+
+    /// If set, code laid out by an earlier run, which is reused wherever the
+    /// code hasn't changed. See [`cache`].
+    #[cfg(feature = "serde")]
+    pub cache: Option<cache::CodeCache>,
+
+    /// Whether to build a cross-reference database of the program. See
+    /// [`WovenDocument::xref`].
+    pub xref: bool,
+
+    /// Whether to annotate each procedure and function definition with the
+    /// sections that use it. This takes an extra pass over the code. See
+    /// [`xref::XrefDatabase::function_uses`].
+    pub function_uses: bool,
+
+    /// Whether to wrap the identifiers in the code in TeX macros giving the
+    /// number of the module where each one is declared, so that uses can link
+    /// to declarations, as in [`weave_html`]. This takes an extra pass over the
+    /// code. See [`prettify::IdentifierAnchors`].
+    pub identifier_links: bool,
+
+    /// Whether to make the output a complete LaTeX document, which can be
+    /// compiled to PDF on its own rather than as part of a Tectonic project.
+    /// In this case, the overrides file is only `\input` if it exists. See
+    /// [`standalone`].
+    pub standalone: bool,
+
+    /// Whether to structure the output like WEAVE's, using the macros of
+    /// `webmac.tex` rather than tt-weave's own. In this case, the code isn't
+    /// highlighted, `webmac.tex` generates the table of contents, so
+    /// [`Self::contents_file`] is ignored, and so are [`Self::mini_index`] and
+    /// [`Self::standalone`]. See [`webmac`].
+    pub webmac: bool,
+}
+
+/// Run the first pass over WEB source text.
+///
+/// This gathers up all of the information needed to weave the program, which
+/// can also be used to emit auxiliary files like the string pool.
+pub fn scan(text: &str, dialect: Dialect) -> Result<State> {
+    pass1::execute(parse_base::Span::new_extra(text, dialect))
+}
+
+/// A woven TeX document, which can be split up into per-module fragments.
+#[derive(Clone, Debug)]
+pub struct WovenDocument {
+    /// The complete TeX text of the document.
+    pub tex: String,
+
+    /// The offset in `tex` at which each module starts.
+    module_starts: Vec<(ModuleId, usize)>,
+
+    /// The offset in `tex` at which the material following the last module
+    /// starts.
+    body_end: usize,
+
+    /// Warnings about the source text, in no particular order. Lints that are
+    /// allowed by [`WeaveOptions::lints`] aren't included, and it's up to the
+    /// caller to decide what to do about denied ones.
+    pub warnings: Vec<Warning>,
+
+    /// The problems encountered while parsing the Pascal code. The code that
+    /// couldn't be parsed is emitted as it's written, so these don't prevent
+    /// the document from being woven, but the caller should report them.
+    pub parse_failures: Vec<CheckFailure>,
+
+    /// How long each phase of weaving each module took, if
+    /// [`WeaveOptions::timings`] is set.
+    pub timings: Option<timings::Timings>,
+
+    /// The measurements of the code of each module, if
+    /// [`WeaveOptions::metrics`] is set. See [`metrics`].
+    pub metrics: Option<metrics::Metrics>,
+
+    /// The code laid out by this run, if [`WeaveOptions::cache`] is set, to
+    /// be saved for the next one. Only the code that was used is included, so
+    /// the cache doesn't accumulate code that has since been edited away.
+    #[cfg(feature = "serde")]
+    pub cache: Option<cache::CodeCache>,
+
+    /// The identifiers declared by the woven code. If only some modules were
+    /// selected, this only covers those modules.
+    pub symbols: symtab::SymbolTable,
+
+    /// Which procedures and functions in the woven code call which. This is
+    /// empty for CWEB programs. If only some modules were selected, only the
+    /// calls made by the code of those modules are included.
+    pub call_graph: callgraph::CallGraph,
+
+    /// The cross-reference database of the woven code, if
+    /// [`WeaveOptions::xref`] is set. See [`xref`].
+    pub xref: Option<xref::XrefDatabase>,
+
+    /// The TeX of the table of contents, if [`WeaveOptions::contents_file`] is
+    /// set, to be written to that file.
+    pub contents: Option<String>,
+
+    /// The TeX of the indices, if [`WeaveOptions::index_file`] is set, to be
+    /// written to that file.
+    pub index: Option<String>,
+}
+
+impl WovenDocument {
+    /// Get the material preceding the first module: the limbo text and the
+    /// table of contents.
+    pub fn preamble(&self) -> &str {
+        let end = self
+            .module_starts
+            .first()
+            .map(|m| m.1)
+            .unwrap_or(self.body_end);
+        &self.tex[..end]
+    }
+
+    /// Iterate over the modules in the document, yielding the ID and TeX text
+    /// of each.
+    pub fn modules(&self) -> impl Iterator<Item = (ModuleId, &str)> {
+        self.module_starts
+            .iter()
+            .enumerate()
+            .map(move |(i, (id, start))| {
+                let end = self
+                    .module_starts
+                    .get(i + 1)
+                    .map(|m| m.1)
+                    .unwrap_or(self.body_end);
+                (*id, &self.tex[*start..end])
+            })
+    }
+
+    /// Get the material following the last module: the indices.
+    pub fn postamble(&self) -> &str {
+        &self.tex[self.body_end..]
+    }
+}
+
+/// Weave WEB source text into TeX.
+///
+/// The `state` must have been created by running [`scan`] on the same `text`.
+/// The `basename` is used to name an overrides file that the output attempts
+/// to `\input`. The Pascal code is highlighted using `theme`.
+///
+/// Unless only some modules are selected, the returned document includes all
+/// of the indices. Any warnings are discarded; use [`weave_document`] to get
+/// them.
+pub fn weave(
+    basename: &str,
+    text: &str,
+    state: &State,
+    theme: &Theme,
+    options: &WeaveOptions,
+) -> Result<String> {
+    Ok(weave_document(basename, text, state, theme, options)?.tex)
+}
+
+/// Weave WEB source text into TeX, keeping track of where each module's TeX
+/// starts and ends.
+///
+/// This is the same as [`weave`], except for the return type, which also
+/// includes any warnings.
+pub fn weave_document(
+    basename: &str,
+    text: &str,
+    state: &State,
+    theme: &Theme,
+    options: &WeaveOptions,
+) -> Result<WovenDocument> {
+    weave_document_to(basename, text, state, theme, options, None)
+}
+
+/// Weave WEB source text into TeX, writing it to `dest` as it goes.
+///
+/// This is the same as [`weave_document`], except that the TeX of each module
+/// is written out as soon as it's finished, rather than the whole document
+/// being held in memory. So, the returned document's [`WovenDocument::tex`] is
+/// empty, and it has no modules. If weaving fails, `dest` is left with the
+/// text written before the failure.
+pub fn weave_to_writer(
+    basename: &str,
+    text: &str,
+    state: &State,
+    theme: &Theme,
+    options: &WeaveOptions,
+    dest: &mut dyn std::io::Write,
+) -> Result<WovenDocument> {
+    weave_document_to(basename, text, state, theme, options, Some(dest))
+}
+
+/// Weave WEB source text into TeX, writing it to `dest` as it goes if that's
+/// set.
+fn weave_document_to(
+    basename: &str,
+    text: &str,
+    state: &State,
+    theme: &Theme,
+    options: &WeaveOptions,
+    mut dest: Option<&mut dyn std::io::Write>,
+) -> Result<WovenDocument> {
+    let input = parse_base::Span::new_extra(text, state.dialect());
+    let pass2::WovenBody {
+        mut tex,
+        mut module_starts,
+        mut warnings,
+        parse_failures,
+        timings,
+        metrics,
+        #[cfg(feature = "serde")]
+        cache,
+        symbols,
+        call_graph,
+        xref,
+    } = pass2::execute(
+        basename,
+        state,
+        theme,
+        options,
+        input,
+        dest.as_mut().map(|d| &mut **d as _),
+    )?;
+    let mut body_end = tex.len();
+
+    let level = options.lints.get(Lint::UndefinedModule);
+
+    if level != LintLevel::Allow {
+        for (name, module) in state.undefined_modules() {
+            let mut message = format!("named module <{}> is never defined", name);
+
+            if let Some(s) = diagnostics::did_you_mean(&state.suggest_module_names(name)) {
+                message = format!("{}; {}", message, s);
+            }
+
+            warnings.push(Warning {
+                lint: Lint::UndefinedModule,
+                level,
+                module,
+                message,
+                location: None,
+            });
+        }
+    }
+
+    let mut contents = None;
+    let mut index = None;
+
+    if options.sections.is_none() {
+        if options.contents_file.is_some() && !options.webmac {
+            let mut text = String::new();
+            state.emit_table_of_contents(&mut text)?;
+            contents = Some(text);
+        }
+
+        let mut text = String::new();
+
+        if options.webmac {
+            state.emit_webmac_index(&mut text)?;
+        } else {
+            state.emit_major_module_index(&mut text)?;
+            state.emit_named_module_index(&mut text)?;
+            state.emit_symbol_index(&symbols, &mut text)?;
+            state.emit_typeset_index(&mut text)?;
+        }
+
+        match options.index_file.as_ref() {
+            Some(name) => {
+                tex.push_str(&format!("\n\\input{{{}}}\n", name));
+                index = Some(text);
+            }
+
+            None => tex.push_str(&text),
+        }
+    }
+
+    if options.webmac {
+        // Without the index, there's no `\con` to end the document.
+        if options.sections.is_some() {
+            tex.push_str("\\end\n");
+        }
+    } else if options.standalone {
+        tex.push_str(standalone::POSTAMBLE);
+    }
+
+    if let Some(dest) = dest {
+        dest.write_all(tex.as_bytes())?;
+        dest.flush()?;
+        tex.clear();
+        module_starts.clear();
+        body_end = 0;
+    }
+
+    Ok(WovenDocument {
+        tex,
+        module_starts,
+        body_end,
+        warnings,
+        parse_failures,
+        timings,
+        metrics,
+        #[cfg(feature = "serde")]
+        cache,
+        symbols,
+        call_graph,
+        xref,
+        contents,
+        index,
+    })
+}
+
+/// Run WEB source text through all of the processing stages, for fuzzing.
+///
+/// The first byte of `data` selects the dialect, and the rest is the text,
+/// with any invalid UTF-8 replaced. The text is scanned, checked, and woven
+/// with default options. Malformed input should only ever produce an error, so
+/// any panic is a bug. The `fuzz` directory of the source tree has a
+/// `cargo fuzz` target built on this.
+pub fn parse_fuzz_input(data: &[u8]) -> Result<()> {
+    let (dialect, text) = match data.split_first() {
+        Some((b, rest)) => {
+            let dialect = match b % 3 {
+                0 => Dialect::Web,
+                1 => Dialect::Web2c,
+                _ => Dialect::Cweb,
+            };
+            (dialect, String::from_utf8_lossy(rest))
+        }
+        None => return Ok(()),
+    };
+
+    let state = scan(&text, dialect)?;
+    check(&text, &state, None)?;
+    weave_document(
+        "fuzz",
+        &text,
+        &state,
+        &Theme::default(),
+        &WeaveOptions::default(),
+    )?;
+    Ok(())
+}
+
+/// Prettify all of the code in WEB source text as HTML.
+///
+/// The `state` must have been created by running [`scan`] on the same `text`.
+/// Rather than a complete document, this returns the code fragments on their
+/// own, in order, so that they can be embedded in other material. The TeX
+/// commentary is omitted. The code is highlighted using `theme`. If `sections`
+/// is specified, only code in those modules is included.
+///
+/// Each declaration of an identifier gets an ID, and each use of one links to
+/// its declaration, as described in [`prettify::IdentifierAnchors`]. Finding
+/// the declarations takes an extra pass over all of the code.
+pub fn weave_html(
+    text: &str,
+    state: &State,
+    theme: &Theme,
+    sections: Option<&SectionSelection>,
+) -> Result<Vec<HtmlFragment>> {
+    let input = parse_base::Span::new_extra(text, state.dialect());
+    pass2::html(state, input, theme, None, sections)
+}
+
+/// Prettify all of the code in WEB source text as HTML, highlighted with both
+/// a light and a dark theme.
+///
+/// This is the same as [`weave_html`], except that the code follows the
+/// reader's `prefers-color-scheme` setting, using `light` or `dark` as
+/// appropriate. The colors are given by CSS custom properties, so the page
+/// must include [`prettify::DUAL_THEME_CSS`]; see
+/// [`prettify::Prettifier::emit_html_dual`].
+pub fn weave_html_dual(
+    text: &str,
+    state: &State,
+    light: &Theme,
+    dark: &Theme,
+    sections: Option<&SectionSelection>,
+) -> Result<Vec<HtmlFragment>> {
+    let input = parse_base::Span::new_extra(text, state.dialect());
+    pass2::html(state, input, light, Some(dark), sections)
+}
+
+/// Generate a standalone HTML site for WEB source text.
+///
+/// The `state` must have been created by running [`scan`] on the same `text`.
+/// The site's page is titled `title`, and its code is highlighted using
+/// `theme`. If `dark_theme` is specified, the code is highlighted with it too,
+/// for readers who prefer a dark color scheme, as in [`weave_html_dual`]. See
+/// the [`site`] module for a description of the site. The files are returned
+/// rather than written out, so that the caller can decide where they go.
+pub fn weave_html_site(
+    title: &str,
+    text: &str,
+    state: &State,
+    theme: &Theme,
+    dark_theme: Option<&Theme>,
+) -> Result<site::HtmlSite> {
+    let fragments = match dark_theme {
+        Some(dark) => weave_html_dual(text, state, theme, dark, None)?,
+        None => weave_html(text, state, theme, None)?,
+    };
+
+    Ok(site::build(title, state, &fragments, dark_theme.is_some()))
+}
+
+/// Options for [`prettify_fragment`].
+#[derive(Clone, Debug, Default)]
+pub struct FragmentOptions {
+    /// The dialect of WEB in which the code is written.
+    pub dialect: Dialect,
+
+    /// The theme used to highlight the code. If unset, the default theme is
+    /// used.
+    pub theme: Option<Theme>,
+
+    /// Whether to emit HTML rather than TeX.
+    pub html: bool,
+
+    /// How the code is laid out. Only the options that concern the code itself
+    /// apply, like [`WeaveOptions::width`], [`WeaveOptions::style`], and
+    /// [`WeaveOptions::webmac`].
+    pub weave: WeaveOptions,
+}
+
+/// A fragment of code prettified by [`prettify_fragment`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PrettifiedFragment {
+    /// The markup for the code.
+    pub output: String,
+
+    /// The problems encountered while parsing the code. Code that can't be
+    /// parsed is still prettified, following its line breaks in the source.
+    pub diagnostics: Vec<FragmentDiagnostic>,
+}
+
+/// A problem parsing a fragment of code given to [`prettify_fragment`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FragmentDiagnostic {
+    /// The byte offsets of the offending code in the fragment.
+    pub range: std::ops::Range<usize>,
+
+    /// What went wrong.
+    pub message: String,
+}
+
+impl FragmentDiagnostic {
+    /// Render a diagnostic describing the problem, quoting the offending code.
+    ///
+    /// The `code` must be the fragment that was prettified, and `path` is used
+    /// to name it.
+    pub fn render(&self, code: &str, path: Option<&str>) -> String {
+        diagnostics::render(
+            code,
+            path,
+            self.range.clone(),
+            "code could not be parsed",
+            &self.message,
+        )
+    }
+}
+
+/// Wrap a fragment of code in a module, so that it can be processed as a WEB
+/// file, returning the text and the offset of the code in it. A fragment that
+/// starts with a macro definition, a format definition, or the name of the
+/// module that it defines already has its own part structure; anything else is
+/// taken to be the code of an unnamed module.
+fn fragment_source(code: &str, dialect: Dialect) -> (String, usize) {
+    let head = code.trim_start();
+
+    let structured = ["@d", "@D", "@f", "@F", "@p", "@P", "@c", "@C"]
+        .iter()
+        .any(|c| head.starts_with(c))
+        || head
+            .strip_prefix("@<")
+            .and_then(|rest| rest.split_once("@>"))
+            .is_some_and(|(_, rest)| {
+                let rest = rest.trim_start();
+                rest.starts_with('=') || rest.starts_with("+=")
+            });
+
+    let prefix = if structured {
+        "@ "
+    } else if dialect.is_cweb() {
+        "@ @c\n"
+    } else {
+        "@ @p\n"
+    };
+
+    (format!("{}{}\n", prefix, code), prefix.len())
+}
+
+/// Prettify a fragment of WEB code that isn't part of a complete WEB file, like
+/// an example in documentation, in one go.
+///
+/// The `code` is processed as the code part of a single module, so it's parsed
+/// as a sequence of toplevels. It may also start with macro definitions or the
+/// name of the module that it defines, as in `@<Do something@>=`. The result
+/// has the markup for just the code, laid out and highlighted according to
+/// `options`, along with any problems encountered while parsing it. The markup
+/// is HTML if [`FragmentOptions::html`] is set, and otherwise TeX using the
+/// same macros as a woven document.
+pub fn prettify_fragment(code: &str, options: &FragmentOptions) -> Result<PrettifiedFragment> {
+    let (text, offset) = fragment_source(code, options.dialect);
+    let state = scan(&text, options.dialect)?;
+    let input = parse_base::Span::new_extra(&text, options.dialect);
+
+    let default_theme;
+    let theme = match options.theme.as_ref() {
+        Some(t) => t,
+        None => {
+            default_theme = themes::default_theme();
+            &default_theme
+        }
+    };
+
+    let (output, failures) = pass2::snippet(&state, input, theme, &options.weave, options.html)?;
+
+    let diagnostics = failures
+        .into_iter()
+        .map(|f| {
+            let clamp = |i: usize| i.saturating_sub(offset).min(code.len());
+
+            FragmentDiagnostic {
+                range: clamp(f.error.span.start)..clamp(f.error.span.end),
+                message: f.error.to_string(),
+            }
+        })
+        .collect();
+
+    Ok(PrettifiedFragment {
+        output,
+        diagnostics,
+    })
+}
+
+/// Parse all of the Pascal code in WEB source text, returning a JSON
+/// serialization of the resulting ASTs.
+///
+/// The `state` must have been created by running [`scan`] on the same `text`.
+/// The result is an array with one entry for each fragment of Pascal code,
+/// giving the module in which it appears, the context in which it appears
+/// (`named`, `anonymous`, `define`, `format`, or `inline`), the module
+/// reference if it is part of a named module, and the AST itself. If
+/// `sections` is specified, only code in those modules is included.
+#[cfg(feature = "serde")]
+pub fn dump_ast(text: &str, state: &State, sections: Option<&SectionSelection>) -> Result<String> {
+    let input = parse_base::Span::new_extra(text, state.dialect());
+    let entries = pass2::dump(state, input, sections)?;
+    Ok(serde_json::to_string_pretty(&entries)?)
+}
+
+/// Check that all of the Pascal code in WEB source text can be parsed,
+/// returning a list of the problems encountered. Parsing continues past each
+/// problem, so a fragment of code may have several. Use
+/// [`CheckFailure::render`] to describe the problems to the user.
+///
+/// The `state` must have been created by running [`scan`] on the same `text`.
+/// Problems that prevent the document from being processed at all, rather
+/// than affecting individual fragments of code, are returned as errors. If
+/// `sections` is specified, only code in those modules is checked.
+pub fn check(
+    text: &str,
+    state: &State,
+    sections: Option<&SectionSelection>,
+) -> Result<Vec<CheckFailure>> {
+    let input = parse_base::Span::new_extra(text, state.dialect());
+    pass2::check(state, input, sections)
+}
+
+/// Build the symbol table of the Pascal code in WEB source text, recording
+/// what each identifier is declared as and where.
+///
+/// The `state` must have been created by running [`scan`] on the same `text`.
+/// Declarations in code that can't be parsed are missed. If `sections` is
+/// specified, only code in those modules is included. When weaving, the table
+/// is also available as [`WovenDocument::symbols`].
+pub fn symbol_table(
+    text: &str,
+    state: &State,
+    sections: Option<&SectionSelection>,
+) -> Result<symtab::SymbolTable> {
+    let input = parse_base::Span::new_extra(text, state.dialect());
+    pass2::symbol_table(state, input, sections)
+}
+
+/// Build the cross-reference database of the Pascal code in WEB source text,
+/// without weaving it.
+///
+/// The `state` must have been created by running [`scan`] on the same `text`.
+/// When weaving, the database is also available as [`WovenDocument::xref`] if
+/// [`WeaveOptions::xref`] is set.
+pub fn cross_reference(text: &str, state: &State) -> Result<xref::XrefDatabase> {
+    let input = parse_base::Span::new_extra(text, state.dialect());
+    pass2::cross_reference(state, input)
+}
+
+/// Extract the text of the comments in the Pascal code of WEB source text, in
+/// order, for tools like spell-checkers.
+///
+/// The `state` must have been created by running [`scan`] on the same `text`.
+/// See [`comments`] for how the comments are converted to plain text. If
+/// `sections` is specified, only the comments in those modules are extracted.
+pub fn extract_comments(
+    text: &str,
+    state: &State,
+    sections: Option<&SectionSelection>,
+) -> Result<Vec<comments::CommentText>> {
+    let input = parse_base::Span::new_extra(text, state.dialect());
+    pass2::comments(state, input, sections)
+}
+
+/// Find the places where an identifier is defined in the Pascal code of WEB
+/// source text, in order.
+///
+/// The `state` must have been created by running [`scan`] on the same `text`.
+/// See [`weblang::WebCode::find_definitions`] for what counts as a definition.
+/// Results may be incomplete for code that can't be parsed. If `sections` is
+/// specified, only code in those modules is searched.
+pub fn find_definitions(
+    text: &str,
+    state: &State,
+    name: &str,
+    sections: Option<&SectionSelection>,
+) -> Result<Vec<IdentifierLocation>> {
+    let input = parse_base::Span::new_extra(text, state.dialect());
+    let query = pass2::IdentifierQuery::Definitions(name.to_owned());
+    pass2::query(state, input, query, sections)
+}
+
+/// Find the places where an identifier is used in the Pascal code of WEB source
+/// text, in order.
+///
+/// This is the counterpart to [`find_definitions`], and works in the same way.
+pub fn find_uses(
+    text: &str,
+    state: &State,
+    name: &str,
+    sections: Option<&SectionSelection>,
+) -> Result<Vec<IdentifierLocation>> {
+    let input = parse_base::Span::new_extra(text, state.dialect());
+    let query = pass2::IdentifierQuery::Uses(name.to_owned());
+    pass2::query(state, input, query, sections)
+}
+
+/// Lex the Pascal code in WEB source text one module at a time.
+///
+/// The `state` must have been created by running [`scan`] on the same `text`.
+/// The returned iterator yields each module, with its fragments of Pascal code,
+/// as it's lexed, so that callers can process a large document without holding
+/// all of its tokens in memory. If `sections` is specified, only those modules
+/// are included.
+pub fn lex_modules<'a>(
+    text: &'a str,
+    state: &'a State,
+    sections: Option<&SectionSelection>,
+) -> Result<ModuleLexer<'a>> {
+    let input = parse_base::Span::new_extra(text, state.dialect());
+    pass2::lex(state, input, sections)
+}
+
+/// Lex all of the Pascal code in WEB source text, returning a JSON
+/// serialization of the resulting tokens.
+///
+/// This is like [`dump_ast`], except that each entry has a list of `tokens`
+/// rather than the parsed `code`. Tokens that are associated with a specific
+/// location in the input are annotated with their line and column. Since no
+/// parsing is done, this works even if the code can't be parsed.
+#[cfg(feature = "serde")]
+pub fn dump_tokens(
+    text: &str,
+    state: &State,
+    sections: Option<&SectionSelection>,
+) -> Result<String> {
+    let mut entries = Vec::new();
+
+    for module in lex_modules(text, state, sections)? {
+        for fragment in module?.fragments {
+            entries.push(serde_json::to_value(fragment)?);
+        }
+    }
+
+    Ok(serde_json::to_string_pretty(&entries)?)
+}
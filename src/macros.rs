@@ -0,0 +1,313 @@
+//! The kinds of WEB macros.
+//!
+//! A WEB macro can stand for just about anything: a number, a string, a piece
+//! of an expression, or one or more statements. Code that uses macros is much
+//! easier to parse and lay out if we know which, so during the first pass each
+//! `@d` definition is classified by looking at its right-hand side, giving a
+//! [`MacroTable`] that's available from [`crate::state::State::macros`].
+//!
+//! The classification is heuristic. Macros defined with `=` are numeric, as
+//! are `==` macros whose right-hand sides are integer arithmetic on literals
+//! and other numeric macros. Right-hand sides consisting of string literals are
+//! string-like. Right-hand sides that are empty, or that contain assignments,
+//! semicolons, or statement keywords like `begin` and `if`, are statement-like,
+//! since Pascal has no other use for those. A macro that just renames another
+//! macro, or that calls a statement-like macro, is of the same kind as that
+//! macro. Everything else is expression-like. Only the part of the right-hand
+//! side before any comment is considered.
+//!
+//! The table also notes the macros, like `return`, that expand to nothing but a
+//! `goto`, since code after them can never run.
+
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    pascal_token::{DelimiterKind, PascalToken, StringLiteralKind},
+    reserved::PascalReservedWord,
+    symbols::normalize,
+};
+
+/// What kind of thing a macro stands for.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MacroKind {
+    /// An integer constant, such as `@d buf_size=500`.
+    Numeric,
+
+    /// A string, such as `@d banner=='This is TeX'`.
+    String,
+
+    /// An expression, or a piece of one, such as `@d link(#)==mem[#].hh.rh`.
+    Expression,
+
+    /// One or more statements, or a piece of one, such as
+    /// `@d incr(#)==#:=#+1`.
+    Statement,
+}
+
+impl MacroKind {
+    /// A short lowercase name for the kind.
+    pub fn name(&self) -> &'static str {
+        match self {
+            MacroKind::Numeric => "numeric",
+            MacroKind::String => "string",
+            MacroKind::Expression => "expression",
+            MacroKind::Statement => "statement",
+        }
+    }
+}
+
+/// What a definition says about the kind of a macro, before the other macros
+/// are known.
+#[derive(Clone, Debug)]
+enum Definition {
+    /// The kind is known outright.
+    Known(MacroKind),
+
+    /// Integer arithmetic involving these identifiers, which is numeric if
+    /// they're all numeric macros.
+    Arithmetic(Vec<String>),
+
+    /// Just another identifier, whose kind this shares.
+    Alias(String),
+
+    /// A call of this identifier, which is statement-like if the identifier is.
+    Call(String),
+}
+
+/// The kind of each macro, keyed by normalized name.
+///
+/// See the [module-level documentation](self) for an overview.
+#[derive(Clone, Debug, Default)]
+pub struct MacroTable {
+    /// The definitions, in the order in which they were seen.
+    definitions: Vec<(String, Definition)>,
+
+    /// The kinds, once they've been resolved.
+    kinds: HashMap<String, MacroKind>,
+
+    /// The macros that expand to a `goto`.
+    jumps: HashSet<String>,
+}
+
+impl MacroTable {
+    /// Record a macro definition, given its tokens, starting with the name of
+    /// the macro. If a macro is defined more than once, the last definition
+    /// wins.
+    pub(crate) fn define(&mut self, tokens: &[PascalToken]) {
+        let tokens: Vec<_> = tokens
+            .iter()
+            .filter(|t| {
+                !matches!(
+                    t,
+                    PascalToken::Formatting
+                        | PascalToken::DefinitionFlag
+                        | PascalToken::CancelDefinitionFlag
+                        | PascalToken::IndexEntry(..)
+                )
+            })
+            .collect();
+
+        let name = match tokens.first() {
+            Some(PascalToken::Identifier(name)) => normalize(&name.value),
+            _ => return,
+        };
+
+        let eq = match tokens
+            .iter()
+            .position(|t| matches!(t, PascalToken::Equals | PascalToken::Equivalence))
+        {
+            Some(i) => i,
+            None => return,
+        };
+
+        let definition = if let PascalToken::Equals = tokens[eq] {
+            Definition::Known(MacroKind::Numeric)
+        } else {
+            classify(&tokens[eq + 1..])
+        };
+
+        match &tokens[eq..] {
+            [PascalToken::Equivalence, goto, PascalToken::Identifier(_) | PascalToken::IntLiteral(..)]
+                if goto.is_reserved_word(PascalReservedWord::Goto) =>
+            {
+                self.jumps.insert(name.clone());
+            }
+
+            _ => {
+                self.jumps.remove(&name);
+            }
+        }
+
+        self.definitions.push((name, definition));
+    }
+
+    /// Work out the kinds of the macros, once all of the definitions have been
+    /// recorded.
+    pub(crate) fn resolve(&mut self) {
+        let definitions: HashMap<_, _> = self.definitions.drain(..).collect();
+        let mut kinds = HashMap::new();
+
+        for name in definitions.keys() {
+            resolve_one(name, &definitions, &mut kinds, &mut HashSet::new());
+        }
+
+        self.kinds = kinds;
+    }
+
+    /// Get the kind of a macro, if the identifier names one.
+    pub fn kind(&self, name: &str) -> Option<MacroKind> {
+        self.kinds.get(&normalize(name)).copied()
+    }
+
+    /// Determine whether an identifier names a statement-like macro.
+    pub fn is_statement(&self, name: &str) -> bool {
+        self.kind(name) == Some(MacroKind::Statement)
+    }
+
+    /// Determine whether an identifier names a macro that expands to a `goto`.
+    pub fn is_jump(&self, name: &str) -> bool {
+        self.jumps.contains(&normalize(name))
+    }
+
+    /// Iterate over the macros and their kinds, keyed by normalized name, in
+    /// no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, MacroKind)> {
+        self.kinds.iter().map(|(k, v)| (k.as_str(), *v))
+    }
+
+    /// The number of macros.
+    pub fn len(&self) -> usize {
+        self.kinds.len()
+    }
+
+    /// Determine whether there are no macros.
+    pub fn is_empty(&self) -> bool {
+        self.kinds.is_empty()
+    }
+}
+
+/// Classify the right-hand side of a `==` definition.
+fn classify(rhs: &[&PascalToken]) -> Definition {
+    use PascalReservedWord::*;
+
+    if rhs.is_empty() {
+        return Definition::Known(MacroKind::Statement);
+    }
+
+    let is_statement = rhs.iter().any(|t| match t {
+        PascalToken::Gets | PascalToken::Semicolon => true,
+
+        PascalToken::ReservedWord(rw) => matches!(
+            rw.value,
+            Begin | End | If | Then | Else | While | Do | For | Repeat | Until | Case | Goto | With
+        ),
+
+        _ => false,
+    });
+
+    if is_statement {
+        return Definition::Known(MacroKind::Statement);
+    }
+
+    // A one-character double-quoted string stands for the character's code,
+    // but other strings are strings.
+
+    let is_char = |t: &PascalToken| match t {
+        PascalToken::StringLiteral(StringLiteralKind::DoubleQuote, s) => {
+            s.value.chars().count() == 1
+        }
+        _ => false,
+    };
+
+    if rhs
+        .iter()
+        .all(|t| matches!(t, PascalToken::StringLiteral(..)) && !is_char(t))
+    {
+        return Definition::Known(MacroKind::String);
+    }
+
+    if let [PascalToken::Identifier(name)] = rhs {
+        return Definition::Alias(normalize(&name.value));
+    }
+
+    let mut deps = Vec::new();
+
+    let is_arithmetic = rhs.iter().all(|t| match t {
+        PascalToken::IntLiteral(..)
+        | PascalToken::Plus
+        | PascalToken::Minus
+        | PascalToken::Times
+        | PascalToken::OpenDelimiter(DelimiterKind::Paren)
+        | PascalToken::CloseDelimiter(DelimiterKind::Paren) => true,
+
+        PascalToken::ReservedWord(rw) => matches!(rw.value, Div | Mod),
+
+        PascalToken::Identifier(name) => {
+            deps.push(normalize(&name.value));
+            true
+        }
+
+        t => is_char(t),
+    });
+
+    if is_arithmetic {
+        return Definition::Arithmetic(deps);
+    }
+
+    if let [PascalToken::Identifier(name), PascalToken::OpenDelimiter(DelimiterKind::Paren), ..] =
+        rhs
+    {
+        return Definition::Call(normalize(&name.value));
+    }
+
+    Definition::Known(MacroKind::Expression)
+}
+
+/// Work out the kind of one macro, following the macros that it refers to.
+/// Macros whose definitions refer to themselves, directly or indirectly, are
+/// expression-like.
+fn resolve_one(
+    name: &str,
+    definitions: &HashMap<String, Definition>,
+    kinds: &mut HashMap<String, MacroKind>,
+    seen: &mut HashSet<String>,
+) -> Option<MacroKind> {
+    if let Some(kind) = kinds.get(name) {
+        return Some(*kind);
+    }
+
+    let definition = definitions.get(name)?;
+
+    if !seen.insert(name.to_owned()) {
+        return Some(MacroKind::Expression);
+    }
+
+    let kind = match definition {
+        Definition::Known(kind) => *kind,
+
+        Definition::Arithmetic(deps) => {
+            if deps
+                .iter()
+                .all(|d| resolve_one(d, definitions, kinds, seen) == Some(MacroKind::Numeric))
+            {
+                MacroKind::Numeric
+            } else {
+                MacroKind::Expression
+            }
+        }
+
+        Definition::Alias(other) => {
+            resolve_one(other, definitions, kinds, seen).unwrap_or(MacroKind::Expression)
+        }
+
+        Definition::Call(other) => match resolve_one(other, definitions, kinds, seen) {
+            Some(MacroKind::Statement) => MacroKind::Statement,
+            _ => MacroKind::Expression,
+        },
+    };
+
+    kinds.insert(name.to_owned(), kind);
+    Some(kind)
+}
@@ -1,50 +1,1524 @@
-use clap::Parser;
-use std::path::PathBuf;
-use tectonic_errors::prelude::*;
+//! The `tt-weave` command-line program.
 
-mod control;
-mod index;
-mod parse_base;
-mod pascal_token;
-mod pass1;
-mod pass2;
-mod prettify;
-mod reserved;
-mod state;
-mod token;
-mod weblang;
+use clap::{ArgMatches, Args as ClapArgs, CommandFactory, FromArgMatches, Parser, Subcommand};
+use log::LevelFilter;
+use std::{
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    time::Instant,
+};
+use syntect::highlighting::Theme;
+use tectonic_errors::prelude::*;
+use tt_weave::{
+    cache::CodeCache,
+    changefile::{self, Renumbered},
+    config::Config,
+    diagnostics::{self, Lint, LintLevel, LintLevels},
+    diff::{self, ModuleChange},
+    prettify::{CodeStyle, CommentStyle, SemicolonPolicy},
+    project,
+    project::ManifestFile,
+    rename,
+    site::HtmlSite,
+    style::StyleLimits,
+    verify::{self, Listing},
+    Dialect, FragmentOptions, InputEncoding, ModuleId, SectionSelection, WeaveOptions,
+    WovenDocument,
+};
 
 /// CLI arguments.
 #[derive(Parser, Debug)]
-#[clap(version, about, long_about = None)]
+#[clap(
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Args {
-    /// Name of the input WEB file to process
-    #[clap()]
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    /// Name of the input WEB file to process, or `-` to read it from standard
+    /// input
+    #[clap(required_unless_present = "project")]
+    input_path: Option<PathBuf>,
+
+    /// Apply this change file to the input before processing it
+    #[clap(long)]
+    change_file: Option<PathBuf>,
+
+    /// Emit a mini-index after each module, listing where the identifiers
+    /// that it uses are defined
+    #[clap(long, overrides_with = "no-mini-index")]
+    mini_index: bool,
+
+    /// Don't emit mini-indices, even if the configuration file asks for them
+    #[clap(long, overrides_with = "mini-index")]
+    no_mini_index: bool,
+
+    /// Annotate each procedure and function definition with the sections
+    /// that use it
+    #[clap(long, overrides_with = "no-function-uses")]
+    function_uses: bool,
+
+    /// Don't annotate procedure and function definitions with the sections
+    /// that use them, even if the configuration file asks for it
+    #[clap(long, overrides_with = "function-uses")]
+    no_function_uses: bool,
+
+    /// Wrap the identifiers in the code in macros giving the sections where
+    /// they're declared, so that each use can link to its declaration
+    #[clap(long, overrides_with = "no-identifier-links")]
+    identifier_links: bool,
+
+    /// Don't link the identifiers in the code to their declarations, even if
+    /// the configuration file asks for it
+    #[clap(long, overrides_with = "identifier-links")]
+    no_identifier_links: bool,
+
+    /// Only process these sections, specified as a list of section numbers and
+    /// ranges like `120-140,260`. The table of contents and the indices are
+    /// omitted
+    #[clap(long)]
+    sections: Option<SectionSelection>,
+
+    /// Omit this named module from the output, along with all of the sections
+    /// that contribute to it. The name may be abbreviated with `...`, as in
+    /// WEB. Sections keep their usual numbers. May be given multiple times
+    #[clap(long, value_name = "NAME", multiple_occurrences = true)]
+    exclude_module: Vec<String>,
+
+    /// Omit the starred part that starts with this section from the output.
+    /// Sections keep their usual numbers. May be given multiple times
+    #[clap(long, value_name = "SECTION", multiple_occurrences = true)]
+    exclude_part: Vec<usize>,
+
+    /// Lay out the code to this width, in characters
+    #[clap(long)]
+    width: Option<usize>,
+
+    /// Lay out the code in the TeX parts of modules to this width, in
+    /// characters, rather than the width of the rest of the code
+    #[clap(long, value_name = "WIDTH")]
+    inline_width: Option<usize>,
+
+    /// Display the code in the TeX parts of modules that takes more than this
+    /// many lines, rather than setting it inline in the text
+    #[clap(long, value_name = "LINES")]
+    inline_max_lines: Option<usize>,
+
+    /// Stop wrapping the code to fit the width once it's nested in more than
+    /// this many levels of indentation
+    #[clap(long, value_name = "LEVELS")]
+    max_depth: Option<usize>,
+
+    /// Highlight the code using this theme, among syntect's defaults
+    #[clap(long)]
+    theme: Option<String>,
+
+    /// Only allow the default highlighting theme, which is built in, rather
+    /// than any of syntect's other themes, which are slower to load
+    #[clap(long)]
+    no_default_themes: bool,
+
+    /// In the HTML site, also highlight the code using this theme, for readers
+    /// who prefer a dark color scheme. The main theme is used otherwise
+    #[clap(long, value_name = "THEME", requires = "html-site")]
+    dark_theme: Option<String>,
+
+    /// Emit this TeX in place of an identifier in the code, specified as
+    /// `NAME=TEX`. May be given multiple times
+    #[clap(long, value_name = "NAME=TEX", multiple_occurrences = true, parse(try_from_str = parse_identifier_tex))]
+    identifier_tex: Vec<(String, String)>,
+
+    /// Write operators like `:=`, `<>`, and `and` as they're written in
+    /// Pascal, rather than substituting C-like ones like `=`, `!=`, and `&&`
+    #[clap(long, overrides_with = "no-preserve-operators")]
+    preserve_operators: bool,
+
+    /// Substitute C-like operators for Pascal's, even if the configuration
+    /// file asks to preserve them
+    #[clap(long, overrides_with = "preserve-operators")]
+    no_preserve_operators: bool,
+
+    /// When to write the semicolons that end statements: after every statement
+    /// that takes one, only where the source has them, or everywhere except
+    /// before the `end` of a block
+    #[clap(long, arg_enum, value_name = "POLICY")]
+    semicolons: Option<SemicolonPolicy>,
+
+    /// Split the digits of long decimal literals into groups of three, as in
+    /// `1_000_000`
+    #[clap(long, overrides_with = "no-group-digits")]
+    group_digits: bool,
+
+    /// Don't group the digits of decimal literals, even if the configuration
+    /// file asks for it
+    #[clap(long, overrides_with = "group-digits")]
+    no_group_digits: bool,
+
+    /// Write hexadecimal literals with uppercase digits, as in `0xFF`
+    #[clap(long, overrides_with = "no-uppercase-hex")]
+    uppercase_hex: bool,
+
+    /// Write hexadecimal literals with lowercase digits, even if the
+    /// configuration file asks for uppercase ones
+    #[clap(long, overrides_with = "uppercase-hex")]
+    no_uppercase_hex: bool,
+
+    /// Write octal literals in WEB's notation, as in `@'777`, rather than
+    /// converting them to hexadecimal
+    #[clap(long, overrides_with = "no-preserve-octal")]
+    preserve_octal: bool,
+
+    /// Convert octal literals to hexadecimal, even if the configuration file
+    /// asks to preserve them
+    #[clap(long, overrides_with = "preserve-octal")]
+    no_preserve_octal: bool,
+
+    /// How to typeset the comments in the code: like code, in a monospaced
+    /// font with their TeX shown as written, or as TeX prose. Modules can
+    /// override this with a `{tt-weave: comments=STYLE}` pragma
+    #[clap(long, arg_enum, value_name = "STYLE")]
+    comments: Option<CommentStyle>,
+
+    /// Don't report this lint. LINT is a code like `W0001`, a name like
+    /// `unreferenced-module`, or `warnings` for all lints. May be given
+    /// multiple times; later flags override earlier ones
+    #[clap(long, value_name = "LINT", multiple_occurrences = true)]
+    allow: Vec<String>,
+
+    /// Report this lint as a warning, which is the default. May be given
+    /// multiple times
+    #[clap(long, value_name = "LINT", multiple_occurrences = true)]
+    warn: Vec<String>,
+
+    /// Report this lint as an error, so that nothing is written if it's
+    /// triggered. May be given multiple times
+    #[clap(long, value_name = "LINT", multiple_occurrences = true)]
+    deny: Vec<String>,
+
+    /// Report code that wouldn't be valid ISO Pascal after tangling, which is
+    /// useful when porting a program to a real Pascal compiler. This is the
+    /// same as `--warn nonstandard-pascal`
+    #[clap(long)]
+    strict: bool,
+
+    /// Report modules whose displayed code takes more than this many lines,
+    /// if the `long-module-code` lint is enabled with `--warn` or `--deny`
+    #[clap(long, value_name = "LINES")]
+    max_code_lines: Option<usize>,
+
+    /// Report module names longer than this many characters, if the
+    /// `long-module-name` lint is enabled with `--warn` or `--deny`
+    #[clap(long, value_name = "CHARS")]
+    max_module_name_length: Option<usize>,
+
+    /// Write the program's string pool to this file, as TANGLE would
+    #[clap(long)]
+    pool_file: Option<PathBuf>,
+
+    /// Write a JSON map of the named modules, listing where each one is
+    /// defined and referenced, to this file
+    #[clap(long)]
+    module_map: Option<PathBuf>,
+
+    /// Write the procedure call graph of the program to this file, in
+    /// Graphviz's DOT language
+    #[clap(long, value_name = "FILE")]
+    emit_callgraph: Option<PathBuf>,
+
+    /// Write a JSON cross-reference database of the program to this file,
+    /// listing its sections and where each identifier, module reference, and
+    /// macro definition appears
+    #[clap(long, value_name = "FILE")]
+    xref: Option<PathBuf>,
+
+    /// Write the warnings about the input to this file, in the SARIF format
+    /// understood by code review tools
+    #[clap(long, value_name = "FILE")]
+    sarif: Option<PathBuf>,
+
+    /// Write a tags file for Vim to this file, listing where the procedures,
+    /// macros, types, named modules, and so on are defined
+    #[clap(long, value_name = "FILE")]
+    ctags: Option<PathBuf>,
+
+    /// Write a tags file for Emacs to this file, like `--ctags`
+    #[clap(long, value_name = "FILE")]
+    etags: Option<PathBuf>,
+
+    /// The dialect of the input. The default is `cweb` if the input filename
+    /// ends in `.w`, and `web2c` otherwise
+    #[clap(long, arg_enum)]
+    dialect: Option<Dialect>,
+
+    /// The encoding of the input and its change file, which are transcoded as
+    /// they're read. The default is `utf-8`, but historical WEB files are
+    /// often in `latin-1` or `cp437`. The output is always UTF-8
+    #[clap(long, arg_enum, value_name = "ENCODING")]
+    input_encoding: Option<InputEncoding>,
+
+    /// Write the output to this file rather than standard output. If this is a
+    /// directory, or ends with a slash, write `$basename.tex` into it, along
+    /// with a fragment file for each section, `$basename-$section.tex`, that
+    /// the main file `\input`s. Files whose contents haven't changed are left
+    /// alone, so that TeX build tools only reprocess the edited sections
+    #[clap(
+        short,
+        long,
+        conflicts_with_all = &["dump-ast", "dump-tokens", "dump-symbols", "check", "find-definitions", "find-uses"]
+    )]
+    output: Option<PathBuf>,
+
+    /// Make the output a complete LaTeX document that can be compiled to PDF
+    /// on its own, as with `tectonic foo.tex`, rather than as part of a
+    /// Tectonic project built from the template
+    #[clap(long)]
+    standalone: bool,
+
+    /// Structure the output like WEAVE's, using the macros of `webmac.tex`,
+    /// so that it can be used in place of WEAVE's output. The code isn't
+    /// highlighted in this case
+    #[clap(long, conflicts_with_all = &["standalone", "mini-index", "contents-file"])]
+    webmac: bool,
+
+    /// Write the table of contents to this file, next to the output, and
+    /// `\input` it from the main document rather than including it directly
+    #[clap(long, value_name = "NAME")]
+    contents_file: Option<String>,
+
+    /// Write the indices to this file, next to the output, and `\input` them
+    /// from the main document rather than including them directly
+    #[clap(long, value_name = "NAME")]
+    index_file: Option<String>,
+
+    /// Instead of weaving the input into TeX, write a standalone HTML site for
+    /// it into this directory: a page with a section for each module, a
+    /// sidebar table of contents, and the index, along with its stylesheet
+    #[clap(
+        long,
+        value_name = "DIR",
+        conflicts_with_all = &["output", "dump-ast", "dump-tokens", "dump-symbols", "check", "find-definitions", "find-uses", "project"]
+    )]
+    html_site: Option<PathBuf>,
+
+    /// Instead of weaving the input, print a JSON dump of the parsed Pascal
+    /// code
+    #[clap(long)]
+    dump_ast: bool,
+
+    /// Instead of weaving the input, print a JSON dump of the tokens of the
+    /// Pascal code
+    #[clap(long, conflicts_with = "dump-ast")]
+    dump_tokens: bool,
+
+    /// Instead of weaving the input, print a JSON dump of the symbol table:
+    /// what each identifier in the Pascal code is declared as, and where
+    #[clap(long, conflicts_with_all = &["dump-ast", "dump-tokens"])]
+    dump_symbols: bool,
+
+    /// Instead of weaving the input, just check that all of its Pascal code
+    /// can be parsed and run the lints, exiting with an error if some of the
+    /// code can't be parsed or a denied lint fires
+    #[clap(long, conflicts_with_all = &["dump-ast", "dump-tokens", "dump-symbols"])]
+    check: bool,
+
+    /// Instead of weaving the input, print the locations where the named
+    /// identifier is defined in the Pascal code
+    #[clap(long, value_name = "NAME", conflicts_with_all = &["dump-ast", "dump-tokens", "dump-symbols", "check"])]
+    find_definitions: Option<String>,
+
+    /// Instead of weaving the input, print the locations where the named
+    /// identifier is used in the Pascal code
+    #[clap(
+        long,
+        value_name = "NAME",
+        conflicts_with_all = &["dump-ast", "dump-tokens", "dump-symbols", "check", "find-definitions"]
+    )]
+    find_uses: Option<String>,
+
+    /// Process all of the WEB files listed in this project manifest, writing
+    /// the outputs to files rather than standard output
+    #[clap(long, conflicts_with_all = &["input-path", "change-file", "output", "contents-file", "index-file", "pool-file", "module-map", "emit-callgraph", "sarif", "xref", "ctags", "etags", "dump-ast", "dump-tokens", "dump-symbols", "find-definitions", "find-uses"])]
+    project: Option<PathBuf>,
+
+    /// Read default options from this configuration file rather than from
+    /// `tt-weave.toml` in the current directory, if it exists. Options given on
+    /// the command line override those in the file
+    #[clap(long)]
+    config: Option<PathBuf>,
+
+    /// Report how long each phase of weaving took, for each module and
+    /// overall, on standard error. Use this to find code that's slow to parse
+    #[clap(long)]
+    timings: bool,
+
+    /// Report measures of the code of each module, and overall, on standard
+    /// error: its number of statements, how deeply its control statements
+    /// are nested, its number of module references, and a rough cyclomatic
+    /// complexity
+    #[clap(long)]
+    metrics: bool,
+
+    /// Keep the laid-out code in this file between runs, so that rerunning
+    /// after an edit only lays out the code that changed. The file is created
+    /// if it doesn't exist
+    #[clap(long, value_name = "FILE")]
+    cache: Option<PathBuf>,
+
+    /// Print more information about what's going on; use multiple times for
+    /// even more. The `RUST_LOG` environment variable overrides this.
+    #[clap(short, long, parse(from_occurrences))]
+    verbose: usize,
+}
+
+/// Subcommands.
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Report which modules' code differs semantically between two versions of
+    /// a WEB file, ignoring changes to comments, layout, and the TeX
+    /// commentary
+    Diff(DiffArgs),
+
+    /// Rename an identifier or a named module throughout a WEB file, leaving
+    /// the TeX commentary and comments alone unless asked
+    Rename(RenameArgs),
+
+    /// Report how a change file renumbers the modules of a WEB file
+    Renumber(RenumberArgs),
+
+    /// Prettify a snippet of code that isn't a complete WEB file, printing the
+    /// markup for just the code
+    ///
+    /// The code is laid out as it would be when weaving, so the settings in
+    /// `tt-weave.toml` apply, unless they're overridden here.
+    Snippet(SnippetArgs),
+
+    /// Run Knuth's WEAVE on a WEB file and report where its module numbering,
+    /// index, and list of module names differ from tt-weave's
+    ///
+    /// TeX isn't run on WEAVE's output: the numbering, the index, and the
+    /// module names are all read from the TeX that WEAVE writes, so only WEAVE
+    /// needs to be installed.
+    Verify(VerifyArgs),
+}
+
+/// Arguments to the `diff` subcommand.
+#[derive(ClapArgs, Debug)]
+struct DiffArgs {
+    /// The old version of the WEB file
+    old_path: PathBuf,
+
+    /// The new version of the WEB file
+    new_path: PathBuf,
+
+    /// The dialect of WEB used by both versions
+    #[clap(long, arg_enum)]
+    dialect: Option<Dialect>,
+
+    /// The encoding of both versions
+    #[clap(long, arg_enum, value_name = "ENCODING")]
+    input_encoding: Option<InputEncoding>,
+}
+
+/// Arguments to the `rename` subcommand.
+#[derive(ClapArgs, Debug)]
+struct RenameArgs {
+    /// The WEB file
     input_path: PathBuf,
+
+    /// The current name
+    old_name: String,
+
+    /// The new name
+    new_name: String,
+
+    /// Rename a named module, rather than an identifier. The current name may
+    /// be abbreviated with a trailing `...`
+    #[clap(long)]
+    module: bool,
+
+    /// Also rename the identifier in the code quoted in the TeX commentary and
+    /// in comments, like `|x|`
+    #[clap(long)]
+    in_text: bool,
+
+    /// Rewrite the WEB file in place, rather than printing the result
+    #[clap(long)]
+    in_place: bool,
+
+    /// The dialect of WEB used by the file
+    #[clap(long, arg_enum)]
+    dialect: Option<Dialect>,
+
+    /// The encoding of the file, which the result is written in too
+    #[clap(long, arg_enum, value_name = "ENCODING")]
+    input_encoding: Option<InputEncoding>,
 }
 
-fn main() -> Result<()> {
-    let args = Args::parse();
+/// Arguments to the `renumber` subcommand.
+#[derive(ClapArgs, Debug)]
+struct RenumberArgs {
+    /// The WEB file
+    input_path: PathBuf,
+
+    /// The change file
+    change_file: PathBuf,
+
+    /// Also write the WEB file with the change file applied to this path, with
+    /// the explicit references to modules in the lines that the change file
+    /// leaves alone, like `\S123` or `section 123`, updated to the new numbers
+    #[clap(long, value_name = "PATH")]
+    rewrite: Option<PathBuf>,
+
+    /// The encoding of the WEB and change files, which the rewritten file is written in too
+    #[clap(long, arg_enum, value_name = "ENCODING")]
+    input_encoding: Option<InputEncoding>,
+}
+
+/// Arguments to the `verify` subcommand.
+#[derive(ClapArgs, Debug)]
+struct VerifyArgs {
+    /// The WEB file
+    input_path: PathBuf,
+
+    /// A change file to apply to the WEB file
+    change_file: Option<PathBuf>,
+
+    /// The WEAVE program to run
+    #[clap(long, value_name = "PROGRAM", default_value = "weave")]
+    weave: String,
+
+    /// The dialect of WEB used by the file
+    #[clap(long, arg_enum)]
+    dialect: Option<Dialect>,
+
+    /// The encoding of the WEB and change files, and of WEAVE's output
+    #[clap(long, arg_enum, value_name = "ENCODING")]
+    input_encoding: Option<InputEncoding>,
+}
+
+/// Arguments to the `snippet` subcommand.
+#[derive(ClapArgs, Debug)]
+struct SnippetArgs {
+    /// The code, which is parsed as the code part of a module. If it's omitted
+    /// or `-`, it's read from standard input
+    code: Option<String>,
+
+    /// Emit HTML rather than TeX
+    #[clap(long)]
+    html: bool,
+
+    /// Lay out the code to this width, in characters
+    #[clap(long)]
+    width: Option<usize>,
+
+    /// Highlight the code using this theme, among syntect's defaults
+    #[clap(long)]
+    theme: Option<String>,
+
+    /// The dialect of WEB in which the code is written
+    #[clap(long, arg_enum)]
+    dialect: Option<Dialect>,
+}
+
+/// Parse a `NAME=TEX` argument to `--identifier-tex`.
+fn parse_identifier_tex(arg: &str) -> Result<(String, String)> {
+    let (name, tex) = a_ok_or!(
+        arg.split_once('=');
+        ["expected an argument of the form `NAME=TEX`, but got `{}`", arg]
+    );
+    Ok((name.to_owned(), tex.to_owned()))
+}
 
-    let basename = a_ok_or!(
-        args.input_path.file_stem().and_then(|s| s.to_str());
-        ["unable to determine a Unicode basename from the input path `{}`", args.input_path.display()]
+/// Work out how to handle each lint from the `--allow`, `--warn`, and `--deny`
+/// flags. As in rustc, later flags override earlier ones, so we need the raw
+/// matches to know the order in which they were given.
+fn lint_levels(matches: &ArgMatches) -> Result<LintLevels> {
+    let mut flags = Vec::new();
+
+    for (id, level) in [
+        ("allow", LintLevel::Allow),
+        ("warn", LintLevel::Warn),
+        ("deny", LintLevel::Deny),
+    ] {
+        if let (Some(indices), Some(names)) = (matches.indices_of(id), matches.values_of(id)) {
+            flags.extend(indices.zip(names).map(|(i, name)| (i, level, name)));
+        }
+    }
+
+    flags.sort_by_key(|f| f.0);
+    let mut levels = LintLevels::default();
+
+    if matches.is_present("strict") {
+        levels.set(Lint::NonstandardPascal, LintLevel::Warn);
+    }
+
+    for (_, level, name) in flags {
+        levels.set_named(name, level)?;
+    }
+
+    Ok(levels)
+}
+
+/// Set up logging, with the level determined by the number of `-v` flags.
+fn init_logging(verbose: usize) {
+    let level = match verbose {
+        0 => LevelFilter::Warn,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+
+    env_logger::Builder::new()
+        .filter_level(level)
+        .format_timestamp(None)
+        .parse_default_env()
+        .init();
+}
+
+/// Determine whether an input path refers to standard input.
+fn is_stdin(input_path: &Path) -> bool {
+    input_path == Path::new("-")
+}
+
+/// Get the basename of an input path, which is used to name associated files.
+/// Standard input is treated as if it came from a file named `stdin.web`.
+fn input_basename(input_path: &Path) -> Result<&str> {
+    if is_stdin(input_path) {
+        return Ok("stdin");
+    }
+
+    Ok(a_ok_or!(
+        input_path.file_stem().and_then(|s| s.to_str());
+        ["unable to determine a Unicode basename from the input path `{}`", input_path.display()]
+    ))
+}
+
+/// Determine the dialect of an input file, if it isn't specified explicitly.
+/// CWEB files customarily have a `.w` extension.
+fn input_dialect(input_path: &Path, dialect: Option<Dialect>) -> Dialect {
+    if let Some(d) = dialect {
+        d
+    } else if input_path.extension().is_some_and(|e| e == "w") {
+        Dialect::Cweb
+    } else {
+        Dialect::default()
+    }
+}
+
+/// Read a text file, transcoding it from the given encoding. The file is
+/// described as `what` in error messages.
+fn read_text(path: &Path, encoding: InputEncoding, what: &str) -> Result<String> {
+    let bytes = atry!(
+        std::fs::read(path);
+        ["failed to read {} `{}`", what, path.display()]
     );
+    Ok(atry!(
+        encoding.decode(bytes);
+        ["failed to read {} `{}` as {} text", what, path.display(), encoding]
+    ))
+}
+
+/// Read an input file, applying its change file if it has one.
+fn read_input(file: &ManifestFile) -> Result<String> {
+    let encoding = file.encoding.unwrap_or_default();
 
     // Make life easy on ourselves: just read the input into a huge string.
-    let text = atry!(
-        std::fs::read_to_string(&args.input_path);
-        ["failed to read input path `{}` as text", args.input_path.display()]
+    let text = if is_stdin(&file.path) {
+        let mut bytes = Vec::new();
+        atry!(
+            std::io::stdin().read_to_end(&mut bytes);
+            ["failed to read standard input"]
+        );
+        atry!(
+            encoding.decode(bytes);
+            ["failed to read standard input as {} text", encoding]
+        )
+    } else {
+        read_text(&file.path, encoding, "input path")?
+    };
+
+    let change_path = match file.change_file.as_ref() {
+        Some(p) => p,
+        None => return Ok(text),
+    };
+
+    let changes = read_text(change_path, encoding, "change file")?;
+
+    Ok(atry!(
+        changefile::apply_change_file(&text, &changes);
+        ["failed to apply change file `{}`", change_path.display()]
+    ))
+}
+
+/// Check that the Pascal code of a WEB file can be parsed, and run the lints
+/// over it. The file is woven as usual, so that the lints see all of the code,
+/// but the output is thrown away. The problems are printed as when weaving,
+/// and this fails if a denied lint fires. Returns the number of fragments of
+/// code that couldn't be parsed.
+fn check_file(
+    file: &ManifestFile,
+    dialect: Dialect,
+    theme: &Theme,
+    options: &WeaveOptions,
+) -> Result<usize> {
+    let (_, doc) = weave_file(file, dialect, theme, options, Some(&mut std::io::sink()))?;
+    Ok(doc.parse_failures.len())
+}
+
+/// Report the semantic differences between two versions of a WEB file.
+fn diff_files(args: &DiffArgs) -> Result<()> {
+    let mut versions = Vec::new();
+
+    for path in [&args.old_path, &args.new_path] {
+        let file = ManifestFile {
+            path: path.clone(),
+            change_file: None,
+            pool_file: None,
+            module_map: None,
+            call_graph: None,
+            sarif: None,
+            dialect: args.dialect,
+            encoding: args.input_encoding,
+        };
+        let text = read_input(&file)?;
+        let state = tt_weave::scan(&text, input_dialect(path, args.dialect))?;
+        versions.push((text, state));
+    }
+
+    let (old_text, old_state) = &versions[0];
+    let (new_text, new_state) = &versions[1];
+
+    for change in diff::diff(old_text, old_state, new_text, new_state)? {
+        match change {
+            ModuleChange::Changed(old, new) if old == new => println!("changed: module {}", old),
+            ModuleChange::Changed(old, new) => {
+                println!(
+                    "changed: module {} (module {} in the new version)",
+                    old, new
+                )
+            }
+            ModuleChange::Removed(old) => println!("removed: module {}", old),
+            ModuleChange::Added(new) => println!("added: module {} in the new version", new),
+        }
+    }
+
+    Ok(())
+}
+
+/// Rename an identifier or a named module in a WEB file.
+fn rename_file(args: &RenameArgs) -> Result<()> {
+    let dialect = input_dialect(&args.input_path, args.dialect);
+
+    if dialect.is_cweb() {
+        bail!("renaming isn't supported for CWEB programs");
+    }
+
+    let file = ManifestFile {
+        path: args.input_path.clone(),
+        change_file: None,
+        pool_file: None,
+        module_map: None,
+        call_graph: None,
+        sarif: None,
+        dialect: args.dialect,
+        encoding: args.input_encoding,
+    };
+    let encoding = args.input_encoding.unwrap_or_default();
+    let text = read_input(&file)?;
+    let state = tt_weave::scan(&text, dialect)?;
+    let xref = tt_weave::cross_reference(&text, &state)?;
+
+    let renaming = if args.module {
+        rename::rename_module(&text, &xref, &args.old_name, &args.new_name)?
+    } else {
+        rename::rename_identifier(&text, &xref, &args.old_name, &args.new_name, args.in_text)?
+    };
+
+    if args.in_place {
+        if is_stdin(&args.input_path) {
+            bail!("standard input can't be rewritten in place");
+        }
+
+        write_output_bytes(&args.input_path, &encoding.encode(&renaming.text)?)?;
+        eprintln!(
+            "renamed `{}` to `{}` in {} place(s)",
+            args.old_name, args.new_name, renaming.count
+        );
+    } else {
+        atry!(
+            std::io::stdout().write_all(&encoding.encode(&renaming.text)?);
+            ["failed to write the result to standard output"]
+        );
+    }
+
+    Ok(())
+}
+
+/// Describe a run of modules, like `module 4` or `modules 4-7`.
+fn module_run(first: ModuleId, last: ModuleId) -> String {
+    if first == last {
+        format!("module {}", first)
+    } else {
+        format!("modules {}-{}", first, last)
+    }
+}
+
+/// Report how a change file renumbers the modules of a WEB file. Runs of
+/// modules that shift by the same amount are reported together.
+fn renumber_files(args: &RenumberArgs) -> Result<()> {
+    let encoding = args.input_encoding.unwrap_or_default();
+    let web = read_text(&args.input_path, encoding, "input path")?;
+    let changes = read_text(&args.change_file, encoding, "change file")?;
+
+    let renumbering = atry!(
+        changefile::renumbering(&web, &changes);
+        ["failed to apply change file `{}`", args.change_file.display()]
+    );
+
+    // The current run of kept modules: the first old and new numbers, and the
+    // number of modules.
+    let mut run: Option<(ModuleId, ModuleId, usize)> = None;
+
+    let flush = |run: &mut Option<(ModuleId, ModuleId, usize)>| {
+        if let Some((old, new, n)) = run.take() {
+            if old == new {
+                println!("unchanged: {}", module_run(old, old + n - 1));
+            } else {
+                println!(
+                    "renumbered: {} as {}",
+                    module_run(old, old + n - 1),
+                    module_run(new, new + n - 1)
+                );
+            }
+        }
+    };
+
+    for r in renumbering {
+        match r {
+            Renumbered::Kept(old, new) => match run.as_mut() {
+                Some((o, n, len)) if *o + *len == old && *n + *len == new => *len += 1,
+                _ => {
+                    flush(&mut run);
+                    run = Some((old, new, 1));
+                }
+            },
+
+            Renumbered::Removed(old) => {
+                flush(&mut run);
+                println!("removed: module {}", old);
+            }
+
+            Renumbered::Added(new) => {
+                flush(&mut run);
+                println!("added: module {} in the changed version", new);
+            }
+        }
+    }
+
+    flush(&mut run);
+
+    if let Some(path) = args.rewrite.as_ref() {
+        let text = changefile::apply_change_file_renumbered(&web, &changes)?;
+        write_output_bytes(path, &encoding.encode(&text)?)?;
+    }
+
+    Ok(())
+}
+
+/// Compare WEAVE's module numbering and cross-references with ours, printing
+/// the differences.
+fn verify_file(args: &VerifyArgs) -> Result<()> {
+    let dialect = input_dialect(&args.input_path, args.dialect);
+
+    if dialect.is_cweb() {
+        bail!("WEAVE can't process CWEB programs");
+    }
+
+    let file = ManifestFile {
+        path: args.input_path.clone(),
+        change_file: args.change_file.clone(),
+        pool_file: None,
+        module_map: None,
+        call_graph: None,
+        sarif: None,
+        dialect: args.dialect,
+        encoding: args.input_encoding,
+    };
+    let text = read_input(&file)?;
+    let state = tt_weave::scan(&text, dialect)?;
+    let ours = Listing::from_state(&state);
+
+    let theirs = verify::run_weave(
+        &args.weave,
+        &args.input_path,
+        args.change_file.as_deref(),
+        args.input_encoding.unwrap_or_default(),
+    )?;
+
+    let divergences = verify::compare(&theirs, &ours);
+
+    for d in &divergences {
+        println!("{}", d);
+    }
+
+    if !divergences.is_empty() {
+        bail!("found {} differences from WEAVE", divergences.len());
+    }
+
+    println!("no differences from WEAVE in {} modules", ours.n_modules);
+    Ok(())
+}
+
+/// Prettify a snippet of code and print the markup.
+fn prettify_snippet(args: &SnippetArgs, theme: &Theme, weave: WeaveOptions) -> Result<()> {
+    let code = match args.code.as_deref() {
+        Some(code) if code != "-" => code.to_owned(),
+
+        _ => {
+            let mut code = String::new();
+            atry!(
+                std::io::stdin().read_to_string(&mut code);
+                ["failed to read standard input as text"]
+            );
+            code
+        }
+    };
+
+    let options = FragmentOptions {
+        dialect: args.dialect.unwrap_or_default(),
+        theme: Some(theme.clone()),
+        html: args.html,
+        weave,
+    };
+
+    let fragment = tt_weave::prettify_fragment(&code, &options)?;
+
+    for d in &fragment.diagnostics {
+        eprintln!("warning: {}", d.render(&code, None));
+    }
+
+    print!("{}", fragment.output);
+    Ok(())
+}
+
+/// Combine a boolean option given on the command line as `--$name` or
+/// `--no-$name`, if either was given.
+fn flag(yes: bool, no: bool) -> Option<bool> {
+    match (yes, no) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    }
+}
+
+/// Assemble the weaving options. The command line takes precedence over the
+/// project manifest, if there is one, which takes precedence over the
+/// configuration file.
+fn weave_options(
+    args: &Args,
+    config: &Config,
+    manifest: Option<&project::Manifest>,
+    lints: LintLevels,
+    cache: Option<CodeCache>,
+) -> WeaveOptions {
+    let choose = |arg: Option<bool>,
+                  in_manifest: fn(&project::Manifest) -> Option<bool>,
+                  in_config: Option<bool>| {
+        arg.or_else(|| manifest.and_then(in_manifest))
+            .or(in_config)
+            .unwrap_or_default()
+    };
+
+    let mut identifier_tex = config.identifier_tex.clone();
+
+    if let Some(m) = manifest {
+        identifier_tex.extend(m.identifier_tex.clone());
+    }
+
+    identifier_tex.extend(args.identifier_tex.iter().cloned());
+
+    let mut style_limits = StyleLimits::default();
+
+    if let Some(n) = args.max_code_lines.or(config.max_code_lines) {
+        style_limits.max_code_lines = n;
+    }
+
+    if let Some(n) = args
+        .max_module_name_length
+        .or(config.max_module_name_length)
+    {
+        style_limits.max_module_name_length = n;
+    }
+
+    WeaveOptions {
+        mini_index: choose(
+            flag(args.mini_index, args.no_mini_index),
+            |m| m.mini_index,
+            config.mini_index,
+        ),
+        sections: args.sections.clone(),
+        exclude_modules: args.exclude_module.clone(),
+        exclude_parts: args.exclude_part.clone(),
+        contents_file: args.contents_file.clone(),
+        index_file: args.index_file.clone(),
+        width: args.width.or(config.width),
+        inline_width: args.inline_width.or(config.inline_width),
+        inline_max_lines: args.inline_max_lines.or(config.inline_max_lines),
+        max_depth: args.max_depth.or(config.max_depth),
+        identifier_tex,
+        lints,
+        style_limits,
+        timings: args.timings,
+        metrics: args.metrics,
+        cache,
+        xref: args.xref.is_some() || args.ctags.is_some() || args.etags.is_some(),
+        function_uses: choose(
+            flag(args.function_uses, args.no_function_uses),
+            |m| m.function_uses,
+            config.function_uses,
+        ),
+        identifier_links: choose(
+            flag(args.identifier_links, args.no_identifier_links),
+            |m| m.identifier_links,
+            config.identifier_links,
+        ),
+        standalone: args.standalone,
+        webmac: args.webmac,
+        style: CodeStyle {
+            preserve_operators: choose(
+                flag(args.preserve_operators, args.no_preserve_operators),
+                |m| m.preserve_operators,
+                config.preserve_operators,
+            ),
+            semicolons: args
+                .semicolons
+                .or_else(|| manifest.and_then(|m| m.semicolons))
+                .or(config.semicolons)
+                .unwrap_or_default(),
+            group_digits: choose(
+                flag(args.group_digits, args.no_group_digits),
+                |m| m.group_digits,
+                config.group_digits,
+            ),
+            uppercase_hex: choose(
+                flag(args.uppercase_hex, args.no_uppercase_hex),
+                |m| m.uppercase_hex,
+                config.uppercase_hex,
+            ),
+            preserve_octal: choose(
+                flag(args.preserve_octal, args.no_preserve_octal),
+                |m| m.preserve_octal,
+                config.preserve_octal,
+            ),
+            comments: args
+                .comments
+                .or_else(|| manifest.and_then(|m| m.comments))
+                .or(config.comments)
+                .unwrap_or_default(),
+            ..CodeStyle::default()
+        },
+    }
+}
+
+/// Weave a single WEB file, returning the generated document along with the
+/// source text that it was generated from, after applying the change file. If
+/// `dest` is set, the TeX is written to it as it's woven, and the returned
+/// document doesn't hold it.
+fn weave_file(
+    file: &ManifestFile,
+    dialect: Dialect,
+    theme: &Theme,
+    options: &WeaveOptions,
+    dest: Option<&mut dyn Write>,
+) -> Result<(String, WovenDocument)> {
+    let basename = input_basename(&file.path)?;
+    let text = read_input(file)?;
+    let scan_start = options.timings.then(Instant::now);
+    let state = tt_weave::scan(&text, dialect)?;
+    let scan_time = scan_start.map(|t| t.elapsed());
+
+    if let Some(pool_path) = file.pool_file.as_ref() {
+        let f = atry!(
+            std::fs::File::create(pool_path);
+            ["failed to create string pool file `{}`", pool_path.display()]
+        );
+        atry!(
+            state.write_pool_file(std::io::BufWriter::new(f));
+            ["failed to write string pool file `{}`", pool_path.display()]
+        );
+    }
+
+    if let Some(map_path) = file.module_map.as_ref() {
+        let f = atry!(
+            std::fs::File::create(map_path);
+            ["failed to create module map file `{}`", map_path.display()]
+        );
+        atry!(
+            state.write_module_map(std::io::BufWriter::new(f));
+            ["failed to write module map file `{}`", map_path.display()]
+        );
+    }
+
+    let weave_start = options.timings.then(Instant::now);
+    let doc = match dest {
+        Some(dest) => tt_weave::weave_to_writer(basename, &text, &state, theme, options, dest)?,
+        None => tt_weave::weave_document(basename, &text, &state, theme, options)?,
+    };
+    let path = file.path.display().to_string();
+
+    if let (Some(timings), Some(scan_time), Some(weave_start)) =
+        (doc.timings.as_ref(), scan_time, weave_start)
+    {
+        eprint!(
+            "timings for `{}`, in milliseconds:\n{}",
+            path,
+            timings.report()
+        );
+        eprintln!(
+            "first pass: {:.3}; second pass: {:.3} elapsed",
+            scan_time.as_secs_f64() * 1000.,
+            weave_start.elapsed().as_secs_f64() * 1000.
+        );
+    }
+
+    if let Some(metrics) = doc.metrics.as_ref() {
+        eprint!("metrics for `{}`:\n{}", path, metrics.report());
+    }
+
+    for f in &doc.parse_failures {
+        eprintln!("error: {}", f.render(&text, Some(&path)));
+    }
+
+    let mut n_denied = 0;
+
+    for w in &doc.warnings {
+        eprintln!("{}", w.render(&text, Some(&path)));
+
+        if w.level == LintLevel::Deny {
+            n_denied += 1;
+        }
+    }
+
+    if let Some(graph_path) = file.call_graph.as_ref() {
+        let f = atry!(
+            std::fs::File::create(graph_path);
+            ["failed to create call graph file `{}`", graph_path.display()]
+        );
+        atry!(
+            doc.call_graph.write_dot(std::io::BufWriter::new(f));
+            ["failed to write call graph file `{}`", graph_path.display()]
+        );
+    }
+
+    // The warnings are written out even if some of them were denied, so that
+    // the tools consuming them can show what went wrong.
+    if let Some(sarif_path) = file.sarif.as_ref() {
+        let f = atry!(
+            std::fs::File::create(sarif_path);
+            ["failed to create SARIF file `{}`", sarif_path.display()]
+        );
+        atry!(
+            serde_json::to_writer_pretty(
+                std::io::BufWriter::new(f),
+                &diagnostics::sarif(&doc.warnings, &text, &path)
+            );
+            ["failed to write SARIF file `{}`", sarif_path.display()]
+        );
+    }
+
+    if n_denied > 0 {
+        bail!(
+            "aborting due to {} denied warning(s) in `{}`",
+            n_denied,
+            path
+        );
+    }
+
+    Ok((text, doc))
+}
+
+/// Weave a single WEB file into an output file, as with [`weave_file`]. If
+/// weaving fails, the partly written file is removed, so that it can't be
+/// mistaken for good output.
+fn weave_file_to(
+    path: &Path,
+    file: &ManifestFile,
+    dialect: Dialect,
+    theme: &Theme,
+    options: &WeaveOptions,
+) -> Result<(String, WovenDocument)> {
+    let f = atry!(
+        std::fs::File::create(path);
+        ["failed to create output file `{}`", path.display()]
+    );
+    let mut dest = std::io::BufWriter::new(f);
+    let result = weave_file(file, dialect, theme, options, Some(&mut dest));
+    drop(dest);
+
+    if result.is_err() {
+        let _ = std::fs::remove_file(path);
+    }
+
+    result
+}
+
+/// Write a text file.
+fn write_output(path: &Path, text: &str) -> Result<()> {
+    write_output_bytes(path, text.as_bytes())
+}
+
+/// Write a file whose text has already been encoded.
+fn write_output_bytes(path: &Path, bytes: &[u8]) -> Result<()> {
+    atry!(
+        std::fs::write(path, bytes);
+        ["failed to write output file `{}`", path.display()]
+    );
+    Ok(())
+}
+
+/// Write a text file, unless it already has the given contents. This leaves
+/// the file's modification time alone, so that build tools don't think that it
+/// needs to be processed again.
+fn write_output_if_changed(path: &Path, text: &str) -> Result<()> {
+    match std::fs::read(path) {
+        Ok(old) if old == text.as_bytes() => Ok(()),
+        _ => write_output(path, text),
+    }
+}
+
+/// Write a woven document into a directory as a main file, `$basename.tex`,
+/// that `\input`s a fragment file for each module, `$basename-$module.tex`.
+///
+/// Only the files whose contents have changed are written, so that editing one
+/// module only touches its own fragment. If `prune` is set, fragment files left
+/// over from earlier runs, for modules that no longer exist, are removed.
+fn write_fragments(dir: &Path, basename: &str, doc: &WovenDocument, prune: bool) -> Result<()> {
+    atry!(
+        std::fs::create_dir_all(dir);
+        ["failed to create output directory `{}`", dir.display()]
+    );
+
+    let mut main = doc.preamble().to_owned();
+    let mut last = 0;
+
+    for (module, tex) in doc.modules() {
+        let name = format!("{}-{}.tex", basename, module);
+        write_output_if_changed(&dir.join(&name), tex)?;
+        main.push_str(&format!("\n\\input{{{}}}\n", name));
+        last = module;
+    }
+
+    main.push_str(doc.postamble());
+    write_output_if_changed(&dir.join(format!("{}.tex", basename)), &main)?;
+
+    if !prune {
+        return Ok(());
+    }
+
+    // Modules are numbered consecutively, so the stale fragments are the ones
+    // past the last module.
+    for module in last + 1.. {
+        let path = dir.join(format!("{}-{}.tex", basename, module));
+
+        if !path.exists() {
+            break;
+        }
+
+        atry!(
+            std::fs::remove_file(&path);
+            ["failed to remove stale fragment file `{}`", path.display()]
+        );
+    }
+
+    Ok(())
+}
+
+/// Write the files of a generated HTML site into a directory.
+fn write_site(dir: &Path, site: &HtmlSite) -> Result<()> {
+    atry!(
+        std::fs::create_dir_all(dir);
+        ["failed to create output directory `{}`", dir.display()]
+    );
+
+    for file in &site.files {
+        write_output(&dir.join(&file.path), &file.contents)?;
+    }
+
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    init_logging(args.verbose);
+
+    match args.command.as_ref() {
+        Some(Command::Diff(diff_args)) => return diff_files(diff_args),
+        Some(Command::Rename(rename_args)) => return rename_file(rename_args),
+        Some(Command::Renumber(renumber_args)) => return renumber_files(renumber_args),
+        Some(Command::Verify(verify_args)) => return verify_file(verify_args),
+
+        // Snippets are laid out just like woven code, so they take the same
+        // route through the configuration file and the theme, with the
+        // subcommand's options standing in for the main command's.
+        Some(Command::Snippet(snippet_args)) => {
+            args.width = snippet_args.width;
+            args.theme = snippet_args.theme.clone();
+        }
+
+        None => {}
+    }
+
+    let mut manifest = match args.project.as_ref() {
+        Some(p) => Some(project::Manifest::load(p)?),
+        None => None,
+    };
+
+    // Files in the manifest without their own encoding use the one given on
+    // the command line.
+    if let Some(m) = manifest.as_mut() {
+        for file in &mut m.files {
+            file.encoding = file.encoding.or(args.input_encoding);
+        }
+    }
+
+    let files = match manifest.as_ref() {
+        Some(m) => &m.files[..],
+        None => &[][..],
+    };
+
+    // Clap ensures that we have an input path if there's no manifest.
+    let single_file = args.input_path.as_ref().map(|p| ManifestFile {
+        path: p.clone(),
+        change_file: args.change_file.clone(),
+        pool_file: args.pool_file.clone(),
+        module_map: args.module_map.clone(),
+        call_graph: args.emit_callgraph.clone(),
+        sarif: args.sarif.clone(),
+        dialect: args.dialect,
+        encoding: args.input_encoding,
+    });
+
+    if let Some(file) = single_file.as_ref() {
+        let dialect = input_dialect(&file.path, args.dialect);
+
+        if args.dump_ast || args.dump_tokens || args.dump_symbols {
+            let text = read_input(file)?;
+            let state = tt_weave::scan(&text, dialect)?;
+
+            let dump = if args.dump_ast {
+                tt_weave::dump_ast(&text, &state, args.sections.as_ref())?
+            } else if args.dump_tokens {
+                tt_weave::dump_tokens(&text, &state, args.sections.as_ref())?
+            } else {
+                let table = tt_weave::symbol_table(&text, &state, args.sections.as_ref())?;
+                serde_json::to_string_pretty(&table)?
+            };
+
+            println!("{}", dump);
+            return Ok(());
+        }
+
+        if args.find_definitions.is_some() || args.find_uses.is_some() {
+            let text = read_input(file)?;
+            let state = tt_weave::scan(&text, dialect)?;
+            let sections = args.sections.as_ref();
+
+            let found = match (args.find_definitions.as_ref(), args.find_uses.as_ref()) {
+                (Some(name), _) => tt_weave::find_definitions(&text, &state, name, sections)?,
+                (None, Some(name)) => tt_weave::find_uses(&text, &state, name, sections)?,
+                (None, None) => unreachable!(),
+            };
+
+            for loc in &found {
+                println!(
+                    "{}:{}:{}: module {}",
+                    file.path.display(),
+                    loc.line,
+                    loc.column,
+                    loc.module
+                );
+            }
+
+            return Ok(());
+        }
+    }
+
+    let config = match args.config.as_ref() {
+        Some(p) => Config::load(p)?,
+        None => Config::find(".")?.unwrap_or_default(),
+    };
+
+    // The theme is relatively expensive to load, so we load it once and share
+    // it between all of the files we process.
+    let theme_name = args
+        .theme
+        .as_deref()
+        .or(config.theme.as_deref())
+        .unwrap_or(tt_weave::DEFAULT_THEME);
+    let theme = &tt_weave::themes::load(theme_name, !args.no_default_themes)?;
+    let lints = lint_levels(&matches)?;
+
+    if let Some(Command::Snippet(snippet_args)) = args.command.as_ref() {
+        let options = weave_options(&args, &config, None, lints, None);
+        return prettify_snippet(snippet_args, theme, options);
+    }
+
+    if args.check {
+        let options = weave_options(&args, &config, manifest.as_ref(), lints, None);
+        let mut n_failed = 0;
+
+        for file in files.iter().chain(single_file.as_ref()) {
+            let dialect = input_dialect(&file.path, file.dialect.or(args.dialect));
+            n_failed += check_file(file, dialect, theme, &options)?;
+        }
+
+        if n_failed > 0 {
+            bail!("{} problem(s) were found while parsing the code", n_failed);
+        }
+
+        return Ok(());
+    }
+
+    if let (Some(dir), Some(file)) = (args.html_site.as_ref(), single_file.as_ref()) {
+        let text = read_input(file)?;
+        let state = tt_weave::scan(&text, input_dialect(&file.path, args.dialect))?;
+        let dark_theme = args
+            .dark_theme
+            .as_deref()
+            .map(|name| tt_weave::themes::load(name, !args.no_default_themes))
+            .transpose()?;
+        let site = tt_weave::weave_html_site(
+            input_basename(&file.path)?,
+            &text,
+            &state,
+            theme,
+            dark_theme.as_ref(),
+        )?;
+        return write_site(dir, &site);
+    }
+
+    let cache = args.cache.as_ref().map(CodeCache::load).transpose()?;
+
+    let manifest = match manifest {
+        Some(m) => m,
+        None => {
+            let file = single_file.unwrap();
+            let dialect = input_dialect(&file.path, args.dialect);
+            let options = weave_options(&args, &config, None, lints, cache);
+
+            // The TeX is written out as it's woven, unless it's to be split up
+            // into fragments.
+            let fragments_dir = args
+                .output
+                .as_ref()
+                .filter(|p| p.is_dir() || p.as_os_str().to_string_lossy().ends_with('/'));
+
+            let (text, doc) = match (args.output.as_ref(), fragments_dir) {
+                (None, _) => {
+                    let stdout = std::io::stdout();
+                    let mut dest = std::io::BufWriter::new(stdout.lock());
+                    weave_file(&file, dialect, theme, &options, Some(&mut dest))?
+                }
+
+                (Some(_), Some(_)) => weave_file(&file, dialect, theme, &options, None)?,
+                (Some(p), None) => weave_file_to(p, &file, dialect, theme, &options)?,
+            };
+
+            if let (Some(path), Some(cache)) = (args.cache.as_ref(), doc.cache.as_ref()) {
+                cache.save(path)?;
+            }
+
+            if let (Some(path), Some(xref)) = (args.xref.as_ref(), doc.xref.as_ref()) {
+                let f = atry!(
+                    std::fs::File::create(path);
+                    ["failed to create cross-reference database `{}`", path.display()]
+                );
+                atry!(
+                    serde_json::to_writer(std::io::BufWriter::new(f), xref);
+                    ["failed to write cross-reference database `{}`", path.display()]
+                );
+            }
+
+            if let (Some(path), Some(xref)) = (args.ctags.as_ref(), doc.xref.as_ref()) {
+                let f = atry!(
+                    std::fs::File::create(path);
+                    ["failed to create tags file `{}`", path.display()]
+                );
+                atry!(
+                    xref.write_ctags(&file.path.display().to_string(), std::io::BufWriter::new(f));
+                    ["failed to write tags file `{}`", path.display()]
+                );
+            }
+
+            if let (Some(path), Some(xref)) = (args.etags.as_ref(), doc.xref.as_ref()) {
+                let f = atry!(
+                    std::fs::File::create(path);
+                    ["failed to create tags file `{}`", path.display()]
+                );
+                atry!(
+                    xref.write_etags(&text, &file.path.display().to_string(), std::io::BufWriter::new(f));
+                    ["failed to write tags file `{}`", path.display()]
+                );
+            }
+
+            let aux_dir = match (args.output.as_ref(), fragments_dir) {
+                (None, _) => PathBuf::from("."),
+
+                (Some(_), Some(dir)) => {
+                    let prune = options.sections.is_none();
+                    write_fragments(dir, input_basename(&file.path)?, &doc, prune)?;
+                    dir.clone()
+                }
+
+                (Some(p), None) => p.parent().map(Path::to_owned).unwrap_or_default(),
+            };
+
+            // The contents and the indices go next to the main document, so
+            // that TeX can find them.
+            if let (Some(name), Some(text)) =
+                (options.contents_file.as_ref(), doc.contents.as_ref())
+            {
+                write_output(&aux_dir.join(name), text)?;
+            }
+
+            if let (Some(name), Some(text)) = (options.index_file.as_ref(), doc.index.as_ref()) {
+                write_output(&aux_dir.join(name), text)?;
+            }
+
+            return Ok(());
+        }
+    };
+
+    let output_dir = manifest.output_dir();
+    let options = weave_options(&args, &config, Some(&manifest), lints, cache);
+
+    atry!(
+        std::fs::create_dir_all(output_dir);
+        ["failed to create output directory `{}`", output_dir.display()]
     );
 
-    let input = parse_base::Span::new(&text);
-    let state = pass1::execute(input)?;
-    //state.dump_pass1();
-    pass2::execute(basename, &state, input)?;
+    // All of the files share the cache.
+    let mut new_cache = CodeCache::new();
+
+    for file in &manifest.files {
+        let dialect = input_dialect(&file.path, file.dialect.or(args.dialect));
+        let mut tex_path = output_dir.join(input_basename(&file.path)?);
+        tex_path.set_extension("tex");
+        let (_, doc) = weave_file_to(&tex_path, file, dialect, theme, &options)?;
+
+        if let Some(cache) = doc.cache {
+            new_cache.merge(cache);
+        }
+    }
 
-    state.emit_major_module_index();
-    state.emit_named_module_index();
-    state.emit_symbol_index();
+    if let Some(path) = args.cache.as_ref() {
+        new_cache.save(path)?;
+    }
 
     Ok(())
 }
@@ -0,0 +1,209 @@
+//! Measuring the code of each module.
+//!
+//! If [`crate::WeaveOptions::metrics`] is set, the second pass measures the
+//! code of each module as it's woven, for studies of how literate programs are
+//! written and to find modules that would be worth splitting up. The code of a
+//! module includes its macro definitions as well as its Pascal code, but not
+//! the code quoted in its TeX commentary. For each module we count:
+//!
+//! - the statements, not counting `begin`/`end` blocks, labels, comments, and
+//!   preprocessor directives, which don't do anything themselves;
+//! - the depth to which control statements (`if`, `case`, and the loops) are
+//!   nested within one another;
+//! - the references to other modules;
+//! - a rough cyclomatic complexity: one, plus one for each `if` statement and
+//!   loop, each item of a `case` statement after the first, and each `and` and
+//!   `or` operator.
+//!
+//! A reference to another module counts as a single statement, whatever that
+//! module contains, so the numbers describe each module as it's read, rather
+//! than the code that it expands into. Code that couldn't be parsed is
+//! measured as far as it could be.
+
+use std::{cmp::max, collections::BTreeMap, fmt::Write};
+
+use crate::{
+    reserved::PascalReservedWord,
+    state::ModuleId,
+    weblang::{
+        module_reference::WebModuleReference, visit::Visitor, Spanned, SpecialFreeCase,
+        WebBinaryExpr, WebCase, WebCode, WebFor, WebIf, WebLoop, WebRepeat, WebStatement, WebWhile,
+    },
+};
+
+/// The measurements of the code of a module.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ModuleMetrics {
+    /// The number of statements.
+    pub statements: usize,
+
+    /// The greatest depth to which control statements are nested.
+    pub depth: usize,
+
+    /// The number of references to other modules.
+    pub module_references: usize,
+
+    /// The rough cyclomatic complexity of the code.
+    pub complexity: usize,
+}
+
+impl Default for ModuleMetrics {
+    fn default() -> Self {
+        ModuleMetrics {
+            statements: 0,
+            depth: 0,
+            module_references: 0,
+            complexity: 1,
+        }
+    }
+}
+
+/// Measure a fragment of code.
+struct Collector<'m> {
+    metrics: &'m mut ModuleMetrics,
+
+    /// The number of control statements around the one being visited.
+    depth: usize,
+}
+
+impl<'m> Collector<'m> {
+    /// Note a decision point of the code.
+    fn decision(&mut self) {
+        self.metrics.complexity += 1;
+    }
+
+    /// Visit the children of a control statement, one level deeper.
+    fn nested(&mut self, walk: impl FnOnce(&mut Self)) {
+        self.depth += 1;
+        self.metrics.depth = max(self.metrics.depth, self.depth);
+        walk(self);
+        self.depth -= 1;
+    }
+}
+
+impl<'a, 'm> Visitor<'a> for Collector<'m> {
+    fn visit_statement(&mut self, stmt: &Spanned<WebStatement<'a>>) {
+        match stmt.node {
+            WebStatement::Block(_)
+            | WebStatement::Label(_)
+            | WebStatement::Comment(_)
+            | WebStatement::PreprocessorDirective(_) => {}
+            _ => self.metrics.statements += 1,
+        }
+
+        stmt.walk(self);
+    }
+
+    fn visit_if(&mut self, if_: &WebIf<'a>) {
+        self.decision();
+        self.nested(|c| if_.walk(c));
+    }
+
+    fn visit_while(&mut self, while_: &WebWhile<'a>) {
+        self.decision();
+        self.nested(|c| while_.walk(c));
+    }
+
+    fn visit_for(&mut self, for_: &WebFor<'a>) {
+        self.decision();
+        self.nested(|c| for_.walk(c));
+    }
+
+    fn visit_repeat(&mut self, repeat: &WebRepeat<'a>) {
+        self.decision();
+        self.nested(|c| repeat.walk(c));
+    }
+
+    fn visit_loop(&mut self, loop_: &WebLoop<'a>) {
+        self.decision();
+        self.nested(|c| loop_.walk(c));
+    }
+
+    fn visit_case(&mut self, case: &WebCase<'a>) {
+        self.metrics.complexity += case.items().len().saturating_sub(1);
+        self.nested(|c| case.walk(c));
+    }
+
+    fn visit_free_case(&mut self, case: &SpecialFreeCase<'a>) {
+        // This is an item of a `case` statement whose other items are
+        // elsewhere.
+        self.decision();
+        case.walk(self);
+    }
+
+    fn visit_binary_expr(&mut self, expr: &WebBinaryExpr<'a>) {
+        if expr.op().is_reserved_word(PascalReservedWord::And)
+            || expr.op().is_reserved_word(PascalReservedWord::Or)
+        {
+            self.decision();
+        }
+
+        expr.walk(self);
+    }
+
+    fn visit_module_reference(&mut self, _mref: &WebModuleReference<'a>) {
+        self.metrics.module_references += 1;
+    }
+}
+
+/// The measurements of the code of each module.
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    /// The measurements of each module that has code.
+    pub modules: BTreeMap<ModuleId, ModuleMetrics>,
+}
+
+impl Metrics {
+    /// Measure a fragment of the code of a module.
+    pub(crate) fn add(&mut self, module: ModuleId, code: &WebCode) {
+        let mut collector = Collector {
+            metrics: self.modules.entry(module).or_default(),
+            depth: 0,
+        };
+
+        collector.visit_code(code);
+    }
+
+    /// Get the measurements of all of the modules together: the totals of the
+    /// counts and complexities, and the greatest depth.
+    pub fn total(&self) -> ModuleMetrics {
+        let mut total = ModuleMetrics {
+            complexity: 0,
+            ..ModuleMetrics::default()
+        };
+
+        for m in self.modules.values() {
+            total.statements += m.statements;
+            total.depth = max(total.depth, m.depth);
+            total.module_references += m.module_references;
+            total.complexity += m.complexity;
+        }
+
+        total
+    }
+
+    /// Render a table of the measurements of each module, followed by those
+    /// of all of them together.
+    pub fn report(&self) -> String {
+        let mut text = format!(
+            "{:>8} {:>10} {:>10} {:>10} {:>10}\n",
+            "module", "statements", "depth", "references", "complexity"
+        );
+
+        let mut row = |label: &str, m: &ModuleMetrics| {
+            writeln!(
+                text,
+                "{:>8} {:>10} {:>10} {:>10} {:>10}",
+                label, m.statements, m.depth, m.module_references, m.complexity
+            )
+            .expect("writing to a String cannot fail");
+        };
+
+        for (module, m) in &self.modules {
+            row(&module.to_string(), m);
+        }
+
+        row("total", &self.total());
+        text
+    }
+}
@@ -5,9 +5,14 @@ use nom::{
     Err, IResult,
 };
 use nom_locate::LocatedSpan;
+#[cfg(feature = "serde")]
+use serde::{ser::SerializeStruct, Serialize, Serializer};
 use std::{borrow::Cow, fmt};
 
-pub type Span<'a> = LocatedSpan<&'a str>;
+use crate::dialect::Dialect;
+
+/// The input span type. The "extra" data is the dialect of the input.
+pub type Span<'a> = LocatedSpan<&'a str, Dialect>;
 pub type ParseError<'a> = (Span<'a>, ErrorKind);
 pub type ParseResult<'a, T> = IResult<Span<'a>, T, ParseError<'a>>;
 
@@ -15,6 +20,12 @@ pub fn new_parse_error<'a, T>(s: Span<'a>, k: ErrorKind) -> ParseResult<'a, T> {
     Err(Err::Error(ParseError::from_error_kind(s, k)))
 }
 
+/// Serialize a bare span as its text, for use with `#[serde(serialize_with)]`.
+#[cfg(feature = "serde")]
+pub fn serialize_span_text<S: Serializer>(span: &Span, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(span.fragment())
+}
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 #[allow(dead_code)]
 pub struct SpanValue<'a, T> {
@@ -23,6 +34,19 @@ pub struct SpanValue<'a, T> {
     pub value: T,
 }
 
+/// Values are serialized along with the line and column at which they start,
+/// which is generally all that anyone wants to know about the span.
+#[cfg(feature = "serde")]
+impl<'a, T: Serialize> Serialize for SpanValue<'a, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("SpanValue", 3)?;
+        state.serialize_field("value", &self.value)?;
+        state.serialize_field("line", &self.start.location_line())?;
+        state.serialize_field("column", &self.start.get_utf8_column())?;
+        state.end()
+    }
+}
+
 impl<'a, T: fmt::Display> fmt::Display for SpanValue<'a, T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         self.value.fmt(f)
@@ -35,4 +59,8 @@ impl<'a> StringSpan<'a> {
     pub fn len(&self) -> usize {
         self.value.as_ref().len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.value.as_ref().is_empty()
+    }
 }
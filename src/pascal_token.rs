@@ -3,29 +3,34 @@
 use nom::{
     branch::alt,
     bytes::complete::{tag, take_while},
-    character::complete::{alpha1, alphanumeric1, char, one_of},
-    combinator::{map_res, recognize},
+    character::complete::{char, one_of, satisfy},
+    combinator::{map_res, opt, recognize},
     error::ErrorKind,
-    multi::{many0_count, many1},
+    multi::many1,
     sequence::{pair, tuple},
     InputTakeAtPosition,
 };
 use nom_locate::position;
+#[cfg(feature = "serde")]
+use serde::Serialize;
 use std::{borrow::Cow, collections::HashMap, convert::TryFrom, fmt};
 
+#[cfg(feature = "serde")]
+use crate::parse_base::serialize_span_text;
 use crate::{
     control::ControlKind,
     index::IndexEntryKind,
     parse_base::{new_parse_error, ParseError, ParseResult, Span, SpanValue, StringSpan},
     prettify::{
         Prettifier, RenderInline, DECIMAL_LITERAL_SCOPE, FLOAT_LITERAL_SCOPE, HEX_LITERAL_SCOPE,
-        STRING_LITERAL_SCOPE,
+        OCTAL_LITERAL_SCOPE, STRING_LITERAL_SCOPE,
     },
     reserved::PascalReservedWord,
     token::{expect_token, next_token, take_until_terminator, Token},
 };
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub enum DelimiterKind {
     Paren,
 
@@ -37,6 +42,7 @@ pub enum DelimiterKind {
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub enum IntLiteralKind {
     Decimal,
     Octal,
@@ -44,12 +50,14 @@ pub enum IntLiteralKind {
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub enum StringLiteralKind {
     SingleQuote,
     DoubleQuote,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub enum PascalToken<'a> {
     /// The `@t` control code: TeX text for the woven output.
     TexString(StringSpan<'a>),
@@ -120,7 +128,7 @@ pub enum PascalToken<'a> {
 
     /// Needed to parse WEB macros. We track a span so that we can pretend it's
     /// an identifier.
-    Hash(Span<'a>),
+    Hash(#[cfg_attr(feature = "serde", serde(serialize_with = "serialize_span_text"))] Span<'a>),
 
     StringPoolChecksum,
 
@@ -134,7 +142,9 @@ pub enum PascalToken<'a> {
 
     /// We store the value in text form as a span so that we can preserve
     /// eq-ness for this and all deriving types.
-    FloatLiteral(Span<'a>),
+    FloatLiteral(
+        #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_span_text"))] Span<'a>,
+    ),
 
     IndexEntry(IndexEntryKind, StringSpan<'a>),
 
@@ -273,13 +283,22 @@ fn match_pascal_control_code_token(span: Span) -> ParseResult<PascalToken> {
     Ok((span, ptok))
 }
 
+/// Match the text of an identifier or reserved word: a letter followed by
+/// letters, digits, and underscores. Letters outside of ASCII are allowed,
+/// since localized programs use them.
+fn identifier_text(span: Span) -> ParseResult<Span> {
+    recognize(pair(
+        satisfy(|c| c.is_alphabetic()),
+        take_while(|c: char| c.is_alphanumeric() || c == '_'),
+    ))(span)
+}
+
 fn match_reserved_word_token<'a>(
     overrides: Option<&FormatOverrides>,
 ) -> impl Fn(Span<'a>) -> ParseResult<'a, PascalToken<'a>> + '_ {
     move |span: Span<'a>| {
         let (span, start) = position(span)?;
-        let (span, text) =
-            recognize(pair(alpha1, many0_count(alt((alphanumeric1, tag("_"))))))(span)?;
+        let (span, text) = identifier_text(span)?;
         let (span, end) = position(span)?;
 
         match PascalReservedWord::try_from(&text[..]) {
@@ -316,8 +335,7 @@ fn match_identifier_token<'a>(
     move |span: Span<'a>| {
         // We can ignore control codes since the alphanumeric scanner won't match them.
         let (span, start) = position(span)?;
-        let (span, text) =
-            recognize(pair(alpha1, many0_count(alt((alphanumeric1, tag("_"))))))(span)?;
+        let (span, text) = identifier_text(span)?;
         let (span, end) = position(span)?;
 
         let value: Cow<str> = Cow::Borrowed(&text);
@@ -442,7 +460,7 @@ fn match_punct_token(span: Span) -> ParseResult<PascalToken> {
 fn scan_decimal_literal(span: Span) -> ParseResult<usize> {
     // FIXME this is blah; derived from nom example
     map_res(recognize(many1(one_of("0123456789"))), |out: Span| {
-        usize::from_str_radix(&out, 10)
+        out.parse::<usize>()
     })(span)
 }
 
@@ -487,15 +505,21 @@ fn match_hex_literal_token(span: Span) -> ParseResult<PascalToken> {
     ))
 }
 
-/// This is weak, but sufficient for our needs.
+/// This is weak, but sufficient for our needs. The literal needs a fractional
+/// part, an exponent, or both, as in `0.5`, `1.0e-3`, or `1E6`, the latter two
+/// being needed for METAFONT's real arithmetic.
 fn match_float_literal_token(span: Span) -> ParseResult<PascalToken> {
     let (span, text) = recognize(tuple((
         many1(one_of("0123456789")),
-        tag("."),
-        many1(one_of("0123456789")),
+        opt(pair(tag("."), many1(one_of("0123456789")))),
+        opt(tuple((
+            one_of("eE"),
+            opt(one_of("+-")),
+            many1(one_of("0123456789")),
+        ))),
     )))(span)?;
 
-    if text.parse::<f64>().is_ok() {
+    if text.contains(['.', 'e', 'E']) && text.parse::<f64>().is_ok() {
         Ok((span, PascalToken::FloatLiteral(text)))
     } else {
         new_parse_error(span, ErrorKind::Float)
@@ -520,11 +544,12 @@ fn match_string_literal(span: Span) -> ParseResult<PascalToken> {
         _ => return new_parse_error(span, ErrorKind::Char),
     };
 
-    let (span, contents) = span.split_at_position(|c| c == '\n' || c == '@' || c == delim)?;
+    let (span, contents) =
+        span.split_at_position_complete(|c| c == '\n' || c == '@' || c == delim)?;
     let (span, terminator) = next_token(span)?;
 
     if let Token::Char('\n') = terminator {
-        return new_parse_error(span, ErrorKind::Char);
+        new_parse_error(span, ErrorKind::Char)
     } else if let Token::Control(_) = terminator {
         // We'll need to allocate this string.
         let mut span = span;
@@ -622,6 +647,32 @@ pub fn match_pascal_token<'a>(
 
 // Prettification
 
+/// Write a decimal number with its digits split into groups of three, as in
+/// `1_000_000`, if it has more than four of them.
+fn grouped_digits(n: isize) -> String {
+    let digits = n.unsigned_abs().to_string();
+
+    if digits.len() <= 4 {
+        return n.to_string();
+    }
+
+    let mut text = String::with_capacity(digits.len() * 4 / 3 + 1);
+
+    if n < 0 {
+        text.push('-');
+    }
+
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            text.push('_');
+        }
+
+        text.push(c);
+    }
+
+    text
+}
+
 impl<'a> RenderInline for PascalToken<'a> {
     fn measure_inline(&self) -> usize {
         match self {
@@ -699,15 +750,19 @@ impl<'a> RenderInline for PascalToken<'a> {
             PascalToken::TexString(_) => {}
 
             PascalToken::ReservedWord(sv) => {
-                dest.noscope_push(sv.value);
+                if dest.style().preserve_operators {
+                    dest.noscope_push(sv.value.pascal_spelling());
+                } else {
+                    dest.noscope_push(sv.value);
+                }
             }
 
             PascalToken::Identifier(ss) => {
-                dest.noscope_push(ss.value.as_ref());
+                dest.identifier(ss);
             }
 
             PascalToken::FormattedIdentifier(ss, _) => {
-                dest.noscope_push(ss.value.as_ref());
+                dest.identifier(ss);
             }
 
             PascalToken::OpenDelimiter(dk) => {
@@ -775,11 +830,19 @@ impl<'a> RenderInline for PascalToken<'a> {
             }
 
             PascalToken::Equals => {
-                dest.noscope_push("==");
+                dest.noscope_push(if dest.style().preserve_operators {
+                    "="
+                } else {
+                    "=="
+                });
             }
 
             PascalToken::NotEquals => {
-                dest.noscope_push("!=");
+                dest.noscope_push(if dest.style().preserve_operators {
+                    "<>"
+                } else {
+                    "!="
+                });
             }
 
             PascalToken::DoubleDot => {
@@ -787,11 +850,19 @@ impl<'a> RenderInline for PascalToken<'a> {
             }
 
             PascalToken::Gets => {
-                dest.noscope_push("=");
+                dest.noscope_push(if dest.style().preserve_operators {
+                    ":="
+                } else {
+                    "="
+                });
             }
 
             PascalToken::Equivalence => {
-                dest.noscope_push("===");
+                dest.noscope_push(if dest.style().preserve_operators {
+                    "=="
+                } else {
+                    "==="
+                });
             }
 
             PascalToken::Colon => {
@@ -818,16 +889,44 @@ impl<'a> RenderInline for PascalToken<'a> {
             PascalToken::CancelDefinitionFlag => {}
 
             PascalToken::IntLiteral(kind, n) => {
+                let style = dest.style();
+                let (group_digits, uppercase_hex) = (style.group_digits, style.uppercase_hex);
+                let preserve_octal = style.preserve_octal;
+
                 match kind {
+                    IntLiteralKind::Decimal if group_digits => {
+                        dest.scope_push(*DECIMAL_LITERAL_SCOPE, grouped_digits(*n))
+                    }
+
                     IntLiteralKind::Decimal => dest.scope_push(*DECIMAL_LITERAL_SCOPE, n),
 
+                    IntLiteralKind::Octal if preserve_octal => {
+                        dest.scope_push(*OCTAL_LITERAL_SCOPE, format!("@'{:o}", n));
+                    }
+
                     // I think octal is dumb, so I present it as hex.
+                    IntLiteralKind::Octal | IntLiteralKind::Hex if uppercase_hex => {
+                        dest.scope_push(*HEX_LITERAL_SCOPE, format!("0x{:X}", n));
+                    }
+
                     IntLiteralKind::Octal | IntLiteralKind::Hex => {
                         dest.scope_push(*HEX_LITERAL_SCOPE, format!("0x{:x}", n));
                     }
                 }
             }
 
+            PascalToken::StringLiteral(kind, ss) if dest.style().preserve_keywords => {
+                let delim = match kind {
+                    StringLiteralKind::SingleQuote => '\'',
+                    StringLiteralKind::DoubleQuote => '"',
+                };
+
+                dest.scope_push(
+                    *STRING_LITERAL_SCOPE,
+                    format!("{}{}{}", delim, ss.value, delim),
+                );
+            }
+
             PascalToken::StringLiteral(kind, ss) => match kind {
                 StringLiteralKind::SingleQuote => {
                     dest.scope_push(*STRING_LITERAL_SCOPE, format!("{:?}", ss.value));
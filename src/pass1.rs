@@ -1,39 +1,106 @@
 //! The first pass -- building up the index.
 
-use nom::{bytes::complete::take_while, character::complete::char, error::ErrorKind, Finish};
+use log::{debug, error, warn};
+use nom::{
+    branch::alt,
+    bytes::complete::take_while,
+    character::complete::{char, digit1},
+    combinator::{map_res, success, value},
+    error::ErrorKind,
+    Finish, Slice,
+};
 use tectonic_errors::prelude::*;
 
 use crate::{
+    c_token::{match_c_comment_end, match_c_comment_start, match_c_token, CToken},
     control::ControlKind,
     index::IndexEntryKind,
     parse_base::{new_parse_error, ParseResult, Span, SpanValue},
-    pascal_token::{match_pascal_token, PascalToken},
+    pascal_token::{match_pascal_token, PascalToken, StringLiteralKind},
     reserved::PascalReservedWord,
     state::{ModuleId, State},
     token::{next_token, Token},
 };
 
-/// Skip the "limbo" section at the start of the WEB file.
+/// Scan the "limbo" section at the start of the WEB file, returning its TeX
+/// text and the module-boundary token that ended it.
 ///
-/// This method approximately corresponds to WEAVE:89, `skip_limbo`.
-fn skip_limbo(mut span: Span) -> ParseResult<Token> {
-    // I feel like there must be a better way to do this. The way that peeking
-    // seems to work in nom, I think we have to loop char-by-char in order to be
-    // able to rewind to the '@' when we find our match.
+/// This method approximately corresponds to WEAVE:89, `skip_limbo`, and
+/// WEAVE:132, `copy_limbo`. As in WEAVE, `@@` becomes a literal `@`, `@z` is
+/// silently ignored (these can be left over from change files), and any other
+/// control code is erroneous and becomes a lone `@`. Warnings about such
+/// errors are printed if `warn` is true. The exception is that CWEB allows
+/// format definitions in limbo; we skip those, since we don't use them for C.
+pub fn scan_limbo(mut span: Span, warn: bool) -> ParseResult<(String, Token)> {
+    let mut text = String::new();
     let mut tok;
 
     loop {
-        (span, tok) = next_token(span)?;
+        match next_token(span) {
+            Ok((rest, t)) => {
+                span = rest;
+                tok = t;
+            }
+
+            Err(nom::Err::Error((_, ErrorKind::Char))) => {
+                // An `@` followed by something that isn't a control code at all.
+                let c = span.fragment()[1..].chars().next().unwrap();
+                span = span.slice(1 + c.len_utf8()..);
+
+                if c != 'z' && c != 'Z' {
+                    if warn {
+                        warn!("double @ required outside of sections (`@{}`)", c);
+                    }
+
+                    text.push('@');
+                }
+
+                continue;
+            }
+
+            Err(e) => return Err(e),
+        }
 
         match tok {
-            Token::Char(_) | Token::Control(ControlKind::AtLiteral) => continue,
+            Token::Char(c) => text.push(c),
+            Token::Control(ControlKind::AtLiteral) => text.push('@'),
+            Token::Control(ControlKind::FormatDefinition) => {
+                (span, _) = take_while(|c| c != '\n')(span)?;
+            }
             Token::Control(ControlKind::NewMajorModule)
-            | Token::Control(ControlKind::NewMinorModule) => return Ok((span, tok)),
-            _ => return new_parse_error(span, ErrorKind::Char),
+            | Token::Control(ControlKind::NewMinorModule) => return Ok((span, (text, tok))),
+            Token::Control(k) => {
+                if warn {
+                    warn!(
+                        "double @ required outside of sections (`@{}`)",
+                        k.syntax_char()
+                    );
+                }
+
+                text.push('@');
+            }
         }
     }
 }
 
+/// Scan the optional depth specification that may follow a `@*`.
+///
+/// This is a CWEB extension used by some WEB derivatives: `@**` indicates a
+/// depth of -1, `@*N` indicates a depth of N, and a plain `@*` indicates a
+/// depth of 0. If the dialect doesn't allow depth specifications, the depth is
+/// always 0.
+pub fn scan_major_module_depth(span: Span) -> ParseResult<i32> {
+    if !span.extra.allows_module_depths() {
+        return Ok((span, 0));
+    }
+
+    alt((
+        value(-1, char('*')),
+        map_res(digit1, |s: Span| s.fragment().parse::<i32>()),
+        success(0),
+    ))(span)
+}
+
 /// WEAVE:91, `skip_comment`
 ///
 /// Skip over the TeX portion of a comment inside an outer-Pascal section. This
@@ -104,18 +171,27 @@ fn first_pass_skip_tex<'a>(mut span: Span<'a>) -> ParseResult<'a, Token> {
 /// continues until one of the following tokens is found: `{`, `|`, `@f`, `@d`,
 /// `@p`, `@<`, `@ `, or `@*`. Therefore it will stop at comments rather than
 /// nesting into them.
+///
+/// If `in_code` is true, this Pascal is part of the program code, rather than
+/// TeX text or a comment, and so its double-quoted strings go into the string
+/// pool.
 fn first_pass_scan_pascal_only<'a>(
     cur_module: ModuleId,
+    in_code: bool,
     state: &mut State,
     mut span: Span<'a>,
 ) -> ParseResult<'a, Token> {
+    if state.dialect().is_cweb() {
+        return first_pass_scan_c(cur_module, in_code, state, span);
+    }
+
     let mut tok;
     let mut ptok;
 
     loop {
         (span, _) = take_while(|c| c == ' ' || c == '\t' || c == '\n')(span)?;
 
-        let prev_span = span.clone();
+        let prev_span = span;
         (span, tok) = next_token(span)?;
 
         match tok {
@@ -172,6 +248,16 @@ fn first_pass_scan_pascal_only<'a>(
                 state.set_definition_flag(false);
             }
 
+            PascalToken::StringLiteral(StringLiteralKind::DoubleQuote, text)
+                if in_code && state.dialect().has_string_pool() =>
+            {
+                state.add_pool_string_piece(
+                    &text.value,
+                    text.start.location_offset(),
+                    text.end.location_offset(),
+                );
+            }
+
             _ => {}
         }
     }
@@ -187,6 +273,10 @@ fn first_pass_scan_pascal<'a>(
     state: &mut State,
     mut span: Span<'a>,
 ) -> ParseResult<'a, Token> {
+    if state.dialect().is_cweb() {
+        return first_pass_scan_c(cur_module, true, state, span);
+    }
+
     let mut tok;
 
     let mut prev_span = span;
@@ -201,7 +291,7 @@ fn first_pass_scan_pascal<'a>(
                 (span, depth) = first_pass_skip_comment(1, span)?;
 
                 while depth > 0 {
-                    (span, tok) = first_pass_scan_pascal_only(cur_module, state, span)?;
+                    (span, tok) = first_pass_scan_pascal_only(cur_module, false, state, span)?;
 
                     if let Token::Char('|') = tok {
                         (span, depth) = first_pass_skip_comment(depth, span)?;
@@ -223,9 +313,118 @@ fn first_pass_scan_pascal<'a>(
                 return Ok((span, tok));
             }
 
+            Token::Char('|') => {
+                // A stray `|` in the code means nothing, and we've already
+                // consumed it, so just carry on after it. See WEAVE:112.
+                prev_span = span;
+                (span, tok) = first_pass_scan_pascal_only(cur_module, true, state, span)?;
+            }
+
             _ => {
-                (span, tok) = first_pass_scan_pascal_only(cur_module, state, prev_span)?;
+                (span, tok) = first_pass_scan_pascal_only(cur_module, true, state, prev_span)?;
+            }
+        }
+    }
+}
+
+/// The CWEB analogue of `first_pass_skip_comment`.
+///
+/// Skip over a C comment, which is TeX that may contain C code delimited by
+/// `|`. If `block` is true, this is a `/* ... */` comment; otherwise it's a
+/// `//` comment, which ends at the end of the line.
+fn first_pass_skip_c_comment<'a>(
+    cur_module: ModuleId,
+    block: bool,
+    state: &mut State,
+    mut span: Span<'a>,
+) -> ParseResult<'a, ()> {
+    let mut tok;
+
+    loop {
+        if let Ok((rest, _)) = match_c_comment_end(block)(span) {
+            return Ok((rest, ()));
+        }
+
+        (span, tok) = next_token(span)?;
+
+        match tok {
+            Token::Char('|') => {
+                (span, _) = first_pass_scan_c(cur_module, false, state, span)?;
+            }
+
+            Token::Char('\\') => {
+                (span, _) = next_token(span)?;
+            }
+
+            Token::Control(ControlKind::NewMajorModule)
+            | Token::Control(ControlKind::NewMinorModule) => {
+                return new_parse_error(span, ErrorKind::Char)
+            }
+
+            _ => {}
+        }
+    }
+}
+
+/// The CWEB analogue of `first_pass_scan_pascal_only` and
+/// `first_pass_scan_pascal`.
+///
+/// Read C tokens and store cross-references to identifiers, skipping over
+/// comments. Reading continues until one of the following tokens is found:
+/// `@f`, `@d`, `@c`, `@<`, `@ `, or `@*`; or, if `in_code` is false, a `|`.
+/// In program code `|` is an operator, so it can't end the C text.
+fn first_pass_scan_c<'a>(
+    cur_module: ModuleId,
+    in_code: bool,
+    state: &mut State,
+    mut span: Span<'a>,
+) -> ParseResult<'a, Token> {
+    let mut ctok;
+
+    loop {
+        if let Ok((rest, block)) = match_c_comment_start(span) {
+            (span, _) = first_pass_skip_c_comment(cur_module, block, state, rest)?;
+            continue;
+        }
+
+        (span, _) = take_while(|c| c == ' ' || c == '\t' || c == '\n')(span)?;
+        let (rest, tok) = next_token(span)?;
+
+        match tok {
+            Token::Char('|') if !in_code => return Ok((rest, tok)),
+
+            Token::Control(ControlKind::MacroDefinition)
+            | Token::Control(ControlKind::FormatDefinition)
+            | Token::Control(ControlKind::StartUnnamedPascal)
+            | Token::Control(ControlKind::ModuleName)
+            | Token::Control(ControlKind::NewMinorModule)
+            | Token::Control(ControlKind::NewMajorModule) => {
+                return Ok((rest, tok));
             }
+
+            _ => {}
+        }
+
+        (span, ctok) = match_c_token(span)?;
+
+        match ctok {
+            CToken::Identifier(text) => {
+                state.add_index_entry(text.value.into_owned(), IndexEntryKind::Normal, cur_module);
+            }
+
+            CToken::IndexEntry(kind, text) => {
+                state.add_index_entry(text.value.into_owned(), kind, cur_module);
+            }
+
+            CToken::DefinitionFlag => {
+                state.set_definition_flag(true);
+            }
+
+            CToken::CancelDefinitionFlag => {
+                state.set_definition_flag(false);
+            }
+
+            _ => {}
         }
     }
 }
@@ -245,6 +444,9 @@ fn first_pass_handle_tex<'a>(
     let mut tok;
 
     if is_major {
+        let depth;
+        (span, depth) = scan_major_module_depth(span)?;
+
         let mut lead_in = true;
         let mut summary = String::new();
 
@@ -266,13 +468,13 @@ fn first_pass_handle_tex<'a>(
                 }
 
                 _ => {
-                    eprintln!("unexpected token in major-module summary: {:?}", tok);
+                    error!("unexpected token in major-module summary: {:?}", tok);
                     return new_parse_error(span, ErrorKind::Complete);
                 }
             }
         }
 
-        state.register_major_module(cur_module, summary);
+        state.register_major_module(cur_module, depth, summary);
     }
 
     (span, tok) = first_pass_skip_tex(span)?;
@@ -299,7 +501,7 @@ fn first_pass_handle_tex<'a>(
             }
 
             Token::Char('|') => {
-                (span, _) = first_pass_scan_pascal_only(cur_module, state, span)?;
+                (span, _) = first_pass_scan_pascal_only(cur_module, false, state, span)?;
                 (span, tok) = first_pass_skip_tex(span)?;
             }
 
@@ -310,6 +512,46 @@ fn first_pass_handle_tex<'a>(
     }
 }
 
+/// Read the tokens of a macro definition, starting just after the `@d`, so
+/// that the macro can be classified. The tokens are read up to the first
+/// comment or anything else that ends the Pascal code, which is plenty for
+/// classification.
+fn first_pass_classify_macro(state: &mut State, mut span: Span) {
+    let mut tokens = Vec::new();
+
+    loop {
+        if let Ok((rest, _)) = take_while::<_, _, ()>(|c| c == ' ' || c == '\t' || c == '\n')(span)
+        {
+            span = rest;
+        }
+
+        match next_token(span) {
+            Ok((_, Token::Char('|')))
+            | Ok((_, Token::Char('{')))
+            | Ok((_, Token::Control(ControlKind::MacroDefinition)))
+            | Ok((_, Token::Control(ControlKind::FormatDefinition)))
+            | Ok((_, Token::Control(ControlKind::StartUnnamedPascal)))
+            | Ok((_, Token::Control(ControlKind::ModuleName)))
+            | Ok((_, Token::Control(ControlKind::NewMinorModule)))
+            | Ok((_, Token::Control(ControlKind::NewMajorModule)))
+            | Err(_) => break,
+
+            _ => {}
+        }
+
+        match match_pascal_token(span, None) {
+            Ok((rest, ptok)) => {
+                tokens.push(ptok);
+                span = rest;
+            }
+
+            Err(_) => break,
+        }
+    }
+
+    state.add_macro_definition(&tokens);
+}
+
 /// See WEAVE:115-116. Definitions are pretty simple structurally.
 fn first_pass_handle_definitions<'a>(
     cur_module: ModuleId,
@@ -328,6 +570,16 @@ fn first_pass_handle_definitions<'a>(
 
             Token::Control(ControlKind::MacroDefinition) => {
                 state.set_definition_flag(true);
+
+                if !state.dialect().is_cweb() {
+                    first_pass_classify_macro(state, span);
+                }
+
+                (span, tok) = first_pass_scan_pascal(cur_module, state, span)?;
+            }
+
+            Token::Control(ControlKind::FormatDefinition) if state.dialect().is_cweb() => {
+                state.set_definition_flag(true);
                 (span, tok) = first_pass_scan_pascal(cur_module, state, span)?;
             }
 
@@ -339,7 +591,7 @@ fn first_pass_handle_definitions<'a>(
 
                 if let PascalToken::Identifier(text) = ptok {
                     state.add_index_entry(
-                        text.value.to_owned(),
+                        text.value.clone().into_owned(),
                         IndexEntryKind::Normal,
                         cur_module,
                     );
@@ -355,6 +607,15 @@ fn first_pass_handle_definitions<'a>(
                                 IndexEntryKind::Normal,
                                 cur_module,
                             );
+                        } else if let PascalToken::Identifier(other) = ptok {
+                            // Format like another identifier, which might
+                            // itself be formatted like a reserved word.
+                            state.add_index_entry(
+                                other.value.clone().into_owned(),
+                                IndexEntryKind::Normal,
+                                cur_module,
+                            );
+                            state.format_like(text.value.into_owned(), &other.value);
                         }
                     }
                 } else if let PascalToken::ReservedWord(sv) = ptok {
@@ -398,7 +659,7 @@ fn first_pass_handle_definitions<'a>(
             }
 
             Token::Char('|') => {
-                (span, tok) = first_pass_scan_pascal_only(cur_module, state, span)?;
+                (span, tok) = first_pass_scan_pascal_only(cur_module, false, state, span)?;
             }
 
             _ => {
@@ -417,7 +678,7 @@ fn first_pass_handle_pascal<'a>(
 ) -> ParseResult<'a, Token> {
     let mut tok;
 
-    let mut prev_span = span.clone();
+    let mut prev_span = span;
     (span, tok) = next_token(span)?;
 
     loop {
@@ -430,10 +691,20 @@ fn first_pass_handle_pascal<'a>(
             Token::Control(ControlKind::ModuleName) => {
                 state.set_definition_flag(false);
                 (span, _) = state.scan_module_name_and_register(cur_module, span)?;
-                prev_span = span.clone();
+                prev_span = span;
                 (span, tok) = next_token(span)?;
             }
 
+            Token::Control(ControlKind::MacroDefinition)
+            | Token::Control(ControlKind::FormatDefinition)
+            | Token::Control(ControlKind::StartUnnamedPascal) => {
+                error!(
+                    "module {}: definitions and `@p` can't appear in the code part of a module",
+                    cur_module
+                );
+                return new_parse_error(span, ErrorKind::Verify);
+            }
+
             _ => {
                 (span, tok) = first_pass_scan_pascal(cur_module, state, prev_span)?;
             }
@@ -442,24 +713,26 @@ fn first_pass_handle_pascal<'a>(
 }
 
 fn first_pass_inner<'a>(state: &mut State, span: Span<'a>) -> ParseResult<'a, ()> {
-    let (mut span, mut tok) = skip_limbo(span)?;
+    let (mut span, (limbo, mut tok)) = scan_limbo(span, true)?;
+    state.set_limbo(limbo);
     let mut cur_module: ModuleId = 0;
 
     loop {
         // At the top of this loop, we've just read a new-module boundary token.
 
         cur_module += 1;
+        state.register_module(cur_module);
 
         let is_major = match tok {
             Token::Control(ControlKind::NewMajorModule) => {
-                eprintln!("- Major module #{}", cur_module);
+                debug!("major module #{}", cur_module);
                 true
             }
 
             Token::Control(ControlKind::NewMinorModule) => false,
 
             _ => {
-                eprintln!("unexpected module end {:?}", tok);
+                error!("unexpected module end {:?}", tok);
                 return new_parse_error(span, ErrorKind::Complete);
             }
         };
@@ -502,7 +775,7 @@ fn first_pass_inner<'a>(state: &mut State, span: Span<'a>) -> ParseResult<'a, ()
 }
 
 pub fn execute(span: Span) -> Result<State> {
-    let mut state = State::default();
+    let mut state = State::new(span.extra);
 
     match first_pass_inner(&mut state, span).finish() {
         Ok((_remainder, _value)) => {}
@@ -510,6 +783,9 @@ pub fn execute(span: Span) -> Result<State> {
         Err((_remainder, kind)) => return Err(anyhow!(kind.description().to_owned())),
     }
 
+    state.resolve_module_abbreviations()?;
+    state.resolve_macro_kinds();
     state.compute_module_ids();
+    state.finish_string_pool();
     Ok(state)
 }
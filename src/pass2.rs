@@ -1,39 +1,390 @@
 //! The second pass -- emitting TeX
 
+use clap::ArgEnum;
+use lazy_static::lazy_static;
+#[cfg(feature = "serde")]
+use log::info;
+use log::{error, warn};
 use nom::{bytes::complete::take_while, character::complete::char, error::ErrorKind, Finish};
-use std::borrow::Cow;
-use syntect::highlighting::ThemeSet;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "serde")]
+use std::hash::{Hash, Hasher};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt::Write,
+    io::{self, Write as _},
+    ops::Range,
+    sync::Arc,
+};
+use syntect::highlighting::Theme;
 use tectonic_errors::prelude::*;
 
+#[cfg(feature = "serde")]
+use crate::cache::{CodeCache, KeyHasher};
 use crate::{
+    c_token::{match_c_comment_end, match_c_comment_start, match_c_token},
+    callgraph::{CallGraph, CallGraphBuilder},
+    comments::{comment_text, CommentText},
+    constants::MacroValues,
     control::ControlKind,
+    diagnostics::{self, Lint, LintLevel, LintLevels, Warning},
+    directives::DirectiveChecker,
+    labels::{LabelChecker, LabelContext},
+    macros::MacroTable,
+    metrics::Metrics,
     parse_base::{new_parse_error, ParseResult, Span, SpanValue, StringSpan},
-    pascal_token::{PascalToken, StringLiteralKind},
-    prettify::{Prettifier, RenderInline},
+    pascal_token::{DelimiterKind, IntLiteralKind, PascalToken, StringLiteralKind},
+    prettify::{
+        CodeStyle, CommentStyle, IdentifierAnchors, Prettifier, RenderInline, TexInsert,
+        DEFAULT_MAX_DEPTH, DEFAULT_WIDTH,
+    },
+    reachability::ReachabilityChecker,
     reserved::PascalReservedWord,
+    sections::SectionSelection,
     state::{ModuleId, State},
+    strict::StrictChecker,
+    style::{self, CodeLengthChecker, StyleLimits},
+    symbols::SymbolChecker,
+    symtab::SymbolTable,
+    timings::{PhaseTimer, PhaseTimes, Timings},
     token::{next_token, Token},
+    unreachable::find_unreachable,
     weblang::{
-        base::TypesetComment, module_reference::WebModuleReference, WebCode, WebSyntax, WebToken,
+        base::{TypesetComment, WebComment},
+        c::{CCode, CItem},
+        module_reference::WebModuleReference,
+        AstArena, NestingTracker, ToplevelParser, WebCode, WebParseError, WebSyntax, WebToken,
+        WebToplevel,
     },
+    xref::{FunctionUses, XrefDatabase},
+    WeaveOptions,
 };
 
-#[derive(Debug, Default)]
-struct OutputState {
+#[derive(Debug)]
+struct OutputState<'t> {
     col: usize,
     saw_phantom: bool,
+    theme: &'t Theme,
+    text: String,
+
+    /// The complete input text.
+    source: &'t str,
+
+    /// The module currently being processed.
+    module: ModuleId,
+
+    /// If set, we're dumping the parsed Pascal code instead of prettifying it.
+    dump: Option<Vec<serde_json::Value>>,
+
+    /// If set, we're only lexing the Pascal code, and leaving the fragments
+    /// pending for the caller to collect.
+    lex_only: bool,
+
+    /// If set, we're only building the symbol table, so the code doesn't need
+    /// to be prettified.
+    table_only: bool,
+
+    /// If set, we're just checking whether the Pascal code can be parsed,
+    /// and we record the fragments that can't be here.
+    check_failures: Option<Vec<CheckFailure>>,
+
+    /// If set, problems parsing the code are recorded here, while the code is
+    /// still prettified.
+    parse_failures: Option<Vec<CheckFailure>>,
+
+    /// If set, we're looking for occurrences of an identifier instead of
+    /// prettifying the Pascal code, and we record them here.
+    query: Option<(IdentifierQuery, Vec<IdentifierLocation>)>,
+
+    /// If set, we're prettifying the code as HTML rather than TeX, and we
+    /// record the fragments here.
+    html: Option<Vec<HtmlFragment>>,
+
+    /// If set, the TeX for each fragment of code is recorded here, rather than
+    /// being spliced into the output text.
+    fragments: Option<Vec<String>>,
+
+    /// If set, the HTML is highlighted with this dark theme as well as the
+    /// main one, which is taken to be light.
+    dark_theme: Option<&'t Theme>,
+
+    /// If set, only these modules are emitted.
+    sections: Option<SectionSelection>,
+
+    /// If set, the table of contents is `\input` from this file rather than
+    /// emitted inline.
+    contents_file: Option<String>,
+
+    /// Whether to emit a complete LaTeX document.
+    standalone: bool,
+
+    /// Whether to emit TeX structured like WEAVE's, using its macros.
+    webmac: bool,
+
+    /// Whether a module has been started with WEAVE's macros, but not yet
+    /// ended with `\fi`.
+    module_open: bool,
+
+    /// These modules are never emitted.
+    excluded: SectionSelection,
+
+    /// Custom toplevel parsers to try before the built-in ones.
+    toplevel_parsers: Vec<ToplevelParser>,
+
+    /// The kinds of the macros, which guide the parsing and layout of code
+    /// that uses them.
+    macros: Arc<MacroTable>,
+
+    /// The width to which the code is laid out.
+    width: usize,
+
+    /// The width to which code in the TeX part of a module is laid out.
+    inline_width: usize,
+
+    /// If set, code in the TeX part of a module that takes more lines than this
+    /// is displayed rather than set inline.
+    inline_max_lines: Option<usize>,
+
+    /// How many levels of indentation the code may be nested in before it's
+    /// no longer wrapped.
+    max_depth: usize,
+
+    /// Custom TeX to emit in place of particular identifiers.
+    identifier_tex: Arc<HashMap<String, String>>,
+
+    /// How the code is written out.
+    style: CodeStyle,
+
+    /// Where each procedure and function is used, for annotating their
+    /// definitions.
+    function_uses: Arc<HashMap<String, FunctionUses>>,
+
+    /// The anchors linking identifiers to their declarations, in HTML output.
+    anchors: Arc<IdentifierAnchors>,
+
+    /// Settings adjusted by pragmas in the current module.
+    pragmas: Pragmas,
+
+    /// Whether we're skipping the current module because it isn't selected.
+    skipping: bool,
+
+    /// The offset in `text` at which each emitted module starts.
+    module_starts: Vec<(ModuleId, usize)>,
+
+    /// How to handle each kind of warning.
+    lints: LintLevels,
+
+    /// The limits enforced by the style lints.
+    style_limits: StyleLimits,
+
+    /// If set, we're counting the lines of the displayed code of each module.
+    code_length: Option<CodeLengthChecker>,
+
+    /// The warnings that have been issued.
+    warnings: Vec<Warning>,
+
+    /// If set, we're keeping track of labels so that they can be checked once
+    /// all of the code has been seen.
+    labels: Option<LabelChecker>,
+
+    /// If set, we're keeping track of identifiers so that we can check that
+    /// they're all declared once all of the code has been seen.
+    symbols: Option<SymbolChecker>,
+
+    /// If set, we're recording the identifiers that the code declares.
+    symbol_table: Option<SymbolTable>,
+
+    /// If set, we're keeping track of what the code refers to, so that the
+    /// call graph can be worked out once all of the code has been seen.
+    call_graph: Option<CallGraphBuilder>,
+
+    /// If set, we're building a cross-reference database of the program.
+    xref: Option<XrefDatabase>,
+
+    /// If set, we're extracting the text of the comments in the code.
+    comments: Option<Vec<CommentText>>,
+
+    /// The values of the numeric macros defined so far, so that definitions
+    /// can be annotated with their values.
+    macro_values: MacroValues,
+
+    /// If set, we're keeping track of module references so that we can check
+    /// that every named module reaches the program.
+    reachability: Option<ReachabilityChecker>,
+
+    /// If set, we're looking for code that wouldn't be valid Pascal, which
+    /// can only be confirmed once all of the macros are known.
+    strict: Option<StrictChecker>,
+
+    /// If set, we're matching up preprocessor conditionals across the whole
+    /// program.
+    directives: Option<DirectiveChecker>,
+
+    /// The code whose parsing and prettifying has been put off, in order.
+    pending: Vec<PendingCode<'t>>,
+
+    /// If set, we're keeping track of how long each module takes to handle.
+    timings: Option<Timings>,
+
+    /// If set, we're measuring the code of each module.
+    metrics: Option<Metrics>,
+
+    /// If set, code laid out by an earlier run, to be used instead of laying
+    /// it out again.
+    #[cfg(feature = "serde")]
+    cache: Option<&'t CodeCache>,
+
+    /// If set, we record the code laid out by this run here.
+    #[cfg(feature = "serde")]
+    new_cache: Option<CodeCache>,
+
+    /// A hash of the settings that affect the layout of all of the code, for
+    /// the cache keys.
+    #[cfg(feature = "serde")]
+    cache_salt: u64,
+
+    /// The number of fragments of code found in the cache, and the number
+    /// looked up.
+    #[cfg(feature = "serde")]
+    cache_hits: (usize, usize),
 }
 
-impl OutputState {
+impl<'t> OutputState<'t> {
+    fn new(theme: &'t Theme, source: &'t str) -> Self {
+        OutputState {
+            col: 0,
+            saw_phantom: false,
+            theme,
+            text: String::new(),
+            source,
+            module: 0,
+            dump: None,
+            lex_only: false,
+            table_only: false,
+            check_failures: None,
+            parse_failures: None,
+            query: None,
+            html: None,
+            fragments: None,
+            dark_theme: None,
+            sections: None,
+            contents_file: None,
+            standalone: false,
+            webmac: false,
+            module_open: false,
+            excluded: SectionSelection::default(),
+            toplevel_parsers: Vec::new(),
+            macros: Arc::default(),
+            width: DEFAULT_WIDTH,
+            inline_width: DEFAULT_WIDTH,
+            inline_max_lines: None,
+            max_depth: DEFAULT_MAX_DEPTH,
+            identifier_tex: Arc::default(),
+            style: CodeStyle::default(),
+            function_uses: Arc::default(),
+            anchors: Arc::default(),
+            pragmas: Pragmas::default(),
+            skipping: false,
+            module_starts: Vec::new(),
+            lints: LintLevels::default(),
+            style_limits: StyleLimits::default(),
+            code_length: None,
+            labels: None,
+            symbols: None,
+            symbol_table: None,
+            call_graph: None,
+            xref: None,
+            comments: None,
+            macro_values: MacroValues::default(),
+            reachability: None,
+            strict: None,
+            directives: None,
+            warnings: Vec::new(),
+            pending: Vec::new(),
+            timings: None,
+            metrics: None,
+            #[cfg(feature = "serde")]
+            cache: None,
+            #[cfg(feature = "serde")]
+            new_cache: None,
+            #[cfg(feature = "serde")]
+            cache_salt: 0,
+            #[cfg(feature = "serde")]
+            cache_hits: (0, 0),
+        }
+    }
+
+    /// Issue a warning about the current module, unless its lint is allowed.
+    fn warn(&mut self, lint: Lint, message: String, location: Option<(Range<usize>, String)>) {
+        let level = self.lints.get(lint);
+
+        if level != LintLevel::Allow {
+            self.warnings.push(Warning {
+                lint,
+                level,
+                module: self.module,
+                message,
+                location,
+            });
+        }
+    }
+
+    /// Put off the parsing and prettifying of some code in the current module,
+    /// so that it can be done for all of the code in parallel. The code will
+    /// be emitted at the current end of the text. If the code is a macro
+    /// definition with a numeric value worth showing, that's `define_value`.
+    fn defer(
+        &mut self,
+        mode: EmitPascalMode<'t>,
+        kind: PendingKind<'t>,
+        define_value: Option<isize>,
+    ) {
+        self.pending.push(PendingCode {
+            module: self.module,
+            offset: self.text.len(),
+            mode,
+            pragmas: self.pragmas,
+            kind,
+            define_value,
+        });
+    }
+
+    /// Set up the options that affect how the code is laid out.
+    fn set_layout(&mut self, options: &WeaveOptions) {
+        self.width = options.width.unwrap_or(DEFAULT_WIDTH);
+        self.inline_width = options.inline_width.unwrap_or(self.width);
+        self.inline_max_lines = options.inline_max_lines;
+        self.max_depth = options.max_depth.unwrap_or(DEFAULT_MAX_DEPTH);
+        self.identifier_tex = Arc::new(options.identifier_tex.clone());
+        self.style = options.style.clone();
+    }
+
+    /// Get the width to which code emitted in some mode is laid out, unless a
+    /// pragma says otherwise.
+    fn width_for(&self, mode: &EmitPascalMode) -> usize {
+        if mode.is_inline() {
+            self.inline_width
+        } else {
+            self.width
+        }
+    }
+
     fn printc(&mut self, c: char) {
+        if self.skipping {
+            return;
+        }
+
         if c == '\n' {
             if self.col != 0 || !self.saw_phantom {
-                print!("{}", c);
+                self.text.push(c);
             }
             self.col = 0;
             self.saw_phantom = false;
         } else {
-            print!("{}", c);
+            self.text.push(c);
             self.col += 1
         }
     }
@@ -50,27 +401,32 @@ impl OutputState {
 }
 
 /// WEAVE:132, `copy_limbo`, or so.
-fn copy_limbo<'a>(output: &mut OutputState, mut span: Span<'a>) -> ParseResult<'a, Token> {
-    let mut tok;
-
-    loop {
-        (span, tok) = next_token(span)?;
+///
+/// The limbo text was captured in the first pass, so here we just skip over it
+/// and emit the captured version, bracketed by hook macros. The hooks are plain
+/// macros, not an environment, since limbo definitions must not be local to a
+/// group.
+fn copy_limbo<'a>(
+    state: &State,
+    output: &mut OutputState,
+    span: Span<'a>,
+) -> ParseResult<'a, Token> {
+    let (span, (_, tok)) = crate::pass1::scan_limbo(span, false)?;
 
-        match tok {
-            Token::Char(c) => {
-                output.printc(c);
-            }
+    if !output.webmac {
+        output.prints("\\WebBeginLimbo\n");
+    }
 
-            Token::Control(ControlKind::AtLiteral) => {
-                output.printc('@');
-            }
+    output.prints(state.limbo());
 
-            Token::Control(ControlKind::NewMajorModule)
-            | Token::Control(ControlKind::NewMinorModule) => return Ok((span, tok)),
+    if output.col != 0 {
+        output.printc('\n');
+    }
 
-            _ => return new_parse_error(span, ErrorKind::Char),
-        }
+    if !output.webmac {
+        output.prints("\\WebEndLimbo\n");
     }
+    Ok((span, tok))
 }
 
 /// WEAVE:134, `copy_tex`
@@ -143,19 +499,44 @@ fn copy_comment<'a>(mut depth: usize, mut span: Span<'a>) -> ParseResult<'a, (St
     }
 }
 
+/// Match a Pascal token, also returning the range of byte offsets that it
+/// occupies.
+fn match_positioned_pascal_token<'a>(
+    span: Span<'a>,
+    state: &State,
+) -> ParseResult<'a, (PascalToken<'a>, Range<usize>)> {
+    let (span, _) = take_while(|c| c == ' ' || c == '\t' || c == '\n')(span)?;
+    let start = span.location_offset();
+    let (span, ptok) = state.match_pascal_token_with_formats(span)?;
+    Ok((span, (ptok, start..span.location_offset())))
+}
+
+/// Get the next token while scanning code.
+///
+/// Code can run up to the end of the input if it's in the last module, so we
+/// treat the end of the input like the start of a new module. This way the code
+/// gets emitted, after which the main loop notices that the input is done.
+fn next_code_token(span: Span) -> ParseResult<Token> {
+    if span.fragment().is_empty() {
+        Ok((span, Token::Control(ControlKind::NewMinorModule)))
+    } else {
+        next_token(span)
+    }
+}
+
 fn scan_pascal_only<'a>(
     mut span: Span<'a>,
     state: &State,
-) -> ParseResult<'a, (Vec<PascalToken<'a>>, Token)> {
-    let mut ptoks = Vec::new();
+) -> ParseResult<'a, (WebSyntax<'a>, Token)> {
+    let mut code = WebSyntax::default();
     let mut tok;
     let mut ptok;
 
     loop {
         (span, _) = take_while(|c| c == ' ' || c == '\t' || c == '\n')(span)?;
 
-        let prev_span = span.clone();
-        (span, tok) = next_token(span)?;
+        let prev_span = span;
+        (span, tok) = next_code_token(span)?;
 
         match tok {
             Token::Char('|')
@@ -166,7 +547,7 @@ fn scan_pascal_only<'a>(
             | Token::Control(ControlKind::ModuleName)
             | Token::Control(ControlKind::NewMinorModule)
             | Token::Control(ControlKind::NewMajorModule) => {
-                return Ok((span, (ptoks, tok)));
+                return Ok((span, (code, tok)));
             }
             _ => {}
         }
@@ -174,6 +555,7 @@ fn scan_pascal_only<'a>(
         // Looks like we still have Pascal. Now parse it as such.
 
         (span, ptok) = state.match_pascal_token_with_formats(prev_span)?;
+        let pos = prev_span.location_offset()..span.location_offset();
 
         match ptok {
             PascalToken::IndexEntry(_, _)
@@ -182,22 +564,37 @@ fn scan_pascal_only<'a>(
             | PascalToken::ForcedEol
             | PascalToken::Formatting => {}
 
+            // We know the actual value of the checksum, so we might as well
+            // show it.
+            PascalToken::StringPoolChecksum => {
+                code.push(
+                    WebToken::Pascal(PascalToken::IntLiteral(
+                        IntLiteralKind::Decimal,
+                        state.string_pool_checksum() as isize,
+                    )),
+                    pos,
+                );
+            }
+
             // Occasionally TexStrings are used as placeholders inside Pascal
             // expressions in inline Pascal expressions. If we elide them
             // entirely, this causes WEB parser to choke. Our current hack is to
             // try to pick out the ones that are used this way and transform
             // them into identifiers.
-            PascalToken::TexString(sv) => {
+            PascalToken::TexString(sv) if state.dialect().has_xetex_workarounds() => {
                 let text = sv.value.as_ref();
 
                 // Tex strings wrapping math work as identifiers
                 if let Some(t) = text.strip_prefix("$") {
                     if let Some(t) = t.strip_suffix("$") {
-                        ptoks.push(PascalToken::Identifier(StringSpan {
-                            start: sv.start.clone(),
-                            end: sv.start.clone(),
-                            value: Cow::Owned(t.to_owned()),
-                        }));
+                        code.push(
+                            WebToken::Pascal(PascalToken::Identifier(StringSpan {
+                                start: sv.start,
+                                end: sv.start,
+                                value: Cow::Owned(t.to_owned()),
+                            })),
+                            pos,
+                        );
                         continue;
                     }
                 }
@@ -206,14 +603,17 @@ fn scan_pascal_only<'a>(
                 // a string literal.
                 if let Some(t) = text.strip_prefix("\\.{\\'") {
                     if let Some(t) = t.strip_suffix("\\'}") {
-                        ptoks.push(PascalToken::StringLiteral(
-                            StringLiteralKind::SingleQuote,
-                            StringSpan {
-                                start: sv.start.clone(),
-                                end: sv.start.clone(),
-                                value: Cow::Owned(t.to_owned()),
-                            },
-                        ));
+                        code.push(
+                            WebToken::Pascal(PascalToken::StringLiteral(
+                                StringLiteralKind::SingleQuote,
+                                StringSpan {
+                                    start: sv.start,
+                                    end: sv.start,
+                                    value: Cow::Owned(t.to_owned()),
+                                },
+                            )),
+                            pos,
+                        );
                         continue;
                     }
                 }
@@ -221,14 +621,17 @@ fn scan_pascal_only<'a>(
                 // XeTeX(2022.0):345, there's a TexString in identifier
                 // position.
                 if text == "max" {
-                    ptoks.push(PascalToken::StringLiteral(
-                        StringLiteralKind::SingleQuote,
-                        StringSpan {
-                            start: sv.start.clone(),
-                            end: sv.start.clone(),
-                            value: Cow::Owned(text.to_owned()),
-                        },
-                    ));
+                    code.push(
+                        WebToken::Pascal(PascalToken::StringLiteral(
+                            StringLiteralKind::SingleQuote,
+                            StringSpan {
+                                start: sv.start,
+                                end: sv.start,
+                                value: Cow::Owned(text.to_owned()),
+                            },
+                        )),
+                        pos,
+                    );
                     continue;
                 }
 
@@ -237,35 +640,140 @@ fn scan_pascal_only<'a>(
                 if let Some(t) = text.strip_prefix("\\\\{") {
                     if let Some(t) = t.strip_suffix("}") {
                         if t == "stuff" {
-                            ptoks.push(PascalToken::Identifier(StringSpan {
-                                start: sv.start.clone(),
-                                end: sv.start.clone(),
-                                value: Cow::Owned(t.to_owned()),
-                            }));
+                            code.push(
+                                WebToken::Pascal(PascalToken::Identifier(StringSpan {
+                                    start: sv.start,
+                                    end: sv.start,
+                                    value: Cow::Owned(t.to_owned()),
+                                })),
+                                pos,
+                            );
                             continue;
                         }
                     }
                 }
             }
 
+            PascalToken::TexString(_) => {}
+
+            other => {
+                code.push(WebToken::Pascal(other), pos);
+            }
+        }
+    }
+}
+
+/// The CWEB analogue of `copy_comment`.
+///
+/// Copy the TeX text of a C comment. If `block` is true, this is a `/* ... */`
+/// comment; otherwise it's a `//` comment, which ends at the end of the line.
+/// We don't have a good way to represent C code embedded in the comment, so
+/// for now we just flatten it into the text.
+fn copy_c_comment<'a>(block: bool, state: &State, mut span: Span<'a>) -> ParseResult<'a, String> {
+    let mut text = String::new();
+    let mut tok;
+
+    loop {
+        if let Ok((rest, _)) = match_c_comment_end(block)(span) {
+            return Ok((rest, text));
+        }
+
+        (span, tok) = next_token(span)?;
+
+        match tok {
+            Token::Char('|') => {
+                let code;
+                (span, (code, _)) = scan_c(span, state, false)?;
+
+                for (i, item) in code.0.iter().enumerate() {
+                    if i > 0 {
+                        text.push(' ');
+                    }
+
+                    if let CItem::Token(t) = item {
+                        text.push_str(&t.to_string());
+                    }
+                }
+            }
+
+            Token::Char('\\') => {
+                text.push('\\');
+
+                let c;
+                (span, c) = next_token(span)?;
+                c.push_syntax_into(&mut text);
+            }
+
+            Token::Control(ControlKind::NewMajorModule)
+            | Token::Control(ControlKind::NewMinorModule) => {
+                return new_parse_error(span, ErrorKind::Char)
+            }
+
             other => {
-                ptoks.push(other);
+                other.push_syntax_into(&mut text);
+            }
+        }
+    }
+}
+
+/// The CWEB analogue of `scan_pascal` and `scan_pascal_only`.
+///
+/// Read C code up to the next control code that ends it, or if `in_code` is
+/// false, a closing `|`. As with `scan_pascal`, module references end the
+/// scan.
+fn scan_c<'a>(
+    mut span: Span<'a>,
+    state: &State,
+    in_code: bool,
+) -> ParseResult<'a, (CCode<'a>, Token)> {
+    let mut code = CCode::default();
+    let mut ctok;
+
+    loop {
+        if let Ok((rest, block)) = match_c_comment_start(span) {
+            let text;
+            (span, text) = copy_c_comment(block, state, rest)?;
+            code.0
+                .push(CItem::Comment(WebComment(vec![TypesetComment::Tex(text)])));
+            continue;
+        }
+
+        (span, _) = take_while(|c| c == ' ' || c == '\t' || c == '\n')(span)?;
+        let (rest, tok) = next_token(span)?;
+
+        match tok {
+            Token::Char('|') if !in_code => return Ok((rest, (code, tok))),
+
+            Token::Control(ControlKind::MacroDefinition)
+            | Token::Control(ControlKind::FormatDefinition)
+            | Token::Control(ControlKind::StartUnnamedPascal)
+            | Token::Control(ControlKind::ModuleName)
+            | Token::Control(ControlKind::NewMinorModule)
+            | Token::Control(ControlKind::NewMajorModule) => {
+                return Ok((rest, (code, tok)));
             }
+
+            _ => {}
         }
+
+        (span, ctok) = match_c_token(span)?;
+        code.0.push(CItem::Token(ctok));
     }
 }
 
 fn scan_pascal<'a>(mut span: Span<'a>, state: &State) -> ParseResult<'a, (WebSyntax<'a>, Token)> {
-    let mut code = Vec::new();
+    let mut code = WebSyntax::default();
     let mut tok;
     let mut ptoks;
 
     let mut prev_span = span;
-    (span, tok) = next_token(span)?;
+    (span, tok) = next_code_token(span)?;
 
     loop {
         match tok {
             Token::Char('{') => {
+                // We've already consumed the `{`.
+                let start = span.location_offset() - 1;
                 let mut comment = Vec::new();
                 let text;
                 let mut depth;
@@ -275,7 +783,13 @@ fn scan_pascal<'a>(mut span: Span<'a>, state: &State) -> ParseResult<'a, (WebSyn
 
                 while depth > 0 {
                     (span, (ptoks, tok)) = scan_pascal_only(span, state)?;
-                    comment.push(TypesetComment::Pascal(ptoks));
+                    comment.push(TypesetComment::Pascal(
+                        ptoks
+                            .tokens
+                            .into_iter()
+                            .map(WebToken::into_pascal)
+                            .collect(),
+                    ));
 
                     if let Token::Char('|') = tok {
                         let text;
@@ -286,9 +800,9 @@ fn scan_pascal<'a>(mut span: Span<'a>, state: &State) -> ParseResult<'a, (WebSyn
                     }
                 }
 
-                code.push(WebToken::Comment(comment));
+                code.push(WebToken::Comment(comment), start..span.location_offset());
                 prev_span = span;
-                (span, tok) = next_token(span)?;
+                (span, tok) = next_code_token(span)?;
             }
 
             Token::Control(ControlKind::MacroDefinition)
@@ -297,12 +811,20 @@ fn scan_pascal<'a>(mut span: Span<'a>, state: &State) -> ParseResult<'a, (WebSyn
             | Token::Control(ControlKind::ModuleName)
             | Token::Control(ControlKind::NewMinorModule)
             | Token::Control(ControlKind::NewMajorModule) => {
-                return Ok((span, (WebSyntax(code), tok)));
+                return Ok((span, (code, tok)));
+            }
+
+            Token::Char('|') => {
+                // A stray `|` in the code means nothing, and we've already
+                // consumed it, so just carry on after it.
+                prev_span = span;
+                (span, (ptoks, tok)) = scan_pascal_only(span, state)?;
+                code.append(&mut ptoks);
             }
 
             _ => {
                 (span, (ptoks, tok)) = scan_pascal_only(prev_span, state)?;
-                code.extend(ptoks.drain(..).map(|t| WebToken::Pascal(t)));
+                code.append(&mut ptoks);
             }
         }
     }
@@ -322,138 +844,1397 @@ enum EmitPascalMode<'a> {
 
 impl<'a> EmitPascalMode<'a> {
     fn is_inline(&self) -> bool {
-        if let EmitPascalMode::Inline = self {
-            true
-        } else {
-            false
+        matches!(self, EmitPascalMode::Inline)
+    }
+
+    /// A short description of the context of the code, for AST dumps.
+    fn context(&self) -> &'static str {
+        match self {
+            EmitPascalMode::Inline => "inline",
+            EmitPascalMode::Define => "define",
+            EmitPascalMode::Format => "format",
+            EmitPascalMode::Anonymous => "anonymous",
+            EmitPascalMode::NamedModule(..) => "named",
         }
     }
 }
 
-fn emit_pascal<'a>(syntax: WebSyntax<'a>, mode: EmitPascalMode<'a>) {
-    // parse into the AST
-
-    let code = WebCode::parse(&syntax).expect("parse failed");
-
-    // Prettify
-
-    let mut pretty = Prettifier::new();
+lazy_static! {
+    /// A theme for when the code isn't highlighted.
+    static ref PLAIN_THEME: Theme = Theme::default();
+}
 
-    if let EmitPascalMode::NamedModule(mref, is_definition) = &mode {
-        mref.render_inline(&mut pretty);
-        pretty.space();
-        pretty.noscope_push(if *is_definition { "=" } else { "+=" });
-        pretty.noscope_push(" ⟦");
-        pretty.indent_block();
-        pretty.newline_needed();
-    }
+/// How many fragments of code to gather up before processing them. Processing
+/// them in batches keeps the memory use bounded, while still giving plenty of
+/// work to do in parallel.
+const PENDING_BATCH_SIZE: usize = 1024;
 
-    let mut first = true;
+/// A fragment of code whose parsing and prettifying has been put off. Once a
+/// batch of fragments has been scanned, they're all processed in parallel,
+/// since they're independent of each other.
+#[derive(Debug)]
+struct PendingCode<'a> {
+    /// The module in which the code appears.
+    module: ModuleId,
 
-    for tl in &code.0 {
-        if first {
-            first = false;
-        } else {
-            pretty.toplevel_separator();
-        }
+    /// The offset in the output text at which the code is to be emitted.
+    offset: usize,
 
-        tl.prettify(&mut pretty);
-    }
+    mode: EmitPascalMode<'a>,
 
-    if let EmitPascalMode::NamedModule(..) = &mode {
-        pretty.dedent_block();
-        pretty.newline_needed();
-        pretty.noscope_push("⟧");
-    }
+    /// The pragmas in effect for the code.
+    pragmas: Pragmas,
 
-    // Emit with highlighting.
+    kind: PendingKind<'a>,
 
-    let ts = ThemeSet::load_defaults();
-    let theme = &ts.themes["InspiredGitHub"];
-    pretty.emit(theme, mode.is_inline());
+    /// If the code is a macro definition with a numeric value worth showing,
+    /// the value.
+    define_value: Option<isize>,
 }
 
-/// WEAVE:222
-fn handle_tex<'a>(
-    state: &State,
-    output: &mut OutputState,
-    mut span: Span<'a>,
-) -> ParseResult<'a, Token> {
-    let mut tok;
+#[derive(Debug)]
+enum PendingKind<'a> {
+    /// Pascal tokens, along with the positions of the pragmas that were removed
+    /// from them.
+    Pascal(WebSyntax<'a>, Vec<Range<usize>>),
 
-    (span, tok) = copy_tex(output, span)?;
+    /// C code, which has already been parsed.
+    C(CCode<'a>),
+}
 
-    loop {
-        match tok {
-            Token::Control(ControlKind::NewMajorModule)
-            | Token::Control(ControlKind::NewMinorModule)
-            | Token::Control(ControlKind::StartUnnamedPascal)
-            | Token::Control(ControlKind::ModuleName)
-            | Token::Control(ControlKind::MacroDefinition)
-            | Token::Control(ControlKind::FormatDefinition) => {
-                return Ok((span, tok));
-            }
+/// A fragment of code that has been processed.
+struct ProcessedCode<'c> {
+    /// If the code is Pascal, its parse, and the problems encountered.
+    parsed: Option<(WebCode<'c>, Vec<WebParseError>)>,
 
-            Token::Char('|') => {
-                let mut ptoks;
-                (span, (ptoks, _)) = scan_pascal_only(span, state)?;
-                let wrapped = ptoks.drain(..).map(|t| WebToken::Pascal(t)).collect();
-                emit_pascal(WebSyntax(wrapped), EmitPascalMode::Inline);
-                (span, tok) = copy_tex(output, span)?;
-            }
+    /// The prettified code, if it is to be emitted.
+    pretty: Option<Prettified>,
 
-            Token::Char(c) => {
-                output.printc(c);
-                (span, tok) = copy_tex(output, span)?;
-            }
+    /// If we're caching code, the key for the prettified code, and whether
+    /// it was found in the cache. Without the `serde` feature there's no
+    /// cache, so this is always `None`.
+    #[cfg_attr(not(feature = "serde"), allow(dead_code))]
+    cache_key: Option<(u64, bool)>,
 
-            Token::Control(ControlKind::AtLiteral) => {
-                output.printc('@');
-                (span, tok) = copy_tex(output, span)?;
-            }
+    /// The time spent processing the code.
+    times: PhaseTimes,
+}
 
-            //WEAVE:223. See also stringification of PascalToken IntLiteral values.
-            Token::Control(ControlKind::OctalLiteral) => {
-                let value;
-                (span, value) = crate::pascal_token::scan_octal_literal(span)?;
-                output.prints(format!("\\WebOctalLiteralHexed{{{:X}}}", value));
-                (span, tok) = copy_tex(output, span)?;
-            }
+/// A problem parsing a fragment of Pascal code. A single fragment may have
+/// several problems.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CheckFailure {
+    /// The module in which the code appears.
+    pub module: ModuleId,
 
-            //WEAVE:223
-            Token::Control(ControlKind::HexLiteral) => {
-                let value;
-                (span, value) = crate::pascal_token::scan_hex_literal(span)?;
-                output.prints(format!("\\WebHexLiteral{{{:X}}}", value));
-                (span, tok) = copy_tex(output, span)?;
-            }
+    /// The context in which the code appears: `named`, `anonymous`, `define`,
+    /// `format`, or `inline`.
+    pub context: &'static str,
 
-            Token::Control(ControlKind::RomanIndexEntry)
-            | Token::Control(ControlKind::TypewriterIndexEntry)
-            | Token::Control(ControlKind::WildcardIndexEntry) => {
-                output.see_phantom();
-                (span, tok) = state.scan_next(span)?;
-            }
+    /// If the code is part of a named module, the module's name.
+    pub name: Option<String>,
 
-            Token::Control(ControlKind::DefinitionFlag) => {
-                (span, tok) = copy_tex(output, span)?;
-            }
+    /// What went wrong.
+    pub error: WebParseError,
+}
 
-            other => {
-                eprintln!("** unhandled TeX control code in pass2: {:?}", other);
-                return new_parse_error(span, ErrorKind::Char);
-            }
-        }
+impl CheckFailure {
+    /// Render a diagnostic describing the failure, quoting the offending code.
+    ///
+    /// The `text` must be the WEB source text that was checked, and `path` is
+    /// used to name it.
+    pub fn render(&self, text: &str, path: Option<&str>) -> String {
+        let message = match self.name.as_ref() {
+            Some(name) => format!(
+                "module {} (named module <{}>): code could not be parsed",
+                self.module, name
+            ),
+            None => format!(
+                "module {} ({} code): code could not be parsed",
+                self.module, self.context
+            ),
+        };
+
+        diagnostics::render(
+            text,
+            path,
+            self.error.span.start..self.error.span.end,
+            &message,
+            &self.error.to_string(),
+        )
     }
 }
 
-/// WEAVE:225-228.
+/// A fragment of code, prettified as HTML.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct HtmlFragment {
+    /// The module in which the code appears.
+    pub module: ModuleId,
+
+    /// The context in which the code appears, as in [`CheckFailure`].
+    pub context: &'static str,
+
+    /// If the code is part of a named module, the module's name.
+    pub name: Option<String>,
+
+    /// The HTML markup of the code.
+    pub html: String,
+}
+
+/// A fragment of Pascal code, lexed but not parsed.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct LexedFragment<'a> {
+    /// The module in which the code appears.
+    pub module: ModuleId,
+
+    /// The context in which the code appears, as in [`CheckFailure`].
+    pub context: &'static str,
+
+    /// If the code is part of a named module, a reference to the module.
+    pub name: Option<WebModuleReference<'a>>,
+
+    /// The tokens of the code.
+    pub tokens: Vec<WebToken<'a>>,
+
+    /// The range of byte offsets in the input text occupied by each token.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub positions: Vec<Range<usize>>,
+}
+
+/// The Pascal code of a module, lexed but not parsed.
+#[derive(Clone, Debug)]
+pub struct LexedModule<'a> {
+    /// The module.
+    pub module: ModuleId,
+
+    /// The fragments of code in the module, in order.
+    pub fragments: Vec<LexedFragment<'a>>,
+}
+
+/// A search for the occurrences of an identifier in the Pascal code.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum IdentifierQuery {
+    /// Look for the places where the identifier is defined.
+    Definitions(String),
+
+    /// Look for the places where the identifier is used.
+    Uses(String),
+}
+
+/// The location of an occurrence of an identifier.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+pub struct IdentifierLocation {
+    /// The module in which the identifier appears.
+    pub module: ModuleId,
+
+    /// The line in the input at which the identifier appears, starting at 1.
+    pub line: u32,
+
+    /// The column in the input at which the identifier appears, in
+    /// characters, starting at 1.
+    pub column: usize,
+}
+
+/// An entry in a dump of the parsed Pascal code.
+#[cfg(feature = "serde")]
+#[derive(Serialize)]
+struct DumpEntry<'c, 'a> {
+    module: ModuleId,
+    context: &'static str,
+    name: Option<&'c WebModuleReference<'a>>,
+    code: &'c WebCode<'a>,
+}
+
+/// Formatting settings that can be adjusted with pragmas in the source.
+///
+/// A pragma is a comment like `{tt-weave: width=80, verbatim}`. It applies to
+/// the fragment of code in which it appears and the rest of the module. The
+/// comment itself isn't emitted. The directives are `width=N`, `verbatim`, and
+/// `comments=code` or `comments=prose`.
+#[derive(Clone, Copy, Debug, Default)]
+struct Pragmas {
+    /// If set, lay out the code to this width.
+    width: Option<usize>,
+
+    /// If true, emit the code as it appears in the source, rather than
+    /// prettifying it. This is an escape hatch for when the prettifier mangles
+    /// some code.
+    verbatim: bool,
+
+    /// If set, typeset the comments in this style.
+    comments: Option<CommentStyle>,
+}
+
+impl Pragmas {
+    /// If a comment is a pragma, get the text of its directives.
+    fn directives<'c>(comment: &'c [TypesetComment]) -> Option<&'c str> {
+        match comment {
+            [TypesetComment::Tex(text)] => text.trim().strip_prefix("tt-weave:"),
+            _ => None,
+        }
+    }
+
+    /// Apply the directives of a pragma.
+    fn apply(&mut self, directives: &str, module: ModuleId) {
+        for d in directives
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|d| !d.is_empty())
+        {
+            match d.split_once('=') {
+                None if d == "verbatim" => self.verbatim = true,
+
+                Some(("width", w)) => match w.parse() {
+                    Ok(w) => self.width = Some(w),
+                    Err(_) => warn!("ignoring invalid width `{}` in module {}", w, module),
+                },
+
+                Some(("comments", c)) => match CommentStyle::from_str(c, false) {
+                    Ok(c) => self.comments = Some(c),
+                    Err(_) => warn!(
+                        "ignoring invalid comment style `{}` in module {}",
+                        c, module
+                    ),
+                },
+
+                _ => warn!("ignoring unrecognized pragma `{}` in module {}", d, module),
+            }
+        }
+    }
+}
+
+/// Get the source text of some code, leaving out the text in `skip`.
+fn verbatim_source(source: &str, positions: &[Range<usize>], skip: &[Range<usize>]) -> String {
+    let (start, end) = match (positions.first(), positions.last()) {
+        (Some(first), Some(last)) => (first.start, last.end),
+        _ => return String::new(),
+    };
+
+    let mut text = String::new();
+    let mut pos = start;
+
+    for r in skip {
+        if r.start >= pos && r.end <= end {
+            text.push_str(&source[pos..r.start]);
+            pos = r.end;
+        }
+    }
+
+    text.push_str(&source[pos..end]);
+    text
+}
+
+/// Get whether each token of some code starts a new line in the source.
+fn source_line_breaks(source: &str, positions: &[Range<usize>]) -> Vec<bool> {
+    let mut breaks = Vec::with_capacity(positions.len());
+    let mut prev_end = None;
+
+    for r in positions {
+        breaks.push(
+            prev_end
+                .and_then(|e| source.get(e..r.start))
+                .is_some_and(|t| t.contains('\n')),
+        );
+        prev_end = Some(r.end);
+    }
+
+    breaks
+}
+
+fn emit_pascal<'a>(
+    output: &mut OutputState<'a>,
+    mut syntax: WebSyntax<'a>,
+    mode: EmitPascalMode<'a>,
+) {
+    // Macros are evaluated even in modules that are skipped, since the values
+    // of later ones might depend on them.
+    let define_value = match mode {
+        EmitPascalMode::Define => output.macro_values.define(&syntax.tokens),
+        _ => None,
+    };
+
+    if output.skipping {
+        if let Some(labels) = output.labels.as_mut() {
+            labels.skip();
+        }
+
+        if let Some(symbols) = output.symbols.as_mut() {
+            symbols.skip();
+        }
+
+        if let Some(reachability) = output.reachability.as_mut() {
+            reachability.skip();
+        }
+
+        if let Some(strict) = output.strict.as_mut() {
+            strict.skip();
+        }
+
+        if let Some(directives) = output.directives.as_mut() {
+            directives.skip();
+        }
+
+        return;
+    }
+
+    syntax.module = output.module;
+
+    // If we're just lexing, the tokens are wanted as they are in the source.
+
+    if output.lex_only {
+        output.defer(mode, PendingKind::Pascal(syntax, Vec::new()), define_value);
+        return;
+    }
+
+    // Handle any pragmas, which aren't part of the code proper.
+
+    let mut pragma_positions = Vec::new();
+    let mut i = 0;
+
+    while i < syntax.tokens.len() {
+        if let WebToken::Comment(c) = &syntax.tokens[i] {
+            if let Some(d) = Pragmas::directives(c) {
+                output.pragmas.apply(d, output.module);
+                syntax.tokens.remove(i);
+                pragma_positions.push(syntax.positions.remove(i));
+                continue;
+            }
+        }
+
+        i += 1;
+    }
+
+    // A fragment that only held pragmas has nothing to show, unless it's
+    // defining a named module, whose header is still wanted.
+    if syntax.tokens.is_empty()
+        && !pragma_positions.is_empty()
+        && !matches!(mode, EmitPascalMode::NamedModule(..))
+    {
+        return;
+    }
+
+    output.defer(
+        mode,
+        PendingKind::Pascal(syntax, pragma_positions),
+        define_value,
+    );
+}
+
+/// Parse and prettify a fragment of code whose processing was put off. This
+/// only looks at `output`, so that it can be done for all of the fragments in
+/// parallel. The parsed code is allocated in `arena`.
+fn process_pending<'c>(
+    output: &OutputState,
+    pending: &'c PendingCode<'c>,
+    arena: &'c AstArena<'c>,
+) -> ProcessedCode<'c> {
+    let mode = &pending.mode;
+    let width = pending.pragmas.width.unwrap_or(output.width_for(mode));
+    let mut timer = PhaseTimer::start(output.timings.is_some());
+
+    let (syntax, pragma_positions) = match &pending.kind {
+        PendingKind::Pascal(syntax, pragma_positions) => (syntax, pragma_positions),
+
+        PendingKind::C(code) => {
+            let inline = mode.is_inline();
+            let prefix = match mode {
+                EmitPascalMode::Define => Some("#define"),
+                EmitPascalMode::Format => Some("format"),
+                _ => None,
+            };
+
+            let (pretty, cache_key) =
+                prettify_cached(output, pending, width, &mut timer, code, |pretty| {
+                    if let Some(p) = prefix {
+                        pretty.keyword(p);
+                        pretty.space();
+                    }
+
+                    code.prettify(inline, pretty);
+                });
+
+            return ProcessedCode {
+                parsed: None,
+                pretty: Some(pretty),
+                cache_key,
+                times: timer.times,
+            };
+        }
+    };
+
+    // parse into the AST
+
+    let (mut code, errors) = WebCode::parse_with_recovery(syntax, arena, &output.toplevel_parsers);
+    timer.parsed();
+
+    if let Some(value) = pending.define_value {
+        for tl in &mut code.0 {
+            if let WebToplevel::Define(define) = &mut tl.node {
+                define.set_value(value);
+            }
+        }
+    }
+
+    // Code that's only being analyzed doesn't need to be prettified.
+
+    let (pretty, cache_key) = if output.check_failures.is_some()
+        || output.query.is_some()
+        || output.dump.is_some()
+        || output.table_only
+    {
+        (None, None)
+    } else if pending.pragmas.verbatim {
+        let text = verbatim_source(output.source, &syntax.positions, pragma_positions);
+
+        let (pretty, cache_key) =
+            prettify_cached(output, pending, width, &mut timer, &text, |pretty| {
+                for (i, line) in text.lines().enumerate() {
+                    if i > 0 {
+                        pretty.newline_indent();
+                    }
+
+                    pretty.noscope_push(line.trim_end());
+                }
+            });
+
+        (Some(pretty), cache_key)
+    } else {
+        // Unparseable code is laid out following the source line breaks,
+        // so they're part of the cache key too.
+        let line_breaks = source_line_breaks(output.source, &syntax.positions);
+        let pragmas_only = syntax.tokens.is_empty() && !pragma_positions.is_empty();
+        let content = (&syntax.tokens, &line_breaks, pragmas_only);
+
+        let (pretty, cache_key) =
+            prettify_cached(output, pending, width, &mut timer, &content, |pretty| {
+                // A named module defined only by pragmas just gets its header.
+                if pragmas_only {
+                    return;
+                }
+
+                let mut first = true;
+                let mut nesting = NestingTracker::default();
+
+                for (i, tl) in code.0.iter().enumerate() {
+                    nesting.before(tl.as_preprocessor_directive(), pretty);
+
+                    // A statement is the last of its run unless another
+                    // statement follows it.
+                    let is_last = !matches!(
+                        code.0.get(i + 1).map(|next| &next.node),
+                        Some(WebToplevel::Statement(..))
+                    );
+
+                    if first {
+                        first = false;
+                    } else {
+                        pretty.toplevel_separator();
+                    }
+
+                    // Tokens that couldn't be parsed are laid out as in the source.
+                    let first_token = syntax
+                        .positions
+                        .partition_point(|p| p.start < tl.span.start);
+                    tl.node.prettify_with_line_breaks(
+                        &line_breaks[first_token.min(line_breaks.len())..],
+                        is_last,
+                        pretty,
+                    );
+                    nesting.after(tl.as_preprocessor_directive(), pretty);
+                }
+
+                nesting.finish(pretty);
+            });
+
+        (Some(pretty), cache_key)
+    };
+
+    ProcessedCode {
+        parsed: Some((code, errors)),
+        pretty,
+        cache_key,
+        times: timer.times,
+    }
+}
+
+/// Finish handling a fragment of code once it's been processed, emitting it
+/// and keeping track of what it does.
+fn emit_processed<'c>(
+    output: &mut OutputState,
+    pending: &'c PendingCode<'c>,
+    processed: ProcessedCode<'c>,
+) {
+    output.module = pending.module;
+    let mode = &pending.mode;
+
+    let (syntax, code, errors) = match (&pending.kind, processed.parsed) {
+        (PendingKind::Pascal(syntax, _), Some((code, errors))) => (syntax, code, errors),
+
+        _ => {
+            if let Some(pretty) = processed.pretty {
+                emit_prettified(output, mode, pretty);
+            }
+
+            return;
+        }
+    };
+
+    if let EmitPascalMode::Define = mode {
+        check_macro_parameter(output, syntax);
+    }
+
+    if let Some(comments) = output.comments.as_mut() {
+        for (tok, range) in syntax.tokens.iter().zip(&syntax.positions) {
+            if let WebToken::Comment(_) = tok {
+                comments.push(CommentText {
+                    module: output.module,
+                    context: mode.context(),
+                    range: range.clone(),
+                    text: comment_text(&output.source[range.clone()]),
+                });
+            }
+        }
+    }
+
+    if !mode.is_inline() {
+        for name in style::undocumented_constants(&code) {
+            output.warn(
+                Lint::UndocumentedConstant,
+                format!("constant `{}` has no comment", name.value),
+                Some((
+                    name.start.location_offset()..name.end.location_offset(),
+                    "declared without a comment".to_owned(),
+                )),
+            );
+        }
+    }
+
+    let failures: Vec<_> = errors
+        .into_iter()
+        .map(|error| CheckFailure {
+            module: output.module,
+            context: mode.context(),
+            name: match mode {
+                EmitPascalMode::NamedModule(mref, _) => Some(mref.name.value.to_string()),
+                _ => None,
+            },
+            error,
+        })
+        .collect();
+
+    if let Some(all_failures) = output.check_failures.as_mut() {
+        all_failures.extend(failures);
+        return;
+    }
+
+    let module = output.module;
+
+    if let Some((query, found)) = output.query.as_mut() {
+        if !failures.is_empty() {
+            warn!(
+                "some code in module {} could not be parsed, so results may be incomplete",
+                module
+            );
+        }
+
+        let spans = match query {
+            IdentifierQuery::Definitions(name) => code.find_definitions(name),
+            IdentifierQuery::Uses(name) => code.find_uses(name),
+        };
+
+        found.extend(spans.into_iter().map(|s| IdentifierLocation {
+            module,
+            line: s.start.location_line(),
+            column: s.start.get_utf8_column(),
+        }));
+
+        return;
+    }
+
+    // Keep track of what the code does, for checks that can only be done once
+    // all of the code has been seen.
+
+    let macro_name = match (mode, syntax.tokens.get(1)) {
+        (
+            EmitPascalMode::Define,
+            Some(WebToken::Pascal(
+                PascalToken::Identifier(name) | PascalToken::FormattedIdentifier(name, _),
+            )),
+        ) => Some(name),
+        _ => None,
+    };
+
+    if let Some(labels) = output.labels.as_mut() {
+        let context = match mode {
+            EmitPascalMode::Define => {
+                macro_name.map(|name| LabelContext::Macro(name.value.as_ref()))
+            }
+            EmitPascalMode::Anonymous => Some(LabelContext::Anonymous),
+            EmitPascalMode::NamedModule(mref, _) => Some(LabelContext::Named(mref.id)),
+            EmitPascalMode::Inline | EmitPascalMode::Format => None,
+        };
+
+        if let Some(context) = context {
+            labels.add(&code, module, context, failures.is_empty());
+        }
+    }
+
+    if !matches!(mode, EmitPascalMode::Inline | EmitPascalMode::Format) {
+        if let Some(symbols) = output.symbols.as_mut() {
+            symbols.add(&code, module, macro_name);
+        }
+
+        if let Some(table) = output.symbol_table.as_mut() {
+            table.add(&code, module);
+        }
+    }
+
+    if let Some(xref) = output.xref.as_mut() {
+        if let EmitPascalMode::NamedModule(mref, _) = mode {
+            xref.add_module_reference(mref, module, true);
+        }
+
+        xref.add(&code, module, mode.context());
+
+        if let (Some(name), Some(first), Some(last)) = (
+            macro_name,
+            syntax.positions.first(),
+            syntax.positions.last(),
+        ) {
+            let parameterized = matches!(
+                syntax.tokens.get(2),
+                Some(WebToken::Pascal(PascalToken::OpenDelimiter(
+                    DelimiterKind::Paren
+                )))
+            );
+            xref.add_define(name, module, parameterized, first.start..last.end);
+        }
+    }
+
+    if let Some(call_graph) = output.call_graph.as_mut() {
+        match mode {
+            EmitPascalMode::Define => {
+                if let Some(name) = macro_name {
+                    call_graph.add(&code, None, Some(name));
+                }
+            }
+            EmitPascalMode::Anonymous => call_graph.add(&code, None, None),
+            EmitPascalMode::NamedModule(mref, _) => call_graph.add(&code, Some(mref.id), None),
+            EmitPascalMode::Inline | EmitPascalMode::Format => {}
+        }
+    }
+
+    if let Some(metrics) = output.metrics.as_mut() {
+        match mode {
+            EmitPascalMode::Define
+            | EmitPascalMode::Anonymous
+            | EmitPascalMode::NamedModule(..) => metrics.add(output.module, &code),
+            EmitPascalMode::Inline | EmitPascalMode::Format => {}
+        }
+    }
+
+    if let Some(reachability) = output.reachability.as_mut() {
+        match mode {
+            EmitPascalMode::Define | EmitPascalMode::Anonymous => {
+                reachability.add(&syntax.tokens, None)
+            }
+            EmitPascalMode::NamedModule(mref, _) => reachability.add(&syntax.tokens, Some(mref)),
+            EmitPascalMode::Inline | EmitPascalMode::Format => {}
+        }
+    }
+
+    if let Some(strict) = output.strict.as_mut() {
+        match mode {
+            EmitPascalMode::Define => {
+                if let Some(name) = macro_name {
+                    strict.add_macro(name);
+                }
+            }
+            EmitPascalMode::Anonymous | EmitPascalMode::NamedModule(..) => {
+                strict.add(&code, module)
+            }
+            EmitPascalMode::Inline | EmitPascalMode::Format => {}
+        }
+    }
+
+    if let Some(directives) = output.directives.as_mut() {
+        if !matches!(mode, EmitPascalMode::Inline | EmitPascalMode::Format) {
+            directives.add(&code, module);
+        }
+    }
+
+    if matches!(
+        mode,
+        EmitPascalMode::Anonymous | EmitPascalMode::NamedModule(..)
+    ) {
+        for u in find_unreachable(&code, &output.macros) {
+            output.warn(
+                Lint::UnreachableCode,
+                format!("this code follows `{}`, so it can never run", u.jump),
+                Some((u.range, "unreachable".to_owned())),
+            );
+        }
+    }
+
+    if pending.pragmas.verbatim && output.dump.is_none() {
+        if let Some(pretty) = processed.pretty {
+            emit_prettified(output, mode, pretty);
+        }
+
+        return;
+    }
+
+    // The unparseable bits of the code are emitted as-is, but we should let
+    // the user know about them, unless they're being collected.
+
+    if let Some(parse_failures) = output.parse_failures.as_mut() {
+        parse_failures.extend(failures);
+    } else {
+        for f in &failures {
+            error!("{}", f.render(output.source, None));
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    if let Some(dump) = output.dump.as_mut() {
+        let entry = DumpEntry {
+            module,
+            context: mode.context(),
+            name: match mode {
+                EmitPascalMode::NamedModule(mref, _) => Some(mref),
+                _ => None,
+            },
+            code: &code,
+        };
+        let value = serde_json::to_value(entry).expect("AST serialization cannot fail");
+        dump.push(value);
+        return;
+    }
+
+    // Emit
+
+    let width = pending.pragmas.width.unwrap_or(output.width_for(mode));
+    let inline = mode.is_inline();
+
+    let pretty = match processed.pretty {
+        Some(p) => p,
+        None => return,
+    };
+
+    let widest = pretty.widest;
+    emit_prettified(output, mode, pretty);
+
+    if widest > width && !inline {
+        let location = match (syntax.positions.first(), syntax.positions.last()) {
+            (Some(first), Some(last)) => Some((
+                first.start..last.end,
+                format!("this code can't be laid out within {} characters", width),
+            )),
+            _ => None,
+        };
+
+        output.warn(
+            Lint::OverlongLine,
+            format!(
+                "a line of code is {} characters wide, but the limit is {}",
+                widest, width
+            ),
+            location,
+        );
+    }
+}
+
+/// Warn if a macro definition takes a parameter but never uses it, which
+/// usually means that the parameter was forgotten.
+fn check_macro_parameter(output: &mut OutputState, syntax: &WebSyntax) {
+    let toks = &syntax.tokens;
+
+    // The tokens are the synthesized `@d`, the macro name, and then `(#)` if
+    // the macro takes a parameter.
+    let name = match (toks.get(1), toks.get(2..5)) {
+        (
+            Some(WebToken::Pascal(PascalToken::Identifier(name))),
+            Some(
+                [WebToken::Pascal(PascalToken::OpenDelimiter(DelimiterKind::Paren)), WebToken::Pascal(PascalToken::Hash(_)), WebToken::Pascal(PascalToken::CloseDelimiter(DelimiterKind::Paren))],
+            ),
+        ) => name,
+        _ => return,
+    };
+
+    let uses_param = toks[5..]
+        .iter()
+        .any(|t| matches!(t, WebToken::Pascal(PascalToken::Hash(_))));
+
+    if !uses_param {
+        let location = (
+            syntax.positions[2].start..syntax.positions[4].end,
+            "the parameter is declared here".to_owned(),
+        );
+
+        output.warn(
+            Lint::SuspiciousMacro,
+            format!("macro `{}` never uses its parameter", name.value),
+            Some(location),
+        );
+    }
+}
+
+/// Warn if the name of a module is too long.
+fn check_module_name(output: &mut OutputState, mref: &WebModuleReference) {
+    let max = output.style_limits.max_module_name_length;
+    let len = mref.name.value.chars().count();
+
+    if len > max {
+        output.warn(
+            Lint::LongModuleName,
+            format!(
+                "the name of this module is {} characters long, but the limit is {}",
+                len, max
+            ),
+            Some((
+                mref.name.start.location_offset()..mref.name.end.location_offset(),
+                "named here".to_owned(),
+            )),
+        );
+    }
+}
+
+/// The CWEB analogue of `emit_pascal`.
+fn emit_c<'a>(output: &mut OutputState<'a>, code: CCode<'a>, mode: EmitPascalMode<'a>) {
+    if output.skipping {
+        return;
+    }
+
+    output.defer(mode, PendingKind::C(code), None);
+}
+
+/// Code that has been prettified and highlighted, ready to be emitted.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct Prettified {
+    /// The highlighted code, as TeX or HTML, depending on the output.
+    markup: String,
+
+    /// The width of the widest line of the laid-out code, in characters.
+    widest: usize,
+
+    /// The number of lines of the laid-out code.
+    lines: usize,
+}
+
+/// Prettify some code and highlight it.
+///
+/// The function `body` should lay out the code itself. This function handles
+/// the decorations needed for named modules.
+fn prettify_code<F: FnOnce(&mut Prettifier)>(
+    output: &OutputState,
+    pending: &PendingCode,
+    width: usize,
+    timer: &mut PhaseTimer,
+    body: F,
+) -> Prettified {
+    let mode = &pending.mode;
+    let mut style = output.style.clone();
+
+    if let Some(c) = pending.pragmas.comments {
+        style.comments = c;
+    }
+
+    // WEAVE's `\C` can't take the prose as it's marked up for our macros, and
+    // its macros are meant for Pascal as it's written.
+    if output.webmac {
+        style.comments = CommentStyle::Code;
+        style.preserve_operators = true;
+        style.preserve_keywords = true;
+        style.preserve_octal = true;
+    }
+
+    let mut pretty = Prettifier::with_width(width);
+    pretty.set_identifier_tex(output.identifier_tex.clone());
+    pretty.set_style(style);
+    pretty.set_max_depth(output.max_depth);
+    pretty.set_function_uses(output.function_uses.clone());
+    pretty.set_identifier_anchors(output.anchors.clone());
+    pretty.set_macros(output.macros.clone());
+
+    match mode {
+        // WEAVE doesn't bracket the code of a named module.
+        EmitPascalMode::NamedModule(mref, is_definition) if output.webmac => {
+            mref.render_inline(&mut pretty);
+            pretty.space();
+            let (tex, op) = if *is_definition {
+                ("${}\\E{}$", "=")
+            } else {
+                ("${}\\mathrel+\\E{}$", "+=")
+            };
+            pretty.insert(TexInsert::Replacement(tex.to_owned(), op.len()), true);
+            pretty.noscope_push(op);
+            pretty.newline_needed();
+        }
+
+        EmitPascalMode::NamedModule(mref, is_definition) => {
+            mref.render_inline(&mut pretty);
+            pretty.space();
+            pretty.noscope_push(if *is_definition { "=" } else { "+=" });
+            pretty.noscope_push(" ⟦");
+            pretty.indent_block();
+            pretty.newline_needed();
+        }
+
+        _ => {}
+    }
+
+    body(&mut pretty);
+
+    if let EmitPascalMode::NamedModule(..) = mode {
+        if !output.webmac {
+            pretty.dedent_block();
+            pretty.newline_needed();
+            pretty.noscope_push("⟧");
+        }
+    }
+
+    timer.prettified();
+
+    // Highlight.
+
+    let widest = pretty.widest_line();
+    let lines = pretty.line_count();
+    let mut markup = String::new();
+
+    // Inline code that takes too many lines is displayed instead.
+    let inline = mode.is_inline()
+        && output
+            .inline_max_lines
+            .is_none_or(|n| pretty.line_count() <= n);
+
+    if let (Some(_), Some(dark)) = (output.html.as_ref(), output.dark_theme) {
+        pretty.emit_html_dual(output.theme, dark, inline, &mut markup)
+    } else if output.html.is_some() {
+        pretty.emit_html(output.theme, inline, &mut markup)
+    } else if output.webmac {
+        pretty.emit_webmac(inline, &mut markup)
+    } else {
+        pretty.emit(output.theme, inline, &mut markup)
+    }
+    .expect("writing to a String cannot fail");
+
+    timer.emitted();
+    Prettified {
+        markup,
+        widest,
+        lines,
+    }
+}
+
+/// Prettify some code and highlight it, as with [`prettify_code`], unless it's
+/// in the cache. The cache key is made from `content`, which must capture
+/// everything about the code itself that affects its layout, along with the
+/// settings that apply to `pending`.
+#[cfg(feature = "serde")]
+fn prettify_cached<T: Serialize + ?Sized, F: FnOnce(&mut Prettifier)>(
+    output: &OutputState,
+    pending: &PendingCode,
+    width: usize,
+    timer: &mut PhaseTimer,
+    content: &T,
+    body: F,
+) -> (Prettified, Option<(u64, bool)>) {
+    let cache = match output.cache {
+        Some(c) => c,
+        None => return (prettify_code(output, pending, width, timer, body), None),
+    };
+
+    let key = cache_key(output, pending, width, content);
+
+    if let Some(pretty) = cache.get(key) {
+        timer.prettified();
+        return (pretty.clone(), Some((key, true)));
+    }
+
+    let pretty = prettify_code(output, pending, width, timer, body);
+    (pretty, Some((key, false)))
+}
+
+/// Without the `serde` feature there's no cache, so the code is always
+/// prettified.
+#[cfg(not(feature = "serde"))]
+fn prettify_cached<T: ?Sized, F: FnOnce(&mut Prettifier)>(
+    output: &OutputState,
+    pending: &PendingCode,
+    width: usize,
+    timer: &mut PhaseTimer,
+    _content: &T,
+    body: F,
+) -> (Prettified, Option<(u64, bool)>) {
+    (prettify_code(output, pending, width, timer, body), None)
+}
+
+/// Hash everything that affects the layout of a fragment of code, except for
+/// where it is in the source, so that code that hasn't changed can be found in
+/// the cache even if the code before it has.
+#[cfg(feature = "serde")]
+fn cache_key<T: Serialize + ?Sized>(
+    output: &OutputState,
+    pending: &PendingCode,
+    width: usize,
+    content: &T,
+) -> u64 {
+    let mut hasher = KeyHasher::default();
+    output.cache_salt.hash(&mut hasher);
+    output.html.is_some().hash(&mut hasher);
+    output.webmac.hash(&mut hasher);
+    width.hash(&mut hasher);
+    pending.pragmas.verbatim.hash(&mut hasher);
+    pending.pragmas.comments.hash(&mut hasher);
+    pending.mode.context().hash(&mut hasher);
+    pending.define_value.hash(&mut hasher);
+
+    if let EmitPascalMode::NamedModule(mref, is_definition) = &pending.mode {
+        mref.id.hash(&mut hasher);
+        mref.name.value.hash(&mut hasher);
+        is_definition.hash(&mut hasher);
+    }
+
+    let json = serde_json::to_string(content).expect("code serialization cannot fail");
+    hash_without_positions(&json, &mut hasher);
+    hasher.finish()
+}
+
+/// Hash the JSON serialization of some code, leaving out the line and column
+/// numbers of the [`SpanValue`]s in it. These are always serialized as
+/// `,"line":L,"column":C`, which can't appear inside a JSON string, where the
+/// quotes would be escaped.
+#[cfg(feature = "serde")]
+fn hash_without_positions<H: Hasher>(json: &str, hasher: &mut H) {
+    const MARKER: &str = ",\"line\":";
+    let mut rest = json;
+
+    while let Some(i) = rest.find(MARKER) {
+        hasher.write(&rest.as_bytes()[..i]);
+        rest = &rest[i..];
+
+        // Skip the line number, then the column number.
+        for _ in 0..2 {
+            rest = rest[1..].trim_start_matches(|c: char| c != ':');
+            rest = rest[1..].trim_start_matches(|c: char| c.is_ascii_digit());
+        }
+    }
+
+    hasher.write(rest.as_bytes());
+}
+
+/// Emit some prettified code into the output.
+fn emit_prettified(output: &mut OutputState, mode: &EmitPascalMode, pretty: Prettified) {
+    if let (Some(code_length), false) = (output.code_length.as_mut(), mode.is_inline()) {
+        code_length.add(output.module, pretty.lines);
+    }
+
+    if let Some(fragments) = output.html.as_mut() {
+        let name = match mode {
+            EmitPascalMode::NamedModule(mref, _) => Some(mref.name.value.to_string()),
+            _ => None,
+        };
+
+        fragments.push(HtmlFragment {
+            module: output.module,
+            context: mode.context(),
+            name,
+            html: pretty.markup,
+        });
+    } else if let Some(fragments) = output.fragments.as_mut() {
+        fragments.push(pretty.markup);
+    } else {
+        output.text.push_str(&pretty.markup);
+    }
+}
+
+/// Process all of the code whose processing was put off, in parallel if
+/// possible, and then emit it in order, splicing it into the output text at
+/// the places where it appears.
+fn finish_pending(output: &mut OutputState) {
+    let pending = std::mem::take(&mut output.pending);
+    let module = output.module;
+
+    // Only the text after the first of the code needs to be rebuilt.
+    let base = match pending.first() {
+        Some(p) => p.offset,
+        None => return,
+    };
+
+    // Each fragment gets its own arena, since arenas can't be shared between
+    // threads.
+    let mut arenas: Vec<_> = pending
+        .iter()
+        .map(|_| AstArena::with_macros(output.macros.clone()))
+        .collect();
+
+    let processed: Vec<_> = {
+        let output = &*output;
+
+        #[cfg(feature = "parallel")]
+        {
+            pending
+                .par_iter()
+                .zip(arenas.par_iter_mut())
+                .map(|(p, arena)| process_pending(output, p, arena))
+                .collect()
+        }
+
+        #[cfg(not(feature = "parallel"))]
+        {
+            pending
+                .iter()
+                .zip(arenas.iter_mut())
+                .map(|(p, arena)| process_pending(output, p, arena))
+                .collect()
+        }
+    };
+
+    let text = output.text.split_off(base);
+    let mut copied = 0;
+    let mut emitted = Vec::with_capacity(pending.len());
+
+    for (p, processed) in pending.iter().zip(processed) {
+        output.text.push_str(&text[copied..p.offset - base]);
+        copied = p.offset - base;
+
+        let len = output.text.len();
+        let mut timer = PhaseTimer::start(output.timings.is_some());
+        let times = processed.times;
+
+        #[cfg(feature = "serde")]
+        if let (Some(cache), Some((key, hit)), Some(pretty)) = (
+            output.new_cache.as_mut(),
+            processed.cache_key,
+            processed.pretty.as_ref(),
+        ) {
+            cache.insert(key, pretty.clone());
+            output.cache_hits.0 += hit as usize;
+            output.cache_hits.1 += 1;
+        }
+
+        emit_processed(output, p, processed);
+        timer.emitted();
+        emitted.push((p.module, output.text.len() - len));
+
+        if let Some(timings) = output.timings.as_mut() {
+            let t = timings.module_mut(p.module);
+            *t += times;
+            *t += timer.times;
+        }
+    }
+
+    output.text.push_str(&text[copied..]);
+
+    // Each module starts after all of the code in the modules before it.
+
+    let mut shift = 0;
+    let mut emitted = emitted.into_iter().peekable();
+
+    for (m, start) in &mut output.module_starts {
+        while let Some((_, len)) = emitted.next_if(|(module, _)| module < m) {
+            shift += len;
+        }
+
+        *start += shift;
+    }
+
+    output.module = module;
+}
+
+/// WEAVE:222
+fn handle_tex<'a>(
+    state: &State,
+    output: &mut OutputState<'a>,
+    mut span: Span<'a>,
+) -> ParseResult<'a, Token> {
+    let mut tok;
+
+    (span, tok) = copy_tex(output, span)?;
+
+    loop {
+        match tok {
+            Token::Control(ControlKind::NewMajorModule)
+            | Token::Control(ControlKind::NewMinorModule)
+            | Token::Control(ControlKind::StartUnnamedPascal)
+            | Token::Control(ControlKind::ModuleName)
+            | Token::Control(ControlKind::MacroDefinition)
+            | Token::Control(ControlKind::FormatDefinition) => {
+                return Ok((span, tok));
+            }
+
+            Token::Char('|') if state.dialect().is_cweb() => {
+                let code;
+                (span, (code, _)) = scan_c(span, state, false)?;
+                emit_c(output, code, EmitPascalMode::Inline);
+                (span, tok) = copy_tex(output, span)?;
+            }
+
+            Token::Char('|') => {
+                let code;
+                (span, (code, _)) = scan_pascal_only(span, state)?;
+                emit_pascal(output, code, EmitPascalMode::Inline);
+                (span, tok) = copy_tex(output, span)?;
+            }
+
+            Token::Char(c) => {
+                output.printc(c);
+                (span, tok) = copy_tex(output, span)?;
+            }
+
+            Token::Control(ControlKind::AtLiteral) => {
+                output.printc('@');
+                (span, tok) = copy_tex(output, span)?;
+            }
+
+            //WEAVE:223. See also stringification of PascalToken IntLiteral values.
+            Token::Control(ControlKind::OctalLiteral) => {
+                let value;
+                (span, value) = crate::pascal_token::scan_octal_literal(span)?;
+                if output.webmac {
+                    output.prints(format!("\\O{{{:o}}}", value));
+                } else {
+                    output.prints(format!("\\WebOctalLiteralHexed{{{:X}}}", value));
+                }
+                (span, tok) = copy_tex(output, span)?;
+            }
+
+            //WEAVE:223
+            Token::Control(ControlKind::HexLiteral) => {
+                let value;
+                (span, value) = crate::pascal_token::scan_hex_literal(span)?;
+                if output.webmac {
+                    output.prints(format!("\\H{{{:X}}}", value));
+                } else {
+                    output.prints(format!("\\WebHexLiteral{{{:X}}}", value));
+                }
+                (span, tok) = copy_tex(output, span)?;
+            }
+
+            Token::Control(ControlKind::RomanIndexEntry)
+            | Token::Control(ControlKind::TypewriterIndexEntry)
+            | Token::Control(ControlKind::WildcardIndexEntry) => {
+                output.see_phantom();
+                (span, tok) = state.scan_next(span)?;
+            }
+
+            Token::Control(ControlKind::DefinitionFlag) => {
+                (span, tok) = copy_tex(output, span)?;
+            }
+
+            other => {
+                error!("unhandled TeX control code in pass2: {:?}", other);
+                return new_parse_error(span, ErrorKind::Char);
+            }
+        }
+    }
+}
+
+/// WEAVE:225-228.
 fn handle_definitions<'a>(
     state: &State,
+    output: &mut OutputState<'a>,
+    mut span: Span<'a>,
+    mut tok: Token,
+) -> ParseResult<'a, Token> {
+    if state.dialect().is_cweb() {
+        return handle_c_definitions(state, output, span, tok);
+    }
+
+    loop {
+        match tok {
+            Token::Control(ControlKind::NewMajorModule)
+            | Token::Control(ControlKind::NewMinorModule)
+            | Token::Control(ControlKind::StartUnnamedPascal)
+            | Token::Control(ControlKind::ModuleName) => {
+                return Ok((span, tok));
+            }
+
+            Token::Control(ControlKind::MacroDefinition) => {
+                // The synthesized reserved word stands in for the `@d` that
+                // we've already consumed.
+                let mut code = WebSyntax::default();
+                let end = span.location_offset();
+                code.push(
+                    WebToken::Pascal(PascalToken::ReservedWord(SpanValue {
+                        start: Span::new_extra("", span.extra),
+                        end: Span::new_extra("", span.extra),
+                        value: PascalReservedWord::Define,
+                    })),
+                    end - 2..end,
+                );
+
+                let mut rest;
+                (span, (rest, tok)) = scan_pascal(span, state)?;
+                code.append(&mut rest);
+                emit_pascal(output, code, EmitPascalMode::Define);
+            }
+
+            Token::Control(ControlKind::FormatDefinition) => {
+                // The synthesized reserved word stands in for the `@f` that
+                // we've already consumed.
+                let mut code = WebSyntax::default();
+                let end = span.location_offset();
+                code.push(
+                    WebToken::Pascal(PascalToken::ReservedWord(SpanValue {
+                        start: Span::new_extra("", span.extra),
+                        end: Span::new_extra("", span.extra),
+                        value: PascalReservedWord::Format,
+                    })),
+                    end - 2..end,
+                );
+                let (mut ptok, mut pos);
+
+                (span, (ptok, pos)) = match_positioned_pascal_token(span, state)?;
+                code.push(WebToken::Pascal(ptok.clone()), pos);
+
+                if let PascalToken::Identifier(_) = ptok {
+                    (span, (ptok, pos)) = match_positioned_pascal_token(span, state)?;
+                    code.push(WebToken::Pascal(ptok.clone()), pos);
+
+                    if let PascalToken::Equivalence = ptok {
+                        (span, (ptok, pos)) = match_positioned_pascal_token(span, state)?;
+                        code.push(WebToken::Pascal(ptok), pos);
+                    }
+                }
+
+                let mut rest;
+                (span, (rest, tok)) = scan_pascal(span, state)?;
+                code.append(&mut rest);
+                emit_pascal(output, code, EmitPascalMode::Format);
+            }
+
+            Token::Control(ControlKind::RomanIndexEntry) => {
+                (span, tok) = state.scan_next(span)?;
+            }
+            Token::Control(ControlKind::TypewriterIndexEntry) => {
+                (span, tok) = state.scan_next(span)?;
+            }
+            Token::Control(ControlKind::WildcardIndexEntry) => {
+                (span, tok) = state.scan_next(span)?;
+            }
+
+            Token::Char('|') => {
+                let code;
+                (span, (code, tok)) = scan_pascal_only(span, state)?;
+                emit_pascal(output, code, EmitPascalMode::Inline);
+            }
+
+            _ => {
+                (span, tok) = next_token(span)?;
+            }
+        }
+    }
+}
+
+/// The CWEB analogue of `handle_definitions`.
+fn handle_c_definitions<'a>(
+    state: &State,
+    output: &mut OutputState<'a>,
     mut span: Span<'a>,
     mut tok: Token,
 ) -> ParseResult<'a, Token> {
-    let mut ptoks;
+    let mut code;
 
     loop {
         match tok {
@@ -465,62 +2246,25 @@ fn handle_definitions<'a>(
             }
 
             Token::Control(ControlKind::MacroDefinition) => {
-                let mut code;
-                (span, (code, tok)) = scan_pascal(span, state)?;
-                code.0.insert(
-                    0,
-                    WebToken::Pascal(PascalToken::ReservedWord(SpanValue {
-                        start: Span::new(""),
-                        end: Span::new(""),
-                        value: PascalReservedWord::Define,
-                    })),
-                );
-                emit_pascal(code, EmitPascalMode::Define);
+                (span, (code, tok)) = scan_c(span, state, true)?;
+                emit_c(output, code, EmitPascalMode::Define);
             }
 
             Token::Control(ControlKind::FormatDefinition) => {
-                let mut code = vec![WebToken::Pascal(PascalToken::ReservedWord(SpanValue {
-                    start: Span::new(""),
-                    end: Span::new(""),
-                    value: PascalReservedWord::Format,
-                }))];
-                let ptok;
-
-                (span, ptok) = state.match_pascal_token_with_formats(span)?;
-                code.push(WebToken::Pascal(ptok.clone()));
-
-                if let PascalToken::Identifier(_) = ptok {
-                    let ptok2;
-                    (span, ptok2) = state.match_pascal_token_with_formats(span)?;
-                    code.push(WebToken::Pascal(ptok2.clone()));
-
-                    if let PascalToken::Equivalence = ptok2 {
-                        let ptok3;
-                        (span, ptok3) = state.match_pascal_token_with_formats(span)?;
-                        code.push(WebToken::Pascal(ptok3));
-                    }
-                }
-
-                let mut rest;
-                (span, (rest, tok)) = scan_pascal(span, state)?;
-                code.append(&mut rest.0);
-                emit_pascal(WebSyntax(code), EmitPascalMode::Format);
+                (span, (code, tok)) = scan_c(span, state, true)?;
+                emit_c(output, code, EmitPascalMode::Format);
             }
 
-            Token::Control(ControlKind::RomanIndexEntry) => {
-                (span, tok) = state.scan_next(span)?;
-            }
-            Token::Control(ControlKind::TypewriterIndexEntry) => {
-                (span, tok) = state.scan_next(span)?;
-            }
-            Token::Control(ControlKind::WildcardIndexEntry) => {
+            Token::Control(ControlKind::RomanIndexEntry)
+            | Token::Control(ControlKind::TypewriterIndexEntry)
+            | Token::Control(ControlKind::WildcardIndexEntry) => {
                 (span, tok) = state.scan_next(span)?;
             }
 
             Token::Char('|') => {
-                (span, (ptoks, tok)) = scan_pascal_only(span, state)?;
-                let wrapped = ptoks.drain(..).map(|t| WebToken::Pascal(t)).collect();
-                emit_pascal(WebSyntax(wrapped), EmitPascalMode::Inline);
+                (span, (code, _)) = scan_c(span, state, false)?;
+                emit_c(output, code, EmitPascalMode::Inline);
+                (span, tok) = next_token(span)?;
             }
 
             _ => {
@@ -530,118 +2274,822 @@ fn handle_definitions<'a>(
     }
 }
 
+/// The CWEB analogue of `handle_pascal`.
+fn handle_c<'a>(
+    state: &State,
+    output: &mut OutputState<'a>,
+    mut span: Span<'a>,
+    mode: EmitPascalMode<'a>,
+) -> ParseResult<'a, Token> {
+    let mut code = CCode::default();
+    let mut tok;
+
+    loop {
+        let mut block;
+        (span, (block, tok)) = scan_c(span, state, true)?;
+        code.0.append(&mut block.0);
+
+        match tok {
+            Token::Control(ControlKind::NewMajorModule)
+            | Token::Control(ControlKind::NewMinorModule) => {
+                emit_c(output, code, mode);
+                return Ok((span, tok));
+            }
+
+            Token::Control(ControlKind::ModuleName) => {
+                let mref;
+                (span, mref) = state.scan_module_reference(span)?;
+                code.0.push(CItem::ModuleReference(mref));
+            }
+
+            // Definitions aren't allowed after the code has started. Ignore
+            // the control code and keep going.
+            _ => {}
+        }
+    }
+}
+
 fn handle_pascal<'a>(
     state: &State,
+    output: &mut OutputState<'a>,
     mut span: Span<'a>,
     mode: EmitPascalMode<'a>,
 ) -> ParseResult<'a, Token> {
+    if state.dialect().is_cweb() {
+        return handle_c(state, output, span, mode);
+    }
+
     let mut tok;
 
-    let mut prev_span = span.clone();
-    (span, tok) = next_token(span)?;
+    let mut prev_span = span;
+    (span, tok) = next_code_token(span)?;
 
-    let mut code = Vec::new();
+    let mut code = WebSyntax::default();
 
     loop {
         match tok {
             Token::Control(ControlKind::NewMajorModule)
             | Token::Control(ControlKind::NewMinorModule) => {
-                emit_pascal(WebSyntax(code), mode);
+                emit_pascal(output, code, mode);
                 return Ok((span, tok));
             }
 
             Token::Control(ControlKind::ModuleName) => {
+                // We've already consumed the `@<`.
+                let start = span.location_offset() - 2;
                 let mref;
                 (span, mref) = state.scan_module_reference(span)?;
-                code.push(WebToken::ModuleReference(mref));
+                code.push(
+                    WebToken::ModuleReference(mref),
+                    start..span.location_offset(),
+                );
 
-                prev_span = span.clone();
-                (span, tok) = next_token(span)?;
+                prev_span = span;
+                (span, tok) = next_code_token(span)?;
             }
 
             _ => {
                 let mut block;
                 (span, (block, tok)) = scan_pascal(prev_span, state)?;
-                code.append(&mut block.0);
+                code.append(&mut block);
             }
         }
     }
 }
 
-/// WEAVE:218, WEAVE:220, etc.
-fn second_pass_inner<'a>(basename: &str, state: &State, span: Span<'a>) -> ParseResult<'a, ()> {
-    let mut output = OutputState::default();
+/// Emit the material preceding the first module, returning the token that
+/// starts it.
+fn begin_second_pass<'a>(
+    basename: &str,
+    state: &State,
+    output: &mut OutputState<'a>,
+    span: Span<'a>,
+) -> ParseResult<'a, Token> {
+    // WEAVE's macros do their own thing with the limbo material and the table
+    // of contents.
+    if output.webmac {
+        output.prints("% Generated by tt-weave\n");
+        output.prints(crate::webmac::PREAMBLE);
+        return copy_limbo(state, output, span);
+    }
 
     // Note: we *don't* start by emitting `\input webmac` ...
     output
         .prints("% Generated by tt-weave\n% Note: webmac.tex is (intentionally) not loaded here\n");
-    let (mut span, mut tok) = copy_limbo(&mut output, span)?;
-    let mut cur_module: ModuleId = 0;
 
-    // ... but we do have a hack to allow overrides of "limbo" macros
-    output.prints(format!("\n\\input{{{}-overrides.tex}}\n", basename));
+    if output.standalone {
+        output.prints(crate::standalone::PREAMBLE);
+    }
 
-    loop {
-        // At the top of this loop, we've just read a new-module boundary token.
-        // At the moment we don't really care about major vs minor.
-        cur_module += 1;
-        match tok {
-            Token::Control(ControlKind::NewMajorModule) => {
-                output.prints(format!("\n\\WebMajorModule{{{}}} ", cur_module));
-            }
-            Token::Control(ControlKind::NewMinorModule) => {
-                output.prints(format!("\n\\WebMinorModule{{{}}} ", cur_module));
+    let (span, tok) = copy_limbo(state, output, span)?;
+
+    // ... but we do have a hack to allow overrides of "limbo" macros. In a
+    // standalone document, there's no project to provide them.
+    if output.standalone {
+        output.prints(format!(
+            "\n\\InputIfFileExists{{{}-overrides.tex}}{{}}{{}}\n",
+            basename
+        ));
+    } else {
+        output.prints(format!("\n\\input{{{}-overrides.tex}}\n", basename));
+    }
+
+    // The table of contents comes before any of the modules. We omit it if
+    // we're only emitting some of the modules.
+    if output.sections.is_none() {
+        if let Some(name) = output.contents_file.as_ref() {
+            output.prints(format!("\n\\input{{{}}}\n", name));
+        } else {
+            state
+                .emit_table_of_contents(&mut output.text)
+                .expect("writing to a String cannot fail");
+        }
+    }
+
+    Ok((span, tok))
+}
+
+/// Handle the module `cur_module`, which starts with `tok`, returning the token
+/// that starts the next module.
+fn second_pass_module<'a>(
+    state: &State,
+    output: &mut OutputState<'a>,
+    cur_module: ModuleId,
+    mut span: Span<'a>,
+    mut tok: Token,
+) -> ParseResult<'a, Token> {
+    output.module = cur_module;
+    output.pragmas = Pragmas::default();
+
+    // With WEAVE's macros, each module ends with `\fi`.
+    if output.module_open {
+        output.prints("\\fi\n");
+        output.module_open = false;
+    }
+
+    output.skipping = output
+        .sections
+        .as_ref()
+        .is_some_and(|s| !s.contains(cur_module))
+        || output.excluded.contains(cur_module);
+
+    if !output.skipping {
+        output.module_starts.push((cur_module, output.text.len()));
+    }
+
+    if let Some(xref) = output.xref.as_mut() {
+        // The control code that starts the module has already been consumed.
+        let start = span.location_offset() - 2;
+        xref.end_section(start);
+
+        if !output.skipping {
+            let mut line = span.location_line();
+
+            if output.source[..span.location_offset()].ends_with('\n') {
+                line -= 1;
             }
-            _ => {
-                eprintln!("unexpected module end {:?}", tok);
-                return new_parse_error(span, ErrorKind::Complete);
+
+            let major = matches!(tok, Token::Control(ControlKind::NewMajorModule));
+            xref.start_section(cur_module, major, line, start);
+        }
+    }
+
+    match tok {
+        // WEB's `\N` has no notion of depth, so it's dropped.
+        Token::Control(ControlKind::NewMajorModule) if output.webmac => {
+            (span, _) = crate::pass1::scan_major_module_depth(span)?;
+            output.prints(format!("\n\\N{}. ", cur_module));
+            output.module_open = !output.skipping;
+        }
+        Token::Control(ControlKind::NewMinorModule) if output.webmac => {
+            output.prints(format!("\n\\M{}. ", cur_module));
+            output.module_open = !output.skipping;
+        }
+        Token::Control(ControlKind::NewMajorModule) => {
+            // The depth is only specified if it's not the default.
+            let depth;
+            (span, depth) = crate::pass1::scan_major_module_depth(span)?;
+            let depth_arg = if depth == 0 {
+                String::new()
+            } else {
+                format!("[{}]", depth)
+            };
+
+            output.prints(format!(
+                "\n\\WebMajorModule{1}{{{0}}}\\WebModuleAnchor{{{0}}}",
+                cur_module, depth_arg
+            ));
+
+            // The outline entry lets PDF output get a tree of bookmarks.
+            if let Some(m) = state.major_modules().iter().find(|m| m.id == cur_module) {
+                output.prints(format!(
+                    "\\WebOutlineEntry{{{}}}{{{}}}{{{}}}",
+                    cur_module, depth, m.desc
+                ));
             }
+
+            output.prints(" ");
+        }
+        Token::Control(ControlKind::NewMinorModule) => {
+            output.prints(format!(
+                "\n\\WebMinorModule{{{0}}}\\WebModuleAnchor{{{0}}} ",
+                cur_module
+            ));
         }
+        _ => {
+            error!("unexpected module end {:?}", tok);
+            return new_parse_error(span, ErrorKind::Complete);
+        }
+    }
 
-        // Handle the TeX chunk (which can be empty), and find out what ended it.
+    // Handle the TeX chunk (which can be empty), and find out what ended it.
 
-        (span, tok) = handle_tex(state, &mut output, span)?;
+    (span, tok) = handle_tex(state, output, span)?;
 
-        // If there are macro/format definitions, handle those
+    // If there are macro/format definitions, handle those
 
-        match tok {
-            Token::Control(ControlKind::MacroDefinition)
-            | Token::Control(ControlKind::FormatDefinition) => {
-                (span, tok) = handle_definitions(state, span, tok)?;
+    match tok {
+        Token::Control(ControlKind::MacroDefinition)
+        | Token::Control(ControlKind::FormatDefinition) => {
+            (span, tok) = handle_definitions(state, output, span, tok)?;
+        }
+        _ => {}
+    }
+
+    // If there's Pascal, handle that
+
+    match tok {
+        Token::Control(ControlKind::StartUnnamedPascal) => {
+            (span, tok) = handle_pascal(state, output, span, EmitPascalMode::Anonymous)?;
+        }
+
+        Token::Control(ControlKind::ModuleName) => {
+            let mref;
+            (span, mref) = state.scan_module_reference(span)?;
+            let is_definition = mref.id == cur_module;
+
+            if is_definition && !output.skipping {
+                check_module_name(output, &mref);
+            }
+
+            let name = mref.name.value.to_string();
+            let mode = EmitPascalMode::NamedModule(mref, is_definition);
+
+            // there's like one module in XeTeX with a space between module name and equals sign
+            (span, _) = take_while(|c| c == ' ' || c == '\t' || c == '\n')(span)?;
+            (span, _) = char('=')(span)?;
+            (span, tok) = handle_pascal(state, output, span, mode)?;
+
+            if output.webmac {
+                let mut notes = String::new();
+                state
+                    .emit_webmac_module_notes(&name, cur_module, &mut notes)
+                    .expect("writing to a String cannot fail");
+                output.prints(notes);
             }
-            _ => {}
         }
 
-        // If there's Pascal, handle that
+        _ => {}
+    }
+
+    Ok((span, tok))
+}
+
+/// The main body of a woven document.
+#[derive(Clone, Debug)]
+pub struct WovenBody {
+    /// The TeX text, or if it was written out as it was emitted, the text that
+    /// follows the last module.
+    pub tex: String,
 
-        match tok {
-            Token::Control(ControlKind::StartUnnamedPascal) => {
-                (span, tok) = handle_pascal(state, span, EmitPascalMode::Anonymous)?;
+    /// The offset in `tex` at which each emitted module starts. This is
+    /// meaningless if the text was written out as it was emitted.
+    pub module_starts: Vec<(ModuleId, usize)>,
+
+    /// The warnings issued while weaving.
+    pub warnings: Vec<Warning>,
+
+    /// The problems encountered while parsing the code.
+    pub parse_failures: Vec<CheckFailure>,
+
+    /// How long each module took to weave, if requested.
+    pub timings: Option<Timings>,
+
+    /// The measurements of the code of each module, if requested.
+    pub metrics: Option<Metrics>,
+
+    /// The code laid out while weaving, if caching was requested.
+    #[cfg(feature = "serde")]
+    pub cache: Option<CodeCache>,
+
+    /// The identifiers declared by the woven code.
+    pub symbols: SymbolTable,
+
+    /// Which procedures and functions in the woven code call which.
+    pub call_graph: CallGraph,
+
+    /// The cross-reference database of the woven code, if requested.
+    pub xref: Option<XrefDatabase>,
+}
+
+/// Emit the main body of the woven document.
+///
+/// The Pascal code is highlighted using `theme`. If `dest` is set, the text is
+/// written to it as the modules are finished, and the returned body only holds
+/// the text that follows the last module.
+pub fn execute(
+    basename: &str,
+    state: &State,
+    theme: &Theme,
+    options: &WeaveOptions,
+    span: Span,
+    dest: Option<&mut dyn io::Write>,
+) -> Result<WovenBody> {
+    let mut output = OutputState::new(theme, span.fragment());
+    output.sections = options.sections.clone();
+    output.contents_file = options.contents_file.clone();
+    output.standalone = options.standalone;
+    output.webmac = options.webmac;
+    output.lints = options.lints.clone();
+    output.style_limits = options.style_limits;
+    output.code_length = Some(CodeLengthChecker::default());
+    output.labels = Some(LabelChecker::default());
+    output.symbols = Some(SymbolChecker::default());
+    output.symbol_table = Some(SymbolTable::default());
+    output.parse_failures = Some(Vec::new());
+
+    // CWEB programs don't have a single root: the modules that name output
+    // files are never referenced by design. Nor are they written in Pascal.
+    if !state.dialect().is_cweb() {
+        output.reachability = Some(ReachabilityChecker::default());
+        output.call_graph = Some(CallGraphBuilder::default());
+        output.strict = Some(StrictChecker::default());
+        output.directives = Some(DirectiveChecker::default());
+    }
+
+    for name in &options.exclude_modules {
+        for m in state.named_module_contributors(name)? {
+            output.excluded.add(m..=m);
+        }
+    }
+
+    for m in &options.exclude_parts {
+        output.excluded.add(state.major_module_part(*m)?);
+    }
+
+    output.set_layout(options);
+    output.timings = options.timings.then(Timings::default);
+    output.metrics = options.metrics.then(Metrics::default);
+    output.xref = options.xref.then(XrefDatabase::default);
+
+    // Annotating a function with where it's used requires knowing about all of
+    // the code before any of it is laid out, so that takes a pass of its own.
+    if options.function_uses && !state.dialect().is_cweb() {
+        output.function_uses = Arc::new(cross_reference(state, span)?.function_uses());
+    }
+
+    // Likewise for linking identifiers to their declarations.
+    if options.identifier_links && !state.dialect().is_cweb() {
+        let symbols = symbol_table(state, span, None)?;
+        output.anchors = Arc::new(IdentifierAnchors::new(&symbols));
+    }
+
+    #[cfg(feature = "serde")]
+    if let Some(cache) = options.cache.as_ref() {
+        let mut hasher = KeyHasher::default();
+        format!("{:?}", state.dialect()).hash(&mut hasher);
+        serde_json::to_string(theme)?.hash(&mut hasher);
+        let mut identifier_tex: Vec<_> = options.identifier_tex.iter().collect();
+        identifier_tex.sort();
+        identifier_tex.hash(&mut hasher);
+        options.style.hash(&mut hasher);
+        options.inline_max_lines.hash(&mut hasher);
+        output.max_depth.hash(&mut hasher);
+        let mut function_uses: Vec<_> = output.function_uses.iter().collect();
+        function_uses.sort_unstable_by_key(|(name, _)| *name);
+        function_uses.hash(&mut hasher);
+        let mut anchors: Vec<_> = output.anchors.modules().collect();
+        anchors.sort_unstable();
+        anchors.hash(&mut hasher);
+        let mut macros: Vec<_> = state.macros().iter().collect();
+        macros.sort_unstable();
+        macros.hash(&mut hasher);
+
+        output.cache = Some(cache);
+        output.new_cache = Some(CodeCache::new());
+        output.cache_salt = hasher.finish();
+    }
+
+    // WEAVE's macros have no notion of a mini-index.
+    let mini_index = options.mini_index && !options.webmac;
+    run_streaming(basename, state, &mut output, mini_index, span, dest)?;
+
+    #[cfg(feature = "serde")]
+    if output.new_cache.is_some() {
+        info!(
+            "reused {} of {} fragments of code from the cache",
+            output.cache_hits.0, output.cache_hits.1
+        );
+    }
+
+    // The mini-index for the final module is handled here, since the loop above
+    // only terminates when it hits the end of the input.
+    if mini_index && state.n_modules() > 0 && !output.skipping {
+        writeln!(output.text)?;
+        state.emit_mini_index(state.n_modules(), &mut output.text)?;
+    }
+
+    if output.module_open {
+        output.prints("\\fi\n");
+    }
+
+    if let Some(labels) = output.labels.take() {
+        output.warnings.extend(labels.finish(&output.lints));
+    }
+
+    let symbol_table = output.symbol_table.take().unwrap_or_default();
+
+    if let Some(symbols) = output.symbols.take() {
+        output
+            .warnings
+            .extend(symbols.finish(state, &symbol_table, &output.lints));
+    }
+
+    let xref = output.xref.take().map(|mut xref| {
+        xref.end_section(output.source.len());
+        xref.symbols = symbol_table.clone();
+        xref
+    });
+
+    let call_graph = output
+        .call_graph
+        .take()
+        .map(|cg| cg.finish(&symbol_table))
+        .unwrap_or_default();
+
+    if let Some(reachability) = output.reachability.take() {
+        output.warnings.extend(reachability.finish(&output.lints));
+    }
+
+    if let Some(strict) = output.strict.take() {
+        output.warnings.extend(strict.finish(&output.lints));
+    }
+
+    if let Some(directives) = output.directives.take() {
+        output.warnings.extend(directives.finish(&output.lints));
+    }
+
+    if let Some(code_length) = output.code_length.take() {
+        output
+            .warnings
+            .extend(code_length.finish(output.style_limits.max_code_lines, &output.lints));
+    }
+
+    Ok(WovenBody {
+        tex: output.text,
+        module_starts: output.module_starts,
+        warnings: output.warnings,
+        parse_failures: output.parse_failures.unwrap_or_default(),
+        timings: output.timings,
+        metrics: output.metrics,
+        #[cfg(feature = "serde")]
+        cache: output.new_cache,
+        symbols: symbol_table,
+        call_graph,
+        xref,
+    })
+}
+
+/// Prettify all of the code in the document, returning the markup for it on its
+/// own, without the TeX commentary, along with the problems encountered while
+/// parsing it. The code is laid out according to `options`, and the markup is
+/// HTML if `html` is set, and TeX otherwise.
+pub fn snippet(
+    state: &State,
+    span: Span,
+    theme: &Theme,
+    options: &WeaveOptions,
+    html: bool,
+) -> Result<(String, Vec<CheckFailure>)> {
+    let mut output = OutputState::new(theme, span.fragment());
+    output.webmac = options.webmac && !html;
+    output.set_layout(options);
+    output.parse_failures = Some(Vec::new());
+
+    if html {
+        output.html = Some(Vec::new());
+    } else {
+        output.fragments = Some(Vec::new());
+    }
+
+    run("", state, &mut output, false, span)?;
+
+    let markup = match output.html {
+        Some(fragments) => fragments.into_iter().map(|f| f.html).collect(),
+        None => output.fragments.unwrap_or_default().concat(),
+    };
+
+    Ok((markup, output.parse_failures.unwrap_or_default()))
+}
+
+/// Build the cross-reference database of the whole document, without laying
+/// out any of the code.
+pub fn cross_reference(state: &State, span: Span) -> Result<XrefDatabase> {
+    let theme = Theme::default();
+    let mut output = OutputState::new(&theme, span.fragment());
+    output.table_only = true;
+    output.symbol_table = Some(SymbolTable::default());
+    output.xref = Some(XrefDatabase::default());
+    run("", state, &mut output, false, span)?;
+
+    let mut xref = output.xref.unwrap_or_default();
+    xref.end_section(output.source.len());
+    xref.symbols = output.symbol_table.unwrap_or_default();
+    Ok(xref)
+}
+
+/// Extract the text of the comments in the code of the document, without
+/// laying out any of the code.
+pub fn comments(
+    state: &State,
+    span: Span,
+    sections: Option<&SectionSelection>,
+) -> Result<Vec<CommentText>> {
+    let theme = Theme::default();
+    let mut output = OutputState::new(&theme, span.fragment());
+    output.table_only = true;
+    output.comments = Some(Vec::new());
+    output.sections = sections.cloned();
+    run("", state, &mut output, false, span)?;
+    Ok(output.comments.unwrap_or_default())
+}
+
+/// Parse all of the Pascal code in the document, returning a JSON
+/// serialization of the AST of each fragment.
+///
+/// Each fragment is annotated with the ID of the module in which it appears and
+/// the context in which it appears: a named module, a definition, code inlined
+/// into TeX, etc.
+#[cfg(feature = "serde")]
+pub fn dump(
+    state: &State,
+    span: Span,
+    sections: Option<&SectionSelection>,
+) -> Result<Vec<serde_json::Value>> {
+    let theme = Theme::default();
+    let mut output = OutputState::new(&theme, span.fragment());
+    output.dump = Some(Vec::new());
+    output.sections = sections.cloned();
+    run("", state, &mut output, false, span)?;
+    Ok(output.dump.unwrap_or_default())
+}
+
+/// Lexes the Pascal code of a document one module at a time.
+///
+/// This is an iterator over the modules, each of which is only lexed when it's
+/// asked for. So, only one module's worth of tokens is held in memory at once,
+/// and the first modules are available before the rest of the document has
+/// been lexed. CWEB documents yield modules without any code.
+pub struct ModuleLexer<'a> {
+    state: &'a State,
+    output: OutputState<'a>,
+
+    /// The input starting with the next module, and the token that starts it.
+    /// This is `None` once the end of the input has been reached.
+    next: Option<(Span<'a>, Token)>,
+}
+
+impl<'a> Iterator for ModuleLexer<'a> {
+    type Item = Result<LexedModule<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (span, tok) = self.next.take()?;
+
+            if span.fragment().is_empty() {
+                return None;
             }
 
-            Token::Control(ControlKind::ModuleName) => {
-                let mref;
-                (span, mref) = state.scan_module_reference(span)?;
-                let is_definition = mref.id == cur_module;
-                let mode = EmitPascalMode::NamedModule(mref, is_definition);
+            let module = self.output.module + 1;
+            let result = second_pass_module(self.state, &mut self.output, module, span, tok);
+            self.output.text.clear();
 
-                // there's like one module in XeTeX with a space between module name and equals sign
-                (span, _) = take_while(|c| c == ' ' || c == '\t' || c == '\n')(span)?;
-                (span, _) = char('=')(span)?;
-                (span, tok) = handle_pascal(state, span, mode)?;
+            match result.finish() {
+                Ok(next) => self.next = Some(next),
+                Err((_remainder, ErrorKind::Eof)) => {}
+                Err((_remainder, kind)) => {
+                    return Some(Err(anyhow!(kind.description().to_owned())))
+                }
             }
 
-            _ => {}
+            if self.output.skipping {
+                continue;
+            }
+
+            let fragments = self
+                .output
+                .pending
+                .drain(..)
+                .filter_map(|p| match p.kind {
+                    PendingKind::Pascal(syntax, _) => Some(LexedFragment {
+                        module,
+                        context: p.mode.context(),
+                        name: match p.mode {
+                            EmitPascalMode::NamedModule(mref, _) => Some(mref),
+                            _ => None,
+                        },
+                        tokens: syntax.tokens,
+                        positions: syntax.positions,
+                    }),
+
+                    PendingKind::C(_) => None,
+                })
+                .collect();
+
+            return Some(Ok(LexedModule { module, fragments }));
         }
     }
 }
 
-pub fn execute(basename: &str, state: &State, span: Span) -> Result<()> {
-    match second_pass_inner(basename, state, span).finish() {
-        Ok((_remainder, _value)) => {}
-        Err((_remainder, ErrorKind::Eof)) => {}
-        Err((_remainder, kind)) => return Err(anyhow!(kind.description().to_owned())),
+/// Start lexing the Pascal code of the document a module at a time.
+pub fn lex<'a>(
+    state: &'a State,
+    span: Span<'a>,
+    sections: Option<&SectionSelection>,
+) -> Result<ModuleLexer<'a>> {
+    let mut output = OutputState::new(&PLAIN_THEME, span.fragment());
+    output.lex_only = true;
+    output.sections = sections.cloned();
+
+    if let Some(sections) = sections {
+        sections.check(state.n_modules())?;
+    }
+
+    let next = match begin_second_pass("", state, &mut output, span).finish() {
+        Ok(next) => Some(next),
+        Err((_remainder, ErrorKind::Eof)) => None,
+        Err((_remainder, kind)) => bail!(kind.description().to_owned()),
+    };
+
+    output.text.clear();
+
+    Ok(ModuleLexer {
+        state,
+        output,
+        next,
+    })
+}
+
+/// Prettify all of the code in the document as HTML, returning the fragments
+/// in order.
+pub fn html(
+    state: &State,
+    span: Span,
+    theme: &Theme,
+    dark_theme: Option<&Theme>,
+    sections: Option<&SectionSelection>,
+) -> Result<Vec<HtmlFragment>> {
+    // Uses of identifiers link to their declarations even if those are in
+    // modules that aren't selected.
+    let symbols = symbol_table(state, span, None)?;
+
+    let mut output = OutputState::new(theme, span.fragment());
+    output.html = Some(Vec::new());
+    output.dark_theme = dark_theme;
+    output.sections = sections.cloned();
+    output.anchors = Arc::new(IdentifierAnchors::new(&symbols));
+    run("", state, &mut output, false, span)?;
+    Ok(output.html.unwrap_or_default())
+}
+
+/// Find the occurrences of an identifier in the Pascal code of the document.
+///
+/// Code that can't be parsed is skipped, with a warning.
+pub fn query(
+    state: &State,
+    span: Span,
+    query: IdentifierQuery,
+    sections: Option<&SectionSelection>,
+) -> Result<Vec<IdentifierLocation>> {
+    let theme = Theme::default();
+    let mut output = OutputState::new(&theme, span.fragment());
+    output.query = Some((query, Vec::new()));
+    output.sections = sections.cloned();
+    run("", state, &mut output, false, span)?;
+    Ok(output.query.map(|q| q.1).unwrap_or_default())
+}
+
+/// Build the symbol table of the Pascal code in the document.
+pub fn symbol_table(
+    state: &State,
+    span: Span,
+    sections: Option<&SectionSelection>,
+) -> Result<SymbolTable> {
+    let theme = Theme::default();
+    let mut output = OutputState::new(&theme, span.fragment());
+    output.table_only = true;
+    output.symbol_table = Some(SymbolTable::default());
+    output.sections = sections.cloned();
+    run("", state, &mut output, false, span)?;
+    Ok(output.symbol_table.unwrap_or_default())
+}
+
+/// Try to parse all of the Pascal code in the document, returning a list of
+/// the problems encountered.
+pub fn check(
+    state: &State,
+    span: Span,
+    sections: Option<&SectionSelection>,
+) -> Result<Vec<CheckFailure>> {
+    let theme = Theme::default();
+    let mut output = OutputState::new(&theme, span.fragment());
+    output.check_failures = Some(Vec::new());
+    output.sections = sections.cloned();
+    run("", state, &mut output, false, span)?;
+    Ok(output.check_failures.unwrap_or_default())
+}
+
+fn run<'a>(
+    basename: &str,
+    state: &State,
+    output: &mut OutputState<'a>,
+    mini_index: bool,
+    span: Span<'a>,
+) -> Result<()> {
+    run_streaming(basename, state, output, mini_index, span, None)
+}
+
+/// Run the second pass, as with [`run`]. If `dest` is set, the text is written
+/// to it as each batch of modules is finished, rather than all being kept in
+/// memory, so the output text only holds what's been emitted since.
+///
+/// WEAVE:218, WEAVE:220, etc.
+fn run_streaming<'a>(
+    basename: &str,
+    state: &State,
+    output: &mut OutputState<'a>,
+    mini_index: bool,
+    span: Span<'a>,
+    mut dest: Option<&mut dyn io::Write>,
+) -> Result<()> {
+    if let Some(sections) = output.sections.as_ref() {
+        sections.check(state.n_modules())?;
+    }
+
+    output.toplevel_parsers = state.toplevel_parsers().to_vec();
+    output.macros = Arc::new(state.macros().clone());
+
+    let mut next = finish_step(begin_second_pass(basename, state, output, span))?;
+    let mut cur_module: ModuleId = 0;
+
+    // At the top of this loop, we've just read a new-module boundary token.
+    // At the moment we don't really care about major vs minor.
+    while let Some((span, tok)) = next {
+        if span.fragment().is_empty() {
+            break;
+        }
+
+        if mini_index && cur_module > 0 && !output.skipping {
+            state.emit_mini_index(cur_module, &mut output.text)?;
+        }
+
+        cur_module += 1;
+        let mut timer = PhaseTimer::start(output.timings.is_some());
+        let result = second_pass_module(state, output, cur_module, span, tok);
+        timer.lexed();
+
+        if let Some(timings) = output.timings.as_mut() {
+            if !output.skipping {
+                *timings.module_mut(cur_module) += timer.times;
+            }
+        }
+
+        next = finish_step(result)?;
+
+        if output.pending.len() >= PENDING_BATCH_SIZE {
+            finish_pending(output);
+        }
+
+        // Pending code is spliced into the text later, so the text can only be
+        // written out once there isn't any.
+        if let Some(dest) = dest.as_mut() {
+            if output.pending.is_empty() {
+                dest.write_all(output.text.as_bytes())?;
+                output.text.clear();
+            }
+        }
     }
 
+    finish_pending(output);
     Ok(())
 }
+
+/// Get the input following a step of the second pass, and the value that it
+/// produced, or `None` if the step hit the end of the input.
+fn finish_step<'a, T>(result: ParseResult<'a, T>) -> Result<Option<(Span<'a>, T)>> {
+    match result.finish() {
+        Ok(next) => Ok(Some(next)),
+        Err((_remainder, ErrorKind::Eof)) => Ok(None),
+        Err((_remainder, kind)) => bail!(kind.description().to_owned()),
+    }
+}
@@ -1,17 +1,30 @@
 //! Prettify the Pascal source.
 
+use clap::ArgEnum;
 use lazy_static::lazy_static;
+use serde::Deserialize;
 use std::{
+    collections::HashMap,
     fmt::{self, Write},
-    ops::Deref,
+    ops::{Deref, Range},
     str::FromStr,
+    sync::Arc,
 };
 use syntect::{
-    highlighting::{Color, FontStyle, HighlightIterator, HighlightState, Highlighter, Theme},
+    highlighting::{
+        Color, FontStyle, HighlightIterator, HighlightState, Highlighter, Style, Theme,
+    },
     parsing::{Scope, ScopeStack, ScopeStackOp},
 };
 
-use crate::weblang::base::{ModuleId, SpanValue};
+use crate::{
+    macros::{MacroKind, MacroTable},
+    parse_base::StringSpan,
+    symbols::normalize,
+    symtab::SymbolTable,
+    weblang::base::{ModuleId, SpanValue, Spanned},
+    xref::FunctionUses,
+};
 
 // See https://www.sublimetext.com/docs/scope_naming.html for some scope hints.
 
@@ -23,13 +36,189 @@ lazy_static! {
     pub static ref STRING_LITERAL_SCOPE: Scope = Scope::new("string.quoted.double").unwrap();
     pub static ref HEX_LITERAL_SCOPE: Scope =
         Scope::new("constant.numeric.integer.hexadecimal").unwrap();
+    pub static ref OCTAL_LITERAL_SCOPE: Scope =
+        Scope::new("constant.numeric.integer.octal").unwrap();
     pub static ref DECIMAL_LITERAL_SCOPE: Scope =
         Scope::new("constant.numeric.integer.decimal").unwrap();
     pub static ref FLOAT_LITERAL_SCOPE: Scope = Scope::new("constant.numeric.float").unwrap();
     pub static ref LABEL_NAME_SCOPE: Scope = Scope::new("entity.name.label").unwrap();
+    pub static ref PREPROCESSOR_SCOPE: Scope = Scope::new("meta.preprocessor.c").unwrap();
+    pub static ref CONSTANT_MACRO_SCOPE: Scope = Scope::new("constant.other.macro").unwrap();
+    pub static ref FILE_PARAMETER_SCOPE: Scope = Scope::new("support.variable.file").unwrap();
 }
 
-const WIDTH: usize = 60;
+/// The default width, in characters, to which code is laid out.
+pub const DEFAULT_WIDTH: usize = 60;
+
+/// The default number of levels of indentation after which code is no longer
+/// wrapped to fit the width. See [`Prettifier::set_max_depth`].
+pub const DEFAULT_MAX_DEPTH: usize = 12;
+
+/// Choices about how the code is written out, as opposed to how it's laid out.
+///
+/// By default, the code is written in a C-like style: Pascal's `:=` becomes
+/// `=`, `=` becomes `==`, `<>` becomes `!=`, and so on. The code is laid out
+/// as if it were written in the default style, so the options that make tokens
+/// longer may make lines run a little longer than the width aimed for.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct CodeStyle {
+    /// Whether to write the operators as they're written in Pascal, like `:=`,
+    /// `<>`, and `and`, rather than substituting the C-like ones.
+    pub preserve_operators: bool,
+
+    /// When to write the semicolons that end statements.
+    pub semicolons: SemicolonPolicy,
+
+    /// Whether to split the digits of decimal literals longer than four digits
+    /// into groups of three, as in `1_000_000`.
+    pub group_digits: bool,
+
+    /// Whether to write hexadecimal literals with uppercase digits, as in
+    /// `0xFF`, rather than lowercase ones.
+    pub uppercase_hex: bool,
+
+    /// Whether to write octal literals in WEB's notation, as in `@'777`, rather
+    /// than converting them to hexadecimal.
+    pub preserve_octal: bool,
+
+    /// How comments are typeset.
+    pub comments: CommentStyle,
+
+    /// Whether to write the structure of the code as in Pascal, with `begin`
+    /// and `end`, `then` and `do`, and `procedure`, rather than with C-like
+    /// braces. This is set for output structured like WEAVE's; see
+    /// [`crate::webmac`].
+    pub preserve_keywords: bool,
+}
+
+/// How the comments in the code are typeset.
+#[derive(ArgEnum, Clone, Copy, Debug, Default, Deserialize, Eq, Hash, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum CommentStyle {
+    /// Like the rest of the code, in a monospaced font and the color that the
+    /// theme gives comments, with their TeX shown as written.
+    #[default]
+    Code,
+
+    /// As TeX prose, in an italic proportional font, so that their markup,
+    /// including math, is typeset. The comments keep the color that the theme
+    /// gives them. This only affects TeX output using tt-weave's own macros,
+    /// where each word is wrapped in `\WebCommentProse`.
+    Prose,
+}
+
+/// When to write the semicolons that end statements.
+///
+/// Pascal separates statements with semicolons, while the C-like style that
+/// the code is written in terminates them with semicolons. Either way, the
+/// parser doesn't require them, so this only affects the output. Statements
+/// that end with a block, like `if` and `while`, never get semicolons.
+#[derive(ArgEnum, Clone, Copy, Debug, Default, Deserialize, Eq, Hash, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SemicolonPolicy {
+    /// Write a semicolon after every statement, as in C.
+    #[default]
+    Always,
+
+    /// Write a semicolon after the statements that are followed by one in the
+    /// source.
+    AsWritten,
+
+    /// Write a semicolon after every statement except for the last one in a
+    /// `begin`/`end` block, where Pascal doesn't need one.
+    NotBeforeEnd,
+}
+
+/// Write one of WEAVE's operator macros, which take care of their own spacing,
+/// replacing any space written before it.
+fn push_operator(cs: &str, tex: &mut String) {
+    if tex.ends_with("\\ ") {
+        tex.truncate(tex.len() - 2);
+    }
+
+    tex.push_str(cs);
+}
+
+/// The anchors that link identifiers to their declarations.
+///
+/// In HTML output, each declaration gets an ID based on the normalized identifier, like
+/// `ident-curval`, which stays the same from run to run so long as the
+/// declarations don't change. If an identifier is declared more than once, the
+/// declaration that [`SymbolTable::kind`] goes by gets the plain ID, and the
+/// others get the position of the declaration appended, like `ident-k-3`.
+/// Uses of the identifier link to the plain ID, except that uses in the
+/// definition of a procedure or function link to its own parameters and local
+/// variables. In TeX output, identifiers are instead wrapped in macros giving
+/// the number of the module containing the declaration, like
+/// `\WebIdentifierReference{$module}{...}`.
+#[derive(Clone, Debug, Default)]
+pub struct IdentifierAnchors {
+    /// The ID of each declaration, keyed by the offset of the identifier in
+    /// the source text.
+    declarations: HashMap<usize, String>,
+
+    /// The ID that uses of each identifier link to, keyed by normalized name.
+    targets: HashMap<String, String>,
+
+    /// The IDs of the locals of each procedure or function, keyed by the
+    /// normalized names of the procedure or function and the local.
+    locals: HashMap<(String, String), String>,
+
+    /// The module containing each declaration, keyed by ID.
+    modules: HashMap<String, ModuleId>,
+}
+
+impl IdentifierAnchors {
+    /// Set up the anchors for the identifiers declared in a symbol table.
+    pub fn new(symbols: &SymbolTable) -> Self {
+        let mut anchors = IdentifierAnchors::default();
+
+        for sym in symbols.iter() {
+            let key = normalize(&sym.name);
+
+            if anchors.targets.contains_key(&key) {
+                continue;
+            }
+
+            let decls = symbols.get(&key);
+            let primary = decls.iter().position(|s| s.scope.is_none()).unwrap_or(0);
+
+            for (i, decl) in decls.iter().enumerate() {
+                let id = if i == primary {
+                    format!("ident-{}", key)
+                } else {
+                    format!("ident-{}-{}", key, i + 1)
+                };
+
+                if let Some(scope) = decl.scope.as_ref() {
+                    anchors
+                        .locals
+                        .insert((normalize(scope), key.clone()), id.clone());
+                }
+
+                anchors.modules.insert(id.clone(), decl.module);
+                anchors.declarations.insert(decl.range.start, id);
+            }
+
+            anchors
+                .targets
+                .insert(key.clone(), format!("ident-{}", key));
+        }
+
+        anchors
+    }
+
+    /// Iterate over the IDs of the declarations and the modules containing
+    /// them.
+    pub fn modules(&self) -> impl Iterator<Item = (&str, ModuleId)> {
+        self.modules.iter().map(|(id, m)| (id.as_str(), *m))
+    }
+
+    /// Get the module containing the declaration with the given ID.
+    pub fn module(&self, id: &str) -> Option<ModuleId> {
+        self.modules.get(id).copied()
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct Prettifier {
@@ -50,23 +239,136 @@ pub struct Prettifier {
     /// offset during prettification by looking at `text.len()`, which is
     /// measured in bytes.
     inserts: Vec<(usize, TexInsert)>,
+
+    /// Custom TeX to emit in place of particular identifiers.
+    identifier_tex: Arc<HashMap<String, String>>,
+
+    /// Where each procedure and function is used, keyed by normalized name,
+    /// for annotating their definitions.
+    function_uses: Arc<HashMap<String, FunctionUses>>,
+
+    /// The anchors linking identifiers to their declarations.
+    anchors: Arc<IdentifierAnchors>,
+
+    /// The kinds of the macros, which affect how their names are highlighted.
+    macros: Arc<MacroTable>,
+
+    /// The normalized name of the procedure or function being laid out, if
+    /// any, so that its locals can be linked to the right declarations.
+    scope: Option<String>,
+
+    /// How the code is written out.
+    style: CodeStyle,
+
+    /// How many levels of indentation the code being laid out is nested in.
+    depth: usize,
+
+    /// How many levels of indentation are allowed before the code is laid out
+    /// without wrapping.
+    max_depth: usize,
+}
+
+impl Default for Prettifier {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Prettifier {
     pub fn new() -> Self {
+        Self::with_width(DEFAULT_WIDTH)
+    }
+
+    /// Create a prettifier that lays out code to the specified width, in
+    /// characters.
+    pub fn with_width(width: usize) -> Self {
         Prettifier {
-            full_width: WIDTH,
+            full_width: width,
             indent: 0,
-            remaining_width: WIDTH,
+            remaining_width: width,
             newline_needed: false,
             text: String::default(),
             ops: Vec::default(),
             inserts: Vec::default(),
+            identifier_tex: Arc::default(),
+            function_uses: Arc::default(),
+            anchors: Arc::default(),
+            macros: Arc::default(),
+            scope: None,
+            style: CodeStyle::default(),
+            depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
         }
     }
 
+    /// Set the custom TeX to emit in place of particular identifiers.
+    ///
+    /// The code is still laid out as if the identifiers were written out
+    /// normally, so each replacement should typeset to about the same width as
+    /// the identifier that it replaces.
+    pub fn set_identifier_tex(&mut self, table: Arc<HashMap<String, String>>) {
+        self.identifier_tex = table;
+    }
+
+    /// Set where each procedure and function is used, keyed by normalized
+    /// name. Their definitions are annotated with this information.
+    pub fn set_function_uses(&mut self, table: Arc<HashMap<String, FunctionUses>>) {
+        self.function_uses = table;
+    }
+
+    /// Set the anchors linking identifiers to their declarations. See
+    /// [`IdentifierAnchors`] for how they appear in the output.
+    pub fn set_identifier_anchors(&mut self, anchors: Arc<IdentifierAnchors>) {
+        self.anchors = anchors;
+    }
+
+    /// Set the kinds of the macros. Numeric and string-like macros are
+    /// highlighted as constants.
+    pub fn set_macros(&mut self, macros: Arc<MacroTable>) {
+        self.macros = macros;
+    }
+
+    /// Set how the code is written out. See [`CodeStyle`].
+    pub fn set_style(&mut self, style: CodeStyle) {
+        self.style = style;
+    }
+
+    /// Get how the code is written out.
+    pub fn style(&self) -> &CodeStyle {
+        &self.style
+    }
+
+    /// Set how many levels of indentation are allowed before the code is laid
+    /// out without wrapping. Past this depth, everything is considered to fit,
+    /// so the code is written out inline, in long lines, rather than wrapped
+    /// into ever narrower columns.
+    pub fn set_max_depth(&mut self, depth: usize) {
+        self.max_depth = depth;
+    }
+
+    /// Note that the definition of the named procedure or function is being
+    /// laid out, returning the scope to restore with [`Self::leave_scope`]
+    /// when it's done.
+    pub fn enter_scope(&mut self, name: &str) -> Option<String> {
+        self.scope.replace(normalize(name))
+    }
+
+    /// Restore the scope saved by [`Self::enter_scope`].
+    pub fn leave_scope(&mut self, outer: Option<String>) {
+        self.scope = outer;
+    }
+
+    /// Get where a procedure or function is used, if that's known.
+    pub fn function_uses(&self, name: &str) -> Option<&FunctionUses> {
+        self.function_uses.get(&normalize(name))
+    }
+
     #[inline(always)]
     pub fn fits(&self, width: usize) -> bool {
+        if self.depth > self.max_depth {
+            return true;
+        }
+
         let eff_width = if self.newline_needed {
             self.full_width - self.indent
         } else {
@@ -76,11 +378,29 @@ impl Prettifier {
         width <= eff_width
     }
 
+    /// Get the width of the widest line laid out so far, in characters. If
+    /// this is more than the width that we were aiming for, the code couldn't
+    /// be made to fit.
+    pub fn widest_line(&self) -> usize {
+        self.text
+            .lines()
+            .map(|l| l.chars().count())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Get the number of lines laid out so far.
+    pub fn line_count(&self) -> usize {
+        self.text.lines().count()
+    }
+
     pub fn would_fit_on_new_line(&self, width: usize) -> bool {
-        width <= self.full_width - self.indent
+        self.depth > self.max_depth || width <= self.full_width - self.indent
     }
 
     pub fn indent_block(&mut self) -> bool {
+        self.depth += 1;
+
         if self.full_width - self.indent > 4 {
             self.indent += 4;
             true
@@ -90,6 +410,8 @@ impl Prettifier {
     }
 
     pub fn dedent_block(&mut self) -> bool {
+        self.depth = self.depth.saturating_sub(1);
+
         if self.indent > 3 {
             self.indent -= 4;
             true
@@ -99,6 +421,8 @@ impl Prettifier {
     }
 
     pub fn indent_small(&mut self) -> bool {
+        self.depth += 1;
+
         if self.full_width - self.indent > 2 {
             self.indent += 2;
             true
@@ -108,6 +432,8 @@ impl Prettifier {
     }
 
     pub fn dedent_small(&mut self) -> bool {
+        self.depth = self.depth.saturating_sub(1);
+
         if self.indent > 1 {
             self.indent -= 2;
             true
@@ -170,6 +496,75 @@ impl Prettifier {
         self.remaining_width = self.remaining_width.saturating_sub(n1 - n0);
     }
 
+    /// Push an identifier, arranging for it to be replaced with custom TeX if
+    /// any has been set up for it, and for it to be linked to its declaration
+    /// if anchors have been set up.
+    ///
+    /// If the identifier is too long for the rest of the line, TeX is allowed
+    /// to break it after its underscores, so that it doesn't run off the page.
+    pub fn identifier(&mut self, ident: &StringSpan) {
+        self.maybe_newline();
+        let name = ident.value.as_ref();
+
+        let offset = ident.start.location_offset();
+
+        let anchor = match self.anchors.declarations.get(&offset) {
+            Some(id) => Some(TexInsert::StartIdentifierAnchor(id.clone())),
+
+            None => {
+                let key = normalize(name);
+
+                self.scope
+                    .as_ref()
+                    .and_then(|s| self.anchors.locals.get(&(s.clone(), key.clone())))
+                    .or_else(|| self.anchors.targets.get(&key))
+                    .map(|id| TexInsert::StartIdentifierLink(id.clone()))
+            }
+        };
+
+        let anchored = anchor.is_some();
+
+        if let Some(ins) = anchor {
+            self.inserts.push((self.text.len(), ins));
+        }
+
+        let start = self.text.len();
+        let replaced = match self.identifier_tex.get(name) {
+            Some(tex) => {
+                let ins = TexInsert::Replacement(tex.clone(), name.len());
+                self.inserts.push((start, ins));
+                true
+            }
+
+            None => false,
+        };
+
+        let breakable = !replaced && name.len() > self.remaining_width;
+
+        if matches!(
+            self.macros.kind(name),
+            Some(MacroKind::Numeric | MacroKind::String)
+        ) {
+            self.with_scope(*CONSTANT_MACRO_SCOPE, |p| p.noscope_push(name));
+        } else {
+            self.noscope_push(name);
+        }
+
+        if breakable {
+            for (i, _) in name.match_indices('_') {
+                if i + 1 < name.len() {
+                    self.inserts
+                        .push((start + i + 1, TexInsert::IdentifierBreak));
+                }
+            }
+        }
+
+        if anchored {
+            self.inserts
+                .push((self.text.len(), TexInsert::EndIdentifier));
+        }
+    }
+
     pub fn space(&mut self) {
         self.text.push(' ');
         self.remaining_width = self.remaining_width.saturating_sub(1);
@@ -197,19 +592,20 @@ impl Prettifier {
     ///
     /// This is needed to deal with the XeTeX array macro hack. And maybe other
     /// things in the future?
-    fn handle_outer_inserts(
+    fn handle_outer_inserts<W: Write>(
         &self,
         i_text: usize,
         mut insert_idx: usize,
         mut i_next_insert: usize,
-    ) -> (usize, usize) {
+        dest: &mut W,
+    ) -> Result<(usize, usize), fmt::Error> {
         while i_text == i_next_insert {
             match self.inserts[insert_idx].1 {
                 // Macro hack marker specially handled at top of emit()
                 TexInsert::XetexArrayMacroHackMarker => {}
 
                 TexInsert::XetexArrayMacroHackBracket => {
-                    print!("]");
+                    write!(dest, "]")?;
                 }
 
                 _ => break,
@@ -224,26 +620,65 @@ impl Prettifier {
                 .unwrap_or(usize::MAX);
         }
 
-        (insert_idx, i_next_insert)
+        Ok((insert_idx, i_next_insert))
     }
 
     /// Handle the inserts at the given text position.
     ///
-    /// There may be 0, 1, or many to handle.
-    fn handle_inserts(
+    /// There may be 0, 1, or many to handle. If a replacement is handled, the
+    /// offset of the end of the text that it replaces is stored in `skip_to`.
+    /// Replacements are left alone at the end of a highlighted span, so that
+    /// they're styled like the text that they replace.
+    fn handle_inserts<W: Write>(
         &self,
         i_text: usize,
         mut insert_idx: usize,
         mut i_next_insert: usize,
-    ) -> (usize, usize) {
+        span_end: bool,
+        skip_to: &mut usize,
+        dest: &mut W,
+    ) -> Result<(usize, usize), fmt::Error> {
         while i_text == i_next_insert {
-            match self.inserts[insert_idx].1 {
+            match &self.inserts[insert_idx].1 {
                 TexInsert::StartModuleReference(id) => {
-                    print!("\\WebModuleReference{{{}}}{{", id);
+                    write!(dest, "\\WebModuleReference{{{}}}{{", id)?;
                 }
 
                 TexInsert::EndMacro => {
-                    print!("}}");
+                    write!(dest, "}}")?;
+                }
+
+                // Identifiers are wrapped in their own highlighting spans, so
+                // they shouldn't start in the preceding one.
+                TexInsert::StartIdentifierAnchor(_) | TexInsert::StartIdentifierLink(_)
+                    if span_end =>
+                {
+                    break
+                }
+
+                TexInsert::StartIdentifierAnchor(id) => {
+                    let module = self.anchors.module(id).unwrap_or_default();
+                    write!(dest, "\\WebIdentifierDefinition{{{}}}{{", module)?;
+                }
+
+                TexInsert::StartIdentifierLink(id) => {
+                    let module = self.anchors.module(id).unwrap_or_default();
+                    write!(dest, "\\WebIdentifierReference{{{}}}{{", module)?;
+                }
+
+                TexInsert::EndIdentifier => {
+                    write!(dest, "}}")?;
+                }
+
+                TexInsert::Replacement(..) if span_end => break,
+
+                TexInsert::Replacement(tex, len) => {
+                    write!(dest, "{}", tex)?;
+                    *skip_to = i_text + len;
+                }
+
+                TexInsert::IdentifierBreak => {
+                    write!(dest, "\\WebIdentifierBreak{{}}")?;
                 }
 
                 // Break on "outer" inserts so as not to eat them.
@@ -261,16 +696,262 @@ impl Prettifier {
                 .unwrap_or(usize::MAX);
         }
 
-        (insert_idx, i_next_insert)
+        Ok((insert_idx, i_next_insert))
+    }
+
+    /// Emit the prettified code as TeX using the macros of WEAVE's
+    /// `webmac.tex`, rather than highlighting it. See [`crate::webmac`].
+    pub fn emit_webmac<W: Write>(&self, inline: bool, dest: &mut W) -> fmt::Result {
+        let text = &self.text[..];
+        let mut tex = String::new();
+        let mut scopes: Vec<Scope> = Vec::new();
+        let mut i_op = 0;
+        let mut insert_idx = 0;
+        let mut pos = 0;
+
+        // The current indentation, in notches of `\1`.
+        let mut level = 0;
+
+        // Whether the last thing written was a macro that does its own
+        // spacing, so that a space after it should be dropped.
+        let mut spaced = false;
+
+        if !inline {
+            tex.push_str("\\Y\\P");
+        }
+
+        while pos < text.len() {
+            while let Some((_, op)) = self.ops.get(i_op).filter(|(i, _)| *i <= pos) {
+                match op {
+                    ScopeStackOp::Push(scope) => scopes.push(*scope),
+                    ScopeStackOp::Pop(n) => {
+                        scopes.truncate(scopes.len().saturating_sub(*n));
+                    }
+                    _ => {}
+                }
+
+                i_op += 1;
+            }
+
+            let mut skip_to = pos;
+
+            while let Some((_, ins)) = self.inserts.get(insert_idx).filter(|(i, _)| *i <= pos) {
+                match ins {
+                    // The reference is followed by the text `⟦N name⟧` and
+                    // then the end of the macro.
+                    TexInsert::StartModuleReference(id) => {
+                        let end = self.inserts[insert_idx..]
+                            .iter()
+                            .find(|(_, ins)| matches!(ins, TexInsert::EndMacro))
+                            .map(|(i, _)| *i)
+                            .unwrap_or(text.len());
+                        let reference = &text[pos..end];
+
+                        match reference
+                            .strip_prefix('⟦')
+                            .and_then(|r| r.strip_suffix('⟧'))
+                            .and_then(|r| r.split_once(' '))
+                        {
+                            Some((_, name)) => write!(tex, "\\X{}:{}\\X", id, name)?,
+                            None => write!(tex, "section~{}", id)?,
+                        }
+
+                        skip_to = end;
+                    }
+
+                    TexInsert::Replacement(replacement, len) => {
+                        tex.push_str(replacement);
+                        skip_to = pos + len;
+                    }
+
+                    TexInsert::XetexArrayMacroHackBracket => tex.push(']'),
+
+                    _ => {}
+                }
+
+                insert_idx += 1;
+            }
+
+            if skip_to > pos {
+                pos = skip_to;
+                continue;
+            }
+
+            if text[pos..].starts_with('\n') {
+                let rest = &text[pos + 1..];
+                let spaces = rest.len() - rest.trim_start_matches(' ').len();
+                pos += 1 + spaces;
+
+                if inline {
+                    tex.push(' ');
+                    continue;
+                }
+
+                // WEAVE doesn't leave blank lines, so break the line only
+                // once, at the end of the blank ones.
+                if rest[spaces..].starts_with('\n') {
+                    continue;
+                }
+
+                let new_level = spaces / 2;
+
+                while level < new_level {
+                    tex.push_str("\\1");
+                    level += 1;
+                }
+
+                while level > new_level {
+                    tex.push_str("\\2");
+                    level -= 1;
+                }
+
+                tex.push_str("\\6");
+                continue;
+            }
+
+            let mut end = text[pos..].find('\n').map_or(text.len(), |i| pos + i);
+
+            if let Some((i, _)) = self.ops.get(i_op) {
+                end = end.min(*i);
+            }
+
+            // WEAVE's identifiers can't be broken, so don't split them.
+            if let Some((i, _)) = self.inserts[insert_idx..]
+                .iter()
+                .find(|(_, ins)| !matches!(ins, TexInsert::IdentifierBreak))
+            {
+                end = end.min(*i);
+            }
+
+            let mut segment = &text[pos..end];
+            pos = end;
+
+            if std::mem::take(&mut spaced) {
+                segment = segment.strip_prefix(' ').unwrap_or(segment);
+            }
+
+            match scopes.last() {
+                Some(s) if *s == *KEYWORD_SCOPE => match segment {
+                    "@define" => {
+                        tex.push_str("\\D");
+                        spaced = true;
+                    }
+
+                    "@format" => {
+                        tex.push_str("\\F");
+                        spaced = true;
+                    }
+
+                    _ => {
+                        if let Some((cs, _)) = crate::webmac::operator_prefix(segment) {
+                            push_operator(cs, &mut tex);
+                            spaced = true;
+                        } else {
+                            write!(tex, "\\&{{{}}}", segment)?
+                        }
+                    }
+                },
+
+                Some(s) if *s == *STRING_LITERAL_SCOPE => {
+                    tex.push_str(&crate::webmac::string(segment))
+                }
+
+                Some(s) if *s == *COMMENT_SCOPE => {
+                    let comment = segment.trim_start_matches("//").trim();
+
+                    if !comment.is_empty() {
+                        tex.push_str("\\C{");
+
+                        for c in comment.chars() {
+                            crate::webmac::push_escaped(c, &mut tex);
+                        }
+
+                        tex.push('}');
+                    }
+                }
+
+                Some(s) if *s == *HEX_LITERAL_SCOPE && segment.starts_with("0x") => {
+                    write!(tex, "\\H{{{}}}", &segment[2..])?
+                }
+
+                Some(s) if *s == *OCTAL_LITERAL_SCOPE && segment.starts_with("@'") => {
+                    write!(tex, "\\O{{{}}}", &segment[2..])?
+                }
+
+                // Keep the underscores of grouped digits from starting an
+                // identifier.
+                Some(s) if *s == *DECIMAL_LITERAL_SCOPE => {
+                    for c in segment.chars() {
+                        crate::webmac::push_escaped(c, &mut tex);
+                    }
+                }
+
+                // Don't take the exponent of a real number for an identifier.
+                Some(s) if *s == *FLOAT_LITERAL_SCOPE => tex.push_str(segment),
+
+                _ => {
+                    let mut chars = segment.char_indices().peekable();
+
+                    while let Some((i, c)) = chars.next() {
+                        if c.is_alphabetic() || c == '_' {
+                            let mut j = i + c.len_utf8();
+
+                            while let Some((k, c)) =
+                                chars.next_if(|(_, c)| c.is_alphanumeric() || *c == '_')
+                            {
+                                j = k + c.len_utf8();
+                            }
+
+                            let word = &segment[i..j];
+
+                            if let Some((cs, _)) = crate::webmac::operator_prefix(word) {
+                                push_operator(cs, &mut tex);
+                                chars.next_if(|(_, c)| *c == ' ');
+                                spaced = chars.peek().is_none();
+                            } else {
+                                tex.push_str(&crate::webmac::word(word));
+                            }
+                        } else if let Some((cs, len)) =
+                            crate::webmac::operator_prefix(&segment[i..])
+                        {
+                            push_operator(cs, &mut tex);
+
+                            for _ in 1..len {
+                                chars.next();
+                            }
+
+                            chars.next_if(|(_, c)| *c == ' ');
+                            spaced = chars.peek().is_none();
+                        } else if c == ' ' {
+                            tex.push_str("\\ ");
+                        } else {
+                            crate::webmac::push_escaped(c, &mut tex);
+                        }
+                    }
+                }
+            }
+        }
+
+        if inline {
+            write!(dest, "{}", tex)
+        } else {
+            for _ in 0..level {
+                tex.push_str("\\2");
+            }
+
+            writeln!(dest, "{}\\par", tex)
+        }
     }
 
-    pub fn emit(self, theme: &Theme, inline: bool) {
+    /// Emit the prettified code as highlighted TeX.
+    pub fn emit<W: Write>(self, theme: &Theme, inline: bool, dest: &mut W) -> fmt::Result {
         let highlighter = Highlighter::new(theme);
         let initial_stack = ScopeStack::from_str(INITIAL_SCOPES).unwrap();
         let mut hs = HighlightState::new(&highlighter, initial_stack);
         let hi = HighlightIterator::new(&mut hs, &self.ops[..], &self.text[..], &highlighter);
         let mut insert_idx = 0;
         let mut i_text = 0;
+        let mut skip_to = 0;
 
         let xetex_array_macro_hack =
             !self.inserts.is_empty() && self.inserts[0].1.is_xetex_array_macro_hack_marker();
@@ -283,9 +964,9 @@ impl Prettifier {
 
         if xetex_array_macro_hack {
             insert_idx += 1;
-            println!("$[\\WebBeginXetexArrayMacro{{}}%");
+            writeln!(dest, "$[\\WebBeginXetexArrayMacro{{}}%")?;
         } else {
-            println!("\\begin{{{}}}%", env);
+            writeln!(dest, "\\begin{{{}}}%", env)?;
         }
 
         let mut i_next_insert = self
@@ -296,63 +977,313 @@ impl Prettifier {
 
         for (style, span) in hi {
             (insert_idx, i_next_insert) =
-                self.handle_outer_inserts(i_text, insert_idx, i_next_insert);
+                self.handle_outer_inserts(i_text, insert_idx, i_next_insert, dest)?;
 
-            print!(
+            write!(
+                dest,
                 "\\S{{{}}}{{{}}}{{",
                 ColorHexConvert(style.foreground),
                 ColorHexConvert(style.background)
-            );
+            )?;
 
             if style.font_style.intersects(FontStyle::BOLD) {
-                print!("\\bf");
+                write!(dest, "\\bf")?;
             }
 
             if style.font_style.intersects(FontStyle::ITALIC) {
-                print!("\\it");
+                write!(dest, "\\it")?;
             }
 
             if style.font_style.intersects(FontStyle::UNDERLINE) {
-                print!("\\ul");
+                write!(dest, "\\ul")?;
             }
 
-            print!("}}{{");
+            write!(dest, "}}{{")?;
 
             for c in span.chars() {
-                (insert_idx, i_next_insert) =
-                    self.handle_inserts(i_text, insert_idx, i_next_insert);
+                (insert_idx, i_next_insert) = self.handle_inserts(
+                    i_text,
+                    insert_idx,
+                    i_next_insert,
+                    false,
+                    &mut skip_to,
+                    dest,
+                )?;
+
+                if i_text < skip_to {
+                    i_text += c.len_utf8();
+                    continue;
+                }
 
                 match c {
-                    '$' => print!("\\$"),
-                    '%' => print!("\\%"),
-                    '^' => print!("\\^"),
-                    '_' => print!("\\_"),
-                    '{' => print!("\\{{"),
-                    '}' => print!("\\}}"),
-                    '#' => print!("\\#"),
-                    '\\' => print!("{{\\textbackslash}}"),
-                    '&' => print!("\\&"),
-                    '~' => print!("{{\\textasciitilde}}"),
-                    ' ' => print!("\\ "),
-                    '\n' => print!("\\WebNL\n"),
-                    other => print!("{}", other),
+                    '$' => write!(dest, "\\$")?,
+                    '%' => write!(dest, "\\%")?,
+                    '^' => write!(dest, "\\^")?,
+                    '_' => write!(dest, "\\_")?,
+                    '{' => write!(dest, "\\{{")?,
+                    '}' => write!(dest, "\\}}")?,
+                    '#' => write!(dest, "\\#")?,
+                    '\\' => write!(dest, "{{\\textbackslash}}")?,
+                    '&' => write!(dest, "\\&")?,
+                    '~' => write!(dest, "{{\\textasciitilde}}")?,
+                    ' ' => write!(dest, "\\ ")?,
+                    '\n' => writeln!(dest, "\\WebNL")?,
+                    other => write!(dest, "{}", other)?,
                 }
 
                 i_text += c.len_utf8();
             }
 
-            (insert_idx, i_next_insert) = self.handle_inserts(i_text, insert_idx, i_next_insert);
-            print!("}}");
+            (insert_idx, i_next_insert) =
+                self.handle_inserts(i_text, insert_idx, i_next_insert, true, &mut skip_to, dest)?;
+            write!(dest, "}}")?;
         }
 
-        self.handle_outer_inserts(i_text, insert_idx, i_next_insert);
-        println!("%");
+        self.handle_outer_inserts(i_text, insert_idx, i_next_insert, dest)?;
+        writeln!(dest, "%")?;
 
         if xetex_array_macro_hack {
-            println!("\\WebEndXetexArrayMacro$%");
+            writeln!(dest, "\\WebEndXetexArrayMacro$%")?;
         } else {
-            print!("\\end{{{}}}{}", env, terminator);
+            write!(dest, "\\end{{{}}}{}", env, terminator)?;
+        }
+
+        Ok(())
+    }
+
+    /// Emit the prettified code as highlighted HTML.
+    ///
+    /// Display code is wrapped in a `<pre>` element and inline code in a
+    /// `<code>` element, both with the class `tt-weave`. References to modules
+    /// become links to the fragment `#module-N`. If anchors have been set up
+    /// with [`Self::set_identifier_anchors`], declarations of identifiers get
+    /// IDs and uses link to them. The TeX-specific hacks and custom TeX for
+    /// identifiers don't apply here, so those inserts are ignored.
+    pub fn emit_html<W: Write>(self, theme: &Theme, inline: bool, dest: &mut W) -> fmt::Result {
+        let spans = self
+            .highlight_ranges(theme)
+            .into_iter()
+            .map(|(style, range)| (html_style(style), range))
+            .collect();
+
+        self.emit_html_spans(spans, inline, dest)
+    }
+
+    /// Emit the prettified code as HTML highlighted with both a light and a
+    /// dark theme.
+    ///
+    /// This is like [`Self::emit_html`], except that the styles of the
+    /// highlighted spans are given as CSS custom properties, with one set for
+    /// each theme, such as `--tt-weave-light-color` and
+    /// `--tt-weave-dark-color`. The stylesheet [`DUAL_THEME_CSS`] uses them to
+    /// pick the palette that matches the reader's `prefers-color-scheme`
+    /// setting, so it must be included in the page.
+    pub fn emit_html_dual<W: Write>(
+        self,
+        light: &Theme,
+        dark: &Theme,
+        inline: bool,
+        dest: &mut W,
+    ) -> fmt::Result {
+        let light = self.highlight_ranges(light);
+        let dark = self.highlight_ranges(dark);
+
+        // The themes may split the text into spans differently, so we split it
+        // wherever either of them does.
+        let mut spans = Vec::new();
+        let mut light = light.into_iter().peekable();
+        let mut dark = dark.into_iter().peekable();
+        let mut start = 0;
+
+        while let (Some((ls, lr)), Some((ds, dr))) = (light.peek(), dark.peek()) {
+            let end = lr.end.min(dr.end);
+            let mut style = String::new();
+            write_style_properties("light", *ls, &mut style)?;
+            write_style_properties("dark", *ds, &mut style)?;
+            spans.push((style, start..end));
+            start = end;
+
+            if lr.end == end {
+                light.next();
+            }
+
+            if dr.end == end {
+                dark.next();
+            }
+        }
+
+        self.emit_html_spans(spans, inline, dest)
+    }
+
+    /// Highlight the text, returning the style of each span of it.
+    fn highlight_ranges(&self, theme: &Theme) -> Vec<(Style, Range<usize>)> {
+        let highlighter = Highlighter::new(theme);
+        let initial_stack = ScopeStack::from_str(INITIAL_SCOPES).unwrap();
+        let mut hs = HighlightState::new(&highlighter, initial_stack);
+        let hi = HighlightIterator::new(&mut hs, &self.ops[..], &self.text[..], &highlighter);
+        let mut start = 0;
+
+        hi.map(|(style, span)| {
+            let range = start..start + span.len();
+            start = range.end;
+            (style, range)
+        })
+        .collect()
+    }
+
+    /// Emit the text as HTML, split into spans with the given `style`
+    /// attributes.
+    fn emit_html_spans<W: Write>(
+        self,
+        spans: Vec<(String, Range<usize>)>,
+        inline: bool,
+        dest: &mut W,
+    ) -> fmt::Result {
+        let mut inserts = self.inserts.iter().peekable();
+        let mut i_text = 0;
+
+        let elem = if inline { "code" } else { "pre" };
+        write!(dest, "<{} class=\"tt-weave\">", elem)?;
+
+        for (style, range) in spans {
+            write!(dest, "<span style=\"{}\">", style)?;
+
+            for c in self.text[range].chars() {
+                while let Some((_, ins)) = inserts.next_if(|t| t.0 == i_text) {
+                    write_html_insert(ins, dest)?;
+                }
+
+                match c {
+                    '<' => write!(dest, "&lt;")?,
+                    '>' => write!(dest, "&gt;")?,
+                    '&' => write!(dest, "&amp;")?,
+                    '"' => write!(dest, "&quot;")?,
+                    other => write!(dest, "{}", other)?,
+                }
+
+                i_text += c.len_utf8();
+            }
+
+            // Links that start here belong in the next span, so that the
+            // elements nest properly.
+            while let Some((_, ins)) = inserts.next_if(|t| t.0 == i_text && t.1.is_end()) {
+                write_html_insert(ins, dest)?;
+            }
+
+            write!(dest, "</span>")?;
+        }
+
+        for (_, ins) in inserts {
+            write_html_insert(ins, dest)?;
+        }
+
+        write!(dest, "</{}>", elem)
+    }
+}
+
+/// The stylesheet needed by code emitted with [`Prettifier::emit_html_dual`].
+pub const DUAL_THEME_CSS: &str = r#".tt-weave span {
+  color: var(--tt-weave-light-color);
+  background-color: var(--tt-weave-light-background);
+  font-weight: var(--tt-weave-light-font-weight);
+  font-style: var(--tt-weave-light-font-style);
+  text-decoration: var(--tt-weave-light-text-decoration);
+}
+
+@media (prefers-color-scheme: dark) {
+  .tt-weave span {
+    color: var(--tt-weave-dark-color);
+    background-color: var(--tt-weave-dark-background);
+    font-weight: var(--tt-weave-dark-font-weight);
+    font-style: var(--tt-weave-dark-font-style);
+    text-decoration: var(--tt-weave-dark-text-decoration);
+  }
+}
+"#;
+
+/// Get the inline CSS for a highlighted span.
+fn html_style(style: Style) -> String {
+    let mut css = format!(
+        "color:{};background-color:{}",
+        ColorHexConvert(style.foreground),
+        ColorHexConvert(style.background)
+    );
+
+    if style.font_style.intersects(FontStyle::BOLD) {
+        css.push_str(";font-weight:bold");
+    }
+
+    if style.font_style.intersects(FontStyle::ITALIC) {
+        css.push_str(";font-style:italic");
+    }
+
+    if style.font_style.intersects(FontStyle::UNDERLINE) {
+        css.push_str(";text-decoration:underline");
+    }
+
+    css
+}
+
+/// Write the CSS custom properties giving the style of a highlighted span in
+/// one of the palettes used by [`DUAL_THEME_CSS`].
+fn write_style_properties<W: Write>(palette: &str, style: Style, dest: &mut W) -> fmt::Result {
+    let weight = if style.font_style.intersects(FontStyle::BOLD) {
+        "bold"
+    } else {
+        "normal"
+    };
+
+    let font_style = if style.font_style.intersects(FontStyle::ITALIC) {
+        "italic"
+    } else {
+        "normal"
+    };
+
+    let decoration = if style.font_style.intersects(FontStyle::UNDERLINE) {
+        "underline"
+    } else {
+        "none"
+    };
+
+    write!(
+        dest,
+        "--tt-weave-{0}-color:{1};--tt-weave-{0}-background:{2};\
+         --tt-weave-{0}-font-weight:{3};--tt-weave-{0}-font-style:{4};\
+         --tt-weave-{0}-text-decoration:{5};",
+        palette,
+        ColorHexConvert(style.foreground),
+        ColorHexConvert(style.background),
+        weight,
+        font_style,
+        decoration
+    )
+}
+
+/// Write the HTML corresponding to a TeX insert.
+fn write_html_insert<W: Write>(ins: &TexInsert, dest: &mut W) -> fmt::Result {
+    match ins {
+        TexInsert::StartModuleReference(id) => {
+            write!(
+                dest,
+                "<a class=\"tt-weave-module-ref\" href=\"#module-{}\">",
+                id
+            )
+        }
+
+        TexInsert::StartIdentifierAnchor(id) => {
+            write!(dest, "<a class=\"tt-weave-decl\" id=\"{}\">", id)
         }
+
+        TexInsert::StartIdentifierLink(id) => {
+            write!(dest, "<a class=\"tt-weave-ident\" href=\"#{}\">", id)
+        }
+
+        TexInsert::EndMacro | TexInsert::EndIdentifier => write!(dest, "</a>"),
+
+        TexInsert::XetexArrayMacroHackMarker
+        | TexInsert::XetexArrayMacroHackBracket
+        | TexInsert::Replacement(..)
+        | TexInsert::IdentifierBreak => Ok(()),
     }
 }
 
@@ -382,11 +1313,11 @@ impl<T: RenderInline> RenderInline for Box<T> {
 
 impl<T: RenderInline> RenderInline for &T {
     fn measure_inline(&self) -> usize {
-        self.deref().measure_inline()
+        (**self).measure_inline()
     }
 
     fn render_inline(&self, dest: &mut Prettifier) {
-        self.deref().render_inline(dest)
+        (**self).render_inline(dest)
     }
 }
 
@@ -400,6 +1331,16 @@ impl<'a, T: RenderInline> RenderInline for SpanValue<'a, T> {
     }
 }
 
+impl<T: RenderInline> RenderInline for Spanned<T> {
+    fn measure_inline(&self) -> usize {
+        self.node.measure_inline()
+    }
+
+    fn render_inline(&self, dest: &mut Prettifier) {
+        self.node.render_inline(dest)
+    }
+}
+
 /// Measure how wide a sequence of items will be if rendered inline.
 ///
 /// The items are assumed to be rendered with a separator of width `sep_width`.
@@ -457,15 +1398,35 @@ pub enum TexInsert {
     /// The other component of the XeTeX array macro hack: inserts an unescaped `]`
     /// at the specified position.
     XetexArrayMacroHackBracket,
+
+    /// Insert custom TeX in place of the specified number of bytes of the
+    /// following text.
+    Replacement(String, usize),
+
+    /// Start the declaration of an identifier, with the given anchor ID. This
+    /// should be followed by an EndIdentifier.
+    StartIdentifierAnchor(String),
+
+    /// Start a use of an identifier, linking to the given anchor ID. This
+    /// should be followed by an EndIdentifier.
+    StartIdentifierLink(String),
+
+    /// End an identifier started with StartIdentifierAnchor or
+    /// StartIdentifierLink.
+    EndIdentifier,
+
+    /// Allow TeX to break a long identifier here, just after an underscore.
+    IdentifierBreak,
 }
 
 impl TexInsert {
+    /// Whether this insert ends something started by an earlier one.
+    pub fn is_end(&self) -> bool {
+        matches!(self, TexInsert::EndMacro | TexInsert::EndIdentifier)
+    }
+
     pub fn is_xetex_array_macro_hack_marker(&self) -> bool {
-        if let TexInsert::XetexArrayMacroHackMarker = self {
-            true
-        } else {
-            false
-        }
+        matches!(self, TexInsert::XetexArrayMacroHackMarker)
     }
 }
 
@@ -0,0 +1,215 @@
+//! Multi-file WEB projects.
+//!
+//! Some programs come as a family of related WEB files -- say, `mf.web` along
+//! with the WEB files of its companion utilities. A project manifest lets us
+//! process all of them in one run, sharing the highlighting theme and the
+//! weaving options. The manifest is a TOML file like this:
+//!
+//! ```toml
+//! # Where to put the generated files. Relative paths are interpreted
+//! # relative to the directory containing the manifest.
+//! output_dir = "build"
+//!
+//! # Options shared by every file in the project.
+//! mini_index = true
+//!
+//! # Custom TeX to emit in place of particular identifiers.
+//! [identifier_tex]
+//! TeX_banner = "\\TeX\\ banner"
+//!
+//! [[files]]
+//! path = "mf.web"
+//! change_file = "mf.ch"
+//!
+//! [[files]]
+//! path = "mp.web"
+//! pool_file = "mp.pool"
+//! module_map = "mp-modules.json"
+//! call_graph = "mp-calls.dot"
+//! sarif = "mp-warnings.sarif"
+//! dialect = "web"
+//!
+//! [[files]]
+//! path = "mpmath.w"
+//!
+//! [[files]]
+//! path = "oldprog.web"
+//! encoding = "latin-1"
+//! ```
+//!
+//! Each file `$name.web` is woven into `$output_dir/$name.tex`, after applying
+//! its `change_file`, if it has one. If a file has a `pool_file` setting, its
+//! string pool is written there too, relative to the output directory. Likewise
+//! for the `module_map` setting, which requests a JSON map of the file's named
+//! modules, the `call_graph` setting, which requests the file's procedure
+//! call graph in Graphviz's DOT language, and the `sarif` setting, which
+//! requests the warnings about the file in the SARIF format. Files that
+//! aren't in UTF-8 need an `encoding` setting: `latin-1` or `cp437`.
+
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+use tectonic_errors::prelude::*;
+
+use crate::{
+    dialect::Dialect,
+    encoding::InputEncoding,
+    prettify::{CommentStyle, SemicolonPolicy},
+};
+
+/// A project manifest, as deserialized from TOML.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    /// The directory in which to write the outputs.
+    #[serde(default)]
+    pub output_dir: Option<PathBuf>,
+
+    /// Whether to emit mini-indices for all of the files.
+    #[serde(default)]
+    pub mini_index: Option<bool>,
+
+    /// Whether to annotate function definitions with where they're used, in
+    /// all of the files. See [`crate::WeaveOptions::function_uses`].
+    #[serde(default)]
+    pub function_uses: Option<bool>,
+
+    /// Whether to link identifiers to their declarations, in all of the files.
+    /// See [`crate::WeaveOptions::identifier_links`].
+    #[serde(default)]
+    pub identifier_links: Option<bool>,
+
+    /// Whether to write the operators as in Pascal, in all of the files. See
+    /// [`crate::prettify::CodeStyle::preserve_operators`].
+    #[serde(default)]
+    pub preserve_operators: Option<bool>,
+
+    /// When to write the semicolons that end statements in all of the files. See
+    /// [`crate::prettify::SemicolonPolicy`].
+    #[serde(default)]
+    pub semicolons: Option<SemicolonPolicy>,
+
+    /// Whether to group the digits of long decimal literals, in all of the files. See
+    /// [`crate::prettify::CodeStyle::group_digits`].
+    #[serde(default)]
+    pub group_digits: Option<bool>,
+
+    /// Whether to write hexadecimal literals with uppercase digits, in all of the files.
+    #[serde(default)]
+    pub uppercase_hex: Option<bool>,
+
+    /// Whether to write octal literals in WEB's notation, in all of the files.
+    #[serde(default)]
+    pub preserve_octal: Option<bool>,
+
+    /// How to typeset the comments in the code in all of the files. See
+    /// [`crate::prettify::CommentStyle`].
+    #[serde(default)]
+    pub comments: Option<CommentStyle>,
+
+    /// Custom TeX to emit in place of particular identifiers in all of the
+    /// files. See [`crate::WeaveOptions::identifier_tex`].
+    #[serde(default)]
+    pub identifier_tex: HashMap<String, String>,
+
+    /// The files making up the project.
+    pub files: Vec<ManifestFile>,
+}
+
+/// One input file in a project manifest.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestFile {
+    /// The path to the WEB file.
+    pub path: PathBuf,
+
+    /// If specified, apply this change file to the WEB file.
+    #[serde(default)]
+    pub change_file: Option<PathBuf>,
+
+    /// If specified, write the file's string pool here.
+    #[serde(default)]
+    pub pool_file: Option<PathBuf>,
+
+    /// If specified, write a JSON map of the file's named modules here.
+    #[serde(default)]
+    pub module_map: Option<PathBuf>,
+
+    /// If specified, write the file's procedure call graph here, in the DOT
+    /// language.
+    #[serde(default)]
+    pub call_graph: Option<PathBuf>,
+
+    /// If specified, write the warnings about the file here, in the SARIF
+    /// format.
+    #[serde(default)]
+    pub sarif: Option<PathBuf>,
+
+    /// The dialect of the file. If unspecified, the dialect is determined from
+    /// the file extension.
+    #[serde(default)]
+    pub dialect: Option<Dialect>,
+
+    /// The encoding of the file and its change file. If unspecified, the
+    /// encoding given on the command line is used, or else UTF-8.
+    #[serde(default)]
+    pub encoding: Option<InputEncoding>,
+}
+
+impl Manifest {
+    /// Load a manifest from a TOML file.
+    ///
+    /// Relative paths in the manifest are resolved relative to the directory
+    /// containing the manifest, so that the result doesn't depend on the
+    /// current directory.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+
+        let text = atry!(
+            std::fs::read_to_string(path);
+            ["failed to read project manifest `{}`", path.display()]
+        );
+
+        let mut manifest: Manifest = atry!(
+            toml::from_str(&text);
+            ["failed to parse project manifest `{}`", path.display()]
+        );
+
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        let output_dir = base.join(manifest.output_dir.take().unwrap_or_default());
+
+        for file in &mut manifest.files {
+            file.path = base.join(&file.path);
+
+            if let Some(p) = file.change_file.take() {
+                file.change_file = Some(base.join(p));
+            }
+
+            if let Some(p) = file.pool_file.take() {
+                file.pool_file = Some(output_dir.join(p));
+            }
+
+            if let Some(p) = file.module_map.take() {
+                file.module_map = Some(output_dir.join(p));
+            }
+
+            if let Some(p) = file.call_graph.take() {
+                file.call_graph = Some(output_dir.join(p));
+            }
+
+            if let Some(p) = file.sarif.take() {
+                file.sarif = Some(output_dir.join(p));
+            }
+        }
+
+        manifest.output_dir = Some(output_dir);
+        Ok(manifest)
+    }
+
+    /// Get the directory in which outputs should be written.
+    pub fn output_dir(&self) -> &Path {
+        self.output_dir.as_deref().unwrap_or_else(|| Path::new(""))
+    }
+}
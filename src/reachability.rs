@@ -0,0 +1,119 @@
+//! Checking that the code of every named module reaches the program.
+//!
+//! TANGLE builds the program by starting from the unnamed `@p` code and
+//! expanding the module references that it finds, recursively. The code of a
+//! named module that can't be reached that way silently never makes it into
+//! the program. That happens if the module is never referenced at all, but
+//! also if it's only referenced by other unreachable modules, or only mentioned
+//! in the TeX commentary. As each fragment of code is woven,
+//! [`ReachabilityChecker::add`] records the modules that it references, and
+//! [`ReachabilityChecker::finish`] works out which are reachable.
+
+use std::collections::{BTreeMap, HashSet};
+
+use crate::{
+    diagnostics::{Lint, LintLevel, LintLevels, Warning},
+    state::ModuleId,
+    weblang::{module_reference::WebModuleReference, WebToken},
+};
+
+/// Gathers up the module references in the code, so that reachability can be
+/// worked out once all of the code has been seen.
+#[derive(Debug, Default)]
+pub struct ReachabilityChecker {
+    /// The named modules referenced by code that is always part of the
+    /// program: `@p` code, and macros, to be safe.
+    roots: Vec<ModuleId>,
+
+    /// The name of each named module, and the named modules that its code
+    /// references.
+    named: BTreeMap<ModuleId, (String, Vec<ModuleId>)>,
+
+    /// The named modules that are referenced anywhere in the code.
+    referenced: HashSet<ModuleId>,
+
+    /// Whether some of the code wasn't seen, in which case we can't say
+    /// anything.
+    incomplete: bool,
+}
+
+impl ReachabilityChecker {
+    /// Record the module references in a fragment of code. If the code is
+    /// part of a named module, `owner` is a reference to it.
+    pub fn add(&mut self, tokens: &[WebToken], owner: Option<&WebModuleReference>) {
+        let refs = match owner {
+            Some(mref) => {
+                &mut self
+                    .named
+                    .entry(mref.id)
+                    .or_insert_with(|| (mref.name.value.to_string(), Vec::new()))
+                    .1
+            }
+            None => &mut self.roots,
+        };
+
+        for tok in tokens {
+            if let WebToken::ModuleReference(mref) = tok {
+                refs.push(mref.id);
+                self.referenced.insert(mref.id);
+            }
+        }
+    }
+
+    /// Note that some code was skipped, so that reachability can't be worked
+    /// out.
+    pub fn skip(&mut self) {
+        self.incomplete = true;
+    }
+
+    /// Work out which named modules are reachable, returning warnings about the
+    /// ones that aren't.
+    pub fn finish(self, lints: &LintLevels) -> Vec<Warning> {
+        let level = lints.get(Lint::UnreferencedModule);
+
+        if self.incomplete || level == LintLevel::Allow {
+            return Vec::new();
+        }
+
+        let ReachabilityChecker {
+            roots,
+            named,
+            referenced,
+            ..
+        } = self;
+
+        let mut reached: HashSet<ModuleId> = HashSet::new();
+        let mut todo = roots;
+
+        while let Some(id) = todo.pop() {
+            if reached.insert(id) {
+                if let Some((_, refs)) = named.get(&id) {
+                    todo.extend(refs);
+                }
+            }
+        }
+
+        named
+            .into_iter()
+            .filter(|(id, _)| !reached.contains(id))
+            .map(|(id, (name, _))| {
+                let message = if referenced.contains(&id) {
+                    format!(
+                        "named module <{}> is only used by code that never reaches the program",
+                        name
+                    )
+                } else {
+                    format!("named module <{}> is never used", name)
+                };
+
+                Warning {
+                    lint: Lint::UnreferencedModule,
+                    level,
+                    module: id,
+                    message,
+                    location: None,
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,189 @@
+//! Renaming identifiers and named modules.
+//!
+//! Renaming something in a WEB program with a textual search and replace is
+//! error-prone: WEB matches identifiers without regard to case or underscores,
+//! names also turn up as plain words of the TeX commentary, and module names
+//! can be abbreviated. Here we instead use the cross-reference database of the
+//! program, described in [`crate::xref`], to find exactly where the name
+//! appears in the code, and rewrite just those places, leaving the rest of the
+//! source text as it is.
+//!
+//! By default, only the code of the program is rewritten. Identifiers in the
+//! code quoted in the TeX commentary and in comments, like `|x|`, can be
+//! rewritten too, but the plain text around them never is.
+
+use std::convert::TryFrom;
+use tectonic_errors::prelude::*;
+
+use crate::{
+    reserved::PascalReservedWord,
+    symbols::normalize,
+    xref::{XrefDatabase, XrefLocation},
+};
+
+/// The result of renaming something.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Renaming {
+    /// The rewritten source text.
+    pub text: String,
+
+    /// The number of places where the name was rewritten.
+    pub count: usize,
+}
+
+/// Replace the text at each location with `replacement`. If a location ends
+/// with the `@>` that terminates a module name, it's kept.
+fn rewrite(text: &str, mut locations: Vec<XrefLocation>, replacement: &str) -> Renaming {
+    locations.sort_unstable_by_key(|l| l.start);
+    locations.dedup_by_key(|l| l.start);
+
+    let mut rewritten = String::with_capacity(text.len());
+    let mut pos = 0;
+
+    for loc in &locations {
+        rewritten.push_str(&text[pos..loc.start]);
+        rewritten.push_str(replacement);
+
+        if text[loc.start..loc.end].ends_with("@>") {
+            rewritten.push_str("@>");
+        }
+
+        pos = loc.end;
+    }
+
+    rewritten.push_str(&text[pos..]);
+
+    Renaming {
+        text: rewritten,
+        count: locations.len(),
+    }
+}
+
+/// Determine whether a name can be used as an identifier.
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic())
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// Rename an identifier in WEB source text.
+///
+/// The `xref` must be the cross-reference database of `text`, as built by
+/// [`crate::cross_reference`]. Identifiers are matched as WEB matches them,
+/// ignoring case and underscores, so every spelling of `old_name` is replaced
+/// with `new_name`. If `in_text` is set, the identifier is also renamed in the
+/// code quoted in the TeX commentary and in comments.
+///
+/// It's an error if the identifier doesn't appear in the code, or if the new
+/// name is a reserved word or already appears in the code, since renaming
+/// would then merge two different identifiers.
+pub fn rename_identifier(
+    text: &str,
+    xref: &XrefDatabase,
+    old_name: &str,
+    new_name: &str,
+    in_text: bool,
+) -> Result<Renaming> {
+    if !is_identifier(new_name) {
+        bail!("`{}` isn't a valid identifier", new_name);
+    }
+
+    if PascalReservedWord::try_from(new_name.to_ascii_lowercase().as_str()).is_ok() {
+        bail!("`{}` is a reserved word", new_name);
+    }
+
+    let old = normalize(old_name);
+    let new = normalize(new_name);
+
+    if new != old
+        && (xref.identifiers.iter().any(|i| normalize(&i.name) == new)
+            || xref.defines.iter().any(|d| normalize(&d.name) == new))
+    {
+        bail!("`{}` already appears in the code", new_name);
+    }
+
+    let mut locations: Vec<_> = xref
+        .identifiers
+        .iter()
+        .filter(|i| in_text || i.context != "inline")
+        .filter(|i| normalize(&i.name) == old)
+        .map(|i| i.location)
+        .collect();
+
+    locations.extend(
+        xref.defines
+            .iter()
+            .filter(|d| normalize(&d.name) == old)
+            .map(|d| d.location),
+    );
+
+    if in_text {
+        locations.extend(
+            xref.comment_identifiers
+                .iter()
+                .filter(|i| normalize(&i.name) == old)
+                .map(|i| i.location),
+        );
+    }
+
+    if locations.is_empty() {
+        bail!("no identifier named `{}` appears in the code", old_name);
+    }
+
+    Ok(rewrite(text, locations, new_name))
+}
+
+/// Rename a named module in WEB source text.
+///
+/// The `xref` is as for [`rename_identifier`]. The old name may be abbreviated
+/// with a trailing `...`, as in WEB, as long as the abbreviation matches just
+/// one module. Every reference to the module in the code is replaced with the
+/// full new name, including the abbreviated ones.
+///
+/// It's an error if the module isn't referenced in the code, or if there's
+/// already a module with the new name.
+pub fn rename_module(
+    text: &str,
+    xref: &XrefDatabase,
+    old_name: &str,
+    new_name: &str,
+) -> Result<Renaming> {
+    if new_name.trim().is_empty() || new_name.contains("@>") {
+        bail!("`{}` isn't a valid module name", new_name);
+    }
+
+    let mut names: Vec<_> = xref
+        .module_references
+        .iter()
+        .map(|m| m.name.as_str())
+        .filter(|name| match old_name.strip_suffix("...") {
+            Some(prefix) => name.starts_with(prefix),
+            None => *name == old_name,
+        })
+        .collect();
+    names.sort_unstable();
+    names.dedup();
+
+    let full_name = match names[..] {
+        [] => bail!("no module named `{}` is referenced in the code", old_name),
+        [name] => name,
+        _ => bail!(
+            "the abbreviation `{}` matches several modules: `{}`",
+            old_name,
+            names.join("`, `")
+        ),
+    };
+
+    if full_name != new_name && xref.module_references.iter().any(|m| m.name == new_name) {
+        bail!("there's already a module named `{}`", new_name);
+    }
+
+    let locations = xref
+        .module_references
+        .iter()
+        .filter(|m| m.name == full_name)
+        .map(|m| m.location)
+        .collect();
+
+    Ok(rewrite(text, locations, new_name))
+}
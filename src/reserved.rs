@@ -1,11 +1,14 @@
 //! Reserved words in WEB's Pascal.
 
+#[cfg(feature = "serde")]
+use serde::Serialize;
 use std::{convert::TryFrom, fmt};
 
 /// Reserved words in WEB's Pascal.
 ///
 /// See WEAVE:64.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub enum PascalReservedWord {
     And,
     Array,
@@ -53,6 +56,22 @@ pub enum PascalReservedWord {
     Xclause,
 }
 
+impl PascalReservedWord {
+    /// Get the word as written in Pascal. This differs from the [`Display`]
+    /// text for the words that are displayed as C-like operators.
+    ///
+    /// [`Display`]: fmt::Display
+    pub fn pascal_spelling(&self) -> String {
+        match self {
+            PascalReservedWord::And => "and".to_owned(),
+            PascalReservedWord::Mod => "mod".to_owned(),
+            PascalReservedWord::Not => "not".to_owned(),
+            PascalReservedWord::Or => "or".to_owned(),
+            other => other.to_string(),
+        }
+    }
+}
+
 impl fmt::Display for PascalReservedWord {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let text = match self {
@@ -105,7 +124,7 @@ impl TryFrom<&str> for PascalReservedWord {
     fn try_from(value: &str) -> std::result::Result<Self, ()> {
         // Note: intentionally not implemented Define and Format since those are
         // WEB words, not Pascal words.
-        match value.as_ref() {
+        match value {
             "and" => Ok(PascalReservedWord::And),
             "array" => Ok(PascalReservedWord::Array),
             "begin" => Ok(PascalReservedWord::Begin),
@@ -0,0 +1,152 @@
+//! Selecting subsets of modules.
+//!
+//! When working on one part of a large WEB program, it's convenient to process
+//! only the modules of interest. These are specified with a list of module
+//! numbers and inclusive ranges, like `120-140,260`. (Later versions of WEB
+//! call modules "sections", and that's the terminology that we use in the
+//! command-line interface.)
+
+use std::{ops::RangeInclusive, str::FromStr};
+use tectonic_errors::prelude::*;
+
+use crate::state::ModuleId;
+
+/// A selection of modules.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SectionSelection(Vec<RangeInclusive<ModuleId>>);
+
+impl SectionSelection {
+    /// Determine whether the selection includes the given module.
+    pub fn contains(&self, module: ModuleId) -> bool {
+        self.0.iter().any(|r| r.contains(&module))
+    }
+
+    /// Add a range of modules to the selection.
+    pub fn add(&mut self, range: RangeInclusive<ModuleId>) {
+        self.0.push(range);
+    }
+
+    /// Check that the selection only includes modules of a program with
+    /// `n_modules` modules, so that a mistyped selection is an error rather
+    /// than quietly producing nothing.
+    pub fn check(&self, n_modules: ModuleId) -> Result<()> {
+        for range in &self.0 {
+            if *range.end() <= n_modules {
+                continue;
+            }
+
+            let what = if range.start() == range.end() {
+                format!("section {}", range.start())
+            } else {
+                format!("section range {}-{}", range.start(), range.end())
+            };
+
+            match n_modules {
+                0 => bail!("{} is out of range: the program has no sections", what),
+                1 => bail!("{} is out of range: the program only has section 1", what),
+                _ => bail!(
+                    "{} is out of range: the program has sections 1-{}",
+                    what,
+                    n_modules
+                ),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for SectionSelection {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut ranges = Vec::new();
+
+        for item in s.split(',') {
+            let item = item.trim();
+
+            let parse_one = |t: &str| -> Result<ModuleId> {
+                Ok(atry!(
+                    t.trim().parse();
+                    ["invalid section number `{}`", t.trim()]
+                ))
+            };
+
+            let range = match item.split_once('-') {
+                Some((start, end)) => parse_one(start)?..=parse_one(end)?,
+                None => {
+                    let n = parse_one(item)?;
+                    n..=n
+                }
+            };
+
+            if range.is_empty() {
+                bail!("invalid section range `{}`", item);
+            }
+
+            if *range.start() == 0 {
+                bail!("invalid section `{}`: sections are numbered from 1", item);
+            }
+
+            ranges.push(range);
+        }
+
+        Ok(SectionSelection(ranges))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(result: Result<impl std::fmt::Debug>) -> String {
+        result.unwrap_err().to_string()
+    }
+
+    #[test]
+    fn parse() {
+        let sel: SectionSelection = "120-140, 260".parse().unwrap();
+        assert!(sel.contains(120) && sel.contains(140) && sel.contains(260));
+        assert!(!sel.contains(119) && !sel.contains(141));
+
+        assert_eq!(
+            error("0".parse::<SectionSelection>()),
+            "invalid section `0`: sections are numbered from 1"
+        );
+        assert_eq!(
+            error("0-3".parse::<SectionSelection>()),
+            "invalid section `0-3`: sections are numbered from 1"
+        );
+        assert_eq!(
+            error("5-2".parse::<SectionSelection>()),
+            "invalid section range `5-2`"
+        );
+        assert_eq!(
+            error("x".parse::<SectionSelection>()),
+            "invalid section number `x`"
+        );
+    }
+
+    #[test]
+    fn out_of_range() {
+        let sel: SectionSelection = "1-3".parse().unwrap();
+        assert!(sel.check(3).is_ok());
+
+        assert_eq!(
+            error("99".parse::<SectionSelection>().unwrap().check(3)),
+            "section 99 is out of range: the program has sections 1-3"
+        );
+        assert_eq!(
+            error("2, 3-4".parse::<SectionSelection>().unwrap().check(3)),
+            "section range 3-4 is out of range: the program has sections 1-3"
+        );
+        assert_eq!(
+            error(sel.check(1)),
+            "section range 1-3 is out of range: the program only has section 1"
+        );
+        assert_eq!(
+            error(sel.check(0)),
+            "section range 1-3 is out of range: the program has no sections"
+        );
+    }
+}
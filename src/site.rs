@@ -0,0 +1,415 @@
+//! Generating a standalone HTML site for a WEB program.
+//!
+//! Whereas [`crate::weave_html`] just produces the code fragments, for
+//! embedding in other material, [`crate::weave_html_site`] wraps them up into
+//! a complete static site that can be browsed on its own. The site consists of
+//! a single page, `index.html`, with:
+//!
+//! - a sidebar listing the starred modules, as a table of contents;
+//! - a section for each module, with the anchor `#module-N`, containing the
+//!   module's code;
+//! - the index of named modules and the index of symbols, with the anchor
+//!   `#index`.
+//!
+//! The module references in the code link to the modules' anchors, and uses of
+//! identifiers link to their declarations, as with [`crate::weave_html`]. The
+//! layout is controlled by a separate stylesheet, [`STYLESHEET_NAME`], so that
+//! it can easily be replaced. If the code is highlighted with both a light and
+//! a dark theme, the stylesheet picks the one that matches the reader's
+//! preferred color scheme. As with the fragments, the TeX commentary is
+//! omitted, and the TeX of the module names and the titles of the starred
+//! modules is approximated as plain text.
+
+use std::fmt::{self, Write};
+
+use crate::{
+    index::IndexEntryKind,
+    pass2::HtmlFragment,
+    prettify::DUAL_THEME_CSS,
+    state::{ModuleId, Reference, State},
+};
+
+/// The name of the page of the site.
+pub const PAGE_NAME: &str = "index.html";
+
+/// The name of the stylesheet of the site.
+pub const STYLESHEET_NAME: &str = "tt-weave.css";
+
+/// The default stylesheet of the site.
+const STYLESHEET: &str = r#"body {
+  margin: 0;
+  font-family: sans-serif;
+  line-height: 1.4;
+}
+
+nav.tt-weave-toc {
+  position: fixed;
+  top: 0;
+  bottom: 0;
+  left: 0;
+  width: 18rem;
+  overflow-y: auto;
+  padding: 1rem;
+  box-sizing: border-box;
+  border-right: 1px solid #ccc;
+  font-size: 90%;
+}
+
+nav.tt-weave-toc ul {
+  list-style: none;
+  margin: 0;
+  padding: 0;
+}
+
+nav.tt-weave-toc li {
+  margin: 0.2rem 0;
+}
+
+nav.tt-weave-toc .tt-weave-toc-depth-1 {
+  padding-left: 1rem;
+}
+
+nav.tt-weave-toc .tt-weave-toc-depth-2 {
+  padding-left: 2rem;
+}
+
+main {
+  margin-left: 18rem;
+  padding: 1rem 2rem;
+  max-width: 60rem;
+}
+
+section.tt-weave-module {
+  margin-bottom: 2rem;
+}
+
+.tt-weave-module-number {
+  color: #888;
+  text-decoration: none;
+}
+
+.tt-weave-module-name {
+  font-style: italic;
+}
+
+pre.tt-weave {
+  padding: 0.5rem;
+  overflow-x: auto;
+}
+
+a.tt-weave-module-ref,
+a.tt-weave-ident {
+  color: inherit;
+  text-decoration: none;
+}
+
+a.tt-weave-module-ref:hover,
+a.tt-weave-ident:hover {
+  text-decoration: underline;
+}
+
+.tt-weave-index-def {
+  text-decoration: underline;
+}
+"#;
+
+/// The additions to the stylesheet for readers who prefer a dark color
+/// scheme, if the code is highlighted for them.
+const DARK_STYLESHEET: &str = r#"@media (prefers-color-scheme: dark) {
+  body {
+    background-color: #1e1e1e;
+    color: #ddd;
+  }
+
+  a {
+    color: #8ab4f8;
+  }
+
+  nav.tt-weave-toc {
+    border-right-color: #444;
+  }
+}
+"#;
+
+/// A file of a generated site.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SiteFile {
+    /// The path of the file, relative to the root of the site.
+    pub path: String,
+
+    /// The contents of the file.
+    pub contents: String,
+}
+
+/// A generated static HTML site.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct HtmlSite {
+    /// The files of the site.
+    pub files: Vec<SiteFile>,
+}
+
+/// Build a site out of the HTML fragments of a program. If `dual` is set, the
+/// fragments were highlighted with both a light and a dark theme, and the
+/// stylesheet needs to choose between them.
+pub(crate) fn build(
+    title: &str,
+    state: &State,
+    fragments: &[HtmlFragment],
+    dual: bool,
+) -> HtmlSite {
+    let mut page = String::new();
+    // Writing to a string can't fail.
+    emit_page(title, state, fragments, &mut page).unwrap();
+
+    let mut stylesheet = STYLESHEET.to_owned();
+
+    if dual {
+        stylesheet.push('\n');
+        stylesheet.push_str(DARK_STYLESHEET);
+        stylesheet.push('\n');
+        stylesheet.push_str(DUAL_THEME_CSS);
+    }
+
+    HtmlSite {
+        files: vec![
+            SiteFile {
+                path: PAGE_NAME.to_owned(),
+                contents: page,
+            },
+            SiteFile {
+                path: STYLESHEET_NAME.to_owned(),
+                contents: stylesheet,
+            },
+        ],
+    }
+}
+
+fn emit_page<W: Write>(
+    title: &str,
+    state: &State,
+    fragments: &[HtmlFragment],
+    dest: &mut W,
+) -> fmt::Result {
+    writeln!(dest, "<!DOCTYPE html>")?;
+    writeln!(dest, "<html>")?;
+    writeln!(dest, "<head>")?;
+    writeln!(dest, "<meta charset=\"utf-8\">")?;
+    writeln!(dest, "<title>{}</title>", html_escape(title))?;
+    writeln!(
+        dest,
+        "<link rel=\"stylesheet\" href=\"{}\">",
+        STYLESHEET_NAME
+    )?;
+    writeln!(dest, "</head>")?;
+    writeln!(dest, "<body>")?;
+    emit_contents(state, dest)?;
+    writeln!(dest, "<main>")?;
+    writeln!(dest, "<h1>{}</h1>", html_escape(title))?;
+
+    let mut fragments = fragments.iter().peekable();
+
+    for module in 1..=state.n_modules() {
+        emit_module(state, module, dest)?;
+
+        while let Some(frag) = fragments.next_if(|f| f.module == module) {
+            emit_fragment(frag, dest)?;
+        }
+
+        writeln!(dest, "</section>")?;
+    }
+
+    emit_index(state, dest)?;
+    writeln!(dest, "</main>")?;
+    writeln!(dest, "</body>")?;
+    writeln!(dest, "</html>")
+}
+
+/// Emit the sidebar table of contents, listing the starred modules.
+fn emit_contents<W: Write>(state: &State, dest: &mut W) -> fmt::Result {
+    writeln!(dest, "<nav class=\"tt-weave-toc\">")?;
+    writeln!(dest, "<ul>")?;
+
+    for m in state.major_modules() {
+        writeln!(
+            dest,
+            "<li class=\"tt-weave-toc-depth-{}\"><a href=\"#module-{}\">{}. {}</a></li>",
+            m.depth.clamp(0, 2),
+            m.id,
+            m.id,
+            tex_to_html(&m.desc)
+        )?;
+    }
+
+    writeln!(dest, "<li><a href=\"#index\">Index</a></li>")?;
+    writeln!(dest, "</ul>")?;
+    writeln!(dest, "</nav>")
+}
+
+/// Open the section for a module, with its heading.
+fn emit_module<W: Write>(state: &State, module: ModuleId, dest: &mut W) -> fmt::Result {
+    writeln!(
+        dest,
+        "<section class=\"tt-weave-module\" id=\"module-{}\">",
+        module
+    )?;
+
+    let number = format!(
+        "<a class=\"tt-weave-module-number\" href=\"#module-{}\">§{}</a>",
+        module, module
+    );
+
+    match state.major_modules().iter().find(|m| m.id == module) {
+        Some(m) => writeln!(dest, "<h2>{} {}</h2>", number, tex_to_html(&m.desc)),
+        None => writeln!(dest, "<h3>{}</h3>", number),
+    }
+}
+
+/// Emit a fragment of code.
+///
+/// Inline code is skipped, since it only makes sense in the context of the
+/// commentary. Code that's part of a named module is introduced by the
+/// module's name in the fragment itself.
+fn emit_fragment<W: Write>(frag: &HtmlFragment, dest: &mut W) -> fmt::Result {
+    if frag.context == "inline" {
+        return Ok(());
+    }
+
+    writeln!(dest, "{}", frag.html)
+}
+
+/// Emit the indices of named modules and symbols.
+fn emit_index<W: Write>(state: &State, dest: &mut W) -> fmt::Result {
+    writeln!(dest, "<section class=\"tt-weave-index\" id=\"index\">")?;
+    writeln!(dest, "<h2>Index</h2>")?;
+    writeln!(dest, "<h3>Named modules</h3>")?;
+    writeln!(dest, "<ul>")?;
+
+    for (name, id) in state.named_modules() {
+        write!(
+            dest,
+            "<li><span class=\"tt-weave-module-name\">⟨{} <a href=\"#module-{}\">{}</a>⟩</span>",
+            tex_to_html(name),
+            id,
+            id
+        )?;
+
+        if let Some(ixstate) = state.module_index_state(name) {
+            let uses: Vec<_> = ixstate.refs.iter().filter(|r| !r.is_definition).collect();
+
+            if !uses.is_empty() {
+                write!(dest, " Used in ")?;
+                emit_references(uses, dest)?;
+                write!(dest, ".")?;
+            }
+        }
+
+        writeln!(dest, "</li>")?;
+    }
+
+    writeln!(dest, "</ul>")?;
+    writeln!(dest, "<h3>Symbols</h3>")?;
+    writeln!(dest, "<ul>")?;
+
+    for (name, ixstate) in state.symbol_index() {
+        let text = match ixstate.kind {
+            IndexEntryKind::Normal => format!("<code>{}</code>", html_escape(name)),
+            IndexEntryKind::Roman => tex_to_html(name),
+            IndexEntryKind::Typewriter => format!("<tt>{}</tt>", html_escape(name)),
+            IndexEntryKind::Wildcard => html_escape(name),
+        };
+
+        write!(dest, "<li>{}: ", text)?;
+        emit_references(ixstate.refs.iter().collect(), dest)?;
+        writeln!(dest, "</li>")?;
+    }
+
+    writeln!(dest, "</ul>")?;
+    writeln!(dest, "</section>")
+}
+
+/// Emit links to the modules where an index entry appears, in module order.
+/// Definitions are underlined, following WEAVE's convention.
+fn emit_references<W: Write>(mut refs: Vec<&Reference>, dest: &mut W) -> fmt::Result {
+    refs.sort_by_key(|r| r.module);
+
+    for (i, r) in refs.iter().enumerate() {
+        if i > 0 {
+            write!(dest, ", ")?;
+        }
+
+        let class = if r.is_definition {
+            " class=\"tt-weave-index-def\""
+        } else {
+            ""
+        };
+
+        write!(
+            dest,
+            "<a{} href=\"#module-{}\">{}</a>",
+            class, r.module, r.module
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Escape text so that it appears literally in HTML.
+fn html_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            other => escaped.push(other),
+        }
+    }
+
+    escaped
+}
+
+/// Approximate a bit of TeX as HTML.
+///
+/// This doesn't try to interpret the TeX: control sequences are replaced by
+/// their names, so that `\TeX` becomes `TeX`, and grouping braces and math
+/// shifts are dropped. Code in `|...|` is set in a `<code>` element. This is
+/// good enough for most module names and part titles.
+fn tex_to_html(tex: &str) -> String {
+    let mut html = String::with_capacity(tex.len());
+    let mut chars = tex.chars().peekable();
+    let mut in_code = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(c) if c.is_ascii_alphabetic() => {
+                    html.push(c);
+
+                    while let Some(c) = chars.next_if(|c| c.is_ascii_alphabetic()) {
+                        html.push(c);
+                    }
+                }
+
+                Some(c) => html.push_str(&html_escape(&c.to_string())),
+                None => {}
+            },
+
+            '|' => {
+                html.push_str(if in_code { "</code>" } else { "<code>" });
+                in_code = !in_code;
+            }
+
+            '{' | '}' | '$' => {}
+            '~' => html.push(' '),
+            other => html.push_str(&html_escape(&other.to_string())),
+        }
+    }
+
+    if in_code {
+        html.push_str("</code>");
+    }
+
+    html
+}
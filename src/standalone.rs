@@ -0,0 +1,17 @@
+//! Standalone TeX documents.
+//!
+//! Normally, the woven TeX is compiled as part of a Tectonic project like the
+//! one in the `template` directory, whose preamble defines the macros that the
+//! output uses and sets up the HTML output. With
+//! [`crate::WeaveOptions::standalone`], the output is instead wrapped in
+//! [`PREAMBLE`] and [`POSTAMBLE`], which turn it into a complete LaTeX document
+//! that can be compiled to PDF on its own, as with `tectonic foo.tex`. The
+//! preamble defines the same macros for PDF output, with hyperlinked module
+//! references and bookmarks for the starred modules.
+
+/// The material emitted before everything else in a standalone document,
+/// through `\begin{document}`.
+pub const PREAMBLE: &str = include_str!("../assets/standalone-preamble.tex");
+
+/// The material emitted after everything else in a standalone document.
+pub const POSTAMBLE: &str = "\n\\end{document}\n";
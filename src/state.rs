@@ -1,21 +1,31 @@
 //! The main app state.
 
-use lexical_sort::{natural_lexical_cmp, StringSort};
+use lexical_sort::natural_lexical_cmp;
+use log::warn;
 use nom::{bytes::complete::take_while, error::ErrorKind};
 use nom_locate::position;
+use serde::Serialize;
 use std::{
     collections::{btree_map::Entry, BTreeMap, HashMap},
     convert::TryFrom,
+    fmt::{self, Write},
+    io,
+    ops::RangeInclusive,
 };
+use tectonic_errors::prelude::*;
 
 use crate::{
     control::ControlKind,
+    diagnostics::did_you_mean,
+    dialect::Dialect,
     index::IndexEntryKind,
+    macros::MacroTable,
     parse_base::{new_parse_error, ParseResult, Span, StringSpan},
     pascal_token::{match_pascal_token, FormatOverrides, PascalToken},
     reserved::PascalReservedWord,
+    symtab::SymbolTable,
     token::{next_token, take_until_terminator, Token},
-    weblang::module_reference::WebModuleReference,
+    weblang::{module_reference::WebModuleReference, ToplevelParser},
 };
 
 pub type ModuleId = usize;
@@ -41,8 +51,32 @@ impl IndexState {
     }
 }
 
+/// Information about a "major" module, one started with `@*`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MajorModule {
+    pub id: ModuleId,
+
+    /// The depth of the module in the document hierarchy. Plain `@*` modules
+    /// have depth 0; `@**` gives depth -1, and `@*N` gives depth N, following
+    /// the CWEB convention.
+    pub depth: i32,
+
+    /// The descriptive text, as TeX.
+    pub desc: String,
+}
+
+/// An entry in the JSON module map.
+#[derive(Serialize)]
+struct ModuleMapEntry<'a> {
+    name: &'a str,
+    id: ModuleId,
+    definitions: Vec<ModuleId>,
+    references: Vec<ModuleId>,
+}
+
 #[derive(Debug, Default)]
 pub struct State {
+    dialect: Dialect,
     definition_flag: bool,
 
     /// Sorted map of module names to their canonical ID numbers. The sorting
@@ -56,14 +90,140 @@ pub struct State {
 
     /// Descriptions of "major" modules. These are different than "named"
     /// modules. The descriptive text is TeX.
-    major_modules: Vec<(ModuleId, String)>,
+    major_modules: Vec<MajorModule>,
 
-    index_entries: HashMap<String, IndexState>,
+    /// The index, keyed by entry text and kind. The kind is part of the key
+    /// because WEAVE treats, say, the identifier `print` and the `@.print@>`
+    /// typewriter entry as separate entries.
+    index_entries: HashMap<(String, IndexEntryKind), IndexState>,
 
     formatted_identifiers: FormatOverrides,
+
+    /// The total number of modules in the WEB file.
+    n_modules: ModuleId,
+
+    /// The TeX text of the "limbo" section at the beginning of the WEB file.
+    limbo: String,
+
+    /// The string pool: the text of every double-quoted string in the Pascal
+    /// code that isn't a single character. String `i` gets pool number
+    /// `i + 256`.
+    string_pool: Vec<String>,
+
+    /// Lookup table from string text to pool index, since identical strings
+    /// share a pool entry.
+    string_pool_ids: HashMap<String, usize>,
+
+    /// A double-quoted string that we've seen but not yet entered into the
+    /// pool, along with the source offset of its end. WEB denotes a literal `"`
+    /// in a string by doubling it, which our lexer sees as two adjacent
+    /// strings, so we need to wait and see if the next string continues this
+    /// one.
+    pending_pool_string: Option<(String, usize)>,
+
+    /// Custom parsers for toplevels, registered by the library caller.
+    toplevel_parsers: Vec<ToplevelParser>,
+
+    /// References to named modules by abbreviated names. These are checked at
+    /// the end of the first pass, since names seen later might make them
+    /// ambiguous, or make it possible to expand them in the first place.
+    abbreviations: Vec<Abbreviation>,
+
+    /// The kinds of the macros. These are only known at the end of the first
+    /// pass, since macros are often defined in terms of macros defined later.
+    macros: MacroTable,
+}
+
+/// A reference to a named module by an abbreviated name.
+#[derive(Clone, Debug)]
+struct Abbreviation {
+    /// The abbreviation, including the trailing `...`.
+    name: String,
+
+    /// Whether the abbreviation could be expanded when it was scanned.
+    expanded: bool,
+
+    /// The module in which the reference appears.
+    module: ModuleId,
+
+    /// Whether the reference defines the named module.
+    is_definition: bool,
+
+    /// The line of the input on which the reference appears.
+    line: u32,
+}
+
+/// The number of single-character insertions, deletions, and substitutions
+/// needed to turn one string into another.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut diag = row[0];
+        row[0] = i + 1;
+
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                diag
+            } else {
+                1 + diag.min(above).min(row[j])
+            };
+            diag = above;
+        }
+    }
+
+    row[b.len()]
 }
 
+/// The modulus used in computing the string pool checksum. See TANGLE:64.
+const CHECK_SUM_PRIME: u64 = 0o3777777667;
+
 impl State {
+    pub fn new(dialect: Dialect) -> Self {
+        State {
+            dialect,
+            ..State::default()
+        }
+    }
+
+    pub fn dialect(&self) -> Dialect {
+        self.dialect
+    }
+
+    /// Register a custom parser for toplevels in the Pascal code.
+    ///
+    /// When the code is woven or otherwise analyzed, the custom parsers are
+    /// tried, in the order that they were registered, before the built-in
+    /// ones. This allows programs with idiosyncratic constructs to be handled
+    /// without changes to this crate.
+    pub fn add_toplevel_parser(&mut self, parser: ToplevelParser) {
+        self.toplevel_parsers.push(parser);
+    }
+
+    /// Get the custom parsers for toplevels in the Pascal code.
+    pub fn toplevel_parsers(&self) -> &[ToplevelParser] {
+        &self.toplevel_parsers[..]
+    }
+
+    /// Get the kinds of the macros defined by the program.
+    pub fn macros(&self) -> &MacroTable {
+        &self.macros
+    }
+
+    /// Record a macro definition, given its tokens, starting with the name of
+    /// the macro.
+    pub fn add_macro_definition(&mut self, tokens: &[PascalToken]) {
+        self.macros.define(tokens);
+    }
+
+    /// Work out the kinds of the macros. This should be called after all of
+    /// the definitions have been seen.
+    pub fn resolve_macro_kinds(&mut self) {
+        self.macros.resolve();
+    }
+
     pub fn add_index_entry<S: Into<String>>(
         &mut self,
         text: S,
@@ -72,15 +232,17 @@ impl State {
     ) {
         let text = text.into(); // sigh - rust-lang/rust#51604
 
-        if !self.definition_flag {
-            if text.len() == 1 || PascalReservedWord::try_from(&text[..]).is_ok() {
-                return;
-            }
+        if !self.definition_flag
+            && (text.len() == 1
+                || (kind == IndexEntryKind::Normal
+                    && PascalReservedWord::try_from(&text[..]).is_ok()))
+        {
+            return;
         }
 
         let refs = &mut self
             .index_entries
-            .entry(text)
+            .entry((text, kind))
             .or_insert(IndexState::new(kind))
             .refs;
 
@@ -99,6 +261,134 @@ impl State {
         self.definition_flag = false;
     }
 
+    pub fn set_limbo<S: Into<String>>(&mut self, text: S) {
+        self.limbo = text.into();
+    }
+
+    pub fn limbo(&self) -> &str {
+        &self.limbo
+    }
+
+    /// Record a double-quoted string literal from the Pascal code, for the
+    /// purposes of building the string pool. The offsets are the locations of
+    /// the start and end of the string literal in the source, including the
+    /// delimiters.
+    pub fn add_pool_string_piece(&mut self, text: &str, start: usize, end: usize) {
+        if let Some((ref mut pending, ref mut pending_end)) = self.pending_pool_string {
+            if *pending_end == start {
+                pending.push('"');
+                pending.push_str(text);
+                *pending_end = end;
+                return;
+            }
+        }
+
+        self.finish_string_pool();
+        self.pending_pool_string = Some((text.to_owned(), end));
+    }
+
+    /// Finish building the string pool. This should be called after all
+    /// string literals have been seen.
+    ///
+    /// See TANGLE:64. Single-character strings stand for their character codes
+    /// and don't go into the pool.
+    pub fn finish_string_pool(&mut self) {
+        if let Some((text, _)) = self.pending_pool_string.take() {
+            if text.chars().count() != 1 && !self.string_pool_ids.contains_key(&text) {
+                self.string_pool_ids
+                    .insert(text.clone(), self.string_pool.len());
+                self.string_pool.push(text);
+            }
+        }
+    }
+
+    /// Compute the string pool checksum, as represented by `@$` in the WEB
+    /// source. See TANGLE:64.
+    pub fn string_pool_checksum(&self) -> u64 {
+        fn accumulate(sum: u64, value: u64) -> u64 {
+            let mut sum = sum + sum + value;
+
+            while sum > CHECK_SUM_PRIME {
+                sum -= CHECK_SUM_PRIME;
+            }
+
+            sum
+        }
+
+        let mut sum = 271828;
+
+        for text in &self.string_pool {
+            sum = accumulate(sum, text.chars().count() as u64);
+
+            for c in text.chars() {
+                sum = accumulate(sum, c as u64);
+            }
+        }
+
+        sum
+    }
+
+    /// Write out the string pool in the format of a TANGLE `.pool` file.
+    ///
+    /// Each string is written on its own line, preceded by its length as two
+    /// decimal digits. The final line is a `*` followed by the checksum as nine
+    /// decimal digits.
+    pub fn write_pool_file<W: io::Write>(&self, mut dest: W) -> Result<()> {
+        for text in &self.string_pool {
+            let n = text.chars().count();
+
+            if n > 99 {
+                warn!("preprocessed string is too long: {:?}", text);
+            }
+
+            writeln!(dest, "{:02}{}", n, text)?;
+        }
+
+        writeln!(dest, "*{:09}", self.string_pool_checksum())?;
+        Ok(())
+    }
+
+    /// Write out a JSON map of the named modules, for the benefit of external
+    /// tools.
+    ///
+    /// The map is an array with one object per named module, sorted by name.
+    /// Each object gives the module's `name` and `id`, along with the IDs of
+    /// the modules that contribute to its `definitions` and those that contain
+    /// `references` to it.
+    pub fn write_module_map<W: io::Write>(&self, dest: W) -> Result<()> {
+        let entries: Vec<_> = self
+            .named_modules
+            .iter()
+            .map(|(name, id)| {
+                let refs = self
+                    .module_index_state(name)
+                    .map(|ixstate| &ixstate.refs[..])
+                    .unwrap_or_default();
+
+                let (defs, refs): (Vec<&Reference>, Vec<_>) =
+                    refs.iter().partition(|r| r.is_definition);
+
+                ModuleMapEntry {
+                    name,
+                    id: *id,
+                    definitions: defs.iter().map(|r| r.module).collect(),
+                    references: refs.iter().map(|r| r.module).collect(),
+                }
+            })
+            .collect();
+
+        serde_json::to_writer_pretty(dest, &entries)?;
+        Ok(())
+    }
+
+    pub fn register_module(&mut self, module: ModuleId) {
+        self.n_modules = module;
+    }
+
+    pub fn n_modules(&self) -> ModuleId {
+        self.n_modules
+    }
+
     pub fn set_definition_flag(&mut self, f: bool) {
         self.definition_flag = f;
     }
@@ -111,6 +401,23 @@ impl State {
         self.formatted_identifiers.insert(text.into(), equiv);
     }
 
+    /// Format an identifier like another identifier: the same as a reserved
+    /// word if `other` has been formatted as one, and otherwise as an ordinary
+    /// identifier.
+    pub fn format_like<S: Into<String>>(&mut self, text: S, other: &str) {
+        let text = text.into();
+
+        match self.formatted_identifiers.get(other).copied() {
+            Some(equiv) => {
+                self.formatted_identifiers.insert(text, equiv);
+            }
+
+            None => {
+                self.formatted_identifiers.remove(&text);
+            }
+        }
+    }
+
     pub fn match_pascal_token_with_formats<'a>(
         &self,
         span: Span<'a>,
@@ -137,10 +444,28 @@ impl State {
     /// Scan the name of a WEB module.
     ///
     /// Module names are terminated by a terminator control token. Leading and
-    /// trailing whitespace are eaten, and inner whitespace is collapsed.
+    /// trailing whitespace are eaten, and inner whitespace is collapsed. Names
+    /// abbreviated with a trailing `...` are expanded; it's an error if that
+    /// can't be done unambiguously.
     ///
     /// See WEAVE:103-104.
     pub fn scan_module_name<'a>(&self, span: Span<'a>) -> ParseResult<'a, StringSpan<'a>> {
+        let (span, name) = self.scan_literal_module_name(span)?;
+
+        match self.expand_module_name(&name.value) {
+            Some(full) => Ok((
+                span,
+                StringSpan {
+                    value: full.into(),
+                    ..name
+                },
+            )),
+            None => new_parse_error(span, ErrorKind::Fail),
+        }
+    }
+
+    /// Scan the name of a WEB module, without expanding abbreviations.
+    fn scan_literal_module_name<'a>(&self, span: Span<'a>) -> ParseResult<'a, StringSpan<'a>> {
         let (span, start) = position(span)?;
         let (mut span, _) = take_while(|c| c == ' ' || c == '\t' || c == '\n')(span)?;
 
@@ -173,43 +498,43 @@ impl State {
 
         let (span, end) = position(span)?;
 
-        // Now (ab)use the module name table to do the prefix match if we need to
-
-        let matched_name = if let Some(body) = value.strip_suffix("...") {
-            let mut matched_name = String::new();
-
-            for item in self
-                .named_modules
-                .range(body.to_owned()..)
-                .take_while(|i| i.0.starts_with(body))
-            {
-                if matched_name.is_empty() {
-                    matched_name.push_str(&item.0);
-                } else {
-                    return new_parse_error(span, ErrorKind::Fail);
-                }
-            }
-            if matched_name.is_empty() {
-                return new_parse_error(span, ErrorKind::Fail);
-            }
-
-            matched_name
-        } else {
-            value
-        };
-
         Ok((
             span,
             StringSpan {
                 start,
                 end,
-                value: matched_name.into(),
+                value: value.into(),
             },
         ))
     }
 
-    pub fn register_major_module<S: ToString>(&mut self, module: ModuleId, desc: S) {
-        self.major_modules.push((module, desc.to_string()));
+    /// Get the full name of a module, which may be abbreviated with a trailing
+    /// `...`. Returns `None` if an abbreviation doesn't match exactly one of the
+    /// names seen so far.
+    fn expand_module_name(&self, name: &str) -> Option<String> {
+        let body = match name.strip_suffix("...") {
+            Some(b) => b,
+            None => return Some(name.to_owned()),
+        };
+
+        // (Ab)use the module name table to do the prefix match.
+        let mut matches = self
+            .named_modules
+            .range(body.to_owned()..)
+            .take_while(|i| i.0.starts_with(body));
+
+        match (matches.next(), matches.next()) {
+            (Some(m), None) => Some(m.0.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn register_major_module<S: ToString>(&mut self, module: ModuleId, depth: i32, desc: S) {
+        self.major_modules.push(MajorModule {
+            id: module,
+            depth,
+            desc: desc.to_string(),
+        });
     }
 
     pub fn scan_module_name_and_register<'a>(
@@ -217,18 +542,164 @@ impl State {
         module: ModuleId,
         span: Span<'a>,
     ) -> ParseResult<'a, StringSpan<'a>> {
-        let (span, text) = self.scan_module_name(span)?;
+        let (span, text) = self.scan_literal_module_name(span)?;
+        let expansion = self.expand_module_name(&text.value);
+
+        if text.value.ends_with("...") {
+            self.abbreviations.push(Abbreviation {
+                name: text.value.to_string(),
+                expanded: expansion.is_some(),
+                module,
+                is_definition: self.definition_flag,
+                line: text.start.location_line(),
+            });
+        }
+
+        let name = match expansion {
+            Some(n) => n,
+
+            None => {
+                // The full name might not have been seen yet, so try again at
+                // the end of the pass.
+                self.definition_flag = false;
+                return Ok((span, text));
+            }
+        };
 
         // This might be a reference to the module name but not its actual
         // definition, so we should just insert a placeholder module ID for now.
-        self.named_modules.insert(text.value.to_string(), 0);
+        self.named_modules.insert(name.clone(), 0);
+
+        self.add_index_entry(name.clone(), IndexEntryKind::Normal, module);
+        Ok((
+            span,
+            StringSpan {
+                value: name.into(),
+                ..text
+            },
+        ))
+    }
+
+    /// Check the abbreviated module names now that all of the full names are
+    /// known, expanding the ones that couldn't be expanded when they were
+    /// scanned.
+    ///
+    /// This should be called at the end of the first pass. It's an error if an
+    /// abbreviation doesn't match exactly one name.
+    pub fn resolve_module_abbreviations(&mut self) -> Result<()> {
+        for pending in std::mem::take(&mut self.abbreviations) {
+            let body = pending.name.strip_suffix("...").unwrap_or(&pending.name);
+            let matches: Vec<String> = self
+                .named_modules
+                .range(body.to_owned()..)
+                .take_while(|i| i.0.starts_with(body))
+                .map(|i| i.0.clone())
+                .collect();
+
+            match &matches[..] {
+                [_] if pending.expanded => {}
+
+                [full] => {
+                    self.definition_flag = pending.is_definition;
+                    self.add_index_entry(full.clone(), IndexEntryKind::Normal, pending.module);
+
+                    if let Some(ixstate) = self
+                        .index_entries
+                        .get_mut(&(full.clone(), IndexEntryKind::Normal))
+                    {
+                        ixstate.refs.sort_by_key(|r| r.module);
+                    }
+                }
+
+                [] => {
+                    let mut msg = format!(
+                        "line {}: the module name <{}> doesn't match any named module",
+                        pending.line, pending.name
+                    );
+
+                    if let Some(s) = did_you_mean(&self.suggest_module_names(&pending.name)) {
+                        write!(msg, "; {}", s)?;
+                    }
 
-        self.add_index_entry(text.value.to_string(), IndexEntryKind::Normal, module);
-        Ok((span, text))
+                    bail!("{}", msg);
+                }
+
+                _ => {
+                    let names: Vec<_> = matches.iter().map(|m| format!("<{}>", m)).collect();
+                    bail!(
+                        "line {}: the module name <{}> is ambiguous: it could be {}",
+                        pending.line,
+                        pending.name,
+                        names.join(" or ")
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the names of the defined named modules that are most similar to a
+    /// name that doesn't match any of them, best first. If the name is
+    /// abbreviated with a trailing `...`, it's compared against the start of
+    /// each defined name.
+    pub fn suggest_module_names(&self, name: &str) -> Vec<&str> {
+        let (body, abbreviated) = match name.strip_suffix("...") {
+            Some(b) => (b, true),
+            None => (name, false),
+        };
+
+        let n_body = body.chars().count();
+        let max_distance = (n_body / 3).max(2);
+
+        let mut scored: Vec<_> = self
+            .named_modules
+            .keys()
+            .filter(|candidate| {
+                self.module_index_state(candidate)
+                    .is_some_and(|ixstate| ixstate.refs.iter().any(|r| r.is_definition))
+            })
+            .filter_map(|candidate| {
+                let d = if abbreviated {
+                    let prefix: String = candidate.chars().take(n_body).collect();
+                    edit_distance(body, &prefix)
+                } else {
+                    edit_distance(body, candidate)
+                };
+
+                (d <= max_distance).then_some((d, candidate.as_str()))
+            })
+            .collect();
+
+        scored.sort();
+        scored.into_iter().take(3).map(|(_, c)| c).collect()
+    }
+
+    /// Get the named modules that are used but never defined, along with the
+    /// first module that uses each one.
+    pub fn undefined_modules(&self) -> Vec<(&str, ModuleId)> {
+        self.named_modules
+            .keys()
+            .filter_map(|name| {
+                let ixstate = self.module_index_state(name)?;
+
+                if ixstate.refs.iter().any(|r| r.is_definition) {
+                    None
+                } else {
+                    ixstate
+                        .refs
+                        .iter()
+                        .map(|r| r.module)
+                        .min()
+                        .map(|m| (name.as_str(), m))
+                }
+            })
+            .collect()
     }
 
     /// For the second pass: scan a module name and resolve it to a full module
-    /// reference, looking up the module-id.
+    /// reference, looking up the module-id. Module names that are never defined
+    /// get an ID of zero.
     pub fn scan_module_reference<'a>(
         &self,
         span: Span<'a>,
@@ -244,7 +715,11 @@ impl State {
     }
 
     pub fn compute_module_ids(&mut self) {
-        for (name, info) in &self.index_entries {
+        for ((name, kind), info) in &self.index_entries {
+            if *kind != IndexEntryKind::Normal {
+                continue;
+            }
+
             if let Entry::Occupied(mut occ) = self.named_modules.entry(name.clone()) {
                 for r in &info.refs {
                     if r.is_definition {
@@ -256,37 +731,188 @@ impl State {
         }
     }
 
+    /// Get the modules that contribute code to a named module, in order.
+    ///
+    /// As in WEB, the name may be abbreviated with a trailing `...`, so long as
+    /// the abbreviation is unambiguous.
+    pub fn named_module_contributors(&self, name: &str) -> Result<Vec<ModuleId>> {
+        let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+
+        let full_name = if let Some(body) = name.strip_suffix("...") {
+            let mut matches = self
+                .named_modules
+                .range(body.to_owned()..)
+                .take_while(|i| i.0.starts_with(body))
+                .map(|i| i.0);
+
+            match (matches.next(), matches.next()) {
+                (Some(n), None) => n.as_str(),
+                (None, _) => bail!("no named module matches `{}`", name),
+                (Some(_), Some(_)) => bail!("the module name `{}` is ambiguous", name),
+            }
+        } else if self.named_modules.contains_key(&name) {
+            name.as_str()
+        } else {
+            bail!("no such named module `{}`", name);
+        };
+
+        Ok(self
+            .module_index_state(full_name)
+            .map(|ixstate| {
+                ixstate
+                    .refs
+                    .iter()
+                    .filter(|r| r.is_definition)
+                    .map(|r| r.module)
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    /// Get the modules making up the starred part that starts with the given
+    /// module.
+    ///
+    /// The part extends up to the next major module of the same or a higher
+    /// level, or the end of the program.
+    pub fn major_module_part(&self, module: ModuleId) -> Result<RangeInclusive<ModuleId>> {
+        let pos = a_ok_or!(
+            self.major_modules.iter().position(|m| m.id == module);
+            ["module {} is not a starred module", module]
+        );
+        let depth = self.major_modules[pos].depth;
+
+        let end = self.major_modules[pos + 1..]
+            .iter()
+            .find(|m| m.depth <= depth)
+            .map(|m| m.id - 1)
+            .unwrap_or(self.n_modules);
+
+        Ok(module..=end)
+    }
+
+    /// Get the identifiers whose index entries are marked as definitions,
+    /// whether by `@d`, `@f`, or `@!`.
+    pub fn defined_identifiers(&self) -> impl Iterator<Item = &str> {
+        self.index_entries
+            .iter()
+            .filter(|((_, kind), ixstate)| {
+                *kind == IndexEntryKind::Normal && ixstate.refs.iter().any(|r| r.is_definition)
+            })
+            .map(|((name, _), _)| name.as_str())
+    }
+
+    /// Get the major modules, in order.
+    pub fn major_modules(&self) -> &[MajorModule] {
+        &self.major_modules[..]
+    }
+
+    /// Get the named modules and their IDs, sorted by name.
+    pub fn named_modules(&self) -> impl Iterator<Item = (&str, ModuleId)> {
+        self.named_modules
+            .iter()
+            .map(|(name, id)| (name.as_str(), *id))
+    }
+
+    /// Get the index information for a named module.
+    pub fn module_index_state(&self, name: &str) -> Option<&IndexState> {
+        self.index_entries
+            .get(&(name.to_owned(), IndexEntryKind::Normal))
+    }
+
+    /// Get the index entries that don't correspond to named modules, in the
+    /// order in which they should appear in the index.
+    pub fn symbol_index(&self) -> Vec<(&str, &IndexState)> {
+        self.sorted_symbol_index_keys()
+            .into_iter()
+            .map(|key| (key.0.as_str(), self.index_entries.get(key).unwrap()))
+            .collect()
+    }
+
+    /// Get the index entries that don't correspond to named modules, sorted
+    /// in the order in which they should appear in the index.
+    fn sorted_symbol_index_keys(&self) -> Vec<&(String, IndexEntryKind)> {
+        let mut index: Vec<_> = self
+            .index_entries
+            .keys()
+            .filter(|(name, kind)| {
+                *kind != IndexEntryKind::Normal || !self.named_modules.contains_key(name)
+            })
+            .collect();
+
+        index.sort_unstable_by(|a, b| natural_lexical_cmp(&a.0, &b.0).then(a.1.cmp(&b.1)));
+        index
+    }
+
     /// Emit the index of major modules.
     ///
     /// The structure of the emitted TeX is:
     ///
-    /// ```
+    /// ```tex
     /// \begin{WebMajorModuleIndex}
-    ///   \WebMajorModuleIndexEntry{$id}{$desc}
+    ///   \WebMajorModuleIndexEntry{$id}{$depth}{$desc}
     /// \end{WebMajorModuleIndex}
     /// ```
     ///
-    /// So, you should define an environment for the index, and 4-parameter
-    /// command for dealing with each index entry. The command should define a
-    /// `\mref` helper macro to do whatever makes sense for your implementation.
+    /// where $depth is the module's depth in the hierarchy (see
+    /// [`MajorModule::depth`]).
     ///
-    /// Note that the index will be sorted by module name, not module id!
-    pub fn emit_major_module_index(&self) {
-        println!();
-        println!("\\begin{{WebMajorModuleIndex}}");
+    /// So, you should define an environment for the index, and a 3-parameter
+    /// command for dealing with each index entry. The entries are in the order
+    /// of the modules in the source.
+    pub fn emit_major_module_index<W: Write>(&self, dest: &mut W) -> fmt::Result {
+        writeln!(dest)?;
+        writeln!(dest, "\\begin{{WebMajorModuleIndex}}")?;
 
-        for (id, desc) in &self.major_modules {
-            println!("  \\WebMajorModuleIndexEntry{{{}}}{{{}}}", id, desc);
+        for m in &self.major_modules {
+            writeln!(
+                dest,
+                "  \\WebMajorModuleIndexEntry{{{}}}{{{}}}{{{}}}",
+                m.id, m.depth, m.desc
+            )?;
         }
 
-        println!("\\end{{WebMajorModuleIndex}}");
+        writeln!(dest, "\\end{{WebMajorModuleIndex}}")?;
+
+        Ok(())
+    }
+
+    /// Emit the table of contents.
+    ///
+    /// This is built from the same information as the major module index, but
+    /// is meant to be typeset at the beginning of the document. The structure
+    /// of the emitted TeX is:
+    ///
+    /// ```tex
+    /// \begin{WebContents}
+    ///   \WebContentsEntry{$id}{$depth}{$desc}
+    /// \end{WebContents}
+    /// ```
+    ///
+    /// Here the entries are in module order, and $depth is the module's depth
+    /// in the hierarchy (see [`MajorModule::depth`]). The module ID is the hook that
+    /// allows the TeX implementation to link to the module or look up its page
+    /// number.
+    pub fn emit_table_of_contents<W: Write>(&self, dest: &mut W) -> fmt::Result {
+        writeln!(dest, "\\begin{{WebContents}}")?;
+
+        for m in &self.major_modules {
+            writeln!(
+                dest,
+                "  \\WebContentsEntry{{{}}}{{{}}}{{{}}}",
+                m.id, m.depth, m.desc
+            )?;
+        }
+
+        writeln!(dest, "\\end{{WebContents}}")?;
+
+        Ok(())
     }
 
     /// Emit the index of named modules.
     ///
     /// The structure of the emitted TeX is:
     ///
-    /// ```
+    /// ```tex
     /// \begin{WebNamedModuleIndex}
     ///   \WebNamedModuleIndexEntry{$id}{$name}{
     ///     % Modules contributing to the definition of the code:
@@ -308,35 +934,41 @@ impl State {
     /// `\mref` helper macro to do whatever makes sense for your implementation.
     ///
     /// Note that the index will be sorted by module name, not module id!
-    pub fn emit_named_module_index(&self) {
-        println!();
-        println!("\\begin{{WebNamedModuleIndex}}");
+    pub fn emit_named_module_index<W: Write>(&self, dest: &mut W) -> fmt::Result {
+        writeln!(dest)?;
+        writeln!(dest, "\\begin{{WebNamedModuleIndex}}")?;
 
         for (name, id) in self.named_modules.iter() {
-            println!("  \\WebNamedModuleIndexEntry{{{}}}{{{}}}{{%", id, name);
+            writeln!(
+                dest,
+                "  \\WebNamedModuleIndexEntry{{{}}}{{{}}}{{%",
+                id, name
+            )?;
 
-            if let Some(ixstate) = self.index_entries.get(&**name) {
+            if let Some(ixstate) = self.module_index_state(name) {
                 for r in &ixstate.refs {
                     if r.is_definition {
-                        println!("    \\mref{{{}}}%", r.module);
+                        writeln!(dest, "    \\mref{{{}}}%", r.module)?;
                     }
                 }
             }
 
-            println!("  }}{{%");
+            writeln!(dest, "  }}{{%")?;
 
-            if let Some(ixstate) = self.index_entries.get(&**name) {
+            if let Some(ixstate) = self.module_index_state(name) {
                 for r in &ixstate.refs {
                     if !r.is_definition {
-                        println!("    \\mref{{{}}}%", r.module);
+                        writeln!(dest, "    \\mref{{{}}}%", r.module)?;
                     }
                 }
             }
 
-            println!("  }}%");
+            writeln!(dest, "  }}%")?;
         }
 
-        println!("\\end{{WebNamedModuleIndex}}");
+        writeln!(dest, "\\end{{WebNamedModuleIndex}}")?;
+
+        Ok(())
     }
 
     /// Emit the index of non-module symbols.
@@ -346,7 +978,7 @@ impl State {
     /// index entry command is:
     ///
     /// ```tex
-    /// \WebSymbolIndexEntry{$text}{$kind}{$definers}{$references}
+    /// \WebSymbolIndexEntry{$text}{$kind}{$definers}{$references}{$declared}
     /// ```
     ///
     /// where $text is the index entry text and $kind is one of the four
@@ -354,21 +986,18 @@ impl State {
     /// $defines and $references are sequences of `\mref` strings as in the
     /// named module index. *Most* symbols have one definition, but some have
     /// zero (including `\output` strings) and some have multiple (especially
-    /// variables with single-character names).
-    pub fn emit_symbol_index(&self) {
-        println!();
-        println!("\\begin{{WebSymbolIndex}}");
+    /// variables with single-character names). For `\code` entries, $declared
+    /// is what the identifier is declared as according to `symbols`, such as
+    /// `variable` or `macro` (see [`crate::symtab::SymbolKind::name`]), and it's
+    /// empty otherwise.
+    pub fn emit_symbol_index<W: Write>(&self, symbols: &SymbolTable, dest: &mut W) -> fmt::Result {
+        writeln!(dest)?;
+        writeln!(dest, "\\begin{{WebSymbolIndex}}")?;
 
-        let mut index: Vec<_> = self.index_entries.keys().collect();
-        index.string_sort_unstable(natural_lexical_cmp);
-
-        for name in &index {
-            // Named modules are dealt with separately.
-            if self.named_modules.contains_key(&**name) {
-                continue;
-            }
-
-            let info = self.index_entries.get(&**name).unwrap();
+        // Named modules are dealt with separately.
+        for key in self.sorted_symbol_index_keys() {
+            let name = &key.0;
+            let info = self.index_entries.get(key).unwrap();
 
             let kind = match info.kind {
                 IndexEntryKind::Normal => "code",
@@ -377,25 +1006,206 @@ impl State {
                 IndexEntryKind::Wildcard => "custom",
             };
 
-            println!("  \\WebSymbolIndexEntry{{{}}}{{\\{}}}{{%", name, kind);
+            writeln!(dest, "  \\WebSymbolIndexEntry{{{}}}{{\\{}}}{{%", name, kind)?;
 
             for r in &info.refs {
                 if r.is_definition {
-                    println!("    \\mref{{{}}}%", r.module);
+                    writeln!(dest, "    \\mref{{{}}}%", r.module)?;
                 }
             }
 
-            println!("  }}{{%");
+            writeln!(dest, "  }}{{%")?;
 
             for r in &info.refs {
                 if !r.is_definition {
-                    println!("    \\mref{{{}}}%", r.module);
+                    writeln!(dest, "    \\mref{{{}}}%", r.module)?;
+                }
+            }
+
+            let declared = match info.kind {
+                IndexEntryKind::Normal => symbols.kind(name).map(|k| k.name()),
+                _ => None,
+            };
+
+            writeln!(dest, "  }}{{{}}}", declared.unwrap_or_default())?;
+        }
+
+        writeln!(dest, "\\end{{WebSymbolIndex}}")?;
+
+        Ok(())
+    }
+
+    /// Emit the typeset index of symbols.
+    ///
+    /// Unlike the other indices, which are just data to be consumed by the
+    /// HTML chrome, this one is meant to be rendered in the document itself,
+    /// like the index at the end of a WEAVE'd program. The structure of the
+    /// emitted TeX is:
+    ///
+    /// ```tex
+    /// \begin{WebIndex}
+    ///   \WebIndexEntry{$kind}{$text}{\WebIndexDef{$id1}, \WebIndexRef{$id2}}
+    /// \end{WebIndex}
+    /// ```
+    ///
+    /// where $kind is one of `\code`, `\prose`, `\output`, or `\custom` as in
+    /// the symbol index. For `\code` and `\output` entries, $text is escaped
+    /// for TeX; `\prose` entries are passed through unchanged, since they *are*
+    /// TeX; and `\custom` entries are wrapped in a `\9{...}` macro call.
+    ///
+    /// The references are listed in module order, with `\WebIndexDef` used for
+    /// modules where the symbol is defined, which should be underlined
+    /// following WEAVE's convention.
+    pub fn emit_typeset_index<W: Write>(&self, dest: &mut W) -> fmt::Result {
+        writeln!(dest)?;
+        writeln!(dest, "\\begin{{WebIndex}}")?;
+
+        for key in self.sorted_symbol_index_keys() {
+            let name = &key.0;
+            let info = self.index_entries.get(key).unwrap();
+
+            // As in WEAVE, the text of a `@:` entry is passed to the `\9`
+            // macro, so that it can be split into a sort key and display text.
+            let (kind, text) = match info.kind {
+                IndexEntryKind::Normal => ("code", tex_escape(name)),
+                IndexEntryKind::Roman => ("prose", name.to_string()),
+                IndexEntryKind::Typewriter => ("output", tex_escape(name)),
+                IndexEntryKind::Wildcard => ("custom", format!("\\9{{{}}}", name)),
+            };
+
+            write!(dest, "  \\WebIndexEntry{{\\{}}}{{{}}}{{", kind, text)?;
+
+            let mut refs: Vec<_> = info.refs.iter().collect();
+            refs.sort_by_key(|r| r.module);
+            let mut first = true;
+
+            for r in refs {
+                if first {
+                    first = false;
+                } else {
+                    write!(dest, ", ")?;
+                }
+
+                if r.is_definition {
+                    write!(dest, "\\WebIndexDef{{{}}}", r.module)?;
+                } else {
+                    write!(dest, "\\WebIndexRef{{{}}}", r.module)?;
                 }
             }
 
-            println!("  }}");
+            writeln!(dest, "}}")?;
         }
 
-        println!("\\end{{WebSymbolIndex}}");
+        writeln!(dest, "\\end{{WebIndex}}")?;
+
+        Ok(())
+    }
+    /// Emit the "mini-index" for a single module.
+    ///
+    /// This lists the identifiers used in the module, along with the modules
+    /// where they are defined, similar to the mini-indices produced by CTWILL.
+    /// Identifiers defined in the module itself, or not defined anywhere, are
+    /// not listed. The structure of the emitted TeX is:
+    ///
+    /// ```tex
+    /// \begin{WebMiniIndex}
+    ///   \WebMiniIndexEntry{$text}{\mref{$id1}\mref{$id2}}
+    /// \end{WebMiniIndex}
+    /// ```
+    ///
+    /// where $text is escaped for TeX. Nothing is emitted if there are no
+    /// entries.
+    pub fn emit_mini_index<W: Write>(&self, module: ModuleId, dest: &mut W) -> fmt::Result {
+        let mut entries = Vec::new();
+
+        for ((name, kind), info) in &self.index_entries {
+            if *kind != IndexEntryKind::Normal || self.named_modules.contains_key(name) {
+                continue;
+            }
+
+            let mut used_here = false;
+            let mut definers = Vec::new();
+
+            for r in &info.refs {
+                if r.module == module {
+                    if r.is_definition {
+                        used_here = false;
+                        break;
+                    }
+
+                    used_here = true;
+                } else if r.is_definition {
+                    definers.push(r.module);
+                }
+            }
+
+            if used_here && !definers.is_empty() {
+                entries.push((name, definers));
+            }
+        }
+
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        entries.sort_unstable_by(|a, b| natural_lexical_cmp(a.0, b.0));
+
+        writeln!(dest, "\\begin{{WebMiniIndex}}")?;
+
+        for (name, definers) in entries {
+            write!(dest, "  \\WebMiniIndexEntry{{{}}}{{", tex_escape(name))?;
+
+            for id in definers {
+                write!(dest, "\\mref{{{}}}", id)?;
+            }
+
+            writeln!(dest, "}}")?;
+        }
+
+        writeln!(dest, "\\end{{WebMiniIndex}}")?;
+
+        Ok(())
+    }
+}
+
+/// Escape text so that it can be typeset literally by TeX.
+fn tex_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '$' | '%' | '_' | '{' | '}' | '#' | '&' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '^' => escaped.push_str("\\^"),
+            '\\' => escaped.push_str("{\\textbackslash}"),
+            '~' => escaped.push_str("{\\textasciitilde}"),
+            other => escaped.push(other),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Dialect;
+
+    #[test]
+    fn string_pool() {
+        // Single-character strings stand for their character codes, and a
+        // repeated string reuses its number, so the pool only gets three
+        // strings, numbered in order.
+        let web = "@ @p s:=\"Hello\"; t:=\"x\"; u:=\"a\"\"b\"; v:=\"Hello\"; w:=\"World!\";\n";
+        let state = crate::scan(web, Dialect::Web).unwrap();
+        let mut pool = Vec::new();
+        state.write_pool_file(&mut pool).unwrap();
+
+        assert_eq!(
+            String::from_utf8(pool).unwrap(),
+            "05Hello\n03a\"b\n06World!\n*201540939\n"
+        );
+        assert_eq!(state.string_pool_checksum(), 201540939);
     }
 }
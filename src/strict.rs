@@ -0,0 +1,273 @@
+//! Checking that the code would be valid Pascal after tangling.
+//!
+//! The grammar that we use to parse WEB code is deliberately lenient, since
+//! WEB's macros let programs write things that look like Pascal but aren't
+//! quite. For instance, `link(p):=q` is fine if `link` is a macro that expands
+//! to an array access, but not if it's a function. People porting WEB programs
+//! to real Pascal compilers want to know about the constructs that only work
+//! thanks to the leniency, so as each fragment of code is woven,
+//! [`StrictChecker::add`] looks for them, and [`StrictChecker::finish`] reports
+//! the ones that macros don't explain once all of the macros are known.
+//!
+//! Only the code of modules is checked, since the body of a macro can be any
+//! fragment of code at all.
+
+use std::{collections::HashSet, ops::Range};
+
+use crate::{
+    diagnostics::{Lint, LintLevel, LintLevels, Warning},
+    parse_base::StringSpan,
+    state::ModuleId,
+    symbols::normalize,
+    weblang::{
+        base::PascalToken, visit::Visitor, NodeSpan, Spanned, WebCallExpr, WebCode, WebExpr,
+        WebStatement, WebToplevel,
+    },
+};
+
+fn node_range(s: &NodeSpan) -> Range<usize> {
+    s.start..s.end
+}
+
+/// Whether an expression statement could be a procedure call.
+fn is_procedure_call(expr: &WebExpr) -> bool {
+    matches!(
+        expr,
+        WebExpr::Token(PascalToken::Identifier(..) | PascalToken::FormattedIdentifier(..))
+            | WebExpr::Call(..)
+            | WebExpr::ModuleReference(..)
+            | WebExpr::SpecialReturnForm(..)
+            // Labels in declarations split up by module references.
+            | WebExpr::Token(PascalToken::IntLiteral(..))
+    )
+}
+
+/// A construct that might not be valid Pascal.
+#[derive(Clone, Debug)]
+struct Problem {
+    /// If set, the construct is fine if this identifier is a macro.
+    unless_macro: Option<String>,
+
+    /// The module in which the construct appears.
+    module: ModuleId,
+
+    /// Its location in the source text.
+    range: Range<usize>,
+
+    /// What's wrong.
+    message: String,
+
+    /// The label to attach to the location.
+    label: &'static str,
+}
+
+/// Look for constructs that might not be valid Pascal in a fragment of code.
+struct Collector<'c> {
+    checker: &'c mut StrictChecker,
+    module: ModuleId,
+
+    /// Whether the fragment is a lone expression, which is presumably spliced
+    /// into some other expression by a module reference.
+    lone_expr: bool,
+
+    /// Whether the next expression may have a width specifier, as an argument
+    /// to a procedure like `write`.
+    format_ok: bool,
+}
+
+impl<'c> Collector<'c> {
+    fn problem(
+        &mut self,
+        range: Range<usize>,
+        unless_macro: Option<&str>,
+        message: String,
+        label: &'static str,
+    ) {
+        self.checker.problems.push(Problem {
+            unless_macro: unless_macro.map(normalize),
+            module: self.module,
+            range,
+            message,
+            label,
+        });
+    }
+
+    /// Check the left-hand side of an assignment, which must be a variable,
+    /// possibly indexed or with fields accessed.
+    fn check_lhs(&mut self, lhs: &Spanned<WebExpr>) {
+        match &lhs.node {
+            WebExpr::Call(call) => match &call.target().node {
+                WebExpr::Token(
+                    PascalToken::Identifier(name) | PascalToken::FormattedIdentifier(name, _),
+                ) => {
+                    self.problem(
+                        node_range(&lhs.span),
+                        Some(&name.value),
+                        format!(
+                            "assignment to a call of `{}`, which isn't a macro",
+                            name.value
+                        ),
+                        "can't be assigned to in Pascal",
+                    );
+                }
+
+                _ => self.check_lhs(call.target()),
+            },
+
+            WebExpr::Index(index) => self.check_lhs(index.target()),
+
+            WebExpr::Field(field) => self.check_lhs(field.item()),
+
+            WebExpr::Token(
+                PascalToken::Identifier(..)
+                | PascalToken::FormattedIdentifier(..)
+                | PascalToken::Hash(..),
+            ) => {}
+
+            _ => {
+                self.problem(
+                    node_range(&lhs.span),
+                    None,
+                    "assignment to something that isn't a variable".to_owned(),
+                    "not a variable",
+                );
+            }
+        }
+    }
+}
+
+impl<'a, 'c> Visitor<'a> for Collector<'c> {
+    fn visit_statement(&mut self, stmt: &Spanned<WebStatement<'a>>) {
+        match &stmt.node {
+            WebStatement::Assignment(assignment) => self.check_lhs(assignment.lhs()),
+
+            WebStatement::Expr(expr, ..) if !self.lone_expr && !is_procedure_call(expr) => {
+                self.problem(
+                    node_range(&expr.span),
+                    None,
+                    "an expression is used as a statement".to_owned(),
+                    "not a procedure call",
+                );
+            }
+
+            _ => {}
+        }
+
+        stmt.walk(self);
+    }
+
+    fn visit_expr(&mut self, expr: &Spanned<WebExpr<'a>>) {
+        let format_ok = std::mem::take(&mut self.format_ok);
+
+        if let WebExpr::Format(..) = expr.node {
+            if !format_ok {
+                self.problem(
+                    node_range(&expr.span),
+                    None,
+                    "a width specifier is used outside of a procedure call".to_owned(),
+                    "only allowed in arguments to procedures like `write`",
+                );
+            }
+
+            // Allow the `x:w:d` form.
+            self.format_ok = true;
+        }
+
+        expr.walk(self);
+    }
+
+    fn visit_call_expr(&mut self, expr: &WebCallExpr<'a>) {
+        self.visit_expr(expr.target());
+
+        for arg in expr.args() {
+            self.format_ok = true;
+            self.visit_expr(arg);
+        }
+    }
+}
+
+/// Gathers up the constructs that might not be valid Pascal, so that they can
+/// be checked against the macros once all of the code has been seen.
+#[derive(Debug, Default)]
+pub struct StrictChecker {
+    /// The names of the macros, normalized.
+    macros: HashSet<String>,
+
+    /// The constructs that might not be valid Pascal.
+    problems: Vec<Problem>,
+
+    /// Whether some of the code wasn't seen, in which case we can't tell
+    /// whether an identifier is a macro.
+    incomplete: bool,
+}
+
+impl StrictChecker {
+    /// Record the definition of a macro.
+    pub fn add_macro(&mut self, name: &StringSpan) {
+        self.macros.insert(normalize(&name.value));
+    }
+
+    /// Look for constructs that might not be valid Pascal in the code of a
+    /// module.
+    pub fn add(&mut self, code: &WebCode, module: ModuleId) {
+        let lone_expr = matches!(
+            &code.0[..],
+            [Spanned {
+                node: WebToplevel::Statement(
+                    Spanned {
+                        node: WebStatement::Expr(..),
+                        ..
+                    },
+                    _
+                ),
+                ..
+            }]
+        );
+
+        let mut collector = Collector {
+            checker: self,
+            module,
+            lone_expr,
+            format_ok: false,
+        };
+
+        collector.visit_code(code);
+    }
+
+    /// Note that some code was skipped, so that the macros might not all be
+    /// known.
+    pub fn skip(&mut self) {
+        self.incomplete = true;
+    }
+
+    /// Check the constructs against the macros, returning warnings about the
+    /// ones that wouldn't be valid Pascal.
+    pub fn finish(self, lints: &LintLevels) -> Vec<Warning> {
+        let level = lints.get(Lint::NonstandardPascal);
+
+        if level == LintLevel::Allow {
+            return Vec::new();
+        }
+
+        let StrictChecker {
+            macros,
+            problems,
+            incomplete,
+        } = self;
+
+        problems
+            .into_iter()
+            .filter(|p| match &p.unless_macro {
+                Some(name) => !incomplete && !macros.contains(name),
+                None => true,
+            })
+            .map(|p| Warning {
+                lint: Lint::NonstandardPascal,
+                level,
+                module: p.module,
+                message: p.message,
+                location: Some((p.range, p.label.to_owned())),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,115 @@
+//! Checking the style of WEB programs.
+//!
+//! Besides the lints that point out likely mistakes, there are a few about
+//! matters of taste, which are allowed by default, since every program has its
+//! own conventions:
+//!
+//! - [`Lint::LongModuleCode`] reports modules whose displayed code takes more
+//!   lines than [`StyleLimits::max_code_lines`]. Knuth's advice is that a
+//!   module should be understandable at a glance, which gets hard once it no
+//!   longer fits on a page. The lines are counted after the code is laid out,
+//!   so they're the lines that a reader sees, including those of the macro
+//!   definitions, but not those of the code quoted in the TeX commentary.
+//! - [`Lint::LongModuleName`] reports named modules whose names are longer
+//!   than [`StyleLimits::max_module_name_length`] characters.
+//! - [`Lint::UndocumentedConstant`] reports `const` declarations without a
+//!   comment, which WEB programs customarily use to say what each constant
+//!   means.
+
+use std::collections::BTreeMap;
+
+use crate::{
+    diagnostics::{Lint, LintLevel, LintLevels, Warning},
+    parse_base::StringSpan,
+    state::ModuleId,
+    weblang::{visit::Visitor, WebCode, WebConstantDeclaration},
+};
+
+/// The default for [`StyleLimits::max_code_lines`].
+pub const DEFAULT_MAX_CODE_LINES: usize = 50;
+
+/// The default for [`StyleLimits::max_module_name_length`].
+pub const DEFAULT_MAX_MODULE_NAME_LENGTH: usize = 60;
+
+/// The limits enforced by the style lints.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct StyleLimits {
+    /// The most lines that the displayed code of a module may take, for
+    /// [`Lint::LongModuleCode`].
+    pub max_code_lines: usize,
+
+    /// The most characters that the name of a module may have, for
+    /// [`Lint::LongModuleName`].
+    pub max_module_name_length: usize,
+}
+
+impl Default for StyleLimits {
+    fn default() -> Self {
+        StyleLimits {
+            max_code_lines: DEFAULT_MAX_CODE_LINES,
+            max_module_name_length: DEFAULT_MAX_MODULE_NAME_LENGTH,
+        }
+    }
+}
+
+/// Find the constants declared without comments.
+struct Collector<'a> {
+    undocumented: Vec<StringSpan<'a>>,
+}
+
+impl<'a> Visitor<'a> for Collector<'a> {
+    fn visit_const_declaration(&mut self, decl: &WebConstantDeclaration<'a>) {
+        if !decl.has_comment() {
+            self.undocumented.push(decl.name().clone());
+        }
+    }
+}
+
+/// Get the names of the constants declared without comments in a fragment of
+/// code.
+pub(crate) fn undocumented_constants<'a>(code: &WebCode<'a>) -> Vec<StringSpan<'a>> {
+    let mut collector = Collector {
+        undocumented: Vec::new(),
+    };
+
+    collector.visit_code(code);
+    collector.undocumented
+}
+
+/// Keeps track of how many lines the displayed code of each module takes,
+/// which is only known once all of its code has been laid out.
+#[derive(Debug, Default)]
+pub(crate) struct CodeLengthChecker {
+    lines: BTreeMap<ModuleId, usize>,
+}
+
+impl CodeLengthChecker {
+    /// Note that some code of a module was laid out in `lines` lines.
+    pub fn add(&mut self, module: ModuleId, lines: usize) {
+        *self.lines.entry(module).or_default() += lines;
+    }
+
+    /// Report the modules whose code takes more than `max_lines` lines.
+    pub fn finish(self, max_lines: usize, lints: &LintLevels) -> Vec<Warning> {
+        let level = lints.get(Lint::LongModuleCode);
+
+        if level == LintLevel::Allow {
+            return Vec::new();
+        }
+
+        self.lines
+            .into_iter()
+            .filter(|(_, lines)| *lines > max_lines)
+            .map(|(module, lines)| Warning {
+                lint: Lint::LongModuleCode,
+                level,
+                module,
+                message: format!(
+                    "the code of this module takes {} lines, but the limit is {}",
+                    lines, max_lines
+                ),
+                location: None,
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,299 @@
+//! Checking that identifiers are declared and macros are used.
+//!
+//! An identifier that is used in the code but never declared anywhere in the
+//! program is almost always a typo, often introduced by a change file. Going
+//! the other way, a macro that is never used is dead weight. Since WEB code can
+//! use things before they're declared, these can only be checked once all of
+//! the code has been seen. As each fragment of code is woven,
+//! [`SymbolChecker::add`] records the identifiers that it uses, and
+//! [`SymbolChecker::finish`] checks them against the [`SymbolTable`] of the
+//! whole program, along with the definitions recorded in the index.
+//!
+//! Identifiers are compared as TANGLE compares them, ignoring case and
+//! underscores. Identifiers in code that couldn't be parsed are never
+//! reported as undeclared, since we don't know whether they're declarations,
+//! and count as uses of macros. The same goes for the rest of a fragment of
+//! code that couldn't all be parsed, since the parser may have picked up again
+//! partway through a declaration, as with the names of the values of an
+//! enumerated type.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    ops::Range,
+};
+
+use crate::{
+    diagnostics::{Lint, LintLevel, LintLevels, Warning},
+    parse_base::StringSpan,
+    reserved::PascalReservedWord,
+    state::{ModuleId, State},
+    symtab::SymbolTable,
+    weblang::{base::PascalToken, visit::Visitor, Spanned, WebCode, WebToplevel},
+};
+
+/// Identifiers that Pascal, or the usual WEB runtime environment, provides
+/// without any declaration.
+const PREDECLARED: &[&str] = &[
+    "abs", "arctan", "boolean", "break", "break_in", "char", "chr", "close", "cos", "dispose",
+    "eof", "eoln", "erstat", "exp", "false", "get", "input", "integer", "ln", "maxint", "new",
+    "odd", "ord", "output", "pack", "page", "pred", "put", "read", "read_ln", "readln", "real",
+    "reset", "rewrite", "round", "sin", "sqr", "sqrt", "succ", "text", "true", "trunc", "unpack",
+    "write", "write_ln", "writeln",
+];
+
+/// Normalize an identifier so that identifiers that TANGLE treats as the same
+/// compare equal.
+pub(crate) fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(|c| *c != '_')
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// A use or definition of an identifier.
+#[derive(Clone, Debug)]
+struct Use {
+    /// The identifier as it's spelled here.
+    name: String,
+
+    /// The module in which it appears.
+    module: ModuleId,
+
+    /// Its location in the source text.
+    range: Range<usize>,
+}
+
+/// Gather up the identifiers that a fragment of code uses.
+struct Collector<'c> {
+    checker: &'c mut SymbolChecker,
+    module: ModuleId,
+
+    /// Whether the most recent toplevel was a bare `program` keyword, in which
+    /// case the next toplevel names the program.
+    after_program: bool,
+
+    /// Whether the identifiers in the current toplevel are being declared.
+    declaring: bool,
+
+    /// Whether some of the fragment of code couldn't be parsed, in which case
+    /// none of its identifiers are reported.
+    unparsed: bool,
+}
+
+impl<'a, 'c> Visitor<'a> for Collector<'c> {
+    fn visit_toplevel(&mut self, toplevel: &Spanned<WebToplevel<'a>>) {
+        self.declaring = std::mem::take(&mut self.after_program);
+
+        if let WebToplevel::Unparsed(toks) = &toplevel.node {
+            for tok in toks {
+                if let Some(
+                    PascalToken::Identifier(ident) | PascalToken::FormattedIdentifier(ident, _),
+                ) = tok.as_pascal()
+                {
+                    self.checker.unknown.insert(normalize(&ident.value));
+                }
+            }
+        } else {
+            toplevel.walk(self);
+        }
+    }
+
+    fn visit_token(&mut self, token: &PascalToken<'a>) {
+        match token {
+            PascalToken::ReservedWord(rw) if rw.value == PascalReservedWord::Program => {
+                self.after_program = true;
+            }
+
+            PascalToken::Identifier(ident) | PascalToken::FormattedIdentifier(ident, _) => {
+                self.visit_identifier(ident);
+            }
+
+            _ => {}
+        }
+    }
+
+    fn visit_identifier(&mut self, ident: &StringSpan<'a>) {
+        if self.unparsed {
+            self.checker.unknown.insert(normalize(&ident.value));
+            return;
+        }
+
+        if self.declaring {
+            return;
+        }
+
+        let module = self.module;
+
+        self.checker
+            .uses
+            .entry(normalize(&ident.value))
+            .or_insert_with(|| Use {
+                name: ident.value.to_string(),
+                module,
+                range: ident.start.location_offset()..ident.end.location_offset(),
+            });
+    }
+
+    fn visit_defined_identifier(&mut self, _ident: &StringSpan<'a>) {}
+}
+
+/// Gathers up the identifiers that the code uses, so that they can be checked
+/// once all of the code has been seen.
+#[derive(Debug, Default)]
+pub struct SymbolChecker {
+    /// The identifiers that appear in code that couldn't be parsed, normalized.
+    unknown: HashSet<String>,
+
+    /// The first use of each identifier, keyed by its normalized form.
+    uses: BTreeMap<String, Use>,
+
+    /// The definition of each macro.
+    macros: Vec<Use>,
+
+    /// Whether some of the code wasn't seen, in which case we can't say
+    /// anything.
+    incomplete: bool,
+}
+
+impl SymbolChecker {
+    /// Record the identifiers that a fragment of code uses. If the code is a
+    /// macro definition, `macro_name` is the name of the macro.
+    pub fn add(&mut self, code: &WebCode, module: ModuleId, macro_name: Option<&StringSpan>) {
+        if let Some(name) = macro_name {
+            self.macros.push(Use {
+                name: name.value.to_string(),
+                module,
+                range: name.start.location_offset()..name.end.location_offset(),
+            });
+        }
+
+        let unparsed = code
+            .0
+            .iter()
+            .any(|tl| matches!(tl.node, WebToplevel::Unparsed(_)));
+
+        let mut collector = Collector {
+            checker: self,
+            module,
+            after_program: false,
+            declaring: false,
+            unparsed,
+        };
+
+        collector.visit_code(code);
+    }
+
+    /// Note that some code was skipped, so that the identifiers can't be
+    /// checked.
+    pub fn skip(&mut self) {
+        self.incomplete = true;
+    }
+
+    /// Check the identifiers against the declarations in `symbols`, returning
+    /// warnings about the ones that are never declared and the macros that are
+    /// never used.
+    pub fn finish(self, state: &State, symbols: &SymbolTable, lints: &LintLevels) -> Vec<Warning> {
+        if self.incomplete {
+            return Vec::new();
+        }
+
+        let SymbolChecker {
+            unknown,
+            uses,
+            macros,
+            ..
+        } = self;
+
+        let mut warnings = Vec::new();
+        let level = lints.get(Lint::UnusedMacro);
+
+        if level != LintLevel::Allow {
+            for m in macros {
+                let key = normalize(&m.name);
+
+                if !uses.contains_key(&key) && !unknown.contains(&key) {
+                    warnings.push(Warning {
+                        lint: Lint::UnusedMacro,
+                        level,
+                        module: m.module,
+                        message: format!("macro `{}` is never used", m.name),
+                        location: Some((m.range, "defined here".to_owned())),
+                    });
+                }
+            }
+        }
+
+        let level = lints.get(Lint::UndefinedIdentifier);
+
+        if level == LintLevel::Allow {
+            return warnings;
+        }
+
+        let mut defined: HashSet<_> = state.defined_identifiers().map(normalize).collect();
+        defined.extend(PREDECLARED.iter().map(|s| normalize(s)));
+
+        warnings.extend(
+            uses.into_iter()
+                .filter(|(key, u)| {
+                    !defined.contains(key) && !symbols.contains(&u.name) && !unknown.contains(key)
+                })
+                .map(|(_, u)| Warning {
+                    lint: Lint::UndefinedIdentifier,
+                    level,
+                    module: u.module,
+                    message: format!("`{}` is never declared", u.name),
+                    location: Some((u.range, "not declared anywhere in the program".to_owned())),
+                }),
+        );
+
+        warnings.sort_by_key(|w| (w.module, w.location.as_ref().map(|l| l.0.start)));
+        warnings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{diagnostics::Lint, Dialect, WeaveOptions};
+
+    /// Get the warnings about undeclared identifiers in a WEB program.
+    fn warnings(web: &str) -> Vec<String> {
+        let state = crate::scan(web, Dialect::Web).unwrap();
+        let doc = crate::weave_document(
+            "test",
+            web,
+            &state,
+            &crate::themes::default_theme(),
+            &WeaveOptions::default(),
+        )
+        .unwrap();
+
+        doc.warnings
+            .into_iter()
+            .filter(|w| w.lint == Lint::UndefinedIdentifier)
+            .map(|w| w.message)
+            .collect()
+    }
+
+    #[test]
+    fn unparsed_declarations() {
+        // The enumerated type can't be parsed, and the parser picks up again
+        // partway through its declaration.
+        let web = "@ @p program p;\n\
+                   type color = (red, green, blue);\n\
+                   var c: color;\n\
+                   begin c := green; d := red; end.\n\
+                   @ @p procedure q;\n\
+                   begin e := 1; end;\n";
+
+        assert_eq!(warnings(web), ["`e` is never declared"]);
+    }
+
+    #[test]
+    fn procedure_labels() {
+        let web = "@ @p procedure q;\n\
+                   label exit;\n\
+                   begin goto exit; exit: end;\n";
+
+        assert_eq!(warnings(web), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,323 @@
+//! The whole-program symbol table.
+//!
+//! A WEB program declares its identifiers all over the place, and often uses
+//! them before they're declared, so questions like "what is `cur_val`?" can
+//! only be answered once all of the code has been parsed. As each fragment of
+//! code is woven, [`SymbolTable::add`] records the identifiers that it
+//! declares: constants, types, variables, procedures and functions along with
+//! their parameters and locals, record fields, labels, macros, and the program
+//! itself. Use [`crate::symbol_table`] to build the table for a whole program,
+//! or [`crate::WovenDocument::symbols`] to get the one built while weaving.
+//!
+//! Identifiers are looked up as TANGLE compares them, ignoring case and
+//! underscores. Forward declarations aren't recorded, since the procedure or
+//! function is declared again where it's defined, and neither is code that
+//! couldn't be parsed.
+
+use serde::Serialize;
+use std::{collections::BTreeMap, ops::Range};
+
+use crate::{
+    parse_base::StringSpan,
+    reserved::PascalReservedWord,
+    state::ModuleId,
+    symbols::normalize,
+    weblang::{
+        base::PascalToken, visit::Visitor, Spanned, WebCode, WebConstantDeclaration, WebDefine,
+        WebExpr, WebForwardDeclaration, WebFunctionDefinition, WebLabelDeclaration,
+        WebProgramDefinition, WebRecordType, WebToplevel, WebTypeDeclaration, WebVarDeclaration,
+    },
+};
+
+/// What kind of thing an identifier is declared as.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SymbolKind {
+    /// A constant, declared in a `const` block.
+    Constant,
+
+    /// A type, declared in a `type` block.
+    Type,
+
+    /// A variable, declared in a `var` block.
+    Variable,
+
+    /// A parameter of a procedure or function.
+    Parameter,
+
+    /// A field of a record type.
+    Field,
+
+    /// A procedure.
+    Procedure,
+
+    /// A function.
+    Function,
+
+    /// A macro, defined with `@d`.
+    Macro,
+
+    /// A label, declared in a `label` block.
+    Label,
+
+    /// The name of the program.
+    Program,
+}
+
+impl SymbolKind {
+    /// A short lowercase name for the kind, as used in the symbol index.
+    pub fn name(&self) -> &'static str {
+        match self {
+            SymbolKind::Constant => "constant",
+            SymbolKind::Type => "type",
+            SymbolKind::Variable => "variable",
+            SymbolKind::Parameter => "parameter",
+            SymbolKind::Field => "field",
+            SymbolKind::Procedure => "procedure",
+            SymbolKind::Function => "function",
+            SymbolKind::Macro => "macro",
+            SymbolKind::Label => "label",
+            SymbolKind::Program => "program",
+        }
+    }
+}
+
+/// A declaration of an identifier.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct Symbol {
+    /// The identifier as it's spelled in the declaration.
+    pub name: String,
+
+    /// What the identifier is declared as.
+    pub kind: SymbolKind,
+
+    /// The module in which the declaration appears.
+    pub module: ModuleId,
+
+    /// If the identifier is local to a procedure or function, such as a
+    /// parameter or a local variable, the name of the procedure or function.
+    pub scope: Option<String>,
+
+    /// The location of the identifier in the source text.
+    #[serde(skip)]
+    pub range: Range<usize>,
+}
+
+/// Gather up the identifiers that a fragment of code declares.
+struct Collector<'t> {
+    table: &'t mut SymbolTable,
+    module: ModuleId,
+
+    /// What the identifiers being declared are declared as, if anything.
+    kind: Option<SymbolKind>,
+
+    /// The procedure or function whose locals are being declared.
+    scope: Option<String>,
+
+    /// Whether the most recent toplevel was a bare `program` keyword, in which
+    /// case the next toplevel names the program.
+    after_program: bool,
+
+    /// Whether the identifiers in the current toplevel name the program.
+    naming_program: bool,
+}
+
+impl<'t> Collector<'t> {
+    fn declare(&mut self, ident: &StringSpan, kind: SymbolKind) {
+        self.table
+            .symbols
+            .entry(normalize(&ident.value))
+            .or_default()
+            .push(Symbol {
+                name: ident.value.to_string(),
+                kind,
+                module: self.module,
+                scope: self.scope.clone(),
+                range: ident.start.location_offset()..ident.end.location_offset(),
+            });
+    }
+
+    /// Walk a node whose defined identifiers are of the given kind.
+    fn with_kind<F: FnOnce(&mut Self)>(&mut self, kind: SymbolKind, func: F) {
+        let outer = self.kind.replace(kind);
+        func(self);
+        self.kind = outer;
+    }
+}
+
+impl<'a, 't> Visitor<'a> for Collector<'t> {
+    fn visit_toplevel(&mut self, toplevel: &Spanned<WebToplevel<'a>>) {
+        self.naming_program = std::mem::take(&mut self.after_program);
+
+        if !matches!(toplevel.node, WebToplevel::Unparsed(_)) {
+            toplevel.walk(self);
+        }
+    }
+
+    fn visit_define(&mut self, define: &WebDefine<'a>) {
+        self.with_kind(SymbolKind::Macro, |c| define.walk(c));
+    }
+
+    fn visit_program_definition(&mut self, program: &WebProgramDefinition<'a>) {
+        self.with_kind(SymbolKind::Program, |c| program.walk(c));
+    }
+
+    fn visit_label_declaration(&mut self, decl: &WebLabelDeclaration<'a>) {
+        self.with_kind(SymbolKind::Label, |c| decl.walk(c));
+    }
+
+    fn visit_const_declaration(&mut self, decl: &WebConstantDeclaration<'a>) {
+        self.with_kind(SymbolKind::Constant, |c| decl.walk(c));
+    }
+
+    fn visit_var_declaration(&mut self, decl: &WebVarDeclaration<'a>) {
+        self.with_kind(SymbolKind::Variable, |c| decl.walk(c));
+    }
+
+    fn visit_type_declaration(&mut self, decl: &WebTypeDeclaration<'a>) {
+        self.with_kind(SymbolKind::Type, |c| decl.walk(c));
+    }
+
+    fn visit_forward_declaration(&mut self, _decl: &WebForwardDeclaration<'a>) {}
+
+    fn visit_function_definition(&mut self, func: &WebFunctionDefinition<'a>) {
+        let kind = if func.is_function() {
+            SymbolKind::Function
+        } else {
+            SymbolKind::Procedure
+        };
+
+        self.declare(func.name(), kind);
+        let outer = self.scope.replace(func.name().value.to_string());
+
+        self.with_kind(SymbolKind::Parameter, |c| {
+            for arg in func.args() {
+                arg.walk(c);
+            }
+        });
+
+        // Labels declared by name, rather than by number or by macro.
+        for label in func.labels() {
+            if let WebExpr::Token(PascalToken::Identifier(ident)) = &label.name().node {
+                self.declare(ident, SymbolKind::Label);
+            }
+        }
+
+        for constant in func.consts() {
+            self.declare(constant.name(), SymbolKind::Constant);
+        }
+
+        self.with_kind(SymbolKind::Variable, |c| {
+            for item in func.vars() {
+                item.walk(c);
+            }
+        });
+
+        for nested in func.functions() {
+            self.visit_function_definition(nested);
+        }
+
+        self.scope = outer;
+    }
+
+    fn visit_record_type(&mut self, ty: &WebRecordType<'a>) {
+        self.with_kind(SymbolKind::Field, |c| ty.walk(c));
+    }
+
+    fn visit_token(&mut self, token: &PascalToken<'a>) {
+        match token {
+            PascalToken::ReservedWord(rw) if rw.value == PascalReservedWord::Program => {
+                self.after_program = true;
+            }
+
+            PascalToken::Identifier(ident) | PascalToken::FormattedIdentifier(ident, _) => {
+                self.visit_identifier(ident);
+            }
+
+            _ => {}
+        }
+    }
+
+    fn visit_identifier(&mut self, ident: &StringSpan<'a>) {
+        if self.naming_program {
+            self.declare(ident, SymbolKind::Program);
+        }
+    }
+
+    fn visit_defined_identifier(&mut self, ident: &StringSpan<'a>) {
+        if self.naming_program {
+            self.declare(ident, SymbolKind::Program);
+        } else if let Some(kind) = self.kind {
+            self.declare(ident, kind);
+        }
+    }
+}
+
+/// The identifiers declared by a program, keyed by their normalized forms.
+///
+/// See the [module-level documentation](self) for an overview.
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(transparent)]
+pub struct SymbolTable {
+    symbols: BTreeMap<String, Vec<Symbol>>,
+}
+
+impl SymbolTable {
+    /// Record the identifiers that a fragment of code declares.
+    pub fn add(&mut self, code: &WebCode, module: ModuleId) {
+        let mut collector = Collector {
+            table: self,
+            module,
+            kind: None,
+            scope: None,
+            after_program: false,
+            naming_program: false,
+        };
+
+        collector.visit_code(code);
+    }
+
+    /// Get the declarations of an identifier, in the order in which they
+    /// appear. Most identifiers are declared once, but local variables and
+    /// record fields with common names may be declared many times.
+    pub fn get(&self, name: &str) -> &[Symbol] {
+        self.symbols
+            .get(&normalize(name))
+            .map(|v| &v[..])
+            .unwrap_or_default()
+    }
+
+    /// Determine whether an identifier is declared anywhere.
+    pub fn contains(&self, name: &str) -> bool {
+        self.symbols.contains_key(&normalize(name))
+    }
+
+    /// Get what an identifier is declared as. If it's declared several times,
+    /// the first declaration outside of any procedure or function wins, and
+    /// otherwise the first declaration.
+    pub fn kind(&self, name: &str) -> Option<SymbolKind> {
+        let decls = self.get(name);
+
+        decls
+            .iter()
+            .find(|s| s.scope.is_none())
+            .or_else(|| decls.first())
+            .map(|s| s.kind)
+    }
+
+    /// Iterate over all of the declarations, sorted by normalized identifier
+    /// and then in the order in which they appear.
+    pub fn iter(&self) -> impl Iterator<Item = &Symbol> {
+        self.symbols.values().flatten()
+    }
+
+    /// The number of distinct identifiers that are declared.
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    /// Determine whether no identifiers are declared.
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+}
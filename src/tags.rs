@@ -0,0 +1,161 @@
+//! Tags files, for navigating WEB sources in editors.
+//!
+//! Vim and Emacs can jump to the definition of a name using a "tags" file
+//! listing where each name is defined. [`XrefDatabase::write_ctags`] writes one
+//! in the format used by Vim and `ctags`, and [`XrefDatabase::write_etags`]
+//! writes one in the format used by Emacs and `etags`. The tags cover the
+//! procedures, functions, types, constants, and variables declared at the top
+//! level of the program, the macros, and the named modules, whose tags are
+//! their full names.
+//!
+//! The locations come from the cross-reference database, so they refer to the
+//! source text as woven. If a change file was applied, lines may be off.
+
+use std::{collections::HashMap, io};
+
+use crate::{
+    symtab::{Symbol, SymbolKind},
+    xref::{XrefDatabase, XrefLocation},
+};
+
+/// A place where a name is defined.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Tag {
+    /// The name.
+    pub name: String,
+
+    /// What the name is defined as, as a single letter: `p` for a procedure,
+    /// `f` for a function, `t` for a type, `c` for a constant, `v` for a
+    /// variable, `P` for the program, `d` for a macro, or `m` for a named
+    /// module.
+    pub kind: char,
+
+    /// Where the name is defined.
+    pub location: XrefLocation,
+}
+
+/// Get the letter for the kind of a declaration, if it gets a tag at all.
+fn symbol_kind_letter(sym: &Symbol) -> Option<char> {
+    // Locals, like parameters, are too numerous to be useful.
+    if sym.scope.is_some() {
+        return None;
+    }
+
+    match sym.kind {
+        SymbolKind::Procedure => Some('p'),
+        SymbolKind::Function => Some('f'),
+        SymbolKind::Type => Some('t'),
+        SymbolKind::Constant => Some('c'),
+        SymbolKind::Variable => Some('v'),
+        SymbolKind::Program => Some('P'),
+
+        // Macros are tagged from the definitions themselves.
+        SymbolKind::Macro | SymbolKind::Parameter | SymbolKind::Field | SymbolKind::Label => None,
+    }
+}
+
+impl XrefDatabase {
+    /// Get the tags of the program, sorted by name and then by location.
+    pub fn tags(&self) -> Vec<Tag> {
+        let mut tags = Vec::new();
+
+        let declarations: HashMap<_, _> = self
+            .identifiers
+            .iter()
+            .filter(|i| i.definition)
+            .map(|i| (i.location.start, i.location))
+            .collect();
+
+        for sym in self.symbols.iter() {
+            let kind = match symbol_kind_letter(sym) {
+                Some(k) => k,
+                None => continue,
+            };
+
+            if let Some(location) = declarations.get(&sym.range.start) {
+                tags.push(Tag {
+                    name: sym.name.clone(),
+                    kind,
+                    location: *location,
+                });
+            }
+        }
+
+        for def in &self.defines {
+            tags.push(Tag {
+                name: def.name.clone(),
+                kind: 'd',
+                location: def.location,
+            });
+        }
+
+        for mref in self.module_references.iter().filter(|m| m.definition) {
+            tags.push(Tag {
+                name: mref.name.clone(),
+                kind: 'm',
+                location: mref.location,
+            });
+        }
+
+        tags.sort_by(|a, b| {
+            a.name
+                .cmp(&b.name)
+                .then(a.location.start.cmp(&b.location.start))
+        });
+        tags
+    }
+
+    /// Write the tags of the program in the format used by Vim and `ctags`.
+    ///
+    /// The source file is named `path` in the tags file. Editors generally
+    /// interpret it relative to the directory containing the tags file.
+    pub fn write_ctags<W: io::Write>(&self, path: &str, mut dest: W) -> io::Result<()> {
+        writeln!(dest, "!_TAG_FILE_FORMAT\t2\t/extended format/")?;
+        writeln!(dest, "!_TAG_FILE_SORTED\t1\t/0=unsorted, 1=sorted/")?;
+        writeln!(dest, "!_TAG_PROGRAM_NAME\ttt-weave\t//")?;
+
+        for tag in self.tags() {
+            writeln!(
+                dest,
+                "{}\t{}\t{};\"\t{}",
+                tag.name, path, tag.location.line, tag.kind
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Write the tags of the program in the format used by Emacs and `etags`.
+    ///
+    /// The `text` must be the source text that the database describes, since
+    /// this format quotes the start of each line containing a definition. The
+    /// source file is named `path` in the tags file, as with
+    /// [`Self::write_ctags`]. An error is returned if a tag lies outside of
+    /// the `text`, which means that it isn't the text that was described.
+    pub fn write_etags<W: io::Write>(&self, text: &str, path: &str, mut dest: W) -> io::Result<()> {
+        let mut body = String::new();
+
+        for tag in self.tags() {
+            let loc = tag.location;
+            let before = text.get(..loc.start).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("the tag `{}` lies outside of the source text", tag.name),
+                )
+            })?;
+            let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+
+            // Module names can span several lines, but the pattern can't.
+            let pattern = text
+                .get(line_start..loc.end)
+                .and_then(|t| t.lines().next())
+                .unwrap_or_default();
+            body.push_str(&format!(
+                "{}\x7f{}\x01{},{}\n",
+                pattern, tag.name, loc.line, line_start
+            ));
+        }
+
+        write!(dest, "\x0c\n{},{}\n{}", path, body.len(), body)
+    }
+}
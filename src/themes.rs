@@ -0,0 +1,68 @@
+//! Highlighting themes.
+//!
+//! syntect comes with a handful of themes, but loading all of them takes a
+//! noticeable fraction of the time needed to weave a small file. Since most
+//! runs use the default theme, [`crate::DEFAULT_THEME`], it's embedded on its
+//! own as a precompiled binary dump, which loads quickly. The rest of syntect's
+//! themes are only loaded when they're asked for, and only if the
+//! `default-themes` feature is enabled, which it is by default. Builds without
+//! it leave out syntect's assets altogether.
+
+use std::collections::BTreeMap;
+use syntect::{dumps::from_binary, highlighting::Theme};
+use tectonic_errors::prelude::*;
+
+use crate::DEFAULT_THEME;
+
+/// The default theme, dumped from syntect's copy of it with
+/// `syntect::dumps::dump_binary`.
+static DEFAULT_THEME_DUMP: &[u8] = include_bytes!("../assets/InspiredGitHub.themedump");
+
+/// Load the default theme.
+pub fn default_theme() -> Theme {
+    from_binary(DEFAULT_THEME_DUMP)
+}
+
+/// Load a theme by name.
+///
+/// The default theme is always available. The rest of syntect's themes are
+/// only available if `default_themes` is set and the `default-themes` feature
+/// is enabled.
+pub fn load(name: &str, default_themes: bool) -> Result<Theme> {
+    if name == DEFAULT_THEME {
+        return Ok(default_theme());
+    }
+
+    if default_themes {
+        if let Some(theme) = syntect_themes().remove(name) {
+            return Ok(theme);
+        }
+    }
+
+    bail!(
+        "no such highlighting theme `{}`; the available themes are: {}",
+        name,
+        names(default_themes).join(", ")
+    )
+}
+
+/// Get the names of the available themes, in order.
+pub fn names(default_themes: bool) -> Vec<String> {
+    let mut names = vec![DEFAULT_THEME.to_owned()];
+
+    if default_themes {
+        names.extend(syntect_themes().into_keys().filter(|n| n != DEFAULT_THEME));
+    }
+
+    names
+}
+
+#[cfg(feature = "default-themes")]
+fn syntect_themes() -> BTreeMap<String, Theme> {
+    syntect::highlighting::ThemeSet::load_defaults().themes
+}
+
+#[cfg(not(feature = "default-themes"))]
+fn syntect_themes() -> BTreeMap<String, Theme> {
+    BTreeMap::new()
+}
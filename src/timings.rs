@@ -0,0 +1,174 @@
+//! Keeping track of how long weaving takes.
+//!
+//! If [`crate::WeaveOptions::timings`] is set, the second pass measures how
+//! long it spends on each phase of handling each module: lexing the module,
+//! parsing its code, laying the code out, and emitting it with highlighting.
+//! The parser backtracks heavily, so this is the place to look when some code
+//! is unexpectedly slow to weave.
+//!
+//! Since code is processed in parallel, the times are summed over all of the
+//! threads, and so the total can be more than the time that weaving actually
+//! took.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    ops::AddAssign,
+    time::{Duration, Instant},
+};
+
+use crate::state::ModuleId;
+
+/// The time spent on each phase of weaving.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct PhaseTimes {
+    /// Lexing the TeX and code.
+    pub lex: Duration,
+
+    /// Parsing the code.
+    pub parse: Duration,
+
+    /// Laying out the code.
+    pub prettify: Duration,
+
+    /// Highlighting the code, emitting it, and keeping track of what it does
+    /// for the checks done once all of the code has been seen.
+    pub emit: Duration,
+}
+
+impl PhaseTimes {
+    /// Get the total time spent on all of the phases.
+    pub fn total(&self) -> Duration {
+        self.lex + self.parse + self.prettify + self.emit
+    }
+}
+
+impl AddAssign for PhaseTimes {
+    fn add_assign(&mut self, other: PhaseTimes) {
+        self.lex += other.lex;
+        self.parse += other.parse;
+        self.prettify += other.prettify;
+        self.emit += other.emit;
+    }
+}
+
+/// The time spent on each phase of weaving each module.
+#[derive(Clone, Debug, Default)]
+pub struct Timings {
+    /// The times for each module that was woven.
+    pub modules: BTreeMap<ModuleId, PhaseTimes>,
+}
+
+impl Timings {
+    /// Get the times for a module, to add to them.
+    pub(crate) fn module_mut(&mut self, module: ModuleId) -> &mut PhaseTimes {
+        self.modules.entry(module).or_default()
+    }
+
+    /// Get the total time spent on each phase, over all of the modules.
+    pub fn total(&self) -> PhaseTimes {
+        let mut total = PhaseTimes::default();
+
+        for times in self.modules.values() {
+            total += *times;
+        }
+
+        total
+    }
+
+    /// Render a table of the times for each module, followed by the totals,
+    /// in milliseconds.
+    pub fn report(&self) -> String {
+        let mut text = format!(
+            "{:>8} {:>10} {:>10} {:>10} {:>10} {:>10}\n",
+            "module", "lex", "parse", "prettify", "emit", "total"
+        );
+
+        let mut row = |label: &str, t: &PhaseTimes| {
+            writeln!(
+                text,
+                "{:>8} {:>10.3} {:>10.3} {:>10.3} {:>10.3} {:>10.3}",
+                label,
+                millis(t.lex),
+                millis(t.parse),
+                millis(t.prettify),
+                millis(t.emit),
+                millis(t.total())
+            )
+            .expect("writing to a String cannot fail");
+        };
+
+        for (module, times) in &self.modules {
+            row(&module.to_string(), times);
+        }
+
+        row("total", &self.total());
+        text
+    }
+}
+
+fn millis(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.
+}
+
+/// Keeps track of the time spent on each phase of handling something, by
+/// noting when each phase ends.
+///
+/// Getting the time isn't possible on every platform, notably WebAssembly, so
+/// the timer only looks at the clock if timings have been asked for.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct PhaseTimer {
+    /// When the current phase started, if the timer is running.
+    start: Option<Instant>,
+
+    /// The time spent on each phase so far.
+    pub times: PhaseTimes,
+}
+
+impl PhaseTimer {
+    /// Start a timer, which only runs if `enabled` is true.
+    pub fn start(enabled: bool) -> Self {
+        PhaseTimer {
+            start: enabled.then(Instant::now),
+            times: PhaseTimes::default(),
+        }
+    }
+
+    /// Get the time since the current phase started, and start the next one.
+    fn lap(&mut self) -> Duration {
+        match self.start.as_mut() {
+            Some(start) => {
+                let now = Instant::now();
+                let elapsed = now - *start;
+                *start = now;
+                elapsed
+            }
+
+            None => Duration::ZERO,
+        }
+    }
+
+    /// Note the end of some lexing.
+    pub fn lexed(&mut self) {
+        let elapsed = self.lap();
+        self.times.lex += elapsed;
+    }
+
+    /// Note the end of some parsing.
+    pub fn parsed(&mut self) {
+        let elapsed = self.lap();
+        self.times.parse += elapsed;
+    }
+
+    /// Note the end of some layout.
+    pub fn prettified(&mut self) {
+        let elapsed = self.lap();
+        self.times.prettify += elapsed;
+    }
+
+    /// Note the end of some highlighting and emission.
+    pub fn emitted(&mut self) {
+        let elapsed = self.lap();
+        self.times.emit += elapsed;
+    }
+}
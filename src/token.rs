@@ -20,9 +20,10 @@ pub enum Token {
 }
 
 impl Token {
-    pub fn n_chars(&self) -> usize {
+    /// The number of bytes that the token takes up in the source text.
+    pub fn n_bytes(&self) -> usize {
         match self {
-            Token::Char(_) => 1,
+            Token::Char(c) => c.len_utf8(),
             Token::Control(_) => 2,
         }
     }
@@ -54,13 +55,16 @@ pub fn next_token(span: Span) -> ParseResult<Token> {
         .ok_or_else(|| Err::Error(ParseError::from_error_kind(span, ErrorKind::Eof)))?;
 
     if c != '@' {
-        return Ok((span.slice(1..), Token::Char(c)));
+        return Ok((span.slice(c.len_utf8()..), Token::Char(c)));
     }
 
     let c = it
         .next()
         .ok_or_else(|| Err::Error(ParseError::from_error_kind(span, ErrorKind::Eof)))?;
 
+    // The dialect determines which control codes are available.
+    let cweb = span.extra.has_cweb_control_codes();
+
     let k = match c {
         '>' => ControlKind::Terminator,
         '@' => ControlKind::AtLiteral,
@@ -74,7 +78,12 @@ pub fn next_token(span: Span) -> ParseResult<Token> {
         'F' => ControlKind::FormatDefinition,
         'p' => ControlKind::StartUnnamedPascal,
         'P' => ControlKind::StartUnnamedPascal,
+        'c' if cweb => ControlKind::StartUnnamedPascal,
+        'C' if cweb => ControlKind::StartUnnamedPascal,
+        's' if cweb => ControlKind::FormatDefinition,
+        'S' if cweb => ControlKind::FormatDefinition,
         '<' => ControlKind::ModuleName,
+        '(' if cweb => ControlKind::ModuleName, // output file
         '\'' => ControlKind::OctalLiteral,
         '\"' => ControlKind::HexLiteral,
         '$' => ControlKind::StringPoolChecksum,
@@ -95,6 +104,13 @@ pub fn next_token(span: Span) -> ParseResult<Token> {
         '#' => ControlKind::FormatBigBreak,
         '+' => ControlKind::FormatCancelBreak,
         ';' => ControlKind::FormatLikeSemicolon,
+
+        // Leftover change-file terminators are skipped entirely, if the
+        // dialect allows.
+        'z' | 'Z' if span.extra.ignores_stray_change_terminators() => {
+            return next_token(span.slice(2..));
+        }
+
         _ => {
             return Err(Err::Error(ParseError::from_error_kind(
                 span,
@@ -119,9 +135,9 @@ pub fn expect_token<'a>(tok: Token) -> impl Fn(Span<'a>) -> ParseResult<Token> {
 }
 
 /// WEAVE:106
-pub fn take_until_terminator<'a>(span: Span<'a>) -> ParseResult<StringSpan<'a>> {
+pub fn take_until_terminator<'a>(span: Span<'a>) -> ParseResult<'a, StringSpan<'a>> {
     let (mut span, start) = position(span)?;
-    let text_begin = span.clone();
+    let text_begin = span;
     let mut n_taken = 0;
     let mut tok;
 
@@ -147,7 +163,7 @@ pub fn take_until_terminator<'a>(span: Span<'a>) -> ParseResult<StringSpan<'a>>
             }
 
             Token::Char(_) => {
-                n_taken += tok.n_chars();
+                n_taken += tok.n_bytes();
             }
         }
     }
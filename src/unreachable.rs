@@ -0,0 +1,156 @@
+//! Finding code that can never run.
+//!
+//! A statement that follows an unconditional jump in the same sequence of
+//! statements can never run, unless it's labeled so that some other `goto` can
+//! jump to it. That usually means that the code was rearranged and something
+//! got left behind. The jumps are `goto` statements, uses of macros like
+//! `return` that expand to nothing but a `goto` (see
+//! [`crate::macros::MacroTable::is_jump`]), and `begin`/`end` blocks that end
+//! with one of those. The sequences checked are the statements of blocks and
+//! `repeat` loops, including the bodies of case arms, and the statements at the
+//! top level of each fragment of code.
+//!
+//! The check is conservative. A labeled statement, a case arm, a module
+//! reference, or a preprocessor directive ends the unreachable stretch, since
+//! any of them might be jumped to or might contain a label.
+
+use std::ops::Range;
+
+use crate::{
+    macros::MacroTable,
+    weblang::{
+        base::PascalToken, visit::Visitor, Spanned, WebBlock, WebCode, WebExpr, WebRepeat,
+        WebStatement, WebToplevel,
+    },
+};
+
+/// A statement that can never run.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Unreachable {
+    /// The bytes of the source text occupied by the statement.
+    pub range: Range<usize>,
+
+    /// The jump that the statement follows, as it's written, like `goto done`.
+    pub jump: String,
+}
+
+/// Look for statements that follow unconditional jumps.
+struct Collector<'m> {
+    macros: &'m MacroTable,
+    found: Vec<Unreachable>,
+}
+
+impl<'m> Collector<'m> {
+    /// If a statement always jumps somewhere else, describe the jump.
+    fn jump(&self, stmt: &WebStatement) -> Option<String> {
+        match stmt {
+            WebStatement::Goto(goto) => Some(format!("goto {}", goto.label())),
+
+            WebStatement::Expr(expr, ..) => self.jump_macro(&expr.node),
+
+            WebStatement::MacroCall(mc) => self.jump_macro(&mc.call().node),
+
+            WebStatement::Block(block) => block
+                .stmts()
+                .filter(|s| !matches!(s.node, WebStatement::Comment(_)))
+                .last()
+                .and_then(|s| self.jump(&s.node)),
+
+            _ => None,
+        }
+    }
+
+    /// If an expression used as a statement is a jump macro, get its name.
+    fn jump_macro(&self, expr: &WebExpr) -> Option<String> {
+        let name = match expr {
+            WebExpr::Token(PascalToken::Identifier(s) | PascalToken::FormattedIdentifier(s, _))
+            | WebExpr::SpecialReturnForm(
+                PascalToken::Identifier(s) | PascalToken::FormattedIdentifier(s, _),
+            ) => &s.value,
+
+            _ => return None,
+        };
+
+        self.macros.is_jump(name).then(|| name.to_string())
+    }
+
+    /// Check a sequence of statements.
+    fn check<'a, 's, I>(&mut self, stmts: I)
+    where
+        'a: 's,
+        I: IntoIterator<Item = &'s Spanned<WebStatement<'a>>>,
+    {
+        let mut jump: Option<String> = None;
+        let mut reported = false;
+
+        for stmt in stmts {
+            match &stmt.node {
+                WebStatement::Comment(_) => continue,
+
+                WebStatement::Label(_)
+                | WebStatement::SpecialFreeCase(_)
+                | WebStatement::ModuleReference(..)
+                | WebStatement::PreprocessorDirective(_) => {
+                    jump = None;
+                }
+
+                _ => {
+                    if let (Some(j), false) = (jump.as_ref(), reported) {
+                        self.found.push(Unreachable {
+                            range: stmt.span.start..stmt.span.end,
+                            jump: j.clone(),
+                        });
+                        reported = true;
+                    }
+                }
+            }
+
+            if jump.is_none() {
+                jump = self.jump(&stmt.node);
+                reported = false;
+            }
+        }
+    }
+}
+
+impl<'a, 'm> Visitor<'a> for Collector<'m> {
+    fn visit_block(&mut self, block: &WebBlock<'a>) {
+        self.check(block.stmts());
+        block.walk(self);
+    }
+
+    fn visit_repeat(&mut self, repeat: &WebRepeat<'a>) {
+        self.check(repeat.stmts());
+        repeat.walk(self);
+    }
+}
+
+/// Find the statements in a fragment of code that can never run, using
+/// `macros` to recognize macros that jump.
+///
+/// See the [module-level documentation](self) for an overview. Only the first
+/// statement of each unreachable stretch is returned.
+pub fn find_unreachable(code: &WebCode, macros: &MacroTable) -> Vec<Unreachable> {
+    let mut collector = Collector {
+        macros,
+        found: Vec::new(),
+    };
+
+    let toplevel_stmts: Vec<_> = code
+        .0
+        .iter()
+        .filter_map(|tl| match &tl.node {
+            WebToplevel::Statement(stmt, _) => Some(stmt),
+            _ => None,
+        })
+        .collect();
+
+    // Statements separated by declarations or other toplevels aren't in the
+    // same sequence, but in practice they don't mix.
+    if toplevel_stmts.len() == code.0.len() {
+        collector.check(toplevel_stmts);
+    }
+
+    collector.visit_code(code);
+    collector.found
+}
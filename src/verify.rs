@@ -0,0 +1,535 @@
+//! Checking tt-weave's cross-references against WEAVE's.
+//!
+//! tt-weave works out the numbering of the modules, the index, and the list of
+//! module names for itself, so it's worth checking that it gets the same
+//! answers as Knuth's WEAVE. [`run_weave`] runs WEAVE on a program and reads
+//! these back out of the TeX that it writes, as a [`Listing`], and
+//! [`Listing::from_state`] gives tt-weave's version, which [`compare`] checks
+//! against it. Running TeX on WEAVE's output isn't needed, since all of this
+//! can be read from the output itself.
+//!
+//! The index entries are compared as WEAVE writes them, in the form that
+//! [`crate::webmac`] imitates: `\\{identifier}`, `\.{string}`, and so on. The
+//! named modules are matched up by the modules that define them, since WEAVE
+//! typesets their names, where tt-weave leaves them as they're written.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tectonic_errors::prelude::*;
+
+use crate::{
+    encoding::InputEncoding,
+    state::{ModuleId, State},
+};
+
+/// The numbering and cross-references of a program, as WEAVE writes them.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Listing {
+    /// The number of modules.
+    pub n_modules: ModuleId,
+
+    /// The starred modules.
+    pub starred: BTreeSet<ModuleId>,
+
+    /// The index, mapping the text of each entry to the list of modules where
+    /// it appears, like `1, \[5], 10`, where `\[5]` marks a definition.
+    pub index: BTreeMap<String, String>,
+
+    /// The named modules, mapping the list of modules that define each one,
+    /// like `3, 7`, to its name and the notes listing the modules that use it,
+    /// like `\U12.`.
+    pub module_names: BTreeMap<String, (String, String)>,
+}
+
+/// Collapse runs of whitespace into single spaces.
+fn squash(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Split the index or the list of module names into its entries, each of
+/// which starts with `\:`. WEAVE breaks long lines, either at a space, or
+/// anywhere with a `%` at the end of the line, so the lines of an entry are
+/// joined back up.
+fn entries(text: &str) -> Vec<String> {
+    let mut entries: Vec<String> = Vec::new();
+
+    for line in text.lines() {
+        if let Some(entry) = line.strip_prefix("\\:") {
+            entries.push(entry.to_owned());
+        } else if let Some(last) = entries.last_mut() {
+            match last.strip_suffix('%') {
+                Some(joined) => *last = joined.to_owned(),
+                None => last.push(' '),
+            }
+
+            last.push_str(line);
+        }
+    }
+
+    entries
+}
+
+/// Whether an item in a list of references is a module number, possibly
+/// marked as a definition with `\[...]`.
+fn is_reference(item: &str) -> bool {
+    let number = item
+        .strip_prefix("\\[")
+        .and_then(|s| s.strip_suffix(']'))
+        .unwrap_or(item);
+
+    !number.is_empty() && number.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Parse the entries of the index, of the form `\:$text, $refs.`.
+fn parse_index(text: &str) -> BTreeMap<String, String> {
+    let mut index = BTreeMap::new();
+
+    for entry in entries(text) {
+        let entry = squash(&entry);
+        let entry = entry.strip_suffix('.').unwrap_or(&entry);
+        let items: Vec<_> = entry.split(", ").collect();
+        let n_refs = items.iter().rev().take_while(|i| is_reference(i)).count();
+        let split = items.len() - n_refs;
+        index.insert(items[..split].join(", "), items[split..].join(", "));
+    }
+
+    index
+}
+
+/// Parse the list of module names, whose entries have the form
+/// `\:\X$defs:$name\X$notes`.
+fn parse_module_names(text: &str) -> BTreeMap<String, (String, String)> {
+    let mut names = BTreeMap::new();
+
+    for entry in entries(text) {
+        let entry = squash(&entry);
+
+        let (defs, rest) = match entry.strip_prefix("\\X").and_then(|e| e.split_once(':')) {
+            Some(split) => split,
+            None => continue,
+        };
+
+        let (name, notes) = rest.rsplit_once("\\X").unwrap_or((rest, ""));
+        names.insert(defs.to_owned(), (name.to_owned(), notes.trim().to_owned()));
+    }
+
+    names
+}
+
+/// Get the part of the output between two control sequences.
+fn part<'t>(tex: &'t str, start: &str, end: &str) -> &'t str {
+    match tex.split_once(start) {
+        Some((_, after)) => after.split(end).next().unwrap_or(after),
+        None => "",
+    }
+}
+
+impl Listing {
+    /// Read the listing out of the TeX written by WEAVE. WEAVE writes the index
+    /// and the list of module names to separate files, which it `\input`s;
+    /// `read` is called to read them, given their names as they're written.
+    pub fn from_weave_output<F: Fn(&str) -> Result<String>>(tex: &str, read: F) -> Result<Self> {
+        let mut listing = Listing::default();
+
+        for line in tex.lines() {
+            let (starred, rest) = match (line.strip_prefix("\\M"), line.strip_prefix("\\N")) {
+                (Some(rest), _) => (false, rest),
+                (_, Some(rest)) => (true, rest),
+                _ => continue,
+            };
+
+            let module = match rest.split_once('.').map(|(n, _)| n.trim().parse()) {
+                Some(Ok(m)) => m,
+                _ => continue,
+            };
+
+            listing.n_modules = listing.n_modules.max(module);
+
+            if starred {
+                listing.starred.insert(module);
+            }
+        }
+
+        // The parts that are just an `\input` of another file are read from it.
+        let resolve = |text: &str| match text.trim().strip_prefix("\\input") {
+            Some(name) => read(name.trim()),
+            None => Ok(text.to_owned()),
+        };
+
+        listing.index = parse_index(&resolve(part(tex, "\\inx", "\\fin"))?);
+        listing.module_names = parse_module_names(&resolve(part(tex, "\\fin", "\\con"))?);
+        Ok(listing)
+    }
+
+    /// Get tt-weave's version of the listing of a program.
+    pub fn from_state(state: &State) -> Self {
+        let mut tex = String::new();
+        state
+            .emit_webmac_index(&mut tex)
+            .expect("writing to a String cannot fail");
+
+        Listing {
+            n_modules: state.n_modules(),
+            starred: state.major_modules().iter().map(|m| m.id).collect(),
+            index: parse_index(part(&tex, "\\inx", "\\fin")),
+            module_names: parse_module_names(part(&tex, "\\fin", "\\con")),
+        }
+    }
+}
+
+/// Run WEAVE on a WEB file, with a change file if one is given, and read the
+/// listing out of its output.
+///
+/// The `program` is the name of the WEAVE executable, which is run in a
+/// temporary directory, so that its output doesn't clobber anything. WEAVE
+/// copies the characters of its input through unchanged, so its output is read
+/// in the `encoding` of the input.
+pub fn run_weave(
+    program: &str,
+    web: &Path,
+    changes: Option<&Path>,
+    encoding: InputEncoding,
+) -> Result<Listing> {
+    // The directory is deleted when this goes out of scope, however we leave.
+    let temp_dir = TempDir::new()?;
+    let dir = temp_dir.path();
+
+    let mut command = Command::new(program);
+    command.current_dir(dir).arg(fs::canonicalize(web)?);
+
+    if let Some(changes) = changes {
+        command.arg(fs::canonicalize(changes)?);
+    }
+
+    let output = atry!(
+        command.output();
+        ["failed to run `{}`; is WEAVE installed?", program]
+    );
+
+    if !output.status.success() {
+        bail!(
+            "`{}` failed:\n{}",
+            program,
+            String::from_utf8_lossy(&output.stdout)
+        );
+    }
+
+    let read = |name: &str| -> Result<String> {
+        let path = dir.join(name);
+        let path = if path.is_file() {
+            path
+        } else {
+            path.with_extension("tex")
+        };
+
+        let bytes = atry!(
+            fs::read(&path);
+            ["failed to read WEAVE's output file `{}`", path.display()]
+        );
+        Ok(atry!(
+            encoding.decode(bytes);
+            ["failed to read WEAVE's output file `{}` as {} text", path.display(), encoding]
+        ))
+    };
+
+    let stem = a_ok_or!(
+        web.file_stem();
+        ["cannot determine the name of WEB file `{}`", web.display()]
+    );
+    Listing::from_weave_output(&read(&stem.to_string_lossy())?, read)
+}
+
+/// A temporary directory, which is deleted along with its contents when this
+/// is dropped.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Create a new, empty directory. Its name includes the time, so that it
+    /// can't be predicted, and a directory that already exists is never
+    /// reused.
+    fn new() -> Result<Self> {
+        let base = std::env::temp_dir();
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or_default();
+
+        let mut attempt: u32 = 0;
+
+        loop {
+            let name = format!(
+                "tt-weave-verify-{}-{:08x}",
+                std::process::id(),
+                nanos.wrapping_add(attempt.wrapping_mul(0x9e37_79b9))
+            );
+            let path = base.join(name);
+            let result = fs::create_dir(&path);
+
+            if let Err(e) = &result {
+                if e.kind() == io::ErrorKind::AlreadyExists && attempt < 100 {
+                    attempt += 1;
+                    continue;
+                }
+            }
+
+            atry!(
+                result;
+                ["failed to create temporary directory `{}`", path.display()]
+            );
+            return Ok(TempDir(path));
+        }
+    }
+
+    /// The path of the directory.
+    fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A difference between WEAVE's listing of a program and tt-weave's.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Divergence {
+    /// What differs, like `index entry \\{x}`.
+    pub what: String,
+
+    /// WEAVE's version, if it has one.
+    pub weave: Option<String>,
+
+    /// tt-weave's version, if it has one.
+    pub tt_weave: Option<String>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.weave, &self.tt_weave) {
+            (Some(w), Some(t)) => write!(
+                f,
+                "{}: WEAVE has `{}`, but tt-weave has `{}`",
+                self.what, w, t
+            ),
+            (Some(w), None) => write!(f, "{}: only WEAVE has it, with `{}`", self.what, w),
+            (None, Some(t)) => write!(f, "{}: only tt-weave has it, with `{}`", self.what, t),
+            (None, None) => write!(f, "{}", self.what),
+        }
+    }
+}
+
+/// Compare two maps, noting a divergence for each key whose values differ.
+/// The key is described with `what`, and the values with `value`.
+fn compare_maps<V: Eq>(
+    weave: &BTreeMap<String, V>,
+    tt_weave: &BTreeMap<String, V>,
+    what: impl Fn(&str) -> String,
+    value: impl Fn(&V) -> String,
+    divergences: &mut Vec<Divergence>,
+) {
+    let keys: BTreeSet<_> = weave.keys().chain(tt_weave.keys()).collect();
+
+    for key in keys {
+        let (w, t) = (weave.get(key), tt_weave.get(key));
+
+        if w != t {
+            divergences.push(Divergence {
+                what: what(key),
+                weave: w.map(&value),
+                tt_weave: t.map(&value),
+            });
+        }
+    }
+}
+
+/// Compare WEAVE's listing of a program with tt-weave's, returning the ways in
+/// which they differ, in the order of the listing.
+pub fn compare(weave: &Listing, tt_weave: &Listing) -> Vec<Divergence> {
+    let mut divergences = Vec::new();
+
+    if weave.n_modules != tt_weave.n_modules {
+        divergences.push(Divergence {
+            what: "number of modules".to_owned(),
+            weave: Some(weave.n_modules.to_string()),
+            tt_weave: Some(tt_weave.n_modules.to_string()),
+        });
+    }
+
+    for m in weave.starred.symmetric_difference(&tt_weave.starred) {
+        let starred = |l: &Listing| {
+            Some(
+                if l.starred.contains(m) {
+                    "starred"
+                } else {
+                    "unstarred"
+                }
+                .to_owned(),
+            )
+        };
+
+        divergences.push(Divergence {
+            what: format!("module {}", m),
+            weave: starred(weave),
+            tt_weave: starred(tt_weave),
+        });
+    }
+
+    compare_maps(
+        &weave.index,
+        &tt_weave.index,
+        |key| format!("index entry `{}`", key),
+        |refs| refs.clone(),
+        &mut divergences,
+    );
+
+    compare_maps(
+        &weave.module_names,
+        &tt_weave.module_names,
+        |defs| format!("module name defined in {}", defs),
+        |(name, notes)| format!("{} {}", name, notes).trim().to_owned(),
+        &mut divergences,
+    );
+
+    divergences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(entries: &[(&str, &str)]) -> BTreeMap<String, String> {
+        entries
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn index() {
+        let text = r#"\:\\{alpha}, 2, \[3], 10.
+\:\.{"a, b"}, 5.
+\:\&{begin}, 4,
+6.
+\:\\{long\_name}, 15, 1%
+7.
+"#;
+
+        assert_eq!(
+            parse_index(text),
+            map(&[
+                (r"\\{alpha}", r"2, \[3], 10"),
+                (r#"\.{"a, b"}"#, "5"),
+                (r"\&{begin}", "4, 6"),
+                (r"\\{long\_name}", "15, 17"),
+            ])
+        );
+    }
+
+    #[test]
+    fn module_names() {
+        let text = r"\:\X3, 7:Declare the procedure \\{foo}\X
+\U12.
+\:\X5:Globals in the outer block\X
+\U2, 9.
+\:\X8:Unused\X
+";
+
+        let names = parse_module_names(text);
+        assert_eq!(names.len(), 3);
+        assert_eq!(
+            names["3, 7"],
+            (
+                r"Declare the procedure \\{foo}".to_owned(),
+                r"\U12.".to_owned()
+            )
+        );
+        assert_eq!(
+            names["5"],
+            (
+                "Globals in the outer block".to_owned(),
+                r"\U2, 9.".to_owned()
+            )
+        );
+        assert_eq!(names["8"], ("Unused".to_owned(), String::new()));
+    }
+
+    #[test]
+    fn weave_output() {
+        let tex = r"\input webmac
+\N1.  Introduction.
+This is a program.
+\M2. More text.
+\Y\P\4\X2:Main\X${}\E{}$\6
+\N3.  The end.
+\inx
+\input INDEX
+\fin
+\:\X2:Main\X
+\U3.
+\con
+";
+
+        let read = |name: &str| -> Result<String> {
+            match name {
+                "INDEX" => Ok("\\:\\\\{x}, \\[2], 3.\n".to_owned()),
+                _ => bail!("unexpected file `{}`", name),
+            }
+        };
+
+        let listing = Listing::from_weave_output(tex, read).unwrap();
+        assert_eq!(listing.n_modules, 3);
+        assert_eq!(listing.starred, [1, 3].iter().copied().collect());
+        assert_eq!(listing.index, map(&[(r"\\{x}", r"\[2], 3")]));
+        assert_eq!(
+            listing.module_names["2"],
+            ("Main".to_owned(), r"\U3.".to_owned())
+        );
+    }
+
+    #[test]
+    fn comparison() {
+        let weave = Listing {
+            n_modules: 4,
+            starred: [1, 3].iter().copied().collect(),
+            index: map(&[(r"\\{x}", "1, 2"), (r"\\{y}", "3")]),
+            module_names: [("2".to_owned(), ("Main".to_owned(), r"\U3.".to_owned()))]
+                .iter()
+                .cloned()
+                .collect(),
+        };
+
+        assert!(compare(&weave, &weave.clone()).is_empty());
+
+        let tt_weave = Listing {
+            n_modules: 3,
+            starred: [1, 2].iter().copied().collect(),
+            index: map(&[(r"\\{x}", r"1, \[2]"), (r"\\{z}", "4")]),
+            module_names: BTreeMap::new(),
+        };
+
+        let divergences: Vec<_> = compare(&weave, &tt_weave)
+            .iter()
+            .map(|d| d.to_string())
+            .collect();
+
+        assert_eq!(
+            divergences,
+            [
+                "number of modules: WEAVE has `4`, but tt-weave has `3`",
+                "module 2: WEAVE has `unstarred`, but tt-weave has `starred`",
+                "module 3: WEAVE has `starred`, but tt-weave has `unstarred`",
+                r"index entry `\\{x}`: WEAVE has `1, 2`, but tt-weave has `1, \[2]`",
+                r"index entry `\\{y}`: only WEAVE has it, with `3`",
+                r"index entry `\\{z}`: only tt-weave has it, with `4`",
+                r"module name defined in 2: only WEAVE has it, with `Main \U3.`",
+            ]
+        );
+    }
+}
@@ -0,0 +1,38 @@
+//! A JavaScript-facing API, for use in WebAssembly builds.
+//!
+//! This is only available with the `wasm` feature. It's meant to let web pages,
+//! like the Tectonic book, prettify WEB code on the fly. Build it with
+//! something like:
+//!
+//! ```text
+//! wasm-pack build --target web -- --no-default-features --features wasm
+//! ```
+
+use clap::ArgEnum;
+use wasm_bindgen::prelude::*;
+
+use crate::Dialect;
+
+/// Prettify the code in some WEB source text as HTML.
+///
+/// The text should consist of one or more complete modules, optionally
+/// preceded by limbo material. The `dialect` is one of `web`, `web2c`, or
+/// `cweb`; if it's not given, `web2c` is assumed. The return value is a JSON
+/// array with one entry for each fragment of code, giving its `module`, its
+/// `context`, the `name` of the named module that it belongs to, if any, and
+/// its `html`.
+#[wasm_bindgen(js_name = prettifyHtml)]
+pub fn prettify_html(text: &str, dialect: Option<String>) -> Result<String, JsValue> {
+    let dialect = match dialect {
+        Some(d) => Dialect::from_str(&d, true).map_err(|e| JsValue::from_str(&e))?,
+        None => Dialect::default(),
+    };
+
+    let theme = &crate::themes::default_theme();
+
+    let result = crate::scan(text, dialect)
+        .and_then(|state| crate::weave_html(text, &state, theme, None))
+        .and_then(|fragments| Ok(serde_json::to_string(&fragments)?));
+
+    result.map_err(|e| JsValue::from_str(&format!("{:#}", e)))
+}
@@ -2,16 +2,30 @@
 //!
 //! This is *mostly* Pascal, but with a few additions. We implement parsing with
 //! `nom` where the underlying datatype is a sequence of tokens.
+//!
+//! With the `serde` feature, which is on by default, all of the AST types,
+//! including the C code of CWEB programs, implement `serde::Serialize`, so
+//! that parsed code can be cached or handed off to other tools. See
+//! [`crate::dump_ast`] for an example.
+
+// Some variants of the AST are much larger than others. That's fine for our
+// purposes, since the recursive nodes are allocated in an `AstArena`.
+#![allow(clippy::large_enum_variant)]
 
+use log::{debug, log_enabled, trace, Level};
 use nom::{
     branch::alt,
     bytes::complete::take_while,
     combinator::opt,
     multi::{many1, separated_list1},
-    Finish, InputLength,
+    InputIter, InputLength, InputTake,
 };
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use std::fmt;
 
 pub mod base;
+pub mod c;
 mod comment;
 mod const_declaration;
 mod define;
@@ -24,21 +38,54 @@ pub mod module_reference;
 mod modulified_declaration;
 mod preprocessor_directive;
 mod program_definition;
+mod query;
 mod standalone;
 mod statement;
 mod type_declaration;
 mod var_declaration;
+pub mod visit;
 mod webtype;
 
-use crate::prettify::{self, Prettifier, RenderInline, TexInsert, COMMENT_SCOPE};
-
-use self::{
-    base::*,
-    expr::{parse_expr, WebExpr},
-    statement::WebStatement,
+use crate::{
+    pascal_token::DelimiterKind,
+    prettify::{self, Prettifier, RenderInline, TexInsert, COMMENT_SCOPE},
 };
 
-pub use self::base::{WebSyntax, WebToken};
+use self::base::*;
+
+pub use self::{
+    base::{AstArena, NodeSpan, Spanned, WebSyntax, WebToken},
+    comment::WebComment,
+    const_declaration::WebConstantDeclaration,
+    define::{WebDefine, WebDefineRhs},
+    expr::{
+        parse_expr, WebBinaryExpr, WebCallExpr, WebExpr, WebFieldAccessExpr, WebFormatExpr,
+        WebIndexExpr, WebPostfixUnaryExpr, WebPrefixUnaryExpr,
+    },
+    format::WebFormat,
+    forward_declaration::WebForwardDeclaration,
+    function_definition::{
+        WebConstant, WebFunctionDefinition, WebInPlaceVariables, WebLabel, WebVarBlockItem,
+        WebVariables,
+    },
+    label_declaration::WebLabelDeclaration,
+    modulified_declaration::WebModulifiedDeclaration,
+    preprocessor_directive::{
+        DirectiveNesting, DirectiveNode, DirectiveSite, DirectiveTree, NestingTracker,
+        WebPreprocessorDirective,
+    },
+    program_definition::WebProgramDefinition,
+    standalone::WebStandalone,
+    statement::{
+        parse_statement_base as parse_statement, SpecialFreeCase, WebAssignment, WebBlock, WebCase,
+        WebCaseItem, WebFor, WebGoto, WebIf, WebLoop, WebMacroCall, WebModMatchCaseItem,
+        WebOtherCasesItem, WebRepeat, WebStandardCaseItem, WebStatement, WebWhile,
+    },
+    type_declaration::WebTypeDeclaration,
+    var_declaration::WebVarDeclaration,
+    visit::Visitor,
+    webtype::{parse_type, RangeBound, WebArrayType, WebRecordType, WebType},
+};
 
 /// A top-level WEB production.
 ///
@@ -49,6 +96,7 @@ pub use self::base::{WebSyntax, WebToken};
 ///
 /// Toplevel module references are captured as Statements.
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub enum WebToplevel<'a> {
     /// A `@d` definition.
     Define(define::WebDefine<'a>),
@@ -84,11 +132,15 @@ pub enum WebToplevel<'a> {
     ForwardDeclaration(forward_declaration::WebForwardDeclaration<'a>),
 
     /// A Pascal statement.
-    Statement(WebStatement<'a>, Option<WebComment<'a>>),
+    Statement(Spanned<WebStatement<'a>>, Option<WebComment<'a>>),
 
     /// No code at all, needed for XeTeX(2022.0):23.
     Empty,
 
+    /// Tokens that couldn't be parsed, which were skipped so that the rest of
+    /// the code could still be handled. See [`WebCode::parse_with_recovery`].
+    Unparsed(Vec<WebToken<'a>>),
+
     /// `( $ident $ident )`, needed for WEAVE:143
     SpecialParenTwoIdent(StringSpan<'a>, StringSpan<'a>),
 
@@ -96,10 +148,10 @@ pub enum WebToplevel<'a> {
     SpecialEmptyBrackets,
 
     /// `$relational_op $expr`, needed for WEAVE:144
-    SpecialRelationalExpr(PascalToken<'a>, WebExpr<'a>),
+    SpecialRelationalExpr(PascalToken<'a>, Spanned<WebExpr<'a>>),
 
     /// `$expr .. $expr`, needed for WEAVE:144, XeTeX(2022.0):83
-    SpecialRange(WebExpr<'a>, WebExpr<'a>),
+    SpecialRange(Spanned<WebExpr<'a>>, Spanned<WebExpr<'a>>),
 
     /// `$begin_like $function $end_like`, needed for WEAVE:260
     SpecialIfdefFunction(
@@ -125,7 +177,7 @@ pub enum WebToplevel<'a> {
     ),
 
     /// `$start_meta_comment $statement $end_meta_comment`, needed for XeTeX(2022.0):31.
-    SpecialCommentedOut(WebStatement<'a>),
+    SpecialCommentedOut(Spanned<WebStatement<'a>>),
 
     /// `$[$term0, $term1a .. $term1b, ...]`, needed for XeTeX(2022.0):49, and similar
     /// with parentheses, for XeTeX(2022.0):620. The bool is true
@@ -145,18 +197,20 @@ pub enum WebToplevel<'a> {
     },
 
     /// `$expr == $expr`, needed for XeTeX(2022.0):134.
-    SpecialInlineDefine(WebExpr<'a>, WebExpr<'a>),
+    SpecialInlineDefine(Spanned<WebExpr<'a>>, Spanned<WebExpr<'a>>),
 
     /// `$expr, $expr, $expr {,}?`, needed for XeTeX(2022.0):375, with optional
-    /// trailing comma, needed for XeTeX(2022.0):1102 and friends.
+    /// trailing comma, needed for XeTeX(2022.0):1102 and friends, or leading
+    /// comma, needed for the labels that BibTeX adds to its outer block.
     SpecialCommaExprs {
-        exprs: Vec<Box<WebExpr<'a>>>,
+        exprs: Vec<&'a Spanned<WebExpr<'a>>>,
+        leading_comma: bool,
         trailing_comma: bool,
     },
 
     /// `[$expr..$expr]$ident`, needed for XeTeX(2022.0):576, which uses some
     /// macros to create a specialized array table.
-    SpecialArrayMacro(WebExpr<'a>, WebExpr<'a>, StringSpan<'a>),
+    SpecialArrayMacro(Spanned<WebExpr<'a>>, Spanned<WebExpr<'a>>, StringSpan<'a>),
 
     /// `$ident=.25`, needed for XeTeX(2022.0):582.
     SpecialFloatEquality(StringSpan<'a>, PascalToken<'a>),
@@ -174,10 +228,16 @@ pub enum WebToplevel<'a> {
     SpecialImbalancedEnd,
 
     /// `$expr .`, needed for XeTeX(2022.0):684.
-    SpecialExprPeriod(WebExpr<'a>),
+    SpecialExprPeriod(Spanned<WebExpr<'a>>),
+
+    /// `$label, $label, $label;`, with optional comments: the labels in the
+    /// outer block of TeX and METAFONT, which get their `label` keyword from
+    /// the program definition.
+    SpecialLabelList(Vec<WebLabel<'a>>),
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub enum SpecialListLiteralTerm<'a> {
     Single(PascalToken<'a>),
     Range(PascalToken<'a>, PascalToken<'a>),
@@ -211,50 +271,252 @@ impl<'a> RenderInline for SpecialListLiteralTerm<'a> {
 
 /// A block of WEB code: a sequence of parsed-out WEB toplevels
 #[derive(Clone, Debug, Eq, PartialEq)]
-pub struct WebCode<'a>(pub Vec<WebToplevel<'a>>);
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct WebCode<'a>(pub Vec<Spanned<WebToplevel<'a>>>);
+
+/// A parser for a custom kind of toplevel.
+///
+/// Projects whose WEB code contains idiosyncratic constructs can handle them
+/// with custom parsers, which are tried before the built-in ones. A custom
+/// parser can build on the combinators in [`base`] and the building blocks
+/// [`parse_expr`], [`parse_statement`], and [`parse_type`], and should fail
+/// with a `nom::Err::Error` if its construct isn't present. Once it's sure that
+/// it is, it can use `nom::combinator::cut` so that any later problem is
+/// reported as-is, rather than as a failure to find any toplevel. See
+/// [`WebCode::parse_with`] and [`crate::State::add_toplevel_parser`].
+pub type ToplevelParser = for<'a> fn(ParseInput<'a>) -> ParseResult<'a, WebToplevel<'a>>;
+
+/// A failure to parse a block of WEB code.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct WebParseError {
+    /// Where the parse failed: the span of the token that couldn't be handled,
+    /// or an empty span at the end of the code.
+    pub span: NodeSpan,
+
+    /// What went wrong.
+    pub kind: WebErrorKind,
+}
+
+impl fmt::Display for WebParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let delim = |dk: &DelimiterKind, open: bool| match (dk, open) {
+            (DelimiterKind::Paren, true) => "(",
+            (DelimiterKind::Paren, false) => ")",
+            (DelimiterKind::SquareBracket, true) => "[",
+            (DelimiterKind::SquareBracket, false) => "]",
+            (DelimiterKind::MetaComment, true) => "@{",
+            (DelimiterKind::MetaComment, false) => "@}",
+        };
+
+        match &self.kind {
+            WebErrorKind::Eof => write!(f, "the code ended unexpectedly"),
+            WebErrorKind::ExpectedPascalToken => write!(f, "expected a Pascal token"),
+            WebErrorKind::ExpectedIdentifier => write!(f, "expected an identifier"),
+            WebErrorKind::ExpectedStringLiteral => write!(f, "expected a string literal"),
+            WebErrorKind::ExpectedIntLiteral => write!(f, "expected an integer literal"),
+            WebErrorKind::ExpectedComment => write!(f, "expected a comment"),
+            WebErrorKind::ExpectedToplevel => write!(f, "expected a declaration or statement"),
+            WebErrorKind::ExpectedExpr => write!(f, "expected an expression"),
+            WebErrorKind::ExpectedOperator => write!(f, "expected an operator"),
+            WebErrorKind::ExpectedBlockOpener => write!(f, "expected `begin` or similar"),
+            WebErrorKind::ExpectedBlockCloser => write!(f, "expected `end` or similar"),
+            WebErrorKind::ExpectedReservedWord(rw) => write!(f, "expected `{}`", rw),
+            WebErrorKind::ExpectedAnyReservedWord => write!(f, "expected a reserved word"),
+            WebErrorKind::ExpectedFormattedIdentifier(rw) => {
+                write!(f, "expected an identifier formatted like `{}`", rw)
+            }
+            WebErrorKind::UnexpectedReservedWord(rw) => write!(f, "unexpected `{}`", rw),
+            WebErrorKind::ExpectedOpenDelimiter(dk) => write!(f, "expected `{}`", delim(dk, true)),
+            WebErrorKind::ExpectedCloseDelimiter(dk) => {
+                write!(f, "expected `{}`", delim(dk, false))
+            }
+            WebErrorKind::ExpectedVerbatimPascal => write!(f, "expected verbatim Pascal"),
+            WebErrorKind::ExpectedStatementMacro => write!(f, "expected a statement-like macro"),
+            WebErrorKind::IncompleteDefine => write!(f, "incomplete macro definition"),
+            WebErrorKind::NotDefineEdge => write!(f, "expected the end of a macro definition"),
+            WebErrorKind::StringLiteralMergeFail => {
+                write!(f, "couldn't merge adjacent string literals")
+            }
+            WebErrorKind::TooDeeplyNested => write!(
+                f,
+                "the code is nested more than {} levels deep",
+                MAX_NESTING_DEPTH
+            ),
+            WebErrorKind::Nom(k) => write!(f, "parse failure ({})", k.description()),
+        }
+    }
+}
 
 impl<'a> WebCode<'a> {
-    /// Parse a sequence of WEB tokens into sequence of toplevels.
-    pub fn parse(syntax: &'a WebSyntax<'a>) -> Option<WebCode<'a>> {
-        let input = ParseInput(&syntax.0[..]);
+    /// Parse a sequence of WEB tokens into sequence of toplevels, allocating
+    /// the nodes in `arena`.
+    pub fn parse(syntax: &'a WebSyntax<'a>, arena: &'a AstArena<'a>) -> Option<WebCode<'a>> {
+        Self::parse_with(syntax, arena, &[])
+    }
+
+    /// Parse a sequence of WEB tokens into sequence of toplevels, trying the
+    /// `custom` parsers, in order, before the built-in ones.
+    pub fn parse_with(
+        syntax: &'a WebSyntax<'a>,
+        arena: &'a AstArena<'a>,
+        custom: &[ToplevelParser],
+    ) -> Option<WebCode<'a>> {
+        Self::try_parse_with(syntax, arena, custom).ok()
+    }
+
+    /// Like [`Self::parse_with`], but describing the problem if the parse
+    /// fails. If there are several problems, the first one is returned.
+    pub fn try_parse_with(
+        syntax: &'a WebSyntax<'a>,
+        arena: &'a AstArena<'a>,
+        custom: &[ToplevelParser],
+    ) -> Result<WebCode<'a>, WebParseError> {
+        let (code, errors) = Self::parse_with_recovery(syntax, arena, custom);
+
+        match errors.first() {
+            Some(e) => Err(*e),
+            None => Ok(code),
+        }
+    }
+
+    /// Parse a sequence of WEB tokens into a sequence of toplevels, recovering
+    /// from problems rather than giving up.
+    ///
+    /// When a toplevel can't be parsed, the tokens up to the next plausible
+    /// boundary between toplevels (a semicolon, an `end`, or the end of the
+    /// code) are captured as a [`WebToplevel::Unparsed`], the problem is
+    /// recorded, and parsing continues after them. So, the returned code always
+    /// covers all of the tokens, and the problems are returned in order.
+    pub fn parse_with_recovery(
+        syntax: &'a WebSyntax<'a>,
+        arena: &'a AstArena<'a>,
+        custom: &[ToplevelParser],
+    ) -> (WebCode<'a>, Vec<WebParseError>) {
+        let mut input = ParseInput::new(syntax, arena);
 
         if input.input_len() == 0 {
-            return Some(WebCode(vec![WebToplevel::Empty]));
+            let empty = Spanned {
+                span: input.span_to(input),
+                node: WebToplevel::Empty,
+            };
+            return (WebCode(vec![empty]), Vec::new());
         }
 
-        match many1(parse_toplevel)(input).finish() {
-            Ok((remainder, value)) => {
-                if remainder.input_len() > 0 {
-                    eprintln!("\nincomplete parse");
-                    return None;
-                } else {
-                    return Some(WebCode(value));
+        let mut toplevels = Vec::new();
+        let mut errors = Vec::new();
+
+        while input.input_len() > 0 {
+            let n = input
+                .iter_elements()
+                .position(|t| !is_ignored_token(t))
+                .unwrap_or_else(|| input.input_len());
+            let start = input.take_split(n).0;
+
+            let (at, kind) = match parse_toplevel(input, custom) {
+                Ok((rest, tl)) if rest.input_len() < input.input_len() => {
+                    toplevels.push(tl);
+                    input = rest;
+                    continue;
+                }
+
+                Err(nom::Err::Failure((rest, kind))) => {
+                    debug!("parse failure: {:?}", kind);
+                    (rest, kind)
+                }
+
+                // The error from the parser that got the furthest describes the
+                // problem, unless none of them got past the first token, in
+                // which case it's just the complaint of whichever one won out.
+                Err(nom::Err::Error((rest, kind))) if rest.input_len() < start.input_len() => {
+                    (rest, kind)
                 }
+
+                _ => (start, WebErrorKind::ExpectedToplevel),
+            };
+
+            errors.push(WebParseError {
+                span: at.next_span(),
+                kind,
+            });
+
+            let (rest, skipped) = skip_to_boundary(input, at);
+            debug!("skipping {} unparseable tokens", skipped.input_len());
+            toplevels.push(Spanned {
+                span: input.span_to(rest),
+                node: WebToplevel::Unparsed(skipped.tokens.to_vec()),
+            });
+            input = rest;
+        }
+
+        (WebCode(toplevels), errors)
+    }
+}
+
+/// Find the next plausible boundary between toplevels at or after the point
+/// `at` where a parse failed: just after a semicolon, or after an `end` and any
+/// semicolon or period following it. If there isn't one, the boundary is the
+/// end of the code. Returns the remaining input and the skipped input, which
+/// always includes at least one token.
+fn skip_to_boundary<'a>(
+    input: ParseInput<'a>,
+    at: ParseInput<'a>,
+) -> (ParseInput<'a>, ParseInput<'a>) {
+    let toks = input.tokens;
+    let mut n = toks.len();
+
+    for (i, tok) in toks.iter().enumerate().skip(n - at.input_len()) {
+        match tok {
+            WebToken::Pascal(PascalToken::Semicolon) => {
+                n = i + 1;
+                break;
             }
 
-            Err((_remainder, e)) => {
-                eprintln!("parse error: {:?}", e);
-                return None;
+            WebToken::Pascal(PascalToken::ReservedWord(rw))
+                if rw.value == PascalReservedWord::End =>
+            {
+                n = i + 1;
+
+                if let Some(WebToken::Pascal(PascalToken::Semicolon | PascalToken::Period)) =
+                    toks.get(n)
+                {
+                    n += 1;
+                }
+
+                break;
             }
+
+            _ => {}
         }
     }
+
+    input.take_split(n)
 }
 
 fn is_ignored_token(t: WebToken) -> bool {
-    match t {
+    matches!(
+        t,
         WebToken::Pascal(PascalToken::Formatting)
-        | WebToken::Pascal(PascalToken::ForcedEol)
-        | WebToken::Pascal(PascalToken::TexString(..)) => true,
-        _ => false,
-    }
+            | WebToken::Pascal(PascalToken::ForcedEol)
+            | WebToken::Pascal(PascalToken::TexString(..))
+    )
 }
 
-fn parse_toplevel<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebToplevel<'a>> {
+fn parse_toplevel<'a>(
+    input: ParseInput<'a>,
+    custom: &[ToplevelParser],
+) -> ParseResult<'a, Spanned<WebToplevel<'a>>> {
     let (input, _) = take_while(is_ignored_token)(input)?;
 
+    for parser in custom {
+        match spanned(parser)(input) {
+            Err(nom::Err::Error(_)) => {}
+            result => return result,
+        }
+    }
+
     // We have so many possibilities that we need to use multiple alt() calls to
     // avoid the limit of 20-item tuples!
-    let result = alt((
+    let result = spanned(alt((
         // Define comes first since its tail is a toplevel in and of itself.
         define::parse_define,
         format::parse_format,
@@ -285,38 +547,204 @@ fn parse_toplevel<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebToplevel<'a>>
             tl_specials::parse_special_coeff_array,
             tl_specials::parse_special_imbalanced_end,
             tl_specials::parse_special_expr_period,
+            tl_specials::parse_special_label_list,
         )),
         statement::parse_statement,
         standalone::parse_standalone,
-    ))(input);
-
-    //match &result {
-    //    Ok((input, v)) => {
-    //        eprintln!("TL OK: {:?}", v);
-    //        let n = usize::min(input.input_len(), 8);
-    //        for tok in &input.0[..n] {
-    //            eprintln!("- {:?}", tok);
-    //        }
-    //    }
-    //
-    //    Err(nom::Err::Error((input, kind))) => {
-    //        if kind != &WebErrorKind::Eof {
-    //            eprintln!("TL error {:?}", kind);
-    //            let n = usize::min(input.input_len(), 20);
-    //            for tok in &input.0[..n] {
-    //                eprintln!("- {:?}", tok);
-    //            }
-    //        }
-    //    }
-    //
-    //    _ => {
-    //        eprintln!("TL other failure???");
-    //    }
-    //}
+    )))(input);
+
+    if log_enabled!(Level::Trace) {
+        match &result {
+            Ok((input, v)) => {
+                trace!("TL OK: {:?}", v);
+                let n = usize::min(input.input_len(), 8);
+                for tok in &input.tokens[..n] {
+                    trace!("- {:?}", tok);
+                }
+            }
+
+            Err(nom::Err::Error((input, kind))) => {
+                if kind != &WebErrorKind::Eof {
+                    trace!("TL error {:?}", kind);
+                    let n = usize::min(input.input_len(), 20);
+                    for tok in &input.tokens[..n] {
+                        trace!("- {:?}", tok);
+                    }
+                }
+            }
+
+            _ => {
+                trace!("TL other failure???");
+            }
+        }
+    }
 
     result
 }
 
+// Walking
+
+impl<'a> WebCode<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        for tl in &self.0 {
+            visitor.visit_toplevel(tl);
+        }
+    }
+}
+
+impl<'a> WebToplevel<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        match self {
+            WebToplevel::Define(d) => visitor.visit_define(d),
+            WebToplevel::Format(f) => visitor.visit_format(f),
+            WebToplevel::Standalone(s) => visitor.visit_standalone(s),
+            WebToplevel::ProgramDefinition(p) => visitor.visit_program_definition(p),
+            WebToplevel::LabelDeclaration(l) => visitor.visit_label_declaration(l),
+            WebToplevel::ModulifiedDeclaration(m) => visitor.visit_modulified_declaration(m),
+            WebToplevel::FunctionDefinition(f) => visitor.visit_function_definition(f),
+            WebToplevel::ConstDeclaration(c) => visitor.visit_const_declaration(c),
+            WebToplevel::VarDeclaration(v) => visitor.visit_var_declaration(v),
+            WebToplevel::TypeDeclaration(t) => visitor.visit_type_declaration(t),
+            WebToplevel::ForwardDeclaration(f) => visitor.visit_forward_declaration(f),
+
+            WebToplevel::Statement(s, comment) => {
+                visitor.visit_statement(s);
+
+                if let Some(c) = comment {
+                    visitor.visit_comment(c);
+                }
+            }
+
+            WebToplevel::Empty
+            | WebToplevel::SpecialEmptyBrackets
+            | WebToplevel::SpecialImbalancedEnd => {}
+
+            WebToplevel::Unparsed(toks) => {
+                for tok in toks {
+                    match tok {
+                        WebToken::Pascal(t) => visitor.visit_token(t),
+                        WebToken::Comment(c) => visitor.visit_comment(&WebComment(c.clone())),
+                        WebToken::ModuleReference(mr) => visitor.visit_module_reference(mr),
+                    }
+                }
+            }
+
+            WebToplevel::SpecialParenTwoIdent(i1, i2) => {
+                visitor.visit_identifier(i1);
+                visitor.visit_identifier(i2);
+            }
+
+            WebToplevel::SpecialRelationalExpr(op, e) => {
+                visitor.visit_token(op);
+                visitor.visit_expr(e);
+            }
+
+            WebToplevel::SpecialRange(e1, e2) | WebToplevel::SpecialInlineDefine(e1, e2) => {
+                visitor.visit_expr(e1);
+                visitor.visit_expr(e2);
+            }
+
+            WebToplevel::SpecialIfdefFunction(begin, f, end) => {
+                visitor.visit_token(begin);
+                visitor.visit_function_definition(f);
+                visitor.visit_token(end);
+            }
+
+            WebToplevel::SpecialIfdefForward(begin, f, end) => {
+                visitor.visit_token(begin);
+                visitor.visit_forward_declaration(f);
+                visitor.visit_token(end);
+            }
+
+            WebToplevel::SpecialIfdefVarDeclaration(c1, begin, vars, end, c2) => {
+                if let Some(c) = c1 {
+                    visitor.visit_comment(c);
+                }
+
+                visitor.visit_token(begin);
+
+                for v in vars {
+                    visitor.visit_var_declaration(v);
+                }
+
+                visitor.visit_token(end);
+
+                if let Some(c) = c2 {
+                    visitor.visit_comment(c);
+                }
+            }
+
+            WebToplevel::SpecialCommentedOut(s) => visitor.visit_statement(s),
+
+            WebToplevel::SpecialListLiteral { terms, .. } => {
+                for term in terms {
+                    term.walk(visitor);
+                }
+            }
+
+            WebToplevel::SpecialIdentInListLiteral(ident, terms) => {
+                visitor.visit_identifier(ident);
+
+                for term in terms {
+                    term.walk(visitor);
+                }
+            }
+
+            WebToplevel::SpecialListLiteralAssignment { lhs, rhs } => {
+                for term in lhs.iter().chain(rhs.iter()) {
+                    term.walk(visitor);
+                }
+            }
+
+            WebToplevel::SpecialCommaExprs { exprs, .. } => {
+                for e in exprs {
+                    visitor.visit_expr(e);
+                }
+            }
+
+            WebToplevel::SpecialArrayMacro(e1, e2, ident) => {
+                visitor.visit_expr(e1);
+                visitor.visit_expr(e2);
+                visitor.visit_identifier(ident);
+            }
+
+            WebToplevel::SpecialFloatEquality(ident, tok) => {
+                visitor.visit_identifier(ident);
+                visitor.visit_token(tok);
+            }
+
+            WebToplevel::SpecialCoeffArray { name, coeff, base } => {
+                visitor.visit_identifier(name);
+                visitor.visit_token(coeff);
+                visitor.visit_token(base);
+            }
+
+            WebToplevel::SpecialExprPeriod(e) => visitor.visit_expr(e),
+
+            WebToplevel::SpecialLabelList(labels) => {
+                for label in labels {
+                    label.walk(visitor);
+                }
+            }
+        }
+    }
+}
+
+impl<'a> SpecialListLiteralTerm<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        match self {
+            SpecialListLiteralTerm::Single(t) => visitor.visit_token(t),
+            SpecialListLiteralTerm::Range(t1, t2) | SpecialListLiteralTerm::Unary(t1, t2) => {
+                visitor.visit_token(t1);
+                visitor.visit_token(t2);
+            }
+        }
+    }
+}
+
 mod tl_specials {
     use nom::{combinator::map, sequence::tuple};
 
@@ -357,7 +785,7 @@ mod tl_specials {
     }
 
     fn relational_ident_op<'a>(input: ParseInput<'a>) -> ParseResult<'a, PascalToken<'a>> {
-        let (input, wt) = next_token(input)?;
+        let (rest, wt) = next_token(input)?;
 
         if let WebToken::Pascal(pt) = wt {
             match pt {
@@ -366,13 +794,13 @@ mod tl_specials {
                 | PascalToken::Less
                 | PascalToken::LessEquals
                 | PascalToken::Equals
-                | PascalToken::NotEquals => return Ok((input, pt)),
+                | PascalToken::NotEquals => return Ok((rest, pt)),
 
                 _ => {}
             }
         }
 
-        return new_parse_err(input, WebErrorKind::Eof);
+        new_parse_err(input, WebErrorKind::ExpectedOperator)
     }
 
     pub fn parse_special_range<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebToplevel<'a>> {
@@ -539,20 +967,25 @@ mod tl_specials {
         input: ParseInput<'a>,
     ) -> ParseResult<'a, WebToplevel<'a>> {
         let (input, t) = tuple((
-            separated_list1(pascal_token(PascalToken::Comma), map(parse_expr, Box::new)),
+            opt(pascal_token(PascalToken::Comma)),
+            separated_list1(
+                pascal_token(PascalToken::Comma),
+                map(parse_expr, |e| input.arena().alloc_expr(e)),
+            ),
             opt(pascal_token(PascalToken::Comma)),
             self::define::peek_end_of_define,
         ))(input)?;
 
-        if t.0.len() < 2 && !t.1.is_some() {
+        if t.1.len() < 2 && t.0.is_none() && t.2.is_none() {
             // Don't eat single expressions -- we want those to be expr statements.
-            new_parse_err(input, WebErrorKind::Eof)
+            new_parse_err(input, WebErrorKind::ExpectedPascalToken)
         } else {
             Ok((
                 input,
                 WebToplevel::SpecialCommaExprs {
-                    exprs: t.0,
-                    trailing_comma: t.1.is_some(),
+                    exprs: t.1,
+                    leading_comma: t.0.is_some(),
+                    trailing_comma: t.2.is_some(),
                 },
             ))
         }
@@ -632,12 +1065,60 @@ mod tl_specials {
             |t| WebToplevel::SpecialExprPeriod(t.0),
         )(input)
     }
+
+    pub fn parse_special_label_list<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebToplevel<'a>> {
+        let (rest, labels) = function_definition::parse_label_list(input)?;
+        let (rest, _) = define::peek_end_of_define(rest)?;
+
+        if labels.len() < 2 {
+            // Don't eat single expressions -- we want those to be expr statements.
+            new_parse_err(input, WebErrorKind::ExpectedPascalToken)
+        } else {
+            Ok((rest, WebToplevel::SpecialLabelList(labels)))
+        }
+    }
 }
 
 impl<'a> WebToplevel<'a> {
-    pub fn prettify(&self, dest: &mut Prettifier) {
+    /// If this toplevel is a bare preprocessor directive, get it.
+    pub fn as_preprocessor_directive(&self) -> Option<&WebPreprocessorDirective<'a>> {
+        if let WebToplevel::Statement(stmt, _) = self {
+            stmt.as_preprocessor_directive()
+        } else {
+            None
+        }
+    }
+
+    /// Prettify this toplevel, given which of its tokens started a new line in
+    /// the source.
+    ///
+    /// This is the same as [`Self::prettify`], except that code that couldn't
+    /// be parsed is emitted as a listing that follows the source's line
+    /// breaks, marked so that readers know that it hasn't been laid out.
+    pub fn prettify_with_line_breaks(
+        &self,
+        line_breaks: &[bool],
+        is_last: bool,
+        dest: &mut Prettifier,
+    ) {
+        if let WebToplevel::Unparsed(toks) = self {
+            dest.scope_push(*COMMENT_SCOPE, "/*could not be parsed:*/");
+            dest.newline_needed();
+            tl_prettify::unparsed(toks, Some(line_breaks), dest);
+        } else {
+            self.prettify(is_last, dest);
+        }
+    }
+
+    /// Prettify this toplevel. A statement gets a semicolon according to the
+    /// [`prettify::SemicolonPolicy`], as in a `begin`/`end` block; `is_last`
+    /// says whether it's the last of a run of statements, like the last
+    /// statement of a block.
+    pub fn prettify(&self, is_last: bool, dest: &mut Prettifier) {
         match self {
-            WebToplevel::Statement(stmt, comment) => tl_prettify::statement(stmt, comment, dest),
+            WebToplevel::Statement(stmt, comment) => {
+                tl_prettify::statement(stmt, comment, is_last, dest)
+            }
             WebToplevel::Standalone(s) => s.render_inline(dest),
             WebToplevel::Define(d) => d.prettify(dest),
             WebToplevel::Format(f) => f.prettify(dest),
@@ -650,6 +1131,7 @@ impl<'a> WebToplevel<'a> {
             WebToplevel::TypeDeclaration(td) => td.prettify(dest),
             WebToplevel::ForwardDeclaration(fd) => fd.prettify(dest),
             WebToplevel::Empty => dest.scope_push(*COMMENT_SCOPE, "/*nothing*/"),
+            WebToplevel::Unparsed(toks) => tl_prettify::unparsed(toks, None, dest),
 
             WebToplevel::SpecialParenTwoIdent(id1, id2) => {
                 tl_prettify::special_paren_two_ident(id1, id2, dest)
@@ -685,8 +1167,9 @@ impl<'a> WebToplevel<'a> {
             }
             WebToplevel::SpecialCommaExprs {
                 exprs,
+                leading_comma,
                 trailing_comma,
-            } => tl_prettify::special_comma_exprs(exprs, *trailing_comma, dest),
+            } => tl_prettify::special_comma_exprs(exprs, *leading_comma, *trailing_comma, dest),
             WebToplevel::SpecialArrayMacro(e1, e2, id) => {
                 tl_prettify::special_array_macro(e1, e2, id, dest)
             }
@@ -698,6 +1181,9 @@ impl<'a> WebToplevel<'a> {
             }
             WebToplevel::SpecialImbalancedEnd => {}
             WebToplevel::SpecialExprPeriod(expr) => tl_prettify::special_expr_period(expr, dest),
+            WebToplevel::SpecialLabelList(labels) => {
+                function_definition::render_label_list(labels, dest)
+            }
         }
     }
 }
@@ -708,14 +1194,16 @@ mod tl_prettify {
     pub fn statement<'a>(
         stmt: &WebStatement<'a>,
         comment: &Option<WebComment<'a>>,
+        is_last: bool,
         dest: &mut Prettifier,
     ) {
         // Most statements won't be able to be rendered inline, but a few can.
+        // Leave room for a semicolon.
         let clen = comment.as_ref().map(|c| c.measure_inline()).unwrap_or(0);
-        let slen = stmt.measure_inline();
+        let slen = stmt.measure_inline() + 1;
 
         if dest.fits(clen + slen + 1) {
-            stmt.render_inline(dest);
+            stmt.render_inline_with_semicolon(is_last, dest);
 
             if let Some(c) = comment.as_ref() {
                 dest.space();
@@ -727,7 +1215,7 @@ mod tl_prettify {
                 dest.newline_needed();
             }
 
-            stmt.render_inline(dest);
+            stmt.render_inline_with_semicolon(is_last, dest);
         } else {
             if let Some(c) = comment.as_ref() {
                 c.render_inline(dest);
@@ -735,6 +1223,51 @@ mod tl_prettify {
             }
 
             stmt.render_flex(dest);
+            stmt.maybe_semicolon_in_block(is_last, dest);
+        }
+
+        dest.newline_needed();
+    }
+
+    /// Render tokens that couldn't be parsed. We don't know how they should
+    /// be laid out, so if `line_breaks` is given, saying which tokens started
+    /// a new line in the source, we follow the source; otherwise we put them
+    /// all on one line.
+    pub fn unparsed<'a>(
+        toks: &[WebToken<'a>],
+        line_breaks: Option<&[bool]>,
+        dest: &mut Prettifier,
+    ) {
+        let mut first = true;
+        let mut line_break = false;
+
+        for (i, tok) in toks.iter().enumerate() {
+            line_break |= line_breaks.and_then(|b| b.get(i)).copied().unwrap_or(false);
+
+            if is_ignored_token(tok.clone()) {
+                continue;
+            }
+
+            let punct = matches!(
+                tok,
+                WebToken::Pascal(PascalToken::Semicolon | PascalToken::Comma)
+            );
+
+            if first {
+                first = false;
+            } else if line_break {
+                dest.newline_needed();
+            } else if !punct {
+                dest.space();
+            }
+
+            line_break = false;
+
+            match tok {
+                WebToken::Pascal(t) => t.render_inline(dest),
+                WebToken::Comment(c) => WebComment(c.clone()).render_inline(dest),
+                WebToken::ModuleReference(mr) => mr.render_inline(dest),
+            }
         }
 
         dest.newline_needed();
@@ -751,7 +1284,7 @@ mod tl_prettify {
         dest.noscope_push(')');
     }
 
-    pub fn special_empty_brackets<'a>(dest: &mut Prettifier) {
+    pub fn special_empty_brackets(dest: &mut Prettifier) {
         dest.noscope_push("[]");
     }
 
@@ -893,10 +1426,15 @@ mod tl_prettify {
     }
 
     pub fn special_comma_exprs<'a>(
-        exprs: &Vec<Box<WebExpr<'a>>>,
+        exprs: &Vec<&'a Spanned<WebExpr<'a>>>,
+        leading_comma: bool,
         trailing_comma: bool,
         dest: &mut Prettifier,
     ) {
+        if leading_comma {
+            dest.noscope_push(", ");
+        }
+
         if dest.fits(prettify::measure_inline_seq(exprs, 2)) {
             prettify::render_inline_seq(exprs, ", ", dest);
         } else {
@@ -946,7 +1484,7 @@ mod tl_prettify {
         frac: &PascalToken<'a>,
         dest: &mut Prettifier,
     ) {
-        dest.noscope_push(id.value.as_ref());
+        dest.identifier(id);
         dest.noscope_push(" = 0.");
         frac.render_inline(dest);
     }
@@ -957,7 +1495,7 @@ mod tl_prettify {
         base: &PascalToken<'a>,
         dest: &mut Prettifier,
     ) {
-        dest.noscope_push(name.value.as_ref());
+        dest.identifier(name);
         dest.noscope_push('[');
         coeff.render_inline(dest);
         base.render_inline(dest);
@@ -969,3 +1507,168 @@ mod tl_prettify {
         dest.noscope_push('.');
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        prettify::{CodeStyle, SemicolonPolicy},
+        FragmentOptions, WeaveOptions,
+    };
+
+    /// Prettify some code with `webmac.tex`'s macros, which are easy to read,
+    /// and the given semicolon policy.
+    fn prettify(code: &str, semicolons: SemicolonPolicy) -> String {
+        let options = FragmentOptions {
+            dialect: Default::default(),
+            theme: None,
+            html: false,
+            weave: WeaveOptions {
+                webmac: true,
+                style: CodeStyle {
+                    semicolons,
+                    ..CodeStyle::default()
+                },
+                ..WeaveOptions::default()
+            },
+        };
+
+        let fragment = crate::prettify_fragment(code, &options).unwrap();
+        assert_eq!(fragment.diagnostics, [], "parsing `{}`", code);
+        fragment.output
+    }
+
+    /// Get the messages of the problems found while parsing some code. This
+    /// runs on a thread with the stack of a typical main thread, rather than
+    /// that of a test, so that deeply nested code is limited by the parser and
+    /// not by the stack.
+    fn parse_messages(code: String) -> Vec<String> {
+        std::thread::Builder::new()
+            .stack_size(8 << 20)
+            .spawn(move || {
+                let options = FragmentOptions {
+                    dialect: Default::default(),
+                    theme: None,
+                    html: false,
+                    weave: WeaveOptions::default(),
+                };
+
+                let fragment = crate::prettify_fragment(&code, &options).unwrap();
+                fragment
+                    .diagnostics
+                    .into_iter()
+                    .map(|d| d.message)
+                    .collect()
+            })
+            .unwrap()
+            .join()
+            .unwrap()
+    }
+
+    #[test]
+    fn too_deeply_nested() {
+        let n = 1000;
+        let cases = [
+            format!("type t = {}integer;", "^".repeat(n)),
+            format!("type t = {}integer;", "array[1..2] of ".repeat(n)),
+            format!(
+                "type t = {}integer{};",
+                "record a: ".repeat(n),
+                " end".repeat(n)
+            ),
+            format!("{}begin end;", "procedure p; ".repeat(3 * n)),
+            format!("x := {}1{};", "(".repeat(n), ")".repeat(n)),
+            format!("{}x := 1{};", "begin ".repeat(n), " end".repeat(n)),
+        ];
+
+        let expected = format!(
+            "the code is nested more than {} levels deep",
+            super::MAX_NESTING_DEPTH
+        );
+
+        for code in cases {
+            let messages = parse_messages(code);
+            assert_eq!(messages.first(), Some(&expected));
+        }
+    }
+
+    #[test]
+    fn toplevel_semicolons() {
+        let code = "a:=1; b:=a*2 {hi}";
+        let cases = [
+            (SemicolonPolicy::Always, "\\|b\\K\\|a\\ *\\ 2;\\ \\C{hi}"),
+            (SemicolonPolicy::AsWritten, "\\|b\\K\\|a\\ *\\ 2\\ \\C{hi}"),
+            (
+                SemicolonPolicy::NotBeforeEnd,
+                "\\|b\\K\\|a\\ *\\ 2\\ \\C{hi}",
+            ),
+        ];
+
+        for (policy, last) in cases {
+            assert_eq!(
+                prettify(code, policy),
+                format!("\\Y\\P\\|a\\K1;\\6{}\\par\n", last),
+                "with {:?}",
+                policy
+            );
+        }
+    }
+
+    #[test]
+    fn label_numbers() {
+        assert_eq!(
+            prettify("label 10, 20, exit;", SemicolonPolicy::Always),
+            "\\Y\\P\\&{label}\\ 10,\\ 20,\\ \\\\{exit};\\par\n"
+        );
+    }
+
+    #[test]
+    fn string_ranges() {
+        prettify("upper_case = \"A\"..\"Z\";", SemicolonPolicy::Always);
+        prettify("@!digit_char = \"0\"..\"9\";", SemicolonPolicy::Always);
+    }
+
+    /// A module's worth of code, with the output that WEAVE gives for it
+    /// written out by hand, apart from the line breaks and indentation, which
+    /// tt-weave chooses for itself.
+    #[test]
+    fn weave_compatible() {
+        let code = "@d loop==@+ while true do@+
+@d mem_max=@'777
+@p procedure print_digs(k: eight_bits);
+begin while k>0 do
+  begin decr(k); print_char(\"0\"+dig[k]);
+  end;
+if (k<>0) and not done then k := k mod 3 else k := 0;
+end;";
+
+        let expected = [
+            "\\Y\\P\\D\\\\{loop}\\S\\&{while}\\ \\\\{true}\\ \\&{do}\\par",
+            "\\Y\\P\\D\\\\{mem\\_max}\\S\\O{777}\\par",
+            concat!(
+                "\\Y\\P\\&{procedure}\\ \\\\{print\\_digs}(\\|k:\\ \\\\{eight\\_bits});",
+                "\\6\\&{begin}\\1\\1\\6\\&{while}\\ \\|k\\ $>$\\ 0\\ \\&{do}\\ \\&{begin}",
+                "\\1\\1\\6\\\\{decr}(\\|k);",
+                "\\6\\\\{print\\_char}(\\.{\"0\"}\\ +\\ \\\\{dig}[\\|k]);",
+                "\\2\\2\\6\\&{end}",
+                "\\6\\&{if}\\ (\\|k\\I0)\\W\\R\\\\{done}\\ \\&{then}",
+                "\\1\\1\\6\\|k\\K\\|k\\ \\&{mod}\\ 3",
+                "\\2\\2\\6\\&{else}\\1\\1\\6\\|k\\K0;",
+                "\\2\\2\\2\\2\\6\\&{end};\\par",
+            ),
+        ];
+
+        assert_eq!(
+            prettify(code, SemicolonPolicy::AsWritten),
+            format!("{}\n", expected.join("\n"))
+        );
+    }
+
+    #[test]
+    fn banner() {
+        let tex = prettify(
+            "@d banner=='This is BibTeX, Version 0.99d' {printed when the program starts}",
+            SemicolonPolicy::Always,
+        );
+        assert!(tex.contains("\\D\\\\{banner}\\S"), "{}", tex);
+    }
+}
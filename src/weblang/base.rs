@@ -2,32 +2,46 @@
 //!
 //! Other WEB parsing modules do asterisk imports of this module.
 
+#[cfg(feature = "trace-parser")]
+use log::trace;
 use nom::{
     error::{ErrorKind, ParseError as NomParseError},
     multi::many1,
     Err, IResult, InputIter, InputLength, InputTake, Needed, Parser, Slice, UnspecializedInput,
 };
+#[cfg(feature = "serde")]
+use serde::Serialize;
 use std::{
     borrow::Cow,
+    fmt,
     iter::{Cloned, Enumerate},
+    ops::{Deref, Range},
     slice::Iter,
+    sync::Arc,
 };
+use typed_arena::Arena;
 
 // Some utility imports for asterisk importers.
 pub use crate::{
+    macros::{MacroKind, MacroTable},
     parse_base::{SpanValue, StringSpan},
     pascal_token::{DelimiterKind, PascalToken, StringLiteralKind},
     reserved::PascalReservedWord,
     state::ModuleId,
 };
 
-pub use super::{comment::WebComment, module_reference::WebModuleReference, prettify::TexInsert};
+pub use super::{
+    comment::WebComment, module_reference::WebModuleReference, prettify::TexInsert, visit::Visitor,
+};
+
+use super::{WebExpr, WebStatement, WebType};
 
 /// Information about a typeset comment.
 ///
 /// This type is lame. The structure is an interleaving of TeX code and inline
 /// Pascal text, but our data structure doesn't capture that very effectively.
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub enum TypesetComment<'a> {
     Pascal(Vec<PascalToken<'a>>),
     Tex(String),
@@ -35,6 +49,7 @@ pub enum TypesetComment<'a> {
 
 /// A logical token of the WEB language, which we treat as a superset of Pascal.
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub enum WebToken<'a> {
     /// A basic Pascal token.
     Pascal(PascalToken<'a>),
@@ -48,7 +63,7 @@ pub enum WebToken<'a> {
 }
 
 impl<'a> WebToken<'a> {
-    pub fn as_pascal(&self) -> Option<&PascalToken> {
+    pub fn as_pascal(&self) -> Option<&PascalToken<'_>> {
         if let WebToken::Pascal(ptok) = self {
             Some(ptok)
         } else {
@@ -65,17 +80,226 @@ impl<'a> WebToken<'a> {
     }
 }
 
-/// A block of WEB syntax: just a sequence of WEB tokens.
+/// A block of WEB syntax: a sequence of WEB tokens, along with where they came
+/// from.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct WebSyntax<'a> {
+    /// The tokens.
+    pub tokens: Vec<WebToken<'a>>,
+
+    /// The range of byte offsets in the input text occupied by each token.
+    /// This must have the same length as `tokens`. Synthesized tokens get
+    /// empty ranges.
+    pub positions: Vec<Range<usize>>,
+
+    /// The module in which the syntax appears.
+    pub module: ModuleId,
+}
+
+impl<'a> WebSyntax<'a> {
+    /// Add a token to the end of the syntax.
+    pub fn push(&mut self, token: WebToken<'a>, position: Range<usize>) {
+        self.tokens.push(token);
+        self.positions.push(position);
+    }
+
+    /// Move all of the tokens of `other` to the end of this syntax.
+    pub fn append(&mut self, other: &mut WebSyntax<'a>) {
+        self.tokens.append(&mut other.tokens);
+        self.positions.append(&mut other.positions);
+    }
+}
+
+/// The location of a node of the WEB AST: the range of byte offsets in the
+/// input text spanned by its tokens, and the module in which it appears.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct NodeSpan {
+    /// The module in which the node appears.
+    pub module: ModuleId,
+
+    /// The byte offset at which the node starts.
+    pub start: usize,
+
+    /// The byte offset at which the node ends.
+    pub end: usize,
+}
+
+/// An AST node, along with its location.
+///
+/// This dereferences to the node, so it can mostly be treated as such.
 #[derive(Clone, Debug, Eq, PartialEq)]
-pub struct WebSyntax<'a>(pub Vec<WebToken<'a>>);
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Spanned<T> {
+    /// The location of the node.
+    pub span: NodeSpan,
 
-/// The parse input: a slice of tokens
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
-pub struct ParseInput<'a>(pub &'a [WebToken<'a>]);
+    /// The node itself.
+    pub node: T,
+}
+
+impl<T> Deref for Spanned<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.node
+    }
+}
+
+/// Storage for the recursive nodes of the AST.
+///
+/// Parsing allocates a lot of small nodes, many of which are thrown away when
+/// the parser backtracks, and deeply nested expressions would otherwise be
+/// freed one node at a time. Instead, the nodes are allocated in big chunks
+/// that are freed all at once when the arena is dropped, so the arena must
+/// outlive the [`WebCode`](super::WebCode) parsed with it.
+///
+/// The arena also carries the kinds of the program's macros, if they're known,
+/// since they affect how code that uses them is parsed.
+#[derive(Default)]
+pub struct AstArena<'a> {
+    exprs: Arena<Spanned<WebExpr<'a>>>,
+    statements: Arena<Spanned<WebStatement<'a>>>,
+    types: Arena<WebType<'a>>,
+    macros: Option<Arc<MacroTable>>,
+}
+
+impl<'a> AstArena<'a> {
+    /// Create an arena for parsing code that uses macros of known kinds, so
+    /// that uses of statement-like macros are recognized as such.
+    pub fn with_macros(macros: Arc<MacroTable>) -> Self {
+        AstArena {
+            macros: Some(macros),
+            ..AstArena::default()
+        }
+    }
+
+    /// Allocate an expression node.
+    pub fn alloc_expr(&'a self, expr: Spanned<WebExpr<'a>>) -> &'a Spanned<WebExpr<'a>> {
+        self.exprs.alloc(expr)
+    }
+
+    /// Allocate a statement node.
+    pub fn alloc_statement(
+        &'a self,
+        stmt: Spanned<WebStatement<'a>>,
+    ) -> &'a Spanned<WebStatement<'a>> {
+        self.statements.alloc(stmt)
+    }
+
+    /// Allocate a type node.
+    pub fn alloc_type(&'a self, ty: WebType<'a>) -> &'a WebType<'a> {
+        self.types.alloc(ty)
+    }
+}
+
+impl<'a> fmt::Debug for AstArena<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AstArena")
+            .field("exprs", &self.exprs.len())
+            .field("statements", &self.statements.len())
+            .field("types", &self.types.len())
+            .finish()
+    }
+}
+
+/// The parse input: a slice of tokens, along with their positions.
+#[derive(Clone, Copy, Debug)]
+pub struct ParseInput<'a> {
+    /// The tokens.
+    pub tokens: &'a [WebToken<'a>],
+
+    /// The byte ranges of the tokens, parallel to `tokens`.
+    positions: &'a [Range<usize>],
+
+    /// The byte offset of the end of the complete input.
+    eof: usize,
+
+    /// The module in which the input appears.
+    module: ModuleId,
+
+    /// Where the nodes parsed from the input are allocated.
+    arena: &'a AstArena<'a>,
+
+    /// How many levels of nested constructs the input is inside of.
+    /// See [`nested`].
+    depth: usize,
+}
+
+impl<'a> ParseInput<'a> {
+    /// Create a parse input covering a block of WEB syntax, whose nodes will
+    /// be allocated in `arena`.
+    pub fn new(syntax: &'a WebSyntax<'a>, arena: &'a AstArena<'a>) -> Self {
+        assert_eq!(syntax.tokens.len(), syntax.positions.len());
+
+        ParseInput {
+            tokens: &syntax.tokens[..],
+            positions: &syntax.positions[..],
+            eof: syntax.positions.last().map(|r| r.end).unwrap_or(0),
+            module: syntax.module,
+            arena,
+            depth: 0,
+        }
+    }
+
+    /// Get the arena in which the nodes parsed from the input are allocated.
+    pub fn arena(&self) -> &'a AstArena<'a> {
+        self.arena
+    }
+
+    /// Get the kind of a macro, if the identifier names one and the kinds of
+    /// the macros are known.
+    pub fn macro_kind(&self, name: &str) -> Option<MacroKind> {
+        self.arena.macros.as_ref().and_then(|m| m.kind(name))
+    }
+
+    fn with_slices(&self, tokens: &'a [WebToken<'a>], positions: &'a [Range<usize>]) -> Self {
+        ParseInput {
+            tokens,
+            positions,
+            ..*self
+        }
+    }
+
+    /// Get the span of the tokens consumed between this input and `rest`,
+    /// which must be a suffix of it.
+    pub fn span_to(&self, rest: ParseInput<'a>) -> NodeSpan {
+        let n = self.tokens.len() - rest.tokens.len();
+
+        let (start, end) = if n > 0 {
+            (self.positions[0].start, self.positions[n - 1].end)
+        } else {
+            let p = self.positions.first().map(|r| r.start).unwrap_or(self.eof);
+            (p, p)
+        };
+
+        NodeSpan {
+            module: self.module,
+            start,
+            end,
+        }
+    }
+
+    /// Get the span of the next token, or an empty span at the end of the
+    /// input if there are no more tokens.
+    pub fn next_span(&self) -> NodeSpan {
+        let (start, end) = match self.positions.first() {
+            Some(r) => (r.start, r.end),
+            None => (self.eof, self.eof),
+        };
+
+        NodeSpan {
+            module: self.module,
+            start,
+            end,
+        }
+    }
+}
 
 impl<'a> InputLength for ParseInput<'a> {
     fn input_len(&self) -> usize {
-        self.0.len()
+        self.tokens.len()
     }
 }
 
@@ -94,7 +318,7 @@ impl<'a> InputIter for ParseInput<'a> {
 
     #[inline]
     fn iter_elements(&self) -> Self::IterElem {
-        self.0.iter().cloned()
+        self.tokens.iter().cloned()
     }
 
     #[inline]
@@ -102,38 +326,47 @@ impl<'a> InputIter for ParseInput<'a> {
     where
         P: Fn(Self::Item) -> bool,
     {
-        self.0.iter().position(|b| predicate(b.clone()))
+        self.tokens.iter().position(|b| predicate(b.clone()))
     }
 
     #[inline]
     fn slice_index(&self, count: usize) -> Result<usize, Needed> {
-        if self.0.len() >= count {
+        if self.tokens.len() >= count {
             Ok(count)
         } else {
-            Err(Needed::new(count - self.0.len()))
+            Err(Needed::new(count - self.tokens.len()))
         }
     }
 }
 
 impl<'a, R> Slice<R> for ParseInput<'a>
 where
+    R: Clone,
     &'a [WebToken<'a>]: Slice<R>,
+    &'a [Range<usize>]: Slice<R>,
 {
     fn slice(&self, range: R) -> Self {
-        ParseInput(self.0.slice(range))
+        self.with_slices(
+            self.tokens.slice(range.clone()),
+            self.positions.slice(range),
+        )
     }
 }
 
 impl<'a> InputTake for ParseInput<'a> {
     #[inline]
     fn take(&self, count: usize) -> Self {
-        ParseInput(&self.0[0..count])
+        self.with_slices(&self.tokens[0..count], &self.positions[0..count])
     }
 
     #[inline]
     fn take_split(&self, count: usize) -> (Self, Self) {
-        let (prefix, suffix) = self.0.split_at(count);
-        (ParseInput(suffix), ParseInput(prefix))
+        let (prefix, suffix) = self.tokens.split_at(count);
+        let (pprefix, psuffix) = self.positions.split_at(count);
+        (
+            self.with_slices(suffix, psuffix),
+            self.with_slices(prefix, pprefix),
+        )
     }
 }
 
@@ -150,14 +383,22 @@ pub enum WebErrorKind {
     ExpectedIntLiteral,
     ExpectedComment,
     ExpectedToplevel,
+    ExpectedExpr,
+    ExpectedOperator,
+    ExpectedBlockOpener,
+    ExpectedBlockCloser,
     ExpectedReservedWord(PascalReservedWord),
     ExpectedAnyReservedWord,
+    ExpectedFormattedIdentifier(PascalReservedWord),
+    UnexpectedReservedWord(PascalReservedWord),
     ExpectedOpenDelimiter(DelimiterKind),
     ExpectedCloseDelimiter(DelimiterKind),
     ExpectedVerbatimPascal,
+    ExpectedStatementMacro,
     IncompleteDefine,
     NotDefineEdge,
     StringLiteralMergeFail,
+    TooDeeplyNested,
     Nom(ErrorKind),
 }
 
@@ -172,6 +413,22 @@ impl<'a> NomParseError<ParseInput<'a>> for ParseError<'a> {
     fn append(_: ParseInput<'a>, _: ErrorKind, other: Self) -> Self {
         other
     }
+
+    /// When every branch of an `alt` fails, Nom's default is to keep the error
+    /// from the last one, which is usually just a complaint that the first
+    /// token isn't what that branch wanted. We instead keep the error from the
+    /// branch that got the furthest, since it's the one most likely to describe
+    /// the real problem. Ties go to the later branch, unless its error is one
+    /// of Nom's generic ones.
+    fn or(self, other: Self) -> Self {
+        let (ours, theirs) = (self.0.input_len(), other.0.input_len());
+
+        if ours < theirs || (ours == theirs && matches!(other.1, WebErrorKind::Nom(_))) {
+            self
+        } else {
+            other
+        }
+    }
 }
 
 /// The parse result type.
@@ -181,12 +438,37 @@ pub fn new_parse_err<'a, T>(s: ParseInput<'a>, k: WebErrorKind) -> ParseResult<'
     Err(Err::Error((s, k)))
 }
 
+/// How deeply expressions, statements, types, and procedures may be nested in
+/// each other. The parser recurses for each level, so code nested more deeply
+/// than this is rejected rather than risking overflowing the stack.
+pub const MAX_NESTING_DEPTH: usize = 100;
+
+/// Run a parser for something that can contain itself, like an expression, a
+/// statement, a type, or a procedure, one level deeper than `input`. If that's deeper than
+/// [`MAX_NESTING_DEPTH`], this fails outright, so that the parser doesn't
+/// backtrack to try other ways of reaching the same depth.
+pub fn nested<'a, O, F>(input: ParseInput<'a>, inner: F) -> ParseResult<'a, O>
+where
+    F: FnOnce(ParseInput<'a>) -> ParseResult<'a, O>,
+{
+    if input.depth >= MAX_NESTING_DEPTH {
+        return Err(Err::Failure((input, WebErrorKind::TooDeeplyNested)));
+    }
+
+    let (mut rest, output) = inner(ParseInput {
+        depth: input.depth + 1,
+        ..input
+    })?;
+    rest.depth = input.depth;
+    Ok((rest, output))
+}
+
 /// Match and consume the next WEB token.
 pub fn next_token<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebToken<'a>> {
     let wt = input
         .iter_elements()
         .next()
-        .ok_or_else(|| Err::Error((input, WebErrorKind::Eof)))?;
+        .ok_or(Err::Error((input, WebErrorKind::Eof)))?;
     Ok((input.slice(1..), wt))
 }
 
@@ -198,15 +480,15 @@ pub fn pascal_token<'a>(
     expected: PascalToken<'a>,
 ) -> impl Fn(ParseInput<'a>) -> ParseResult<'a, PascalToken<'a>> {
     move |input: ParseInput<'a>| {
-        let (input, wt) = next_token(input)?;
+        let (rest, wt) = next_token(input)?;
 
         if let WebToken::Pascal(found) = wt {
             if found == expected {
-                return Ok((input, found));
+                return Ok((rest, found));
             }
         }
 
-        return new_parse_err(input, WebErrorKind::ExpectedPascalToken);
+        new_parse_err(input, WebErrorKind::ExpectedPascalToken)
     }
 }
 
@@ -215,19 +497,19 @@ pub fn pascal_token<'a>(
 /// Note that this will not match "formatted identifiers" that have had their
 /// behavior overridden with an `@f` command.
 pub fn identifier<'a>(input: ParseInput<'a>) -> ParseResult<'a, StringSpan<'a>> {
-    let (input, wt) = next_token(input)?;
+    let (rest, wt) = next_token(input)?;
 
     if let WebToken::Pascal(PascalToken::Identifier(s)) = wt {
-        return Ok((input, s));
+        return Ok((rest, s));
     }
 
     if let WebToken::Pascal(PascalToken::Hash(p)) = wt {
         // For our purposes, hash marks act like identifiers
         return Ok((
-            input,
+            rest,
             StringSpan {
-                start: p.clone(),
-                end: p.clone(),
+                start: p,
+                end: p,
                 value: "#".into(),
             },
         ));
@@ -237,10 +519,10 @@ pub fn identifier<'a>(input: ParseInput<'a>) -> ParseResult<'a, StringSpan<'a>>
         // `nil` does as well
         if rw.value == PascalReservedWord::Nil {
             return Ok((
-                input,
+                rest,
                 StringSpan {
-                    start: rw.start.clone(),
-                    end: rw.end.clone(),
+                    start: rw.start,
+                    end: rw.end,
                     value: "nil".into(),
                 },
             ));
@@ -252,17 +534,17 @@ pub fn identifier<'a>(input: ParseInput<'a>) -> ParseResult<'a, StringSpan<'a>>
 
 /// Expect a Pascal identifier, returning it as a Pascal token.
 pub fn identifier_as_token<'a>(input: ParseInput<'a>) -> ParseResult<'a, PascalToken<'a>> {
-    let (input, wt) = next_token(input)?;
+    let (rest, wt) = next_token(input)?;
 
     match wt {
-        WebToken::Pascal(tok @ PascalToken::Identifier(_)) => Ok((input, tok)),
-        WebToken::Pascal(tok @ PascalToken::Hash(_)) => Ok((input, tok)),
+        WebToken::Pascal(tok @ PascalToken::Identifier(_)) => Ok((rest, tok)),
+        WebToken::Pascal(tok @ PascalToken::Hash(_)) => Ok((rest, tok)),
         WebToken::Pascal(
             tok @ PascalToken::ReservedWord(SpanValue {
                 value: PascalReservedWord::Nil,
                 ..
             }),
-        ) => Ok((input, tok)),
+        ) => Ok((rest, tok)),
         _ => new_parse_err(input, WebErrorKind::ExpectedIdentifier),
     }
 }
@@ -272,15 +554,15 @@ pub fn reserved_word<'a>(
     rw: PascalReservedWord,
 ) -> impl Fn(ParseInput<'a>) -> ParseResult<'a, SpanValue<'a, PascalReservedWord>> {
     move |input: ParseInput<'a>| {
-        let (input, wt) = next_token(input)?;
+        let (rest, wt) = next_token(input)?;
 
         if let WebToken::Pascal(PascalToken::ReservedWord(sv)) = wt {
             if sv.value == rw {
-                return Ok((input, sv));
+                return Ok((rest, sv));
             }
         }
 
-        return new_parse_err(input, WebErrorKind::ExpectedReservedWord(rw));
+        new_parse_err(input, WebErrorKind::ExpectedReservedWord(rw))
     }
 }
 
@@ -288,23 +570,23 @@ pub fn reserved_word<'a>(
 pub fn any_reserved_word<'a>(
     input: ParseInput<'a>,
 ) -> ParseResult<'a, SpanValue<'a, PascalReservedWord>> {
-    let (input, wt) = next_token(input)?;
+    let (rest, wt) = next_token(input)?;
 
     if let WebToken::Pascal(PascalToken::ReservedWord(sv)) = wt {
-        return Ok((input, sv));
+        return Ok((rest, sv));
     }
 
-    return new_parse_err(input, WebErrorKind::ExpectedAnyReservedWord);
+    new_parse_err(input, WebErrorKind::ExpectedAnyReservedWord)
 }
 
 /// Expect a Pascal string literal token, returning it.
 pub fn string_literal<'a>(input: ParseInput<'a>) -> ParseResult<'a, PascalToken<'a>> {
-    let (input, wt) = next_token(input)?;
+    let (rest, wt) = next_token(input)?;
 
     if let WebToken::Pascal(lit @ PascalToken::StringLiteral(..)) = wt {
-        Ok((input, lit))
+        Ok((rest, lit))
     } else {
-        return new_parse_err(input, WebErrorKind::ExpectedStringLiteral);
+        new_parse_err(input, WebErrorKind::ExpectedStringLiteral)
     }
 }
 
@@ -312,60 +594,68 @@ pub fn string_literal<'a>(input: ParseInput<'a>) -> ParseResult<'a, PascalToken<
 ///
 /// The handling of the sign here is pretty shady.
 pub fn int_literal<'a>(input: ParseInput<'a>) -> ParseResult<'a, PascalToken<'a>> {
-    let (mut input, mut wt) = next_token(input)?;
+    let (mut rest, mut wt) = next_token(input)?;
 
-    let sign = if let WebToken::Pascal(PascalToken::Minus) = wt {
-        (input, wt) = next_token(input)?;
-        -1
-    } else {
-        1
+    let sign = match wt {
+        WebToken::Pascal(PascalToken::Minus) => {
+            (rest, wt) = next_token(rest)?;
+            -1
+        }
+
+        // As in `-1..+1` in TANGLE.
+        WebToken::Pascal(PascalToken::Plus) => {
+            (rest, wt) = next_token(rest)?;
+            1
+        }
+
+        _ => 1,
     };
 
     if let WebToken::Pascal(PascalToken::IntLiteral(kind, n)) = wt {
-        Ok((input, PascalToken::IntLiteral(kind, sign * n)))
+        Ok((rest, PascalToken::IntLiteral(kind, sign * n)))
     } else {
-        return new_parse_err(input, WebErrorKind::ExpectedIntLiteral);
+        new_parse_err(input, WebErrorKind::ExpectedIntLiteral)
     }
 }
 
 /// An open delimiter.
 pub fn open_delimiter<'a>(kind: DelimiterKind) -> impl Fn(ParseInput<'a>) -> ParseResult<'a, ()> {
     move |input: ParseInput<'a>| {
-        let (input, wt) = next_token(input)?;
+        let (rest, wt) = next_token(input)?;
 
         if let WebToken::Pascal(PascalToken::OpenDelimiter(found_kind)) = wt {
             if found_kind == kind {
-                return Ok((input, ()));
+                return Ok((rest, ()));
             }
         }
 
-        return new_parse_err(input, WebErrorKind::ExpectedOpenDelimiter(kind));
+        new_parse_err(input, WebErrorKind::ExpectedOpenDelimiter(kind))
     }
 }
 
 /// A close delimiter.
 pub fn close_delimiter<'a>(kind: DelimiterKind) -> impl Fn(ParseInput<'a>) -> ParseResult<'a, ()> {
     move |input: ParseInput<'a>| {
-        let (input, wt) = next_token(input)?;
+        let (rest, wt) = next_token(input)?;
 
         if let WebToken::Pascal(PascalToken::CloseDelimiter(found_kind)) = wt {
             if found_kind == kind {
-                return Ok((input, ()));
+                return Ok((rest, ()));
             }
         }
 
-        return new_parse_err(input, WebErrorKind::ExpectedCloseDelimiter(kind));
+        new_parse_err(input, WebErrorKind::ExpectedCloseDelimiter(kind))
     }
 }
 
 /// Expect a comment, returning it.
 pub fn comment<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebComment<'a>> {
-    let (input, wt) = next_token(input)?;
+    let (rest, wt) = next_token(input)?;
 
     if let WebToken::Comment(c) = wt {
-        Ok((input, WebComment(c)))
+        Ok((rest, WebComment(c)))
     } else {
-        return new_parse_err(input, WebErrorKind::ExpectedComment);
+        new_parse_err(input, WebErrorKind::ExpectedComment)
     }
 }
 
@@ -443,32 +733,52 @@ pub fn formatted_identifier_like<'a>(
     rw: PascalReservedWord,
 ) -> impl Fn(ParseInput<'a>) -> ParseResult<'a, PascalToken<'a>> {
     move |input: ParseInput<'a>| {
-        let (input, wt) = next_token(input)?;
+        let (rest, wt) = next_token(input)?;
 
         if let WebToken::Pascal(ptok) = wt {
             if let PascalToken::FormattedIdentifier(_, found_rw) = ptok {
                 if rw == found_rw {
-                    return Ok((input, ptok));
+                    return Ok((rest, ptok));
                 }
             }
         }
 
-        return new_parse_err(input, WebErrorKind::Eof);
+        new_parse_err(input, WebErrorKind::ExpectedFormattedIdentifier(rw))
     }
 }
 
 /// Accept a "verbatim Pascal" token.
 pub fn verbatim_pascal<'a>(input: ParseInput<'a>) -> ParseResult<'a, PascalToken<'a>> {
-    let (input, wt) = next_token(input)?;
+    let (rest, wt) = next_token(input)?;
 
     if let WebToken::Pascal(tok @ PascalToken::VerbatimPascal(..)) = wt {
-        return Ok((input, tok));
+        return Ok((rest, tok));
     }
 
-    return new_parse_err(input, WebErrorKind::ExpectedVerbatimPascal);
+    new_parse_err(input, WebErrorKind::ExpectedVerbatimPascal)
 }
 
+/// Run a parser, attaching the span of the tokens that it consumes to its
+/// output.
+pub fn spanned<'a, T, O>(mut inner: T) -> impl FnMut(ParseInput<'a>) -> ParseResult<'a, Spanned<O>>
+where
+    T: Parser<ParseInput<'a>, O, ParseError<'a>>,
+{
+    move |input: ParseInput<'a>| {
+        let (rest, node) = inner.parse(input)?;
+        let span = input.span_to(rest);
+        Ok((rest, Spanned { span, node }))
+    }
+}
+
+/// Wrap a parser so that it traces its input and result, for debugging the
+/// grammar. The `tag` identifies the parser in the trace.
+///
+/// The tracing is very verbose, so it's only compiled in with the
+/// `trace-parser` feature, and then only shown at the `trace` log level.
+/// Otherwise, this just runs the inner parser.
 #[allow(dead_code)]
+#[cfg(feature = "trace-parser")]
 pub fn debug<'a, T, O: std::fmt::Debug>(
     tag: &'static str,
     mut inner: T,
@@ -477,33 +787,48 @@ where
     T: Parser<ParseInput<'a>, O, ParseError<'a>>,
 {
     move |input: ParseInput<'a>| {
-        let n = usize::min(12, input.0.len());
+        let n = usize::min(12, input.tokens.len());
         if n > 0 {
-            eprintln!("*** {} >> {:?}", tag, &input.0[..n - 1]);
+            trace!("*** {} >> {:?}", tag, &input.tokens[..n]);
         } else {
-            eprintln!("*** {} >> (nothing left)", tag);
+            trace!("*** {} >> (nothing left)", tag);
         }
 
         let result = inner.parse(input);
 
         match &result {
             Ok((_, v)) => {
-                eprintln!("*** {} << OK: {:?}", tag, v);
+                trace!("*** {} << OK: {:?}", tag, v);
             }
 
             Err(nom::Err::Error((input, kind))) => {
-                eprintln!("*** {} << err: {:?}", tag, kind);
+                trace!("*** {} << err: {:?}", tag, kind);
                 let n = usize::min(input.input_len(), 6);
-                for tok in &input.0[..n] {
-                    eprintln!("- {:?}", tok);
+                for tok in &input.tokens[..n] {
+                    trace!("- {:?}", tok);
                 }
             }
 
             _ => {
-                eprintln!("TL other failure???");
+                trace!("TL other failure???");
             }
         }
 
         result
     }
 }
+
+/// Wrap a parser so that it traces its input and result, for debugging the
+/// grammar. Without the `trace-parser` feature, this just runs the inner
+/// parser.
+#[allow(dead_code)]
+#[cfg(not(feature = "trace-parser"))]
+pub fn debug<'a, T, O: std::fmt::Debug>(
+    _tag: &'static str,
+    mut inner: T,
+) -> impl FnMut(ParseInput<'a>) -> ParseResult<'a, O>
+where
+    T: Parser<ParseInput<'a>, O, ParseError<'a>>,
+{
+    move |input: ParseInput<'a>| inner.parse(input)
+}
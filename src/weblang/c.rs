@@ -0,0 +1,346 @@
+//! C code, for CWEB input.
+//!
+//! We don't attempt to parse C the way that we parse Pascal. Instead, the
+//! "grammar" here is just enough to lay out the code: braces open and close
+//! indented blocks (except for initializer lists), semicolons outside of
+//! parentheses end lines, preprocessor directives get their own lines, comments
+//! go at the ends of lines, and the spacing between tokens is chosen based on
+//! whether operators appear to be unary or binary.
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use crate::{
+    c_token::CToken,
+    prettify::{Prettifier, RenderInline},
+};
+
+use super::base::*;
+
+/// An item in a block of C code.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub enum CItem<'a> {
+    /// A C token.
+    Token(CToken<'a>),
+
+    /// A comment.
+    Comment(WebComment<'a>),
+
+    /// A reference to a WEB module.
+    ModuleReference(WebModuleReference<'a>),
+}
+
+/// A block of C code.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct CCode<'a>(pub Vec<CItem<'a>>);
+
+/// What we just emitted, which determines the spacing before the next item.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Prev {
+    /// The start of a line; never put a space here.
+    LineStart,
+
+    /// An operand, which may be followed by a binary operator.
+    Operand,
+
+    /// A keyword.
+    Keyword,
+
+    /// Something that should not be followed by a space, like an open
+    /// parenthesis or a unary operator.
+    Tight,
+
+    /// Something that should be followed by a space, like a binary operator.
+    Loose,
+}
+
+struct Layout<'p> {
+    dest: &'p mut Prettifier,
+    inline: bool,
+    prev: Prev,
+    paren_depth: usize,
+
+    /// The number of `?` operators awaiting their `:`.
+    ternary_depth: usize,
+
+    /// For each open brace, whether it opened an initializer list rather than
+    /// a block.
+    braces: Vec<bool>,
+
+    /// Whether the previous token was `=`.
+    after_assign: bool,
+
+    /// Whether we just closed a block, in which case the next item should
+    /// generally start a new line.
+    after_close_brace: bool,
+
+    /// Whether we've ended a line but not yet told the prettifier, so that a
+    /// comment can still be put at the end of it.
+    eol_pending: bool,
+}
+
+impl<'p> Layout<'p> {
+    fn end_line(&mut self) {
+        if !self.inline && self.prev != Prev::LineStart {
+            self.eol_pending = true;
+            self.prev = Prev::LineStart;
+        }
+    }
+
+    fn flush_line(&mut self) {
+        if self.eol_pending {
+            self.dest.newline_needed();
+            self.eol_pending = false;
+        }
+    }
+
+    fn space_unless(&mut self, tight: bool) {
+        if !tight && self.prev != Prev::LineStart {
+            self.dest.space();
+        }
+    }
+
+    fn in_list(&self) -> bool {
+        self.braces.last().copied().unwrap_or(false)
+    }
+
+    /// Handle the line break that generally follows a closing brace, unless
+    /// the next item continues the statement.
+    fn resolve_close_brace(&mut self, next: Option<&CToken>) {
+        if !self.after_close_brace {
+            return;
+        }
+
+        self.after_close_brace = false;
+
+        let continues = next.is_some_and(|t| {
+            t.is_operator(";")
+                || t.is_operator(",")
+                || t.is_operator(")")
+                || t.is_keyword("else")
+                || t.is_keyword("while")
+        });
+
+        if !continues {
+            self.end_line();
+        }
+    }
+
+    fn operand<T: RenderInline>(&mut self, item: &T, is_keyword: bool) {
+        self.space_unless(self.prev == Prev::Tight);
+        item.render_inline(self.dest);
+        self.prev = if is_keyword {
+            Prev::Keyword
+        } else {
+            Prev::Operand
+        };
+    }
+
+    fn operator(&mut self, tok: &CToken, op: &str) {
+        let after_operand = self.prev == Prev::Operand;
+
+        match op {
+            "{" if self.after_assign || self.in_list() => {
+                self.space_unless(self.prev == Prev::Tight);
+                tok.render_inline(self.dest);
+                self.braces.push(true);
+                self.prev = Prev::Tight;
+            }
+
+            "{" => {
+                self.space_unless(false);
+                tok.render_inline(self.dest);
+                self.braces.push(false);
+                self.prev = Prev::Loose;
+
+                if !self.inline {
+                    self.dest.indent_block();
+                    self.end_line();
+                }
+            }
+
+            "}" if self.in_list() => {
+                tok.render_inline(self.dest);
+                self.braces.pop();
+                self.prev = Prev::Operand;
+            }
+
+            "}" => {
+                self.braces.pop();
+
+                if self.inline {
+                    self.space_unless(false);
+                } else {
+                    self.dest.dedent_block();
+                    self.end_line();
+                    self.flush_line();
+                }
+
+                tok.render_inline(self.dest);
+                self.prev = Prev::Operand;
+                self.after_close_brace = true;
+            }
+
+            ";" => {
+                tok.render_inline(self.dest);
+                self.prev = Prev::Loose;
+
+                if self.paren_depth == 0 {
+                    self.end_line();
+                }
+            }
+
+            ":" if self.ternary_depth > 0 => {
+                self.ternary_depth -= 1;
+                self.space_unless(false);
+                tok.render_inline(self.dest);
+                self.prev = Prev::Loose;
+            }
+
+            "," | ":" => {
+                tok.render_inline(self.dest);
+                self.prev = Prev::Loose;
+            }
+
+            "?" => {
+                self.ternary_depth += 1;
+                self.space_unless(false);
+                tok.render_inline(self.dest);
+                self.prev = Prev::Loose;
+            }
+
+            "(" | "[" => {
+                self.space_unless(after_operand || self.prev == Prev::Tight);
+                tok.render_inline(self.dest);
+                self.paren_depth += 1;
+                self.prev = Prev::Tight;
+            }
+
+            ")" | "]" => {
+                tok.render_inline(self.dest);
+                self.paren_depth = self.paren_depth.saturating_sub(1);
+                self.prev = Prev::Operand;
+            }
+
+            "." | "->" => {
+                tok.render_inline(self.dest);
+                self.prev = Prev::Tight;
+            }
+
+            "++" | "--" if after_operand => {
+                tok.render_inline(self.dest);
+                self.prev = Prev::Operand;
+            }
+
+            "++" | "--" | "!" | "~" | "#" | "##" => {
+                self.space_unless(self.prev == Prev::Tight);
+                tok.render_inline(self.dest);
+                self.prev = Prev::Tight;
+            }
+
+            "*" | "&" | "-" | "+" if !after_operand => {
+                self.space_unless(self.prev == Prev::Tight);
+                tok.render_inline(self.dest);
+                self.prev = Prev::Tight;
+            }
+
+            _ => {
+                self.space_unless(false);
+                tok.render_inline(self.dest);
+                self.prev = Prev::Loose;
+            }
+        }
+    }
+
+    fn token(&mut self, tok: &CToken) {
+        self.resolve_close_brace(Some(tok));
+
+        match tok {
+            CToken::LineBreak => {
+                self.end_line();
+                return;
+            }
+
+            CToken::IndexEntry(..)
+            | CToken::Formatting
+            | CToken::DefinitionFlag
+            | CToken::CancelDefinitionFlag => return,
+
+            _ => {}
+        }
+
+        self.flush_line();
+
+        match tok {
+            CToken::Operator(s) => {
+                self.operator(tok, s.value.as_ref());
+                self.after_assign = s.value == "=";
+                return;
+            }
+
+            CToken::Keyword(_) => self.operand(tok, true),
+
+            CToken::Preprocessor(_) => {
+                self.end_line();
+                tok.render_inline(self.dest);
+                self.prev = Prev::Loose;
+                self.end_line();
+            }
+
+            _ => self.operand(tok, false),
+        }
+
+        self.after_assign = false;
+    }
+}
+
+impl<'a> CCode<'a> {
+    /// Lay out the code into the prettifier.
+    ///
+    /// If `inline` is true, everything is kept on one line, as is appropriate
+    /// for code embedded in TeX text.
+    pub fn prettify(&self, inline: bool, dest: &mut Prettifier) {
+        let mut layout = Layout {
+            dest,
+            inline,
+            prev: Prev::LineStart,
+            paren_depth: 0,
+            ternary_depth: 0,
+            braces: Vec::new(),
+            after_assign: false,
+            after_close_brace: false,
+            eol_pending: false,
+        };
+
+        for item in &self.0 {
+            match item {
+                CItem::Token(tok) => layout.token(tok),
+
+                CItem::Comment(c) => {
+                    // If we've just ended a line, the comment goes at the end
+                    // of it.
+                    if layout.eol_pending {
+                        layout.dest.space();
+                    } else {
+                        layout.resolve_close_brace(None);
+                        layout.flush_line();
+                        layout.space_unless(false);
+                    }
+
+                    c.render_inline(layout.dest);
+                    layout.prev = Prev::Loose;
+                    layout.end_line();
+                }
+
+                CItem::ModuleReference(mref) => {
+                    layout.resolve_close_brace(None);
+                    layout.flush_line();
+                    layout.operand(mref, false);
+                    layout.after_assign = false;
+                }
+            }
+        }
+    }
+}
@@ -1,17 +1,21 @@
 //! Comments
 
-use crate::prettify::{Prettifier, RenderInline, COMMENT_SCOPE};
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use crate::prettify::{CommentStyle, Prettifier, RenderInline, TexInsert, COMMENT_SCOPE};
 
 use super::base::*;
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct WebComment<'a>(pub Vec<TypesetComment<'a>>);
 
 impl<'a> RenderInline for WebComment<'a> {
     fn measure_inline(&self) -> usize {
         let mut n = 3; // `// `
 
-        n += self.0.len() - 1; // spaces between items
+        n += self.0.len().saturating_sub(1); // spaces between items
 
         for piece in &self.0[..] {
             match piece {
@@ -23,7 +27,7 @@ impl<'a> RenderInline for WebComment<'a> {
                 }
 
                 TypesetComment::Pascal(toks) => {
-                    n += toks.len() - 1;
+                    n += toks.len().saturating_sub(1);
 
                     for tok in &toks[..] {
                         n += tok.to_string().len();
@@ -49,9 +53,15 @@ impl<'a> RenderInline for WebComment<'a> {
                 match piece {
                     TypesetComment::Tex(s) => {
                         // TODO be mindful of TeX escaping here ... maybe
+                        let prose = d.style().comments == CommentStyle::Prose;
+                        let words = if prose {
+                            tex_words(s)
+                        } else {
+                            s.split_whitespace().collect()
+                        };
                         let mut first = true;
 
-                        for word in s.split_whitespace() {
+                        for word in words {
                             if first {
                                 first = false;
                             } else {
@@ -63,6 +73,11 @@ impl<'a> RenderInline for WebComment<'a> {
                                 d.noscope_push("// ");
                             }
 
+                            if prose {
+                                let tex = format!("\\WebCommentProse{{{}}}", word);
+                                d.insert(TexInsert::Replacement(tex, word.len()), true);
+                            }
+
                             d.noscope_push(word);
                         }
                     }
@@ -90,3 +105,44 @@ impl<'a> RenderInline for WebComment<'a> {
         });
     }
 }
+
+/// Split the TeX text of a comment into words at its whitespace, keeping math
+/// and groups in braces together, so that they can be typeset as they're
+/// written even if the comment is wrapped.
+fn tex_words(text: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut start = None;
+    let mut depth = 0usize;
+    let mut math = false;
+    let mut chars = text.char_indices();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            c if c.is_whitespace() && depth == 0 && !math => {
+                if let Some(s) = start.take() {
+                    words.push(&text[s..i]);
+                }
+
+                continue;
+            }
+
+            // Skip whatever's escaped, like `\$`.
+            '\\' => {
+                chars.next();
+            }
+
+            '{' => depth += 1,
+            '}' => depth = depth.saturating_sub(1),
+            '$' => math = !math,
+            _ => {}
+        }
+
+        start.get_or_insert(i);
+    }
+
+    if let Some(s) = start {
+        words.push(&text[s..]);
+    }
+
+    words
+}
@@ -8,16 +8,22 @@ use nom::{
     combinator::{map, opt},
     sequence::tuple,
 };
+#[cfg(feature = "serde")]
+use serde::Serialize;
 
 use crate::prettify::{Prettifier, RenderInline};
 
 use super::{base::*, WebToplevel};
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct WebConstantDeclaration<'a> {
     /// The name of the constant.
     name: StringSpan<'a>,
 
+    /// The sign of the value, if it has one, as in `min_quarterword = -0`.
+    sign: Option<PascalToken<'a>>,
+
     /// The value of the constant.
     value: PascalToken<'a>,
 
@@ -33,7 +39,7 @@ pub fn parse_constant_declaration<'a>(input: ParseInput<'a>) -> ParseResult<'a,
         tuple((
             identifier,
             pascal_token(PascalToken::Equals),
-            alt((int_literal, identifier_as_token)),
+            parse_constant_value,
             pascal_token(PascalToken::Semicolon),
             opt(comment),
             opt(comment),
@@ -41,7 +47,8 @@ pub fn parse_constant_declaration<'a>(input: ParseInput<'a>) -> ParseResult<'a,
         |tup| {
             WebToplevel::ConstDeclaration(WebConstantDeclaration {
                 name: tup.0,
-                value: tup.2,
+                sign: tup.2 .0,
+                value: tup.2 .1,
                 comment: tup.4,
                 second_comment: tup.5,
             })
@@ -49,6 +56,20 @@ pub fn parse_constant_declaration<'a>(input: ParseInput<'a>) -> ParseResult<'a,
     )(input)
 }
 
+/// Parse the value of a constant: an integer literal or an identifier, possibly
+/// with a sign.
+pub fn parse_constant_value<'a>(
+    input: ParseInput<'a>,
+) -> ParseResult<'a, (Option<PascalToken<'a>>, PascalToken<'a>)> {
+    tuple((
+        opt(alt((
+            pascal_token(PascalToken::Plus),
+            pascal_token(PascalToken::Minus),
+        ))),
+        alt((int_literal, identifier_as_token)),
+    ))(input)
+}
+
 // Prettifying
 
 impl<'a> WebConstantDeclaration<'a> {
@@ -65,9 +86,43 @@ impl<'a> WebConstantDeclaration<'a> {
 
         dest.keyword("const");
         dest.space();
-        dest.noscope_push(&self.name);
+        dest.identifier(&self.name);
         dest.noscope_push(" = ");
+
+        if let Some(sign) = self.sign.as_ref() {
+            sign.render_inline(dest);
+        }
+
         self.value.render_inline(dest);
         dest.noscope_push(';');
     }
 }
+
+// Walking
+
+impl<'a> WebConstantDeclaration<'a> {
+    /// The name of the constant.
+    pub fn name(&self) -> &StringSpan<'a> {
+        &self.name
+    }
+
+    /// Whether the declaration has a comment.
+    pub fn has_comment(&self) -> bool {
+        self.comment.is_some() || self.second_comment.is_some()
+    }
+
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        visitor.visit_defined_identifier(&self.name);
+        if let Some(sign) = &self.sign {
+            visitor.visit_token(sign);
+        }
+        visitor.visit_token(&self.value);
+        if let Some(c) = &self.comment {
+            visitor.visit_comment(c);
+        }
+        if let Some(c) = &self.second_comment {
+            visitor.visit_comment(c);
+        }
+    }
+}
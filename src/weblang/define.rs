@@ -3,6 +3,7 @@
 //! This has the general form `@d LHS == RHS`. The LHS might not be simple
 //! identifier if it has macro parameter, and the RHS can be any toplevel.
 
+use log::debug;
 use nom::{
     branch::alt,
     bytes::complete::take_while1,
@@ -11,6 +12,8 @@ use nom::{
     sequence::tuple,
     InputLength,
 };
+#[cfg(feature = "serde")]
+use serde::Serialize;
 
 use crate::prettify::{self, Prettifier, RenderInline, COMMENT_SCOPE};
 
@@ -24,6 +27,7 @@ use super::{
 
 /// A `@d` definition
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct WebDefine<'a> {
     /// The LHS of the define. This may be a sequence of tokens like `blah(#)`.
     lhs: Vec<PascalToken<'a>>,
@@ -33,15 +37,17 @@ pub struct WebDefine<'a> {
 
     /// Optional trailing comment.
     comment: Option<WebComment<'a>>,
+
+    /// The value of the macro, if it's a numeric constant whose value is worth
+    /// showing. The parser can't know this, since it depends on other macros.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    value: Option<isize>,
 }
 
 pub fn parse_define<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebToplevel<'a>> {
     fn is_define_lhs_token(t: WebToken) -> bool {
         if let Some(pt) = t.as_pascal() {
-            match pt {
-                PascalToken::Equals | PascalToken::Equivalence => false,
-                _ => true,
-            }
+            !matches!(pt, PascalToken::Equals | PascalToken::Equivalence)
         } else {
             false
         }
@@ -61,11 +67,16 @@ pub fn parse_define<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebToplevel<'a
     ))(input)?;
 
     if input.input_len() != 0 {
-        eprintln!("\n\n** incomplete @define parse, remaining: {:?}\n", input);
+        debug!("incomplete @define parse, remaining: {:?}", input);
         return new_parse_err(input, WebErrorKind::IncompleteDefine);
     }
 
-    let lhs = items.1 .0.iter().map(|t| t.clone().into_pascal()).collect();
+    let lhs = items
+        .1
+        .tokens
+        .iter()
+        .map(|t| t.clone().into_pascal())
+        .collect();
     let mut rhs = items.3 .0;
     let mut comment = items.3 .1;
 
@@ -73,17 +84,34 @@ pub fn parse_define<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebToplevel<'a
 
     if let WebDefineRhs::Statements(ref mut stmts) = &mut rhs {
         if stmts.len() == 1 && comment.is_none() {
-            if let WebStatement::Expr(_, ref mut sc) = &mut stmts[0] {
+            if let WebStatement::Expr(_, ref mut sc, _) = &mut stmts[0].node {
                 comment = sc.take();
             }
         }
     }
 
-    Ok((input, WebToplevel::Define(WebDefine { lhs, rhs, comment })))
+    Ok((
+        input,
+        WebToplevel::Define(WebDefine {
+            lhs,
+            rhs,
+            comment,
+            value: None,
+        }),
+    ))
+}
+
+impl<'a> WebDefine<'a> {
+    /// Set the numeric value of the macro, so that it's shown when the
+    /// definition is prettified.
+    pub fn set_value(&mut self, value: isize) {
+        self.value = Some(value);
+    }
 }
 
 /// The right-hand-side of a `@d` definition
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub enum WebDefineRhs<'a> {
     ReservedWord(SpanValue<'a, PascalReservedWord>),
 
@@ -99,18 +127,18 @@ pub enum WebDefineRhs<'a> {
     /// Definition of `othercases`: `{label}{colon}`
     OthercasesDefinition(StringSpan<'a>),
 
-    Statements(Vec<WebStatement<'a>>),
+    Statements(Vec<Spanned<WebStatement<'a>>>),
 
     /// A comma-separated group of exprs, needed for WEAVE#95.
-    CommaExprs(Vec<Box<WebExpr<'a>>>),
+    CommaExprs(Vec<&'a Spanned<WebExpr<'a>>>),
 
     /// A series of statements, then an imbalanced `end` keyword. Needed for
     /// WEAVE#125, WEAVE#148.
-    StatementsThenEnd(Vec<WebStatement<'a>>),
+    StatementsThenEnd(Vec<Spanned<WebStatement<'a>>>),
 
     /// An imbalanced `begin` keyword, then a series of statements. Needed
     /// for WEAVE#148.
-    BeginThenStatements(Vec<WebStatement<'a>>),
+    BeginThenStatements(Vec<Spanned<WebStatement<'a>>>),
 
     /// A synthesized identifier, needed for XeTeX(2022.0)#4
     SynthesizedIdentifier(Vec<StringSpan<'a>>),
@@ -124,13 +152,13 @@ pub enum WebDefineRhs<'a> {
     EndAndEndif(PascalToken<'a>),
 
     /// An `if` and `begin` with statements, but no `end`
-    IncompleteIf(Box<WebExpr<'a>>, Vec<WebStatement<'a>>),
+    IncompleteIf(&'a Spanned<WebExpr<'a>>, Vec<Spanned<WebStatement<'a>>>),
 
     /// A statement ending with `.0` because it involves floating point
     /// literals. Big old hack for a couple of forms appearing in
     /// XeTeX(2022.0):113. The token is the trailing int-literal token that is
     /// really the fractional part of a float literal.
-    FloatyStatement(WebStatement<'a>, PascalToken<'a>),
+    FloatyStatement(Spanned<WebStatement<'a>>, PascalToken<'a>),
 
     /// Super-specialized for XeTeX(2022.0):589: there are a variety of forms
     /// like `$expr [ $expr {$verbatim{(} $ident,$ident,$ident}?`.
@@ -153,6 +181,10 @@ pub enum WebDefineRhs<'a> {
 
     /// `$expr then $stmt else $stmt`, needed for XeTeX(2022.0):1360.
     XetexUndumpMiddle(SpecialXetexUndumpMiddle<'a>),
+
+    /// A type, for macros that alias type expressions, like
+    /// `@d two_halves==packed record rh:halfword; lh:halfword; end`.
+    Type(WebType<'a>),
 }
 
 fn parse_define_rhs<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebDefineRhs<'a>> {
@@ -167,6 +199,7 @@ fn parse_define_rhs<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebDefineRhs<'
         parse_end_and_endif,
         parse_incomplete_if,
         parse_begin_then_statements,
+        parse_type_definition,
         parse_comma_exprs,
         parse_synthesized_identifier,
         parse_xetex_char_info_head,
@@ -175,7 +208,7 @@ fn parse_define_rhs<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebDefineRhs<'
         parse_xetex_math_accessor_body,
         parse_xetex_undump_head,
         parse_xetex_undump_middle,
-        map(any_reserved_word, |rw| WebDefineRhs::ReservedWord(rw)),
+        map(any_reserved_word, WebDefineRhs::ReservedWord),
     ))(input)
 }
 
@@ -186,7 +219,7 @@ fn parse_inverted_statement<'a>(
 ) -> ParseResult<'a, (WebDefineRhs<'a>, Option<WebComment<'a>>)> {
     map(
         tuple((
-            map(comment, |c| Some(c)),
+            map(comment, Some),
             map(many1(statement::parse_statement_base), |s| {
                 WebDefineRhs::Statements(s)
             }),
@@ -213,6 +246,13 @@ pub fn peek_end_of_define<'a>(input: ParseInput<'a>) -> ParseResult<'a, ()> {
     }
 }
 
+/// Parse a type that makes up the whole right-hand side.
+fn parse_type_definition<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebDefineRhs<'a>> {
+    map(tuple((parse_type, peek_end_of_define)), |t| {
+        WebDefineRhs::Type(t.0)
+    })(input)
+}
+
 /// Parse a "floaty" statement (XeTeX(2022.0):113).
 fn parse_floaty_statement<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebDefineRhs<'a>> {
     map(
@@ -263,11 +303,10 @@ fn parse_ifdef_like<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebDefineRhs<'
         peek_end_of_define,
     ))(input)?;
 
-    let is_open = if let PascalToken::OpenDelimiter(DelimiterKind::MetaComment) = items.0 {
-        true
-    } else {
-        false
-    };
+    let is_open = matches!(
+        items.0,
+        PascalToken::OpenDelimiter(DelimiterKind::MetaComment)
+    );
 
     Ok((input, WebDefineRhs::IfdefLike(is_open)))
 }
@@ -295,11 +334,13 @@ fn parse_othercases<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebDefineRhs<'
 }
 
 fn parse_comma_exprs<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebDefineRhs<'a>> {
-    let (input, exprs) =
-        separated_list1(pascal_token(PascalToken::Comma), map(parse_expr, Box::new))(input)?;
+    let (input, exprs) = separated_list1(
+        pascal_token(PascalToken::Comma),
+        map(parse_expr, |e| input.arena().alloc_expr(e)),
+    )(input)?;
 
     if exprs.len() == 1 {
-        return new_parse_err(input, WebErrorKind::Eof);
+        new_parse_err(input, WebErrorKind::ExpectedPascalToken)
     } else {
         Ok((input, WebDefineRhs::CommaExprs(exprs)))
     }
@@ -309,7 +350,7 @@ fn parse_synthesized_identifier<'a>(input: ParseInput<'a>) -> ParseResult<'a, We
     let (input, idents) = separated_list1(pascal_token(PascalToken::PasteText), identifier)(input)?;
 
     if idents.len() == 1 {
-        return new_parse_err(input, WebErrorKind::Eof);
+        new_parse_err(input, WebErrorKind::ExpectedPascalToken)
     } else {
         Ok((input, WebDefineRhs::SynthesizedIdentifier(idents)))
     }
@@ -349,14 +390,15 @@ fn parse_incomplete_if<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebDefineRh
             reserved_word(PascalReservedWord::Begin),
             many1(statement::parse_statement_base),
         )),
-        |t| WebDefineRhs::IncompleteIf(Box::new(t.1), t.4),
+        |t| WebDefineRhs::IncompleteIf(input.arena().alloc_expr(t.1), t.4),
     )(input)
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct SpecialXetexCharInfoHead<'a> {
-    start: Box<WebExpr<'a>>,
-    middle: Box<WebExpr<'a>>,
+    start: &'a Spanned<WebExpr<'a>>,
+    middle: &'a Spanned<WebExpr<'a>>,
     tail_args: Vec<StringSpan<'a>>,
 }
 
@@ -373,8 +415,8 @@ fn parse_xetex_char_info_head<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebD
         )),
         |t| {
             WebDefineRhs::XetexCharInfoHead(SpecialXetexCharInfoHead {
-                start: Box::new(t.0),
-                middle: Box::new(t.2),
+                start: input.arena().alloc_expr(t.0),
+                middle: input.arena().alloc_expr(t.2),
                 tail_args: t.3.map(|tt| tt.1).unwrap_or_default(),
             })
         },
@@ -382,15 +424,15 @@ fn parse_xetex_char_info_head<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebD
 }
 
 fn verbatim_open_paren<'a>(input: ParseInput<'a>) -> ParseResult<'a, ()> {
-    let (input, tok) = verbatim_pascal(input)?;
+    let (rest, tok) = verbatim_pascal(input)?;
 
     if let PascalToken::VerbatimPascal(ss) = tok {
         if ss.value.as_ref() == "(" {
-            return Ok((input, ()));
+            return Ok((rest, ()));
         }
     }
 
-    new_parse_err(input, WebErrorKind::Eof)
+    new_parse_err(input, WebErrorKind::ExpectedVerbatimPascal)
 }
 
 impl<'a> RenderInline for SpecialXetexCharInfoHead<'a> {
@@ -431,10 +473,11 @@ impl<'a> RenderInline for SpecialXetexCharInfoHead<'a> {
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct SpecialXetexCharInfoTail<'a> {
-    start: Box<WebExpr<'a>>,
+    start: &'a Spanned<WebExpr<'a>>,
     has_right_paren: bool,
-    end: Box<WebExpr<'a>>,
+    end: &'a Spanned<WebExpr<'a>>,
 }
 
 fn parse_xetex_char_info_tail<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebDefineRhs<'a>> {
@@ -449,16 +492,16 @@ fn parse_xetex_char_info_tail<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebD
 
     if let Some(PascalToken::VerbatimPascal(ss)) = t.1.as_ref() {
         if ss.value.as_ref() != ")" {
-            return new_parse_err(input, WebErrorKind::Eof);
+            return new_parse_err(input, WebErrorKind::ExpectedVerbatimPascal);
         }
     }
 
     Ok((
         input,
         WebDefineRhs::XetexCharInfoTail(SpecialXetexCharInfoTail {
-            start: Box::new(t.0),
+            start: input.arena().alloc_expr(t.0),
             has_right_paren: t.1.is_some(),
-            end: Box::new(t.4),
+            end: input.arena().alloc_expr(t.4),
         }),
     ))
 }
@@ -482,6 +525,7 @@ impl<'a> RenderInline for SpecialXetexCharInfoTail<'a> {
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct SpecialXetexMathAccessorHead<'a> {
     arg: Option<(StringSpan<'a>, StringSpan<'a>)>,
     ret_type: WebType<'a>,
@@ -520,12 +564,12 @@ fn parse_xetex_math_accessor_head<'a>(input: ParseInput<'a>) -> ParseResult<'a,
 }
 
 fn hash_token<'a>(input: ParseInput<'a>) -> ParseResult<'a, ()> {
-    let (input, tok) = next_token(input)?;
+    let (rest, tok) = next_token(input)?;
 
     if let WebToken::Pascal(PascalToken::Hash(_)) = tok {
-        Ok((input, ()))
+        Ok((rest, ()))
     } else {
-        new_parse_err(input, WebErrorKind::Eof)
+        new_parse_err(input, WebErrorKind::ExpectedPascalToken)
     }
 }
 
@@ -554,9 +598,10 @@ impl<'a> SpecialXetexMathAccessorHead<'a> {
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct SpecialXetexMathAccessorBody<'a> {
     args: Vec<super::var_declaration::WebVarDeclaration<'a>>,
-    body: Vec<WebStatement<'a>>,
+    body: Vec<Spanned<WebStatement<'a>>>,
 }
 
 fn parse_xetex_math_accessor_body<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebDefineRhs<'a>> {
@@ -598,9 +643,10 @@ impl<'a> SpecialXetexMathAccessorBody<'a> {
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct SpecialXetexUndumpHead<'a> {
-    stmt: WebStatement<'a>,
-    test: Box<WebExpr<'a>>,
+    stmt: Spanned<WebStatement<'a>>,
+    test: &'a Spanned<WebExpr<'a>>,
 }
 
 fn parse_xetex_undump_head<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebDefineRhs<'a>> {
@@ -614,17 +660,18 @@ fn parse_xetex_undump_head<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebDefi
         |t| {
             WebDefineRhs::XetexUndumpHead(SpecialXetexUndumpHead {
                 stmt: t.1,
-                test: Box::new(t.3),
+                test: input.arena().alloc_expr(t.3),
             })
         },
     )(input)
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct SpecialXetexUndumpMiddle<'a> {
-    test: Box<WebExpr<'a>>,
-    then: WebStatement<'a>,
-    else_: WebStatement<'a>,
+    test: &'a Spanned<WebExpr<'a>>,
+    then: Spanned<WebStatement<'a>>,
+    else_: Spanned<WebStatement<'a>>,
 }
 
 fn parse_xetex_undump_middle<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebDefineRhs<'a>> {
@@ -639,7 +686,7 @@ fn parse_xetex_undump_middle<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebDe
         )),
         |t| {
             WebDefineRhs::XetexUndumpMiddle(SpecialXetexUndumpMiddle {
-                test: Box::new(t.0),
+                test: input.arena().alloc_expr(t.0),
                 then: t.2,
                 else_: t.4,
             })
@@ -658,23 +705,29 @@ impl<'a> WebDefine<'a> {
             .as_ref()
             .map(|c| 1 + c.measure_inline())
             .unwrap_or(0);
+        let v_width = self.value_note(dest).map(|n| 1 + n.len()).unwrap_or(0);
 
-        if dest.fits(11 + lhs_width + rhs_width + c_width) {
+        if dest.fits(12 + lhs_width + rhs_width + v_width + c_width) {
             dest.keyword("@define");
             dest.space();
 
             for t in &self.lhs {
-                dest.noscope_push(t);
+                render_lhs_token(t, dest);
             }
 
-            dest.noscope_push(" => ");
+            dest.noscope_push(define_operator(dest));
             self.rhs.render_inline(dest);
 
+            if let Some(note) = self.value_note(dest) {
+                dest.space();
+                dest.scope_push(*COMMENT_SCOPE, note);
+            }
+
             if let Some(c) = self.comment.as_ref() {
                 dest.space();
                 c.render_inline(dest);
             }
-        } else if dest.fits(11 + lhs_width + rhs_width) {
+        } else if dest.fits(12 + lhs_width + rhs_width + v_width) {
             // We can't get here without the comment being Some, but ...
             if let Some(c) = self.comment.as_ref() {
                 c.render_inline(dest);
@@ -685,11 +738,16 @@ impl<'a> WebDefine<'a> {
             dest.space();
 
             for t in &self.lhs {
-                dest.noscope_push(t);
+                render_lhs_token(t, dest);
             }
 
-            dest.noscope_push(" => ");
+            dest.noscope_push(define_operator(dest));
             self.rhs.render_inline(dest);
+
+            if let Some(note) = self.value_note(dest) {
+                dest.space();
+                dest.scope_push(*COMMENT_SCOPE, note);
+            }
         } else {
             if let Some(c) = self.comment.as_ref() {
                 c.render_inline(dest);
@@ -700,17 +758,51 @@ impl<'a> WebDefine<'a> {
             dest.space();
 
             for t in &self.lhs {
-                dest.noscope_push(t);
+                render_lhs_token(t, dest);
             }
 
-            dest.noscope_push(" =>");
+            dest.noscope_push(define_operator(dest).trim_end());
             dest.indent_block();
             dest.newline_indent();
             render_rhs_flex(&self.rhs, dest);
+
+            if let Some(note) = self.value_note(dest) {
+                dest.newline_needed();
+                dest.scope_push(*COMMENT_SCOPE, note);
+            }
         }
 
         dest.newline_needed();
     }
+
+    /// The annotation giving the value of the macro, if it has one. WEAVE
+    /// doesn't give these, so there aren't any when the code is written with
+    /// Pascal's keywords.
+    fn value_note(&self, dest: &Prettifier) -> Option<String> {
+        if dest.style().preserve_keywords {
+            return None;
+        }
+
+        self.value.map(|v| format!("/* = {} */", v))
+    }
+}
+
+/// The operator written between the two sides of a definition.
+fn define_operator(dest: &Prettifier) -> &'static str {
+    if dest.style().preserve_keywords {
+        " == "
+    } else {
+        " => "
+    }
+}
+
+/// Render a token on the left-hand side of a definition, so that the name of
+/// the macro is treated as an identifier.
+fn render_lhs_token(t: &PascalToken, dest: &mut Prettifier) {
+    match t {
+        PascalToken::Identifier(ident) => dest.identifier(ident),
+        _ => dest.noscope_push(t),
+    }
 }
 
 impl<'a> RenderInline for WebDefineRhs<'a> {
@@ -751,6 +843,7 @@ impl<'a> RenderInline for WebDefineRhs<'a> {
             }
             WebDefineRhs::XetexCharInfoHead(cih) => cih.measure_inline(),
             WebDefineRhs::XetexCharInfoTail(cit) => cit.measure_inline(),
+            WebDefineRhs::Type(ty) => ty.measure_inline(),
         }
     }
 
@@ -777,8 +870,9 @@ impl<'a> RenderInline for WebDefineRhs<'a> {
             WebDefineRhs::LoopDefinition(t) => {
                 dest.keyword("while");
                 dest.space();
-                dest.noscope_push(t.value.as_ref());
-                dest.noscope_push(" {");
+                dest.identifier(t);
+                dest.space();
+                open_block("do", dest);
             }
 
             WebDefineRhs::EmptyDefinition => dest.noscope_push("/*nothing*/"),
@@ -813,6 +907,7 @@ impl<'a> RenderInline for WebDefineRhs<'a> {
 
             WebDefineRhs::XetexCharInfoHead(cih) => cih.render_inline(dest),
             WebDefineRhs::XetexCharInfoTail(cit) => cit.render_inline(dest),
+            WebDefineRhs::Type(ty) => ty.render_inline(dest),
         }
     }
 }
@@ -832,7 +927,7 @@ fn render_rhs_flex<'a>(rhs: &WebDefineRhs<'a>, dest: &mut Prettifier) {
         }
 
         WebDefineRhs::Statements(stmts) => {
-            let i_last = stmts.len() - 1;
+            let i_last = stmts.len().saturating_sub(1);
 
             for (i, s) in stmts.iter().enumerate() {
                 s.render_flex(dest);
@@ -844,7 +939,7 @@ fn render_rhs_flex<'a>(rhs: &WebDefineRhs<'a>, dest: &mut Prettifier) {
         }
 
         WebDefineRhs::CommaExprs(exprs) => {
-            let i_last = exprs.len() - 1;
+            let i_last = exprs.len().saturating_sub(1);
 
             for (i, e) in exprs.iter().enumerate() {
                 e.render_inline(dest);
@@ -861,22 +956,24 @@ fn render_rhs_flex<'a>(rhs: &WebDefineRhs<'a>, dest: &mut Prettifier) {
             dest.scope_push(*COMMENT_SCOPE, "/*... opened earlier ...*/");
             dest.indent_block();
 
-            for s in stmts {
+            let i_last = stmts.len().saturating_sub(1);
+
+            for (i, s) in stmts.iter().enumerate() {
                 dest.newline_indent();
                 s.render_flex(dest);
-                s.maybe_semicolon(dest);
+                s.maybe_semicolon_in_block(i == i_last, dest);
             }
 
             dest.dedent_block();
             dest.newline_indent();
-            dest.noscope_push("}");
+            close_block(dest);
         }
 
         WebDefineRhs::BeginThenStatements(stmts) => {
-            dest.noscope_push("{");
+            open_block("begin", dest);
             dest.indent_block();
 
-            let i_last = stmts.len() - 1;
+            let i_last = stmts.len().saturating_sub(1);
 
             for (i, s) in stmts.iter().enumerate() {
                 dest.newline_indent();
@@ -894,12 +991,23 @@ fn render_rhs_flex<'a>(rhs: &WebDefineRhs<'a>, dest: &mut Prettifier) {
 
         WebDefineRhs::IncompleteIf(expr, stmts) => {
             dest.keyword("if");
-            dest.noscope_push(" (");
-            expr.render_flex(dest);
-            dest.noscope_push(") {");
+
+            if dest.style().preserve_keywords {
+                dest.space();
+                expr.render_flex(dest);
+                dest.space();
+                dest.keyword("then");
+                dest.space();
+                dest.keyword("begin");
+            } else {
+                dest.noscope_push(" (");
+                expr.render_flex(dest);
+                dest.noscope_push(") {");
+            }
+
             dest.indent_block();
 
-            let i_last = stmts.len() - 1;
+            let i_last = stmts.len().saturating_sub(1);
 
             for (i, s) in stmts.iter().enumerate() {
                 dest.newline_indent();
@@ -917,19 +1025,34 @@ fn render_rhs_flex<'a>(rhs: &WebDefineRhs<'a>, dest: &mut Prettifier) {
 
         WebDefineRhs::IfdefAndIf(beg, ident) => {
             beg.render_inline(dest);
-            dest.noscope_push("!{");
+
+            if !dest.style().preserve_keywords {
+                dest.noscope_push("!{");
+            }
+
             dest.indent_block();
             dest.newline_indent();
             dest.keyword("if");
             dest.space();
             dest.noscope_push(ident);
             dest.space();
-            dest.noscope_push("{");
+
+            if dest.style().preserve_keywords {
+                dest.keyword("then");
+                dest.space();
+            }
+
+            open_block("begin", dest);
         }
 
         WebDefineRhs::EndAndEndif(end) => {
             dest.indent_block();
-            dest.noscope_push("}");
+            close_block(dest);
+
+            if dest.style().preserve_keywords {
+                dest.noscope_push(';');
+            }
+
             dest.dedent_block();
             dest.newline_indent();
             end.render_inline(dest);
@@ -937,6 +1060,7 @@ fn render_rhs_flex<'a>(rhs: &WebDefineRhs<'a>, dest: &mut Prettifier) {
 
         WebDefineRhs::XetexMathAccessorHead(mah) => mah.prettify(dest),
         WebDefineRhs::XetexMathAccessorBody(mab) => mab.prettify(dest),
+        WebDefineRhs::Type(ty) => ty.render_flex(dest),
 
         WebDefineRhs::XetexUndumpHead(uh) => {
             dest.noscope_push("{");
@@ -977,3 +1101,150 @@ fn render_rhs_flex<'a>(rhs: &WebDefineRhs<'a>, dest: &mut Prettifier) {
         }
     }
 }
+
+/// Open a block in the right-hand side of a definition: with the Pascal
+/// keyword `pascal` when the code is written with Pascal's keywords, and with a
+/// brace otherwise.
+fn open_block(pascal: &str, dest: &mut Prettifier) {
+    if dest.style().preserve_keywords {
+        dest.keyword(pascal);
+    } else {
+        dest.noscope_push("{");
+    }
+}
+
+/// Close a block in the right-hand side of a definition, with `end` or a brace
+/// as with [`open_block`].
+fn close_block(dest: &mut Prettifier) {
+    if dest.style().preserve_keywords {
+        dest.keyword("end");
+    } else {
+        dest.noscope_push("}");
+    }
+}
+
+// Walking
+
+impl<'a> WebDefine<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        for (i, tok) in self.lhs.iter().enumerate() {
+            match tok {
+                PascalToken::Identifier(ident) | PascalToken::FormattedIdentifier(ident, _)
+                    if i == 0 =>
+                {
+                    visitor.visit_defined_identifier(ident)
+                }
+                _ => visitor.visit_token(tok),
+            }
+        }
+
+        self.rhs.walk(visitor);
+
+        if let Some(c) = &self.comment {
+            visitor.visit_comment(c);
+        }
+    }
+}
+
+impl<'a> WebDefineRhs<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        match self {
+            WebDefineRhs::ReservedWord(_)
+            | WebDefineRhs::IfdefLike(_)
+            | WebDefineRhs::EmptyDefinition => {}
+
+            WebDefineRhs::LoopDefinition(ident) | WebDefineRhs::OthercasesDefinition(ident) => {
+                visitor.visit_identifier(ident);
+            }
+
+            WebDefineRhs::Statements(stmts)
+            | WebDefineRhs::StatementsThenEnd(stmts)
+            | WebDefineRhs::BeginThenStatements(stmts) => {
+                for stmt in stmts {
+                    visitor.visit_statement(stmt);
+                }
+            }
+
+            WebDefineRhs::CommaExprs(exprs) => {
+                for expr in exprs {
+                    visitor.visit_expr(expr);
+                }
+            }
+
+            WebDefineRhs::SynthesizedIdentifier(pieces) => {
+                for piece in pieces {
+                    visitor.visit_identifier(piece);
+                }
+            }
+
+            WebDefineRhs::IfdefAndIf(tok, ident) => {
+                visitor.visit_token(tok);
+                visitor.visit_identifier(ident);
+            }
+
+            WebDefineRhs::EndAndEndif(tok) => visitor.visit_token(tok),
+
+            WebDefineRhs::IncompleteIf(test, stmts) => {
+                visitor.visit_expr(test);
+
+                for stmt in stmts {
+                    visitor.visit_statement(stmt);
+                }
+            }
+
+            WebDefineRhs::FloatyStatement(stmt, tok) => {
+                visitor.visit_statement(stmt);
+                visitor.visit_token(tok);
+            }
+
+            WebDefineRhs::XetexCharInfoHead(head) => {
+                visitor.visit_expr(head.start);
+                visitor.visit_expr(head.middle);
+
+                for arg in &head.tail_args {
+                    visitor.visit_identifier(arg);
+                }
+            }
+
+            WebDefineRhs::XetexCharInfoTail(tail) => {
+                visitor.visit_expr(tail.start);
+                visitor.visit_expr(tail.end);
+            }
+
+            WebDefineRhs::XetexMathAccessorHead(head) => {
+                if let Some((arg, arg_type)) = &head.arg {
+                    visitor.visit_identifier(arg);
+                    visitor.visit_identifier(arg_type);
+                }
+
+                visitor.visit_type(&head.ret_type);
+                visitor.visit_identifier(&head.body);
+            }
+
+            WebDefineRhs::XetexMathAccessorBody(body) => {
+                for arg in &body.args {
+                    visitor.visit_var_declaration(arg);
+                }
+
+                for stmt in &body.body {
+                    visitor.visit_statement(stmt);
+                }
+            }
+
+            WebDefineRhs::XetexUndumpHead(head) => {
+                visitor.visit_statement(&head.stmt);
+                visitor.visit_expr(head.test);
+            }
+
+            WebDefineRhs::XetexUndumpMiddle(middle) => {
+                visitor.visit_expr(middle.test);
+                visitor.visit_statement(&middle.then);
+                visitor.visit_statement(&middle.else_);
+            }
+
+            WebDefineRhs::Type(ty) => visitor.visit_type(ty),
+        }
+    }
+}
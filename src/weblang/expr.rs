@@ -6,12 +6,15 @@ use nom::{
     multi::separated_list0,
     sequence::tuple,
 };
+#[cfg(feature = "serde")]
+use serde::Serialize;
 
 use crate::prettify::{self, Prettifier, RenderInline};
 
 use super::{base::*, module_reference::parse_module_reference};
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub enum WebExpr<'a> {
     /// A binary expression.
     Binary(WebBinaryExpr<'a>),
@@ -39,7 +42,7 @@ pub enum WebExpr<'a> {
 
     /// A parenthesized subexpression. The comment is needed
     /// for XeTeX(2022.0):877.
-    Paren(Box<WebExpr<'a>>, Option<WebComment<'a>>),
+    Paren(&'a Spanned<WebExpr<'a>>, Option<WebComment<'a>>),
 
     /// A module reference as an expression, needed for XeTeX(2022.0):59.
     ModuleReference(WebModuleReference<'a>),
@@ -56,24 +59,22 @@ pub enum WebExpr<'a> {
     SpecialReturnForm(PascalToken<'a>),
 }
 
-pub fn parse_expr<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebExpr<'a>> {
+pub fn parse_expr<'a>(start: ParseInput<'a>) -> ParseResult<'a, Spanned<WebExpr<'a>>> {
+    nested(start, parse_expr_unnested)
+}
+
+fn parse_expr_unnested<'a>(start: ParseInput<'a>) -> ParseResult<'a, Spanned<WebExpr<'a>>> {
     // First try the "advancing" forms, which may recurse with an advanced input,
-    // and the "atom" forms, which won't recurse:
+    // and the "atom" forms, which won't recurse. The token form goes last so
+    // that if nothing matches, its error is the one that's reported.
 
-    let result = alt((
+    let (mut input, mut expr) = spanned(alt((
         parse_prefix_unary_expr,
         parse_paren_expr,
-        map(merged_string_literals, |t| WebExpr::Token(t)),
+        map(merged_string_literals, WebExpr::Token),
+        map(parse_module_reference, WebExpr::ModuleReference),
         parse_token_expr,
-        map(parse_module_reference, |mr| WebExpr::ModuleReference(mr)),
-    ))(input);
-
-    let (mut input, mut expr) = match result {
-        Ok(t) => t,
-        _ => {
-            return result;
-        }
-    };
+    )))(start)?;
 
     // If that worked, now gobble up as many left-recursive forms as we can.
     // These may recurse, but with an advanced input since we've eaten the
@@ -91,7 +92,10 @@ pub fn parse_expr<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebExpr<'a>> {
 
         if let Ok((new_input, tail)) = result {
             input = new_input;
-            expr = tail.finalize(Box::new(expr));
+            expr = Spanned {
+                span: start.span_to(input),
+                node: tail.finalize(start.arena().alloc_expr(expr)),
+            };
         } else {
             return Ok((input, expr));
         }
@@ -103,17 +107,10 @@ pub fn parse_expr<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebExpr<'a>> {
 ///
 /// Due to WEB's macros, things that look like function calls can appear
 /// as LHSes.
-pub fn parse_lhs_expr<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebExpr<'a>> {
+pub fn parse_lhs_expr<'a>(start: ParseInput<'a>) -> ParseResult<'a, Spanned<WebExpr<'a>>> {
     // LHS-valid advancing/atom forms:
 
-    let result = parse_token_expr(input);
-
-    let (mut input, mut expr) = match result {
-        Ok(t) => t,
-        _ => {
-            return result;
-        }
-    };
+    let (mut input, mut expr) = spanned(parse_token_expr)(start)?;
 
     // LHS-valid left-recursive forms:
 
@@ -122,7 +119,10 @@ pub fn parse_lhs_expr<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebExpr<'a>>
 
         if let Ok((new_input, tail)) = result {
             input = new_input;
-            expr = tail.finalize(Box::new(expr));
+            expr = Spanned {
+                span: start.span_to(input),
+                node: tail.finalize(start.arena().alloc_expr(expr)),
+            };
         } else {
             return Ok((input, expr));
         }
@@ -133,18 +133,11 @@ pub fn parse_lhs_expr<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebExpr<'a>>
 /// really all integers, but due to WEB's macros may look like integer literals,
 /// double-quoted string literals, identifiers, function calls (WEB macros), or
 /// simple binary math expressions.
-pub fn parse_case_match_expr<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebExpr<'a>> {
-    let result = alt((
-        map(merged_string_literals, |t| WebExpr::Token(t)),
+pub fn parse_case_match_expr<'a>(start: ParseInput<'a>) -> ParseResult<'a, Spanned<WebExpr<'a>>> {
+    let (mut input, mut expr) = spanned(alt((
+        map(merged_string_literals, WebExpr::Token),
         parse_token_expr,
-    ))(input);
-
-    let (mut input, mut expr) = match result {
-        Ok(t) => t,
-        _ => {
-            return result;
-        }
-    };
+    )))(start)?;
 
     // Check for call() form or binary form.
 
@@ -152,7 +145,10 @@ pub fn parse_case_match_expr<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebEx
 
     if let Ok((new_input, tail)) = result {
         input = new_input;
-        expr = tail.finalize(Box::new(expr));
+        expr = Spanned {
+            span: start.span_to(input),
+            node: tail.finalize(start.arena().alloc_expr(expr)),
+        };
     }
 
     Ok((input, expr))
@@ -161,7 +157,7 @@ pub fn parse_case_match_expr<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebEx
 // "Atom" forms that do not include sub-expressions
 
 fn parse_token_expr<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebExpr<'a>> {
-    let (input, wt) = next_token(input)?;
+    let (rest, wt) = next_token(input)?;
 
     if let WebToken::Pascal(pt) = wt {
         match pt {
@@ -174,13 +170,17 @@ fn parse_token_expr<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebExpr<'a>> {
                 value: PascalReservedWord::Nil,
                 ..
             })
-            | PascalToken::StringPoolChecksum => return Ok((input, WebExpr::Token(pt))),
+            | PascalToken::StringPoolChecksum => return Ok((rest, WebExpr::Token(pt))),
+
+            PascalToken::ReservedWord(sv) => {
+                return new_parse_err(input, WebErrorKind::UnexpectedReservedWord(sv.value));
+            }
 
             _ => {}
         }
     }
 
-    return new_parse_err(input, WebErrorKind::Eof);
+    new_parse_err(input, WebErrorKind::ExpectedExpr)
 }
 
 // "Advancing" forms that include sub-expressions, but also require leading
@@ -194,28 +194,29 @@ fn parse_paren_expr<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebExpr<'a>> {
             pascal_token(PascalToken::CloseDelimiter(DelimiterKind::Paren)),
             opt(comment),
         )),
-        |t| WebExpr::Paren(Box::new(t.1), t.3),
+        |t| WebExpr::Paren(input.arena().alloc_expr(t.1), t.3),
     )(input)
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct WebPrefixUnaryExpr<'a> {
     op: PascalToken<'a>,
 
-    inner: Box<WebExpr<'a>>,
+    inner: &'a Spanned<WebExpr<'a>>,
 }
 
 fn parse_prefix_unary_expr<'a>(s: ParseInput<'a>) -> ParseResult<'a, WebExpr<'a>> {
     let (s, items) = tuple((prefix_unary_expr_op, parse_expr))(s)?;
 
     let op = items.0;
-    let inner = Box::new(items.1);
+    let inner = s.arena().alloc_expr(items.1);
 
     Ok((s, WebExpr::PrefixUnary(WebPrefixUnaryExpr { op, inner })))
 }
 
 fn prefix_unary_expr_op<'a>(input: ParseInput<'a>) -> ParseResult<'a, PascalToken<'a>> {
-    let (input, wt) = next_token(input)?;
+    let (rest, wt) = next_token(input)?;
 
     if let WebToken::Pascal(pt) = wt {
         match pt {
@@ -224,13 +225,13 @@ fn prefix_unary_expr_op<'a>(input: ParseInput<'a>) -> ParseResult<'a, PascalToke
             | PascalToken::ReservedWord(SpanValue {
                 value: PascalReservedWord::Not,
                 ..
-            }) => return Ok((input, pt)),
+            }) => return Ok((rest, pt)),
 
             _ => {}
         }
     }
 
-    return new_parse_err(input, WebErrorKind::Eof);
+    new_parse_err(input, WebErrorKind::ExpectedOperator)
 }
 
 // "Left-recursive" forms that start with a subexpression. We have to
@@ -238,17 +239,18 @@ fn prefix_unary_expr_op<'a>(input: ParseInput<'a>) -> ParseResult<'a, PascalToke
 // lead to an infinite call stack.
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub enum LeftRecursiveTail<'a> {
-    Binary(PascalToken<'a>, Box<WebExpr<'a>>),
+    Binary(PascalToken<'a>, &'a Spanned<WebExpr<'a>>),
     PostfixUnary(PascalToken<'a>),
-    Call(Vec<Box<WebExpr<'a>>>),
+    Call(Vec<&'a Spanned<WebExpr<'a>>>),
     Index(Vec<WebIndexTerm<'a>>),
     Field(StringSpan<'a>),
     Format(PascalToken<'a>),
 }
 
 impl<'a> LeftRecursiveTail<'a> {
-    fn finalize(self, head: Box<WebExpr<'a>>) -> WebExpr<'a> {
+    fn finalize(self, head: &'a Spanned<WebExpr<'a>>) -> WebExpr<'a> {
         match self {
             LeftRecursiveTail::Binary(op, rhs) => {
                 WebExpr::Binary(WebBinaryExpr { lhs: head, op, rhs })
@@ -269,24 +271,25 @@ impl<'a> LeftRecursiveTail<'a> {
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct WebBinaryExpr<'a> {
-    lhs: Box<WebExpr<'a>>,
+    lhs: &'a Spanned<WebExpr<'a>>,
 
     op: PascalToken<'a>,
 
-    rhs: Box<WebExpr<'a>>,
+    rhs: &'a Spanned<WebExpr<'a>>,
 }
 
 fn binary_tail<'a>(s: ParseInput<'a>) -> ParseResult<'a, LeftRecursiveTail<'a>> {
     map(tuple((binary_expr_op, parse_expr)), |t| {
-        LeftRecursiveTail::Binary(t.0, Box::new(t.1))
+        LeftRecursiveTail::Binary(t.0, s.arena().alloc_expr(t.1))
     })(s)
 }
 
 /// In XeTeX(2022.0):1386, the `@&` "paste" command is used like a binary
 /// operator, and it's convenient to treat it as such here.
 fn binary_expr_op<'a>(input: ParseInput<'a>) -> ParseResult<'a, PascalToken<'a>> {
-    let (input, wt) = next_token(input)?;
+    let (rest, wt) = next_token(input)?;
 
     if let WebToken::Pascal(pt) = wt {
         match pt {
@@ -316,20 +319,21 @@ fn binary_expr_op<'a>(input: ParseInput<'a>) -> ParseResult<'a, PascalToken<'a>>
             | PascalToken::ReservedWord(SpanValue {
                 value: PascalReservedWord::Or,
                 ..
-            }) => return Ok((input, pt)),
+            }) => return Ok((rest, pt)),
 
             _ => {}
         }
     }
 
-    return new_parse_err(input, WebErrorKind::Eof);
+    new_parse_err(input, WebErrorKind::ExpectedOperator)
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct WebPostfixUnaryExpr<'a> {
     op: PascalToken<'a>,
 
-    inner: Box<WebExpr<'a>>,
+    inner: &'a Spanned<WebExpr<'a>>,
 }
 
 fn postfix_unary_tail<'a>(s: ParseInput<'a>) -> ParseResult<'a, LeftRecursiveTail<'a>> {
@@ -339,23 +343,35 @@ fn postfix_unary_tail<'a>(s: ParseInput<'a>) -> ParseResult<'a, LeftRecursiveTai
 }
 
 fn postfix_unary_expr_op<'a>(input: ParseInput<'a>) -> ParseResult<'a, PascalToken<'a>> {
-    let (input, wt) = next_token(input)?;
+    let (rest, wt) = next_token(input)?;
 
     if let WebToken::Pascal(pt) = wt {
-        match pt {
-            PascalToken::Caret => return Ok((input, pt)),
-            _ => {}
+        if pt == PascalToken::Caret {
+            return Ok((rest, pt));
         }
     }
 
-    return new_parse_err(input, WebErrorKind::Eof);
+    new_parse_err(input, WebErrorKind::ExpectedOperator)
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct WebCallExpr<'a> {
-    target: Box<WebExpr<'a>>,
+    target: &'a Spanned<WebExpr<'a>>,
 
-    args: Vec<Box<WebExpr<'a>>>,
+    args: Vec<&'a Spanned<WebExpr<'a>>>,
+}
+
+impl<'a> WebCallExpr<'a> {
+    /// The function or procedure that is called.
+    pub fn target(&self) -> &Spanned<WebExpr<'a>> {
+        self.target
+    }
+
+    /// The arguments of the call.
+    pub fn args(&self) -> impl Iterator<Item = &Spanned<WebExpr<'a>>> {
+        self.args.iter().copied()
+    }
 }
 
 fn call_tail<'a>(s: ParseInput<'a>) -> ParseResult<'a, LeftRecursiveTail<'a>> {
@@ -364,7 +380,7 @@ fn call_tail<'a>(s: ParseInput<'a>) -> ParseResult<'a, LeftRecursiveTail<'a>> {
             open_delimiter(DelimiterKind::Paren),
             separated_list0(
                 pascal_token(PascalToken::Comma),
-                map(parse_expr_with_call_specials, |e| Box::new(e)),
+                map(parse_expr_with_call_specials, |e| s.arena().alloc_expr(e)),
             ),
             close_delimiter(DelimiterKind::Paren),
         )),
@@ -372,31 +388,41 @@ fn call_tail<'a>(s: ParseInput<'a>) -> ParseResult<'a, LeftRecursiveTail<'a>> {
     )(s)
 }
 
-fn parse_expr_with_call_specials<'a>(s: ParseInput<'a>) -> ParseResult<'a, WebExpr<'a>> {
+fn parse_expr_with_call_specials<'a>(s: ParseInput<'a>) -> ParseResult<'a, Spanned<WebExpr<'a>>> {
     alt((
-        map(
+        spanned(map(
             tuple((reserved_word(PascalReservedWord::Goto), identifier)),
             |t| WebExpr::SpecialGotoForm(t.1),
-        ),
-        map(formatted_identifier_like(PascalReservedWord::Nil), |tok| {
-            WebExpr::SpecialReturnForm(tok)
-        }),
+        )),
+        spanned(map(
+            formatted_identifier_like(PascalReservedWord::Nil),
+            WebExpr::SpecialReturnForm,
+        )),
         parse_expr,
     ))(s)
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct WebIndexExpr<'a> {
-    target: Box<WebExpr<'a>>,
+    target: &'a Spanned<WebExpr<'a>>,
 
     args: Vec<WebIndexTerm<'a>>,
 }
 
+impl<'a> WebIndexExpr<'a> {
+    /// The array that is indexed.
+    pub fn target(&self) -> &Spanned<WebExpr<'a>> {
+        self.target
+    }
+}
+
 /// The `Range` option is needed for some inline Pascal such as in WEAVE#65.
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub enum WebIndexTerm<'a> {
-    Expr(Box<WebExpr<'a>>),
-    Range(Box<WebExpr<'a>>, Box<WebExpr<'a>>),
+    Expr(&'a Spanned<WebExpr<'a>>),
+    Range(&'a Spanned<WebExpr<'a>>, &'a Spanned<WebExpr<'a>>),
 }
 
 fn index_tail<'a>(s: ParseInput<'a>) -> ParseResult<'a, LeftRecursiveTail<'a>> {
@@ -410,23 +436,27 @@ fn index_tail<'a>(s: ParseInput<'a>) -> ParseResult<'a, LeftRecursiveTail<'a>> {
     )(s)
 }
 
+/// An index term is parsed as an expression that might turn out to be the
+/// start of a range, rather than trying a range first and then an expression.
+/// Otherwise, the expression would be parsed twice, and nested indices would
+/// take exponential time.
 fn index_term<'a>(s: ParseInput<'a>) -> ParseResult<'a, WebIndexTerm<'a>> {
-    alt((
-        range_index_term,
-        map(parse_expr, |e| WebIndexTerm::Expr(Box::new(e))),
-    ))(s)
-}
+    let (rest, lo) = parse_expr(s)?;
+    let lo = s.arena().alloc_expr(lo);
+    let (rest, hi) = opt(tuple((pascal_token(PascalToken::DoubleDot), parse_expr)))(rest)?;
 
-fn range_index_term<'a>(s: ParseInput<'a>) -> ParseResult<'a, WebIndexTerm<'a>> {
-    map(
-        tuple((parse_expr, pascal_token(PascalToken::DoubleDot), parse_expr)),
-        |t| WebIndexTerm::Range(Box::new(t.0), Box::new(t.2)),
-    )(s)
+    let term = match hi {
+        Some((_, hi)) => WebIndexTerm::Range(lo, s.arena().alloc_expr(hi)),
+        None => WebIndexTerm::Expr(lo),
+    };
+
+    Ok((rest, term))
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct WebFormatExpr<'a> {
-    inner: Box<WebExpr<'a>>,
+    inner: &'a Spanned<WebExpr<'a>>,
     width: PascalToken<'a>,
 }
 
@@ -438,11 +468,19 @@ fn format_tail<'a>(s: ParseInput<'a>) -> ParseResult<'a, LeftRecursiveTail<'a>>
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct WebFieldAccessExpr<'a> {
-    item: Box<WebExpr<'a>>,
+    item: &'a Spanned<WebExpr<'a>>,
     field: StringSpan<'a>,
 }
 
+impl<'a> WebFieldAccessExpr<'a> {
+    /// The record whose field is accessed.
+    pub fn item(&self) -> &Spanned<WebExpr<'a>> {
+        self.item
+    }
+}
+
 fn field_tail<'a>(s: ParseInput<'a>) -> ParseResult<'a, LeftRecursiveTail<'a>> {
     map(
         tuple((pascal_token(PascalToken::Period), identifier)),
@@ -517,7 +555,7 @@ impl<'a> RenderInline for WebExpr<'a> {
             }
 
             WebExpr::PrefixUnary(pu) => {
-                pu.op.render_inline(dest);
+                pu.render_op(dest);
                 pu.inner.render_inline(dest);
             }
 
@@ -568,7 +606,7 @@ impl<'a> RenderInline for WebExpr<'a> {
             WebExpr::SpecialGotoForm(id) => {
                 dest.keyword("goto");
                 dest.space();
-                dest.noscope_push(&id);
+                dest.noscope_push(id);
             }
 
             WebExpr::SpecialReturnForm(tok) => {
@@ -603,7 +641,7 @@ impl<'a> WebExpr<'a> {
             }
 
             WebExpr::PrefixUnary(pu) => {
-                pu.op.render_inline(dest);
+                pu.render_op(dest);
                 pu.inner.render_flex(dest);
             }
 
@@ -709,12 +747,12 @@ impl<'a> WebExpr<'a> {
 
                 if dest.fits(wf) {
                     dest.noscope_push('.');
-                    dest.noscope_push(f.field.value.as_ref());
+                    dest.identifier(&f.field);
                 } else {
                     dest.indent_small();
                     dest.newline_indent();
                     dest.noscope_push('.');
-                    dest.noscope_push(f.field.value.as_ref());
+                    dest.identifier(&f.field);
                     dest.dedent_small();
                 }
             }
@@ -784,7 +822,7 @@ impl<'a> WebExpr<'a> {
     /// This will be called with `dest` filled out ready to display `self` as the
     /// RHS at the pipe symbol here:
     ///
-    /// ```
+    /// ```text
     /// <LHS>
     /// <op> |<RHS>
     /// ```
@@ -857,3 +895,115 @@ impl<'a> WebIndexTerm<'a> {
         }
     }
 }
+
+// Walking
+
+impl<'a> WebExpr<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        match self {
+            WebExpr::Binary(e) => visitor.visit_binary_expr(e),
+            WebExpr::PrefixUnary(e) => visitor.visit_prefix_unary_expr(e),
+            WebExpr::PostfixUnary(e) => visitor.visit_postfix_unary_expr(e),
+            WebExpr::Token(tok) => visitor.visit_token(tok),
+            WebExpr::Call(e) => visitor.visit_call_expr(e),
+            WebExpr::Index(e) => visitor.visit_index_expr(e),
+            WebExpr::Field(e) => visitor.visit_field_access_expr(e),
+            WebExpr::Format(e) => visitor.visit_format_expr(e),
+            WebExpr::Paren(inner, comment) => {
+                visitor.visit_expr(inner);
+
+                if let Some(c) = comment {
+                    visitor.visit_comment(c);
+                }
+            }
+            WebExpr::ModuleReference(mref) => visitor.visit_module_reference(mref),
+            WebExpr::SpecialGotoForm(label) => visitor.visit_identifier(label),
+            WebExpr::SpecialReturnForm(tok) => visitor.visit_token(tok),
+        }
+    }
+}
+
+impl<'a> WebBinaryExpr<'a> {
+    /// The operator joining the two sides.
+    pub fn op(&self) -> &PascalToken<'a> {
+        &self.op
+    }
+
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        visitor.visit_expr(self.lhs);
+        visitor.visit_token(&self.op);
+        visitor.visit_expr(self.rhs);
+    }
+}
+
+impl<'a> WebPrefixUnaryExpr<'a> {
+    /// Render the operator. When the operators are written as in Pascal, `not`
+    /// needs a space to separate it from its operand.
+    fn render_op(&self, dest: &mut Prettifier) {
+        self.op.render_inline(dest);
+
+        if dest.style().preserve_operators && self.op.is_reserved_word(PascalReservedWord::Not) {
+            dest.space();
+        }
+    }
+
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        visitor.visit_token(&self.op);
+        visitor.visit_expr(self.inner);
+    }
+}
+
+impl<'a> WebPostfixUnaryExpr<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        visitor.visit_expr(self.inner);
+        visitor.visit_token(&self.op);
+    }
+}
+
+impl<'a> WebCallExpr<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        visitor.visit_expr(self.target);
+
+        for arg in &self.args {
+            visitor.visit_expr(arg);
+        }
+    }
+}
+
+impl<'a> WebIndexExpr<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        visitor.visit_expr(self.target);
+
+        for arg in &self.args {
+            match arg {
+                WebIndexTerm::Expr(e) => visitor.visit_expr(e),
+                WebIndexTerm::Range(lo, hi) => {
+                    visitor.visit_expr(lo);
+                    visitor.visit_expr(hi);
+                }
+            }
+        }
+    }
+}
+
+impl<'a> WebFieldAccessExpr<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        visitor.visit_expr(self.item);
+        visitor.visit_identifier(&self.field);
+    }
+}
+
+impl<'a> WebFormatExpr<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        visitor.visit_expr(self.inner);
+        visitor.visit_token(&self.width);
+    }
+}
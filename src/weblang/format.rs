@@ -1,10 +1,18 @@
 //! A WEB `@f` format definition.
 //!
-//! These have the form `@f identifier == reservedword`.
-//!
-//! TODO: honor these!
-
-use nom::{branch::alt, combinator::opt, sequence::tuple};
+//! These have the form `@f identifier == reservedword`, or `@f identifier ==
+//! identifier` to format one identifier like another. The formatting itself is
+//! applied during the first pass, when the relation is recorded with
+//! [`crate::state::State::add_formatted_identifier`]; here we just render the
+//! definition as `format X like Y;`.
+
+use nom::{
+    branch::alt,
+    combinator::{map, opt},
+    sequence::tuple,
+};
+#[cfg(feature = "serde")]
+use serde::Serialize;
 use std::borrow::Cow;
 
 use crate::prettify::{Prettifier, RenderInline};
@@ -12,12 +20,14 @@ use crate::prettify::{Prettifier, RenderInline};
 use super::{base::*, WebToplevel};
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct WebFormat<'a> {
     /// The LHS of the format: an identifier.
     lhs: StringSpan<'a>,
 
-    /// The RHS: a reserved word
-    rhs: PascalReservedWord,
+    /// The RHS, as written: a reserved word, or an identifier whose
+    /// formatting the LHS borrows.
+    rhs: PascalToken<'a>,
 
     /// Optional trailing comment.
     comment: Option<WebComment<'a>>,
@@ -28,7 +38,10 @@ pub fn parse_format<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebToplevel<'a
         reserved_word(PascalReservedWord::Format),
         identifier_or_formatted_or_reserved,
         pascal_token(PascalToken::Equivalence),
-        alt((any_reserved_word, true_identifier_workaround)),
+        alt((
+            map(any_reserved_word, PascalToken::ReservedWord),
+            format_rhs_identifier,
+        )),
         opt(comment),
     ))(input)?;
 
@@ -36,7 +49,7 @@ pub fn parse_format<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebToplevel<'a
         input,
         WebToplevel::Format(WebFormat {
             lhs: items.1,
-            rhs: items.3.value,
+            rhs: items.3,
             comment: items.4,
         }),
     ))
@@ -45,68 +58,59 @@ pub fn parse_format<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebToplevel<'a
 fn identifier_or_formatted_or_reserved<'a>(
     input: ParseInput<'a>,
 ) -> ParseResult<'a, StringSpan<'a>> {
-    let (input, wt) = next_token(input)?;
+    let (rest, wt) = next_token(input)?;
 
     if let WebToken::Pascal(PascalToken::Identifier(s)) = wt {
-        Ok((input, s))
+        Ok((rest, s))
     } else if let WebToken::Pascal(PascalToken::FormattedIdentifier(s, _)) = wt {
-        Ok((input, s))
+        Ok((rest, s))
     } else if let WebToken::Pascal(PascalToken::ReservedWord(sv)) = wt {
         let ss = StringSpan {
             value: Cow::Owned(sv.value.to_string()),
             start: sv.start,
             end: sv.end,
         };
-        Ok((input, ss))
+        Ok((rest, ss))
     } else {
-        return new_parse_err(input, WebErrorKind::ExpectedIdentifier);
+        new_parse_err(input, WebErrorKind::ExpectedIdentifier)
     }
 }
 
-/// (Xe)TeX "formats" `type` as `true` to defuse its special-ness. But `true`
-/// isn't a reserved word. Since our support for "format" is hacky anyway, hack
-/// some more by mapping this to `Define`, which is PascalReservedWord enum
-/// variant that we've added relative to WEB.
-///
-/// (Xe)TeX also formats `mtype` as `type`, which is parsed as an identifier
-/// due to the above. So in that case we have to hack in the other direction.
-fn true_identifier_workaround<'a>(
-    input: ParseInput<'a>,
-) -> ParseResult<'a, SpanValue<'a, PascalReservedWord>> {
-    let (input, wt) = next_token(input)?;
-
-    if let WebToken::Pascal(PascalToken::Identifier(s)) = wt {
-        if s.value == "true" {
-            let rv = SpanValue {
-                value: PascalReservedWord::Define,
-                start: s.start,
-                end: s.end,
-            };
-
-            return Ok((input, rv));
-        } else if s.value == "type" {
-            let rv = SpanValue {
-                value: PascalReservedWord::Type,
-                start: s.start,
-                end: s.end,
-            };
-
-            return Ok((input, rv));
-        }
+/// The RHS of a format can also be an identifier, possibly formatted itself.
+/// (Xe)TeX "formats" `type` as `true` to defuse its special-ness, and also
+/// formats `mtype` as `type`, which is lexed as an identifier due to the
+/// former.
+fn format_rhs_identifier<'a>(input: ParseInput<'a>) -> ParseResult<'a, PascalToken<'a>> {
+    let (rest, wt) = next_token(input)?;
+
+    if let WebToken::Pascal(
+        tok @ (PascalToken::Identifier(_) | PascalToken::FormattedIdentifier(..)),
+    ) = wt
+    {
+        return Ok((rest, tok));
     }
 
-    new_parse_err(input, WebErrorKind::Eof)
+    new_parse_err(input, WebErrorKind::ExpectedIdentifier)
 }
 
 // Prettification
 
 impl<'a> WebFormat<'a> {
     pub fn prettify(&self, dest: &mut Prettifier) {
-        dest.keyword("@format");
+        dest.keyword("format");
+        dest.space();
+        dest.identifier(&self.lhs);
         dest.space();
-        dest.noscope_push(self.lhs.value.as_ref());
-        dest.noscope_push(" ~ ");
-        dest.noscope_push(self.rhs);
+        dest.keyword("like");
+        dest.space();
+
+        match &self.rhs {
+            PascalToken::Identifier(s) | PascalToken::FormattedIdentifier(s, _) => {
+                dest.identifier(s)
+            }
+            tok => tok.render_inline(dest),
+        }
+
         dest.noscope_push(";");
 
         if let Some(c) = self.comment.as_ref() {
@@ -115,3 +119,16 @@ impl<'a> WebFormat<'a> {
         }
     }
 }
+
+// Walking
+
+impl<'a> WebFormat<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        visitor.visit_identifier(&self.lhs);
+        visitor.visit_token(&self.rhs);
+        if let Some(c) = &self.comment {
+            visitor.visit_comment(c);
+        }
+    }
+}
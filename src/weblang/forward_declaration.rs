@@ -5,12 +5,15 @@ use nom::{
     combinator::{map, opt},
     sequence::tuple,
 };
+#[cfg(feature = "serde")]
+use serde::Serialize;
 
 use crate::prettify::{Prettifier, RenderInline};
 
 use super::{base::*, WebToplevel};
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct WebForwardDeclaration<'a> {
     /// The name(s) of the function or procedure.
     name: StringSpan<'a>,
@@ -68,3 +71,15 @@ impl<'a> WebForwardDeclaration<'a> {
         dest.noscope_push("();");
     }
 }
+
+// Walking
+
+impl<'a> WebForwardDeclaration<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        visitor.visit_defined_identifier(&self.name);
+        if let Some(c) = &self.comment {
+            visitor.visit_comment(c);
+        }
+    }
+}
@@ -6,14 +6,20 @@
 use nom::{
     branch::alt,
     combinator::{map, opt},
-    multi::{many1, separated_list0},
+    multi::{many0, many1, separated_list0},
     sequence::tuple,
 };
+#[cfg(feature = "serde")]
+use serde::Serialize;
 
-use crate::prettify::{self, Prettifier, RenderInline};
+use crate::{
+    prettify::{self, Prettifier, RenderInline, TexInsert, COMMENT_SCOPE},
+    xref::FunctionUses,
+};
 
 use super::{
     base::*,
+    const_declaration::parse_constant_value,
     expr::{parse_expr, WebExpr},
     module_reference::parse_module_reference,
     statement::{parse_statement_base, WebStatement},
@@ -25,6 +31,7 @@ use super::{
 ///
 /// For simplicity, we just call them both "functions".
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct WebFunctionDefinition<'a> {
     /// The name of the function.
     name: StringSpan<'a>,
@@ -48,16 +55,58 @@ pub struct WebFunctionDefinition<'a> {
     /// Records in the function's `var` block.
     vars: Vec<WebVarBlockItem<'a>>,
 
+    /// Procedures and functions nested inside this one, as in METAFONT.
+    functions: Vec<WebFunctionDefinition<'a>>,
+
     /// The statement(s) that comprise the function — almost always a block.
-    stmt: WebStatement<'a>,
+    stmt: Spanned<WebStatement<'a>>,
 
     /// The comment associated with end of the function.
     closing_comment: Option<WebComment<'a>>,
 }
 
+impl<'a> WebFunctionDefinition<'a> {
+    /// The name of the procedure or function.
+    pub fn name(&self) -> &StringSpan<'a> {
+        &self.name
+    }
+
+    /// Whether this is a function, with a return value, rather than a
+    /// procedure.
+    pub fn is_function(&self) -> bool {
+        self.return_type.is_some()
+    }
+
+    /// The groups of arguments taken by the procedure or function.
+    pub fn args(&self) -> &[WebVariables<'a>] {
+        &self.args
+    }
+
+    /// The labels declared by the procedure or function.
+    pub fn labels(&self) -> &[WebLabel<'a>] {
+        &self.labels
+    }
+
+    /// The constants declared by the procedure or function.
+    pub fn consts(&self) -> &[WebConstant<'a>] {
+        &self.consts
+    }
+
+    /// The items of the procedure or function's `var` block.
+    pub fn vars(&self) -> &[WebVarBlockItem<'a>] {
+        &self.vars
+    }
+
+    /// The procedures and functions defined inside this one.
+    pub fn functions(&self) -> &[WebFunctionDefinition<'a>] {
+        &self.functions
+    }
+}
+
 // The `var` block
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct WebVariables<'a> {
     /// Whether a function argument is marked with the `var` keyword. This may
     /// be more properly per-name, but this is sufficient for our use case.
@@ -71,6 +120,7 @@ pub struct WebVariables<'a> {
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub enum WebVarBlockItem<'a> {
     /// A reference to a module that (hopefully) contains variable definitions.
     ModuleReference(WebModuleReference<'a>),
@@ -83,6 +133,7 @@ pub enum WebVarBlockItem<'a> {
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct WebInPlaceVariables<'a> {
     vars: WebVariables<'a>,
     comment: Option<WebComment<'a>>,
@@ -93,7 +144,7 @@ fn parse_var_block_item<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebVarBloc
         map(parse_module_reference, |t| {
             WebVarBlockItem::ModuleReference(t)
         }),
-        map(parse_in_place_vars, |v| WebVarBlockItem::InPlace(v)),
+        map(parse_in_place_vars, WebVarBlockItem::InPlace),
         map(
             tuple((
                 formatted_identifier_like(PascalReservedWord::Begin),
@@ -146,22 +197,36 @@ fn parse_argument_group<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebVariabl
 /// XeTeX(2022.0):1084, since WEB preprocesses basic arithmetic on numerical
 /// constants.
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct WebLabel<'a> {
-    name: Box<WebExpr<'a>>,
+    name: &'a Spanned<WebExpr<'a>>,
     comment: Option<WebComment<'a>>,
 }
 
-/// This is a little tricky since the comments are optional and *after* the
-/// separator between items.
+impl<'a> WebLabel<'a> {
+    /// The label itself.
+    pub fn name(&self) -> &Spanned<WebExpr<'a>> {
+        self.name
+    }
+}
+
 fn parse_label_section<'a>(input: ParseInput<'a>) -> ParseResult<'a, Vec<WebLabel<'a>>> {
-    let (mut input, _) = reserved_word(PascalReservedWord::Label)(input)?;
+    let (input, _) = reserved_word(PascalReservedWord::Label)(input)?;
+    parse_label_list(input)
+}
 
+/// Parse the labels following a `label` keyword, through the terminating
+/// semicolon.
+///
+/// This is a little tricky since the comments are optional and *after* the
+/// separator between items.
+pub fn parse_label_list<'a>(mut input: ParseInput<'a>) -> ParseResult<'a, Vec<WebLabel<'a>>> {
     let mut items = Vec::new();
 
     loop {
         let item;
         (input, item) = tuple((
-            map(parse_expr, Box::new),
+            map(parse_expr, |e| input.arena().alloc_expr(e)),
             alt((
                 pascal_token(PascalToken::Comma),
                 pascal_token(PascalToken::Semicolon),
@@ -183,34 +248,52 @@ fn parse_label_section<'a>(input: ParseInput<'a>) -> ParseResult<'a, Vec<WebLabe
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct WebConstant<'a> {
     name: StringSpan<'a>,
+    sign: Option<PascalToken<'a>>,
     value: PascalToken<'a>,
 }
 
+impl<'a> WebConstant<'a> {
+    /// The name of the constant.
+    pub fn name(&self) -> &StringSpan<'a> {
+        &self.name
+    }
+}
+
 fn parse_const_item<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebConstant<'a>> {
     map(
         tuple((
             identifier,
             pascal_token(PascalToken::Equals),
-            int_literal,
+            parse_constant_value,
             pascal_token(PascalToken::Semicolon),
         )),
         |t| WebConstant {
             name: t.0,
-            value: t.2,
+            sign: t.2 .0,
+            value: t.2 .1,
         },
     )(input)
 }
 
 impl<'a> RenderInline for WebConstant<'a> {
     fn measure_inline(&self) -> usize {
-        self.name.len() + 4 + self.value.measure_inline()
+        self.name.len()
+            + 4
+            + self.sign.as_ref().map(|s| s.measure_inline()).unwrap_or(0)
+            + self.value.measure_inline()
     }
 
     fn render_inline(&self, dest: &mut Prettifier) {
-        dest.noscope_push(&self.name);
+        dest.identifier(&self.name);
         dest.noscope_push(" = ");
+
+        if let Some(sign) = self.sign.as_ref() {
+            sign.render_inline(dest);
+        }
+
         self.value.render_inline(dest);
         dest.noscope_push(';');
     }
@@ -220,6 +303,12 @@ impl<'a> RenderInline for WebConstant<'a> {
 
 pub fn parse_function_definition_base<'a>(
     input: ParseInput<'a>,
+) -> ParseResult<'a, WebFunctionDefinition<'a>> {
+    nested(input, parse_function_definition_unnested)
+}
+
+fn parse_function_definition_unnested<'a>(
+    input: ParseInput<'a>,
 ) -> ParseResult<'a, WebFunctionDefinition<'a>> {
     let (input, items) = tuple((
         alt((
@@ -244,6 +333,7 @@ pub fn parse_function_definition_base<'a>(
             reserved_word(PascalReservedWord::Var),
             many1(parse_var_block_item),
         ))),
+        many0(parse_function_definition_base),
         alt((
             parse_statement_base,
             // XeTex(2022.0):638 has a procedure definition with an outer
@@ -268,8 +358,9 @@ pub fn parse_function_definition_base<'a>(
     let labels = items.6.unwrap_or_default();
     let consts = items.7.map(|t| t.1).unwrap_or_default();
     let vars = items.8.map(|t| t.1).unwrap_or_default();
-    let stmt = items.9;
-    let closing_comment = items.10;
+    let functions = items.9;
+    let stmt = items.10;
+    let closing_comment = items.11;
 
     Ok((
         input,
@@ -281,6 +372,7 @@ pub fn parse_function_definition_base<'a>(
             labels,
             consts,
             vars,
+            functions,
             stmt,
             closing_comment,
         },
@@ -297,6 +389,8 @@ pub fn parse_function_definition<'a>(input: ParseInput<'a>) -> ParseResult<'a, W
 
 impl<'a> WebFunctionDefinition<'a> {
     pub fn prettify(&self, dest: &mut Prettifier) {
+        let outer = dest.enter_scope(&self.name.value);
+
         // Opening comment
 
         if let Some(c) = self.opening_comment.as_ref() {
@@ -307,47 +401,67 @@ impl<'a> WebFunctionDefinition<'a> {
         // Prototype: name, args, retval
 
         let wname = self.name.value.as_ref().len();
-        let wargs: usize =
-            self.args.iter().map(|a| a.measure_inline()).sum::<usize>() + 2 * (self.args.len() - 1);
+        let wargs: usize = self.args.iter().map(|a| a.measure_inline()).sum::<usize>()
+            + 2 * (self.args.len().saturating_sub(1));
         let wret = self
             .return_type
             .as_ref()
             .map(|r| r.measure_inline() + 2) // type + ": "
             .unwrap_or(0);
 
-        if dest.fits(wname + wargs + wret + 13) {
-            // Single-line prototype: "function () {"
-            dest.keyword("function");
-            dest.space();
-            dest.noscope_push(self.name.value.as_ref());
+        // In Pascal, the arguments are separated by semicolons, and a
+        // procedure without any has no parentheses.
+        let pascal = dest.style().preserve_keywords;
+        let (keyword, sep) = match (pascal, self.return_type.is_some()) {
+            (false, _) => ("function", ','),
+            (true, false) => ("procedure", ';'),
+            (true, true) => ("function", ';'),
+        };
+        let parens = !pascal || !self.args.is_empty();
+        let one_line = dest.fits(wname + wargs + wret + 13);
+
+        dest.keyword(keyword);
+        dest.space();
+        dest.identifier(&self.name);
+
+        if parens {
             dest.noscope_push('(');
-            prettify::render_inline_seq(&self.args, ", ", dest);
+        }
+
+        if one_line {
+            // Single-line prototype: "function () {"
+            prettify::render_inline_seq(&self.args, if pascal { "; " } else { ", " }, dest);
         } else {
-            // Multi-line function prototype
-            dest.keyword("function");
-            dest.space();
-            dest.noscope_push(self.name.value.as_ref());
-            dest.noscope_push('(');
+            // Multi-line function prototype: break after the name, with one
+            // argument group per line, and the return type after the closing
+            // paren.
             dest.indent_small();
             dest.newline_needed();
 
-            for arg in &self.args {
-                arg.render_inline(dest);
-                dest.noscope_push(',');
+            for (i, arg) in self.args.iter().enumerate() {
+                let last = i + 1 == self.args.len();
+                arg.render_flex(if last { 0 } else { 1 }, dest);
+
+                if !last {
+                    dest.noscope_push(sep);
+                }
+
                 dest.newline_needed();
             }
 
             dest.dedent_small();
         }
 
-        dest.noscope_push(')');
+        if parens {
+            dest.noscope_push(')');
+        }
 
         if let Some(r) = self.return_type.as_ref() {
             dest.noscope_push(": ");
             r.render_inline(dest);
         }
 
-        dest.noscope_push(" {");
+        dest.noscope_push(if pascal { ";" } else { " {" });
         dest.newline_needed();
         dest.indent_block();
 
@@ -409,23 +523,7 @@ impl<'a> WebFunctionDefinition<'a> {
 
                     dest.keyword("label");
                     dest.indent_block();
-
-                    let i_last = self.labels.len() - 1;
-
-                    for (i, label) in self.labels.iter().enumerate() {
-                        dest.newline_needed();
-
-                        let sep = if i == i_last { ';' } else { ',' };
-
-                        label.name.render_inline(dest);
-                        dest.noscope_push(sep);
-
-                        if let Some(c) = label.comment.as_ref() {
-                            dest.space();
-                            c.render_inline(dest);
-                        }
-                    }
-
+                    render_labels_vertically(&self.labels, dest);
                     dest.dedent_block();
                 }
             }
@@ -444,16 +542,16 @@ impl<'a> WebFunctionDefinition<'a> {
                 dest.space();
                 self.consts[0].render_inline(dest);
             } else {
-                // Multi-line form
+                // Multi-line form, laid out like the labels.
                 dest.keyword("const");
-                dest.indent_small();
+                dest.indent_block();
 
                 for c in &self.consts {
                     dest.newline_needed();
                     c.render_inline(dest);
                 }
 
-                dest.dedent_small();
+                dest.dedent_block();
             }
 
             dest.newline_needed();
@@ -469,37 +567,58 @@ impl<'a> WebFunctionDefinition<'a> {
                 // "var ;"
                 dest.keyword("var");
                 dest.space();
-                prettify::render_inline_seq(&self.vars, ", ", dest);
+                prettify::render_inline_seq(&self.vars, if pascal { "; " } else { ", " }, dest);
                 dest.noscope_push(";");
             } else {
-                // Multi-line var declarations
+                // Multi-line var declarations, with module references on
+                // their own lines like everything else.
                 dest.keyword("var");
-                dest.indent_small();
+                dest.indent_block();
                 dest.newline_needed();
 
                 let n_last = self.vars.len() - 1;
 
                 for (i, v) in self.vars.iter().enumerate() {
-                    let term = if i == n_last { ';' } else { ',' };
+                    let term = if i == n_last || pascal { ';' } else { ',' };
 
                     v.prettify(dest, term);
                     dest.newline_needed();
                 }
 
-                dest.dedent_small();
+                dest.dedent_block();
             }
 
             dest.newline_needed();
         }
 
-        // Statements
+        // Nested functions
 
-        if !self.labels.is_empty() || !self.vars.is_empty() {
+        for f in &self.functions {
             dest.newline_indent();
             dest.newline_needed();
+            f.prettify(dest);
+        }
+
+        // Statements, set off from any local declarations by a blank line
+
+        if !self.labels.is_empty()
+            || !self.consts.is_empty()
+            || !self.vars.is_empty()
+            || !self.functions.is_empty()
+        {
+            dest.newline_indent();
+            dest.newline_needed();
+        }
+
+        // In Pascal, the body is the procedure's own `begin`/`end` block.
+        if pascal {
+            dest.dedent_block();
+            self.stmt.render_flex(dest);
+            dest.noscope_push(';');
+        } else {
+            self.stmt.render_in_block(dest);
         }
 
-        self.stmt.render_in_block(dest);
         dest.newline_needed();
 
         // Closing comment
@@ -511,13 +630,140 @@ impl<'a> WebFunctionDefinition<'a> {
 
         // Done
 
+        if !pascal {
+            dest.newline_needed();
+            dest.dedent_block();
+            dest.noscope_push("}");
+            dest.newline_needed();
+        }
+
+        dest.leave_scope(outer);
+
+        // Where the function is used, if that's been worked out
+
+        if let Some(uses) = dest.function_uses(&self.name.value).cloned() {
+            render_function_uses(&uses, dest);
+            dest.newline_needed();
+        }
+    }
+}
+
+/// Render the annotation saying where a function is defined and used, as a
+/// comment with links to the sections, wrapping it if it gets too long.
+fn render_function_uses(uses: &FunctionUses, dest: &mut Prettifier) {
+    dest.with_scope(*COMMENT_SCOPE, |d| {
+        d.noscope_push("// defined in ");
+        render_section_link(uses.defined, d);
+
+        let n_last = uses.used.len().saturating_sub(1);
+
+        for (i, m) in uses.used.iter().enumerate() {
+            d.noscope_push(if i == 0 { "; used in" } else { "," });
+
+            // "§" is one character.
+            let w = m.to_string().len() + 2 + (i != n_last) as usize;
+
+            if d.fits(w) {
+                d.space();
+            } else {
+                d.newline_needed();
+                d.noscope_push("//   ");
+            }
+
+            render_section_link(*m, d);
+        }
+    });
+}
+
+impl<'a> WebVariables<'a> {
+    /// Render a group of variables, wrapping the names if the group doesn't
+    /// fit on one line. `trailing` is the width of whatever must follow the
+    /// group on the same line.
+    fn render_flex(&self, trailing: usize, dest: &mut Prettifier) {
+        if dest.fits(self.measure_inline() + trailing) {
+            self.render_inline(dest);
+            return;
+        }
+
+        if self.is_var {
+            dest.keyword("var");
+            dest.space();
+        }
+
+        let wty = self.ty.measure_inline();
+        let mut indented = false;
+
+        for (i, n) in self.names.iter().enumerate() {
+            // The last name needs room for the type, and the others for a
+            // comma.
+            let wn = n.value.len()
+                + if i + 1 == self.names.len() {
+                    2 + wty + trailing
+                } else {
+                    1
+                };
+
+            if i > 0 {
+                dest.noscope_push(',');
+
+                if dest.fits(wn + 1) {
+                    dest.space();
+                } else {
+                    if !indented {
+                        indented = dest.indent_small();
+                    }
+
+                    dest.newline_indent();
+                }
+            }
+
+            dest.identifier(n);
+        }
+
+        dest.noscope_push(": ");
+        self.ty.render_inline(dest);
+
+        if indented {
+            dest.dedent_small();
+        }
+    }
+}
+
+/// Render a list of labels one per line, each followed by its comment.
+fn render_labels_vertically(labels: &[WebLabel], dest: &mut Prettifier) {
+    let i_last = labels.len().saturating_sub(1);
+
+    for (i, label) in labels.iter().enumerate() {
         dest.newline_needed();
-        dest.dedent_block();
-        dest.noscope_push("}");
-        dest.newline_needed();
+
+        let sep = if i == i_last { ';' } else { ',' };
+
+        label.name.render_inline(dest);
+        dest.noscope_push(sep);
+
+        if let Some(c) = label.comment.as_ref() {
+            dest.space();
+            c.render_inline(dest);
+        }
     }
 }
 
+/// Render a list of labels without the `label` keyword, on one line if
+/// possible.
+pub fn render_label_list(labels: &Vec<WebLabel>, dest: &mut Prettifier) {
+    if dest.fits(labels.measure_inline()) {
+        labels.render_inline(dest);
+    } else {
+        render_labels_vertically(labels, dest);
+    }
+}
+
+fn render_section_link(module: ModuleId, dest: &mut Prettifier) {
+    dest.insert(TexInsert::StartModuleReference(module), true);
+    dest.noscope_push(format!("§{}", module));
+    dest.insert(TexInsert::EndMacro, false);
+}
+
 impl<'a> RenderInline for WebVariables<'a> {
     fn measure_inline(&self) -> usize {
         let mut w = 0;
@@ -530,7 +776,7 @@ impl<'a> RenderInline for WebVariables<'a> {
             w += n.value.as_ref().len();
         }
 
-        w += 2 * (self.names.len() - 1); // ", " between names
+        w += 2 * (self.names.len().saturating_sub(1)); // ", " between names
         w += 2; // ": "
         w += self.ty.measure_inline();
         w
@@ -551,7 +797,7 @@ impl<'a> RenderInline for WebVariables<'a> {
                 dest.noscope_push(", ");
             }
 
-            dest.noscope_push(n.value.as_ref());
+            dest.identifier(n);
         }
 
         dest.noscope_push(": ");
@@ -676,3 +922,97 @@ impl<'a> RenderInline for Vec<WebLabel<'a>> {
         }
     }
 }
+
+// Walking
+
+impl<'a> WebFunctionDefinition<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        visitor.visit_defined_identifier(&self.name);
+
+        for arg in &self.args {
+            arg.walk(visitor);
+        }
+
+        if let Some(ty) = &self.return_type {
+            visitor.visit_type(ty);
+        }
+
+        if let Some(c) = &self.opening_comment {
+            visitor.visit_comment(c);
+        }
+
+        for label in &self.labels {
+            label.walk(visitor);
+        }
+
+        for constant in &self.consts {
+            visitor.visit_defined_identifier(&constant.name);
+            if let Some(sign) = &constant.sign {
+                visitor.visit_token(sign);
+            }
+            visitor.visit_token(&constant.value);
+        }
+
+        for item in &self.vars {
+            item.walk(visitor);
+        }
+
+        for f in &self.functions {
+            visitor.visit_function_definition(f);
+        }
+
+        visitor.visit_statement(&self.stmt);
+        if let Some(c) = &self.closing_comment {
+            visitor.visit_comment(c);
+        }
+    }
+}
+
+impl<'a> WebLabel<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        visitor.visit_expr(self.name);
+
+        if let Some(c) = &self.comment {
+            visitor.visit_comment(c);
+        }
+    }
+}
+
+impl<'a> WebVariables<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        for name in &self.names {
+            visitor.visit_defined_identifier(name);
+        }
+
+        visitor.visit_type(&self.ty);
+    }
+}
+
+impl<'a> WebVarBlockItem<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        match self {
+            WebVarBlockItem::ModuleReference(mref) => visitor.visit_module_reference(mref),
+            WebVarBlockItem::InPlace(ipv) => ipv.walk(visitor),
+            WebVarBlockItem::IfdefInPlace(begin, ipv, end) => {
+                visitor.visit_token(begin);
+                ipv.walk(visitor);
+                visitor.visit_token(end);
+            }
+        }
+    }
+}
+
+impl<'a> WebInPlaceVariables<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        self.vars.walk(visitor);
+
+        if let Some(c) = &self.comment {
+            visitor.visit_comment(c);
+        }
+    }
+}
@@ -2,17 +2,30 @@
 //!
 //! WEB programs use `@d` definitions to give labels symbolic names.
 
-use nom::{combinator::opt, sequence::tuple};
+use nom::{branch::alt, combinator::opt, multi::separated_list1, sequence::tuple};
+#[cfg(feature = "serde")]
+use serde::Serialize;
 
 use crate::prettify::{Prettifier, RenderInline};
 
-use super::{base::*, WebToplevel};
+use super::{
+    base::*,
+    module_reference::{parse_module_reference, WebModuleReference},
+    WebToplevel,
+};
 
 /// A label declaration.
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct WebLabelDeclaration<'a> {
-    /// The label name.
-    name: StringSpan<'a>,
+    /// The labels: identifiers, as in WEB programs, which give labels names
+    /// with `@d`, or numbers, as in standard Pascal. There can be several, as
+    /// in the outer block of BibTeX.
+    names: Vec<Spanned<PascalToken<'a>>>,
+
+    /// A module listing more labels, as in BibTeX's
+    /// `label close_up_shop,exit_program @<Labels in the outer block@>;`.
+    more: Option<WebModuleReference<'a>>,
 
     /// An optional associated comment.
     comment: Option<WebComment<'a>>,
@@ -21,7 +34,11 @@ pub struct WebLabelDeclaration<'a> {
 pub fn parse_label_declaration<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebToplevel<'a>> {
     let (input, items) = tuple((
         reserved_word(PascalReservedWord::Label),
-        identifier,
+        separated_list1(
+            pascal_token(PascalToken::Comma),
+            spanned(alt((identifier_as_token, int_literal))),
+        ),
+        opt(parse_module_reference),
         pascal_token(PascalToken::Semicolon),
         opt(comment),
     ))(input)?;
@@ -29,25 +46,61 @@ pub fn parse_label_declaration<'a>(input: ParseInput<'a>) -> ParseResult<'a, Web
     Ok((
         input,
         WebToplevel::LabelDeclaration(WebLabelDeclaration {
-            name: items.1,
-            comment: items.3,
+            names: items.1,
+            more: items.2,
+            comment: items.4,
         }),
     ))
 }
 
 impl<'a> WebLabelDeclaration<'a> {
+    /// The labels.
+    pub fn names(&self) -> &[Spanned<PascalToken<'a>>] {
+        &self.names
+    }
+
+    /// The module listing more labels, if any.
+    pub fn more(&self) -> Option<&WebModuleReference<'a>> {
+        self.more.as_ref()
+    }
+
+    fn render_names(&self, dest: &mut Prettifier) {
+        for (i, name) in self.names.iter().enumerate() {
+            if i > 0 {
+                dest.noscope_push(", ");
+            }
+
+            name.node.render_inline(dest);
+        }
+
+        if let Some(mr) = self.more.as_ref() {
+            dest.space();
+            mr.render_inline(dest);
+        }
+    }
+
     pub fn prettify(&self, dest: &mut Prettifier) {
         let clen = self
             .comment
             .as_ref()
             .map(|c| c.measure_inline())
             .unwrap_or(0);
-        let slen = self.name.value.len() + 7;
+        let slen = self
+            .names
+            .iter()
+            .map(|n| n.node.measure_inline() + 2)
+            .sum::<usize>()
+            + self
+                .more
+                .as_ref()
+                .map(|mr| mr.measure_inline() + 1)
+                .unwrap_or(0)
+            + 5;
 
         if dest.fits(clen + slen + 1) {
             dest.keyword("label");
             dest.space();
-            dest.noscope_push(self.name.value.as_ref());
+            self.render_names(dest);
             dest.noscope_push(';');
 
             if let Some(c) = self.comment.as_ref() {
@@ -64,9 +117,31 @@ impl<'a> WebLabelDeclaration<'a> {
 
             dest.keyword("label");
             dest.space();
-            dest.noscope_push(self.name.value.as_ref());
+            self.render_names(dest);
             dest.noscope_push(';');
             dest.newline_needed();
         }
     }
 }
+
+// Walking
+
+impl<'a> WebLabelDeclaration<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        for name in &self.names {
+            match &name.node {
+                PascalToken::Identifier(s) => visitor.visit_defined_identifier(s),
+                other => visitor.visit_label(other),
+            }
+        }
+
+        if let Some(mr) = &self.more {
+            visitor.visit_module_reference(mr);
+        }
+
+        if let Some(c) = &self.comment {
+            visitor.visit_comment(c);
+        }
+    }
+}
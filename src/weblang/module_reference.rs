@@ -1,11 +1,15 @@
 //! A reference to a WEB module
 
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
 use crate::prettify::{Prettifier, RenderInline};
 
 use super::base::*;
 
 /// A reference to a WEB module.
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct WebModuleReference<'a> {
     pub name: StringSpan<'a>,
     pub id: ModuleId,
@@ -15,12 +19,12 @@ pub struct WebModuleReference<'a> {
 pub fn parse_module_reference<'a>(
     input: ParseInput<'a>,
 ) -> ParseResult<'a, WebModuleReference<'a>> {
-    let (input, wt) = next_token(input)?;
+    let (rest, wt) = next_token(input)?;
 
     if let WebToken::ModuleReference(mr) = wt {
-        Ok((input, mr))
+        Ok((rest, mr))
     } else {
-        return new_parse_err(input, WebErrorKind::ExpectedIdentifier);
+        new_parse_err(input, WebErrorKind::ExpectedIdentifier)
     }
 }
 
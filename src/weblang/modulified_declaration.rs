@@ -1,6 +1,8 @@
 //! TODO: replace this with code used for var blocks etc!
 
 use nom::sequence::tuple;
+#[cfg(feature = "serde")]
+use serde::Serialize;
 
 use crate::prettify::{Prettifier, RenderInline};
 
@@ -8,6 +10,7 @@ use super::{base::*, module_reference::parse_module_reference, WebToplevel};
 
 /// A group of declarations done by referencing a module.
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct WebModulifiedDeclaration<'a> {
     /// The kind of declaration
     kind: PascalReservedWord,
@@ -19,7 +22,7 @@ pub struct WebModulifiedDeclaration<'a> {
 /// `(const|type|var) <module-ref>`
 pub fn parse_modulified_declaration<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebToplevel<'a>> {
     fn declaration_keyword<'a>(input: ParseInput<'a>) -> ParseResult<'a, PascalReservedWord> {
-        let (input, wt) = next_token(input)?;
+        let (rest, wt) = next_token(input)?;
 
         let rw = if let WebToken::Pascal(PascalToken::ReservedWord(sv)) = wt {
             sv.value
@@ -31,7 +34,7 @@ pub fn parse_modulified_declaration<'a>(input: ParseInput<'a>) -> ParseResult<'a
 
         match rw {
             PascalReservedWord::Const | PascalReservedWord::Type | PascalReservedWord::Var => {
-                Ok((input, rw))
+                Ok((rest, rw))
             }
 
             _ => new_parse_err(input, WebErrorKind::ExpectedPascalToken),
@@ -59,3 +62,12 @@ impl<'a> WebModulifiedDeclaration<'a> {
         dest.newline_needed();
     }
 }
+
+// Walking
+
+impl<'a> WebModulifiedDeclaration<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        visitor.visit_module_reference(&self.module);
+    }
+}
@@ -1,15 +1,22 @@
 //! Pascal preprocessor directives.
 //!
 //! These are special constructs that would matter if we were actually compiling
-//! this Pascal code. We just implement them because we have to.
+//! this Pascal code. We just implement them because we have to. We do, however,
+//! try to recognize conditionals like `$ifdef(...)` and `$endif(...)` so that
+//! the code between them can be indented, and so that their pairing can be
+//! modeled as a [`DirectiveTree`].
 
 use nom::{combinator::opt, multi::many0, sequence::tuple};
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use std::ops::Range;
 
-use crate::prettify::{Prettifier, RenderInline};
+use crate::prettify::{self, Prettifier, RenderInline};
 
 use super::base::*;
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct WebPreprocessorDirective<'a> {
     /// The tokens comprising this directive
     tokens: Vec<PascalToken<'a>>,
@@ -39,32 +46,244 @@ pub fn parse_preprocessor_directive_base<'a>(
 }
 
 fn any_pascal_except_close_meta<'a>(input: ParseInput<'a>) -> ParseResult<'a, PascalToken<'a>> {
-    let (input, wt) = next_token(input)?;
+    let (rest, wt) = next_token(input)?;
 
     if let WebToken::Pascal(ptok) = wt {
         if let PascalToken::CloseDelimiter(DelimiterKind::MetaComment) = ptok {
         } else {
-            return Ok((input, ptok));
+            return Ok((rest, ptok));
         }
     }
 
-    return new_parse_err(input, WebErrorKind::ExpectedPascalToken);
+    new_parse_err(input, WebErrorKind::ExpectedPascalToken)
+}
+
+/// How a directive affects the nesting of the code around it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DirectiveNesting {
+    /// The directive doesn't affect nesting.
+    None,
+
+    /// The directive opens a conditional, like `$ifdef`.
+    Open,
+
+    /// The directive separates branches of a conditional, like `$else`.
+    Middle,
+
+    /// The directive closes a conditional, like `$endif`.
+    Close,
 }
 
 impl<'a> WebPreprocessorDirective<'a> {
+    /// The tokens of the directive proper, without the delimiters.
+    fn body(&self) -> &[PascalToken<'a>] {
+        // The first two tokens are `@{@&` and the last is `@}`.
+        &self.tokens[2..self.tokens.len() - 1]
+    }
+
+    /// Determine how this directive affects nesting, based on the first word
+    /// that it contains.
+    pub fn nesting(&self) -> DirectiveNesting {
+        for t in self.body() {
+            match t {
+                PascalToken::Identifier(s) => {
+                    return match s.value.as_ref() {
+                        "ifdef" | "ifndef" => DirectiveNesting::Open,
+                        "elif" | "elifdef" | "elifndef" => DirectiveNesting::Middle,
+                        "endif" => DirectiveNesting::Close,
+                        _ => DirectiveNesting::None,
+                    };
+                }
+
+                PascalToken::ReservedWord(rw) => {
+                    return match rw.value {
+                        PascalReservedWord::If => DirectiveNesting::Open,
+                        PascalReservedWord::Else => DirectiveNesting::Middle,
+                        _ => DirectiveNesting::None,
+                    };
+                }
+
+                _ => {}
+            }
+        }
+
+        DirectiveNesting::None
+    }
+
     pub fn prettify(&self, dest: &mut Prettifier) {
         if let Some(c) = self.comment.as_ref() {
             c.render_inline(dest);
             dest.newline_needed();
         }
 
-        dest.noscope_push("pascal_preprocessor!(");
+        dest.with_scope(*prettify::PREPROCESSOR_SCOPE, |d| {
+            for t in self.body() {
+                t.render_inline(d);
+            }
+        });
+
+        dest.newline_needed();
+    }
+}
 
-        for t in &self.tokens {
-            t.render_inline(dest);
+/// Track the nesting of preprocessor conditionals in a sequence of statements,
+/// so that the statements that they enclose can be indented.
+///
+/// Call `before` and `after` around the rendering of each item in the
+/// sequence, and `finish` at its end. The tracker only undoes the indentation
+/// that it has applied, so that unbalanced conditionals, which are common since
+/// a conditional can span multiple modules, don't mess up the surrounding code.
+#[derive(Debug, Default)]
+pub struct NestingTracker {
+    /// For each open conditional, whether we were able to indent for it.
+    indents: Vec<bool>,
+}
+
+impl NestingTracker {
+    pub fn before(&mut self, pd: Option<&WebPreprocessorDirective>, dest: &mut Prettifier) {
+        let nesting = pd.map(|pd| pd.nesting()).unwrap_or(DirectiveNesting::None);
+
+        if let DirectiveNesting::Middle | DirectiveNesting::Close = nesting {
+            if let Some(indented) = self.indents.pop() {
+                if indented {
+                    dest.dedent_block();
+                }
+            }
         }
+    }
 
-        dest.noscope_push(");");
-        dest.newline_needed();
+    pub fn after(&mut self, pd: Option<&WebPreprocessorDirective>, dest: &mut Prettifier) {
+        let nesting = pd.map(|pd| pd.nesting()).unwrap_or(DirectiveNesting::None);
+
+        if let DirectiveNesting::Open | DirectiveNesting::Middle = nesting {
+            self.indents.push(dest.indent_block());
+        }
+    }
+
+    pub fn finish(self, dest: &mut Prettifier) {
+        for indented in self.indents {
+            if indented {
+                dest.dedent_block();
+            }
+        }
+    }
+}
+
+/// Where a directive appears.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DirectiveSite {
+    /// The module in which it appears.
+    pub module: ModuleId,
+
+    /// The bytes of the source text that it occupies.
+    pub range: Range<usize>,
+}
+
+/// A preprocessor conditional: an opening directive, any directives that
+/// separate its branches, and the closing directive, along with the
+/// conditionals nested inside it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DirectiveNode {
+    /// The directive that opens the conditional.
+    pub open: DirectiveSite,
+
+    /// The directives that separate the branches, like `$else`.
+    pub middles: Vec<DirectiveSite>,
+
+    /// The directive that closes the conditional, if there is one.
+    pub close: Option<DirectiveSite>,
+
+    /// The conditionals nested inside this one.
+    pub children: Vec<DirectiveNode>,
+}
+
+/// The nesting of the preprocessor conditionals in a program.
+///
+/// Conditionals often span multiple modules, so the tree is built up by
+/// [`Self::add`]ing directives one at a time, in the order in which they appear
+/// in the source text. Directives that close or continue a conditional that
+/// was never opened are set aside as strays.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct DirectiveTree {
+    /// The outermost conditionals.
+    pub roots: Vec<DirectiveNode>,
+
+    /// The directives that don't match any opening directive.
+    pub strays: Vec<DirectiveSite>,
+
+    /// The conditionals that are currently open, innermost last.
+    open: Vec<DirectiveNode>,
+}
+
+impl DirectiveTree {
+    /// Add a directive, with the given effect on nesting.
+    pub fn add(&mut self, nesting: DirectiveNesting, site: DirectiveSite) {
+        match nesting {
+            DirectiveNesting::None => {}
+
+            DirectiveNesting::Open => self.open.push(DirectiveNode {
+                open: site,
+                middles: Vec::new(),
+                close: None,
+                children: Vec::new(),
+            }),
+
+            DirectiveNesting::Middle => match self.open.last_mut() {
+                Some(node) => node.middles.push(site),
+                None => self.strays.push(site),
+            },
+
+            DirectiveNesting::Close => match self.open.pop() {
+                Some(mut node) => {
+                    node.close = Some(site);
+                    self.attach(node);
+                }
+                None => self.strays.push(site),
+            },
+        }
+    }
+
+    /// Put a completed conditional into its parent, or make it a root.
+    fn attach(&mut self, node: DirectiveNode) {
+        match self.open.last_mut() {
+            Some(parent) => parent.children.push(node),
+            None => self.roots.push(node),
+        }
+    }
+
+    /// Finish building the tree, leaving any conditionals that are still open
+    /// in it without closing directives.
+    pub fn finish(mut self) -> Self {
+        while let Some(node) = self.open.pop() {
+            self.attach(node);
+        }
+
+        self
+    }
+
+    /// Iterate over all of the conditionals, outermost first.
+    pub fn iter(&self) -> impl Iterator<Item = &DirectiveNode> {
+        let mut todo: Vec<_> = self.roots.iter().rev().collect();
+
+        std::iter::from_fn(move || {
+            let node = todo.pop()?;
+            todo.extend(node.children.iter().rev());
+            Some(node)
+        })
+    }
+}
+
+// Walking
+
+impl<'a> WebPreprocessorDirective<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        for tok in &self.tokens {
+            visitor.visit_token(tok);
+        }
+
+        if let Some(c) = &self.comment {
+            visitor.visit_comment(c);
+        }
     }
 }
@@ -6,15 +6,26 @@
 //! WEB implementations always split the program across the entire source file,
 //! so the program definition is always incomplete.
 //!
+//! The arguments are the files that the program uses to talk to the outside
+//! world, like `input` and `output`. Programs that open all of their files
+//! themselves, like TeX, may leave out the parenthesized list entirely.
+//!
 //! TODO: reuse some of the block stuff built for function definitions.
 
-use nom::{multi::separated_list0, sequence::tuple};
+use nom::{
+    combinator::opt,
+    multi::separated_list0,
+    sequence::{delimited, tuple},
+};
+#[cfg(feature = "serde")]
+use serde::Serialize;
 
-use crate::prettify::Prettifier;
+use crate::prettify::{self, Prettifier};
 
 use super::{base::*, WebToplevel};
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct WebProgramDefinition<'a> {
     name: StringSpan<'a>,
     args: Vec<StringSpan<'a>>,
@@ -24,9 +35,11 @@ pub fn parse_program_definition<'a>(input: ParseInput<'a>) -> ParseResult<'a, We
     let (input, items) = tuple((
         reserved_word(PascalReservedWord::Program),
         identifier,
-        open_delimiter(DelimiterKind::Paren),
-        separated_list0(pascal_token(PascalToken::Comma), identifier),
-        close_delimiter(DelimiterKind::Paren),
+        opt(delimited(
+            open_delimiter(DelimiterKind::Paren),
+            separated_list0(pascal_token(PascalToken::Comma), identifier),
+            close_delimiter(DelimiterKind::Paren),
+        )),
         pascal_token(PascalToken::Semicolon),
     ))(input)?;
 
@@ -34,7 +47,7 @@ pub fn parse_program_definition<'a>(input: ParseInput<'a>) -> ParseResult<'a, We
         input,
         WebToplevel::ProgramDefinition(WebProgramDefinition {
             name: items.1,
-            args: items.3,
+            args: items.2.unwrap_or_default(),
         }),
     ))
 }
@@ -43,25 +56,74 @@ impl<'a> WebProgramDefinition<'a> {
     pub fn prettify(&self, dest: &mut Prettifier) {
         dest.keyword("program");
         dest.space();
-        dest.noscope_push(self.name.value.as_ref());
-        dest.noscope_push('(');
+        dest.identifier(&self.name);
 
-        let mut first = true;
+        if !self.args.is_empty() {
+            let wargs: usize =
+                self.args.iter().map(|a| a.value.len()).sum::<usize>() + 2 * (self.args.len() - 1);
 
-        for arg in &self.args {
-            if first {
-                first = false;
+            // 3 = len("():")
+            if dest.fits(wargs + 3) {
+                dest.noscope_push('(');
+
+                for (i, arg) in self.args.iter().enumerate() {
+                    if i > 0 {
+                        dest.noscope_push(", ");
+                    }
+
+                    self.render_arg(arg, dest);
+                }
             } else {
-                dest.noscope_push(", ");
+                // Fill the lines with as many files as will fit.
+                dest.noscope_push('(');
+                dest.indent_small();
+                dest.newline_indent();
+
+                for (i, arg) in self.args.iter().enumerate() {
+                    // 2 = len(" ,")
+                    if i > 0 && !dest.fits(arg.value.len() + 2) {
+                        dest.newline_indent();
+                    } else if i > 0 {
+                        dest.space();
+                    }
+
+                    self.render_arg(arg, dest);
+
+                    if i + 1 < self.args.len() {
+                        dest.noscope_push(',');
+                    }
+                }
+
+                dest.dedent_small();
+                dest.newline_needed();
             }
 
-            dest.noscope_push(arg.value.as_ref());
+            dest.noscope_push(')');
         }
 
-        dest.noscope_push("):");
+        dest.noscope_push(':');
 
         // special! Imbalanced indent!
         dest.indent_block();
         dest.newline_needed();
     }
+
+    /// Render one of the program's file arguments, which are styled distinctly
+    /// from ordinary identifiers.
+    fn render_arg(&self, arg: &StringSpan<'a>, dest: &mut Prettifier) {
+        dest.with_scope(*prettify::FILE_PARAMETER_SCOPE, |d| d.identifier(arg));
+    }
+}
+
+// Walking
+
+impl<'a> WebProgramDefinition<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        visitor.visit_defined_identifier(&self.name);
+
+        for arg in &self.args {
+            visitor.visit_identifier(arg);
+        }
+    }
 }
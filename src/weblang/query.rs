@@ -0,0 +1,58 @@
+//! Finding identifiers in WEB code.
+
+use super::{base::*, WebCode};
+
+/// A visitor that gathers up the occurrences of an identifier.
+struct IdentifierFinder<'n, 'a> {
+    /// The identifier to look for.
+    name: &'n str,
+
+    /// Whether to gather the places where the identifier is defined, rather
+    /// than the places where it's used.
+    definitions: bool,
+
+    found: Vec<StringSpan<'a>>,
+}
+
+impl<'n, 'a> Visitor<'a> for IdentifierFinder<'n, 'a> {
+    fn visit_identifier(&mut self, ident: &StringSpan<'a>) {
+        if !self.definitions && ident.value == self.name {
+            self.found.push(ident.clone());
+        }
+    }
+
+    fn visit_defined_identifier(&mut self, ident: &StringSpan<'a>) {
+        if self.definitions && ident.value == self.name {
+            self.found.push(ident.clone());
+        }
+    }
+}
+
+impl<'a> WebCode<'a> {
+    fn find_identifier(&self, name: &str, definitions: bool) -> Vec<StringSpan<'a>> {
+        let mut finder = IdentifierFinder {
+            name,
+            definitions,
+            found: Vec::new(),
+        };
+        finder.visit_code(self);
+        finder.found
+    }
+
+    /// Find the places where an identifier is defined: where it's declared as
+    /// a constant, type, variable, label, procedure, or function, including
+    /// procedure arguments and record fields, or where it's defined as a macro
+    /// with `@d`.
+    ///
+    /// Identifiers are matched exactly. Occurrences in comments aren't
+    /// included.
+    pub fn find_definitions(&self, name: &str) -> Vec<StringSpan<'a>> {
+        self.find_identifier(name, true)
+    }
+
+    /// Find the places where an identifier is used, as opposed to where it's
+    /// defined. See [`Self::find_definitions`].
+    pub fn find_uses(&self, name: &str) -> Vec<StringSpan<'a>> {
+        self.find_identifier(name, false)
+    }
+}
@@ -3,52 +3,110 @@
 //! Allowed tokens are reserved words; formatted identifiers, which act as
 //! reserved words; and "verbatim Pascal" (XeTeX(2022.0):135). These shouldn't
 //! come up in actual code, but are needed for inline TeX discussion of the
-//! code.
+//! code. A run of them, possibly separated by commas, as in `|packed, file|`,
+//! is captured as one standalone so that it can be rendered on one line.
 
-use nom::{branch::alt, combinator::map};
+use nom::{
+    branch::alt,
+    combinator::{map, opt},
+    multi::many0,
+    sequence::tuple,
+};
+#[cfg(feature = "serde")]
+use serde::Serialize;
 
 use crate::prettify::{Prettifier, RenderInline};
 
 use super::{base::*, WebToplevel};
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct WebStandalone<'a> {
-    /// The token.
-    token: PascalToken<'a>,
+    /// The tokens, each with a flag indicating whether a comma preceded it.
+    tokens: Vec<(bool, PascalToken<'a>)>,
 }
 
-pub fn parse_standalone_base<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebStandalone<'a>> {
+fn parse_standalone_token<'a>(input: ParseInput<'a>) -> ParseResult<'a, PascalToken<'a>> {
     alt((
-        map(any_reserved_word, |s| WebStandalone {
-            token: PascalToken::ReservedWord(s),
-        }),
+        map(any_reserved_word, PascalToken::ReservedWord),
         any_formatted_identifier,
-        map(verbatim_pascal, |token| WebStandalone { token }),
+        verbatim_pascal,
     ))(input)
 }
 
+pub fn parse_standalone_base<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebStandalone<'a>> {
+    map(
+        tuple((
+            parse_standalone_token,
+            many0(tuple((
+                map(opt(pascal_token(PascalToken::Comma)), |c| c.is_some()),
+                parse_standalone_token,
+            ))),
+        )),
+        |(first, rest)| {
+            let mut tokens = vec![(false, first)];
+            tokens.extend(rest);
+            WebStandalone { tokens }
+        },
+    )(input)
+}
+
 pub fn parse_standalone<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebToplevel<'a>> {
-    map(parse_standalone_base, |s| WebToplevel::Standalone(s))(input)
+    map(parse_standalone_base, WebToplevel::Standalone)(input)
 }
 
-fn any_formatted_identifier<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebStandalone<'a>> {
-    let (input, wt) = next_token(input)?;
+fn any_formatted_identifier<'a>(input: ParseInput<'a>) -> ParseResult<'a, PascalToken<'a>> {
+    let (rest, wt) = next_token(input)?;
 
     if let WebToken::Pascal(token) = wt {
         if let PascalToken::FormattedIdentifier(..) = token {
-            return Ok((input, WebStandalone { token }));
+            return Ok((rest, token));
         }
     }
 
-    return new_parse_err(input, WebErrorKind::ExpectedIdentifier);
+    new_parse_err(input, WebErrorKind::ExpectedIdentifier)
 }
 
 impl<'a> RenderInline for WebStandalone<'a> {
     fn measure_inline(&self) -> usize {
-        self.token.measure_inline()
+        // Each separator is either " " or ", ".
+        self.tokens
+            .iter()
+            .enumerate()
+            .map(|(i, (comma, t))| {
+                let sep = match (i, comma) {
+                    (0, _) => 0,
+                    (_, true) => 2,
+                    (_, false) => 1,
+                };
+
+                sep + t.measure_inline()
+            })
+            .sum()
     }
 
     fn render_inline(&self, dest: &mut Prettifier) {
-        self.token.render_inline(dest);
+        for (i, (comma, t)) in self.tokens.iter().enumerate() {
+            if i > 0 {
+                if *comma {
+                    dest.noscope_push(',');
+                }
+
+                dest.space();
+            }
+
+            t.render_inline(dest);
+        }
+    }
+}
+
+// Walking
+
+impl<'a> WebStandalone<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        for (_, t) in &self.tokens {
+            visitor.visit_token(t);
+        }
     }
 }
@@ -2,22 +2,26 @@
 
 use nom::{
     branch::alt,
-    combinator::{map, opt},
+    combinator::{cut, map, opt, peek},
     multi::{many0, many1, separated_list1},
     sequence::tuple,
 };
-use std::{borrow::Cow, ops::Deref};
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use std::borrow::Cow;
 
-use crate::prettify::{self, Prettifier, RenderInline};
+use crate::prettify::{self, Prettifier, RenderInline, SemicolonPolicy};
 
 use super::{
     base::*,
     expr::{parse_case_match_expr, parse_expr, parse_lhs_expr, WebExpr},
     module_reference::parse_module_reference,
-    preprocessor_directive, WebToplevel,
+    preprocessor_directive::{self, NestingTracker, WebPreprocessorDirective},
+    WebToplevel,
 };
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub enum WebStatement<'a> {
     /// A reference to a module.
     ModuleReference(WebModuleReference<'a>, Option<WebComment<'a>>),
@@ -58,15 +62,27 @@ pub enum WebStatement<'a> {
     /// A freestanding comment.
     Comment(WebComment<'a>),
 
-    /// A statement that's just an expression.
-    Expr(WebExpr<'a>, Option<WebComment<'a>>),
+    /// A statement that's just an expression, with an optional comment, and
+    /// whether it's followed by a semicolon.
+    Expr(Spanned<WebExpr<'a>>, Option<WebComment<'a>>, bool),
+
+    /// A use of a statement-like macro.
+    MacroCall(WebMacroCall<'a>),
 
     /// A free-floating case statement, needed for WEAVE#88.
     SpecialFreeCase(SpecialFreeCase<'a>),
 }
 
-pub fn parse_statement_base<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebStatement<'a>> {
-    alt((
+pub fn parse_statement_base<'a>(
+    input: ParseInput<'a>,
+) -> ParseResult<'a, Spanned<WebStatement<'a>>> {
+    nested(input, parse_statement_unnested)
+}
+
+fn parse_statement_unnested<'a>(
+    input: ParseInput<'a>,
+) -> ParseResult<'a, Spanned<WebStatement<'a>>> {
+    spanned(alt((
         parse_mod_ref_statement,
         parse_block,
         map(
@@ -84,8 +100,9 @@ pub fn parse_statement_base<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebSta
         parse_loop,
         parse_special_free_case,
         map(comment, WebStatement::Comment),
+        parse_macro_call,
         parse_expr_statement,
-    ))(input)
+    )))(input)
 }
 
 pub fn parse_statement<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebToplevel<'a>> {
@@ -112,11 +129,66 @@ fn parse_expr_statement<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebStateme
             opt(pascal_token(PascalToken::Semicolon)),
             opt(comment),
         )),
-        |t| WebStatement::Expr(t.0, t.2),
+        |t| WebStatement::Expr(t.0, t.2, t.1.is_some()),
     )(input)
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct WebMacroCall<'a> {
+    /// The macro, along with any arguments.
+    call: Spanned<WebExpr<'a>>,
+
+    /// Whether the use runs straight into the next statement, without a
+    /// semicolon. Macros whose expansions end with a semicolon, or with
+    /// something like a `do`, are used this way.
+    unterminated: bool,
+
+    /// Whether the use is followed by a semicolon.
+    semicolon: bool,
+
+    /// Optional trailing comment.
+    comment: Option<WebComment<'a>>,
+}
+
+impl<'a> WebMacroCall<'a> {
+    /// The macro, along with any arguments.
+    pub fn call(&self) -> &Spanned<WebExpr<'a>> {
+        &self.call
+    }
+}
+
+/// A statement-like macro is a statement in its own right, so it ends the
+/// statement even if no semicolon follows, and whatever comes next isn't
+/// treated as a continuation of an expression.
+fn parse_macro_call<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebStatement<'a>> {
+    let (_, name) = identifier(input)?;
+
+    if input.macro_kind(&name.value) != Some(MacroKind::Statement) {
+        return new_parse_err(input, WebErrorKind::ExpectedStatementMacro);
+    }
+
+    let (rest, (call, semicolon, comment)) = tuple((
+        parse_lhs_expr,
+        opt(pascal_token(PascalToken::Semicolon)),
+        opt(comment),
+    ))(input)?;
+
+    let unterminated = semicolon.is_none() && peek(parse_statement_base)(rest).is_ok();
+
+    Ok((
+        rest,
+        WebStatement::MacroCall(WebMacroCall {
+            call,
+            unterminated,
+            semicolon: semicolon.is_some(),
+            comment,
+        }),
+    ))
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct WebBlock<'a> {
     /// The token that opens the block.
     opener: PascalToken<'a>,
@@ -125,7 +197,7 @@ pub struct WebBlock<'a> {
     pre_comment: Option<WebComment<'a>>,
 
     /// Inner statements.
-    stmts: Vec<Box<WebStatement<'a>>>,
+    stmts: Vec<&'a Spanned<WebStatement<'a>>>,
 
     /// The token that closes the block.
     closer: PascalToken<'a>,
@@ -141,7 +213,9 @@ fn parse_block<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebStatement<'a>> {
         block_opener,
         opt(pascal_token(PascalToken::Semicolon)),
         opt(comment),
-        many0(map(parse_statement_base, |s| Box::new(s))),
+        many0(map(parse_statement_base, |s| {
+            input.arena().alloc_statement(s)
+        })),
         block_closer,
         opt(pascal_token(PascalToken::Semicolon)),
         opt(pascal_token(PascalToken::Period)), // for the very end of program
@@ -166,10 +240,17 @@ fn parse_block<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebStatement<'a>> {
     ))
 }
 
+impl<'a> WebBlock<'a> {
+    /// The statements in the block.
+    pub fn stmts(&self) -> impl Iterator<Item = &Spanned<WebStatement<'a>>> {
+        self.stmts.iter().copied()
+    }
+}
+
 /// Match a token that opens a block: either `begin`, or a formatted identifier
 /// that behaves like it.
 fn block_opener<'a>(input: ParseInput<'a>) -> ParseResult<'a, PascalToken<'a>> {
-    let (input, wt) = next_token(input)?;
+    let (rest, wt) = next_token(input)?;
 
     if let WebToken::Pascal(ptok) = wt {
         if let PascalToken::ReservedWord(SpanValue {
@@ -177,19 +258,19 @@ fn block_opener<'a>(input: ParseInput<'a>) -> ParseResult<'a, PascalToken<'a>> {
             ..
         }) = ptok
         {
-            return Ok((input, ptok));
+            return Ok((rest, ptok));
         } else if let PascalToken::FormattedIdentifier(_, PascalReservedWord::Begin) = ptok {
-            return Ok((input, ptok));
+            return Ok((rest, ptok));
         }
     }
 
-    return new_parse_err(input, WebErrorKind::Eof);
+    new_parse_err(input, WebErrorKind::ExpectedBlockOpener)
 }
 
 /// Match a token that closes a block: either `end`, or a formatted identifier
 /// that behaves like it.
 fn block_closer<'a>(input: ParseInput<'a>) -> ParseResult<'a, PascalToken<'a>> {
-    let (input, wt) = next_token(input)?;
+    let (rest, wt) = next_token(input)?;
 
     if let WebToken::Pascal(ptok) = wt {
         if let PascalToken::ReservedWord(SpanValue {
@@ -197,42 +278,47 @@ fn block_closer<'a>(input: ParseInput<'a>) -> ParseResult<'a, PascalToken<'a>> {
             ..
         }) = ptok
         {
-            return Ok((input, ptok));
+            return Ok((rest, ptok));
         } else if let PascalToken::FormattedIdentifier(_, PascalReservedWord::End) = ptok {
-            return Ok((input, ptok));
+            return Ok((rest, ptok));
         }
     }
 
-    return new_parse_err(input, WebErrorKind::Eof);
+    new_parse_err(input, WebErrorKind::ExpectedBlockCloser)
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct WebAssignment<'a> {
     /// The left-hand side.
-    lhs: Box<WebExpr<'a>>,
+    lhs: &'a Spanned<WebExpr<'a>>,
 
     /// The right-hand side.
-    rhs: Box<WebExpr<'a>>,
+    rhs: &'a Spanned<WebExpr<'a>>,
 
     /// Optional comment.
     comment: Option<WebComment<'a>>,
 
     /// Optional second comment.
     second_comment: Option<WebComment<'a>>,
+
+    /// Whether the assignment is followed by a semicolon.
+    semicolon: bool,
 }
 
 fn parse_assignment<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebStatement<'a>> {
     let (input, items) = tuple((
         parse_lhs_expr,
         pascal_token(PascalToken::Gets),
-        parse_expr,
+        cut(parse_expr),
         opt(pascal_token(PascalToken::Semicolon)),
         opt(comment),
         opt(comment),
     ))(input)?;
 
-    let lhs = Box::new(items.0);
-    let rhs = Box::new(items.2);
+    let lhs = input.arena().alloc_expr(items.0);
+    let rhs = input.arena().alloc_expr(items.2);
+    let semicolon = items.3.is_some();
     let comment = items.4;
     let second_comment = items.5;
 
@@ -243,17 +329,43 @@ fn parse_assignment<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebStatement<'
             rhs,
             comment,
             second_comment,
+            semicolon,
         }),
     ))
 }
 
+impl<'a> WebAssignment<'a> {
+    /// The expression that is assigned to.
+    pub fn lhs(&self) -> &Spanned<WebExpr<'a>> {
+        self.lhs
+    }
+
+    /// The expression whose value is assigned.
+    pub fn rhs(&self) -> &Spanned<WebExpr<'a>> {
+        self.rhs
+    }
+}
+
+/// The operator written between the two sides of an assignment.
+fn assignment_operator(dest: &Prettifier) -> &'static str {
+    if dest.style().preserve_operators {
+        " := "
+    } else {
+        " = "
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct WebGoto<'a> {
     /// The label.
     label: PascalToken<'a>,
 
     /// Optional comment.
     comment: Option<WebComment<'a>>,
+
+    /// Whether the statement is followed by a semicolon.
+    semicolon: bool,
 }
 
 fn parse_goto<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebStatement<'a>> {
@@ -265,28 +377,44 @@ fn parse_goto<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebStatement<'a>> {
     ))(input)?;
 
     let label = items.1;
+    let semicolon = items.2.is_some();
     let comment = items.3;
 
-    Ok((input, WebStatement::Goto(WebGoto { label, comment })))
+    Ok((
+        input,
+        WebStatement::Goto(WebGoto {
+            label,
+            comment,
+            semicolon,
+        }),
+    ))
+}
+
+impl<'a> WebGoto<'a> {
+    /// The label that is jumped to.
+    pub fn label(&self) -> &PascalToken<'a> {
+        &self.label
+    }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct WebIf<'a> {
     /// Optional comment before the `if`
     opening_comment: Option<WebComment<'a>>,
 
     /// The test expression
-    test: Box<WebExpr<'a>>,
+    test: &'a Spanned<WebExpr<'a>>,
 
     /// Optional comment after the test
     test_comment: Option<WebComment<'a>>,
 
     /// The `then` statement, which may be a block.
-    then: Box<WebStatement<'a>>,
+    then: &'a Spanned<WebStatement<'a>>,
 
     /// The optional `else` statement, which may be a block, or may be another
     /// `if` statement.
-    else_: Option<Box<WebStatement<'a>>>,
+    else_: Option<&'a Spanned<WebStatement<'a>>>,
 
     /// Optional comment associated with the start of the else block.
     else_head_comment: Option<WebComment<'a>>,
@@ -312,12 +440,12 @@ fn parse_if<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebStatement<'a>> {
     ))(input)?;
 
     let opening_comment = items.0;
-    let test = Box::new(items.2);
+    let test = input.arena().alloc_expr(items.2);
     let test_comment = items.4;
-    let then = Box::new(items.5);
+    let then = input.arena().alloc_statement(items.5);
     let (else_head_comment, else_) = items
         .6
-        .map(|t| (t.1, Some(Box::new(t.2))))
+        .map(|t| (t.1, Some(input.arena().alloc_statement(t.2))))
         .unwrap_or((None, None));
     let else_tail_comment = items.7;
 
@@ -336,29 +464,30 @@ fn parse_if<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebStatement<'a>> {
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct WebWhile<'a> {
     /// The loop test expression
-    test: Box<WebExpr<'a>>,
+    test: &'a Spanned<WebExpr<'a>>,
 
     /// Optional comment after the test
     test_comment: Option<WebComment<'a>>,
 
     /// The `do` statement, which may be a block.
-    do_: Box<WebStatement<'a>>,
+    do_: &'a Spanned<WebStatement<'a>>,
 }
 
 fn parse_while<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebStatement<'a>> {
     let (input, items) = tuple((
         reserved_word(PascalReservedWord::While),
-        parse_expr,
-        reserved_word(PascalReservedWord::Do),
+        cut(parse_expr),
+        cut(reserved_word(PascalReservedWord::Do)),
         opt(comment),
         parse_statement_base,
     ))(input)?;
 
-    let test = Box::new(items.1);
+    let test = input.arena().alloc_expr(items.1);
     let test_comment = items.3;
-    let do_ = Box::new(items.4);
+    let do_ = input.arena().alloc_statement(items.4);
 
     Ok((
         input,
@@ -371,45 +500,46 @@ fn parse_while<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebStatement<'a>> {
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct WebFor<'a> {
     /// The loop variable
     var: StringSpan<'a>,
 
     /// The start expression.
-    start: Box<WebExpr<'a>>,
+    start: &'a Spanned<WebExpr<'a>>,
 
     /// Whether this is a "downto" (decreasing) loop, rather than increasing.
     is_down: bool,
 
     /// The end expression.
-    end: Box<WebExpr<'a>>,
+    end: &'a Spanned<WebExpr<'a>>,
 
     /// An optional comment for the loop top.
     top_comment: Option<WebComment<'a>>,
 
     /// The `do` statement, which may be a block.
-    do_: Box<WebStatement<'a>>,
+    do_: &'a Spanned<WebStatement<'a>>,
 }
 
 fn parse_for<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebStatement<'a>> {
     let (input, items) = tuple((
         reserved_word(PascalReservedWord::For),
-        identifier,
-        pascal_token(PascalToken::Gets),
-        parse_expr,
-        parse_for_direction_word,
-        parse_expr,
-        reserved_word(PascalReservedWord::Do),
+        cut(identifier),
+        cut(pascal_token(PascalToken::Gets)),
+        cut(parse_expr),
+        cut(parse_for_direction_word),
+        cut(parse_expr),
+        cut(reserved_word(PascalReservedWord::Do)),
         opt(comment),
         parse_statement_base,
     ))(input)?;
 
     let var = items.1;
-    let start = Box::new(items.3);
+    let start = input.arena().alloc_expr(items.3);
     let is_down = items.4;
-    let end = Box::new(items.5);
+    let end = input.arena().alloc_expr(items.5);
     let top_comment = items.7;
-    let do_ = Box::new(items.8);
+    let do_ = input.arena().alloc_statement(items.8);
 
     Ok((
         input,
@@ -432,24 +562,30 @@ fn parse_for_direction_word<'a>(input: ParseInput<'a>) -> ParseResult<'a, bool>
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct WebRepeat<'a> {
     /// The loop test expression
-    test: Box<WebExpr<'a>>,
+    test: &'a Spanned<WebExpr<'a>>,
 
     /// The statements comprising the loop. Unlike most other compound
     /// statements, these come in a sequence without being encased in a
     /// begin/end block.
-    stmts: Vec<Box<WebStatement<'a>>>,
+    stmts: Vec<&'a Spanned<WebStatement<'a>>>,
 
     /// Optional comment at end of loop.
     closing_comment: Option<WebComment<'a>>,
+
+    /// Whether the loop is followed by a semicolon.
+    semicolon: bool,
 }
 
 fn parse_repeat<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebStatement<'a>> {
     map(
         tuple((
             reserved_word(PascalReservedWord::Repeat),
-            many1(map(parse_statement_base, |s| Box::new(s))),
+            many1(map(parse_statement_base, |s| {
+                input.arena().alloc_statement(s)
+            })),
             reserved_word(PascalReservedWord::Until),
             parse_expr,
             opt(pascal_token(PascalToken::Semicolon)),
@@ -457,40 +593,52 @@ fn parse_repeat<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebStatement<'a>>
         )),
         |t| {
             WebStatement::Repeat(WebRepeat {
-                test: Box::new(t.3),
+                test: input.arena().alloc_expr(t.3),
                 stmts: t.1,
                 closing_comment: t.5,
+                semicolon: t.4.is_some(),
             })
         },
     )(input)
 }
 
+impl<'a> WebRepeat<'a> {
+    /// The statements in the body of the loop.
+    pub fn stmts(&self) -> impl Iterator<Item = &Spanned<WebStatement<'a>>> {
+        self.stmts.iter().copied()
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct WebLoop<'a> {
     /// The identifier used in the loop definition
     keyword: StringSpan<'a>,
 
     /// The `do` statement, which may be a block.
-    do_: Box<WebStatement<'a>>,
+    do_: &'a Spanned<WebStatement<'a>>,
 }
 
 fn parse_loop<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebStatement<'a>> {
     map(tuple((loop_like_identifier, parse_statement_base)), |t| {
         WebStatement::Loop(WebLoop {
             keyword: t.0,
-            do_: Box::new(t.1),
+            do_: input.arena().alloc_statement(t.1),
         })
     })(input)
 }
 
 pub fn loop_like_identifier<'a>(input: ParseInput<'a>) -> ParseResult<'a, StringSpan<'a>> {
-    let (input, wt) = next_token(input)?;
+    let (rest, wt) = next_token(input)?;
 
     if let WebToken::Pascal(PascalToken::FormattedIdentifier(ss, PascalReservedWord::Xclause)) = wt
     {
-        Ok((input, ss))
+        Ok((rest, ss))
     } else {
-        new_parse_err(input, WebErrorKind::Eof)
+        new_parse_err(
+            input,
+            WebErrorKind::ExpectedFormattedIdentifier(PascalReservedWord::Xclause),
+        )
     }
 }
 
@@ -505,9 +653,10 @@ fn parse_label<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebStatement<'a>> {
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct WebCase<'a> {
     /// The input to the case statement.
-    var: Box<WebExpr<'a>>,
+    var: &'a Spanned<WebExpr<'a>>,
 
     /// Items within the case statement.
     items: Vec<WebCaseItem<'a>>,
@@ -517,6 +666,7 @@ pub struct WebCase<'a> {
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub enum WebCaseItem<'a> {
     ModuleReference(WebModuleReference<'a>),
     Standard(WebStandardCaseItem<'a>),
@@ -532,36 +682,39 @@ pub enum WebCaseItem<'a> {
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct WebStandardCaseItem<'a> {
     /// The matched cases. These may be identifiers, string literals,
     /// integer literals, or WEB macros that look like function calls.
-    matches: Vec<Box<WebExpr<'a>>>,
+    matches: Vec<&'a Spanned<WebExpr<'a>>>,
 
     /// The associated statement.
-    stmt: Box<WebStatement<'a>>,
+    stmt: &'a Spanned<WebStatement<'a>>,
 
     /// Optional comment.
     comment: Option<WebComment<'a>>,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct WebModMatchCaseItem<'a> {
     match_: WebModuleReference<'a>,
 
     /// The associated statement.
-    stmt: Box<WebStatement<'a>>,
+    stmt: &'a Spanned<WebStatement<'a>>,
 
     /// Optional comment.
     comment: Option<WebComment<'a>>,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct WebOtherCasesItem<'a> {
     /// The formatted identifier used to tag this item.
     tag: StringSpan<'a>,
 
     /// The associated statement.
-    stmt: Box<WebStatement<'a>>,
+    stmt: &'a Spanned<WebStatement<'a>>,
 
     /// Optional comment.
     comment: Option<WebComment<'a>>,
@@ -571,7 +724,7 @@ fn parse_case<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebStatement<'a>> {
     map(
         tuple((
             reserved_word(PascalReservedWord::Case),
-            map(parse_expr, Box::new),
+            map(parse_expr, |e| input.arena().alloc_expr(e)),
             reserved_word(PascalReservedWord::Of),
             many1(alt((
                 parse_mod_match_case_item,
@@ -598,10 +751,10 @@ fn parse_case<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebStatement<'a>> {
 ///
 /// WEAVE#192 ends the case state with an actual End keyword.
 fn parse_case_terminator<'a>(input: ParseInput<'a>) -> ParseResult<'a, StringSpan<'a>> {
-    let (input, wt) = next_token(input)?;
+    let (rest, wt) = next_token(input)?;
 
     if let WebToken::Pascal(PascalToken::FormattedIdentifier(ss, PascalReservedWord::End)) = wt {
-        Ok((input, ss))
+        Ok((rest, ss))
     } else if let WebToken::Pascal(PascalToken::ReservedWord(SpanValue {
         value: PascalReservedWord::End,
         start,
@@ -613,9 +766,9 @@ fn parse_case_terminator<'a>(input: ParseInput<'a>) -> ParseResult<'a, StringSpa
             end,
             value: Cow::Owned("end".to_owned()),
         };
-        Ok((input, ss))
+        Ok((rest, ss))
     } else {
-        new_parse_err(input, WebErrorKind::Eof)
+        new_parse_err(input, WebErrorKind::ExpectedBlockCloser)
     }
 }
 
@@ -640,7 +793,7 @@ fn parse_other_cases_item<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebCaseI
         |t| {
             WebCaseItem::OtherCases(WebOtherCasesItem {
                 tag: t.0,
-                stmt: Box::new(t.1),
+                stmt: input.arena().alloc_statement(t.1),
                 comment: t.3,
             })
         },
@@ -649,12 +802,15 @@ fn parse_other_cases_item<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebCaseI
 
 /// `endcases` is a formatted identifier formatted like `Else`
 fn parse_other_cases_tag<'a>(input: ParseInput<'a>) -> ParseResult<'a, StringSpan<'a>> {
-    let (input, wt) = next_token(input)?;
+    let (rest, wt) = next_token(input)?;
 
     if let WebToken::Pascal(PascalToken::FormattedIdentifier(ss, PascalReservedWord::Else)) = wt {
-        Ok((input, ss))
+        Ok((rest, ss))
     } else {
-        new_parse_err(input, WebErrorKind::Eof)
+        new_parse_err(
+            input,
+            WebErrorKind::ExpectedFormattedIdentifier(PascalReservedWord::Else),
+        )
     }
 }
 
@@ -666,7 +822,7 @@ fn parse_standard_case_item_base<'a>(
         tuple((
             separated_list1(
                 pascal_token(PascalToken::Comma),
-                map(parse_case_match_expr, Box::new),
+                map(parse_case_match_expr, |e| input.arena().alloc_expr(e)),
             ),
             pascal_token(PascalToken::Colon),
             opt(comment),
@@ -676,7 +832,7 @@ fn parse_standard_case_item_base<'a>(
         )),
         |t| WebStandardCaseItem {
             matches: t.0,
-            stmt: Box::new(t.3),
+            stmt: input.arena().alloc_statement(t.3),
             comment: t.2.or(t.5),
         },
     )(input)
@@ -709,7 +865,7 @@ fn parse_mod_match_case_item<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebCa
         |t| {
             WebCaseItem::ModMatch(WebModMatchCaseItem {
                 match_: t.0,
-                stmt: Box::new(t.2),
+                stmt: input.arena().alloc_statement(t.2),
                 comment: t.4,
             })
         },
@@ -721,12 +877,13 @@ fn parse_mod_match_case_item<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebCa
 /// Note that if the case is an identifier, we can't distinguish between this
 /// and a "goto" label. This happens in WEAVE:188.
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct SpecialFreeCase<'a> {
     /// The matched cases.
-    matches: Vec<Box<WebExpr<'a>>>,
+    matches: Vec<&'a Spanned<WebExpr<'a>>>,
 
     /// The associated statement.
-    stmt: Box<WebStatement<'a>>,
+    stmt: &'a Spanned<WebStatement<'a>>,
 }
 
 fn parse_special_free_case<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebStatement<'a>> {
@@ -734,7 +891,7 @@ fn parse_special_free_case<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebStat
         tuple((
             separated_list1(
                 pascal_token(PascalToken::Comma),
-                map(parse_case_match_expr, Box::new),
+                map(parse_case_match_expr, |e| input.arena().alloc_expr(e)),
             ),
             pascal_token(PascalToken::Colon),
             parse_statement_base,
@@ -743,7 +900,7 @@ fn parse_special_free_case<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebStat
         |t| {
             WebStatement::SpecialFreeCase(SpecialFreeCase {
                 matches: t.0,
-                stmt: Box::new(t.2),
+                stmt: input.arena().alloc_statement(t.2),
             })
         },
     )(input)
@@ -764,7 +921,12 @@ impl<'a> RenderInline for WebStatement<'a> {
             | WebStatement::Loop(_)
             | WebStatement::PreprocessorDirective(_) => prettify::NOT_INLINE,
 
-            WebStatement::Expr(expr, comment) => {
+            WebStatement::Expr(expr, comment, _)
+            | WebStatement::MacroCall(WebMacroCall {
+                call: expr,
+                comment,
+                ..
+            }) => {
                 expr.measure_inline()
                     + comment
                         .as_ref()
@@ -809,6 +971,30 @@ impl<'a> RenderInline for WebStatement<'a> {
     }
 
     fn render_inline(&self, dest: &mut Prettifier) {
+        self.render_inline_code(dest);
+
+        if let Some(c) = self.inline_comment() {
+            dest.space();
+            c.render_inline(dest);
+        }
+    }
+}
+
+impl<'a> WebStatement<'a> {
+    /// Get the comment that follows the statement when it's rendered inline.
+    fn inline_comment(&self) -> Option<&WebComment<'a>> {
+        match self {
+            WebStatement::Expr(_, comment, _)
+            | WebStatement::MacroCall(WebMacroCall { comment, .. })
+            | WebStatement::ModuleReference(_, comment) => comment.as_ref(),
+            WebStatement::Assignment(a) => a.comment.as_ref(),
+            WebStatement::Goto(g) => g.comment.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Render the statement inline, without the comment that follows it.
+    fn render_inline_code(&self, dest: &mut Prettifier) {
         match self {
             WebStatement::Block(_)
             | WebStatement::If(_)
@@ -820,44 +1006,23 @@ impl<'a> RenderInline for WebStatement<'a> {
             | WebStatement::Loop(_)
             | WebStatement::PreprocessorDirective(_) => dest.noscope_push("XXX-stmt-inline"),
 
-            WebStatement::Expr(expr, comment) => {
+            WebStatement::Expr(expr, ..)
+            | WebStatement::MacroCall(WebMacroCall { call: expr, .. }) => {
                 expr.render_inline(dest);
-
-                if let Some(c) = comment {
-                    dest.space();
-                    c.render_inline(dest);
-                }
             }
 
-            WebStatement::ModuleReference(mr, comment) => {
-                mr.render_inline(dest);
-
-                if let Some(c) = comment {
-                    dest.space();
-                    c.render_inline(dest);
-                }
-            }
+            WebStatement::ModuleReference(mr, _) => mr.render_inline(dest),
 
             WebStatement::Assignment(a) => {
                 a.lhs.render_inline(dest);
-                dest.noscope_push(" = ");
+                dest.noscope_push(assignment_operator(dest));
                 a.rhs.render_inline(dest);
-
-                if let Some(c) = a.comment.as_ref() {
-                    dest.space();
-                    c.render_inline(dest);
-                }
             }
 
             WebStatement::Goto(g) => {
                 dest.keyword("goto");
                 dest.space();
                 dest.scope_push(*prettify::LABEL_NAME_SCOPE, &g.label);
-
-                if let Some(c) = g.comment.as_ref() {
-                    dest.space();
-                    c.render_inline(dest);
-                }
             }
 
             WebStatement::Label(l) => {
@@ -884,18 +1049,67 @@ impl<'a> WebStatement<'a> {
             | WebStatement::While(_)
             | WebStatement::For(_)
             | WebStatement::Loop(_)
+            | WebStatement::PreprocessorDirective(_)
             | WebStatement::Comment(_) => false,
 
-            WebStatement::PreprocessorDirective(_)
-            | WebStatement::Expr(..)
+            WebStatement::Expr(..)
             | WebStatement::Assignment(_)
             | WebStatement::Repeat(_)
             | WebStatement::Goto(_) => true,
+
+            WebStatement::MacroCall(mc) => !mc.unterminated,
         }
     }
 
+    pub fn as_preprocessor_directive(&self) -> Option<&WebPreprocessorDirective<'a>> {
+        if let WebStatement::PreprocessorDirective(pd) = self {
+            Some(pd)
+        } else {
+            None
+        }
+    }
+
+    /// Whether the statement is followed by a semicolon in the source.
+    fn has_semicolon(&self) -> bool {
+        match self {
+            WebStatement::Expr(_, _, semicolon) => *semicolon,
+            WebStatement::Assignment(a) => a.semicolon,
+            WebStatement::Repeat(r) => r.semicolon,
+            WebStatement::Goto(g) => g.semicolon,
+            WebStatement::MacroCall(mc) => mc.semicolon,
+            _ => false,
+        }
+    }
+
+    /// Render the statement inline, followed by its semicolon if it gets one,
+    /// as with [`Self::maybe_semicolon_in_block`]. The semicolon goes before
+    /// the statement's comment, if it has one.
+    pub fn render_inline_with_semicolon(&self, is_last: bool, dest: &mut Prettifier) {
+        self.render_inline_code(dest);
+        self.maybe_semicolon_in_block(is_last, dest);
+
+        if let Some(c) = self.inline_comment() {
+            dest.space();
+            c.render_inline(dest);
+        }
+    }
+
+    /// Write a semicolon after the statement, if it gets one according to the
+    /// [`SemicolonPolicy`].
     pub fn maybe_semicolon(&self, dest: &mut Prettifier) {
-        if self.wants_semicolon() {
+        self.maybe_semicolon_in_block(false, dest);
+    }
+
+    /// Write a semicolon after a statement in a `begin`/`end` block, if it
+    /// gets one. If `is_last`, the statement is the last one in the block.
+    pub fn maybe_semicolon_in_block(&self, is_last: bool, dest: &mut Prettifier) {
+        let wanted = match dest.style().semicolons {
+            SemicolonPolicy::Always => self.wants_semicolon(),
+            SemicolonPolicy::AsWritten => self.wants_semicolon() && self.has_semicolon(),
+            SemicolonPolicy::NotBeforeEnd => self.wants_semicolon() && !is_last,
+        };
+
+        if wanted {
             dest.noscope_push(';')
         }
     }
@@ -904,6 +1118,12 @@ impl<'a> WebStatement<'a> {
     /// block structure. All statements are rendered normally except for Blocks
     /// that don't have an unusual guard.
     pub fn render_in_block(&self, dest: &mut Prettifier) {
+        if dest.style().preserve_keywords {
+            self.render_flex(dest);
+            self.maybe_semicolon(dest);
+            return;
+        }
+
         if let WebStatement::Block(block) = self {
             if block.opener.is_reserved_word(PascalReservedWord::Begin) {
                 if let Some(c) = block.pre_comment.as_ref() {
@@ -911,12 +1131,19 @@ impl<'a> WebStatement<'a> {
                     dest.newline_needed();
                 }
 
-                for s in &block.stmts {
+                let mut nesting = NestingTracker::default();
+                let i_last = block.stmts.len().saturating_sub(1);
+
+                for (i, s) in block.stmts.iter().enumerate() {
+                    nesting.before(s.as_preprocessor_directive(), dest);
                     s.render_flex(dest);
-                    s.maybe_semicolon(dest);
+                    s.maybe_semicolon_in_block(i == i_last, dest);
                     dest.newline_needed();
+                    nesting.after(s.as_preprocessor_directive(), dest);
                 }
 
+                nesting.finish(dest);
+
                 if let Some(c) = block.post_comment.as_ref() {
                     c.render_inline(dest);
                     dest.newline_needed();
@@ -931,8 +1158,17 @@ impl<'a> WebStatement<'a> {
     }
 
     pub fn render_flex(&self, dest: &mut Prettifier) {
+        if dest.style().preserve_keywords && self.render_pascal(dest) {
+            return;
+        }
+
         match self {
-            WebStatement::Expr(expr, comment) => {
+            WebStatement::Expr(expr, comment, _)
+            | WebStatement::MacroCall(WebMacroCall {
+                call: expr,
+                comment,
+                ..
+            }) => {
                 if let Some(c) = comment.as_ref() {
                     c.render_inline(dest);
                     dest.newline_needed();
@@ -972,12 +1208,19 @@ impl<'a> WebStatement<'a> {
                     dest.newline_needed();
                 }
 
-                for s in &block.stmts {
+                let mut nesting = NestingTracker::default();
+                let i_last = block.stmts.len().saturating_sub(1);
+
+                for (i, s) in block.stmts.iter().enumerate() {
+                    nesting.before(s.as_preprocessor_directive(), dest);
                     s.render_flex(dest);
-                    s.maybe_semicolon(dest);
+                    s.maybe_semicolon_in_block(i == i_last, dest);
                     dest.newline_needed();
+                    nesting.after(s.as_preprocessor_directive(), dest);
                 }
 
+                nesting.finish(dest);
+
                 if let Some(c) = block.post_comment.as_ref() {
                     c.render_inline(dest);
                     dest.newline_needed();
@@ -999,7 +1242,7 @@ impl<'a> WebStatement<'a> {
                 }
 
                 a.lhs.render_flex(dest);
-                dest.noscope_push(" = ");
+                dest.noscope_push(assignment_operator(dest));
                 a.rhs.render_flex(dest);
             }
 
@@ -1017,7 +1260,7 @@ impl<'a> WebStatement<'a> {
             WebStatement::Label(l) => {
                 let dented = dest.dedent_small();
                 dest.newline_needed();
-                dest.noscope_push(&l);
+                dest.noscope_push(l);
                 dest.noscope_push(':');
 
                 if dented {
@@ -1062,7 +1305,7 @@ impl<'a> WebStatement<'a> {
 
                 if let Some(e) = &i.else_ {
                     // Make `else if` inline for prettiness
-                    if let WebStatement::If(_) = e.deref() {
+                    if let WebStatement::If(_) = &e.node {
                         dest.space();
                         dest.keyword("else");
                         dest.space();
@@ -1149,12 +1392,18 @@ impl<'a> WebStatement<'a> {
                 dest.noscope_push(" {");
                 dest.indent_block();
 
+                let mut nesting = NestingTracker::default();
+
                 for s in &r.stmts {
+                    nesting.before(s.as_preprocessor_directive(), dest);
                     dest.newline_needed();
                     s.render_flex(dest);
                     s.maybe_semicolon(dest);
+                    nesting.after(s.as_preprocessor_directive(), dest);
                 }
 
+                nesting.finish(dest);
+
                 if let Some(c) = r.closing_comment.as_ref() {
                     c.render_inline(dest);
                     dest.newline_needed();
@@ -1208,7 +1457,7 @@ impl<'a> WebStatement<'a> {
                 if dest.fits(wm) {
                     prettify::render_inline_seq(&sfc.matches, ", ", dest);
                 } else {
-                    let i_last = sfc.matches.len() - 1;
+                    let i_last = sfc.matches.len().saturating_sub(1);
 
                     for (i, tok) in sfc.matches.iter().enumerate() {
                         dest.newline_needed();
@@ -1233,6 +1482,222 @@ impl<'a> WebStatement<'a> {
             }
         }
     }
+
+    /// Render the statement with Pascal's keywords rather than braces, if it's
+    /// one whose structure is written differently in Pascal. Returns whether
+    /// it was rendered.
+    fn render_pascal(&self, dest: &mut Prettifier) -> bool {
+        match self {
+            WebStatement::Block(block) => {
+                if block.opener.is_reserved_word(PascalReservedWord::Begin) {
+                    dest.keyword("begin");
+                } else {
+                    block.opener.render_inline(dest);
+                }
+
+                dest.indent_block();
+
+                if let Some(c) = block.pre_comment.as_ref() {
+                    dest.newline_needed();
+                    c.render_inline(dest);
+                }
+
+                let mut nesting = NestingTracker::default();
+                let i_last = block.stmts.len().saturating_sub(1);
+
+                for (i, s) in block.stmts.iter().enumerate() {
+                    nesting.before(s.as_preprocessor_directive(), dest);
+                    dest.newline_needed();
+                    s.render_flex(dest);
+                    s.maybe_semicolon_in_block(i == i_last, dest);
+                    nesting.after(s.as_preprocessor_directive(), dest);
+                }
+
+                nesting.finish(dest);
+
+                if let Some(c) = block.post_comment.as_ref() {
+                    dest.newline_needed();
+                    c.render_inline(dest);
+                }
+
+                dest.dedent_block();
+                dest.newline_needed();
+
+                if block.closer.is_reserved_word(PascalReservedWord::End) {
+                    dest.keyword("end");
+                } else {
+                    block.closer.render_inline(dest);
+                }
+            }
+
+            WebStatement::If(i) => {
+                if let Some(c) = i.opening_comment.as_ref() {
+                    c.render_inline(dest);
+                    dest.newline_needed();
+                }
+
+                if let Some(c) = i.test_comment.as_ref() {
+                    c.render_inline(dest);
+                    dest.newline_needed();
+                }
+
+                dest.keyword("if");
+                dest.space();
+                i.test.render_flex(dest);
+                dest.space();
+                dest.keyword("then");
+                // Pascal doesn't allow a semicolon before `else`.
+                let then_block = render_pascal_body(i.then, i.else_.is_none(), dest);
+
+                if let Some(e) = &i.else_ {
+                    if then_block {
+                        dest.space();
+                    } else {
+                        dest.newline_needed();
+                    }
+
+                    dest.keyword("else");
+
+                    for c in [&i.else_head_comment, &i.else_tail_comment]
+                        .iter()
+                        .filter_map(|c| c.as_ref())
+                    {
+                        dest.space();
+                        c.render_inline(dest);
+                    }
+
+                    // Keep `else if` together, as in the C-like style.
+                    if let WebStatement::If(_) = &e.node {
+                        dest.space();
+                        e.render_flex(dest);
+                    } else {
+                        render_pascal_body(e, true, dest);
+                    }
+                }
+            }
+
+            WebStatement::While(w) => {
+                if let Some(c) = w.test_comment.as_ref() {
+                    c.render_inline(dest);
+                    dest.newline_needed();
+                }
+
+                dest.keyword("while");
+                dest.space();
+                w.test.render_flex(dest);
+                dest.space();
+                dest.keyword("do");
+                render_pascal_body(w.do_, true, dest);
+            }
+
+            WebStatement::For(f) => {
+                if let Some(c) = f.top_comment.as_ref() {
+                    c.render_inline(dest);
+                    dest.newline_needed();
+                }
+
+                dest.keyword("for");
+                dest.space();
+                dest.noscope_push(&f.var);
+                dest.noscope_push(assignment_operator(dest));
+                f.start.render_flex(dest);
+                dest.space();
+                dest.keyword(if f.is_down { "downto" } else { "to" });
+                dest.space();
+                f.end.render_flex(dest);
+                dest.space();
+                dest.keyword("do");
+                render_pascal_body(f.do_, true, dest);
+            }
+
+            WebStatement::Repeat(r) => {
+                dest.keyword("repeat");
+                dest.indent_block();
+
+                let mut nesting = NestingTracker::default();
+                let i_last = r.stmts.len().saturating_sub(1);
+
+                for (i, s) in r.stmts.iter().enumerate() {
+                    nesting.before(s.as_preprocessor_directive(), dest);
+                    dest.newline_needed();
+                    s.render_flex(dest);
+                    s.maybe_semicolon_in_block(i == i_last, dest);
+                    nesting.after(s.as_preprocessor_directive(), dest);
+                }
+
+                nesting.finish(dest);
+
+                if let Some(c) = r.closing_comment.as_ref() {
+                    dest.newline_needed();
+                    c.render_inline(dest);
+                }
+
+                dest.dedent_block();
+                dest.newline_needed();
+                dest.keyword("until");
+                dest.space();
+                r.test.render_flex(dest);
+            }
+
+            WebStatement::Loop(l) => {
+                dest.keyword(&l.keyword);
+                render_pascal_body(l.do_, true, dest);
+            }
+
+            WebStatement::Case(c) => {
+                dest.keyword("case");
+                dest.space();
+                c.var.render_flex(dest);
+                dest.space();
+                dest.keyword("of");
+                dest.indent_small();
+
+                for item in &c.items {
+                    dest.newline_needed();
+                    item.render_flex(dest);
+                }
+
+                if let Some(c) = c.comment.as_ref() {
+                    dest.newline_needed();
+                    c.render_inline(dest);
+                }
+
+                dest.dedent_small();
+                dest.newline_needed();
+                dest.keyword("end");
+            }
+
+            _ => return false,
+        }
+
+        true
+    }
+}
+
+/// Render the statement governed by a Pascal `then`, `else`, or `do`, which
+/// has just been written. A `begin`/`end` block starts on the same line, and
+/// any other statement goes on the next one, indented, followed by its
+/// semicolon if `semicolon` is set and it gets one. Returns whether the
+/// statement was such a block.
+fn render_pascal_body(stmt: &WebStatement, semicolon: bool, dest: &mut Prettifier) -> bool {
+    if let WebStatement::Block(block) = stmt {
+        if block.opener.is_reserved_word(PascalReservedWord::Begin) {
+            dest.space();
+            stmt.render_flex(dest);
+            return true;
+        }
+    }
+
+    dest.indent_block();
+    dest.newline_needed();
+    stmt.render_flex(dest);
+
+    if semicolon {
+        stmt.maybe_semicolon(dest);
+    }
+
+    dest.dedent_block();
+    false
 }
 
 impl<'a> WebCaseItem<'a> {
@@ -1288,7 +1753,7 @@ impl<'a> WebStandardCaseItem<'a> {
         if dest.fits(wm) {
             prettify::render_inline_seq(&self.matches, ", ", dest);
         } else {
-            let i_last = self.matches.len() - 1;
+            let i_last = self.matches.len().saturating_sub(1);
 
             for (i, expr) in self.matches.iter().enumerate() {
                 dest.newline_needed();
@@ -1325,3 +1790,264 @@ impl<'a> WebModMatchCaseItem<'a> {
         dest.dedent_small();
     }
 }
+
+// Walking
+
+impl<'a> WebStatement<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        match self {
+            WebStatement::ModuleReference(mref, comment) => {
+                visitor.visit_module_reference(mref);
+
+                if let Some(c) = comment {
+                    visitor.visit_comment(c);
+                }
+            }
+            WebStatement::Block(b) => visitor.visit_block(b),
+            WebStatement::Assignment(a) => visitor.visit_assignment(a),
+            WebStatement::PreprocessorDirective(pd) => visitor.visit_preprocessor_directive(pd),
+            WebStatement::Goto(g) => visitor.visit_goto(g),
+            WebStatement::If(i) => visitor.visit_if(i),
+            WebStatement::While(w) => visitor.visit_while(w),
+            WebStatement::For(f) => visitor.visit_for(f),
+            WebStatement::Repeat(r) => visitor.visit_repeat(r),
+            WebStatement::Loop(l) => visitor.visit_loop(l),
+            WebStatement::Label(l) => visitor.visit_label(l),
+            WebStatement::Case(c) => visitor.visit_case(c),
+            WebStatement::Comment(c) => visitor.visit_comment(c),
+            WebStatement::Expr(e, comment, _) => {
+                visitor.visit_expr(e);
+
+                if let Some(c) = comment {
+                    visitor.visit_comment(c);
+                }
+            }
+            WebStatement::MacroCall(mc) => visitor.visit_macro_call(mc),
+            WebStatement::SpecialFreeCase(c) => visitor.visit_free_case(c),
+        }
+    }
+}
+
+impl<'a> WebBlock<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        visitor.visit_token(&self.opener);
+
+        if let Some(c) = &self.pre_comment {
+            visitor.visit_comment(c);
+        }
+
+        for stmt in &self.stmts {
+            visitor.visit_statement(stmt);
+        }
+
+        visitor.visit_token(&self.closer);
+
+        if let Some(c) = &self.post_comment {
+            visitor.visit_comment(c);
+        }
+    }
+}
+
+impl<'a> WebAssignment<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        visitor.visit_expr(self.lhs);
+        visitor.visit_expr(self.rhs);
+        if let Some(c) = &self.comment {
+            visitor.visit_comment(c);
+        }
+        if let Some(c) = &self.second_comment {
+            visitor.visit_comment(c);
+        }
+    }
+}
+
+impl<'a> WebMacroCall<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        visitor.visit_expr(&self.call);
+
+        if let Some(c) = &self.comment {
+            visitor.visit_comment(c);
+        }
+    }
+}
+
+impl<'a> WebGoto<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        visitor.visit_token(&self.label);
+        if let Some(c) = &self.comment {
+            visitor.visit_comment(c);
+        }
+    }
+}
+
+impl<'a> WebIf<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        if let Some(c) = &self.opening_comment {
+            visitor.visit_comment(c);
+        }
+
+        visitor.visit_expr(self.test);
+
+        if let Some(c) = &self.test_comment {
+            visitor.visit_comment(c);
+        }
+
+        visitor.visit_statement(self.then);
+
+        if let Some(c) = &self.else_head_comment {
+            visitor.visit_comment(c);
+        }
+
+        if let Some(s) = &self.else_ {
+            visitor.visit_statement(s);
+        }
+
+        if let Some(c) = &self.else_tail_comment {
+            visitor.visit_comment(c);
+        }
+    }
+}
+
+impl<'a> WebWhile<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        visitor.visit_expr(self.test);
+
+        if let Some(c) = &self.test_comment {
+            visitor.visit_comment(c);
+        }
+
+        visitor.visit_statement(self.do_);
+    }
+}
+
+impl<'a> WebFor<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        visitor.visit_identifier(&self.var);
+        visitor.visit_expr(self.start);
+        visitor.visit_expr(self.end);
+
+        if let Some(c) = &self.top_comment {
+            visitor.visit_comment(c);
+        }
+
+        visitor.visit_statement(self.do_);
+    }
+}
+
+impl<'a> WebRepeat<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        for stmt in &self.stmts {
+            visitor.visit_statement(stmt);
+        }
+
+        visitor.visit_expr(self.test);
+
+        if let Some(c) = &self.closing_comment {
+            visitor.visit_comment(c);
+        }
+    }
+}
+
+impl<'a> WebLoop<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        visitor.visit_identifier(&self.keyword);
+        visitor.visit_statement(self.do_);
+    }
+}
+
+impl<'a> WebCase<'a> {
+    /// The items within the case statement.
+    pub fn items(&self) -> &[WebCaseItem<'a>] {
+        &self.items
+    }
+
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        visitor.visit_expr(self.var);
+
+        for item in &self.items {
+            item.walk(visitor);
+        }
+
+        if let Some(c) = &self.comment {
+            visitor.visit_comment(c);
+        }
+    }
+}
+
+impl<'a> WebCaseItem<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        match self {
+            WebCaseItem::ModuleReference(mref) => visitor.visit_module_reference(mref),
+            WebCaseItem::Standard(item) => item.walk(visitor),
+            WebCaseItem::OtherCases(item) => item.walk(visitor),
+            WebCaseItem::IfdefStandard(begin, item, end) => {
+                visitor.visit_token(begin);
+                item.walk(visitor);
+                visitor.visit_token(end);
+            }
+            WebCaseItem::ModMatch(item) => item.walk(visitor),
+        }
+    }
+}
+
+impl<'a> WebStandardCaseItem<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        for m in &self.matches {
+            visitor.visit_expr(m);
+        }
+
+        visitor.visit_statement(self.stmt);
+
+        if let Some(c) = &self.comment {
+            visitor.visit_comment(c);
+        }
+    }
+}
+
+impl<'a> WebModMatchCaseItem<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        visitor.visit_module_reference(&self.match_);
+        visitor.visit_statement(self.stmt);
+
+        if let Some(c) = &self.comment {
+            visitor.visit_comment(c);
+        }
+    }
+}
+
+impl<'a> WebOtherCasesItem<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        visitor.visit_identifier(&self.tag);
+        visitor.visit_statement(self.stmt);
+
+        if let Some(c) = &self.comment {
+            visitor.visit_comment(c);
+        }
+    }
+}
+
+impl<'a> SpecialFreeCase<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        for m in &self.matches {
+            visitor.visit_expr(m);
+        }
+
+        visitor.visit_statement(self.stmt);
+    }
+}
@@ -7,6 +7,8 @@ use nom::{
     combinator::{map, opt},
     sequence::tuple,
 };
+#[cfg(feature = "serde")]
+use serde::Serialize;
 
 use crate::prettify::{Prettifier, RenderInline};
 
@@ -17,6 +19,7 @@ use super::{
 };
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct WebTypeDeclaration<'a> {
     /// The name of the new type.
     name: StringSpan<'a>,
@@ -58,9 +61,22 @@ impl<'a> WebTypeDeclaration<'a> {
 
         dest.keyword("type");
         dest.space();
-        dest.noscope_push(&self.name);
+        dest.identifier(&self.name);
         dest.noscope_push(" = ");
         self.ty.render_flex(dest);
         dest.noscope_push(';');
     }
 }
+
+// Walking
+
+impl<'a> WebTypeDeclaration<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        visitor.visit_defined_identifier(&self.name);
+        visitor.visit_type(&self.ty);
+        if let Some(c) = &self.comment {
+            visitor.visit_comment(c);
+        }
+    }
+}
@@ -1,13 +1,18 @@
 //! A declaration of a variable.
 //!
 //! In Pascal these happen inside `var` blocks but in typical WEB programs there
-//! are also "toplevel" instances.
+//! are also "toplevel" instances. These usually have a comment after them, and
+//! sometimes one between the names and the type, as in `@!x: {the index}
+//! integer;`. All of the comments are rendered together, after the declaration
+//! if they fit and above it otherwise.
 
 use nom::{
     combinator::{map, opt},
     multi::separated_list0,
     sequence::tuple,
 };
+#[cfg(feature = "serde")]
+use serde::Serialize;
 
 use crate::prettify::{Prettifier, RenderInline};
 
@@ -18,6 +23,7 @@ use super::{
 };
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct WebVarDeclaration<'a> {
     /// The name(s) of the variable(s).
     names: Vec<StringSpan<'a>>,
@@ -25,6 +31,9 @@ pub struct WebVarDeclaration<'a> {
     /// The type of the variable(s).
     ty: WebType<'a>,
 
+    /// Comments between the names and the type, on either side of the colon.
+    inner_comments: Vec<WebComment<'a>>,
+
     /// Optional comment.
     comment: Option<WebComment<'a>>,
 
@@ -40,7 +49,9 @@ pub fn parse_var_declaration_base<'a>(
     map(
         tuple((
             separated_list0(pascal_token(PascalToken::Comma), identifier),
+            opt(comment),
             pascal_token(PascalToken::Colon),
+            opt(comment),
             parse_type,
             peek_toplevel_boundary,
             opt(comment),
@@ -48,19 +59,18 @@ pub fn parse_var_declaration_base<'a>(
         )),
         |tup| WebVarDeclaration {
             names: tup.0,
-            ty: tup.2,
-            comment: tup.4,
-            second_comment: tup.5,
+            ty: tup.4,
+            inner_comments: tup.1.into_iter().chain(tup.3).collect(),
+            comment: tup.6,
+            second_comment: tup.7,
         },
     )(input)
 }
 
 fn peek_toplevel_boundary<'a>(input: ParseInput<'a>) -> ParseResult<'a, ()> {
     match next_token(input) {
-        Err(nom::Err::Error((_next_input, kind))) => {
-            if kind == WebErrorKind::Eof {
-                return Ok((input, ()));
-            }
+        Err(nom::Err::Error((_next_input, WebErrorKind::Eof))) => {
+            return Ok((input, ()));
         }
 
         Ok((_next_input, WebToken::Comment(..))) => {
@@ -88,15 +98,12 @@ pub fn parse_var_declaration<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebTo
 
 impl<'a> WebVarDeclaration<'a> {
     pub fn prettify(&self, dest: &mut Prettifier) {
-        if let Some(c) = self.comment.as_ref() {
-            c.render_inline(dest);
-            dest.newline_needed();
-        }
-
-        if let Some(c) = self.second_comment.as_ref() {
-            c.render_inline(dest);
-            dest.newline_needed();
-        }
+        let comments: Vec<_> = self
+            .inner_comments
+            .iter()
+            .chain(self.comment.as_ref())
+            .chain(self.second_comment.as_ref())
+            .collect();
 
         let mut wi = 4;
 
@@ -106,6 +113,16 @@ impl<'a> WebVarDeclaration<'a> {
 
         wi += self.ty.measure_inline();
 
+        let wc: usize = comments.iter().map(|c| c.measure_inline() + 1).sum();
+        let trailing = !comments.is_empty() && dest.fits(wi + 1 + wc); // + ";"
+
+        if !trailing {
+            for c in &comments {
+                c.render_inline(dest);
+                dest.newline_needed();
+            }
+        }
+
         dest.keyword("var");
         dest.space();
 
@@ -119,14 +136,14 @@ impl<'a> WebVarDeclaration<'a> {
                     dest.noscope_push(", ");
                 }
 
-                dest.noscope_push(n);
+                dest.identifier(n);
             }
         } else {
-            let i_last = self.names.len() - 1;
+            let i_last = self.names.len().saturating_sub(1);
             dest.indent_small();
 
             for (i, n) in self.names.iter().enumerate() {
-                dest.noscope_push(n);
+                dest.identifier(n);
 
                 if i != i_last {
                     dest.noscope_push(',');
@@ -140,5 +157,35 @@ impl<'a> WebVarDeclaration<'a> {
         dest.noscope_push(": ");
         self.ty.render_flex(dest);
         dest.noscope_push(';');
+
+        if trailing {
+            for c in &comments {
+                dest.space();
+                c.render_inline(dest);
+            }
+        }
+    }
+}
+
+// Walking
+
+impl<'a> WebVarDeclaration<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        for name in &self.names {
+            visitor.visit_defined_identifier(name);
+        }
+
+        for c in &self.inner_comments {
+            visitor.visit_comment(c);
+        }
+
+        visitor.visit_type(&self.ty);
+        if let Some(c) = &self.comment {
+            visitor.visit_comment(c);
+        }
+        if let Some(c) = &self.second_comment {
+            visitor.visit_comment(c);
+        }
     }
 }
@@ -0,0 +1,286 @@
+//! Walking the WEB AST.
+//!
+//! Implement [`Visitor`] to analyze parsed WEB code without having to write out
+//! a match over every kind of node. The walk calls the `visit_*` method for each
+//! node that it reaches. By default, each method continues the walk into the
+//! node's children by calling the node's `walk` method, so you only need to
+//! override the methods for the nodes that you care about. If an override still
+//! wants the children to be visited, it should call `walk` itself.
+//!
+//! Toplevels, statements, and expressions are visited along with their
+//! locations in the input, as [`Spanned`] values.
+//!
+//! For instance, this collects the names of all of the procedures and functions
+//! that are defined in a block of code:
+//!
+//! ```
+//! use tt_weave::weblang::{visit::Visitor, WebFunctionDefinition};
+//!
+//! struct FunctionNames(Vec<String>);
+//!
+//! impl<'a> Visitor<'a> for FunctionNames {
+//!     fn visit_function_definition(&mut self, func: &WebFunctionDefinition<'a>) {
+//!         self.0.push(func.name().value.to_string());
+//!         func.walk(self);
+//!     }
+//! }
+//! ```
+
+use super::{
+    base::*,
+    const_declaration::WebConstantDeclaration,
+    define::WebDefine,
+    expr::{
+        WebBinaryExpr, WebCallExpr, WebExpr, WebFieldAccessExpr, WebFormatExpr, WebIndexExpr,
+        WebPostfixUnaryExpr, WebPrefixUnaryExpr,
+    },
+    format::WebFormat,
+    forward_declaration::WebForwardDeclaration,
+    function_definition::WebFunctionDefinition,
+    label_declaration::WebLabelDeclaration,
+    modulified_declaration::WebModulifiedDeclaration,
+    preprocessor_directive::WebPreprocessorDirective,
+    program_definition::WebProgramDefinition,
+    standalone::WebStandalone,
+    statement::{
+        SpecialFreeCase, WebAssignment, WebBlock, WebCase, WebFor, WebGoto, WebIf, WebLoop,
+        WebMacroCall, WebRepeat, WebStatement, WebWhile,
+    },
+    type_declaration::WebTypeDeclaration,
+    var_declaration::WebVarDeclaration,
+    webtype::{WebArrayType, WebRecordType, WebType},
+    WebCode, WebToplevel,
+};
+
+/// A visitor over the WEB AST.
+///
+/// See the [module-level documentation](self) for an overview.
+pub trait Visitor<'a> {
+    /// Visit a block of WEB code.
+    fn visit_code(&mut self, code: &WebCode<'a>) {
+        code.walk(self);
+    }
+
+    // Toplevels
+
+    /// Visit a toplevel. This is called for every toplevel, before the more
+    /// specific method for its kind, if there is one.
+    fn visit_toplevel(&mut self, toplevel: &Spanned<WebToplevel<'a>>) {
+        toplevel.walk(self);
+    }
+
+    /// Visit a `@d` definition.
+    fn visit_define(&mut self, define: &WebDefine<'a>) {
+        define.walk(self);
+    }
+
+    /// Visit a `@f` format definition.
+    fn visit_format(&mut self, format: &WebFormat<'a>) {
+        format.walk(self);
+    }
+
+    /// Visit a standalone token.
+    fn visit_standalone(&mut self, standalone: &WebStandalone<'a>) {
+        standalone.walk(self);
+    }
+
+    /// Visit the program definition.
+    fn visit_program_definition(&mut self, program: &WebProgramDefinition<'a>) {
+        program.walk(self);
+    }
+
+    /// Visit a label declaration.
+    fn visit_label_declaration(&mut self, decl: &WebLabelDeclaration<'a>) {
+        decl.walk(self);
+    }
+
+    /// Visit a group of declarations done by referencing a module.
+    fn visit_modulified_declaration(&mut self, decl: &WebModulifiedDeclaration<'a>) {
+        decl.walk(self);
+    }
+
+    /// Visit the definition of a procedure or function.
+    fn visit_function_definition(&mut self, func: &WebFunctionDefinition<'a>) {
+        func.walk(self);
+    }
+
+    /// Visit a constant declaration.
+    fn visit_const_declaration(&mut self, decl: &WebConstantDeclaration<'a>) {
+        decl.walk(self);
+    }
+
+    /// Visit a variable declaration.
+    fn visit_var_declaration(&mut self, decl: &WebVarDeclaration<'a>) {
+        decl.walk(self);
+    }
+
+    /// Visit a type declaration.
+    fn visit_type_declaration(&mut self, decl: &WebTypeDeclaration<'a>) {
+        decl.walk(self);
+    }
+
+    /// Visit a forward declaration of a procedure or function.
+    fn visit_forward_declaration(&mut self, decl: &WebForwardDeclaration<'a>) {
+        decl.walk(self);
+    }
+
+    // Statements
+
+    /// Visit a statement. This is called for every statement, before the more
+    /// specific method for its kind, if there is one.
+    fn visit_statement(&mut self, stmt: &Spanned<WebStatement<'a>>) {
+        stmt.walk(self);
+    }
+
+    /// Visit a `begin`/`end` block.
+    fn visit_block(&mut self, block: &WebBlock<'a>) {
+        block.walk(self);
+    }
+
+    /// Visit an assignment.
+    fn visit_assignment(&mut self, assignment: &WebAssignment<'a>) {
+        assignment.walk(self);
+    }
+
+    /// Visit a preprocessor directive.
+    fn visit_preprocessor_directive(&mut self, directive: &WebPreprocessorDirective<'a>) {
+        directive.walk(self);
+    }
+
+    /// Visit a `goto` statement.
+    fn visit_goto(&mut self, goto: &WebGoto<'a>) {
+        goto.walk(self);
+    }
+
+    /// Visit a use of a macro that expands to a statement, like `incr(k)`.
+    fn visit_macro_call(&mut self, call: &WebMacroCall<'a>) {
+        call.walk(self);
+    }
+
+    /// Visit an `if` statement.
+    fn visit_if(&mut self, if_: &WebIf<'a>) {
+        if_.walk(self);
+    }
+
+    /// Visit a `while` loop.
+    fn visit_while(&mut self, while_: &WebWhile<'a>) {
+        while_.walk(self);
+    }
+
+    /// Visit a `for` loop.
+    fn visit_for(&mut self, for_: &WebFor<'a>) {
+        for_.walk(self);
+    }
+
+    /// Visit a `repeat`/`until` loop.
+    fn visit_repeat(&mut self, repeat: &WebRepeat<'a>) {
+        repeat.walk(self);
+    }
+
+    /// Visit a `loop` loop.
+    fn visit_loop(&mut self, loop_: &WebLoop<'a>) {
+        loop_.walk(self);
+    }
+
+    /// Visit a statement label.
+    fn visit_label(&mut self, label: &PascalToken<'a>) {
+        self.visit_token(label);
+    }
+
+    /// Visit a `case` statement.
+    fn visit_case(&mut self, case: &WebCase<'a>) {
+        case.walk(self);
+    }
+
+    /// Visit a free-floating case statement.
+    fn visit_free_case(&mut self, case: &SpecialFreeCase<'a>) {
+        case.walk(self);
+    }
+
+    // Expressions
+
+    /// Visit an expression. This is called for every expression, before the
+    /// more specific method for its kind, if there is one.
+    fn visit_expr(&mut self, expr: &Spanned<WebExpr<'a>>) {
+        expr.walk(self);
+    }
+
+    /// Visit a binary expression.
+    fn visit_binary_expr(&mut self, expr: &WebBinaryExpr<'a>) {
+        expr.walk(self);
+    }
+
+    /// Visit a prefix unary expression.
+    fn visit_prefix_unary_expr(&mut self, expr: &WebPrefixUnaryExpr<'a>) {
+        expr.walk(self);
+    }
+
+    /// Visit a postfix unary expression.
+    fn visit_postfix_unary_expr(&mut self, expr: &WebPostfixUnaryExpr<'a>) {
+        expr.walk(self);
+    }
+
+    /// Visit a function or procedure call.
+    fn visit_call_expr(&mut self, expr: &WebCallExpr<'a>) {
+        expr.walk(self);
+    }
+
+    /// Visit an array indexing expression.
+    fn visit_index_expr(&mut self, expr: &WebIndexExpr<'a>) {
+        expr.walk(self);
+    }
+
+    /// Visit a field access expression.
+    fn visit_field_access_expr(&mut self, expr: &WebFieldAccessExpr<'a>) {
+        expr.walk(self);
+    }
+
+    /// Visit an expression with a width specifier.
+    fn visit_format_expr(&mut self, expr: &WebFormatExpr<'a>) {
+        expr.walk(self);
+    }
+
+    // Types
+
+    /// Visit a type. This is called for every type, before the more specific
+    /// method for its kind, if there is one.
+    fn visit_type(&mut self, ty: &WebType<'a>) {
+        ty.walk(self);
+    }
+
+    /// Visit an array type.
+    fn visit_array_type(&mut self, ty: &WebArrayType<'a>) {
+        ty.walk(self);
+    }
+
+    /// Visit a record type.
+    fn visit_record_type(&mut self, ty: &WebRecordType<'a>) {
+        ty.walk(self);
+    }
+
+    // Leaves
+
+    /// Visit a Pascal token. By default, identifier tokens are passed along to
+    /// [`Self::visit_identifier`].
+    fn visit_token(&mut self, token: &PascalToken<'a>) {
+        if let PascalToken::Identifier(ident) | PascalToken::FormattedIdentifier(ident, _) = token {
+            self.visit_identifier(ident);
+        }
+    }
+
+    /// Visit an identifier.
+    fn visit_identifier(&mut self, _ident: &StringSpan<'a>) {}
+
+    /// Visit an identifier that is being defined, such as the name in a
+    /// variable declaration or the macro name of a `@d`. By default, this is
+    /// passed along to [`Self::visit_identifier`].
+    fn visit_defined_identifier(&mut self, ident: &StringSpan<'a>) {
+        self.visit_identifier(ident);
+    }
+
+    /// Visit a reference to a WEB module.
+    fn visit_module_reference(&mut self, _mref: &WebModuleReference<'a>) {}
+
+    /// Visit a comment.
+    fn visit_comment(&mut self, _comment: &WebComment<'a>) {}
+}
@@ -5,15 +5,21 @@
 use nom::{
     branch::alt,
     combinator::{map, opt},
-    multi::{many1, separated_list0, separated_list1},
+    multi::{many0, many1, separated_list0, separated_list1},
     sequence::tuple,
 };
+#[cfg(feature = "serde")]
+use serde::Serialize;
 
 use crate::prettify::{self, Prettifier, RenderInline};
 
-use super::base::*;
+use super::{
+    base::*,
+    module_reference::{parse_module_reference, WebModuleReference},
+};
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub enum WebType<'a> {
     Integer,
     Real,
@@ -23,10 +29,15 @@ pub enum WebType<'a> {
     Array(WebArrayType<'a>),
     Record(WebRecordType<'a>),
     UserDefined(StringSpan<'a>),
-    Pointer(Box<WebType<'a>>),
+    Pointer(&'a WebType<'a>),
+
+    /// A module reference standing in for a type, as in system-dependent
+    /// sections: `type foo = @<Machine-dependent type@>;`.
+    ModuleReference(WebModuleReference<'a>),
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub enum RangeBound<'a> {
     Literal(PascalToken<'a>),
     Symbolic1(StringSpan<'a>),
@@ -35,6 +46,10 @@ pub enum RangeBound<'a> {
 }
 
 pub fn parse_type<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebType<'a>> {
+    nested(input, parse_type_unnested)
+}
+
+fn parse_type_unnested<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebType<'a>> {
     alt((
         named("integer", WebType::Integer),
         named("real", WebType::Real),
@@ -44,7 +59,8 @@ pub fn parse_type<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebType<'a>> {
         parse_record,
         parse_array,
         parse_range,
-        map(identifier, |s| WebType::UserDefined(s)),
+        map(identifier, WebType::UserDefined),
+        map(parse_module_reference, WebType::ModuleReference),
     ))(input)
 }
 
@@ -65,7 +81,7 @@ fn named<'a>(
 
 fn parse_pointer<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebType<'a>> {
     map(tuple((pascal_token(PascalToken::Caret), parse_type)), |t| {
-        WebType::Pointer(Box::new(t.1))
+        WebType::Pointer(input.arena().alloc_type(t.1))
     })(input)
 }
 
@@ -82,10 +98,10 @@ fn parse_range<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebType<'a>> {
 
 fn parse_range_bound<'a>(input: ParseInput<'a>) -> ParseResult<'a, RangeBound<'a>> {
     alt((
-        map(int_literal, |t| RangeBound::Literal(t)),
-        map(merged_string_literals, |t| RangeBound::Literal(t)),
+        map(int_literal, RangeBound::Literal),
+        map(merged_string_literals, RangeBound::Literal),
         parse_binary_range_bound,
-        map(identifier, |i| RangeBound::Symbolic1(i)),
+        map(identifier, RangeBound::Symbolic1),
         parse_unary_range_bound,
     ))(input)
 }
@@ -132,10 +148,11 @@ fn parse_packed_file_of<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebType<'a
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct WebArrayType<'a> {
     is_packed: bool,
-    axes: Vec<Box<WebType<'a>>>,
-    element: Box<WebType<'a>>,
+    axes: Vec<&'a WebType<'a>>,
+    element: &'a WebType<'a>,
 }
 
 fn parse_array<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebType<'a>> {
@@ -146,11 +163,11 @@ fn parse_array<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebType<'a>> {
             pascal_token(PascalToken::OpenDelimiter(DelimiterKind::SquareBracket)),
             separated_list0(
                 pascal_token(PascalToken::Comma),
-                map(parse_type, |e| Box::new(e)),
+                map(parse_type, |t| input.arena().alloc_type(t)),
             ),
             pascal_token(PascalToken::CloseDelimiter(DelimiterKind::SquareBracket)),
             reserved_word(PascalReservedWord::Of),
-            map(parse_type, |e| Box::new(e)),
+            map(parse_type, |t| input.arena().alloc_type(t)),
         )),
         |t| {
             WebType::Array(WebArrayType {
@@ -163,15 +180,39 @@ fn parse_array<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebType<'a>> {
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct WebRecordType<'a> {
     is_packed: bool,
     fields: Vec<WebRecordField<'a>>,
+
+    /// The variant part, as in METAFONT's `memory_word`.
+    variants: Option<WebRecordVariantPart<'a>>,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct WebRecordField<'a> {
     names: Vec<PascalToken<'a>>,
-    ty: Box<WebType<'a>>,
+    ty: &'a WebType<'a>,
+    comment: Option<WebComment<'a>>,
+}
+
+/// The variant part of a record: `case $tag : $type of $variants`, where the
+/// tag name is optional.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct WebRecordVariantPart<'a> {
+    tag: Option<StringSpan<'a>>,
+    tag_type: &'a WebType<'a>,
+    variants: Vec<WebRecordVariant<'a>>,
+}
+
+/// One variant of a record: `$labels : ( $fields )`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct WebRecordVariant<'a> {
+    labels: Vec<PascalToken<'a>>,
+    fields: Vec<WebRecordField<'a>>,
     comment: Option<WebComment<'a>>,
 }
 
@@ -180,35 +221,80 @@ fn parse_record<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebType<'a>> {
         tuple((
             opt(reserved_word(PascalReservedWord::Packed)),
             reserved_word(PascalReservedWord::Record),
-            many1(parse_record_field),
+            many0(parse_record_field),
+            opt(parse_record_variant_part),
             reserved_word(PascalReservedWord::End),
         )),
         |t| {
             WebType::Record(WebRecordType {
                 is_packed: t.0.is_some(),
                 fields: t.2,
+                variants: t.3,
             })
         },
     )(input)
 }
 
+/// The semicolon is optional since Pascal doesn't require one after the last
+/// field before an `end` or a closing parenthesis.
 fn parse_record_field<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebRecordField<'a>> {
     map(
         tuple((
             separated_list1(pascal_token(PascalToken::Comma), identifier_as_token),
             pascal_token(PascalToken::Colon),
             parse_type,
-            pascal_token(PascalToken::Semicolon),
+            opt(pascal_token(PascalToken::Semicolon)),
             opt(comment),
         )),
         |t| WebRecordField {
             names: t.0,
-            ty: Box::new(t.2),
+            ty: input.arena().alloc_type(t.2),
             comment: t.4,
         },
     )(input)
 }
 
+fn parse_record_variant_part<'a>(
+    input: ParseInput<'a>,
+) -> ParseResult<'a, WebRecordVariantPart<'a>> {
+    map(
+        tuple((
+            reserved_word(PascalReservedWord::Case),
+            opt(tuple((identifier, pascal_token(PascalToken::Colon)))),
+            parse_type,
+            reserved_word(PascalReservedWord::Of),
+            many1(parse_record_variant),
+        )),
+        |t| WebRecordVariantPart {
+            tag: t.1.map(|t| t.0),
+            tag_type: input.arena().alloc_type(t.2),
+            variants: t.4,
+        },
+    )(input)
+}
+
+fn parse_record_variant<'a>(input: ParseInput<'a>) -> ParseResult<'a, WebRecordVariant<'a>> {
+    map(
+        tuple((
+            separated_list1(
+                pascal_token(PascalToken::Comma),
+                alt((int_literal, merged_string_literals, identifier_as_token)),
+            ),
+            pascal_token(PascalToken::Colon),
+            open_delimiter(DelimiterKind::Paren),
+            many0(parse_record_field),
+            close_delimiter(DelimiterKind::Paren),
+            opt(pascal_token(PascalToken::Semicolon)),
+            opt(comment),
+        )),
+        |t| WebRecordVariant {
+            labels: t.0,
+            fields: t.3,
+            comment: t.6,
+        },
+    )(input)
+}
+
 // Prettifying
 
 impl<'a> RenderInline for WebType<'a> {
@@ -223,6 +309,7 @@ impl<'a> RenderInline for WebType<'a> {
             WebType::Record(_rec) => prettify::NOT_INLINE,
             WebType::Pointer(ty) => 1 + ty.measure_inline(),
             WebType::UserDefined(s) => s.value.as_ref().len(),
+            WebType::ModuleReference(mr) => mr.measure_inline(),
         }
     }
 
@@ -240,7 +327,7 @@ impl<'a> RenderInline for WebType<'a> {
 
             WebType::PackedFileOf(t) => {
                 dest.noscope_push("packed file of ");
-                dest.noscope_push(t.value.as_ref());
+                dest.identifier(t);
             }
 
             WebType::Array(arr) => arr.render_inline(dest),
@@ -251,7 +338,8 @@ impl<'a> RenderInline for WebType<'a> {
                 ty.render_inline(dest);
             }
 
-            WebType::UserDefined(s) => dest.noscope_push(s.value.as_ref()),
+            WebType::UserDefined(s) => dest.identifier(s),
+            WebType::ModuleReference(mr) => mr.render_inline(dest),
         }
     }
 }
@@ -265,7 +353,8 @@ impl<'a> WebType<'a> {
             | WebType::UserDefined(_)
             | WebType::PackedFileOf(_)
             | WebType::Pointer(_)
-            | WebType::Range(..) => self.render_inline(dest),
+            | WebType::Range(..)
+            | WebType::ModuleReference(_) => self.render_inline(dest),
 
             WebType::Array(arr) => arr.render_flex(dest),
             WebType::Record(rec) => rec.render_flex(dest),
@@ -288,10 +377,10 @@ impl<'a> RenderInline for RangeBound<'a> {
     fn render_inline(&self, dest: &mut Prettifier) {
         match self {
             RangeBound::Literal(t) => t.render_inline(dest),
-            RangeBound::Symbolic1(s) => dest.noscope_push(s.value.as_ref()),
+            RangeBound::Symbolic1(s) => dest.identifier(s),
             RangeBound::Symbolic2(s1, op, s2) => {
                 dest.noscope_push('(');
-                dest.noscope_push(s1.value.as_ref());
+                dest.identifier(s1);
                 dest.space();
                 op.render_inline(dest);
                 dest.space();
@@ -300,7 +389,7 @@ impl<'a> RenderInline for RangeBound<'a> {
             }
             RangeBound::UnarySymbolic(op, s) => {
                 op.render_inline(dest);
-                dest.noscope_push(s.value.as_ref());
+                dest.identifier(s);
             }
         }
     }
@@ -384,55 +473,237 @@ impl<'a> WebRecordType<'a> {
             dest.noscope_push("packed ");
         }
 
-        dest.noscope_push("record {");
+        let pascal = dest.style().preserve_keywords;
+        dest.noscope_push(if pascal { "record" } else { "record {" });
         dest.indent_block();
+        render_record_fields(&self.fields, self.variants.is_none(), dest);
 
-        for f in &self.fields {
+        if let Some(vp) = self.variants.as_ref() {
             dest.newline_needed();
+            vp.render_flex(dest);
+        }
 
-            let wc = f
-                .comment
-                .as_ref()
-                .map(|c| c.measure_inline() + 1)
-                .unwrap_or(0);
+        dest.dedent_block();
+        dest.newline_indent();
 
-            let wn = prettify::measure_inline_seq(&f.names, 2);
+        if pascal {
+            dest.keyword("end");
+        } else {
+            dest.noscope_push('}');
+        }
+    }
+}
 
-            if dest.fits(wn + f.ty.measure_inline() + wc + 3) {
-                prettify::render_inline_seq(&f.names, ", ", dest);
-                dest.noscope_push(": ");
-                f.ty.render_inline(dest);
-                dest.noscope_push(',');
+impl<'a> WebRecordVariantPart<'a> {
+    /// Render the variant part like a `case` statement, with the fields of
+    /// each variant indented below its labels.
+    fn render_flex(&self, dest: &mut Prettifier) {
+        dest.keyword("case");
+        dest.space();
+
+        if let Some(tag) = self.tag.as_ref() {
+            dest.identifier(tag);
+            dest.noscope_push(": ");
+        }
+
+        // In Pascal, the variants end with the record.
+        let pascal = dest.style().preserve_keywords;
+        self.tag_type.render_inline(dest);
+        dest.space();
+
+        if pascal {
+            dest.keyword("of");
+        } else {
+            dest.noscope_push('{');
+        }
+
+        dest.indent_small();
+        let i_last = self.variants.len().saturating_sub(1);
+
+        for (i, v) in self.variants.iter().enumerate() {
+            dest.newline_needed();
+
+            if let Some(c) = v.comment.as_ref() {
+                c.render_inline(dest);
+                dest.newline_needed();
+            }
+
+            prettify::render_inline_seq(&v.labels, ", ", dest);
+            dest.noscope_push(if pascal { ": (" } else { ":" });
+            dest.indent_small();
+            render_record_fields(&v.fields, true, dest);
+            dest.dedent_small();
+
+            if pascal {
+                dest.noscope_push(if i == i_last { ")" } else { ");" });
+            }
+        }
 
-                if let Some(c) = f.comment.as_ref() {
-                    dest.space();
-                    c.render_inline(dest);
+        dest.dedent_small();
+
+        if !pascal {
+            dest.newline_needed();
+            dest.noscope_push('}');
+        }
+    }
+}
+
+/// Render the fields of a record or of one of its variants. In the C-like
+/// style, each is followed by a comma. In Pascal, they're separated by
+/// semicolons, and the last one is followed by one unless it's `last` in its
+/// record or variant.
+fn render_record_fields(fields: &[WebRecordField], last: bool, dest: &mut Prettifier) {
+    let pascal = dest.style().preserve_keywords;
+    let i_last = fields.len().saturating_sub(1);
+
+    for (i, f) in fields.iter().enumerate() {
+        let term = match (pascal, last && i == i_last) {
+            (false, _) => ",",
+            (true, false) => ";",
+            (true, true) => "",
+        };
+
+        dest.newline_needed();
+
+        let wc = f
+            .comment
+            .as_ref()
+            .map(|c| c.measure_inline() + 1)
+            .unwrap_or(0);
+
+        let wn = prettify::measure_inline_seq(&f.names, 2);
+
+        if dest.fits(wn + f.ty.measure_inline() + wc + 3) {
+            prettify::render_inline_seq(&f.names, ", ", dest);
+            dest.noscope_push(": ");
+            f.ty.render_inline(dest);
+            dest.noscope_push(term);
+
+            if let Some(c) = f.comment.as_ref() {
+                dest.space();
+                c.render_inline(dest);
+            }
+        } else if dest.fits(wn + f.ty.measure_inline() + 3) {
+            if let Some(c) = f.comment.as_ref() {
+                c.render_inline(dest);
+                dest.newline_needed();
+            }
+
+            prettify::render_inline_seq(&f.names, ", ", dest);
+            dest.noscope_push(": ");
+            f.ty.render_inline(dest);
+            dest.noscope_push(term);
+        } else {
+            if let Some(c) = f.comment.as_ref() {
+                c.render_inline(dest);
+                dest.newline_needed();
+            }
+
+            prettify::render_inline_seq(&f.names, ", ", dest);
+            dest.noscope_push(": ");
+            f.ty.render_flex(dest);
+            dest.noscope_push(term);
+        }
+    }
+}
+
+// Walking
+
+impl<'a> WebType<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        match self {
+            WebType::Integer | WebType::Real | WebType::Boolean => {}
+            WebType::Range(lo, hi) => {
+                lo.walk(visitor);
+                hi.walk(visitor);
+            }
+            WebType::PackedFileOf(ident) | WebType::UserDefined(ident) => {
+                visitor.visit_identifier(ident)
+            }
+            WebType::Array(arr) => visitor.visit_array_type(arr),
+            WebType::Record(rec) => visitor.visit_record_type(rec),
+            WebType::Pointer(inner) => visitor.visit_type(inner),
+            WebType::ModuleReference(mref) => visitor.visit_module_reference(mref),
+        }
+    }
+}
+
+impl<'a> RangeBound<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        match self {
+            RangeBound::Literal(tok) => visitor.visit_token(tok),
+            RangeBound::Symbolic1(ident) => visitor.visit_identifier(ident),
+            RangeBound::Symbolic2(ident, op, tok) => {
+                visitor.visit_identifier(ident);
+                visitor.visit_token(op);
+                visitor.visit_token(tok);
+            }
+            RangeBound::UnarySymbolic(op, ident) => {
+                visitor.visit_token(op);
+                visitor.visit_identifier(ident);
+            }
+        }
+    }
+}
+
+impl<'a> WebArrayType<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        for axis in &self.axes {
+            visitor.visit_type(axis);
+        }
+
+        visitor.visit_type(self.element);
+    }
+}
+
+impl<'a> WebRecordType<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        for field in &self.fields {
+            field.walk(visitor);
+        }
+
+        if let Some(vp) = &self.variants {
+            if let Some(tag) = &vp.tag {
+                visitor.visit_defined_identifier(tag);
+            }
+
+            visitor.visit_type(vp.tag_type);
+
+            for v in &vp.variants {
+                for label in &v.labels {
+                    visitor.visit_token(label);
                 }
-            } else if dest.fits(wn + f.ty.measure_inline() + 3) {
-                if let Some(c) = f.comment.as_ref() {
-                    c.render_inline(dest);
-                    dest.newline_needed();
+
+                for field in &v.fields {
+                    field.walk(visitor);
                 }
 
-                prettify::render_inline_seq(&f.names, ", ", dest);
-                dest.noscope_push(": ");
-                f.ty.render_inline(dest);
-                dest.noscope_push(',');
-            } else {
-                if let Some(c) = f.comment.as_ref() {
-                    c.render_inline(dest);
-                    dest.newline_needed();
+                if let Some(c) = &v.comment {
+                    visitor.visit_comment(c);
                 }
+            }
+        }
+    }
+}
 
-                prettify::render_inline_seq(&f.names, ", ", dest);
-                dest.noscope_push(": ");
-                f.ty.render_flex(dest);
-                dest.noscope_push(',');
+impl<'a> WebRecordField<'a> {
+    /// Visit the children of this node.
+    pub fn walk<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        for name in &self.names {
+            match name {
+                PascalToken::Identifier(ident) => visitor.visit_defined_identifier(ident),
+                _ => visitor.visit_token(name),
             }
         }
 
-        dest.dedent_block();
-        dest.newline_indent();
-        dest.noscope_push('}');
+        visitor.visit_type(self.ty);
+
+        if let Some(c) = &self.comment {
+            visitor.visit_comment(c);
+        }
     }
 }
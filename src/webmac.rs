@@ -0,0 +1,270 @@
+//! Output structured like WEAVE's.
+//!
+//! tt-weave's usual output uses its own macros, which are defined by the
+//! preamble of the Tectonic template. With [`crate::WeaveOptions::webmac`],
+//! the output is instead structured like the `.tex` files written by Knuth's
+//! WEAVE, using the macros of `webmac.tex`, so that documents and build
+//! scripts that expect WEAVE's conventions can switch to tt-weave as they are:
+//!
+//! - the document starts with `\input webmac`, followed by the limbo material;
+//! - starred modules start with `\N` and the others with `\M`, and each module
+//!   ends with `\fi`;
+//! - code is introduced by `\Y\P`, its lines are separated by `\6`, and its
+//!   indentation is changed with `\1` and `\2`. Reserved words are set with
+//!   `\&`, identifiers with `\\` or `\|`, strings with `\.`, comments with
+//!   `\C`, and module names with `\X`;
+//! - the code of a named module is followed by `\A` and `\U` notes listing the
+//!   other modules that define it and the ones that use it;
+//! - the document ends with the index, between `\inx` and `\fin`, then the list
+//!   of module names, and finally `\con`, with which `webmac.tex` typesets the
+//!   table of contents and ends the document.
+//!
+//! The code is still laid out by tt-weave, but it's written as Pascal, as if
+//! [`crate::prettify::CodeStyle::preserve_operators`],
+//! [`crate::prettify::CodeStyle::preserve_keywords`], and
+//! [`crate::prettify::CodeStyle::preserve_octal`] were set, and with WEAVE's
+//! macros for the operators, like `\K` for `:=` and `\S` for the `==` of a
+//! definition, which is set as `\D` name `\S` value. The values of constants
+//! aren't noted, and the code isn't highlighted.
+
+use std::{
+    convert::TryFrom,
+    fmt::{self, Write},
+};
+
+use crate::{
+    index::IndexEntryKind,
+    reserved::PascalReservedWord,
+    state::{ModuleId, Reference, State},
+};
+
+/// The material emitted before everything else.
+pub const PREAMBLE: &str = "\\input webmac\n";
+
+/// Set an identifier as WEAVE does, in italics: with `\|` if it's a single
+/// letter, and with `\\` otherwise.
+pub(crate) fn identifier(name: &str) -> String {
+    if name.len() == 1 {
+        format!("\\|{}", name)
+    } else {
+        format!("\\\\{{{}}}", name.replace('_', "\\_"))
+    }
+}
+
+/// Set a word of code: in bold with `\&` if it's a reserved word, and as an
+/// identifier otherwise. Reserved words are usually emitted with the keyword
+/// scope, but not all of them are: `div` and `nil`, for instance, and any of
+/// them in code that couldn't be parsed.
+pub(crate) fn word(name: &str) -> String {
+    if PascalReservedWord::try_from(name).is_ok() {
+        format!("\\&{{{}}}", name)
+    } else {
+        identifier(name)
+    }
+}
+
+/// Set a string in typewriter type with `\.`, escaping the characters that
+/// `webmac.tex` expects to be escaped there.
+pub(crate) fn string(text: &str) -> String {
+    let mut tex = String::with_capacity(text.len() + 4);
+    tex.push_str("\\.{");
+
+    for c in text.chars() {
+        match c {
+            '\\' | '\'' | '`' | '{' | '}' | '~' | ' ' | '_' | '&' | '#' | '$' | '%' | '^' => {
+                tex.push('\\');
+                tex.push(c);
+            }
+            other => tex.push(other),
+        }
+    }
+
+    tex.push('}');
+    tex
+}
+
+/// The macros with which WEAVE sets Pascal's operators, which take care of
+/// their own spacing.
+const OPERATORS: &[(&str, &str)] = &[
+    (":=", "\\K"),
+    ("==", "\\S"),
+    ("<>", "\\I"),
+    ("<=", "\\L"),
+    (">=", "\\G"),
+    ("and", "\\W"),
+    ("or", "\\V"),
+    ("not", "\\R"),
+];
+
+/// Get the macro for an operator, if `text` is one that WEAVE sets with a
+/// macro. Words are only operators if they're the whole of `text`.
+pub(crate) fn operator_prefix(text: &str) -> Option<(&'static str, usize)> {
+    OPERATORS.iter().find_map(|(op, cs)| {
+        let is_word = op.starts_with(char::is_alphabetic);
+
+        if (is_word && text == *op) || (!is_word && text.starts_with(op)) {
+            Some((*cs, op.len()))
+        } else {
+            None
+        }
+    })
+}
+
+/// Escape a character of code that isn't part of a word, so that it can be
+/// typeset in roman type. Some characters are missing from TeX's text fonts,
+/// so they're typeset in math mode.
+pub(crate) fn push_escaped(c: char, dest: &mut String) {
+    match c {
+        '<' => dest.push_str("$<$"),
+        '>' => dest.push_str("$>$"),
+        '|' => dest.push_str("$\\vert$"),
+        '{' => dest.push_str("$\\{$"),
+        '}' => dest.push_str("$\\}$"),
+        '\\' => dest.push_str("$\\backslash$"),
+        '^' => dest.push_str("$\\uparrow$"),
+        '~' => dest.push_str("$\\sim$"),
+        '"' => dest.push_str("{\\tt\\char`\\\"}"),
+        '&' => dest.push_str("{\\char`\\&}"),
+        '_' | '#' | '$' | '%' => {
+            dest.push('\\');
+            dest.push(c);
+        }
+        '⟦' => dest.push_str("$\\langle$"),
+        '⟧' => dest.push_str("$\\rangle$"),
+        other => dest.push(other),
+    }
+}
+
+/// Write a list of modules, as WEAVE does in its notes: `1`, `1\ET2`, or
+/// `1, 2\ETs3`.
+fn write_module_list<W: Write>(modules: &[ModuleId], dest: &mut W) -> fmt::Result {
+    for (i, m) in modules.iter().enumerate() {
+        if i > 0 {
+            if i + 1 < modules.len() {
+                write!(dest, ", ")?;
+            } else if modules.len() == 2 {
+                write!(dest, "\\ET")?;
+            } else {
+                write!(dest, "\\ETs")?;
+            }
+        }
+
+        write!(dest, "{}", m)?;
+    }
+
+    Ok(())
+}
+
+/// Write a note listing some modules, with the macro `single` if there's only
+/// one, and `plural` otherwise. Nothing is written if there are none.
+fn write_note<W: Write>(
+    single: &str,
+    plural: &str,
+    modules: &[ModuleId],
+    dest: &mut W,
+) -> fmt::Result {
+    if modules.is_empty() {
+        return Ok(());
+    }
+
+    let cs = if modules.len() == 1 { single } else { plural };
+    write!(dest, "{}", cs)?;
+    write_module_list(modules, dest)?;
+    writeln!(dest, ".")
+}
+
+/// Get the modules in a list of references, in order, keeping either the
+/// definitions or the uses.
+fn modules_of(refs: &[Reference], definitions: bool) -> Vec<ModuleId> {
+    let mut modules: Vec<_> = refs
+        .iter()
+        .filter(|r| r.is_definition == definitions)
+        .map(|r| r.module)
+        .collect();
+    modules.sort_unstable();
+    modules.dedup();
+    modules
+}
+
+impl State {
+    /// Emit the notes that WEAVE puts after the code of a named module: the
+    /// other modules that contribute to its definition, with `\A` or `\As`,
+    /// and the modules that use it, with `\U` or `\Us`.
+    pub fn emit_webmac_module_notes<W: Write>(
+        &self,
+        name: &str,
+        module: ModuleId,
+        dest: &mut W,
+    ) -> fmt::Result {
+        let refs = match self.module_index_state(name) {
+            Some(ixstate) => &ixstate.refs[..],
+            None => return Ok(()),
+        };
+
+        let mut others = modules_of(refs, true);
+        others.retain(|m| *m != module);
+        write_note("\\A", "\\As", &others, dest)?;
+        write_note("\\U", "\\Us", &modules_of(refs, false), dest)
+    }
+
+    /// Emit the index and the list of module names as WEAVE does, followed by
+    /// `\con`, which typesets the table of contents and ends the document.
+    ///
+    /// Each entry of the index has the form `\:$text, $refs.`, where $refs
+    /// lists the modules where the entry appears, with the ones where it's
+    /// defined underlined as `\[$id]`.
+    pub fn emit_webmac_index<W: Write>(&self, dest: &mut W) -> fmt::Result {
+        writeln!(dest, "\\inx")?;
+
+        for (name, info) in self.symbol_index() {
+            let text = match info.kind {
+                IndexEntryKind::Normal => identifier(name),
+                IndexEntryKind::Roman => name.to_owned(),
+                IndexEntryKind::Typewriter => string(name),
+                IndexEntryKind::Wildcard => format!("\\9{{{}}}", name),
+            };
+
+            write!(dest, "\\:{}, ", text)?;
+
+            let mut refs: Vec<_> = info.refs.iter().collect();
+            refs.sort_by_key(|r| r.module);
+
+            for (i, r) in refs.iter().enumerate() {
+                if i > 0 {
+                    write!(dest, ", ")?;
+                }
+
+                if r.is_definition {
+                    write!(dest, "\\[{}]", r.module)?;
+                } else {
+                    write!(dest, "{}", r.module)?;
+                }
+            }
+
+            writeln!(dest, ".")?;
+        }
+
+        writeln!(dest, "\\fin")?;
+
+        for (name, _) in self.named_modules() {
+            let refs = self
+                .module_index_state(name)
+                .map(|ixstate| &ixstate.refs[..])
+                .unwrap_or_default();
+
+            write!(dest, "\\:\\X")?;
+            write_module_list(&modules_of(refs, true), dest)?;
+            write!(dest, ":{}\\X", name)?;
+
+            let uses = modules_of(refs, false);
+
+            if uses.is_empty() {
+                writeln!(dest)?;
+            } else {
+                write_note("\\U", "\\Us", &uses, dest)?;
+            }
+        }
+
+        writeln!(dest, "\\con")
+    }
+}
@@ -0,0 +1,344 @@
+//! A cross-reference database of a WEB program.
+//!
+//! Search tools, editors, and documentation generators often want to know
+//! where things are in a WEB program without parsing it themselves. When
+//! [`crate::WeaveOptions::xref`] is set, weaving gathers up a database of every
+//! section, every occurrence of an identifier in the code, every reference to a
+//! named module, and every macro definition, which is returned as
+//! [`crate::WovenDocument::xref`]. It serializes to JSON.
+//!
+//! Locations are given as byte offsets into the source text, along with the
+//! line and column of the start, counting from 1, with columns counted in
+//! characters. Occurrences in code that couldn't be parsed are included, but
+//! they're all counted as uses, since we can't tell which are declarations.
+//! Identifiers in the bits of code quoted in comments, like `|x|`, are listed
+//! separately, since they're not part of the program.
+//!
+//! The database also drives the annotations that say where each procedure and
+//! function is used, when [`crate::WeaveOptions::function_uses`] is set; see
+//! [`XrefDatabase::function_uses`].
+
+use serde::Serialize;
+use std::{collections::HashMap, ops::Range};
+
+use crate::{
+    parse_base::StringSpan,
+    pascal_token::PascalToken,
+    state::ModuleId,
+    symbols::normalize,
+    symtab::{SymbolKind, SymbolTable},
+    weblang::{
+        base::TypesetComment, module_reference::WebModuleReference, visit::Visitor, WebCode,
+        WebComment,
+    },
+};
+
+/// Where something appears in the source text.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+pub struct XrefLocation {
+    /// The line on which it starts.
+    pub line: u32,
+
+    /// The column at which it starts.
+    pub column: usize,
+
+    /// The byte offset at which it starts.
+    pub start: usize,
+
+    /// The byte offset at which it ends.
+    pub end: usize,
+}
+
+impl XrefLocation {
+    fn of(span: &StringSpan) -> Self {
+        XrefLocation {
+            line: span.start.location_line(),
+            column: span.start.get_utf8_column(),
+            start: span.start.location_offset(),
+            end: span.end.location_offset(),
+        }
+    }
+}
+
+/// A section of the program: one module, from its opening control code up to
+/// the next one.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct XrefSection {
+    /// The module.
+    pub module: ModuleId,
+
+    /// Whether this is a starred module, which starts a major part.
+    pub major: bool,
+
+    /// The line on which the module starts.
+    pub line: u32,
+
+    /// The byte offset at which the module starts.
+    pub start: usize,
+
+    /// The byte offset at which the module ends.
+    pub end: usize,
+}
+
+/// An occurrence of an identifier in the code.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct XrefIdentifier {
+    /// The identifier as it's spelled here.
+    pub name: String,
+
+    /// The module in which it appears.
+    pub module: ModuleId,
+
+    /// Whether the identifier is being declared here, as opposed to used. See
+    /// [`crate::weblang::WebCode::find_definitions`].
+    pub definition: bool,
+
+    /// The context in which the code appears: `named`, `anonymous`, `define`,
+    /// `format`, or `inline`.
+    pub context: &'static str,
+
+    /// Where the identifier appears.
+    pub location: XrefLocation,
+}
+
+/// A reference to a named module in the code.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct XrefModuleReference {
+    /// The module that the name refers to: the first module that contributes
+    /// code to it.
+    pub target: ModuleId,
+
+    /// The full name of the module.
+    pub name: String,
+
+    /// The module in which the reference appears.
+    pub module: ModuleId,
+
+    /// Whether this is the name at the head of some code, which defines or
+    /// extends the module, as opposed to a use of the module's code.
+    pub definition: bool,
+
+    /// Where the name appears.
+    pub location: XrefLocation,
+}
+
+/// A macro definition.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct XrefDefine {
+    /// The name of the macro.
+    pub name: String,
+
+    /// The module in which it's defined.
+    pub module: ModuleId,
+
+    /// Whether the macro takes a parameter.
+    pub parameterized: bool,
+
+    /// Where the name of the macro appears.
+    pub location: XrefLocation,
+
+    /// The byte offsets occupied by the whole definition, starting with the
+    /// `@d`.
+    pub extent: Range<usize>,
+}
+
+/// Where a procedure or function is defined and used.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct FunctionUses {
+    /// The module in which it's defined.
+    pub defined: ModuleId,
+
+    /// The other modules in which its name appears, in order.
+    pub used: Vec<ModuleId>,
+}
+
+/// Gather up the identifiers and module references in a fragment of code.
+struct Collector<'d> {
+    db: &'d mut XrefDatabase,
+    module: ModuleId,
+    context: &'static str,
+}
+
+impl<'d> Collector<'d> {
+    fn occurrence(&self, ident: &StringSpan, definition: bool) -> XrefIdentifier {
+        XrefIdentifier {
+            name: ident.value.to_string(),
+            module: self.module,
+            definition,
+            context: self.context,
+            location: XrefLocation::of(ident),
+        }
+    }
+
+    fn identifier(&mut self, ident: &StringSpan, definition: bool) {
+        let occurrence = self.occurrence(ident, definition);
+        self.db.identifiers.push(occurrence);
+    }
+}
+
+impl<'a, 'd> Visitor<'a> for Collector<'d> {
+    fn visit_identifier(&mut self, ident: &StringSpan<'a>) {
+        self.identifier(ident, false);
+    }
+
+    fn visit_defined_identifier(&mut self, ident: &StringSpan<'a>) {
+        self.identifier(ident, true);
+    }
+
+    fn visit_module_reference(&mut self, mref: &WebModuleReference<'a>) {
+        self.db.add_module_reference(mref, self.module, false);
+    }
+
+    fn visit_comment(&mut self, comment: &WebComment<'a>) {
+        for piece in &comment.0 {
+            if let TypesetComment::Pascal(tokens) = piece {
+                for token in tokens {
+                    if let PascalToken::Identifier(ident)
+                    | PascalToken::FormattedIdentifier(ident, _) = token
+                    {
+                        let occurrence = self.occurrence(ident, false);
+                        self.db.comment_identifiers.push(occurrence);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The cross-reference database of a program.
+///
+/// See the [module-level documentation](self) for an overview. Everything is
+/// listed in the order in which it appears in the source text, except that
+/// the occurrences in each fragment of code are listed in the order in which
+/// they're visited, which is almost always the same.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct XrefDatabase {
+    /// The sections of the program.
+    pub sections: Vec<XrefSection>,
+
+    /// The occurrences of identifiers in the code.
+    pub identifiers: Vec<XrefIdentifier>,
+
+    /// The occurrences of identifiers in the code quoted in comments. These
+    /// are all counted as uses.
+    pub comment_identifiers: Vec<XrefIdentifier>,
+
+    /// The references to named modules in the code.
+    pub module_references: Vec<XrefModuleReference>,
+
+    /// The macro definitions.
+    pub defines: Vec<XrefDefine>,
+
+    /// What each identifier is declared as.
+    pub symbols: SymbolTable,
+}
+
+impl XrefDatabase {
+    /// Note that a module starts at the byte offset `start`, on `line`. This
+    /// also ends the previous section.
+    pub(crate) fn start_section(&mut self, module: ModuleId, major: bool, line: u32, start: usize) {
+        self.end_section(start);
+        self.sections.push(XrefSection {
+            module,
+            major,
+            line,
+            start,
+            end: start,
+        });
+    }
+
+    /// Note that the most recently started section ends at the byte offset
+    /// `end`.
+    pub(crate) fn end_section(&mut self, end: usize) {
+        if let Some(prev) = self.sections.last_mut() {
+            if prev.end == prev.start {
+                prev.end = end;
+            }
+        }
+    }
+
+    /// Record the identifiers and module references in a fragment of code that
+    /// appears in `module`, in the given `context`.
+    pub(crate) fn add(&mut self, code: &WebCode, module: ModuleId, context: &'static str) {
+        let mut collector = Collector {
+            db: self,
+            module,
+            context,
+        };
+
+        collector.visit_code(code);
+    }
+
+    /// Record a reference to a named module.
+    pub(crate) fn add_module_reference(
+        &mut self,
+        mref: &WebModuleReference,
+        module: ModuleId,
+        definition: bool,
+    ) {
+        self.module_references.push(XrefModuleReference {
+            target: mref.id,
+            name: mref.name.value.to_string(),
+            module,
+            definition,
+            location: XrefLocation::of(&mref.name),
+        });
+    }
+
+    /// Record a macro definition, which occupies `extent` in the source text.
+    pub(crate) fn add_define(
+        &mut self,
+        name: &StringSpan,
+        module: ModuleId,
+        parameterized: bool,
+        extent: Range<usize>,
+    ) {
+        self.defines.push(XrefDefine {
+            name: name.value.to_string(),
+            module,
+            parameterized,
+            location: XrefLocation::of(name),
+            extent,
+        });
+    }
+
+    /// Work out where each procedure and function is defined and used, keyed
+    /// by normalized name.
+    ///
+    /// A procedure that's defined more than once, as nested procedures can be,
+    /// gets its first definition. Every appearance of the name outside of the
+    /// module that defines it counts as a use, including appearances in macros
+    /// and assignments that set a function's return value.
+    pub fn function_uses(&self) -> HashMap<String, FunctionUses> {
+        let mut uses = HashMap::new();
+
+        for sym in self.symbols.iter() {
+            if matches!(sym.kind, SymbolKind::Procedure | SymbolKind::Function) {
+                uses.entry(normalize(&sym.name))
+                    .or_insert_with(|| FunctionUses {
+                        defined: sym.module,
+                        used: Vec::new(),
+                    });
+            }
+        }
+
+        for ident in &self.identifiers {
+            if ident.definition {
+                continue;
+            }
+
+            if let Some(u) = uses.get_mut(&normalize(&ident.name)) {
+                if ident.module != u.defined {
+                    u.used.push(ident.module);
+                }
+            }
+        }
+
+        for u in uses.values_mut() {
+            u.used.sort_unstable();
+            u.used.dedup();
+        }
+
+        uses
+    }
+}
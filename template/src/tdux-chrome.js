@@ -168,11 +168,16 @@ var toggleContents = (function keyboard() {
 
     Array.from(ttWeaveMajorModuleIndex).forEach(function (info) {
       var id = info.id;
+      var depth = info.depth;
       var desc = info.d;
 
       var li = document.createElement('li');
       ul.appendChild(li);
 
+      if (depth > 0) {
+        li.style.marginLeft = `${depth}em`;
+      }
+
       var a = document.createElement('a');
       li.appendChild(a);
       a.href = `#m${id}`;
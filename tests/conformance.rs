@@ -0,0 +1,56 @@
+//! Conformance checks against Knuth's WEB programs.
+//!
+//! The programs aren't distributed with tt-weave, so each check reads its
+//! reference file from the path given in an environment variable, and is
+//! skipped if the variable isn't set. For instance:
+//!
+//! ```sh
+//! TT_WEAVE_TANGLE_WEB=/path/to/tangle.web cargo test --test conformance
+//! ```
+
+use std::{env, fs};
+use tt_weave::Dialect;
+
+/// Check that all of the Pascal code of the WEB file named by the environment
+/// variable `var` can be parsed, as with `tt-weave --check`, and that none of
+/// it is left as an unparsed toplevel.
+fn check_reference(var: &str) {
+    let path = match env::var_os(var) {
+        Some(p) => p,
+        None => {
+            eprintln!("skipping: set `{}` to the path of the reference file", var);
+            return;
+        }
+    };
+
+    let text = fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("failed to read `{}`: {}", path.to_string_lossy(), e));
+    let state = tt_weave::scan(&text, Dialect::Web).expect("the first pass failed");
+
+    let failures = tt_weave::check(&text, &state, None).expect("the check failed");
+    let path = path.to_string_lossy();
+
+    for f in &failures {
+        eprintln!("error: {}", f.render(&text, Some(&path)));
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{} problem(s) were found while parsing the code",
+        failures.len()
+    );
+
+    #[cfg(feature = "serde")]
+    {
+        let ast = tt_weave::dump_ast(&text, &state, None).expect("the AST dump failed");
+        assert!(
+            !ast.contains("\"Unparsed\""),
+            "some of the code was left unparsed"
+        );
+    }
+}
+
+#[test]
+fn tangle_web() {
+    check_reference("TT_WEAVE_TANGLE_WEB");
+}
@@ -0,0 +1,70 @@
+//! Regression inputs for the fuzzing entry point.
+//!
+//! Each of these once crashed, hung, or ran out of memory when given to
+//! [`tt_weave::parse_fuzz_input`]. They're run as ordinary tests so that they
+//! stay fixed without needing `cargo fuzz`.
+
+use std::thread;
+
+/// Run a WEB file through the fuzzing entry point. Errors are fine, but it
+/// should neither panic nor overflow the stack. This runs on a thread with the
+/// stack of a typical main thread, rather than that of a test.
+fn fuzz(web: String) {
+    let mut data = vec![0]; // select the WEB dialect
+    data.extend(web.into_bytes());
+
+    thread::Builder::new()
+        .stack_size(8 << 20)
+        .spawn(move || {
+            let _ = tt_weave::parse_fuzz_input(&data);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn stray_code_delimiter() {
+    fuzz("@ @p x := 1 | y := 2\n".to_owned());
+}
+
+#[test]
+fn unterminated_string() {
+    fuzz("@ @p x := 'abc".to_owned());
+}
+
+#[test]
+fn nested_pointer_types() {
+    fuzz(format!("@ @p type t = {}integer;\n", "^".repeat(1000)));
+}
+
+#[test]
+fn nested_array_types() {
+    fuzz(format!(
+        "@ @p type t = {}integer;\n",
+        "array[1..2] of ".repeat(1000)
+    ));
+}
+
+#[test]
+fn nested_record_types() {
+    fuzz(format!(
+        "@ @p type t = {}integer{};\n",
+        "record a: ".repeat(1000),
+        " end".repeat(1000)
+    ));
+}
+
+#[test]
+fn nested_procedures() {
+    fuzz(format!("@ @p {}begin end;\n", "procedure p; ".repeat(3000)));
+}
+
+#[test]
+fn nested_indices() {
+    fuzz(format!(
+        "@ @p x := a{}{};\n",
+        "[1".repeat(60),
+        "]".repeat(60)
+    ));
+}
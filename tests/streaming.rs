@@ -0,0 +1,35 @@
+//! Weaving straight into a writer.
+
+use tt_weave::{Dialect, WeaveOptions};
+
+/// The TeX written out as a document is woven should be the same as that of the
+/// document woven in memory, including when there's enough code that it's
+/// processed in several batches.
+#[test]
+fn matches_weave_document() {
+    let mut web = String::from("\\def\\title{STREAMING}\n");
+
+    for i in 0..1500 {
+        web.push_str(&format!(
+            "@ Module {}.\n@d m{}=={}\n@p x := m{} + {{a comment}} 1;\n",
+            i, i, i, i
+        ));
+    }
+
+    let state = tt_weave::scan(&web, Dialect::Web).unwrap();
+    let theme = tt_weave::themes::default_theme();
+    let options = WeaveOptions {
+        mini_index: true,
+        ..WeaveOptions::default()
+    };
+
+    let doc = tt_weave::weave_document("test", &web, &state, &theme, &options).unwrap();
+    let mut streamed = Vec::new();
+    let streamed_doc =
+        tt_weave::weave_to_writer("test", &web, &state, &theme, &options, &mut streamed).unwrap();
+
+    assert!(String::from_utf8(streamed).unwrap() == doc.tex);
+    assert!(streamed_doc.tex.is_empty());
+    assert_eq!(streamed_doc.modules().count(), 0);
+    assert_eq!(streamed_doc.warnings.len(), doc.warnings.len());
+}